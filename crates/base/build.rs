@@ -188,7 +188,7 @@ mod supabase_startup_snapshot {
         let user_agent = String::from("supabase");
         let fs = Arc::new(deno_fs::RealFs);
         let extensions: Vec<Extension> = vec![
-            sb_core_permissions::init_ops_and_esm(false, None),
+            sb_core_permissions::init_ops_and_esm(false, None, None),
             deno_webidl::deno_webidl::init_ops_and_esm(),
             deno_console::deno_console::init_ops_and_esm(),
             deno_url::deno_url::init_ops_and_esm(),
@@ -263,4 +263,14 @@ fn main() {
     let runtime_snapshot_path = o.join("RUNTIME_SNAPSHOT.bin");
 
     supabase_startup_snapshot::create_runtime_snapshot(runtime_snapshot_path.clone());
+
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/control_plane.proto");
+
+        tonic_build::configure()
+            .build_client(false)
+            .compile(&["proto/control_plane.proto"], &["proto"])
+            .unwrap();
+    }
 }
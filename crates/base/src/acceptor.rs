@@ -0,0 +1,292 @@
+//! Accepting the connections of a port on several sockets bound to it with
+//! `SO_REUSEPORT`, each driven by a task of its own, so that a single accept
+//! loop is not the bottleneck at high connection rates. The kernel spreads
+//! the incoming connections across the sockets, and the accepted ones are
+//! handed to the accept loop of the server through a queue.
+//!
+//! With the `io-uring` feature, the port can be served by a single io_uring
+//! thread instead (see `crate::io_uring`).
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use anyhow::{bail, Error};
+use log::error;
+use sb_core::SharedMetricSource;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// How many accepted connections each acceptor may queue up before it stops
+/// accepting, leaving the rest in the backlog of its socket.
+static QUEUE_SIZE_PER_ACCEPTOR: usize = 1024;
+
+static LISTEN_BACKLOG: i32 = 1024;
+
+pub(crate) enum Acceptor {
+    Single(TcpListener),
+    ReusePort {
+        local_addr: SocketAddr,
+        rx: mpsc::Receiver<io::Result<(TcpStream, SocketAddr)>>,
+        tasks: Vec<JoinHandle<()>>,
+    },
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    IoUring(crate::io_uring::IoUringAcceptor),
+}
+
+/// A connection accepted by an [`Acceptor`].
+pub(crate) enum AcceptedStream {
+    Tcp(TcpStream),
+    /// The end of a connection driven by the io_uring thread.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    IoUring(tokio::io::DuplexStream),
+}
+
+impl AcceptedStream {
+    pub(crate) fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Self::Tcp(it) => it.set_nodelay(nodelay),
+            // NOTE: The io_uring thread sets it up as it accepts the socket.
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Self::IoUring(_) => Ok(()),
+        }
+    }
+}
+
+impl AsyncRead for AcceptedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(it) => Pin::new(it).poll_read(cx, buf),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Self::IoUring(it) => Pin::new(it).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AcceptedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(it) => Pin::new(it).poll_write(cx, buf),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Self::IoUring(it) => Pin::new(it).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(it) => Pin::new(it).poll_flush(cx),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Self::IoUring(it) => Pin::new(it).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(it) => Pin::new(it).poll_shutdown(cx),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Self::IoUring(it) => Pin::new(it).poll_shutdown(cx),
+        }
+    }
+}
+
+impl Drop for Acceptor {
+    fn drop(&mut self) {
+        if let Self::ReusePort { tasks, .. } = self {
+            for task in tasks.iter() {
+                task.abort();
+            }
+        }
+    }
+}
+
+impl Acceptor {
+    /// Binds `count` sockets to the address, or a single one without
+    /// `SO_REUSEPORT` if `count` is 1. A `count` of 0 binds one per core.
+    pub(crate) async fn bind(
+        addr: SocketAddr,
+        count: usize,
+        metric_src: SharedMetricSource,
+    ) -> Result<Self, Error> {
+        let count = match count {
+            0 => std::thread::available_parallelism().map_or(1, |it| it.get()),
+            count => count,
+        };
+
+        if count == 1 {
+            return Ok(Self::Single(TcpListener::bind(addr).await?));
+        }
+
+        if !cfg!(unix) {
+            bail!(
+                "multiple acceptors require SO_REUSEPORT, which is not supported on this platform"
+            );
+        }
+
+        // NOTE: The first socket picks the port if none is given, and the
+        // others are bound to the same one.
+        let first = bind_reuse_port(addr)?;
+        let local_addr = first.local_addr()?;
+        let mut listeners = vec![first];
+
+        for _ in 1..count {
+            listeners.push(bind_reuse_port(local_addr)?);
+        }
+
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE_PER_ACCEPTOR * count);
+        let tasks = listeners
+            .into_iter()
+            .map(|listener| {
+                let tx = tx.clone();
+                let metric_src = metric_src.clone();
+
+                tokio::spawn(async move {
+                    loop {
+                        let result = listener.accept().await;
+
+                        metric_src.incl_queued_accepts();
+
+                        if tx.send(result).await.is_err() {
+                            metric_src.decl_queued_accepts();
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        Ok(Self::ReusePort {
+            local_addr,
+            rx,
+            tasks,
+        })
+    }
+
+    /// Binds a single socket, whose connections are driven through io_uring.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub(crate) async fn bind_io_uring(
+        addr: SocketAddr,
+        tcp_nodelay: bool,
+        metric_src: SharedMetricSource,
+    ) -> Result<Self, Error> {
+        Ok(Self::IoUring(
+            crate::io_uring::IoUringAcceptor::bind(addr, tcp_nodelay, metric_src).await?,
+        ))
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Self::Single(listener) => listener.local_addr(),
+            Self::ReusePort { local_addr, .. } => Ok(*local_addr),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Self::IoUring(it) => Ok(it.local_addr),
+        }
+    }
+
+    pub(crate) async fn accept(
+        &mut self,
+        metric_src: &SharedMetricSource,
+    ) -> io::Result<(AcceptedStream, SocketAddr)> {
+        let result = match self {
+            Self::Single(listener) => listener
+                .accept()
+                .await
+                .map(|(stream, addr)| (AcceptedStream::Tcp(stream), addr)),
+            Self::ReusePort { rx, .. } => {
+                let Some(result) = rx.recv().await else {
+                    error!("acceptors are gone");
+                    return std::future::pending().await;
+                };
+
+                metric_src.decl_queued_accepts();
+                result.map(|(stream, addr)| (AcceptedStream::Tcp(stream), addr))
+            }
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Self::IoUring(it) => {
+                let Some(result) = it.rx.recv().await else {
+                    error!("io_uring thread is gone");
+                    return std::future::pending().await;
+                };
+
+                metric_src.decl_queued_accepts();
+                result.map(|(io, addr)| (AcceptedStream::IoUring(io), addr))
+            }
+        };
+
+        match result.as_ref() {
+            Ok(_) => metric_src.incl_accepted_connections(),
+            Err(_) => metric_src.incl_accept_errors(),
+        }
+
+        result
+    }
+}
+
+#[cfg(unix)]
+fn bind_reuse_port(addr: SocketAddr) -> io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+#[cfg(not(unix))]
+fn bind_reuse_port(_addr: SocketAddr) -> io::Result<TcpListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use sb_core::SharedMetricSource;
+    use tokio::net::TcpStream;
+
+    use super::Acceptor;
+
+    #[tokio::test]
+    async fn test_reuse_port_acceptors() {
+        let metric_src = SharedMetricSource::default();
+        let mut acceptor = Acceptor::bind(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            4,
+            metric_src.clone(),
+        )
+        .await
+        .unwrap();
+
+        let addr = acceptor.local_addr().unwrap();
+        let mut clients = vec![];
+
+        for _ in 0..16 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+
+        for _ in 0..16 {
+            acceptor.accept(&metric_src).await.unwrap();
+        }
+
+        assert_eq!(metric_src.accepted_connections(), 16);
+        assert_eq!(metric_src.queued_accepts(), 0);
+    }
+}
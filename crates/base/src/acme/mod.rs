@@ -0,0 +1,481 @@
+//! Obtains and renews the certificate of the TLS listener from an ACME server
+//! such as Let's Encrypt.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Error};
+use deno_core::serde_json;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, Order, OrderStatus,
+};
+use log::{error, info};
+use once_cell::sync::Lazy;
+use rcgen::{CertificateParams, CustomExtension, DistinguishedName, KeyPair};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_util::sync::CancellationToken;
+
+use crate::server::ServerEvent;
+use crate::tls_cert::{
+    create_certified_key, get_dns_names, get_not_after, parse_cert_chain, parse_key,
+    report_cert_expiry, CertMonitorOptions, CertResolver,
+};
+
+pub static LETS_ENCRYPT_DIRECTORY_URL: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// The ALPN protocol of the TLS-ALPN-01 challenge (RFC 8737).
+pub(crate) static ACME_TLS_ALPN_PROTOCOL: &[u8] = b"acme-tls/1";
+
+static HTTP_01_PATH_PREFIX: &str = "/.well-known/acme-challenge/";
+static ACCOUNT_FILE: &str = "account.json";
+static CERT_FILE: &str = "cert.pem";
+static KEY_FILE: &str = "key.pem";
+static RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+static MAX_POLLS: usize = 30;
+static POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The key authorizations of the pending HTTP-01 challenges, by token.
+static HTTP_01_CHALLENGES: Lazy<RwLock<HashMap<String, String>>> = Lazy::new(Default::default);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AcmeChallengeType {
+    /// Answered by the non-secure listener, which must be reachable on port 80
+    /// of every domain.
+    Http01,
+    /// Answered by the TLS listener, which must be reachable on port 443 of
+    /// every domain.
+    #[default]
+    TlsAlpn01,
+}
+
+impl AcmeChallengeType {
+    fn as_challenge_type(&self) -> ChallengeType {
+        match self {
+            Self::Http01 => ChallengeType::Http01,
+            Self::TlsAlpn01 => ChallengeType::TlsAlpn01,
+        }
+    }
+}
+
+impl FromStr for AcmeChallengeType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "http-01" => Ok(Self::Http01),
+            "tls-alpn-01" => Ok(Self::TlsAlpn01),
+            _ => bail!("unsupported ACME challenge type: {s}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AcmeOptions {
+    pub directory_url: String,
+    pub domains: Vec<String>,
+    pub contact_email: Option<String>,
+    /// Where the account key and the issued certificate are kept across
+    /// restarts.
+    pub cache_dir: PathBuf,
+    pub challenge_type: AcmeChallengeType,
+    pub renew_before_days: u64,
+}
+
+/// Returns the key authorization to respond with if the path is the one of a
+/// pending HTTP-01 challenge.
+pub(crate) fn get_http_01_response(path: &str) -> Option<String> {
+    let token = path.strip_prefix(HTTP_01_PATH_PREFIX)?;
+
+    HTTP_01_CHALLENGES.read().unwrap().get(token).cloned()
+}
+
+/// Loads the certificate issued by a previous run, unless it does not cover
+/// all of the configured domains.
+pub(crate) fn load_cached_cert(
+    opts: &AcmeOptions,
+) -> Result<Option<(CertifiedKey, SystemTime)>, Error> {
+    let cert_path = opts.cache_dir.join(CERT_FILE);
+    let key_path = opts.cache_dir.join(KEY_FILE);
+
+    if !cert_path.exists() || !key_path.exists() {
+        return Ok(None);
+    }
+
+    let key = std::fs::read(&key_path)
+        .with_context(|| format!("can't read key file: {}", key_path.display()))?;
+    let cert = std::fs::read(&cert_path)
+        .with_context(|| format!("can't read cert file: {}", cert_path.display()))?;
+
+    let cert_chain = parse_cert_chain(&cert)?;
+    let dns_names = get_dns_names(&cert_chain)?;
+
+    if !opts.domains.iter().all(|it| dns_names.contains(it)) {
+        info!("cached TLS certificate does not cover all domains; ignoring it");
+        return Ok(None);
+    }
+
+    let not_after = get_not_after(&cert_chain)?;
+
+    Ok(Some((
+        create_certified_key(cert_chain, &parse_key(&key)?, None)?,
+        not_after,
+    )))
+}
+
+/// Returns the self-signed certificate that answers the TLS-ALPN-01 challenge
+/// of a domain (RFC 8737), along with its key.
+fn create_tls_alpn_01_cert(domain: &str, digest: &[u8]) -> Result<CertifiedKey, Error> {
+    let key = KeyPair::generate()?;
+    let mut params = CertificateParams::new(vec![domain.to_string()])?;
+
+    params.distinguished_name = DistinguishedName::new();
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(digest)];
+
+    let cert = params.self_signed(&key)?;
+
+    create_certified_key(
+        vec![CertificateDer::from(cert.der().to_vec())],
+        &PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+        None,
+    )
+}
+
+/// Returns a new key for the domains along with the CSR of its certificate.
+fn create_csr(domains: &[String]) -> Result<(KeyPair, Vec<u8>), Error> {
+    let key = KeyPair::generate()?;
+    let mut params = CertificateParams::new(domains.to_vec())?;
+
+    params.distinguished_name = DistinguishedName::new();
+
+    let csr = params.serialize_request(&key)?;
+
+    Ok((key, csr.der().to_vec()))
+}
+
+/// Restores the account whose credentials are kept at `path`, or registers a
+/// new one and keeps its credentials there.
+async fn load_or_create_account(
+    directory_url: &str,
+    contact_email: Option<&str>,
+    path: &Path,
+) -> Result<Account, Error> {
+    match tokio::fs::read(path).await {
+        Ok(it) => {
+            let credentials = serde_json::from_slice::<AccountCredentials>(&it)
+                .with_context(|| format!("invalid ACME account: {}", path.display()))?;
+
+            return Account::from_credentials(credentials)
+                .await
+                .with_context(|| "can't restore ACME account");
+        }
+
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(
+                Error::from(err).context(format!("can't read ACME account: {}", path.display()))
+            )
+        }
+    }
+
+    let contact = contact_email.map(|it| format!("mailto:{it}"));
+    let contact = contact.iter().map(String::as_str).collect::<Vec<_>>();
+    let (account, credentials) = Account::create(
+        &NewAccount {
+            contact: &contact,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        directory_url,
+        None,
+    )
+    .await
+    .with_context(|| "can't register ACME account")?;
+
+    tokio::fs::write(path, serde_json::to_vec(&credentials)?)
+        .await
+        .with_context(|| format!("can't save ACME account: {}", path.display()))?;
+
+    Ok(account)
+}
+
+/// Waits until the order is no longer pending nor processing.
+async fn poll_order(order: &mut Order) -> Result<OrderStatus, Error> {
+    for _ in 0..MAX_POLLS {
+        let status = order.refresh().await?.status;
+
+        if !matches!(status, OrderStatus::Pending | OrderStatus::Processing) {
+            return Ok(status);
+        }
+
+        sleep(POLL_INTERVAL).await;
+    }
+
+    bail!("ACME order timed out")
+}
+
+/// Withdraws the answer of a challenge once it has been validated (or not).
+struct ChallengeGuard<'a> {
+    resolver: &'a CertResolver,
+    domain: String,
+    token: String,
+}
+
+impl Drop for ChallengeGuard<'_> {
+    fn drop(&mut self) {
+        HTTP_01_CHALLENGES.write().unwrap().remove(&self.token);
+        self.resolver.remove_challenge(&self.domain);
+    }
+}
+
+/// Keeps the certificate of the TLS listener issued by the ACME server,
+/// renewing it some days before it expires.
+pub(crate) struct AcmeManager {
+    pub opts: AcmeOptions,
+    pub resolver: Arc<CertResolver>,
+    pub not_after: Option<SystemTime>,
+}
+
+impl AcmeManager {
+    pub(crate) async fn run(
+        mut self,
+        opts: CertMonitorOptions,
+        event_tx: Option<mpsc::UnboundedSender<ServerEvent>>,
+        cancel: CancellationToken,
+    ) {
+        let mut renewals = 0;
+
+        loop {
+            let needs_renewal = self.not_after.map_or(true, |it| {
+                it.duration_since(SystemTime::now()).map_or(true, |it| {
+                    it < Duration::from_secs(self.opts.renew_before_days * 86400)
+                })
+            });
+
+            let mut interval = opts.check_interval;
+
+            if needs_renewal {
+                info!("requesting TLS certificate from ACME server");
+
+                match self.obtain().await {
+                    Ok(not_after) => {
+                        self.not_after = Some(not_after);
+                        renewals += 1;
+
+                        info!("TLS certificate issued by ACME server");
+
+                        if let Some(tx) = event_tx.as_ref() {
+                            let _ = tx.send(ServerEvent::TlsCertificateReloaded);
+                        }
+                    }
+
+                    Err(err) => {
+                        error!("can't obtain TLS certificate from ACME server: {err:#}");
+                        interval = interval.min(RETRY_INTERVAL);
+                    }
+                }
+            }
+
+            if let Some(not_after) = self.not_after {
+                report_cert_expiry(not_after, renewals, false, &opts, event_tx.as_ref());
+            }
+
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = sleep(interval) => {}
+            }
+        }
+    }
+
+    fn add_challenge(
+        &self,
+        order: &Order,
+        challenge: &instant_acme::Challenge,
+        domain: &str,
+    ) -> Result<ChallengeGuard<'_>, Error> {
+        let key_authorization = order.key_authorization(challenge);
+        let token = challenge.token.as_str();
+
+        match self.opts.challenge_type {
+            AcmeChallengeType::Http01 => {
+                HTTP_01_CHALLENGES
+                    .write()
+                    .unwrap()
+                    .insert(token.to_string(), key_authorization.as_str().to_string());
+            }
+
+            AcmeChallengeType::TlsAlpn01 => {
+                self.resolver.add_challenge(
+                    domain.to_string(),
+                    create_tls_alpn_01_cert(domain, key_authorization.digest().as_ref())?,
+                );
+            }
+        }
+
+        Ok(ChallengeGuard {
+            resolver: &self.resolver,
+            domain: domain.to_string(),
+            token: token.to_string(),
+        })
+    }
+
+    async fn obtain(&self) -> Result<SystemTime, Error> {
+        let cache_dir = &self.opts.cache_dir;
+
+        tokio::fs::create_dir_all(cache_dir)
+            .await
+            .with_context(|| format!("can't create ACME cache dir: {}", cache_dir.display()))?;
+
+        let account = load_or_create_account(
+            &self.opts.directory_url,
+            self.opts.contact_email.as_deref(),
+            &cache_dir.join(ACCOUNT_FILE),
+        )
+        .await?;
+
+        let identifiers = self
+            .opts
+            .domains
+            .iter()
+            .map(|it| Identifier::Dns(it.clone()))
+            .collect::<Vec<_>>();
+
+        let mut order = account
+            .new_order(&NewOrder {
+                identifiers: &identifiers,
+            })
+            .await
+            .with_context(|| "can't place ACME order")?;
+
+        let challenge_type = self.opts.challenge_type.as_challenge_type();
+        let mut guards = vec![];
+
+        for authz in order.authorizations().await? {
+            match authz.status {
+                AuthorizationStatus::Valid => continue,
+                AuthorizationStatus::Pending => {}
+                status => bail!("unexpected ACME authorization status: {status:?}"),
+            }
+
+            #[allow(unreachable_patterns)]
+            let domain = match &authz.identifier {
+                Identifier::Dns(it) => it.clone(),
+                it => bail!("unexpected ACME identifier: {it:?}"),
+            };
+
+            let Some(challenge) = authz
+                .challenges
+                .iter()
+                .find(|it| it.r#type == challenge_type)
+            else {
+                bail!("ACME server offers no {challenge_type:?} challenge for {domain}");
+            };
+
+            guards.push(self.add_challenge(&order, challenge, &domain)?);
+            order.set_challenge_ready(&challenge.url).await?;
+        }
+
+        match poll_order(&mut order).await? {
+            OrderStatus::Ready => {}
+            status => bail!("ACME order failed: {status:?}"),
+        }
+
+        drop(guards);
+
+        let (key, csr) = create_csr(&self.opts.domains)?;
+
+        order.finalize(&csr).await?;
+
+        let mut cert = None;
+
+        for _ in 0..MAX_POLLS {
+            cert = order.certificate().await?;
+
+            if cert.is_some() {
+                break;
+            }
+
+            sleep(POLL_INTERVAL).await;
+        }
+
+        let cert = cert.context("ACME certificate timed out")?;
+        let cert_chain = parse_cert_chain(cert.as_bytes())?;
+        let not_after = get_not_after(&cert_chain)?;
+        let certified_key = create_certified_key(
+            cert_chain,
+            &PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+            None,
+        )?;
+
+        tokio::fs::write(cache_dir.join(KEY_FILE), key.serialize_pem())
+            .await
+            .with_context(|| "can't save TLS key")?;
+        tokio::fs::write(cache_dir.join(CERT_FILE), &cert)
+            .await
+            .with_context(|| "can't save TLS certificate")?;
+
+        self.resolver.replace(certified_key);
+
+        Ok(not_after)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use x509_parser::certification_request::X509CertificationRequest;
+    use x509_parser::prelude::{FromDer, X509Certificate};
+
+    use super::{create_csr, create_tls_alpn_01_cert, get_dns_names, AcmeChallengeType};
+
+    /// The OID of the `acmeIdentifier` extension (RFC 8737).
+    static ACME_IDENTIFIER_OID: &str = "1.3.6.1.5.5.7.1.31";
+
+    #[test]
+    fn test_create_tls_alpn_01_cert() {
+        let digest = [7u8; 32];
+        let certified_key = create_tls_alpn_01_cert("example.com", &digest).unwrap();
+        let (_, cert) = X509Certificate::from_der(&certified_key.cert[0]).unwrap();
+        let ext = cert
+            .extensions()
+            .iter()
+            .find(|it| it.oid.to_id_string() == ACME_IDENTIFIER_OID)
+            .unwrap();
+
+        assert!(ext.critical);
+        // NOTE: The digest is wrapped in an octet string.
+        assert_eq!(&ext.value[2..], digest);
+        assert_eq!(
+            get_dns_names(&certified_key.cert).unwrap(),
+            vec!["example.com".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_create_csr() {
+        let domains = vec!["example.com".to_string(), "www.example.com".to_string()];
+        let (_, csr) = create_csr(&domains).unwrap();
+        let (_, req) = X509CertificationRequest::from_der(&csr).unwrap();
+
+        assert!(req
+            .certification_request_info
+            .subject
+            .iter()
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_challenge_type() {
+        assert_eq!(
+            "tls-alpn-01".parse::<AcmeChallengeType>().unwrap(),
+            AcmeChallengeType::TlsAlpn01
+        );
+        assert!("dns-01".parse::<AcmeChallengeType>().is_err());
+    }
+}
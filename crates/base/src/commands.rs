@@ -1,11 +1,18 @@
 use crate::{
     inspector_server::Inspector,
-    rt_worker::{worker_ctx::TerminationToken, worker_pool::WorkerPoolPolicy},
+    rt_worker::{
+        client_ip::ClientIpPolicy, internal_auth::InternalApiAuth, worker_ctx::TerminationToken,
+        worker_pool::WorkerPoolPolicy,
+    },
     server::{Server, ServerFlags, ServerHealth, Tls, WorkerEntrypoints},
-    InspectorOption,
+    shutdown_report::ShutdownReportTarget,
+    InspectorOption, ReadyTarget,
 };
 use anyhow::Error;
+use event_worker::routing::EventRoute;
+use log::error;
 use sb_graph::DecoratorType;
+use std::path::PathBuf;
 use tokio::sync::mpsc::Sender;
 
 #[allow(clippy::too_many_arguments)]
@@ -14,7 +21,11 @@ pub async fn start_server(
     port: u16,
     tls: Option<Tls>,
     main_service_path: String,
+    maybe_functions_dir: Option<String>,
+    maybe_internal_api_auth: Option<InternalApiAuth>,
+    maybe_client_ip_policy: Option<ClientIpPolicy>,
     event_worker_path: Option<String>,
+    event_worker_routes: Vec<(EventRoute, String)>,
     decorator: Option<DecoratorType>,
     user_worker_policy: Option<WorkerPoolPolicy>,
     import_map_path: Option<String>,
@@ -26,13 +37,20 @@ pub async fn start_server(
     inspector_option: Option<InspectorOption>,
     jsx_specifier: Option<String>,
     jsx_module: Option<String>,
+    ready_target: Option<ReadyTarget>,
+    config_path: Option<PathBuf>,
+    shutdown_report_target: Option<ShutdownReportTarget>,
 ) -> Result<(), Error> {
     let mut server = Server::new(
         ip,
         port,
         tls,
         main_service_path,
+        maybe_functions_dir,
+        maybe_internal_api_auth,
+        maybe_client_ip_policy,
         event_worker_path,
+        event_worker_routes,
         decorator,
         user_worker_policy,
         import_map_path,
@@ -44,8 +62,18 @@ pub async fn start_server(
         inspector_option.map(Inspector::from_option),
         jsx_specifier,
         jsx_module,
+        ready_target,
+        config_path,
     )
     .await?;
 
-    server.listen().await
+    let result = server.listen().await;
+
+    if let Some(target) = shutdown_report_target {
+        if let Err(err) = target.write(&server.shutdown_report(result.as_ref().err())) {
+            error!("{:#}", err);
+        }
+    }
+
+    result
 }
@@ -0,0 +1,467 @@
+//! Crash dumps written when the process is aborted by V8, e.g. because an
+//! isolate ran out of memory or a check of V8 failed, so that the state of the
+//! runtime at the time can be looked into post-mortem.
+//!
+//! A dump is a JSON file holding the reason of the crash, the backtrace of the
+//! thread that crashed and the workers that were alive along with their last
+//! known heap statistics.
+//!
+//! The dump of an abort is written from a signal handler, which can neither
+//! allocate nor lock, so it is rendered ahead of time whenever a worker comes
+//! or goes. It only lists the workers, without a backtrace or heap statistics.
+
+#[cfg(unix)]
+use std::cell::UnsafeCell;
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::ffi::CString;
+use std::ffi::{c_char, CStr};
+use std::fs;
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use base_mem_check::{MemCheckState, WorkerHeapStatistics};
+use deno_core::serde_json;
+use deno_core::v8::{Isolate, OomDetails};
+use once_cell::sync::OnceCell;
+use sb_workers::context::WorkerKind;
+use serde::Serialize;
+
+static CRASH_DUMPER: OnceCell<CrashDumper> = OnceCell::new();
+
+/// Set once a dump has been written, as V8 aborts the process right after the
+/// OOM handler returns, which would otherwise be dumped a second time.
+static IS_DUMPED: AtomicBool = AtomicBool::new(false);
+
+struct WorkerEntry {
+    kind: WorkerKind,
+    main_module: String,
+    /// Only kept up to date for user workers, by their GC prologue callback.
+    mem_check_state: Option<Arc<RwLock<MemCheckState>>>,
+}
+
+/// Room for the dump of an abort. If the workers don't fit in it, they are
+/// left out.
+#[cfg(unix)]
+static MAX_ABORT_DUMP_SIZE: usize = 256 * 1024;
+
+struct CrashDumper {
+    dir: PathBuf,
+    next_id: AtomicU64,
+    workers: Mutex<HashMap<u64, WorkerEntry>>,
+    #[cfg(unix)]
+    abort_dump: AbortDump,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkerSummary {
+    kind: String,
+    main_module: String,
+    heap: Option<WorkerHeapStatistics>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CrashDump<'a> {
+    reason: &'a str,
+    location: Option<String>,
+    detail: Option<String>,
+    pid: u32,
+    version: &'static str,
+    /// `None` for the dump of an abort, in which case the modification time of
+    /// the file tells when the process crashed.
+    timestamp: Option<u64>,
+    thread: Option<String>,
+    backtrace: Option<String>,
+    /// `None` if the workers could not be looked up without risking a
+    /// deadlock.
+    workers: Option<Vec<WorkerSummary>>,
+}
+
+/// Writes a crash dump to the given directory whenever V8 aborts the process.
+pub fn install(dir: impl Into<PathBuf>) -> Result<(), anyhow::Error> {
+    let dir = dir.into();
+
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("can't create crash dump directory {}", dir.display()))?;
+
+    #[cfg(unix)]
+    let abort_dump = AbortDump::new(&dir.join(format!("crash-{}.json", std::process::id())))?;
+
+    let is_installed = CRASH_DUMPER
+        .set(CrashDumper {
+            dir,
+            next_id: AtomicU64::new(0),
+            workers: Mutex::default(),
+            #[cfg(unix)]
+            abort_dump,
+        })
+        .is_ok();
+
+    if is_installed {
+        #[cfg(unix)]
+        install_abort_handler();
+    }
+
+    Ok(())
+}
+
+/// Lists a worker in the crash dumps for as long as the returned guard lives,
+/// and dumps the process if its isolate runs out of memory.
+pub(crate) fn register_worker(
+    isolate: &mut Isolate,
+    kind: WorkerKind,
+    main_module: String,
+    mem_check_state: Option<Arc<RwLock<MemCheckState>>>,
+) -> Option<WorkerGuard> {
+    let dumper = CRASH_DUMPER.get()?;
+    let id = dumper.next_id.fetch_add(1, Ordering::Relaxed);
+
+    isolate.set_oom_error_handler(oom_error_handler);
+
+    let mut workers = dumper.workers.lock().unwrap();
+
+    workers.insert(
+        id,
+        WorkerEntry {
+            kind,
+            main_module,
+            mem_check_state,
+        },
+    );
+
+    #[cfg(unix)]
+    dumper.abort_dump.update(&workers);
+
+    Some(WorkerGuard(id))
+}
+
+pub(crate) struct WorkerGuard(u64);
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        if let Some(dumper) = CRASH_DUMPER.get() {
+            let mut workers = dumper.workers.lock().unwrap();
+
+            workers.remove(&self.0);
+
+            #[cfg(unix)]
+            dumper.abort_dump.update(&workers);
+        }
+    }
+}
+
+fn to_string(ptr: *const c_char) -> Option<String> {
+    // SAFETY: V8 hands over either null or a NUL-terminated string.
+    (!ptr.is_null()).then(|| {
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    })
+}
+
+extern "C" fn oom_error_handler(location: *const c_char, details: &OomDetails) {
+    write_dump(
+        if details.is_heap_oom {
+            "heap out of memory"
+        } else {
+            "process out of memory"
+        },
+        to_string(location),
+        to_string(details.detail),
+    );
+
+    std::process::abort();
+}
+
+fn write_dump(reason: &str, location: Option<String>, detail: Option<String>) {
+    let Some(dumper) = CRASH_DUMPER.get() else {
+        return;
+    };
+
+    if IS_DUMPED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    // NOTE: The thread that crashed may be holding the lock already.
+    let workers = dumper.workers.try_lock().ok().map(|workers| {
+        workers
+            .values()
+            .map(|it| WorkerSummary {
+                kind: it.kind.to_string(),
+                main_module: it.main_module.clone(),
+                heap: it
+                    .mem_check_state
+                    .as_ref()
+                    .and_then(|it| it.try_read().ok().map(|it| it.current)),
+            })
+            .collect()
+    });
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|it| it.as_secs())
+        .unwrap_or_default();
+
+    let pid = std::process::id();
+    let dump = CrashDump {
+        reason,
+        location,
+        detail,
+        pid,
+        version: env!("CARGO_PKG_VERSION"),
+        timestamp: Some(timestamp),
+        thread: std::thread::current().name().map(str::to_string),
+        backtrace: Some(std::backtrace::Backtrace::force_capture().to_string()),
+        workers,
+    };
+
+    let path = dumper.dir.join(format!("crash-{timestamp}-{pid}.json"));
+
+    match serde_json::to_vec_pretty(&dump).map(|it| fs::write(&path, it)) {
+        Ok(Ok(())) => eprintln!("crash dump written to {}", path.display()),
+        Ok(Err(err)) => eprintln!("can't write crash dump: {err}"),
+        Err(err) => eprintln!("can't serialize crash dump: {err}"),
+    }
+}
+
+/// The dump of an abort, rendered ahead of time into one of two buffers that
+/// are allocated once, so that the signal handler only has to `write(2)` it.
+///
+/// NOTE: Updates render into the buffer that is not published and then publish
+/// it, so the handler could only read a torn dump if two updates completed
+/// while it is writing.
+#[cfg(unix)]
+struct AbortDump {
+    path: CString,
+    message: Box<[u8]>,
+    buffers: [UnsafeCell<Box<[u8]>>; 2],
+    /// The length of the published dump, times two, plus its buffer.
+    published: AtomicUsize,
+}
+
+// SAFETY: The buffers are only written by `update`, which is called with the
+// lock of the workers held, and never to the published one.
+#[cfg(unix)]
+unsafe impl Sync for AbortDump {}
+
+#[cfg(unix)]
+impl AbortDump {
+    fn new(path: &Path) -> Result<Self, anyhow::Error> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let this = Self {
+            path: CString::new(path.as_os_str().as_bytes())
+                .context("invalid crash dump directory")?,
+            message: format!("crash dump written to {}\n", path.display())
+                .into_bytes()
+                .into_boxed_slice(),
+            buffers: [
+                UnsafeCell::new(vec![0; MAX_ABORT_DUMP_SIZE].into_boxed_slice()),
+                UnsafeCell::new(vec![0; MAX_ABORT_DUMP_SIZE].into_boxed_slice()),
+            ],
+            published: AtomicUsize::new(0),
+        };
+
+        this.update(&HashMap::new());
+
+        Ok(this)
+    }
+
+    fn render(workers: Option<&HashMap<u64, WorkerEntry>>) -> Vec<u8> {
+        let dump = CrashDump {
+            reason: "fatal error",
+            location: None,
+            detail: None,
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION"),
+            timestamp: None,
+            thread: None,
+            backtrace: None,
+            workers: workers.map(|it| {
+                it.values()
+                    .map(|it| WorkerSummary {
+                        kind: it.kind.to_string(),
+                        main_module: it.main_module.clone(),
+                        heap: None,
+                    })
+                    .collect()
+            }),
+        };
+
+        serde_json::to_vec_pretty(&dump).unwrap_or_default()
+    }
+
+    /// Renders the dump for the given workers and publishes it.
+    fn update(&self, workers: &HashMap<u64, WorkerEntry>) {
+        let mut dump = Self::render(Some(workers));
+
+        if dump.len() > MAX_ABORT_DUMP_SIZE {
+            dump = Self::render(None);
+        }
+
+        let idx = 1 - (self.published.load(Ordering::Acquire) & 1);
+
+        // SAFETY: See the impl of `Sync`.
+        unsafe { (*self.buffers[idx].get())[..dump.len()].copy_from_slice(&dump) };
+
+        self.published
+            .store(dump.len() * 2 + idx, Ordering::Release);
+    }
+
+    fn published(&self) -> &[u8] {
+        let published = self.published.load(Ordering::Acquire);
+
+        // SAFETY: See the impl of `Sync`.
+        unsafe { &(*self.buffers[published & 1].get())[..published / 2] }
+    }
+
+    /// Writes the published dump to its file. Only calls async-signal-safe
+    /// functions on buffers that are already allocated.
+    fn write(&self) {
+        fn write_all(fd: libc::c_int, mut buf: &[u8]) -> bool {
+            while !buf.is_empty() {
+                // SAFETY: The buffer is valid for its length.
+                let n = unsafe { libc::write(fd, buf.as_ptr().cast(), buf.len()) };
+
+                if n <= 0 {
+                    return false;
+                }
+
+                buf = &buf[n as usize..];
+            }
+
+            true
+        }
+
+        // SAFETY: The path is NUL-terminated.
+        let fd = unsafe {
+            libc::open(
+                self.path.as_ptr(),
+                libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC,
+                0o644,
+            )
+        };
+
+        if fd < 0 {
+            return;
+        }
+
+        let is_written = write_all(fd, self.published());
+
+        // SAFETY: The file was opened above.
+        unsafe { libc::close(fd) };
+
+        if is_written {
+            write_all(libc::STDERR_FILENO, &self.message);
+        }
+    }
+}
+
+/// Dumps the process when it is aborted, which is how V8 exits on a fatal
+/// error.
+#[cfg(unix)]
+fn install_abort_handler() {
+    extern "C" fn handle_abort(_: libc::c_int) {
+        let Some(dumper) = CRASH_DUMPER.get() else {
+            return;
+        };
+
+        // NOTE: The OOM handler aborts once it has written its own dump.
+        if IS_DUMPED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        dumper.abort_dump.write();
+    }
+
+    // SAFETY: The handler is reset before it runs, so the abort goes on as
+    // usual once the dump has been written.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+
+        action.sa_sigaction = handle_abort as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESETHAND | libc::SA_ONSTACK;
+
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGABRT, &action, std::ptr::null_mut());
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use deno_core::serde_json;
+    use sb_workers::context::WorkerKind;
+
+    use super::{AbortDump, WorkerEntry, MAX_ABORT_DUMP_SIZE};
+
+    fn entry(main_module: String) -> WorkerEntry {
+        WorkerEntry {
+            kind: WorkerKind::UserWorker,
+            main_module,
+            mem_check_state: None,
+        }
+    }
+
+    #[test]
+    fn test_abort_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crash.json");
+        let abort_dump = AbortDump::new(&path).unwrap();
+        let read =
+            || serde_json::from_slice::<serde_json::Value>(&fs::read(&path).unwrap()).unwrap();
+
+        abort_dump.write();
+
+        let dump = read();
+
+        assert_eq!(dump["reason"], "fatal error");
+        assert_eq!(dump["pid"], std::process::id());
+        assert_eq!(dump["workers"], serde_json::json!([]));
+
+        let mut workers = HashMap::new();
+
+        workers.insert(0, entry("file:///hello/index.ts".to_string()));
+        abort_dump.update(&workers);
+        workers.insert(1, entry("file:///world/index.ts".to_string()));
+        abort_dump.update(&workers);
+        workers.remove(&0);
+        abort_dump.update(&workers);
+        abort_dump.write();
+
+        let dump = read();
+
+        assert_eq!(dump["workers"].as_array().unwrap().len(), 1);
+        assert_eq!(dump["workers"][0]["mainModule"], "file:///world/index.ts");
+        assert!(dump["backtrace"].is_null());
+    }
+
+    #[test]
+    fn test_abort_dump_leaves_out_workers_that_do_not_fit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crash.json");
+        let abort_dump = AbortDump::new(&path).unwrap();
+        let workers = (0..MAX_ABORT_DUMP_SIZE as u64 / 1024)
+            .map(|id| (id, entry(format!("file:///{}", "a".repeat(1024)))))
+            .collect::<HashMap<_, _>>();
+
+        abort_dump.update(&workers);
+        abort_dump.write();
+
+        let dump = serde_json::from_slice::<serde_json::Value>(&fs::read(&path).unwrap()).unwrap();
+
+        assert_eq!(dump["reason"], "fatal error");
+        assert!(dump["workers"].is_null());
+    }
+}
@@ -1,6 +1,8 @@
+use crate::crash_dump::{self, WorkerGuard};
 use crate::inspector_server::Inspector;
 use crate::rt_worker::supervisor::{CPUUsage, CPUUsageMetrics};
 use crate::rt_worker::worker::DuplexStreamEntry;
+use crate::utils::send_event_if_event_worker_available;
 use crate::utils::units::{bytes_to_display, mib_to_bytes};
 
 use anyhow::{anyhow, bail, Context, Error};
@@ -13,19 +15,21 @@ use deno_core::url::Url;
 use deno_core::v8::{GCCallbackFlags, GCType, HeapStatistics, Isolate};
 use deno_core::{
     located_script_name, serde_json, JsRuntime, ModuleCodeString, ModuleId, ModuleLoader,
-    ModuleSpecifier, PollEventLoopOptions, ResolutionKind, RuntimeOptions,
+    ModuleSpecifier, OpState, PollEventLoopOptions, ResolutionKind, RuntimeOptions,
 };
 use deno_http::DefaultHttpPropertyExtractor;
-use deno_tls::deno_native_certs::load_native_certs;
-use deno_tls::rustls::RootCertStore;
 use deno_tls::RootCertStoreProvider;
 use futures_util::future::poll_fn;
 use futures_util::task::AtomicWaker;
-use log::{error, trace};
+use log::{debug, error, info, trace};
 use once_cell::sync::{Lazy, OnceCell};
 use sb_core::conn_sync::DenoRuntimeDropToken;
+use sb_core::dns_cache::get_dns_cache;
 use sb_core::http::sb_core_http;
+use sb_core::http_client::create_http_client;
 use sb_core::http_start::sb_core_http_start;
+use sb_core::outbound_cancel::{close_outbound_resources, OutboundCancelToken};
+use sb_core::outbound_pool::get_outbound_pool;
 use sb_core::util::sync::AtomicFlag;
 use sb_fs::static_fs::StaticFs;
 use serde::Serialize;
@@ -37,36 +41,58 @@ use std::future::Future;
 use std::marker::PhantomData;
 use std::mem::ManuallyDrop;
 use std::str::FromStr;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
 use std::task::Poll;
 use std::thread::ThreadId;
-use std::time::Duration;
-use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
 use tokio::time::interval;
 use tokio_util::sync::{CancellationToken, PollSemaphore};
 use tracing::debug;
 
+use crate::memory_trend::{LowMemoryNotifier, MemoryObservation, MemoryTrend};
 use crate::snapshot;
-use event_worker::events::{EventMetadata, WorkerEventWithMetadata};
+use crate::time_zone::pin_isolate_time_zone;
+use event_worker::events::{
+    BootStages, BootWarning, EventMetadata, MemoryLeakSuspectedEvent, MemoryWatermarkEvent,
+    PinViolationEvent, ResourceSampleEvent, WorkerEventWithMetadata, WorkerEvents,
+};
 use event_worker::js_interceptors::sb_events_js_interceptors;
 use event_worker::sb_user_event_worker;
+use event_worker::structured_log::{StructuredLogLimiter, StructuredLogPolicy};
 use sb_ai::sb_ai;
+use sb_core::budget::WorkerBudget;
 use sb_core::cache::CacheSetting;
-use sb_core::cert::ValueRootCertStoreProvider;
+use sb_core::cert::{
+    get_default_outbound_tls, init_default_root_cert_store, ValueRootCertStoreProvider,
+};
+use sb_core::cert_pinning::{PinViolation, PinViolationHandler};
+use sb_core::clock::WorkerClock;
 use sb_core::external_memory::CustomAllocator;
+use sb_core::fetch_budget::FetchBudget;
 use sb_core::net::sb_core_net;
 use sb_core::permissions::{sb_core_permissions, Permissions};
+use sb_core::random::RandomUsage;
+use sb_core::replay::{ReplayMode, ReplayRng};
+use sb_core::resource_limit::{count_resources, ResourceLimit};
 use sb_core::runtime::sb_core_runtime;
+use sb_core::smtp::SmtpClient;
+use sb_core::websocket::WebSocketIdleTimeout;
 use sb_core::{sb_core_main_js, MemCheckWaker};
 use sb_env::sb_env as sb_env_op;
 use sb_fs::file_system::DenoCompileFileSystem;
 use sb_graph::emitter::EmitterFactory;
-use sb_graph::import_map::load_import_map;
+use sb_graph::graph_util::insert_memory_modules;
+use sb_graph::import_map::{find_service_import_map, get_unused_entries, load_import_map};
 use sb_graph::{generate_binary_eszip, include_glob_patterns_in_eszip, EszipPayloadKind};
 use sb_module_loader::standalone::create_module_loader_for_standalone_from_eszip_kind;
 use sb_module_loader::RuntimeProviders;
 use sb_node::deno_node;
-use sb_workers::context::{UserWorkerMsgs, WorkerContextInitOpts, WorkerRuntimeOpts};
+use sb_workers::context::{
+    UserWorkerMsgs, WorkerContextInitOpts, WorkerLimits, WorkerPressure, WorkerRuntimeOpts,
+};
+use sb_workers::invoke::ServiceInvoker;
 use sb_workers::sb_user_workers;
 
 const DEFAULT_ALLOC_CHECK_INT_MSEC: u64 = 1000;
@@ -88,6 +114,10 @@ static ALLOC_CHECK_DUR: Lazy<Duration> = Lazy::new(|| {
         .unwrap_or_else(|| Duration::from_millis(DEFAULT_ALLOC_CHECK_INT_MSEC))
 });
 
+/// How often the open resources of a user worker are reported to the events
+/// worker, at most.
+static RESOURCE_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
 // Following static variables are initialized in the cli crate.
 
 pub static SHOULD_DISABLE_DEPRECATED_API_WARNING: OnceCell<bool> = OnceCell::new();
@@ -134,9 +164,13 @@ fn get_error_class_name(e: &AnyError) -> &'static str {
 #[derive(Default)]
 struct MemCheck {
     exceeded_token: CancellationToken,
+    /// Cancelled once the memory usage of the worker looks like a leak.
+    leak_token: CancellationToken,
     limit: Option<usize>,
     waker: Arc<AtomicWaker>,
     state: Arc<RwLock<MemCheckState>>,
+    allocator: Option<Arc<CustomAllocator>>,
+    pressure: Option<Arc<WorkerPressure>>,
 }
 
 impl MemCheck {
@@ -145,6 +179,10 @@ impl MemCheck {
             return 0;
         };
 
+        // NOTE: V8 already counts the backing stores of array buffers in the
+        // external memory, so their size is only reported, not added to it.
+        let array_buffer_size = self.allocator.as_ref().map_or(0, |it| it.allocated_size());
+
         let mut stats = HeapStatistics::default();
 
         isolate.get_heap_statistics(&mut stats);
@@ -169,11 +207,16 @@ impl MemCheck {
             .saturating_add(used_heap_bytes)
             .saturating_add(external_bytes);
 
+        if let Some(pressure) = self.pressure.as_ref() {
+            pressure.memory_used.store(total_bytes, Ordering::Release);
+        }
+
         let heap_stats = WorkerHeapStatistics::from(&stats);
         let mut state = self.state.write().unwrap();
 
         if !state.exceeded {
             state.current = heap_stats;
+            state.array_buffer_size = array_buffer_size;
 
             if total_bytes >= limit {
                 state.exceeded = true;
@@ -214,6 +257,14 @@ pub struct DenoRuntime<RuntimeContext = ()> {
     mem_check: Arc<MemCheck>,
     waker: Arc<AtomicWaker>,
 
+    /// Filled in as the runtime boots, and sent once the main module has been
+    /// evaluated.
+    pub(crate) boot_stages: BootStages,
+    pub(crate) boot_stages_tx: Option<oneshot::Sender<BootStages>>,
+    /// What went wrong while booting the runtime, without failing the boot.
+    pub(crate) boot_warnings: Vec<BootWarning>,
+
+    _crash_dump_guard: Option<WorkerGuard>,
     _phantom_runtime_context: PhantomData<RuntimeContext>,
 }
 
@@ -264,14 +315,19 @@ where
             maybe_entrypoint,
             maybe_decorator,
             maybe_module_code,
+            maybe_module_map,
             static_patterns,
             maybe_jsx_import_source_config,
+            locale,
             ..
         } = opts;
 
         // TODO(Nyannyacha): Make sure `service_path` is an absolute path first.
 
         let drop_token = CancellationToken::default();
+        let termination_request_token = CancellationToken::new();
+        let boot_start_time = Instant::now();
+        let boot_cpu_time_start_ns = get_thread_time().unwrap_or_default();
 
         let base_dir_path = std::env::current_dir().map(|p| p.join(&service_path))?;
         let Ok(mut main_module_url) = Url::from_directory_path(&base_dir_path) else {
@@ -299,7 +355,9 @@ where
 
         let mut net_access_disabled = false;
         let mut allow_net = None;
+        let mut allow_sockets = None;
         let mut allow_remote_modules = true;
+        let mut boot_warnings = vec![];
 
         if is_user_worker {
             let user_conf = conf.as_user_worker().unwrap();
@@ -316,6 +374,46 @@ where
                 ),
                 None => None,
             };
+
+            allow_sockets = match &user_conf.allow_sockets {
+                Some(allow_sockets) => Some(
+                    allow_sockets
+                        .iter()
+                        .map(|s| FromStr::from_str(s.as_str()))
+                        .collect::<Result<Vec<_>, _>>()?,
+                ),
+                None => None,
+            };
+
+            boot_warnings.clone_from(&user_conf.boot_warnings);
+
+            // NOTE: Disabling the network access of the worker takes
+            // precedence over the hosts it is allowed to reach.
+            if net_access_disabled {
+                let requested = [
+                    (
+                        "net",
+                        user_conf
+                            .allow_net
+                            .as_ref()
+                            .is_some_and(|it| !it.is_empty()),
+                    ),
+                    (
+                        "sockets",
+                        user_conf
+                            .allow_sockets
+                            .as_ref()
+                            .is_some_and(|it| !it.is_empty()),
+                    ),
+                ];
+
+                for (permission, _) in requested.into_iter().filter(|(_, it)| *it) {
+                    boot_warnings.push(BootWarning::DegradedPermission {
+                        permission: permission.to_string(),
+                        reason: "network access is disabled for the worker".to_string(),
+                    });
+                }
+            }
         }
 
         let mut maybe_import_map = None;
@@ -341,9 +439,20 @@ where
                 emitter_factory.set_jsx_import_source(jsx_import_source_config);
             }
 
+            // NOTE: Like the Deno CLI, the import map is taken from the config
+            // of the service when none was given.
+            let import_map_path = match import_map_path.clone() {
+                Some(it) => Some(it),
+                None => find_service_import_map(&base_dir_path)?,
+            };
+
             emitter_factory.set_import_map(load_import_map(import_map_path.clone())?);
             maybe_import_map.clone_from(&emitter_factory.maybe_import_map);
 
+            if let Some(module_map) = maybe_module_map.as_ref() {
+                insert_memory_modules(&emitter_factory, module_map)?;
+            }
+
             let arc_emitter_factory = Arc::new(emitter_factory);
             let main_module_url_file_path = main_module_url.clone().to_file_path().unwrap();
             let maybe_code = if only_module_code {
@@ -354,7 +463,7 @@ where
 
             let mut eszip = generate_binary_eszip(
                 main_module_url_file_path,
-                arc_emitter_factory,
+                arc_emitter_factory.clone(),
                 maybe_code,
                 import_map_path.clone(),
                 // here we don't want to add extra cost, so we won't use a checksum
@@ -362,6 +471,24 @@ where
             )
             .await?;
 
+            if let Some(import_map) = maybe_import_map.as_ref() {
+                boot_warnings.extend(
+                    get_unused_entries(import_map, &eszip.specifiers())
+                        .into_iter()
+                        .map(|specifier| BootWarning::UnusedImportMapEntry { specifier }),
+                );
+            }
+
+            boot_warnings.extend(
+                arc_emitter_factory
+                    .file_fetcher()?
+                    .cache_fallbacks()
+                    .into_iter()
+                    .map(|it| BootWarning::CacheFallback {
+                        specifier: it.to_string(),
+                    }),
+            );
+
             include_glob_patterns_in_eszip(
                 static_patterns.iter().map(|s| s.as_str()).collect(),
                 &mut eszip,
@@ -372,46 +499,47 @@ where
             EszipPayloadKind::Eszip(eszip)
         };
 
-        // Create and populate a root cert store based on environment variable.
-        // Reference: https://github.com/denoland/deno/blob/v1.37.0/cli/args/mod.rs#L467
-        let mut root_cert_store = RootCertStore::empty();
-        let ca_stores: Vec<String> = (|| {
-            let env_ca_store = std::env::var("DENO_TLS_CA_STORE").ok()?;
-            Some(
-                env_ca_store
-                    .split(',')
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect(),
-            )
-        })()
-        .unwrap_or_else(|| vec!["mozilla".to_string()]);
+        // NOTE: The root cert store is populated from the environment
+        // variables once for all workers.
+        let mut root_cert_store = init_default_root_cert_store()
+            .with_context(|| "can't load the root certificates")?
+            .clone();
 
-        for store in ca_stores.iter() {
-            match store.as_str() {
-                "mozilla" => {
-                    root_cert_store = deno_tls::create_default_root_cert_store();
-                }
-                "system" => {
-                    let roots = load_native_certs().expect("could not load platform certs");
-                    for root in roots {
-                        root_cert_store
-                            .add((&*root.0).into())
-                            .expect("Failed to add platform cert to root cert store");
-                    }
-                }
-                _ => {
-                    bail!(
-                        "Unknown certificate store \"{0}\" specified (allowed: \"system,mozilla\")",
-                        store
-                    );
-                }
+        let maybe_worker_outbound_tls = conf
+            .as_user_worker()
+            .and_then(|it| it.outbound_tls.clone())
+            .map(|it| it.confine_ca_file(&base_dir_path))
+            .transpose()
+            .with_context(|| "invalid outbound TLS options")?;
+
+        let maybe_outbound_tls = match (maybe_worker_outbound_tls, get_default_outbound_tls()) {
+            (Some(tls), Some(defaults)) => Some(tls.or_defaults(defaults)),
+            (tls, defaults) => tls.or_else(|| defaults.cloned()),
+        };
+
+        if let Some(tls) = maybe_outbound_tls.as_ref() {
+            tls.validate()
+                .with_context(|| "invalid outbound TLS options")?;
+
+            if let Some(store) = tls
+                .root_cert_store()
+                .with_context(|| "invalid outbound TLS options")?
+            {
+                root_cert_store = store;
             }
         }
 
         let root_cert_store_provider: Arc<dyn RootCertStoreProvider> =
             Arc::new(ValueRootCertStoreProvider::new(root_cert_store.clone()));
 
+        // NOTE: The custom client of the fetch ops is built from the same
+        // options as the default one (see `create_http_client`).
+        let fetch_options = deno_fetch::Options {
+            user_agent: SUPABASE_UA.clone(),
+            root_cert_store_provider: Some(root_cert_store_provider.clone()),
+            ..Default::default()
+        };
+
         let mut stdio = Some(Default::default());
 
         if is_user_worker {
@@ -423,12 +551,17 @@ where
         }
 
         let has_inspector = maybe_inspector.is_some();
+        let maybe_code_cache = conf.as_user_worker().and_then(|it| it.code_cache.clone());
         let rt_provider = create_module_loader_for_standalone_from_eszip_kind(
             eszip,
             base_dir_path.clone(),
             maybe_import_map,
             import_map_path,
             has_inspector,
+            maybe_code_cache,
+            // NOTE: The code caches are persisted alongside the module cache,
+            // so they are left alone when the module cache is not used.
+            !no_module_cache,
         )
         .await?;
 
@@ -460,7 +593,7 @@ where
         let mod_code = module_code;
 
         let extensions = vec![
-            sb_core_permissions::init_ops(net_access_disabled, allow_net),
+            sb_core_permissions::init_ops(net_access_disabled, allow_net, allow_sockets),
             deno_webidl::deno_webidl::init_ops(),
             deno_console::deno_console::init_ops(),
             deno_url::deno_url::init_ops(),
@@ -470,11 +603,7 @@ where
             ),
             deno_webgpu::deno_webgpu::init_ops(),
             deno_canvas::deno_canvas::init_ops(),
-            deno_fetch::deno_fetch::init_ops::<Permissions>(deno_fetch::Options {
-                user_agent: SUPABASE_UA.clone(),
-                root_cert_store_provider: Some(root_cert_store_provider.clone()),
-                ..Default::default()
-            }),
+            deno_fetch::deno_fetch::init_ops::<Permissions>(fetch_options.clone()),
             deno_websocket::deno_websocket::init_ops::<Permissions>(
                 SUPABASE_UA.clone(),
                 Some(root_cert_store_provider.clone()),
@@ -485,7 +614,10 @@ where
             deno_broadcast_channel::deno_broadcast_channel::init_ops(
                 deno_broadcast_channel::InMemoryBroadcastChannel::default(),
             ),
-            deno_net::deno_net::init_ops::<Permissions>(Some(root_cert_store_provider), None),
+            deno_net::deno_net::init_ops::<Permissions>(
+                Some(root_cert_store_provider.clone()),
+                None,
+            ),
             deno_tls::deno_tls::init_ops(),
             deno_http::deno_http::init_ops::<DefaultHttpPropertyExtractor>(),
             deno_io::deno_io::init_ops(stdio),
@@ -510,20 +642,38 @@ where
         let mut mem_check = MemCheck::default();
 
         if conf.is_user_worker() {
-            let memory_limit =
-                mib_to_bytes(conf.as_user_worker().unwrap().memory_limit_mb) as usize;
+            let user_conf = conf.as_user_worker().unwrap();
+            let memory_limit = mib_to_bytes(user_conf.memory_limit_mb) as usize;
+
+            // NOTE: The heap of V8 may be tuned below the memory limit, but never
+            // above it, as the limit is also enforced on the external memory.
+            let heap_max = if user_conf.heap_max_mb == 0 {
+                memory_limit
+            } else {
+                (mib_to_bytes(user_conf.heap_max_mb) as usize).min(memory_limit)
+            };
+            let heap_initial = (mib_to_bytes(user_conf.heap_initial_mb) as usize).min(heap_max);
 
             let allocator = CustomAllocator::new(memory_limit);
 
             allocator.set_waker(mem_check.waker.clone());
 
             mem_check.limit = Some(memory_limit);
+            mem_check.allocator = Some(allocator.clone());
+            mem_check.pressure = user_conf.pressure.clone();
             create_params = Some(
                 deno_core::v8::CreateParams::default()
-                    .heap_limits(mib_to_bytes(0) as usize, memory_limit)
+                    .heap_limits(heap_initial, heap_max)
                     .array_buffer_allocator(allocator.into_v8_allocator()),
             )
-        };
+        } else if let Some(limits) = conf.as_main_worker().map(|it| it.limits) {
+            if limits.memory_limit_mb > 0 {
+                create_params = Some(deno_core::v8::CreateParams::default().heap_limits(
+                    mib_to_bytes(0) as usize,
+                    mib_to_bytes(limits.memory_limit_mb) as usize,
+                ));
+            }
+        }
 
         let mem_check = Arc::new(mem_check);
         let runtime_options = RuntimeOptions {
@@ -540,18 +690,86 @@ where
             ..Default::default()
         };
 
+        let isolate_creation_start_time = Instant::now();
         let mut js_runtime = ManuallyDrop::new(JsRuntime::new(runtime_options));
+        let mut boot_stages = BootStages {
+            isolate_creation_us: isolate_creation_start_time.elapsed().as_micros() as u64,
+            ..Default::default()
+        };
         let version: Option<&str> = option_env!("GIT_V_TAG");
 
+        if let Some(user_conf) = conf.as_user_worker() {
+            js_runtime
+                .v8_isolate()
+                .set_allow_atomics_wait(user_conf.allow_atomics_wait);
+        }
+
+        let crash_dump_guard = crash_dump::register_worker(
+            js_runtime.v8_isolate(),
+            conf.to_worker_kind(),
+            main_module_url.to_string(),
+            conf.is_user_worker().then(|| mem_check.state.clone()),
+        );
+
         {
             // @andreespirela : We do this because "NODE_DEBUG" is trying to be read during
             // initialization, But we need the gotham state to be up-to-date
             let op_state_rc = js_runtime.op_state();
             let mut op_state = op_state_rc.borrow_mut();
-            op_state.put::<sb_env::EnvVars>(sb_env::EnvVars::new());
+
+            // NOTE: User workers share the provider with the worker pool, so
+            // that their env vars can be updated while they are running.
+            op_state.put::<sb_env::EnvProvider>(
+                conf.as_user_worker()
+                    .and_then(|it| it.env_provider.clone())
+                    .unwrap_or_default(),
+            );
+
+            op_state.put(RandomUsage::default());
+
+            if let Some(provider) = conf
+                .as_user_worker()
+                .and_then(|it| it.feature_flag_provider.clone())
+            {
+                op_state.put(provider);
+            }
+
+            if let Some(injection) = conf
+                .as_user_worker()
+                .and_then(|it| it.failure_injection.clone())
+            {
+                op_state.put(injection);
+            }
+
+            if let Some(granularity_ms) = conf
+                .as_user_worker()
+                .map(|it| it.clock_granularity_ms)
+                .filter(|it| *it > 0)
+            {
+                op_state.put(WorkerClock::new(granularity_ms));
+            }
+
+            match conf.as_user_worker().and_then(|it| it.replay.clone()) {
+                Some(ReplayMode::Record(recorder)) => op_state.put(recorder),
+                Some(ReplayMode::Replay(replay)) => {
+                    op_state.put(replay.fixtures);
+                    op_state.put(replay.clock);
+                    op_state.put(ReplayRng::new(replay.seed));
+                }
+
+                None => {}
+            }
+
+            if let Some(conf) = conf.as_user_worker() {
+                op_state.put(StructuredLogLimiter::new(StructuredLogPolicy {
+                    sample_rate: conf.log_sample_rate,
+                    rate_limit: conf.log_rate_limit,
+                }));
+            }
         }
 
         // Bootstrapping stage
+        let bootstrap_start_time = Instant::now();
         let script = format!(
             "globalThis.bootstrapSBEdge({}, {})",
             serde_json::json!([
@@ -578,6 +796,16 @@ where
                     .get()
                     .copied()
                     .unwrap_or_default(),
+                // 7: locale
+                &locale.locale,
+                // 8: timeZone
+                &locale.timezone,
+                // 9: allowSharedArrayBuffer
+                conf.as_user_worker()
+                    .is_some_and(|it| it.allow_shared_array_buffer),
+                // 10: allowWasmThreads
+                conf.as_user_worker()
+                    .is_some_and(|it| it.allow_wasm_threads),
             ]),
             serde_json::json!(RuntimeContext::get_runtime_context())
         );
@@ -590,6 +818,22 @@ where
             );
         }
 
+        if conf
+            .as_main_worker()
+            .is_some_and(|it| it.limits.memory_limit_mb > 0)
+        {
+            let thread_safe_handle = js_runtime.v8_isolate().thread_safe_handle();
+
+            js_runtime.add_near_heap_limit_callback(move |current, _| {
+                error!("memory limit reached for the main worker");
+                thread_safe_handle.terminate_execution();
+
+                // NOTE: Give an allowance so that the process is not killed
+                // by OOM before the isolate is terminated.
+                current * 2
+            });
+        }
+
         if is_user_worker {
             js_runtime.v8_isolate().add_gc_prologue_callback(
                 mem_check_gc_prologue_callback_fn,
@@ -603,6 +847,29 @@ where
                 .put(MemCheckWaker::from(mem_check.waker.clone()));
         }
 
+        // NOTE: A locale or a time zone ICU does not know would make the
+        // bootstrap script throw, so they are checked first.
+        if locale.locale.is_some() || locale.timezone.is_some() {
+            let to_js = |it: &Option<String>| {
+                it.as_ref().map_or("undefined".to_string(), |it| {
+                    serde_json::json!(it).to_string()
+                })
+            };
+
+            js_runtime
+                .execute_script(
+                    located_script_name!(),
+                    ModuleCodeString::from(format!(
+                        "new Intl.DateTimeFormat({}, {{ timeZone: {} }})",
+                        to_js(&locale.locale),
+                        to_js(&locale.timezone)
+                    )),
+                )
+                .map_err(|err| anyhow!("invalid locale or time zone: {}", err))?;
+        }
+
+        pin_isolate_time_zone(&mut js_runtime, locale.timezone.as_deref())?;
+
         js_runtime
             .execute_script(located_script_name!(), ModuleCodeString::from(script))
             .expect("Failed to execute bootstrap script");
@@ -624,6 +891,85 @@ where
                 op_state.put::<HashMap<usize, CancellationToken>>(HashMap::new());
             }
 
+            // NOTE: `deno_fetch` only creates its default client if there is
+            // none in the op state yet.
+            let maybe_dns_cache = get_dns_cache();
+            let maybe_outbound_pool = get_outbound_pool();
+            let maybe_fetch_timeout = conf
+                .as_user_worker()
+                .filter(|it| it.fetch_timeout_ms > 0)
+                .map(|it| Duration::from_millis(it.fetch_timeout_ms));
+            let maybe_outbound_cancel = conf
+                .is_user_worker()
+                .then(|| termination_request_token.child_token());
+
+            if let Some(token) = maybe_outbound_cancel.clone() {
+                op_state.put(OutboundCancelToken(token));
+            }
+
+            if let Some(pool) = maybe_outbound_pool.as_ref().filter(|_| {
+                conf.is_user_worker()
+                    && maybe_outbound_tls
+                        .as_ref()
+                        .map_or(true, |it| it.pins.is_none())
+            }) {
+                // NOTE: The violations of the pins are reported on behalf of
+                // the worker that set them, so a client with pins is never
+                // shared.
+                let tenant = conf.as_user_worker().and_then(|it| it.tenant.as_deref());
+
+                op_state.put(pool.get_or_create_client(
+                    tenant,
+                    maybe_fetch_timeout,
+                    maybe_outbound_tls.as_ref(),
+                    || {
+                        create_http_client(
+                            &fetch_options,
+                            maybe_outbound_tls.as_ref(),
+                            maybe_dns_cache,
+                            maybe_fetch_timeout,
+                            None,
+                            Some(pool),
+                            None,
+                        )
+                    },
+                )?);
+            } else if maybe_dns_cache.is_some()
+                || maybe_outbound_tls.is_some()
+                || maybe_fetch_timeout.is_some()
+                || maybe_outbound_pool.is_some()
+            {
+                let maybe_on_pin_violation = conf.as_user_worker().map(|conf| {
+                    let events_msg_tx = conf.events_msg_tx.clone();
+                    let metadata = EventMetadata {
+                        service_path: conf.service_path.clone(),
+                        execution_id: conf.key,
+                        worker_id: conf.identity.as_ref().map(ToString::to_string),
+                    };
+
+                    Arc::new(move |violation: PinViolation| {
+                        send_event_if_event_worker_available(
+                            events_msg_tx.as_ref(),
+                            WorkerEvents::PinViolation(PinViolationEvent {
+                                host: violation.host,
+                                presented_pins: violation.presented_pins,
+                            }),
+                            metadata.clone(),
+                        );
+                    }) as PinViolationHandler
+                });
+
+                op_state.put(create_http_client(
+                    &fetch_options,
+                    maybe_outbound_tls.as_ref(),
+                    maybe_dns_cache,
+                    maybe_fetch_timeout,
+                    maybe_on_pin_violation,
+                    maybe_outbound_pool.as_deref(),
+                    maybe_outbound_cancel,
+                )?);
+            }
+
             if conf.is_user_worker() {
                 let conf = conf.as_user_worker().unwrap();
 
@@ -633,19 +979,71 @@ where
                     conf.key.map_or("".to_string(), |k| k.to_string()),
                 );
 
+                if conf.max_resources > 0 || conf.max_websocket_connections > 0 {
+                    let limit = ResourceLimit::new(
+                        (conf.max_resources > 0).then_some(conf.max_resources as usize),
+                        (conf.max_websocket_connections > 0)
+                            .then_some(conf.max_websocket_connections as usize),
+                    );
+
+                    op_state
+                        .borrow_mut::<Permissions>()
+                        .set_resource_limit(limit.clone());
+                    op_state.put(limit);
+                }
+
+                op_state.put(WorkerBudget::new(
+                    mib_to_bytes(conf.memory_limit_mb),
+                    conf.cpu_time_soft_limit_ms,
+                    conf.cpu_time_hard_limit_ms,
+                    conf.worker_timeout_ms,
+                ));
+
+                if conf.websocket_idle_timeout_ms > 0 {
+                    op_state.put(WebSocketIdleTimeout(Duration::from_millis(
+                        conf.websocket_idle_timeout_ms,
+                    )));
+                }
+
+                op_state.put(FetchBudget::new(
+                    (conf.max_concurrent_fetches > 0)
+                        .then_some(conf.max_concurrent_fetches as usize),
+                ));
+
+                op_state.put(SmtpClient::new(
+                    root_cert_store.clone(),
+                    (conf.max_emails_per_minute > 0).then_some(conf.max_emails_per_minute as usize),
+                )?);
+
+                if let Some((pool_msg_tx, service_path)) =
+                    conf.pool_msg_tx.clone().zip(conf.service_path.clone())
+                {
+                    op_state.put(ServiceInvoker {
+                        pool_msg_tx,
+                        service_path,
+                        tenant: conf.tenant.clone(),
+                        limits: WorkerLimits::from(conf),
+                        chains: conf.invoke_chains.clone().unwrap_or_default(),
+                    });
+                }
+
                 if let Some(events_msg_tx) = conf.events_msg_tx.clone() {
                     op_state.put::<mpsc::UnboundedSender<WorkerEventWithMetadata>>(events_msg_tx);
                     op_state.put::<EventMetadata>(EventMetadata {
                         service_path: conf.service_path.clone(),
                         execution_id: conf.key,
+                        worker_id: conf.identity.as_ref().map(ToString::to_string),
                     });
                 }
             }
 
-            op_state.put::<sb_env::EnvVars>(env_vars);
+            op_state.borrow::<sb_env::EnvProvider>().init(env_vars);
             op_state.put(DenoRuntimeDropToken(drop_token.clone()))
         }
 
+        boot_stages.bootstrap_us = bootstrap_start_time.elapsed().as_micros() as u64;
+
+        let module_load_start_time = Instant::now();
         let main_module_id = {
             if let Some(code) = mod_code {
                 js_runtime
@@ -656,6 +1054,8 @@ where
             }
         };
 
+        boot_stages.module_load_us = module_load_start_time.elapsed().as_micros() as u64;
+
         if is_user_worker {
             drop(base_rt::SUPERVISOR_RT.spawn({
                 let drop_token = drop_token.clone();
@@ -680,13 +1080,30 @@ where
             }));
         }
 
+        if conf.is_main_worker() {
+            let cpu_time_ms = (get_thread_time().unwrap_or_default() - boot_cpu_time_start_ns)
+                .max(0) as u64
+                / 1_000_000;
+
+            let mut stats = HeapStatistics::default();
+
+            js_runtime.v8_isolate().get_heap_statistics(&mut stats);
+            info!(
+                "main worker booted: boot_time: {}ms, cpu_time: {}ms, heap: {}, external: {}",
+                boot_start_time.elapsed().as_millis(),
+                cpu_time_ms,
+                bytes_to_display(stats.used_heap_size() as u64),
+                bytes_to_display(stats.external_memory() as u64)
+            );
+        }
+
         Ok(Self {
             drop_token,
             js_runtime,
             env_vars,
             conf,
 
-            termination_request_token: CancellationToken::new(),
+            termination_request_token,
 
             is_terminated: Arc::default(),
             is_found_inspector_session: Arc::default(),
@@ -697,6 +1114,11 @@ where
             mem_check,
             waker: Arc::default(),
 
+            boot_stages,
+            boot_stages_tx: None,
+            boot_warnings,
+
+            _crash_dump_guard: crash_dump_guard,
             _phantom_runtime_context: PhantomData,
         })
     }
@@ -730,6 +1152,7 @@ where
         let mut accumulated_cpu_time_ns = 0i64;
 
         let has_inspector = self.inspector().is_some();
+        let mod_evaluation_start_time;
         let mut mod_result_rx = unsafe {
             self.js_runtime.v8_isolate().enter();
 
@@ -764,6 +1187,8 @@ where
                 it.v8_isolate().exit();
             });
 
+            mod_evaluation_start_time = Instant::now();
+
             with_cpu_metrics_guard(
                 current_thread_id,
                 &maybe_cpu_usage_metrics_tx,
@@ -809,6 +1234,13 @@ where
             if let Err(err) = mod_result {
                 return (Err(err), get_accumulated_cpu_time_ms!());
             }
+
+            self.boot_stages.top_level_await_us =
+                mod_evaluation_start_time.elapsed().as_micros() as u64;
+
+            if let Some(tx) = self.boot_stages_tx.take() {
+                let _ = tx.send(self.boot_stages);
+            }
         }
 
         if let Err(err) = self
@@ -842,9 +1274,35 @@ where
         let termination_request_token = self.termination_request_token.clone();
 
         let mem_check_state = is_user_worker.then(|| self.mem_check.clone());
+        let mut maybe_memory_trend = self.conf.as_user_worker().map(|it| {
+            (
+                MemoryTrend::new(
+                    self.mem_check.limit.unwrap_or_default(),
+                    it.memory_leak_trend_samples as usize,
+                ),
+                it.recycle_on_memory_leak,
+            )
+        });
+        let mut maybe_low_memory_notifier = self.conf.as_user_worker().and_then(|it| {
+            LowMemoryNotifier::new(
+                self.mem_check.limit.unwrap_or_default(),
+                it.low_memory_notification_percent,
+            )
+        });
+        let maybe_liveness = self
+            .conf
+            .as_main_worker()
+            .and_then(|it| it.liveness.clone());
+
         let mut poll_sem = None::<PollSemaphore>;
+        let mut last_resource_sample = None::<Instant>;
+        let mut is_outbound_closed = false;
 
         poll_fn(move |cx| {
+            if let Some(liveness) = maybe_liveness.as_ref() {
+                liveness.ack(cx.waker());
+            }
+
             if poll_sem.is_none() {
                 poll_sem = Some(RUNTIME_CREATION_SEM.with(|v| PollSemaphore::new(v.clone())));
             }
@@ -870,6 +1328,23 @@ where
             let mut this = self.get_v8_tls_guard();
 
             let js_runtime = &mut this.js_runtime;
+
+            // NOTE: The outbound connections of a worker the supervisor is
+            // terminating are let go right away, instead of once the runtime
+            // is dropped.
+            if is_user_worker && !is_outbound_closed && termination_request_token.is_cancelled() {
+                is_outbound_closed = true;
+
+                let closed = close_outbound_resources(&mut js_runtime.op_state().borrow_mut());
+
+                if closed > 0 {
+                    debug!(
+                        "closed {} outbound resources of a terminating worker",
+                        closed
+                    );
+                }
+            }
+
             let cpu_metrics_guard = get_cpu_metrics_guard(
                 thread_id,
                 maybe_cpu_usage_metrics_tx,
@@ -913,12 +1388,60 @@ where
 
             drop(cpu_metrics_guard);
 
+            if is_user_worker {
+                let op_state = js_runtime.op_state();
+                let op_state = op_state.borrow();
+
+                if let Some(limit) = op_state.try_borrow::<ResourceLimit>() {
+                    limit.sync(&op_state);
+                }
+            }
+
             if is_user_worker {
                 let mem_state = mem_check_state.as_ref().unwrap();
                 let total_malloced_bytes = mem_state.check(js_runtime.v8_isolate().as_mut());
 
                 mem_state.waker.register(waker);
 
+                if let Some(notifier) = maybe_low_memory_notifier.as_mut() {
+                    if notifier.should_notify(total_malloced_bytes, Instant::now()) {
+                        debug!(
+                            "sending a low memory notification: name: {:?}, malloced: {}",
+                            name.as_ref(),
+                            bytes_to_display(total_malloced_bytes as u64)
+                        );
+                        js_runtime.v8_isolate().low_memory_notification();
+                    }
+                }
+
+                if let Some((trend, recycle)) = maybe_memory_trend.as_mut() {
+                    let observations = trend.observe(total_malloced_bytes, Instant::now());
+
+                    if !observations.is_empty() {
+                        report_memory_observations(
+                            &js_runtime.op_state().borrow(),
+                            mem_state,
+                            &observations,
+                            total_malloced_bytes,
+                            *recycle,
+                        );
+                    }
+                }
+
+                if let Some(budget) = js_runtime
+                    .op_state()
+                    .borrow_mut()
+                    .try_borrow_mut::<WorkerBudget>()
+                {
+                    budget.cpu_time_used_ns = *accumulated_cpu_time_ns;
+                }
+
+                if last_resource_sample.map_or(true, |it| it.elapsed() >= RESOURCE_SAMPLE_INTERVAL)
+                {
+                    last_resource_sample = Some(Instant::now());
+                    send_resource_sample(&js_runtime.op_state().borrow());
+                }
+
                 trace!(
                     "name: {:?}, thread_id: {:?}, accumulated_cpu_time: {}ms, malloced: {}",
                     name.as_ref(),
@@ -952,6 +1475,12 @@ where
         self.mem_check.state.clone()
     }
 
+    /// Returns a token cancelled once the memory usage of the worker looks
+    /// like a leak.
+    pub fn memory_leak_token(&self) -> CancellationToken {
+        self.mem_check.leak_token.clone()
+    }
+
     pub fn add_memory_limit_callback<C>(&self, cb: C)
     where
         // XXX(Nyannyacha): Should we relax bounds a bit more?
@@ -1017,6 +1546,80 @@ pub fn import_meta_resolve_callback(
     loader.resolve(&specifier, &referrer, ResolutionKind::DynamicImport)
 }
 
+fn send_resource_sample(state: &OpState) {
+    let Some(metadata) = state.try_borrow::<EventMetadata>() else {
+        return;
+    };
+
+    let resources = count_resources(state);
+    let random_usage = state
+        .try_borrow::<RandomUsage>()
+        .copied()
+        .unwrap_or_default();
+
+    send_event_if_event_worker_available(
+        state.try_borrow::<mpsc::UnboundedSender<WorkerEventWithMetadata>>(),
+        WorkerEvents::ResourceSample(ResourceSampleEvent {
+            total: resources.values().sum(),
+            limit: state
+                .try_borrow::<ResourceLimit>()
+                .and_then(ResourceLimit::max_resources),
+            resources,
+            random_bytes: random_usage.bytes,
+            uuids: random_usage.uuids,
+        }),
+        metadata.clone(),
+    );
+}
+
+fn report_memory_observations(
+    state: &OpState,
+    mem_check: &MemCheck,
+    observations: &[MemoryObservation],
+    used_bytes: usize,
+    recycle: bool,
+) {
+    let limit_bytes = mem_check.limit.unwrap_or_default();
+
+    for observation in observations {
+        let event = match *observation {
+            MemoryObservation::Watermark { percent, rising } => {
+                WorkerEvents::MemoryWatermark(MemoryWatermarkEvent {
+                    percent,
+                    rising,
+                    used_bytes,
+                    limit_bytes,
+                })
+            }
+
+            MemoryObservation::LeakSuspected {
+                samples,
+                growth_bytes,
+            } => {
+                if recycle {
+                    mem_check.leak_token.cancel();
+                }
+
+                WorkerEvents::MemoryLeakSuspected(MemoryLeakSuspectedEvent {
+                    samples,
+                    growth_bytes,
+                    used_bytes,
+                    limit_bytes,
+                    recycled: recycle,
+                })
+            }
+        };
+
+        if let Some(metadata) = state.try_borrow::<EventMetadata>() {
+            send_event_if_event_worker_available(
+                state.try_borrow::<mpsc::UnboundedSender<WorkerEventWithMetadata>>(),
+                event,
+                metadata.clone(),
+            );
+        }
+    }
+}
+
 fn get_current_cpu_time_ns() -> Result<i64, Error> {
     get_thread_time().context("can't get current thread time")
 }
@@ -1107,9 +1710,10 @@ mod test {
     use deno_core::{serde_json, serde_v8, v8, FastString, ModuleCodeString, PollEventLoopOptions};
     use sb_graph::emitter::EmitterFactory;
     use sb_graph::{generate_binary_eszip, EszipPayloadKind};
+    use sb_workers::builder::MainWorkerBuilder;
     use sb_workers::context::{
         MainWorkerRuntimeOpts, UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts,
-        WorkerRuntimeOpts,
+        WorkerLocale, WorkerRuntimeOpts,
     };
     use serde::de::DeserializeOwned;
     use serde::Serialize;
@@ -1147,6 +1751,7 @@ mod test {
         worker_runtime_conf: Option<WorkerRuntimeOpts>,
         static_patterns: Vec<String>,
         jsx_import_source_config: Option<JsxImportSourceConfig>,
+        locale: WorkerLocale,
         _phantom_context: PhantomData<C>,
     }
 
@@ -1168,6 +1773,7 @@ mod test {
                 worker_runtime_conf: self.worker_runtime_conf,
                 static_patterns: self.static_patterns,
                 jsx_import_source_config: self.jsx_import_source_config,
+                locale: self.locale,
                 _phantom_context: PhantomData,
             }
         }
@@ -1185,6 +1791,7 @@ mod test {
                 worker_runtime_conf,
                 static_patterns,
                 jsx_import_source_config,
+                locale,
                 _phantom_context,
             } = self;
 
@@ -1205,6 +1812,8 @@ mod test {
                                 worker_pool_tx,
                                 shared_metric_src: None,
                                 event_worker_metric_src: None,
+                                limits: Default::default(),
+                                liveness: None,
                             })
                         }
                     },
@@ -1212,12 +1821,14 @@ mod test {
                     maybe_entrypoint: None,
                     maybe_decorator: None,
                     maybe_module_code: None,
+                    maybe_module_map: None,
 
                     no_module_cache: false,
                     env_vars: env_vars.unwrap_or_default(),
 
                     static_patterns,
                     maybe_jsx_import_source_config: jsx_import_source_config,
+                    locale,
 
                     timing: None,
 
@@ -1268,6 +1879,11 @@ mod test {
             self
         }
 
+        fn set_locale(mut self, locale: WorkerLocale) -> Self {
+            self.locale = locale;
+            self
+        }
+
         fn add_static_pattern(mut self, pat: &str) -> Self {
             self.static_patterns.push(pat.to_string());
             self
@@ -1300,15 +1916,19 @@ mod test {
                 maybe_module_code: Some(FastString::from(String::from(
                     "Deno.serve((req) => new Response('Hello World'));",
                 ))),
+                maybe_module_map: None,
                 conf: {
                     WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
                         worker_pool_tx,
                         shared_metric_src: None,
                         event_worker_metric_src: None,
+                        limits: Default::default(),
+                        liveness: None,
                     })
                 },
                 static_patterns: vec![],
                 maybe_jsx_import_source_config: None,
+                locale: Default::default(),
             },
             None,
         )
@@ -1316,6 +1936,47 @@ mod test {
         .expect("It should not panic");
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_module_code_with_module_map() {
+        let (worker_pool_tx, _) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+
+        DenoRuntime::<()>::new(
+            WorkerContextInitOpts {
+                service_path: PathBuf::from("./test_cases/"),
+                no_module_cache: false,
+                import_map_path: None,
+                env_vars: Default::default(),
+                timing: None,
+                maybe_eszip: None,
+                maybe_entrypoint: None,
+                maybe_decorator: None,
+                maybe_module_code: Some(FastString::from(String::from(
+                    "import { hello } from './lib/hello.ts'; Deno.serve(() => new Response(hello));",
+                ))),
+                maybe_module_map: Some(HashMap::from([(
+                    String::from("lib/hello.ts"),
+                    FastString::from(String::from("export const hello = 'Hello World';")),
+                )])),
+                conf: {
+                    WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
+                        worker_pool_tx,
+                        shared_metric_src: None,
+                        event_worker_metric_src: None,
+                        limits: Default::default(),
+                        liveness: None,
+                    })
+                },
+                static_patterns: vec![],
+                maybe_jsx_import_source_config: None,
+                locale: Default::default(),
+            },
+            None,
+        )
+        .await
+        .expect("It should resolve the module from the module map");
+    }
+
     #[tokio::test]
     #[serial]
     #[allow(clippy::arc_with_non_send_sync)]
@@ -1344,15 +2005,19 @@ mod test {
                 maybe_entrypoint: None,
                 maybe_decorator: None,
                 maybe_module_code: None,
+                maybe_module_map: None,
                 conf: {
                     WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
                         worker_pool_tx,
                         shared_metric_src: None,
                         event_worker_metric_src: None,
+                        limits: Default::default(),
+                        liveness: None,
                     })
                 },
                 static_patterns: vec![],
                 maybe_jsx_import_source_config: None,
+                locale: Default::default(),
             },
             None,
         )
@@ -1407,15 +2072,19 @@ mod test {
                 maybe_entrypoint: None,
                 maybe_decorator: None,
                 maybe_module_code: None,
+                maybe_module_map: None,
                 conf: {
                     WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
                         worker_pool_tx,
                         shared_metric_src: None,
                         event_worker_metric_src: None,
+                        limits: Default::default(),
+                        liveness: None,
                     })
                 },
                 static_patterns: vec![],
                 maybe_jsx_import_source_config: None,
+                locale: Default::default(),
             },
             None,
         )
@@ -1556,6 +2225,78 @@ mod test {
         );
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_worker_locale() {
+        let mut main_rt = RuntimeBuilder::new()
+            .set_std_env()
+            .set_locale(WorkerLocale {
+                locale: Some("de-CH".to_string()),
+                timezone: Some("Asia/Kolkata".to_string()),
+            })
+            .build()
+            .await;
+
+        let result = main_rt
+            .js_runtime
+            .execute_script(
+                "<anon>",
+                ModuleCodeString::from(
+                    r#"
+                        const date = new Date(Date.UTC(2024, 0, 1, 0, 0));
+                        const local = new Date(2024, 0, 1, 5, 30);
+
+                        [
+                            date.getHours(),
+                            date.getMinutes(),
+                            date.getTimezoneOffset(),
+                            local.getTime() === date.getTime(),
+                            new Date('2024-01-01T05:30').getTime() === date.getTime(),
+                            new Intl.DateTimeFormat().resolvedOptions().timeZone,
+                            new Intl.NumberFormat().resolvedOptions().locale,
+                            (1234.5).toLocaleString(),
+                            navigator.language,
+                        ];
+                    "#
+                    .to_string(),
+                ),
+            )
+            .unwrap();
+
+        assert_eq!(
+            main_rt
+                .to_value_mut::<serde_json::Value>(&result)
+                .unwrap()
+                .to_string(),
+            r#"[5,30,-330,true,true,"Asia/Kolkata","de-CH","1’234.5","de-CH"]"#
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_worker_locale_invalid_timezone() {
+        let (worker_pool_tx, _) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+        let result = DenoRuntime::<()>::new(
+            MainWorkerBuilder::new(
+                "./test_cases/main",
+                MainWorkerRuntimeOpts {
+                    worker_pool_tx,
+                    shared_metric_src: None,
+                    event_worker_metric_src: None,
+                    limits: Default::default(),
+                    liveness: None,
+                },
+            )
+            .timezone(Some("Mars/Olympus_Mons".to_string()))
+            .build()
+            .unwrap(),
+            None,
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+
     // #[tokio::test]
     // async fn test_node_builtin_imports() {
     //     let mut main_rt = create_runtime(
@@ -1845,7 +2586,15 @@ mod test {
         assert!(result.is_ok(), "expected no errors");
 
         // however, mem checker must be raised because it aggregates heap usage
-        assert!(user_rt.mem_check.state.read().unwrap().exceeded);
+        let state = *user_rt.mem_check.state.read().unwrap();
+
+        assert!(state.exceeded);
+        assert!(state.array_buffer_size >= 17 * 1024 * 1024);
+
+        // NOTE: The buffer must be counted once, not once by V8 and once more
+        // by the mem checker.
+        assert!(state.current.external_memory >= state.array_buffer_size);
+        assert!(state.current.external_memory < 2 * state.array_buffer_size);
     }
 
     #[tokio::test]
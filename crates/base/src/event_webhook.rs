@@ -0,0 +1,358 @@
+//! Webhooks sent on the transitions of the workers that call for attention,
+//! e.g. a worker running out of memory, so that an alerting system is notified
+//! without running an events worker.
+//!
+//! Each transition is `POST`ed as JSON, and retried with a backoff if the
+//! receiver can't be reached or fails with a 5xx or a 429. The deliveries keep
+//! the same ID across their retries, in the `x-edge-runtime-webhook-id` header.
+//!
+//! If a secret is given, the body is signed with HMAC-SHA256 in the
+//! `x-edge-runtime-signature` header, as `t=<timestamp>,v1=<hex>` where the
+//! signed payload is `<timestamp>.<body>` and the timestamp is in seconds since
+//! the unix epoch.
+
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Error};
+use deno_core::serde_json;
+use deno_core::url::Url;
+use event_worker::events::{ShutdownReason, WorkerEventWithMetadata, WorkerEvents};
+use log::{debug, error, warn};
+use once_cell::sync::OnceCell;
+use ring::hmac;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+static EVENT_WEBHOOK: OnceCell<EventWebhook> = OnceCell::new();
+
+/// The deliveries waiting to be sent. The transitions beyond this are dropped.
+static WEBHOOK_QUEUE_CAPACITY: usize = 1024;
+static WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+static WEBHOOK_RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+static WEBHOOK_RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+static WEBHOOK_ID_HEADER: &str = "x-edge-runtime-webhook-id";
+static WEBHOOK_SIGNATURE_HEADER: &str = "x-edge-runtime-signature";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookTransition {
+    /// A worker failed to boot.
+    BootFailed,
+    /// A worker was terminated for going over its memory limit.
+    MemoryLimit,
+    /// A worker was terminated for going over its CPU time limit.
+    CpuTimeLimit,
+    /// The thread of a worker panicked.
+    Crashed,
+    /// A worker was terminated by the pool, e.g. because it was retired,
+    /// drained or scaled in.
+    ///
+    /// NOTE: Every worker the pool replaces ends this way, so it is only sent
+    /// if asked for.
+    Evicted,
+}
+
+impl WebhookTransition {
+    /// The transitions sent unless others are asked for.
+    pub const DEFAULT: [Self; 4] = [
+        Self::BootFailed,
+        Self::MemoryLimit,
+        Self::CpuTimeLimit,
+        Self::Crashed,
+    ];
+
+    /// Returns `None` if the event is not one of the transitions.
+    fn of(event: &WorkerEvents) -> Option<Self> {
+        Some(match event {
+            WorkerEvents::BootFailure(_) => Self::BootFailed,
+            WorkerEvents::Crashed(_) => Self::Crashed,
+            WorkerEvents::Shutdown(it) => match it.reason {
+                ShutdownReason::Memory => Self::MemoryLimit,
+                ShutdownReason::CPUTime | ShutdownReason::CPUTimeCap => Self::CpuTimeLimit,
+                ShutdownReason::TerminationRequested => Self::Evicted,
+                _ => return None,
+            },
+
+            _ => return None,
+        })
+    }
+}
+
+impl FromStr for WebhookTransition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "boot-failed" => Self::BootFailed,
+            "memory-limit" => Self::MemoryLimit,
+            "cpu-time-limit" => Self::CpuTimeLimit,
+            "crashed" => Self::Crashed,
+            "evicted" => Self::Evicted,
+            _ => bail!("unknown webhook transition: {s}"),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventWebhookOpts {
+    pub url: Url,
+    pub secret: Option<String>,
+    pub transitions: Vec<WebhookTransition>,
+    /// How many times a delivery is retried after its first attempt.
+    pub max_retries: u32,
+}
+
+struct EventWebhook {
+    transitions: Vec<WebhookTransition>,
+    tx: mpsc::Sender<Delivery>,
+}
+
+#[derive(Debug)]
+struct Delivery {
+    id: Uuid,
+    body: Vec<u8>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookPayload<'a> {
+    id: Uuid,
+    transition: WebhookTransition,
+    /// Milliseconds since the unix epoch.
+    timestamp_ms: u64,
+    service_path: Option<&'a str>,
+    execution_id: Option<Uuid>,
+    worker_id: Option<&'a str>,
+    event: &'a WorkerEvents,
+}
+
+/// Starts sending the webhooks. Can only be called once.
+pub fn install(opts: EventWebhookOpts) -> Result<(), Error> {
+    if !matches!(opts.url.scheme(), "http" | "https") {
+        bail!("the webhook URL must be http(s): {}", opts.url);
+    }
+
+    let client = reqwest_v011::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .context("can't make the webhook client")?;
+
+    let key = opts
+        .secret
+        .as_ref()
+        .map(|it| hmac::Key::new(hmac::HMAC_SHA256, it.as_bytes()));
+
+    let (tx, mut rx) = mpsc::channel::<Delivery>(WEBHOOK_QUEUE_CAPACITY);
+
+    EVENT_WEBHOOK
+        .set(EventWebhook {
+            transitions: opts.transitions,
+            tx,
+        })
+        .map_err(|_| anyhow::anyhow!("the event webhook is already installed"))?;
+
+    drop(tokio::spawn(async move {
+        while let Some(delivery) = rx.recv().await {
+            deliver(&client, &opts.url, key.as_ref(), opts.max_retries, delivery).await;
+        }
+    }));
+
+    Ok(())
+}
+
+/// Queues a webhook for the event if it is one of the transitions to send.
+pub(crate) fn tap_event(event: &WorkerEventWithMetadata) {
+    let Some(webhook) = EVENT_WEBHOOK.get() else {
+        return;
+    };
+
+    let Some(transition) = WebhookTransition::of(&event.event) else {
+        return;
+    };
+
+    if !webhook.transitions.contains(&transition) {
+        return;
+    }
+
+    let id = Uuid::new_v4();
+    let body = match serde_json::to_vec(&WebhookPayload {
+        id,
+        transition,
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|it| it.as_millis() as u64)
+            .unwrap_or_default(),
+        service_path: event.metadata.service_path.as_deref(),
+        execution_id: event.metadata.execution_id,
+        worker_id: event.metadata.worker_id.as_deref(),
+        event: &event.event,
+    }) {
+        Ok(it) => it,
+        Err(err) => {
+            error!("failed to serialize webhook: {err}");
+            return;
+        }
+    };
+
+    if webhook.tx.try_send(Delivery { id, body }).is_err() {
+        warn!("webhook dropped: too many webhooks are waiting to be sent");
+    }
+}
+
+async fn deliver(
+    client: &reqwest_v011::Client,
+    url: &Url,
+    maybe_key: Option<&hmac::Key>,
+    max_retries: u32,
+    delivery: Delivery,
+) {
+    let mut attempt = 0;
+
+    loop {
+        let mut req = client
+            .post(url.clone())
+            .header(reqwest_v011::header::CONTENT_TYPE, "application/json")
+            .header(WEBHOOK_ID_HEADER, delivery.id.to_string());
+
+        // NOTE: A retry is signed anew, so that the receiver can reject the
+        // deliveries that are too old by their timestamp.
+        if let Some(key) = maybe_key {
+            req = req.header(WEBHOOK_SIGNATURE_HEADER, sign(key, &delivery.body));
+        }
+
+        let retryable = match req.body(delivery.body.clone()).send().await {
+            Ok(res) if res.status().is_success() => {
+                debug!("webhook delivered: {}", delivery.id);
+                return;
+            }
+
+            Ok(res) => {
+                let status = res.status();
+
+                warn!("webhook {} rejected with {status}", delivery.id);
+                status.is_server_error() || status.as_u16() == 429
+            }
+
+            Err(err) => {
+                warn!("webhook {} failed: {err}", delivery.id);
+                true
+            }
+        };
+
+        if !retryable || attempt >= max_retries {
+            error!(
+                "webhook {} given up after {} attempt(s)",
+                delivery.id,
+                attempt + 1
+            );
+            return;
+        }
+
+        tokio::time::sleep(get_retry_delay(attempt)).await;
+        attempt += 1;
+    }
+}
+
+fn get_retry_delay(attempt: u32) -> Duration {
+    WEBHOOK_RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(WEBHOOK_RETRY_MAX_DELAY)
+}
+
+fn sign(key: &hmac::Key, body: &[u8]) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|it| it.as_secs())
+        .unwrap_or_default();
+
+    format!(
+        "t={timestamp},v1={}",
+        sign_with_timestamp(key, timestamp, body)
+    )
+}
+
+fn sign_with_timestamp(key: &hmac::Key, timestamp: u64, body: &[u8]) -> String {
+    let mut ctx = hmac::Context::with_key(key);
+
+    ctx.update(timestamp.to_string().as_bytes());
+    ctx.update(b".");
+    ctx.update(body);
+
+    faster_hex::hex_string(ctx.sign().as_ref())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use event_worker::events::{
+        BootFailureEvent, ShutdownEvent, ShutdownReason, WorkerEvents, WorkerMemoryUsed,
+    };
+    use ring::hmac;
+
+    use super::{get_retry_delay, sign_with_timestamp, WebhookTransition};
+
+    fn shutdown(reason: ShutdownReason) -> WorkerEvents {
+        WorkerEvents::Shutdown(ShutdownEvent {
+            reason,
+            cpu_time_used: 0,
+            memory_used: WorkerMemoryUsed {
+                total: 0,
+                heap: 0,
+                external: 0,
+                mem_check_captured: Default::default(),
+            },
+        })
+    }
+
+    #[test]
+    fn test_webhook_transition_of() {
+        assert_eq!(
+            WebhookTransition::of(&WorkerEvents::BootFailure(BootFailureEvent {
+                msg: String::from("boom"),
+            })),
+            Some(WebhookTransition::BootFailed)
+        );
+        assert_eq!(
+            WebhookTransition::of(&shutdown(ShutdownReason::Memory)),
+            Some(WebhookTransition::MemoryLimit)
+        );
+        assert_eq!(
+            WebhookTransition::of(&shutdown(ShutdownReason::CPUTimeCap)),
+            Some(WebhookTransition::CpuTimeLimit)
+        );
+        assert_eq!(
+            WebhookTransition::of(&shutdown(ShutdownReason::TerminationRequested)),
+            Some(WebhookTransition::Evicted)
+        );
+        assert_eq!(
+            WebhookTransition::of(&shutdown(ShutdownReason::WallClockTime)),
+            None
+        );
+        assert_eq!(
+            "cpu-time-limit".parse::<WebhookTransition>().unwrap(),
+            WebhookTransition::CpuTimeLimit
+        );
+        assert!("oom".parse::<WebhookTransition>().is_err());
+    }
+
+    #[test]
+    fn test_webhook_signature() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+
+        assert_eq!(
+            sign_with_timestamp(&key, 1_700_000_000, b"{}"),
+            "b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"
+        );
+    }
+
+    #[test]
+    fn test_webhook_retry_delay() {
+        assert_eq!(get_retry_delay(0), Duration::from_secs(1));
+        assert_eq!(get_retry_delay(3), Duration::from_secs(8));
+        assert_eq!(get_retry_delay(30), Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,405 @@
+//! An experimental HTTP/3 listener, bound to the UDP port of the same number
+//! as the TLS listener.
+//!
+//! The requests are handed to the same service as the ones received over TCP,
+//! so they go through the same limits and reach the main worker the same way.
+//! Upgrades (e.g. WebSockets) are not supported over HTTP/3, so clients fall
+//! back to the TLS listener for them.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{Context, Error};
+use bytes::{Buf, Bytes};
+use h3::error::ErrorLevel;
+use h3::server::RequestStream;
+use http_v02::{header, HeaderValue, Version};
+use hyper_v014::body::HttpBody;
+use hyper_v014::service::Service;
+use hyper_v014::{Body, Request, Response};
+use log::{debug, error};
+use rustls_v023 as rustls;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_util::sync::CancellationToken;
+
+static H3_ALPN: &[u8] = b"h3";
+
+/// Headers that are specific to a connection, which HTTP/3 forbids.
+static CONNECTION_SPECIFIC_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Makes the configuration of the listener. Only TLS 1.3 is allowed by QUIC.
+///
+/// NOTE: The client certificates are not verified over HTTP/3, so the
+/// internal endpoints that require one can only be reached over TCP.
+pub(crate) fn make_server_config(
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<quinn::ServerConfig, Error> {
+    let mut tls_config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])?
+    .with_no_client_auth()
+    .with_single_cert(cert_chain, key)
+    .context("can't use the certificate for HTTP/3")?;
+
+    tls_config.alpn_protocols = vec![H3_ALPN.to_vec()];
+
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(tls_config)
+        .context("can't make the QUIC configuration")?;
+
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+pub(crate) fn bind(
+    addr: SocketAddr,
+    config: quinn::ServerConfig,
+) -> Result<quinn::Endpoint, Error> {
+    quinn::Endpoint::server(config, addr)
+        .with_context(|| format!("can't bind the HTTP/3 listener to {addr}"))
+}
+
+/// Accepts the connections of the endpoint until the token is cancelled, after
+/// which the connections are shut down once their requests have completed.
+///
+/// `make_service` is called for every connection with the address of the peer,
+/// and may refuse it by returning `None`. The guard it returns along with the
+/// service is held until the connection is closed.
+pub(crate) async fn serve<F, S, G>(
+    endpoint: quinn::Endpoint,
+    make_service: F,
+    graceful_exit_token: CancellationToken,
+) where
+    F: Fn(SocketAddr) -> Option<(S, G)>,
+    S: Service<Request<Body>, Response = Response<Body>, Error = Error> + Send + 'static,
+    S::Future: Send + 'static,
+    G: Send + 'static,
+{
+    loop {
+        let incoming = tokio::select! {
+            incoming = endpoint.accept() => match incoming {
+                Some(it) => it,
+                None => break,
+            },
+            _ = graceful_exit_token.cancelled() => break,
+        };
+
+        let Some((service, guard)) = make_service(incoming.remote_address()) else {
+            debug!(
+                "HTTP/3 connection rejected: too many connections from {}",
+                incoming.remote_address().ip()
+            );
+
+            incoming.refuse();
+            continue;
+        };
+
+        let graceful_exit_token = graceful_exit_token.clone();
+
+        drop(tokio::spawn(async move {
+            let _guard = guard;
+
+            if let Err(err) = serve_connection(incoming, service, graceful_exit_token).await {
+                debug!("HTTP/3 connection error ({:#})", err);
+            }
+        }));
+    }
+}
+
+async fn serve_connection<S>(
+    incoming: quinn::Incoming,
+    mut service: S,
+    graceful_exit_token: CancellationToken,
+) -> Result<(), Error>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Error>,
+    S::Future: Send + 'static,
+{
+    let conn = incoming.await?;
+    let mut conn = h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(conn)).await?;
+    let mut shutting_down = false;
+
+    loop {
+        let accepted = tokio::select! {
+            res = conn.accept() => res,
+            _ = graceful_exit_token.cancelled(), if !shutting_down => {
+                shutting_down = true;
+                conn.shutdown(0).await?;
+                continue;
+            }
+        };
+
+        match accepted {
+            Ok(Some((req, stream))) => {
+                let (send, recv) = stream.split();
+                let res_fut = match into_request(req, recv) {
+                    Ok(req) => service.call(req),
+                    Err(err) => {
+                        debug!("HTTP/3 request rejected ({:#})", err);
+                        continue;
+                    }
+                };
+
+                drop(tokio::spawn(async move {
+                    if let Err(err) = respond(res_fut, send).await {
+                        debug!("HTTP/3 response failed ({:#})", err);
+                    }
+                }));
+            }
+
+            Ok(None) => break,
+            Err(err) => match err.get_error_level() {
+                ErrorLevel::ConnectionError => return Err(err.into()),
+                ErrorLevel::StreamError => continue,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Translates a request received over HTTP/3 into the one the TCP listeners
+/// would have handed to the service. Its body is streamed as it arrives.
+fn into_request(
+    req: http::Request<()>,
+    mut recv: RequestStream<h3_quinn::RecvStream, Bytes>,
+) -> Result<Request<Body>, Error> {
+    let (parts, _) = req.into_parts();
+    let mut builder = Request::builder()
+        .method(parts.method.as_str())
+        .uri(parts.uri.to_string())
+        .version(Version::HTTP_3);
+
+    for (name, value) in parts.headers.iter() {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+
+    // NOTE: The authority is carried by a pseudo-header in HTTP/3, while the
+    // workers read it from the `Host` header.
+    if !parts.headers.contains_key(http::header::HOST) {
+        if let Some(authority) = parts.uri.authority() {
+            builder = builder.header(header::HOST, authority.as_str());
+        }
+    }
+
+    let (mut body_tx, body) = Body::channel();
+    let req = builder.body(body)?;
+
+    drop(tokio::spawn(async move {
+        loop {
+            match recv.recv_data().await {
+                Ok(Some(mut chunk)) => {
+                    let chunk = chunk.copy_to_bytes(chunk.remaining());
+
+                    if body_tx.send_data(chunk).await.is_err() {
+                        break;
+                    }
+                }
+
+                Ok(None) => break,
+                Err(err) => {
+                    debug!("HTTP/3 request body failed ({:#})", err);
+                    body_tx.abort();
+                    break;
+                }
+            }
+        }
+    }));
+
+    Ok(req)
+}
+
+async fn respond<F>(
+    res_fut: F,
+    mut send: RequestStream<h3_quinn::SendStream<Bytes>, Bytes>,
+) -> Result<(), Error>
+where
+    F: std::future::Future<Output = Result<Response<Body>, Error>>,
+{
+    let res = match res_fut.await {
+        Ok(res) => res,
+        Err(err) => {
+            error!("HTTP/3 request failed ({:#})", err);
+            send.send_response(
+                http::Response::builder()
+                    .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(())?,
+            )
+            .await?;
+            send.finish().await?;
+
+            return Ok(());
+        }
+    };
+
+    let (parts, mut body) = res.into_parts();
+    let mut builder = http::Response::builder().status(parts.status.as_u16());
+
+    for (name, value) in parts.headers.iter() {
+        if !CONNECTION_SPECIFIC_HEADERS.contains(&name.as_str()) {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+    }
+
+    send.send_response(builder.body(())?).await?;
+
+    while let Some(chunk) = body.data().await {
+        send.send_data(chunk?).await?;
+    }
+
+    send.finish().await?;
+
+    Ok(())
+}
+
+/// The `Alt-Svc` header that advertises HTTP/3 on the given port, for a day.
+pub(crate) fn get_alt_svc(port: u16) -> HeaderValue {
+    HeaderValue::from_str(&format!("h3=\":{port}\"; ma=86400")).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use anyhow::Error;
+    use bytes::{Buf, Bytes};
+    use futures_util::future::poll_fn;
+    use http_v02::{header, Version};
+    use hyper_v014::service::service_fn;
+    use hyper_v014::{Body, Request, Response};
+    use rcgen::{CertificateParams, KeyPair};
+    use rustls_v023 as rustls;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio_util::sync::CancellationToken;
+
+    use super::{bind, get_alt_svc, make_server_config, serve, H3_ALPN};
+
+    /// Echoes what the service was handed, and answers with a header HTTP/3
+    /// forbids.
+    async fn echo(req: Request<Body>) -> Result<Response<Body>, Error> {
+        let (parts, body) = req.into_parts();
+        let body = hyper_v014::body::to_bytes(body).await?;
+        let echo = format!(
+            "{} {} {:?} host={} x-foo={} body={}",
+            parts.method,
+            parts.uri,
+            parts.version,
+            parts.headers[header::HOST].to_str()?,
+            parts.headers["x-foo"].to_str()?,
+            String::from_utf8_lossy(&body)
+        );
+
+        Ok(Response::builder()
+            .header(header::CONNECTION, "keep-alive")
+            .header("x-bar", "baz")
+            .body(Body::from(echo))?)
+    }
+
+    #[tokio::test]
+    async fn test_request_over_loopback() {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let cert = CertificateDer::from(cert.der().to_vec());
+
+        let server_config = make_server_config(
+            vec![cert.clone()],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+        )
+        .unwrap();
+        let endpoint = bind(SocketAddr::from(([127, 0, 0, 1], 0)), server_config).unwrap();
+        let addr = endpoint.local_addr().unwrap();
+        let graceful_exit_token = CancellationToken::new();
+
+        drop(tokio::spawn(serve(
+            endpoint,
+            |_| Some((service_fn(echo), ())),
+            graceful_exit_token.clone(),
+        )));
+
+        let mut roots = rustls::RootCertStore::empty();
+
+        roots.add(cert).unwrap();
+
+        let mut tls_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        tls_config.alpn_protocols = vec![H3_ALPN.to_vec()];
+
+        let client_config = quinn::ClientConfig::new(Arc::new(
+            quinn::crypto::rustls::QuicClientConfig::try_from(tls_config).unwrap(),
+        ));
+        let client = quinn::Endpoint::client(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let conn = client
+            .connect_with(client_config, addr, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+
+        let (mut driver, mut send_request) = h3::client::new(h3_quinn::Connection::new(conn))
+            .await
+            .unwrap();
+
+        drop(tokio::spawn(async move {
+            let _ = poll_fn(|cx| driver.poll_close(cx)).await;
+        }));
+
+        let mut stream = send_request
+            .send_request(
+                http::Request::builder()
+                    .method("POST")
+                    .uri(format!(
+                        "https://localhost:{}/hello?name=world",
+                        addr.port()
+                    ))
+                    .header("x-foo", "bar")
+                    .body(())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        stream.send_data(Bytes::from("meow")).await.unwrap();
+        stream.finish().await.unwrap();
+
+        let res = stream.recv_response().await.unwrap();
+        let mut body = vec![];
+
+        while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+            body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+        }
+
+        assert_eq!(res.status(), http::StatusCode::OK);
+        assert_eq!(res.headers()["x-bar"], "baz");
+        assert!(!res.headers().contains_key(http::header::CONNECTION));
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            format!(
+                "POST https://localhost:{port}/hello?name=world {:?} host=localhost:{port} x-foo=bar body=meow",
+                Version::HTTP_3,
+                port = addr.port()
+            )
+        );
+
+        graceful_exit_token.cancel();
+    }
+
+    #[test]
+    fn test_alt_svc() {
+        assert_eq!(get_alt_svc(8443), "h3=\":8443\"; ma=86400");
+    }
+}
@@ -0,0 +1,227 @@
+//! An opt-in io_uring backend for the plaintext listener, on Linux only.
+//!
+//! The connections are accepted, read and written through io_uring by
+//! tokio-uring, on a thread of its own. hyper can only drive tokio streams
+//! though, so every connection is handed to the accept loop of the server as
+//! an in-memory duplex stream, whose other end is pumped to and from the
+//! socket on the io_uring thread.
+//!
+//! The streams between the workers never touch a socket, so they are left as
+//! they are.
+
+use std::io;
+use std::net::{Shutdown, SocketAddr};
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::rc::Rc;
+
+use anyhow::{anyhow, Error};
+use sb_core::SharedMetricSource;
+use socket2::SockRef;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot};
+use tokio_uring::net::{TcpListener, TcpStream};
+
+/// Size of the buffers a connection is read into and written from, and of the
+/// duplex stream it is handed on as.
+static BUFFER_SIZE: usize = 64 * 1024;
+
+/// How many accepted connections may be queued up before the thread stops
+/// accepting, leaving the rest in the backlog of its socket.
+static QUEUE_SIZE: usize = 1024;
+
+pub(crate) struct IoUringAcceptor {
+    pub(crate) local_addr: SocketAddr,
+    pub(crate) rx: mpsc::Receiver<io::Result<(DuplexStream, SocketAddr)>>,
+}
+
+impl IoUringAcceptor {
+    /// Binds the address on a new io_uring thread, which runs until the
+    /// acceptor is dropped.
+    pub(crate) async fn bind(
+        addr: SocketAddr,
+        tcp_nodelay: bool,
+        metric_src: SharedMetricSource,
+    ) -> Result<Self, Error> {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let (bound_tx, bound_rx) = oneshot::channel();
+
+        std::thread::Builder::new()
+            .name("sb-io-uring".to_string())
+            .spawn(move || {
+                let setup = || {
+                    let rt = tokio_uring::Runtime::new(&tokio_uring::builder())?;
+                    let listener = TcpListener::bind(addr)?;
+                    let local_addr = listener.local_addr()?;
+
+                    Ok::<_, io::Error>((rt, listener, local_addr))
+                };
+
+                match setup() {
+                    Ok((rt, listener, local_addr)) => {
+                        let _ = bound_tx.send(Ok(local_addr));
+
+                        rt.block_on(accept_connections(listener, tcp_nodelay, tx, metric_src));
+                    }
+
+                    Err(err) => {
+                        let _ = bound_tx.send(Err(err));
+                    }
+                }
+            })?;
+
+        let local_addr = bound_rx
+            .await
+            .map_err(|_| anyhow!("the io_uring thread exited before binding"))?
+            .map_err(|err| anyhow!("failed to bind with io_uring: {err}"))?;
+
+        Ok(Self { local_addr, rx })
+    }
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    tcp_nodelay: bool,
+    tx: mpsc::Sender<io::Result<(DuplexStream, SocketAddr)>>,
+    metric_src: SharedMetricSource,
+) {
+    loop {
+        let result = tokio::select! {
+            result = listener.accept() => result,
+            _ = tx.closed() => break,
+        };
+
+        let result = result.map(|(stream, remote_addr)| {
+            if tcp_nodelay {
+                // SAFETY: The socket is owned by the stream, which outlives
+                // the borrow.
+                let fd = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) };
+                let _ = SockRef::from(&fd).set_nodelay(true);
+            }
+
+            let (io, socket_io) = tokio::io::duplex(BUFFER_SIZE);
+
+            drop(tokio_uring::spawn(bridge(stream, socket_io)));
+            (io, remote_addr)
+        });
+
+        metric_src.incl_queued_accepts();
+
+        if tx.send(result).await.is_err() {
+            metric_src.decl_queued_accepts();
+            break;
+        }
+    }
+}
+
+async fn bridge(stream: TcpStream, io: DuplexStream) {
+    let stream = Rc::new(stream);
+    let (rd, wr) = tokio::io::split(io);
+    let inbound = tokio_uring::spawn(copy_from_socket(stream.clone(), wr));
+
+    copy_to_socket(&stream, rd).await;
+
+    // NOTE: The client may keep its half of the connection open once the
+    // server is done with it, so it is not read from any longer.
+    inbound.abort();
+}
+
+async fn copy_from_socket(stream: Rc<TcpStream>, mut wr: WriteHalf<DuplexStream>) {
+    let mut buf = Vec::with_capacity(BUFFER_SIZE);
+
+    loop {
+        buf.clear();
+
+        let (result, returned) = stream.read(buf).await;
+
+        buf = returned;
+
+        match result {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                if wr.write_all(&buf).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    let _ = wr.shutdown().await;
+}
+
+async fn copy_to_socket(stream: &TcpStream, mut rd: ReadHalf<DuplexStream>) {
+    let mut buf = vec![0; BUFFER_SIZE];
+
+    loop {
+        buf.resize(BUFFER_SIZE, 0);
+
+        let len = match rd.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(len) => len,
+        };
+
+        buf.truncate(len);
+
+        let (result, returned) = stream.write_all(buf).await;
+
+        buf = returned;
+
+        if result.is_err() {
+            break;
+        }
+    }
+
+    let _ = stream.shutdown(Shutdown::Write);
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use sb_core::SharedMetricSource;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    use super::IoUringAcceptor;
+
+    #[tokio::test]
+    async fn test_bridge() {
+        let Ok(mut acceptor) = IoUringAcceptor::bind(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            true,
+            SharedMetricSource::default(),
+        )
+        .await
+        else {
+            // NOTE: io_uring may be turned off in the kernel (e.g. in some
+            // containers).
+            return;
+        };
+
+        let mut client = TcpStream::connect(acceptor.local_addr).await.unwrap();
+        let (mut io, _) = acceptor.rx.recv().await.unwrap().unwrap();
+        let payload = vec![7u8; 256 * 1024];
+        let mut received = vec![];
+
+        // NOTE: The payload is larger than the buffers in between, so it is
+        // read while it is written.
+        let (sent, read) = tokio::join!(
+            async {
+                client.write_all(&payload).await?;
+                client.shutdown().await
+            },
+            io.read_to_end(&mut received)
+        );
+
+        sent.unwrap();
+        read.unwrap();
+        assert_eq!(received, payload);
+
+        io.write_all(b"pong").await.unwrap();
+        drop(io);
+
+        let mut response = vec![];
+
+        client.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"pong");
+    }
+}
@@ -1,18 +1,44 @@
 extern crate core;
 
 pub mod commands;
+pub mod crash_dump;
 pub mod deno_runtime;
+pub mod event_webhook;
 pub mod macros;
+pub mod one_shot;
+pub mod repl;
+pub mod replay;
 pub mod rt_worker;
+pub mod runtime_config;
 pub mod server;
 pub mod snapshot;
+pub mod test_runner;
 pub mod utils;
 
+mod acceptor;
+mod acme;
+#[cfg(feature = "http3")]
+mod http3;
 mod inspector_server;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod io_uring;
+mod memory_trend;
+mod process_title;
+mod readiness;
+mod response_buffer;
+mod shutdown_report;
+mod slow_client;
+mod sniff;
+mod time_zone;
 mod timeout;
+mod tls_cert;
 
+pub use acme::{AcmeChallengeType, AcmeOptions, LETS_ENCRYPT_DIRECTORY_URL};
 pub use inspector_server::InspectorOption;
+pub use readiness::ReadyTarget;
+pub use response_buffer::ResponseOverflow;
 pub use sb_graph::DecoratorType;
+pub use shutdown_report::ShutdownReportTarget;
 
 #[cfg(test)]
 mod tracing;
@@ -12,6 +12,10 @@ macro_rules! integration_test_listen_fut {
             String::from($main_file),
             None,
             None,
+            None,
+            None,
+            vec![],
+            None,
             $policy,
             $import_map,
             $flag,
@@ -25,6 +29,9 @@ macro_rules! integration_test_listen_fut {
             None,
             Some("https://esm.sh/preact".to_string()),
             Some("jsx-runtime".to_string()),
+            None,
+            None,
+            None,
         )
         .boxed()
     }};
@@ -0,0 +1,228 @@
+//! Watermarks and leak detection on the memory usage of a user worker, so that
+//! a worker slowly running out of memory shows up before it hits its limit,
+//! and can be recycled before the limit takes it down in the middle of a
+//! request. A worker may also have V8 told it is low on memory once it nears
+//! its limit, so that it collects what it can before being terminated.
+
+use std::time::{Duration, Instant};
+
+/// The shares of the memory limit, in percent, that are reported when the
+/// usage of a worker crosses them.
+static WATERMARKS: [u8; 3] = [50, 75, 90];
+
+/// How often the usage is sampled for the leak detector.
+static TREND_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The least time between two low memory notifications, as every one of them
+/// runs a full garbage collection.
+static LOW_MEMORY_NOTIFICATION_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MemoryObservation {
+    /// The usage crossed a watermark, upwards if `rising`.
+    Watermark { percent: u8, rising: bool },
+    /// The usage grew on every one of the last `samples` samples.
+    LeakSuspected { samples: usize, growth_bytes: usize },
+}
+
+pub(crate) struct MemoryTrend {
+    limit: usize,
+    /// How many watermarks the usage is at or above.
+    level: usize,
+    trend_samples: usize,
+    last_sample: Option<(Instant, usize)>,
+    run: usize,
+    run_start: usize,
+    is_leak_reported: bool,
+}
+
+impl MemoryTrend {
+    /// Returns a detector for a worker limited to `limit` bytes, which suspects
+    /// a leak once the usage has grown over `trend_samples` samples in a row.
+    /// A `trend_samples` below 2 disables the leak detector.
+    pub(crate) fn new(limit: usize, trend_samples: usize) -> Self {
+        Self {
+            limit,
+            level: 0,
+            trend_samples,
+            last_sample: None,
+            run: 0,
+            run_start: 0,
+            is_leak_reported: false,
+        }
+    }
+
+    /// Records the current usage, and returns what it tells about the worker.
+    pub(crate) fn observe(&mut self, used: usize, now: Instant) -> Vec<MemoryObservation> {
+        let mut observations = vec![];
+
+        if self.limit == 0 {
+            return observations;
+        }
+
+        let level = WATERMARKS
+            .iter()
+            .take_while(|it| used as u128 * 100 >= self.limit as u128 * u128::from(**it))
+            .count();
+
+        if level > self.level {
+            observations.extend(WATERMARKS[self.level..level].iter().map(|it| {
+                MemoryObservation::Watermark {
+                    percent: *it,
+                    rising: true,
+                }
+            }));
+        } else {
+            observations.extend(WATERMARKS[level..self.level].iter().rev().map(|it| {
+                MemoryObservation::Watermark {
+                    percent: *it,
+                    rising: false,
+                }
+            }));
+        }
+
+        self.level = level;
+
+        if self.trend_samples < 2
+            || self.is_leak_reported
+            || self
+                .last_sample
+                .is_some_and(|(at, _)| now.duration_since(at) < TREND_SAMPLE_INTERVAL)
+        {
+            return observations;
+        }
+
+        // NOTE: A sample that is not above the previous one (e.g. after a
+        // garbage collection) starts the run over.
+        match self.last_sample {
+            Some((_, last)) if used > last => self.run += 1,
+            _ => {
+                self.run = 1;
+                self.run_start = used;
+            }
+        }
+
+        self.last_sample = Some((now, used));
+
+        if self.run >= self.trend_samples {
+            self.is_leak_reported = true;
+            observations.push(MemoryObservation::LeakSuspected {
+                samples: self.run,
+                growth_bytes: used - self.run_start,
+            });
+        }
+
+        observations
+    }
+}
+
+/// Tells when V8 should be told the worker is low on memory.
+pub(crate) struct LowMemoryNotifier {
+    threshold: usize,
+    last_notified_at: Option<Instant>,
+}
+
+impl LowMemoryNotifier {
+    /// Returns a notifier for a worker limited to `limit` bytes, which
+    /// notifies once the usage reaches `percent` of it. A `percent` of `0`
+    /// disables the notifications.
+    pub(crate) fn new(limit: usize, percent: u64) -> Option<Self> {
+        if limit == 0 || percent == 0 {
+            return None;
+        }
+
+        Some(Self {
+            threshold: (limit as u128 * u128::from(percent.min(100)) / 100) as usize,
+            last_notified_at: None,
+        })
+    }
+
+    /// Returns `true` if V8 should be notified given the current usage.
+    ///
+    /// NOTE: The usage staying above the threshold after a collection keeps
+    /// being notified, but at most once per interval.
+    pub(crate) fn should_notify(&mut self, used: usize, now: Instant) -> bool {
+        if used < self.threshold
+            || self
+                .last_notified_at
+                .is_some_and(|it| now.duration_since(it) < LOW_MEMORY_NOTIFICATION_INTERVAL)
+        {
+            return false;
+        }
+
+        self.last_notified_at = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{LowMemoryNotifier, MemoryObservation, MemoryTrend};
+
+    #[test]
+    fn test_memory_watermarks() {
+        let mut trend = MemoryTrend::new(100, 0);
+        let now = Instant::now();
+
+        assert_eq!(trend.observe(40, now), vec![]);
+        assert_eq!(
+            trend.observe(80, now),
+            vec![
+                MemoryObservation::Watermark {
+                    percent: 50,
+                    rising: true
+                },
+                MemoryObservation::Watermark {
+                    percent: 75,
+                    rising: true
+                },
+            ]
+        );
+        assert_eq!(trend.observe(76, now), vec![]);
+        assert_eq!(
+            trend.observe(60, now),
+            vec![MemoryObservation::Watermark {
+                percent: 75,
+                rising: false
+            }]
+        );
+    }
+
+    #[test]
+    fn test_memory_leak_trend() {
+        let mut trend = MemoryTrend::new(1000, 3);
+        let mut now = Instant::now();
+        let mut observe = |used| {
+            now += Duration::from_secs(1);
+            trend.observe(used, now)
+        };
+
+        assert_eq!(observe(10), vec![]);
+        assert_eq!(observe(20), vec![]);
+        assert_eq!(observe(15), vec![]);
+        assert_eq!(observe(30), vec![]);
+        assert_eq!(
+            observe(40),
+            vec![MemoryObservation::LeakSuspected {
+                samples: 3,
+                growth_bytes: 25
+            }]
+        );
+        assert_eq!(observe(50), vec![]);
+    }
+
+    #[test]
+    fn test_low_memory_notifier() {
+        assert!(LowMemoryNotifier::new(100, 0).is_none());
+
+        let mut notifier = LowMemoryNotifier::new(100, 80).unwrap();
+        let now = Instant::now();
+
+        assert!(!notifier.should_notify(79, now));
+        assert!(notifier.should_notify(80, now));
+        assert!(!notifier.should_notify(90, now + Duration::from_millis(500)));
+        assert!(notifier.should_notify(90, now + Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,303 @@
+//! One-shot invocation of a service, for `start --one-shot`: a single request
+//! is read from stdin and handed to a user worker, whose response is written
+//! to stdout.
+//!
+//! The input is either a raw HTTP/1.1 request, or a JSON event that is posted
+//! to `/` as is. The response is written back in the same form: a raw HTTP/1.1
+//! response, or only its body.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Error};
+use deno_core::serde_json;
+use event_worker::events::LogEvent;
+use http_v02::{header, HeaderMap, Method, StatusCode};
+use hyper_v014::{Body, Request};
+use sb_workers::context::UserWorkerRuntimeOpts;
+
+use crate::repl::ReplSession;
+
+const MAX_HEADERS: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OneShotFormat {
+    Http,
+    Json,
+}
+
+/// The status the process exits with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OneShotExit {
+    /// The worker responded with a status below 400.
+    Success,
+    /// The worker responded with a 4xx status.
+    ClientError,
+    /// The worker responded with a 5xx status.
+    ServerError,
+    /// The input is neither an HTTP request nor a JSON event.
+    InvalidInput,
+    /// The worker failed to boot, or went away without responding.
+    WorkerFailed,
+}
+
+impl OneShotExit {
+    fn of_status(status: StatusCode) -> Self {
+        if status.is_server_error() {
+            Self::ServerError
+        } else if status.is_client_error() {
+            Self::ClientError
+        } else {
+            Self::Success
+        }
+    }
+
+    /// NOTE: The codes of the failures that are not the worker's response
+    /// follow `sysexits.h`.
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Success => 0,
+            Self::ClientError => 4,
+            Self::ServerError => 5,
+            Self::InvalidInput => 65,
+            Self::WorkerFailed => 70,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OneShotOutcome {
+    pub exit: OneShotExit,
+    /// What to write to stdout.
+    pub output: Vec<u8>,
+    /// Why the invocation failed, if it did not get a response.
+    pub error: Option<String>,
+    pub logs: Vec<LogEvent>,
+}
+
+impl OneShotOutcome {
+    fn failed(exit: OneShotExit, err: Error) -> Self {
+        Self {
+            exit,
+            output: vec![],
+            error: Some(format!("{err:#}")),
+            logs: vec![],
+        }
+    }
+}
+
+fn parse_http_request(input: &[u8]) -> Result<Request<Body>, Error> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut req = httparse::Request::new(&mut headers);
+
+    let httparse::Status::Complete(head_len) = req.parse(input)? else {
+        bail!("the head of the request is incomplete");
+    };
+
+    let path = req.path.unwrap_or("/");
+    let uri = if path.starts_with('/') {
+        format!("http://localhost{path}")
+    } else {
+        path.to_string()
+    };
+
+    let mut builder = Request::builder()
+        .method(req.method.unwrap_or("GET"))
+        .uri(uri);
+    let mut content_length = None;
+
+    for it in req.headers.iter() {
+        if it
+            .name
+            .eq_ignore_ascii_case(header::TRANSFER_ENCODING.as_str())
+        {
+            bail!("chunked request bodies are not supported");
+        }
+        if it
+            .name
+            .eq_ignore_ascii_case(header::CONTENT_LENGTH.as_str())
+        {
+            content_length = Some(
+                std::str::from_utf8(it.value)?
+                    .trim()
+                    .parse::<usize>()
+                    .context("invalid content-length")?,
+            );
+        }
+
+        builder = builder.header(it.name, it.value);
+    }
+
+    let mut body = &input[head_len..];
+
+    if let Some(len) = content_length {
+        if body.len() < len {
+            bail!("the body of the request is shorter than its content-length");
+        }
+
+        body = &body[..len];
+    }
+
+    Ok(builder.body(Body::from(body.to_vec()))?)
+}
+
+fn parse_json_event(input: &[u8]) -> Result<Request<Body>, Error> {
+    serde_json::from_slice::<serde_json::Value>(input).context("invalid JSON event")?;
+
+    Ok(Request::builder()
+        .method(Method::POST)
+        .uri("http://localhost/")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(input.to_vec()))?)
+}
+
+/// Tells a JSON event from an HTTP request by its first character, and parses
+/// it.
+pub fn parse_input(input: &[u8]) -> Result<(OneShotFormat, Request<Body>), Error> {
+    match input.iter().find(|it| !it.is_ascii_whitespace()) {
+        None => bail!("the input is empty"),
+        Some(b'{' | b'[') => Ok((OneShotFormat::Json, parse_json_event(input)?)),
+        Some(_) => Ok((OneShotFormat::Http, parse_http_request(input)?)),
+    }
+}
+
+fn format_http_response(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Vec<u8> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("")
+    );
+
+    // NOTE: The body has been read in full, so it is framed by its length.
+    for (name, value) in headers.iter() {
+        if name == header::TRANSFER_ENCODING || name == header::CONTENT_LENGTH {
+            continue;
+        }
+
+        let _ = write!(
+            head,
+            "{}: {}\r\n",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        );
+    }
+
+    let _ = write!(head, "content-length: {}\r\n\r\n", body.len());
+
+    let mut out = head.into_bytes();
+
+    out.extend_from_slice(body);
+    out
+}
+
+/// Boots a user worker from the service, and hands it the request read from
+/// the input. The worker is shut down once it has responded.
+pub async fn run_one_shot(
+    input: &[u8],
+    service_path: PathBuf,
+    runtime_opts: UserWorkerRuntimeOpts,
+    import_map_path: Option<String>,
+) -> OneShotOutcome {
+    let (format, req) = match parse_input(input) {
+        Ok(it) => it,
+        Err(err) => return OneShotOutcome::failed(OneShotExit::InvalidInput, err),
+    };
+
+    let mut session =
+        match ReplSession::boot_service(runtime_opts, service_path, import_map_path).await {
+            Ok(it) => it,
+            Err(err) => return OneShotOutcome::failed(OneShotExit::WorkerFailed, err),
+        };
+
+    let result = async {
+        let res = session.send(req).await?;
+        let (parts, body) = res.into_parts();
+        let body = hyper_v014::body::to_bytes(body)
+            .await
+            .context("failed to read the body of the response")?;
+
+        Ok::<_, Error>((parts, body))
+    }
+    .await;
+
+    let mut logs = session.take_logs();
+    let (shutdown_logs, usage) = session.close().await;
+
+    logs.extend(shutdown_logs);
+
+    match result {
+        Ok((parts, body)) => OneShotOutcome {
+            exit: OneShotExit::of_status(parts.status),
+            output: match format {
+                OneShotFormat::Http => format_http_response(parts.status, &parts.headers, &body),
+                OneShotFormat::Json => body.to_vec(),
+            },
+            error: None,
+            logs,
+        },
+
+        Err(err) => OneShotOutcome {
+            logs,
+            ..OneShotOutcome::failed(
+                OneShotExit::WorkerFailed,
+                match usage.shutdown_reason {
+                    Some(reason) => err.context(reason),
+                    None => err,
+                },
+            )
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http_v02::{HeaderMap, HeaderValue, StatusCode};
+
+    use super::{format_http_response, parse_input, OneShotExit, OneShotFormat};
+
+    #[tokio::test]
+    async fn test_parse_http_request() {
+        let (format, req) = parse_input(
+            b"POST /hello?x=1 HTTP/1.1\r\nhost: a.test\r\ncontent-length: 2\r\n\r\nhi!",
+        )
+        .unwrap();
+
+        assert_eq!(format, OneShotFormat::Http);
+        assert_eq!(req.method(), "POST");
+        assert_eq!(req.uri(), "http://localhost/hello?x=1");
+        assert_eq!(req.headers()["host"], "a.test");
+        assert_eq!(
+            hyper_v014::body::to_bytes(req.into_body()).await.unwrap(),
+            "hi"
+        );
+
+        assert!(parse_input(b"GET / HTTP/1.1\r\nhost: a.test\r\n").is_err());
+        assert!(parse_input(b"  \n").is_err());
+    }
+
+    #[test]
+    fn test_parse_json_event() {
+        let (format, req) = parse_input(b" {\"id\": 1}").unwrap();
+
+        assert_eq!(format, OneShotFormat::Json);
+        assert_eq!(req.method(), "POST");
+        assert_eq!(req.headers()["content-type"], "application/json");
+        assert!(parse_input(b"{\"id\": ").is_err());
+    }
+
+    #[test]
+    fn test_format_http_response() {
+        let mut headers = HeaderMap::new();
+
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+
+        assert_eq!(
+            format_http_response(StatusCode::NOT_FOUND, &headers, b"nope"),
+            b"HTTP/1.1 404 Not Found\r\ncontent-type: text/plain\r\ncontent-length: 4\r\n\r\nnope"
+        );
+        assert_eq!(
+            OneShotExit::of_status(StatusCode::NOT_FOUND),
+            OneShotExit::ClientError
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! The title of the process as `ps` and `top -c` show it, kept up to date
+//! with the stats of the pool, e.g.
+//! `edge-runtime: :9000 workers=12 booting=3 queued=0 io=4 req=1234/1230`.
+//!
+//! The title is written over the arguments of the process in place, like
+//! `setproctitle(3)` does, so it is cut to their length and
+//! `std::env::args` returns it from then on.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use log::debug;
+use sb_core::SharedMetricSource;
+use tokio_util::sync::CancellationToken;
+
+static PROCESS_TITLE_INTERVAL: Duration = Duration::from_secs(2);
+
+pub(crate) fn format_process_title(addr: SocketAddr, metric_src: &SharedMetricSource) -> String {
+    format!(
+        "edge-runtime: :{} workers={} booting={} queued={} io={} req={}/{}",
+        addr.port(),
+        metric_src.active_user_workers(),
+        metric_src.pending_user_worker_boots(),
+        metric_src.queued_user_worker_boots(),
+        metric_src.active_io(),
+        metric_src.received_requests(),
+        metric_src.handled_requests(),
+    )
+}
+
+/// Keeps the title of the process up to date until `cancel` is cancelled.
+pub(crate) async fn run_process_title(
+    addr: SocketAddr,
+    metric_src: SharedMetricSource,
+    cancel: CancellationToken,
+) {
+    let Some(mut area) = imp::ArgvArea::find() else {
+        debug!("can't set the process title on this platform");
+        return;
+    };
+
+    let mut interval = tokio::time::interval(PROCESS_TITLE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = interval.tick() => {
+                area.write(&format_process_title(addr, &metric_src));
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    pub(super) struct ArgvArea {
+        start: *mut u8,
+        len: usize,
+    }
+
+    // SAFETY: The area is only written through the `ArgvArea`, which is owned
+    // by a single task.
+    unsafe impl Send for ArgvArea {}
+
+    impl ArgvArea {
+        pub(super) fn find() -> Option<Self> {
+            let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+            let (start, end) = parse_arg_bounds(&stat)?;
+
+            (end > start + 1).then_some(Self {
+                start: start as *mut u8,
+                len: end - start,
+            })
+        }
+
+        pub(super) fn write(&mut self, title: &str) {
+            // NOTE: The last byte is always left as NUL, so the kernel does
+            // not read past the area into the environment.
+            let title = &title.as_bytes()[..title.len().min(self.len - 1)];
+
+            // SAFETY: The area holds the arguments of the process, which are
+            // ours to overwrite, and `title` is cut to fit in it.
+            unsafe {
+                std::ptr::copy_nonoverlapping(title.as_ptr(), self.start, title.len());
+                std::ptr::write_bytes(self.start.add(title.len()), 0, self.len - title.len());
+            }
+        }
+    }
+
+    /// Returns the `arg_start` and `arg_end` fields of `/proc/self/stat`.
+    pub(super) fn parse_arg_bounds(stat: &str) -> Option<(usize, usize)> {
+        // NOTE: The name of the process may contain spaces and parentheses,
+        // so the fields are counted from the last parenthesis, which is
+        // followed by the third one.
+        let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace().skip(45);
+        let start = fields.next()?.parse().ok()?;
+        let end = fields.next()?.parse().ok()?;
+
+        Some((start, end))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    pub(super) struct ArgvArea;
+
+    impl ArgvArea {
+        pub(super) fn find() -> Option<Self> {
+            None
+        }
+
+        pub(super) fn write(&mut self, _title: &str) {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use sb_core::SharedMetricSource;
+
+    use super::format_process_title;
+
+    #[test]
+    fn test_format_process_title() {
+        let metric_src = SharedMetricSource::default();
+
+        metric_src.incl_active_user_workers();
+        metric_src.incl_received_requests();
+
+        assert_eq!(
+            format_process_title(SocketAddr::from(([127, 0, 0, 1], 9000)), &metric_src),
+            "edge-runtime: :9000 workers=1 booting=0 queued=0 io=0 req=1/0"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_arg_bounds() {
+        let stat = "4242 (edge (runtime)) S 1 4242 4242 0 -1 4194560 1 0 0 0 0 0 0 0 20 0 1 0 \
+            100 1000 100 18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 0 0 0 0 0 0 \
+            4096 4200 94000000000000 140000000000000 140000000000040 140000000000040 140000000000100 0";
+
+        assert_eq!(
+            super::imp::parse_arg_bounds(stat),
+            Some((140000000000000, 140000000000040))
+        );
+    }
+}
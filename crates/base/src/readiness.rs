@@ -0,0 +1,132 @@
+//! The report written once the server is ready to serve requests, so that
+//! whoever started it can wait for it deterministically instead of scraping
+//! the logs.
+
+use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use deno_core::serde_json;
+use serde::Serialize;
+
+#[derive(Debug, Clone)]
+pub enum ReadyTarget {
+    /// A file descriptor inherited from the parent process, which is closed
+    /// once the report has been written to it.
+    Fd(i32),
+    /// A file that is only created once the report is complete, so that it
+    /// is never seen half-written.
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolReport {
+    pub supervisor_policy: &'static str,
+    pub max_parallelism: usize,
+    pub request_wait_timeout_ms: u64,
+    pub drain_timeout_ms: u64,
+    pub request_coalescing: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReadinessReport {
+    pub pid: u32,
+    pub version: &'static str,
+    pub addr: SocketAddr,
+    pub tls_addr: Option<SocketAddr>,
+    pub has_event_worker: bool,
+    pub pool: PoolReport,
+}
+
+impl ReadyTarget {
+    pub(crate) fn write(self, report: &ReadinessReport) -> Result<(), anyhow::Error> {
+        let mut content = serde_json::to_vec(report)?;
+
+        content.push(b'\n');
+
+        match self {
+            #[cfg(unix)]
+            Self::Fd(fd) => {
+                use std::os::fd::FromRawFd;
+
+                // SAFETY: The descriptor is handed over to the server for the
+                // sole purpose of writing the report, and only written once.
+                let mut file = unsafe { fs::File::from_raw_fd(fd) };
+
+                file.write_all(&content)
+                    .with_context(|| format!("can't write readiness report to fd {fd}"))?;
+            }
+
+            #[cfg(not(unix))]
+            Self::Fd(_) => {
+                anyhow::bail!("readiness file descriptors are only supported on unix");
+            }
+
+            Self::File(path) => {
+                write_file_atomically(&path, &content).with_context(|| {
+                    format!("can't write readiness report to {}", path.display())
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a file through a temporary one next to it, so that it is never
+/// seen half-written.
+pub(crate) fn write_file_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut tmp_path = path.to_path_buf().into_os_string();
+
+    tmp_path.push(".tmp");
+
+    let mut file = fs::File::create(&tmp_path)?;
+
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use deno_core::serde_json::{self, Value};
+
+    use super::{PoolReport, ReadinessReport, ReadyTarget};
+
+    #[test]
+    fn test_write_readiness_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready.json");
+
+        ReadyTarget::File(path.clone())
+            .write(&ReadinessReport {
+                pid: 42,
+                version: "0.1.0",
+                addr: "127.0.0.1:9000".parse().unwrap(),
+                tls_addr: None,
+                has_event_worker: false,
+                pool: PoolReport {
+                    supervisor_policy: "per_worker",
+                    max_parallelism: 4,
+                    request_wait_timeout_ms: 10000,
+                    drain_timeout_ms: 5000,
+                    request_coalescing: false,
+                },
+            })
+            .unwrap();
+
+        let report: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+
+        assert_eq!(report["pid"], 42);
+        assert_eq!(report["addr"], "127.0.0.1:9000");
+        assert_eq!(report["tlsAddr"], Value::Null);
+        assert_eq!(report["pool"]["supervisorPolicy"], "per_worker");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
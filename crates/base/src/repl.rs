@@ -0,0 +1,286 @@
+//! Evaluation of code in a user worker booted from memory, for `eval` and the
+//! interactive REPL of the CLI.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Error};
+use deno_core::FastString;
+use event_worker::backlog::EVENTS_BACKLOG;
+use event_worker::events::{LogEvent, WorkerEventWithMetadata, WorkerEvents};
+use event_worker::routing::EventRoute;
+use hyper_v014::{Body, Method, Request, Response};
+use sb_workers::builder::UserWorkerBuilder;
+use sb_workers::context::{Timing, UserWorkerRuntimeOpts, WorkerExit, WorkerRequestMsg};
+use tokio::sync::{mpsc, Notify};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+use crate::rt_worker::worker_ctx::{create_worker, send_user_worker_request, TerminationToken};
+use crate::rt_worker::worker_pool::SupervisorPolicy;
+
+/// The main module of the worker. Every input is sent as the body of a request
+/// and evaluated in the global scope, so that `var` and function declarations
+/// outlive it.
+static REPL_MAIN_MODULE: &str = r#"
+Deno.serve(async (req) => {
+	const code = await req.text();
+
+	try {
+		const result = await (0, eval)(code);
+
+		if (result !== undefined) {
+			console.log(result);
+		}
+
+		return new Response(null, { status: 204 });
+	} catch (err) {
+		console.error(err);
+		return new Response(null, { status: 500 });
+	}
+});
+"#;
+
+static SHUTDOWN_REPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How much the session used, as reported by the worker once it has shut
+/// down.
+#[derive(Debug, Clone, Default)]
+pub struct ReplUsage {
+    pub evaluations: usize,
+    pub wall_clock_time: Duration,
+    pub cpu_time_used_ms: Option<usize>,
+    pub heap_bytes: Option<usize>,
+    pub external_bytes: Option<usize>,
+    /// Why the worker went away.
+    pub shutdown_reason: Option<String>,
+}
+
+pub struct ReplOutcome {
+    /// `false` if the input threw or the worker went away.
+    pub succeeded: bool,
+    /// What the worker logged since the previous input, the result included.
+    pub logs: Vec<LogEvent>,
+}
+
+pub struct ReplSession {
+    msg_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    exit: WorkerExit,
+    events_rx: mpsc::UnboundedReceiver<WorkerEventWithMetadata>,
+    req_start_tx: mpsc::UnboundedSender<Arc<Notify>>,
+    req_end_tx: mpsc::UnboundedSender<()>,
+    termination_token: TerminationToken,
+    started_at: Instant,
+    usage: ReplUsage,
+}
+
+impl ReplSession {
+    /// Boots a user worker with the given limits. The supervisor applies them
+    /// to every input on its own, as it would to a request.
+    pub async fn new(runtime_opts: UserWorkerRuntimeOpts) -> Result<Self, Error> {
+        Self::boot(runtime_opts, REPL_MAIN_MODULE, None, None).await
+    }
+
+    /// Boots a user worker whose main module is given in memory, along with
+    /// the modules it may import from memory.
+    pub(crate) async fn boot(
+        runtime_opts: UserWorkerRuntimeOpts,
+        main_module: &str,
+        maybe_module_map: Option<HashMap<String, FastString>>,
+        import_map_path: Option<String>,
+    ) -> Result<Self, Error> {
+        Self::start(
+            UserWorkerBuilder::new(std::env::current_dir().unwrap_or(PathBuf::from(".")))
+                .module_code(Some(main_module.to_string().into()))
+                .module_map(maybe_module_map)
+                .import_map_path(import_map_path),
+            runtime_opts,
+        )
+        .await
+    }
+
+    /// Boots a user worker from the entrypoint of a service.
+    pub(crate) async fn boot_service(
+        runtime_opts: UserWorkerRuntimeOpts,
+        service_path: PathBuf,
+        import_map_path: Option<String>,
+    ) -> Result<Self, Error> {
+        Self::start(
+            UserWorkerBuilder::new(service_path).import_map_path(import_map_path),
+            runtime_opts,
+        )
+        .await
+    }
+
+    async fn start(
+        builder: UserWorkerBuilder,
+        runtime_opts: UserWorkerRuntimeOpts,
+    ) -> Result<Self, Error> {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (req_start_tx, req_start_rx) = mpsc::unbounded_channel();
+        let (req_end_tx, req_end_rx) = mpsc::unbounded_channel();
+        let termination_token = TerminationToken::new();
+
+        let opts = builder
+            .timing(Some(Timing {
+                req: (req_start_rx, req_end_rx),
+                ..Default::default()
+            }))
+            .runtime_opts(UserWorkerRuntimeOpts {
+                events_msg_tx: Some(events_tx),
+                ..runtime_opts
+            })
+            .build()?;
+
+        let ctx = create_worker(
+            (
+                opts,
+                SupervisorPolicy::PerRequest { oneshot: false },
+                Some(termination_token.clone()),
+            ),
+            None,
+            None,
+        )
+        .await
+        .context("failed to boot the worker")?;
+
+        Ok(Self {
+            msg_tx: ctx.msg_tx,
+            exit: ctx.exit,
+            events_rx,
+            req_start_tx,
+            req_end_tx,
+            termination_token,
+            started_at: Instant::now(),
+            usage: ReplUsage::default(),
+        })
+    }
+
+    pub async fn eval(&mut self, code: &str) -> Result<ReplOutcome, Error> {
+        self.usage.evaluations += 1;
+
+        let res = self.request(code.to_string()).await;
+
+        // NOTE: The worker logs before it responds, so whatever the input has
+        // logged is already in the channel.
+        Ok(ReplOutcome {
+            succeeded: res.is_ok_and(|it| it.status().is_success()),
+            logs: self.take_logs(),
+        })
+    }
+
+    /// Sends an input to the worker, within the limits of a single request.
+    pub(crate) async fn request(&mut self, body: String) -> Result<Response<Body>, Error> {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost/")
+            .body(Body::from(body))?;
+
+        self.send(req).await
+    }
+
+    /// Sends a request to the worker, within the limits of a single request.
+    pub(crate) async fn send(&mut self, req: Request<Body>) -> Result<Response<Body>, Error> {
+        let fence = Arc::<Notify>::default();
+
+        self.req_start_tx
+            .send(fence.clone())
+            .context("the worker is gone")?;
+
+        fence.notified().await;
+
+        let res = send_user_worker_request(
+            self.msg_tx.clone(),
+            req,
+            CancellationToken::new(),
+            self.exit.clone(),
+            None,
+        )
+        .await;
+
+        let _ = self.req_end_tx.send(());
+
+        res
+    }
+
+    /// Returns what the worker logged since the last input, e.g. from timers.
+    pub fn take_logs(&mut self) -> Vec<LogEvent> {
+        let mut logs = vec![];
+
+        while let Ok(event) = self.events_rx.try_recv() {
+            if let Some(log) = self.on_event(event) {
+                logs.push(log);
+            }
+        }
+
+        logs
+    }
+
+    fn on_event(&mut self, event: WorkerEventWithMetadata) -> Option<LogEvent> {
+        let route = EventRoute::of(&event.event);
+
+        match event.event {
+            WorkerEvents::Log(log) => {
+                EVENTS_BACKLOG.leave(route);
+                return Some(log);
+            }
+
+            WorkerEvents::StructuredLog(it) => {
+                EVENTS_BACKLOG.leave(route);
+                return Some(LogEvent {
+                    msg: it.to_line(),
+                    level: it.level,
+                });
+            }
+
+            WorkerEvents::Shutdown(it) => {
+                self.usage.cpu_time_used_ms = Some(it.cpu_time_used);
+                self.usage.heap_bytes = Some(it.memory_used.heap);
+                self.usage.external_bytes = Some(it.memory_used.external);
+                self.usage.shutdown_reason = Some(format!("{:?}", it.reason));
+            }
+
+            WorkerEvents::UncaughtException(it) => {
+                self.usage.cpu_time_used_ms = Some(it.cpu_time_used);
+                self.usage.shutdown_reason = Some(it.exception);
+            }
+
+            WorkerEvents::Crashed(it) => {
+                self.usage.shutdown_reason = Some(it.message);
+            }
+
+            _ => {}
+        }
+
+        EVENTS_BACKLOG.leave(route);
+        None
+    }
+
+    /// Shuts the worker down, returning the logs it left behind along with
+    /// what the session used.
+    pub async fn close(mut self) -> (Vec<LogEvent>, ReplUsage) {
+        self.termination_token.inbound.cancel();
+        self.termination_token.outbound.cancelled().await;
+
+        let mut logs = vec![];
+
+        // NOTE: The shutdown may be reported right after the token has been
+        // cancelled, so it is waited for a little while.
+        while self.usage.shutdown_reason.is_none() {
+            let Ok(Some(event)) = timeout(SHUTDOWN_REPORT_TIMEOUT, self.events_rx.recv()).await
+            else {
+                break;
+            };
+
+            if let Some(log) = self.on_event(event) {
+                logs.push(log);
+            }
+        }
+
+        self.usage.wall_clock_time = self.started_at.elapsed();
+
+        (logs, self.usage)
+    }
+}
@@ -0,0 +1,356 @@
+//! Replays a recording of the traffic of a function (see `sb_core::replay`)
+//! against a version of it, offline, and tells which responses changed.
+//!
+//! The requests are replayed one after the other in a single worker, in the
+//! order they were recorded in.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Error};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use event_worker::events::LogEvent;
+use hyper_v014::{Body, Request};
+use sb_core::replay::{
+    read_recording, RecordedExchange, RecordingEntry, Replay, ReplayClock, ReplayFixtures,
+    ReplayMode,
+};
+use sb_workers::context::UserWorkerRuntimeOpts;
+
+use crate::repl::ReplSession;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayOutcome {
+    /// The response has the recorded status and body.
+    Matched,
+    Changed {
+        expected_status: Option<u16>,
+        actual_status: u16,
+        body_changed: bool,
+    },
+    /// The request failed while it did not when it was recorded.
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayResult {
+    pub method: String,
+    pub url: String,
+    pub outcome: ReplayOutcome,
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub results: Vec<ReplayResult>,
+    pub logs: Vec<LogEvent>,
+    /// Why the worker went away, if it did.
+    pub shutdown_reason: Option<String>,
+}
+
+impl ReplayReport {
+    pub fn is_failed(&self) -> bool {
+        self.results
+            .iter()
+            .any(|it| it.outcome != ReplayOutcome::Matched)
+    }
+}
+
+/// Picks the exchanges of a service out of a recording, along with the
+/// fetches its workers made. The service must be given if the recording has
+/// the traffic of more than one.
+fn select_service(
+    entries: Vec<RecordingEntry>,
+    maybe_service_path: Option<&str>,
+) -> Result<(Vec<RecordedExchange>, ReplayFixtures), Error> {
+    let services = entries
+        .iter()
+        .filter_map(|it| match it {
+            RecordingEntry::Request(it) => Some(it.service_path.as_str()),
+            RecordingEntry::Fetch(_) => None,
+        })
+        .collect::<BTreeSet<_>>();
+
+    let service_path = match maybe_service_path {
+        Some(it) => it.to_string(),
+        None if services.len() == 1 => services.first().unwrap().to_string(),
+        None if services.is_empty() => bail!("the recording has no request"),
+        None => bail!(
+            "the recording has the requests of more than one service, pick one of: {}",
+            services.into_iter().collect::<Vec<_>>().join(", ")
+        ),
+    };
+
+    let mut exchanges = vec![];
+    let mut fetches = vec![];
+
+    for entry in entries {
+        match entry {
+            RecordingEntry::Request(it) if it.service_path == service_path => exchanges.push(it),
+            RecordingEntry::Fetch(it) if it.service_path == service_path => fetches.push(it),
+            _ => {}
+        }
+    }
+
+    if exchanges.is_empty() {
+        bail!("the recording has no request to {service_path}");
+    }
+
+    Ok((exchanges, ReplayFixtures::new(fetches)))
+}
+
+fn build_request(exchange: &RecordedExchange) -> Result<Request<Body>, Error> {
+    let uri = if exchange.url.starts_with('/') {
+        format!("http://localhost{}", exchange.url)
+    } else {
+        exchange.url.clone()
+    };
+
+    let mut req = Request::builder().method(exchange.method.as_str()).uri(uri);
+
+    for (name, value) in exchange.headers.iter() {
+        req = req.header(name.as_str(), value.as_str());
+    }
+
+    Ok(req.body(Body::from(STANDARD.decode(&exchange.body)?))?)
+}
+
+/// Replays the recorded requests of a service against the version of it at
+/// `service_path`.
+///
+/// The clock of the worker reads the time every request was recorded at, and
+/// its random values are drawn from a generator seeded with `seed`, so that
+/// replaying the same recording twice gives the same results.
+pub async fn replay_recording(
+    recording_path: &Path,
+    maybe_recorded_service_path: Option<&str>,
+    service_path: PathBuf,
+    seed: u64,
+    runtime_opts: UserWorkerRuntimeOpts,
+    import_map_path: Option<String>,
+) -> Result<ReplayReport, Error> {
+    let (exchanges, fixtures) =
+        select_service(read_recording(recording_path)?, maybe_recorded_service_path)?;
+
+    let fixtures = Arc::new(fixtures);
+    let clock = ReplayClock::new(exchanges.iter().map(|it| it.started_at).min().unwrap());
+    let mut session = ReplSession::boot_service(
+        UserWorkerRuntimeOpts {
+            replay: Some(ReplayMode::Replay(Replay {
+                fixtures: fixtures.clone(),
+                clock: clock.clone(),
+                seed,
+            })),
+            ..runtime_opts
+        },
+        service_path,
+        import_map_path,
+    )
+    .await?;
+
+    let mut report = ReplayReport::default();
+
+    for exchange in exchanges.iter() {
+        clock.set(exchange.started_at);
+        fixtures.set_exchange(exchange.request_id.clone());
+
+        let result = async {
+            if exchange.body_truncated {
+                bail!("the body of the request was not recorded in full");
+            }
+
+            let res = session.send(build_request(exchange)?).await?;
+            let status = res.status().as_u16();
+            let body = hyper_v014::body::to_bytes(res.into_body())
+                .await
+                .context("failed to read the body of the response")?;
+
+            Ok::<_, Error>((status, body))
+        }
+        .await;
+
+        let outcome = match result {
+            Ok((status, body)) => {
+                // NOTE: Only the head of a large body is recorded.
+                let body_changed = STANDARD.decode(&exchange.response_body).map_or(true, |it| {
+                    if exchange.response_body_truncated {
+                        !body.starts_with(&it)
+                    } else {
+                        it != body
+                    }
+                });
+
+                if exchange.status == Some(status) && !body_changed {
+                    ReplayOutcome::Matched
+                } else {
+                    ReplayOutcome::Changed {
+                        expected_status: exchange.status,
+                        actual_status: status,
+                        body_changed,
+                    }
+                }
+            }
+
+            // NOTE: A request that failed when it was recorded is expected to
+            // fail again.
+            Err(_) if exchange.status.is_none() => ReplayOutcome::Matched,
+            Err(err) => ReplayOutcome::Failed(format!("{err:#}")),
+        };
+
+        report.logs.extend(session.take_logs());
+        report.results.push(ReplayResult {
+            method: exchange.method.clone(),
+            url: exchange.url.clone(),
+            outcome,
+        });
+    }
+
+    let (logs, usage) = session.close().await;
+
+    report.logs.extend(logs);
+    report.shutdown_reason = usage.shutdown_reason;
+
+    Ok(report)
+}
+
+pub fn format_replay_report(report: &ReplayReport) -> String {
+    let mut out = String::new();
+    let (mut matched, mut changed, mut failed) = (0, 0, 0);
+
+    for result in report.results.iter() {
+        let _ = write!(out, "{} {} ... ", result.method, result.url);
+
+        match &result.outcome {
+            ReplayOutcome::Matched => {
+                matched += 1;
+                let _ = writeln!(out, "ok");
+            }
+
+            ReplayOutcome::Changed {
+                expected_status,
+                actual_status,
+                body_changed,
+            } => {
+                let mut changes = vec![];
+
+                changed += 1;
+
+                if *expected_status != Some(*actual_status) {
+                    changes.push(format!(
+                        "status {} -> {actual_status}",
+                        expected_status.map_or("error".to_string(), |it| it.to_string())
+                    ));
+                }
+                if *body_changed {
+                    changes.push("body changed".to_string());
+                }
+
+                let _ = writeln!(out, "CHANGED ({})", changes.join(", "));
+            }
+
+            ReplayOutcome::Failed(err) => {
+                failed += 1;
+                let _ = writeln!(out, "FAILED\n{err}");
+            }
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "\nreplayed {} requests: {matched} ok, {changed} changed, {failed} failed",
+        report.results.len()
+    );
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use sb_core::replay::{RecordedExchange, RecordedFetch, RecordingEntry};
+
+    use super::{format_replay_report, select_service, ReplayOutcome, ReplayReport, ReplayResult};
+
+    fn exchange(service_path: &str) -> RecordingEntry {
+        RecordingEntry::Request(RecordedExchange {
+            service_path: service_path.to_string(),
+            request_id: None,
+            started_at: 0,
+            method: "GET".to_string(),
+            url: "/".to_string(),
+            headers: vec![],
+            body: String::new(),
+            body_truncated: false,
+            status: Some(200),
+            response_headers: vec![],
+            response_body: String::new(),
+            response_body_truncated: false,
+            error: None,
+        })
+    }
+
+    fn fetch(service_path: &str) -> RecordingEntry {
+        RecordingEntry::Fetch(RecordedFetch {
+            service_path: service_path.to_string(),
+            request_id: None,
+            method: "GET".to_string(),
+            url: "https://a.test/".to_string(),
+            status: 200,
+            headers: vec![],
+            body: String::new(),
+            body_truncated: false,
+        })
+    }
+
+    #[test]
+    fn test_select_service() {
+        let entries = vec![
+            exchange("./a"),
+            fetch("./a"),
+            exchange("./b"),
+            fetch("./b"),
+            exchange("./a"),
+        ];
+
+        assert!(select_service(entries.clone(), None).is_err());
+        assert!(select_service(entries.clone(), Some("./c")).is_err());
+
+        let (exchanges, fixtures) = select_service(entries, Some("./a")).unwrap();
+
+        assert_eq!(exchanges.len(), 2);
+        assert!(fixtures.take(None, "GET", "https://a.test/").is_some());
+        assert!(fixtures.take(None, "GET", "https://a.test/").is_none());
+    }
+
+    #[test]
+    fn test_format_replay_report() {
+        let result = |outcome| ReplayResult {
+            method: "GET".to_string(),
+            url: "/hello".to_string(),
+            outcome,
+        };
+        let report = ReplayReport {
+            results: vec![
+                result(ReplayOutcome::Matched),
+                result(ReplayOutcome::Changed {
+                    expected_status: Some(200),
+                    actual_status: 500,
+                    body_changed: true,
+                }),
+            ],
+            ..Default::default()
+        };
+
+        assert!(report.is_failed());
+        assert_eq!(
+            format_replay_report(&report),
+            concat!(
+                "GET /hello ... ok\n",
+                "GET /hello ... CHANGED (status 200 -> 500, body changed)\n",
+                "\n",
+                "replayed 2 requests: 1 ok, 1 changed, 0 failed\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,348 @@
+//! Buffering of the responses of the workers ahead of their clients, so that a
+//! worker is not held on to by a client that reads its response slowly.
+//!
+//! A response is read ahead into memory up to a cap. Past it, the worker is
+//! either held back until the client catches up, or the rest of the response
+//! is spilled to a temporary file, as long as the files of all the responses
+//! fit in a quota.
+
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+use futures_util::{Stream, StreamExt};
+use hyper_v014::body::Bytes;
+use log::debug;
+use sb_core::SharedMetricSource;
+use tempfile::TempPath;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+
+/// How many bytes the responses may spill to disk at once, if no quota is set.
+static DEFAULT_SPILL_QUOTA: u64 = 1024 * 1024 * 1024;
+
+/// What happens to a response once its buffer is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseOverflow {
+    /// The worker is held back until the client has read some of it.
+    #[default]
+    Backpressure,
+    /// The rest of the response goes to a temporary file, or is held back as
+    /// above once the quota of the files is used up.
+    Spill,
+}
+
+impl FromStr for ResponseOverflow {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "backpressure" => Self::Backpressure,
+            "spill" => Self::Spill,
+            _ => bail!("unknown response buffer overflow mode: {s}"),
+        })
+    }
+}
+
+/// The bytes all the responses have spilled to disk.
+#[derive(Debug)]
+pub(crate) struct SpillQuota {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl SpillQuota {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit: limit.unwrap_or(DEFAULT_SPILL_QUOTA),
+            used: AtomicU64::new(0),
+        }
+    }
+
+    fn try_reserve(&self, size: u64) -> bool {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(size).filter(|it| *it <= self.limit)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, size: u64) {
+        self.used.fetch_sub(size, Ordering::AcqRel);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ResponseBufferLimits {
+    /// How many bytes of a response may be held in memory.
+    pub max_size: usize,
+    pub overflow: ResponseOverflow,
+    pub spill_quota: Arc<SpillQuota>,
+}
+
+/// A chunk held in memory, whose bytes are given back to the buffer of the
+/// response once it is read or dropped.
+struct BufferedChunk {
+    bytes: Bytes,
+    _permit: OwnedSemaphorePermit,
+    metric_src: SharedMetricSource,
+}
+
+impl Drop for BufferedChunk {
+    fn drop(&mut self) {
+        self.metric_src
+            .decl_buffered_response_bytes(self.bytes.len());
+    }
+}
+
+/// The temporary file of a response, which is deleted and given back to the
+/// quota once both ends of the response are done with it.
+struct SpillFile {
+    path: TempPath,
+    size: AtomicU64,
+    quota: Arc<SpillQuota>,
+    metric_src: SharedMetricSource,
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let size = *self.size.get_mut();
+
+        self.quota.release(size);
+        self.metric_src.decl_spilled_response_bytes(size as usize);
+    }
+}
+
+enum Segment {
+    Memory(BufferedChunk),
+    Disk { len: usize, file: Arc<SpillFile> },
+}
+
+/// Reads the body of a response ahead of the client within the limits, and
+/// returns the body the client reads instead.
+pub(crate) fn buffer_response<S, E>(
+    body: S,
+    limits: ResponseBufferLimits,
+    metric_src: SharedMetricSource,
+) -> impl Stream<Item = Result<Bytes, io::Error>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    drop(tokio::spawn(read_ahead(body, limits, metric_src, tx)));
+
+    futures_util::stream::unfold(
+        (rx, None::<(File, Arc<SpillFile>)>),
+        |(mut rx, mut reader)| async move {
+            let chunk = match rx.recv().await? {
+                Ok(Segment::Memory(chunk)) => Ok(chunk.bytes.clone()),
+                Ok(Segment::Disk { len, file }) => read_spilled(&mut reader, file, len).await,
+                Err(err) => Err(err),
+            };
+
+            Some((chunk, (rx, reader)))
+        },
+    )
+}
+
+async fn read_spilled(
+    reader: &mut Option<(File, Arc<SpillFile>)>,
+    file: Arc<SpillFile>,
+    len: usize,
+) -> Result<Bytes, io::Error> {
+    // NOTE: A response spills to a single file, which is read in the order it
+    // was written.
+    if reader.is_none() {
+        let handle = File::open(&file.path).await?;
+
+        *reader = Some((handle, file));
+    }
+
+    let (handle, _) = reader.as_mut().unwrap();
+    let mut buf = vec![0; len];
+
+    handle.read_exact(&mut buf).await?;
+
+    Ok(Bytes::from(buf))
+}
+
+async fn read_ahead<S, E>(
+    mut body: S,
+    limits: ResponseBufferLimits,
+    metric_src: SharedMetricSource,
+    tx: mpsc::UnboundedSender<Result<Segment, io::Error>>,
+) where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let max_size = limits.max_size.clamp(1, u32::MAX as usize) as u32;
+    let memory = Arc::new(Semaphore::new(max_size as usize));
+    let mut spill = None::<(File, Arc<SpillFile>)>;
+
+    loop {
+        let chunk = tokio::select! {
+            chunk = body.next() => chunk,
+            _ = tx.closed() => return,
+        };
+
+        let chunk = match chunk {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(err)) => {
+                let _ = tx.send(Err(io::Error::other(err)));
+                return;
+            }
+            None => return,
+        };
+
+        if chunk.is_empty() {
+            continue;
+        }
+
+        // NOTE: A chunk larger than the buffer takes all of it.
+        let permits = chunk.len().min(max_size as usize) as u32;
+        let segment = match memory.clone().try_acquire_many_owned(permits) {
+            Ok(permit) => Ok(buffer_chunk(chunk, permit, &metric_src)),
+            Err(_) if limits.overflow == ResponseOverflow::Spill => {
+                match spill_chunk(&mut spill, &chunk, &limits, &metric_src).await {
+                    Ok(Some(segment)) => Ok(segment),
+                    Ok(None) => Err(chunk),
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        return;
+                    }
+                }
+            }
+
+            Err(_) => Err(chunk),
+        };
+
+        let segment = match segment {
+            Ok(segment) => segment,
+            Err(chunk) => {
+                let permit = tokio::select! {
+                    permit = memory.clone().acquire_many_owned(permits) => permit.unwrap(),
+                    _ = tx.closed() => return,
+                };
+
+                buffer_chunk(chunk, permit, &metric_src)
+            }
+        };
+
+        if tx.send(Ok(segment)).is_err() {
+            return;
+        }
+    }
+}
+
+fn buffer_chunk(
+    bytes: Bytes,
+    permit: OwnedSemaphorePermit,
+    metric_src: &SharedMetricSource,
+) -> Segment {
+    metric_src.incl_buffered_response_bytes(bytes.len());
+
+    Segment::Memory(BufferedChunk {
+        bytes,
+        _permit: permit,
+        metric_src: metric_src.clone(),
+    })
+}
+
+/// Returns `None` if the chunk would take the files past their quota.
+async fn spill_chunk(
+    spill: &mut Option<(File, Arc<SpillFile>)>,
+    chunk: &Bytes,
+    limits: &ResponseBufferLimits,
+    metric_src: &SharedMetricSource,
+) -> Result<Option<Segment>, io::Error> {
+    let len = chunk.len() as u64;
+
+    if !limits.spill_quota.try_reserve(len) {
+        debug!("response spill quota is used up");
+        return Ok(None);
+    }
+
+    if spill.is_none() {
+        let (file, path) = match tempfile::NamedTempFile::new() {
+            Ok(it) => it.into_parts(),
+            Err(err) => {
+                limits.spill_quota.release(len);
+                return Err(err);
+            }
+        };
+
+        metric_src.incl_spilled_responses();
+        *spill = Some((
+            File::from_std(file),
+            Arc::new(SpillFile {
+                path,
+                size: AtomicU64::new(0),
+                quota: limits.spill_quota.clone(),
+                metric_src: metric_src.clone(),
+            }),
+        ));
+    }
+
+    let (writer, file) = spill.as_mut().unwrap();
+
+    // NOTE: The file owns the reservation from here on, so that it is given
+    // back even if the write fails.
+    file.size.fetch_add(len, Ordering::AcqRel);
+    metric_src.incl_spilled_response_bytes(len as usize);
+
+    writer.write_all(chunk).await?;
+    writer.flush().await?;
+
+    Ok(Some(Segment::Disk {
+        len: chunk.len(),
+        file: file.clone(),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use futures_util::StreamExt;
+    use hyper_v014::body::Bytes;
+    use sb_core::SharedMetricSource;
+
+    use super::{buffer_response, ResponseBufferLimits, ResponseOverflow, SpillQuota};
+
+    #[tokio::test]
+    async fn test_buffer_response_spill() {
+        let metric_src = SharedMetricSource::default();
+        let quota = Arc::new(SpillQuota::new(Some(8)));
+        let chunks = ["hello", " ", "world", "!", "!!"]
+            .into_iter()
+            .map(|it| Ok::<_, std::io::Error>(Bytes::from_static(it.as_bytes())))
+            .collect::<Vec<_>>();
+
+        let body = buffer_response(
+            futures_util::stream::iter(chunks),
+            ResponseBufferLimits {
+                max_size: 6,
+                overflow: ResponseOverflow::Spill,
+                spill_quota: quota.clone(),
+            },
+            metric_src.clone(),
+        );
+
+        let body = body
+            .map(|it| it.unwrap())
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+
+        assert_eq!(body, b"hello world!!!");
+        assert_eq!(metric_src.spilled_responses(), 1);
+        assert_eq!(metric_src.buffered_response_bytes(), 0);
+        assert_eq!(metric_src.spilled_response_bytes(), 0);
+        assert_eq!(quota.used.load(std::sync::atomic::Ordering::Acquire), 0);
+    }
+}
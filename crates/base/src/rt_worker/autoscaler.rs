@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use event_worker::events::ScaleReason;
+use sb_workers::context::{AutoscalePolicy, WorkerIdentity};
+
+/// How often the autoscaler revisits the number of workers of each service.
+pub(crate) static AUTOSCALE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of latencies kept per service between two ticks.
+static MAX_LATENCY_SAMPLES: usize = 1024;
+
+#[derive(Debug, Default)]
+struct LoadEntry {
+    in_flight: usize,
+    latencies_ms: Vec<u64>,
+}
+
+/// The load of a service between two ticks of the autoscaler.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ServiceLoad {
+    /// Workers serving the service.
+    pub instances: usize,
+    /// Workers being booted for the service.
+    pub booting: usize,
+    /// Requests sent to the workers of the service that have not been
+    /// responded to yet.
+    pub queue_depth: usize,
+    pub p95_latency_ms: Option<u64>,
+}
+
+/// Tracks the in-flight requests and the latencies of the services that have
+/// an autoscale policy.
+#[derive(Debug, Clone, Default)]
+pub struct LoadTracker {
+    services: Arc<Mutex<HashMap<WorkerIdentity, LoadEntry>>>,
+}
+
+impl LoadTracker {
+    /// Counts a request as in-flight until the returned guard is dropped.
+    pub(crate) fn start(&self, identity: &WorkerIdentity) -> InFlightRequest {
+        self.services
+            .lock()
+            .unwrap()
+            .entry(identity.clone())
+            .or_default()
+            .in_flight += 1;
+
+        InFlightRequest {
+            tracker: self.clone(),
+            identity: identity.clone(),
+        }
+    }
+
+    /// Takes the queue depth and the p95 latency of every service, resetting
+    /// the latencies.
+    pub(crate) fn take(&self) -> HashMap<WorkerIdentity, (usize, Option<u64>)> {
+        let mut services = self.services.lock().unwrap();
+        let loads = services
+            .iter_mut()
+            .map(|(identity, entry)| {
+                let latencies = std::mem::take(&mut entry.latencies_ms);
+
+                (
+                    identity.clone(),
+                    (entry.in_flight, percentile(latencies, 95)),
+                )
+            })
+            .collect();
+
+        services.retain(|_, it| it.in_flight > 0);
+        loads
+    }
+}
+
+/// A request counted in the queue depth of its service.
+pub(crate) struct InFlightRequest {
+    tracker: LoadTracker,
+    identity: WorkerIdentity,
+}
+
+impl InFlightRequest {
+    /// Records the latency of the request once it has been responded to.
+    pub(crate) fn finish(self, latency: Duration) {
+        let mut services = self.tracker.services.lock().unwrap();
+
+        if let Some(entry) = services.get_mut(&self.identity) {
+            if entry.latencies_ms.len() < MAX_LATENCY_SAMPLES {
+                entry
+                    .latencies_ms
+                    .push(latency.as_millis().try_into().unwrap_or(u64::MAX));
+            }
+        }
+    }
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        let mut services = self.tracker.services.lock().unwrap();
+        let entry = services.entry(self.identity.clone()).or_default();
+
+        entry.in_flight = entry.in_flight.saturating_sub(1);
+    }
+}
+
+fn percentile(mut samples: Vec<u64>, p: usize) -> Option<u64> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    samples.sort_unstable();
+
+    let rank = (samples.len() * p + 99) / 100;
+
+    samples.get(rank.saturating_sub(1)).copied()
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ScaleState {
+    /// The policy of the service, kept so that the minimum number of workers
+    /// is restored even after all of them have retired, until the service is
+    /// no longer hosted.
+    pub policy: Option<Arc<AutoscalePolicy>>,
+    pub booting: Arc<AtomicUsize>,
+    low_since: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Scale {
+    Up(usize, ScaleReason),
+    Down(ScaleReason),
+    Hold,
+}
+
+/// Scales the number of workers of each service with an autoscale policy.
+#[derive(Debug, Default)]
+pub struct Autoscaler {
+    pub load: LoadTracker,
+    pub(crate) services: HashMap<WorkerIdentity, ScaleState>,
+}
+
+impl Autoscaler {
+    /// Returns the state of a service given the policy of its workers, or
+    /// `None` if it has had no autoscale policy.
+    pub(crate) fn state(
+        &mut self,
+        identity: &WorkerIdentity,
+        maybe_policy: Option<Arc<AutoscalePolicy>>,
+    ) -> Option<&mut ScaleState> {
+        let Some(policy) = maybe_policy else {
+            return self.services.get_mut(identity);
+        };
+
+        let state = self.services.entry(identity.clone()).or_default();
+
+        state.policy = Some(policy);
+        Some(state)
+    }
+
+    /// Forgets a service that is no longer hosted.
+    pub(crate) fn remove(&mut self, identity: &WorkerIdentity) {
+        self.services.remove(identity);
+    }
+}
+
+/// Decides how the number of workers of a service should change.
+///
+/// Workers are added as soon as the load exceeds the target, but only retired
+/// one at a time once the load has stayed below it for the scale-down delay.
+pub(crate) fn decide(
+    policy: &AutoscalePolicy,
+    load: &ServiceLoad,
+    state: &mut ScaleState,
+    now: Instant,
+) -> Scale {
+    let min = policy.min_instances;
+    let max = policy.max_instances.max(min);
+    let current = load.instances + load.booting;
+
+    if current < min {
+        state.low_since = None;
+        return Scale::Up(min - current, ScaleReason::MinInstances);
+    }
+
+    let target_queue_depth = policy.target_queue_depth.max(1);
+    let desired = (load.queue_depth + target_queue_depth - 1) / target_queue_depth;
+
+    if desired > current && current < max {
+        state.low_since = None;
+        return Scale::Up(desired.min(max) - current, ScaleReason::QueueDepth);
+    }
+
+    let is_slow = policy
+        .target_p95_latency_ms
+        .zip(load.p95_latency_ms)
+        .map_or(false, |(target, p95)| p95 > target);
+
+    // NOTE: Another worker only helps with latency if every worker is busy,
+    // and the outcome of a boot is awaited before adding another one.
+    if is_slow && load.queue_depth >= load.instances {
+        state.low_since = None;
+
+        if load.booting == 0 && current < max {
+            return Scale::Up(1, ScaleReason::Latency);
+        }
+
+        return Scale::Hold;
+    }
+
+    if desired >= load.instances || load.instances <= min || load.booting > 0 {
+        state.low_since = None;
+        return Scale::Hold;
+    }
+
+    let low_since = *state.low_since.get_or_insert(now);
+
+    if now.duration_since(low_since) < Duration::from_millis(policy.scale_down_delay_ms) {
+        return Scale::Hold;
+    }
+
+    // NOTE: The delay starts over for the next worker to retire.
+    state.low_since = Some(now);
+    Scale::Down(ScaleReason::Idle)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use std::sync::Arc;
+
+    use event_worker::events::ScaleReason;
+    use sb_workers::context::{AutoscalePolicy, WorkerIdentity};
+
+    use super::{decide, percentile, Autoscaler, Scale, ScaleState, ServiceLoad};
+
+    #[test]
+    fn test_autoscaler_state() {
+        let mut autoscaler = Autoscaler::default();
+        let identity = |service: &str| WorkerIdentity {
+            tenant: None,
+            service: service.to_string(),
+            revision: None,
+        };
+
+        let policy = Arc::new(AutoscalePolicy {
+            min_instances: 1,
+            max_instances: 4,
+            target_queue_depth: 2,
+            target_p95_latency_ms: None,
+            scale_down_delay_ms: 1000,
+            health_check: None,
+        });
+
+        assert!(autoscaler.state(&identity("a"), None).is_none());
+        assert!(autoscaler
+            .state(&identity("b"), Some(policy.clone()))
+            .is_some());
+
+        // NOTE: The policy is kept after the workers that carried it are gone.
+        assert!(autoscaler.state(&identity("b"), None).is_some());
+        assert_eq!(autoscaler.services.len(), 1);
+
+        autoscaler.remove(&identity("b"));
+
+        assert!(autoscaler.state(&identity("b"), None).is_none());
+        assert!(autoscaler.services.is_empty());
+    }
+
+    #[test]
+    fn test_autoscale_decision() {
+        let policy = AutoscalePolicy {
+            min_instances: 1,
+            max_instances: 4,
+            target_queue_depth: 2,
+            target_p95_latency_ms: Some(100),
+            scale_down_delay_ms: 1000,
+            health_check: None,
+        };
+
+        let mut state = ScaleState::default();
+        let now = Instant::now();
+        let load = |instances, booting, queue_depth, p95_latency_ms| ServiceLoad {
+            instances,
+            booting,
+            queue_depth,
+            p95_latency_ms,
+        };
+
+        let mut scale = |load: ServiceLoad, now| decide(&policy, &load, &mut state, now);
+
+        assert_eq!(
+            scale(load(0, 0, 0, None), now),
+            Scale::Up(1, ScaleReason::MinInstances)
+        );
+        assert_eq!(
+            scale(load(1, 0, 5, None), now),
+            Scale::Up(2, ScaleReason::QueueDepth)
+        );
+        assert_eq!(
+            scale(load(1, 0, 100, None), now),
+            Scale::Up(3, ScaleReason::QueueDepth)
+        );
+        assert_eq!(
+            scale(load(2, 0, 2, Some(250)), now),
+            Scale::Up(1, ScaleReason::Latency)
+        );
+        assert_eq!(scale(load(2, 1, 2, Some(250)), now), Scale::Hold);
+
+        // The load has to stay low for the whole delay.
+        assert_eq!(scale(load(3, 0, 0, None), now), Scale::Hold);
+        assert_eq!(
+            scale(load(3, 0, 1, None), now + Duration::from_millis(500)),
+            Scale::Hold
+        );
+        assert_eq!(
+            scale(load(3, 0, 0, None), now + Duration::from_millis(1000)),
+            Scale::Down(ScaleReason::Idle)
+        );
+        assert_eq!(
+            scale(load(2, 0, 0, None), now + Duration::from_millis(1500)),
+            Scale::Hold
+        );
+        assert_eq!(
+            scale(load(1, 0, 0, None), now + Duration::from_millis(5000)),
+            Scale::Hold
+        );
+    }
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(vec![], 95), None);
+        assert_eq!(percentile(vec![7], 95), Some(7));
+        assert_eq!(percentile((1..=100).rev().collect(), 95), Some(95));
+    }
+}
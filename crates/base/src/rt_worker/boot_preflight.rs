@@ -0,0 +1,285 @@
+//! A check of the resources of the host and the pool before a user worker is
+//! booted, so that its creation is refused while the host is under pressure
+//! instead of booting a worker that would be OOM-killed along with others.
+
+use std::sync::Arc;
+
+use sb_workers::errors::ResourceShortage;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::server::ServerFlags;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BootPreflightPolicy {
+    /// Memory in MiB the host must have available once the worker has taken
+    /// its memory limit.
+    pub min_free_memory_mb: Option<u64>,
+    /// 1-minute load average per core past which boots are refused.
+    pub max_load_avg_per_core: Option<f64>,
+    /// Sum of the memory limits of the user workers of the pool in MiB that
+    /// a boot may not take the pool past, see [`MemoryBudget`].
+    pub memory_budget_mb: Option<u64>,
+}
+
+/// The memory the user workers of the pool may commit to in total, which a
+/// worker takes its memory limit from before it boots and gives back once it
+/// is gone.
+///
+/// NOTE: The limit is taken at once, so that boots running side by side can't
+/// both see room for themselves and go past the budget together.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    budget_mb: u64,
+    sem: Arc<Semaphore>,
+}
+
+impl MemoryBudget {
+    pub fn new(budget_mb: u64) -> Self {
+        let budget_mb = budget_mb.min(Semaphore::MAX_PERMITS as u64);
+
+        Self {
+            budget_mb,
+            sem: Arc::new(Semaphore::new(budget_mb as usize)),
+        }
+    }
+
+    /// Sum of the memory limits taken from the budget in MiB.
+    pub fn committed_mb(&self) -> u64 {
+        self.budget_mb
+            .saturating_sub(self.sem.available_permits() as u64)
+    }
+
+    /// Takes the memory limit of a worker from the budget, if there is room
+    /// left for it.
+    pub fn try_reserve(
+        &self,
+        memory_limit_mb: u64,
+    ) -> Result<OwnedSemaphorePermit, ResourceShortage> {
+        u32::try_from(memory_limit_mb)
+            .ok()
+            .and_then(|it| self.sem.clone().try_acquire_many_owned(it).ok())
+            .ok_or(ResourceShortage::MemoryBudget)
+    }
+}
+
+/// The resources of the host, as far as they can be read.
+///
+/// NOTE: The memory available is bounded by the memory limit of the cgroup
+/// the runtime runs in, since a container is OOM-killed on its limit long
+/// before the host runs out of memory.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostResources {
+    pub available_memory_mb: Option<u64>,
+    pub load_avg_per_core: Option<f64>,
+}
+
+impl HostResources {
+    pub fn read() -> Self {
+        Self {
+            available_memory_mb: read_available_memory_mb(),
+            load_avg_per_core: read_load_avg_per_core(),
+        }
+    }
+}
+
+impl BootPreflightPolicy {
+    /// Returns the policy of the flags, if any threshold is set.
+    pub fn from_flags(flags: &ServerFlags) -> Option<Self> {
+        let policy = Self {
+            min_free_memory_mb: flags.boot_min_free_memory_mb,
+            max_load_avg_per_core: flags.boot_max_load_avg,
+            memory_budget_mb: flags.boot_memory_budget_mb,
+        };
+
+        (policy.min_free_memory_mb.is_some()
+            || policy.max_load_avg_per_core.is_some()
+            || policy.memory_budget_mb.is_some())
+        .then_some(policy)
+    }
+
+    /// Returns what the host is short of for a worker with the given memory
+    /// limit to be booted, if anything. Resources that can't be read are never
+    /// short.
+    ///
+    /// NOTE: The memory budget of the pool is not checked here but reserved,
+    /// see [`MemoryBudget`].
+    pub fn check(
+        &self,
+        host: &HostResources,
+        memory_limit_mb: u64,
+    ) -> Result<(), ResourceShortage> {
+        if let Some((min, available)) = self.min_free_memory_mb.zip(host.available_memory_mb) {
+            if available.saturating_sub(memory_limit_mb) < min {
+                return Err(ResourceShortage::Memory);
+            }
+        }
+
+        if let Some((max, load)) = self.max_load_avg_per_core.zip(host.load_avg_per_core) {
+            if load > max {
+                return Err(ResourceShortage::Load);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_available_memory_mb() -> Option<u64> {
+    let read = |path: &str| std::fs::read_to_string(path).ok();
+    let maybe_host = read("/proc/meminfo").and_then(|it| parse_available_memory_mb(&it));
+
+    // NOTE: cgroup v2 first, then v1. A cgroup without a limit leaves the
+    // memory of the host.
+    let maybe_cgroup = read("/sys/fs/cgroup/memory.max")
+        .zip(read("/sys/fs/cgroup/memory.current"))
+        .or_else(|| {
+            read("/sys/fs/cgroup/memory/memory.limit_in_bytes")
+                .zip(read("/sys/fs/cgroup/memory/memory.usage_in_bytes"))
+        })
+        .and_then(|(max, current)| parse_cgroup_available_memory_mb(&max, &current));
+
+    match (maybe_host, maybe_cgroup) {
+        (Some(host), Some(cgroup)) => Some(host.min(cgroup)),
+        (host, cgroup) => host.or(cgroup),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_available_memory_mb() -> Option<u64> {
+    None
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_available_memory_mb(meminfo: &str) -> Option<u64> {
+    let kib = meminfo
+        .lines()
+        .find_map(|it| it.strip_prefix("MemAvailable:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kib / 1024)
+}
+
+/// Returns the memory left below the limit of a cgroup, given its limit and
+/// usage in bytes. Returns `None` if the cgroup has no limit.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_cgroup_available_memory_mb(max: &str, current: &str) -> Option<u64> {
+    let max = max.trim().parse::<u64>().ok()?;
+    let current = current.trim().parse::<u64>().ok()?;
+
+    // NOTE: cgroup v1 reports a huge number rather than no limit at all.
+    if max >= i64::MAX as u64 / 2 {
+        return None;
+    }
+
+    Some(max.saturating_sub(current) / 1024 / 1024)
+}
+
+#[cfg(unix)]
+fn read_load_avg_per_core() -> Option<f64> {
+    let mut load = [0f64; 3];
+
+    // SAFETY: `load` has room for the three samples asked for.
+    if unsafe { libc::getloadavg(load.as_mut_ptr(), 3) } < 1 {
+        return None;
+    }
+
+    let cores = std::thread::available_parallelism().map_or(1, |it| it.get());
+
+    Some(load[0] / cores as f64)
+}
+
+#[cfg(not(unix))]
+fn read_load_avg_per_core() -> Option<f64> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use sb_workers::errors::ResourceShortage;
+
+    use super::{
+        parse_available_memory_mb, parse_cgroup_available_memory_mb, BootPreflightPolicy,
+        HostResources, MemoryBudget,
+    };
+
+    #[test]
+    fn test_boot_preflight_policy() {
+        let policy = BootPreflightPolicy {
+            min_free_memory_mb: Some(512),
+            max_load_avg_per_core: Some(2.0),
+            memory_budget_mb: Some(1024),
+        };
+
+        let host = HostResources {
+            available_memory_mb: Some(1024),
+            load_avg_per_core: Some(0.5),
+        };
+
+        assert_eq!(policy.check(&host, 256), Ok(()));
+        assert_eq!(policy.check(&host, 768), Err(ResourceShortage::Memory));
+        assert_eq!(
+            policy.check(
+                &HostResources {
+                    load_avg_per_core: Some(2.5),
+                    ..host
+                },
+                256
+            ),
+            Err(ResourceShortage::Load)
+        );
+
+        // NOTE: What can't be read is never short.
+        assert_eq!(policy.check(&HostResources::default(), 256), Ok(()));
+    }
+
+    #[test]
+    fn test_memory_budget_reserves_limits() {
+        let budget = MemoryBudget::new(1024);
+        let first = budget.try_reserve(768).unwrap();
+
+        assert_eq!(budget.committed_mb(), 768);
+        assert_eq!(
+            budget.try_reserve(512).err(),
+            Some(ResourceShortage::MemoryBudget)
+        );
+
+        let _second = budget.try_reserve(256).unwrap();
+
+        assert_eq!(budget.committed_mb(), 1024);
+
+        drop(first);
+
+        assert_eq!(budget.committed_mb(), 256);
+        assert!(budget.try_reserve(512).is_ok());
+        assert!(budget.try_reserve(u64::MAX).is_err());
+    }
+
+    #[test]
+    fn test_parse_available_memory() {
+        let meminfo = "MemTotal:       16318480 kB\nMemFree:         1021340 kB\nMemAvailable:    8388608 kB\n";
+
+        assert_eq!(parse_available_memory_mb(meminfo), Some(8192));
+        assert_eq!(parse_available_memory_mb("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn test_parse_cgroup_available_memory() {
+        assert_eq!(
+            parse_cgroup_available_memory_mb("1073741824\n", "268435456\n"),
+            Some(768)
+        );
+        assert_eq!(
+            parse_cgroup_available_memory_mb("max\n", "268435456\n"),
+            None
+        );
+        assert_eq!(
+            parse_cgroup_available_memory_mb("9223372036854771712\n", "268435456\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Verification of the code that workers boot from against trusted public
+//! keys, for hosts that must only run code they know the origin of.
+//!
+//! A bundle (`.tar.gz`, `.zip` or `.eszip`) is signed by a detached ed25519
+//! signature of its content, which is found next to it under the same path or
+//! URL with `.sig` appended. An eszip that a user worker is created from in
+//! memory carries its signature in `maybeEszipSignature` instead. Signatures
+//! and keys are encoded in base64.
+//!
+//! Once signatures are required, a user worker can only boot from a signed
+//! bundle or eszip, so its code can't come from a plain directory or from
+//! `maybeModuleCode` either. The main and events workers are still allowed to
+//! boot from a directory, since they are given by the host itself.
+
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Error};
+use base64::Engine;
+use log::debug;
+use once_cell::sync::OnceCell;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use sb_graph::EszipPayloadKind;
+use sb_workers::context::WorkerContextInitOpts;
+use sb_workers::errors::BundleSignatureError;
+
+use super::service_bundle::BundleKind;
+use super::utils::write_body_bounded;
+
+static BUNDLE_VERIFIER: OnceCell<BundleVerifier> = OnceCell::new();
+
+static SIGNATURE_EXT: &str = "sig";
+
+/// Largest signature that is fetched from a URL. An ed25519 signature takes
+/// 88 bytes in base64.
+static MAX_SIGNATURE_SIZE: u64 = 4096;
+static SIGNATURE_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct BundleVerifier {
+    keys: Vec<Vec<u8>>,
+}
+
+impl BundleVerifier {
+    /// Returns a verifier trusting the given base64-encoded ed25519 public
+    /// keys.
+    pub fn new<I, S>(keys: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let keys = keys
+            .into_iter()
+            .map(|it| {
+                let it = it.as_ref().trim();
+                let key = base64::engine::general_purpose::STANDARD
+                    .decode(it)
+                    .ok()
+                    .filter(|key| key.len() == 32)
+                    .ok_or_else(|| anyhow!("invalid ed25519 public key: {it}"))?;
+
+                Ok(key)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if keys.is_empty() {
+            return Err(anyhow!(
+                "at least one public key is required to verify bundles"
+            ));
+        }
+
+        Ok(Self { keys })
+    }
+
+    /// Verifies the content of a bundle against its base64-encoded signature.
+    pub fn verify(
+        &self,
+        data: &[u8],
+        maybe_signature: Option<&str>,
+    ) -> Result<(), BundleSignatureError> {
+        let signature = base64::engine::general_purpose::STANDARD
+            .decode(
+                maybe_signature
+                    .ok_or(BundleSignatureError::Unsigned)?
+                    .trim(),
+            )
+            .map_err(|_| BundleSignatureError::Malformed)?;
+
+        if signature.len() != 64 {
+            return Err(BundleSignatureError::Malformed);
+        }
+
+        if self.keys.iter().any(|key| {
+            UnparsedPublicKey::new(&ED25519, key)
+                .verify(data, &signature)
+                .is_ok()
+        }) {
+            Ok(())
+        } else {
+            Err(BundleSignatureError::Untrusted)
+        }
+    }
+}
+
+/// Requires the bundles of the workers to be signed by one of the keys of
+/// the verifier from now on.
+pub fn require_signed_bundles(verifier: BundleVerifier) {
+    if BUNDLE_VERIFIER.set(verifier).is_err() {
+        debug!("the bundle verifier is already set");
+    }
+}
+
+/// Returns the verifier the bundles must pass, if signatures are required.
+pub fn bundle_verifier() -> Option<&'static BundleVerifier> {
+    BUNDLE_VERIFIER.get()
+}
+
+/// Returns where the signature of the bundle at the given path or URL is.
+fn signature_location(path: &str) -> String {
+    let end = path.find(['?', '#']).unwrap_or(path.len());
+
+    format!("{}.{SIGNATURE_EXT}{}", &path[..end], &path[end..])
+}
+
+fn read_local_signature(location: &str) -> Result<Option<String>, Error> {
+    match std::fs::read_to_string(location) {
+        Ok(it) => Ok(Some(it)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("failed to read signature: {location}")),
+    }
+}
+
+fn check(
+    verifier: &BundleVerifier,
+    path: &str,
+    data: &[u8],
+    maybe_signature: Option<String>,
+) -> Result<(), Error> {
+    verifier
+        .verify(data, maybe_signature.as_deref())
+        .map_err(|err| Error::from(err).context(format!("bundle rejected: {path}")))
+}
+
+/// Returns the detached signature of the bundle at the given path or URL, if
+/// it has one.
+pub(crate) async fn find_signature(path: &str) -> Result<Option<String>, Error> {
+    let location = signature_location(path);
+
+    if !(path.starts_with("http://") || path.starts_with("https://")) {
+        return tokio::task::spawn_blocking(move || read_local_signature(&location)).await?;
+    }
+
+    tokio::time::timeout(SIGNATURE_FETCH_TIMEOUT, async {
+        let res = reqwest_v011::get(&location).await?;
+
+        if res.status() == reqwest_v011::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let mut buf = vec![];
+
+        write_body_bounded(res.error_for_status()?, MAX_SIGNATURE_SIZE, &mut buf).await?;
+        Ok::<_, Error>(Some(String::from_utf8(buf)?))
+    })
+    .await
+    .map_err(|_| anyhow!("timed out"))
+    .and_then(|it| it)
+    .with_context(|| format!("failed to fetch signature: {location}"))
+}
+
+/// Verifies the content of the bundle at the given path or URL against its
+/// detached signature, if signatures are required.
+pub async fn verify_bundle(path: &str, data: &[u8]) -> Result<(), Error> {
+    let Some(verifier) = bundle_verifier() else {
+        return Ok(());
+    };
+
+    check(verifier, path, data, find_signature(path).await?)
+}
+
+/// Like [`verify_bundle`], for a bundle read from a local file at once.
+pub fn verify_bundle_file(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let Some(verifier) = bundle_verifier() else {
+        return Ok(());
+    };
+
+    let path = path.to_string_lossy();
+
+    check(
+        verifier,
+        &path,
+        data,
+        read_local_signature(&signature_location(&path))?,
+    )
+}
+
+/// Checks that a user worker is created from signed code, if signatures are
+/// required. The signature of a bundle is checked once it is resolved, see
+/// [`verify_bundle`], and the one of a local eszip once it is loaded, see
+/// [`load_local_eszip`](super::service_bundle::load_local_eszip).
+pub fn verify_user_worker_code(opts: &WorkerContextInitOpts) -> Result<(), Error> {
+    let Some(verifier) = bundle_verifier() else {
+        return Ok(());
+    };
+
+    let is_bundle = opts
+        .service_path
+        .to_str()
+        .and_then(BundleKind::detect)
+        .is_some();
+
+    let maybe_signature = opts
+        .conf
+        .as_user_worker()
+        .and_then(|it| it.eszip_signature.as_deref());
+
+    let result = if opts.maybe_module_code.is_some() || opts.maybe_module_map.is_some() {
+        Err(BundleSignatureError::Unsigned)
+    } else {
+        match opts.maybe_eszip.as_ref() {
+            Some(EszipPayloadKind::JsBufferKind(it)) => verifier.verify(it, maybe_signature),
+            Some(EszipPayloadKind::VecKind(it)) => verifier.verify(it, maybe_signature),
+            Some(EszipPayloadKind::Eszip(_)) => Err(BundleSignatureError::Unsigned),
+            None if is_bundle => Ok(()),
+            None => Err(BundleSignatureError::Unsigned),
+        }
+    };
+
+    result.map_err(|err| {
+        Error::from(err).context(format!("worker rejected: {}", opts.service_path.display()))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use base64::Engine;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use sb_workers::errors::BundleSignatureError;
+
+    use super::{signature_location, BundleVerifier};
+
+    #[test]
+    fn test_bundle_verifier() {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let verifier = BundleVerifier::new([engine.encode(key_pair.public_key())]).unwrap();
+        let signature = engine.encode(key_pair.sign(b"bundle"));
+
+        assert_eq!(verifier.verify(b"bundle", Some(&signature)), Ok(()));
+        assert_eq!(
+            verifier.verify(b"tampered", Some(&signature)),
+            Err(BundleSignatureError::Untrusted)
+        );
+        assert_eq!(
+            verifier.verify(b"bundle", Some("not base64")),
+            Err(BundleSignatureError::Malformed)
+        );
+        assert_eq!(
+            verifier.verify(b"bundle", None),
+            Err(BundleSignatureError::Unsigned)
+        );
+    }
+
+    #[test]
+    fn test_signature_location() {
+        assert_eq!(signature_location("./a.eszip"), "./a.eszip.sig");
+        assert_eq!(
+            signature_location("https://host/a.tar.gz?v=1"),
+            "https://host/a.tar.gz.sig?v=1"
+        );
+    }
+}
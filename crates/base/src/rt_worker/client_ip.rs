@@ -0,0 +1,138 @@
+use std::net::{IpAddr, SocketAddr};
+
+use http_v02::header::HeaderMap;
+use ipnetwork::IpNetwork;
+
+/// The header that carries the client IP resolved by the server to the
+/// workers. Whatever the client sent in it is discarded.
+pub static CLIENT_IP_HEADER: &str = "x-client-ip";
+
+static FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// How the IP of the client is resolved and which clients are let in.
+#[derive(Debug, Clone, Default)]
+pub struct ClientIpPolicy {
+    /// `X-Forwarded-For` is only honored if the request comes from one of
+    /// these ranges.
+    pub trusted_proxies: Vec<IpNetwork>,
+    /// Accepts only clients in one of these ranges, if any is given.
+    pub allowed_cidrs: Vec<IpNetwork>,
+    /// Rejects clients in any of these ranges, even if they are allowed.
+    pub denied_cidrs: Vec<IpNetwork>,
+}
+
+impl ClientIpPolicy {
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|it| it.contains(ip))
+    }
+
+    /// Returns the IP of the client that sent the request.
+    ///
+    /// If the peer is a trusted proxy, `X-Forwarded-For` is walked from the
+    /// right, and the first address that is not a trusted proxy is the client.
+    pub fn resolve(&self, remote_addr: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut ip = remote_addr?.ip().to_canonical();
+
+        if !self.is_trusted_proxy(ip) {
+            return Some(ip);
+        }
+
+        let forwarded = headers
+            .get_all(FORWARDED_FOR_HEADER)
+            .iter()
+            .filter_map(|it| it.to_str().ok())
+            .flat_map(|it| it.split(','))
+            .collect::<Vec<_>>();
+
+        for hop in forwarded.into_iter().rev() {
+            // NOTE: Stop at the first malformed hop, as nothing on the left of
+            // it can be trusted.
+            let Some(hop) = parse_hop(hop.trim()) else {
+                break;
+            };
+
+            ip = hop;
+
+            if !self.is_trusted_proxy(ip) {
+                break;
+            }
+        }
+
+        Some(ip)
+    }
+
+    /// Returns `false` if the client must be rejected. Clients whose IP can't
+    /// be resolved are only let in if no allowlist is configured.
+    pub fn is_allowed(&self, ip: Option<IpAddr>) -> bool {
+        let Some(ip) = ip else {
+            return self.allowed_cidrs.is_empty();
+        };
+
+        if self.denied_cidrs.iter().any(|it| it.contains(ip)) {
+            return false;
+        }
+
+        self.allowed_cidrs.is_empty() || self.allowed_cidrs.iter().any(|it| it.contains(ip))
+    }
+}
+
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    if let Ok(ip) = hop.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+
+    // NOTE: Some proxies append the port of the client as well.
+    hop.parse::<SocketAddr>()
+        .ok()
+        .map(|it| it.ip().to_canonical())
+}
+
+#[cfg(test)]
+mod test {
+    use http_v02::header::{HeaderMap, HeaderValue};
+
+    use super::ClientIpPolicy;
+
+    #[test]
+    fn test_resolve_client_ip() {
+        let policy = ClientIpPolicy {
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            allowed_cidrs: vec![],
+            denied_cidrs: vec!["203.0.113.0/24".parse().unwrap()],
+        };
+
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+
+            headers.insert("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+            headers
+        };
+
+        let resolve = |addr: &str, value: &str| {
+            policy
+                .resolve(Some(addr.parse().unwrap()), &headers(value))
+                .map(|it| it.to_string())
+        };
+
+        assert_eq!(
+            resolve("192.168.0.1:1234", "1.1.1.1").as_deref(),
+            Some("192.168.0.1")
+        );
+        assert_eq!(
+            resolve("10.0.0.1:1234", "1.1.1.1, 2.2.2.2, 10.0.0.2").as_deref(),
+            Some("2.2.2.2")
+        );
+        assert_eq!(
+            resolve("10.0.0.1:1234", "garbage, 10.0.0.3").as_deref(),
+            Some("10.0.0.3")
+        );
+        assert_eq!(
+            resolve("[::ffff:10.0.0.1]:1234", "[2001:db8::1]:443").as_deref(),
+            Some("2001:db8::1")
+        );
+
+        assert!(policy.is_allowed(Some("1.1.1.1".parse().unwrap())));
+        assert!(!policy.is_allowed(Some("203.0.113.7".parse().unwrap())));
+        assert!(policy.is_allowed(None));
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Error};
+use bytes::Bytes;
+use futures_util::future::{BoxFuture, Shared};
+use futures_util::FutureExt;
+use http_utils::utils::get_upgrade_type;
+use http_v02::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Version};
+use hyper_v014::Body;
+use sb_workers::context::SendRequestResult;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+/// Request headers that usually affect the response, so requests that differ
+/// in any of them are never coalesced.
+static VARY_HEADERS: &[header::HeaderName] = &[
+    header::ACCEPT,
+    header::ACCEPT_ENCODING,
+    header::ACCEPT_LANGUAGE,
+    header::AUTHORIZATION,
+    header::COOKIE,
+    header::RANGE,
+];
+
+/// Responses larger than this are streamed to the leader only, and the
+/// followers fall back to sending their own requests.
+static MAX_SHARED_BODY_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoalesceKey {
+    worker_key: Uuid,
+    uri: String,
+    vary: Vec<Option<HeaderValue>>,
+}
+
+impl CoalesceKey {
+    /// Returns `None` if the request must not be coalesced.
+    pub fn from_request(worker_key: Uuid, req: &Request<Body>) -> Option<Self> {
+        let headers = req.headers();
+
+        if req.method() != Method::GET
+            || get_upgrade_type(headers).is_some()
+            || headers.contains_key(header::CONTENT_LENGTH)
+            || headers.contains_key(header::TRANSFER_ENCODING)
+        {
+            return None;
+        }
+
+        Some(Self {
+            worker_key,
+            uri: req.uri().to_string(),
+            vary: VARY_HEADERS
+                .iter()
+                .map(|it| headers.get(it).cloned())
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SharedResponse {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl SharedResponse {
+    fn to_response(&self) -> Response<Body> {
+        let mut res = Response::new(Body::from(self.body.clone()));
+
+        *res.status_mut() = self.status;
+        *res.version_mut() = self.version;
+        *res.headers_mut() = self.headers.clone();
+
+        res
+    }
+}
+
+type InflightResponse = Shared<BoxFuture<'static, Option<SharedResponse>>>;
+
+/// Lets concurrent identical `GET` requests to the same worker share a single
+/// invocation of the worker.
+#[derive(Debug, Clone, Default)]
+pub struct RequestCoalescer {
+    inflight: Arc<Mutex<HashMap<CoalesceKey, InflightResponse>>>,
+}
+
+impl RequestCoalescer {
+    pub async fn run<F, Fut>(
+        &self,
+        key: CoalesceKey,
+        req: Request<Body>,
+        req_end_tx: mpsc::UnboundedSender<()>,
+        handler: F,
+    ) -> Result<SendRequestResult, Error>
+    where
+        F: FnOnce(Request<Body>) -> Fut,
+        Fut: Future<Output = Result<SendRequestResult, Error>>,
+    {
+        let (leader_tx, maybe_inflight) = {
+            let mut inflight = self.inflight.lock().unwrap();
+
+            match inflight.get(&key) {
+                Some(fut) => (None, Some(fut.clone())),
+                None => {
+                    let (tx, rx) = oneshot::channel::<Option<SharedResponse>>();
+
+                    inflight.insert(key.clone(), rx.map(|it| it.ok().flatten()).boxed().shared());
+                    (Some(tx), None)
+                }
+            }
+        };
+
+        if let Some(fut) = maybe_inflight {
+            return match fut.await {
+                Some(res) => Ok((res.to_response(), req_end_tx)),
+
+                // The response of the leader could not be shared, so the
+                // request should be sent on its own.
+                None => handler(req).await,
+            };
+        }
+
+        let result = handler(req).await;
+        let leader_tx = leader_tx.unwrap();
+
+        self.inflight.lock().unwrap().remove(&key);
+
+        let (res, req_end_tx) = match result {
+            Ok(it) => it,
+            Err(err) => {
+                let _ = leader_tx.send(None);
+                return Err(err);
+            }
+        };
+
+        if !is_shareable(&res) {
+            let _ = leader_tx.send(None);
+            return Ok((res, req_end_tx));
+        }
+
+        let (parts, body) = res.into_parts();
+        let body = match hyper_v014::body::to_bytes(body).await {
+            Ok(it) => it,
+            Err(err) => {
+                let _ = leader_tx.send(None);
+                let _ = req_end_tx.send(());
+                return Err(anyhow!(err));
+            }
+        };
+
+        let shared = SharedResponse {
+            status: parts.status,
+            version: parts.version,
+            headers: parts.headers,
+            body,
+        };
+
+        let res = shared.to_response();
+        let _ = leader_tx.send(Some(shared));
+
+        Ok((res, req_end_tx))
+    }
+}
+
+fn is_shareable(res: &Response<Body>) -> bool {
+    let headers = res.headers();
+
+    if headers.contains_key(header::SET_COOKIE) {
+        return false;
+    }
+
+    let is_event_stream = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|it| it.to_str().ok())
+        .map_or(false, |it| it.starts_with("text/event-stream"));
+
+    let is_small_enough = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.parse::<u64>().ok())
+        .map_or(false, |it| it <= MAX_SHARED_BODY_SIZE);
+
+    !is_event_stream && is_small_enough
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use http_v02::{header, Request, Response};
+    use hyper_v014::Body;
+    use tokio::sync::{mpsc, oneshot};
+    use uuid::Uuid;
+
+    use super::{CoalesceKey, RequestCoalescer};
+
+    fn get(uri: &str, token: &str) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .header(header::AUTHORIZATION, token)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn respond(body: &'static str, set_cookie: bool) -> Response<Body> {
+        let mut res = Response::builder().header(header::CONTENT_LENGTH, body.len());
+
+        if set_cookie {
+            res = res.header(header::SET_COOKIE, "a=b");
+        }
+
+        res.body(Body::from(body)).unwrap()
+    }
+
+    async fn body_of(res: Response<Body>) -> String {
+        let body = hyper_v014::body::to_bytes(res.into_body()).await.unwrap();
+
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    async fn run_concurrently(set_cookie: bool) -> (usize, String, String) {
+        let coalescer = RequestCoalescer::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let key = CoalesceKey::from_request(Uuid::nil(), &get("/a", "t")).unwrap();
+        let (req_end_tx, _req_end_rx) = mpsc::unbounded_channel();
+        let (gate_tx, gate_rx) = oneshot::channel::<()>();
+        let handler = |gate_rx: Option<oneshot::Receiver<()>>| {
+            let calls = calls.clone();
+            let req_end_tx = req_end_tx.clone();
+
+            move |_| async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+
+                if let Some(rx) = gate_rx {
+                    let _ = rx.await;
+                }
+
+                Ok::<_, anyhow::Error>((respond("hello", set_cookie), req_end_tx))
+            }
+        };
+
+        let (leader, follower, _) = tokio::join!(
+            coalescer.run(
+                key.clone(),
+                get("/a", "t"),
+                req_end_tx.clone(),
+                handler(Some(gate_rx))
+            ),
+            coalescer.run(key, get("/a", "t"), req_end_tx.clone(), handler(None)),
+            async move {
+                tokio::task::yield_now().await;
+                gate_tx.send(()).unwrap();
+            }
+        );
+
+        (
+            calls.load(Ordering::SeqCst),
+            body_of(leader.unwrap().0).await,
+            body_of(follower.unwrap().0).await,
+        )
+    }
+
+    #[test]
+    fn test_coalesce_key() {
+        let key = CoalesceKey::from_request(Uuid::nil(), &get("/a", "t"));
+
+        assert!(key.is_some());
+        assert_eq!(key, CoalesceKey::from_request(Uuid::nil(), &get("/a", "t")));
+        assert_ne!(key, CoalesceKey::from_request(Uuid::nil(), &get("/a", "u")));
+        assert_ne!(key, CoalesceKey::from_request(Uuid::nil(), &get("/b", "t")));
+
+        let mut post = get("/a", "t");
+
+        *post.method_mut() = http_v02::Method::POST;
+
+        assert_eq!(CoalesceKey::from_request(Uuid::nil(), &post), None);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_share_one_invocation() {
+        assert_eq!(
+            run_concurrently(false).await,
+            (1, "hello".to_string(), "hello".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_private_responses_are_not_shared() {
+        assert_eq!(
+            run_concurrently(true).await,
+            (2, "hello".to_string(), "hello".to_string())
+        );
+    }
+}
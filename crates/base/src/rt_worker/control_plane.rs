@@ -0,0 +1,516 @@
+//! A gRPC service that maps onto the messages of the worker pool, so that the
+//! control plane managing the user workers can live in a different process or
+//! host. It is the counterpart of the internal workers API, see
+//! `proto/control_plane.proto`.
+//!
+//! The callers present the bearer token of the internal API, so the service
+//! is only served in the clear on a loopback address.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use event_worker::events::WorkerEventWithMetadata;
+use futures_util::{future, stream, Stream, StreamExt};
+use http_v02::{Method, Request, StatusCode};
+use hyper_v014::Body;
+use log::{debug, error};
+use once_cell::sync::Lazy;
+use sb_graph::DecoratorType;
+use sb_workers::context::{
+    CreateUserWorkerResult, SendRequestResult, UserWorkerMsgs, UserWorkerRuntimeOpts,
+    WorkerContextInitOpts, WorkerRuntimeOpts,
+};
+use sb_workers::errors::WorkerOptionsChangedError;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tonic::{Status, Streaming};
+use uuid::Uuid;
+
+use super::internal_auth::{ConnInfo, InternalApiAuth};
+use super::router::ReqEndOnDrop;
+
+pub mod proto {
+    tonic::include_proto!("edge_runtime.control_plane.v1");
+}
+
+use proto::control_plane_server::{ControlPlane, ControlPlaneServer};
+use proto::{
+    request_frame, response_frame, CreateWorkerRequest, CreateWorkerResponse, Header, RequestFrame,
+    ResponseFrame, ResponseHead, ShutdownRequest, ShutdownResponse, WatchEventsRequest,
+    WorkerEvent,
+};
+
+/// The watchers falling further behind than this are disconnected.
+static EVENT_TAP_CAPACITY: usize = 1024;
+
+static EVENT_TAP: Lazy<broadcast::Sender<WorkerEvent>> =
+    Lazy::new(|| broadcast::channel(EVENT_TAP_CAPACITY).0);
+
+/// Hands a copy of the event to the watchers of the control plane, if any.
+pub(crate) fn tap_event(event: &WorkerEventWithMetadata) {
+    if EVENT_TAP.receiver_count() == 0 {
+        return;
+    }
+
+    let json = match serde_json::to_string(event) {
+        Ok(it) => it,
+        Err(err) => {
+            error!("failed to serialize event: {err}");
+            return;
+        }
+    };
+
+    let _ = EVENT_TAP.send(WorkerEvent {
+        service_path: event.metadata.service_path.clone(),
+        execution_id: event.metadata.execution_id.map(|it| it.to_string()),
+        json,
+    });
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlPlaneOpts {
+    pub import_map_path: Option<String>,
+    pub no_module_cache: bool,
+    pub maybe_decorator: Option<DecoratorType>,
+    /// The callers are authenticated the same way as the ones of the internal
+    /// workers API, except that client certificates are not supported.
+    pub auth: InternalApiAuth,
+}
+
+impl CreateWorkerRequest {
+    fn into_init_opts(self, opts: &ControlPlaneOpts) -> WorkerContextInitOpts {
+        let mut conf = UserWorkerRuntimeOpts {
+            force_create: self.force_create,
+            idempotency_key: self.idempotency_key,
+            tenant: self.tenant,
+            revision: self.revision,
+            allow_net: (!self.allow_net.is_empty()).then_some(self.allow_net),
+            ..Default::default()
+        };
+
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                $(
+                    if let Some(value) = self.$field {
+                        conf.$field = value;
+                    }
+                )*
+            };
+        }
+
+        merge!(
+            memory_limit_mb,
+            worker_timeout_ms,
+            cpu_time_soft_limit_ms,
+            cpu_time_hard_limit_ms,
+            net_access_disabled
+        );
+
+        WorkerContextInitOpts {
+            service_path: PathBuf::from(self.service_path),
+            no_module_cache: self.no_module_cache.unwrap_or(opts.no_module_cache),
+            import_map_path: self
+                .import_map_path
+                .or_else(|| opts.import_map_path.clone()),
+            env_vars: self.env_vars,
+            timing: None,
+            conf: WorkerRuntimeOpts::UserWorker(conf),
+            maybe_eszip: None,
+            maybe_module_code: None,
+            maybe_module_map: None,
+            maybe_entrypoint: None,
+            maybe_decorator: opts.maybe_decorator,
+            static_patterns: vec![],
+            maybe_jsx_import_source_config: None,
+            locale: Default::default(),
+        }
+    }
+}
+
+fn worker_pool_gone() -> Status {
+    Status::unavailable("worker pool is gone")
+}
+
+fn parse_key(key: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(key).map_err(|_| Status::invalid_argument("invalid worker key"))
+}
+
+struct ControlPlaneService {
+    opts: Arc<ControlPlaneOpts>,
+    worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+}
+
+impl ControlPlaneService {
+    fn authorize<T>(&self, req: &tonic::Request<T>) -> Result<(), Status> {
+        let conn_info = ConnInfo {
+            remote_addr: req.remote_addr(),
+            ..Default::default()
+        };
+
+        let maybe_token = req
+            .metadata()
+            .get("authorization")
+            .and_then(|it| it.to_str().ok())
+            .and_then(|it| it.strip_prefix("Bearer "));
+
+        self.opts
+            .auth
+            .check_conn(conn_info, maybe_token)
+            .map_err(|(status, msg)| match status {
+                StatusCode::UNAUTHORIZED => Status::unauthenticated(msg),
+                _ => Status::permission_denied(msg),
+            })
+    }
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl ControlPlane for ControlPlaneService {
+    type SendRequestStreamStream = ResponseStream<ResponseFrame>;
+    type WatchEventsStream = ResponseStream<WorkerEvent>;
+
+    async fn create_worker(
+        &self,
+        req: tonic::Request<CreateWorkerRequest>,
+    ) -> Result<tonic::Response<CreateWorkerResponse>, Status> {
+        self.authorize(&req)?;
+
+        let (tx, rx) = oneshot::channel::<Result<CreateUserWorkerResult, Error>>();
+
+        self.worker_pool_tx
+            .send(UserWorkerMsgs::Create(
+                req.into_inner().into_init_opts(&self.opts),
+                tx,
+            ))
+            .map_err(|_| worker_pool_gone())?;
+
+        let CreateUserWorkerResult {
+            key,
+            warnings,
+            decision,
+        } = rx
+            .await
+            .map_err(|_| worker_pool_gone())?
+            .map_err(
+                |err| match err.downcast_ref::<WorkerOptionsChangedError>() {
+                    Some(it) => Status::failed_precondition(it.to_string()),
+                    None => {
+                        error!("failed to create a worker through the control plane: {err:#}");
+                        Status::internal("failed to create the worker")
+                    }
+                },
+            )?;
+
+        Ok(tonic::Response::new(CreateWorkerResponse {
+            key: key.to_string(),
+            warnings: warnings.iter().map(ToString::to_string).collect(),
+            decision: decision.to_string(),
+        }))
+    }
+
+    async fn send_request_stream(
+        &self,
+        req: tonic::Request<Streaming<RequestFrame>>,
+    ) -> Result<tonic::Response<Self::SendRequestStreamStream>, Status> {
+        self.authorize(&req)?;
+
+        let mut frames = req.into_inner();
+        let Some(request_frame::Frame::Head(head)) =
+            frames.message().await?.and_then(|it| it.frame)
+        else {
+            return Err(Status::invalid_argument(
+                "the first frame must be the head of the request",
+            ));
+        };
+
+        let key = parse_key(&head.worker_key)?;
+        let method = Method::from_bytes(head.method.as_bytes())
+            .map_err(|_| Status::invalid_argument("invalid method"))?;
+
+        let mut builder = Request::builder().method(method).uri(head.uri);
+
+        for Header { name, value } in head.headers {
+            builder = builder.header(name, value);
+        }
+
+        let body = Body::wrap_stream(frames.filter_map(|it| {
+            future::ready(match it {
+                Ok(RequestFrame {
+                    frame: Some(request_frame::Frame::Body(chunk)),
+                }) => Some(Ok(chunk)),
+                Ok(_) => Some(Err(Status::invalid_argument(
+                    "only the first frame may be the head of the request",
+                ))),
+                Err(status) => Some(Err(status)),
+            })
+        }));
+
+        let req = builder
+            .body(body)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+
+        let (res_tx, res_rx) = oneshot::channel::<Result<SendRequestResult, Error>>();
+
+        self.worker_pool_tx
+            .send(UserWorkerMsgs::SendRequest(key, req, res_tx, None))
+            .map_err(|_| worker_pool_gone())?;
+
+        let (res, req_end_tx) = res_rx
+            .await
+            .map_err(|_| worker_pool_gone())?
+            .map_err(|err| {
+                error!("failed to send a request through the control plane: {err:#}");
+                Status::unavailable("the worker failed to respond")
+            })?;
+
+        let (parts, body) = res.into_parts();
+        let head = ResponseFrame {
+            frame: Some(response_frame::Frame::Head(ResponseHead {
+                status: parts.status.as_u16() as u32,
+                headers: parts
+                    .headers
+                    .iter()
+                    .map(|(name, value)| Header {
+                        name: name.to_string(),
+                        value: value.as_bytes().to_vec(),
+                    })
+                    .collect(),
+            })),
+        };
+
+        let body = ReqEndOnDrop {
+            inner: body,
+            req_end_tx: Some(req_end_tx),
+        }
+        .map(|it| match it {
+            Ok(chunk) => Ok(ResponseFrame {
+                frame: Some(response_frame::Frame::Body(chunk.to_vec())),
+            }),
+            Err(err) => {
+                error!("failed to read the response body of a worker: {err:#}");
+                Err(Status::internal("failed to read the response body"))
+            }
+        });
+
+        Ok(tonic::Response::new(Box::pin(
+            stream::once(future::ready(Ok(head))).chain(body),
+        )))
+    }
+
+    async fn shutdown(
+        &self,
+        req: tonic::Request<ShutdownRequest>,
+    ) -> Result<tonic::Response<ShutdownResponse>, Status> {
+        self.authorize(&req)?;
+
+        let key = parse_key(&req.get_ref().worker_key)?;
+        let (tx, rx) = oneshot::channel::<bool>();
+
+        self.worker_pool_tx
+            .send(UserWorkerMsgs::Terminate(key, tx))
+            .map_err(|_| worker_pool_gone())?;
+
+        Ok(tonic::Response::new(ShutdownResponse {
+            found: rx.await.map_err(|_| worker_pool_gone())?,
+        }))
+    }
+
+    async fn watch_events(
+        &self,
+        req: tonic::Request<WatchEventsRequest>,
+    ) -> Result<tonic::Response<Self::WatchEventsStream>, Status> {
+        self.authorize(&req)?;
+
+        let maybe_service_path = req.into_inner().service_path;
+        let events = stream::unfold(Some(EVENT_TAP.subscribe()), |maybe_rx| async move {
+            let mut rx = maybe_rx?;
+
+            match rx.recv().await {
+                Ok(event) => Some((Ok(event), Some(rx))),
+                Err(broadcast::error::RecvError::Lagged(count)) => Some((
+                    Err(Status::resource_exhausted(format!(
+                        "the watcher fell behind and missed {count} events"
+                    ))),
+                    None,
+                )),
+                Err(broadcast::error::RecvError::Closed) => None,
+            }
+        })
+        .filter(move |it| {
+            future::ready(match (it, maybe_service_path.as_deref()) {
+                (Ok(event), Some(service_path)) => {
+                    event.service_path.as_deref() == Some(service_path)
+                }
+                _ => true,
+            })
+        });
+
+        Ok(tonic::Response::new(Box::pin(events)))
+    }
+}
+
+/// Serves the control plane on the given address until the token is
+/// cancelled, over TLS if a configuration is given. Otherwise the address must
+/// be a loopback one.
+pub async fn serve_control_plane(
+    addr: SocketAddr,
+    opts: ControlPlaneOpts,
+    maybe_tls_config: Option<Arc<ServerConfig>>,
+    worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    token: CancellationToken,
+) -> Result<(), Error> {
+    let router = tonic::transport::Server::builder().add_service(ControlPlaneServer::new(
+        ControlPlaneService {
+            opts: Arc::new(opts),
+            worker_pool_tx,
+        },
+    ));
+
+    let Some(tls_config) = maybe_tls_config else {
+        if !addr.ip().to_canonical().is_loopback() {
+            bail!("the gRPC control plane must be served over TLS unless it is bound to a loopback address");
+        }
+
+        router
+            .serve_with_shutdown(addr, token.cancelled_owned())
+            .await?;
+
+        return Ok(());
+    };
+
+    let listener = TcpListener::bind(addr).await?;
+    let acceptor = TlsAcceptor::from(tls_config);
+    let (conn_tx, conn_rx) = mpsc::unbounded_channel();
+
+    // NOTE: The handshakes are done apart from the accept loop, so that a slow
+    // client does not hold up the others.
+    drop(tokio::spawn({
+        let token = token.clone();
+
+        async move {
+            loop {
+                let conn = tokio::select! {
+                    res = listener.accept() => match res {
+                        Ok((conn, _)) => conn,
+                        Err(err) => {
+                            error!("failed to accept a gRPC control plane connection: {err}");
+                            continue;
+                        }
+                    },
+                    _ = token.cancelled() => break,
+                };
+
+                let acceptor = acceptor.clone();
+                let conn_tx = conn_tx.clone();
+
+                drop(tokio::spawn(async move {
+                    match acceptor.accept(conn).await {
+                        Ok(conn) => {
+                            let _ = conn_tx.send(conn);
+                        }
+
+                        Err(err) => debug!("gRPC control plane TLS handshake failed: {err}"),
+                    }
+                }));
+            }
+        }
+    }));
+
+    let incoming = stream::unfold(conn_rx, |mut rx| async move {
+        rx.recv()
+            .await
+            .map(|conn| (Ok::<_, std::io::Error>(conn), rx))
+    });
+
+    router
+        .serve_with_incoming_shutdown(incoming, token.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    use super::proto::CreateWorkerRequest;
+    use super::{serve_control_plane, ControlPlaneOpts};
+
+    #[tokio::test]
+    async fn test_control_plane_requires_tls_off_loopback() {
+        let opts = || ControlPlaneOpts {
+            import_map_path: None,
+            no_module_cache: false,
+            maybe_decorator: None,
+            auth: Default::default(),
+        };
+
+        let (worker_pool_tx, _) = mpsc::unbounded_channel();
+        let token = CancellationToken::new();
+        let err = serve_control_plane(
+            "0.0.0.0:0".parse().unwrap(),
+            opts(),
+            None,
+            worker_pool_tx.clone(),
+            token.clone(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(err.to_string().contains("TLS"));
+
+        token.cancel();
+
+        assert!(serve_control_plane(
+            "127.0.0.1:0".parse().unwrap(),
+            opts(),
+            None,
+            worker_pool_tx,
+            token,
+        )
+        .await
+        .is_ok());
+    }
+
+    #[test]
+    fn test_create_worker_request_into_init_opts() {
+        let opts = ControlPlaneOpts {
+            import_map_path: Some("./import_map.json".to_string()),
+            no_module_cache: false,
+            maybe_decorator: None,
+            auth: Default::default(),
+        };
+
+        let init_opts = CreateWorkerRequest {
+            service_path: "./foo".to_string(),
+            env_vars: HashMap::from([("FOO".to_string(), "bar".to_string())]),
+            memory_limit_mb: Some(64),
+            tenant: Some("acme".to_string()),
+            ..Default::default()
+        }
+        .into_init_opts(&opts);
+
+        let conf = init_opts.conf.as_user_worker().unwrap();
+
+        assert_eq!(init_opts.service_path.to_str(), Some("./foo"));
+        assert_eq!(
+            init_opts.import_map_path.as_deref(),
+            Some("./import_map.json")
+        );
+        assert_eq!(init_opts.env_vars["FOO"], "bar");
+        assert_eq!(conf.memory_limit_mb, 64);
+        assert_eq!(conf.tenant.as_deref(), Some("acme"));
+        assert!(conf.allow_net.is_none());
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Error};
+use sb_workers::context::{CreateUserWorkerResult, WorkerIdentity};
+use tokio::sync::oneshot;
+
+type CreateResultSender = oneshot::Sender<Result<CreateUserWorkerResult, Error>>;
+
+/// Creations with the same key boot a single worker.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CreateKey {
+    pub identity: WorkerIdentity,
+    pub idempotency_key: Option<String>,
+}
+
+/// Lets concurrent creations of the same worker await a single boot and share
+/// its outcome, so that racing creations do not boot isolates to no avail.
+#[derive(Debug, Clone, Default)]
+pub struct CreateDeduplicator {
+    pending: Arc<Mutex<HashMap<CreateKey, Vec<CreateResultSender>>>>,
+}
+
+impl CreateDeduplicator {
+    /// Joins the creation in flight with the same key, if any. Otherwise, the
+    /// sender is given back and the caller should lead the creation.
+    pub(crate) fn join(
+        &self,
+        key: &CreateKey,
+        tx: CreateResultSender,
+    ) -> Option<CreateResultSender> {
+        match self.pending.lock().unwrap().get_mut(key) {
+            Some(followers) => {
+                followers.push(tx);
+                None
+            }
+
+            None => Some(tx),
+        }
+    }
+
+    /// Marks a creation as in flight, so that the following creations with the
+    /// same key join it until the returned guard is settled or dropped.
+    pub(crate) fn lead(&self, key: CreateKey) -> PendingCreate {
+        self.pending.lock().unwrap().insert(key.clone(), vec![]);
+
+        PendingCreate {
+            dedup: self.clone(),
+            key: Some(key),
+        }
+    }
+}
+
+/// A creation in flight that other creations may join.
+pub(crate) struct PendingCreate {
+    dedup: CreateDeduplicator,
+    key: Option<CreateKey>,
+}
+
+impl PendingCreate {
+    /// Stops accepting followers and returns the ones that joined so far.
+    pub(crate) fn release(mut self) -> Followers {
+        self.take_followers()
+    }
+
+    /// Shares the outcome of the creation with the followers, returning how
+    /// many of them received it.
+    pub(crate) fn settle(self, result: &Result<CreateUserWorkerResult, Error>) -> usize {
+        self.release().send(result)
+    }
+
+    fn take_followers(&mut self) -> Followers {
+        let followers = self
+            .key
+            .take()
+            .and_then(|it| self.dedup.pending.lock().unwrap().remove(&it))
+            .unwrap_or_default();
+
+        Followers(followers)
+    }
+}
+
+impl Drop for PendingCreate {
+    fn drop(&mut self) {
+        if self.key.is_some() {
+            self.take_followers()
+                .send(&Err(anyhow!("worker creation was abandoned")));
+        }
+    }
+}
+
+pub(crate) struct Followers(Vec<CreateResultSender>);
+
+impl Followers {
+    pub(crate) fn send(self, result: &Result<CreateUserWorkerResult, Error>) -> usize {
+        self.0
+            .into_iter()
+            .map(|tx| {
+                tx.send(match result {
+                    Ok(CreateUserWorkerResult {
+                        key,
+                        warnings,
+                        decision,
+                    }) => Ok(CreateUserWorkerResult {
+                        key: *key,
+                        warnings: warnings.clone(),
+                        decision: *decision,
+                    }),
+                    Err(err) => Err(anyhow!("{err:#}")),
+                })
+            })
+            .filter(Result::is_ok)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sb_workers::context::{CreateDecision, CreateUserWorkerResult, WorkerIdentity};
+    use tokio::sync::oneshot;
+    use uuid::Uuid;
+
+    use super::{CreateDeduplicator, CreateKey};
+
+    #[test]
+    fn test_create_dedup() {
+        let dedup = CreateDeduplicator::default();
+        let key = |idempotency_key: Option<&str>| CreateKey {
+            identity: WorkerIdentity {
+                tenant: None,
+                service: "./hello".to_string(),
+                revision: Some("1".to_string()),
+            },
+            idempotency_key: idempotency_key.map(str::to_string),
+        };
+
+        let (tx, _rx) = oneshot::channel();
+        let tx = dedup.join(&key(Some("a")), tx).unwrap();
+        let pending = dedup.lead(key(Some("a")));
+
+        let (follower_tx, mut follower_rx) = oneshot::channel();
+        let (other_tx, _other_rx) = oneshot::channel();
+
+        assert!(dedup.join(&key(Some("a")), follower_tx).is_none());
+        assert!(dedup.join(&key(Some("b")), other_tx).is_some());
+
+        let worker_key = Uuid::new_v4();
+
+        assert_eq!(
+            pending.settle(&Ok(CreateUserWorkerResult {
+                key: worker_key,
+                warnings: vec![],
+                decision: CreateDecision::Created,
+            })),
+            1
+        );
+        assert_eq!(follower_rx.try_recv().unwrap().unwrap().key, worker_key);
+
+        // A settled creation is not joined anymore, and the followers of an
+        // abandoned one are failed.
+        let tx = dedup.join(&key(Some("a")), tx).unwrap();
+        let pending = dedup.lead(key(Some("a")));
+        let (follower_tx, mut follower_rx) = oneshot::channel();
+
+        assert!(dedup.join(&key(Some("a")), follower_tx).is_none());
+        drop(pending);
+        assert!(follower_rx.try_recv().unwrap().is_err());
+        assert!(dedup.join(&key(Some("a")), tx).is_some());
+    }
+}
@@ -0,0 +1,71 @@
+//! The deadlines requests carry along, so that the timeouts of the services a
+//! request goes through compose.
+//!
+//! A deadline is given in milliseconds since the unix epoch by the
+//! `x-deadline-ms` header. It bounds the time the runtime waits for the
+//! response of a worker, and the fetches a user worker makes while handling
+//! the request carry it on upstream.
+//!
+//! NOTE: Only the runtime sets the header, so the one a client sends is
+//! stripped at the edge.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http_v02::Request;
+
+pub static DEADLINE_HEADER: &str = "x-deadline-ms";
+
+/// Returns the deadline of a request, if it carries a valid one.
+pub fn get_deadline<B>(req: &Request<B>) -> Option<SystemTime> {
+    req.headers()
+        .get(DEADLINE_HEADER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|it| UNIX_EPOCH.checked_add(Duration::from_millis(it)))
+}
+
+/// Returns the time left until the deadline, which is zero once it has
+/// expired.
+pub fn get_time_left(deadline: SystemTime) -> Duration {
+    deadline
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use http_v02::Request;
+
+    use super::{get_deadline, get_time_left, DEADLINE_HEADER};
+
+    fn request(deadline: &str) -> Request<()> {
+        Request::builder()
+            .header(DEADLINE_HEADER, deadline)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_get_deadline() {
+        assert_eq!(
+            get_deadline(&request("1700000000000")),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!(get_deadline(&request("soon")), None);
+        assert_eq!(get_deadline(&request("-1")), None);
+        assert_eq!(get_deadline(&Request::new(())), None);
+    }
+
+    #[test]
+    fn test_get_time_left() {
+        assert_eq!(get_time_left(UNIX_EPOCH), Duration::ZERO);
+        assert!(
+            get_time_left(SystemTime::now() + Duration::from_secs(60)) > Duration::from_secs(59)
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Error};
+use http_v02::StatusCode;
+use hyper_v014::{Body, Request};
+use sb_workers::context::{HealthCheck, HealthReport, UserWorkerProfile};
+use tokio::sync::Notify;
+
+use super::worker_ctx::send_user_worker_request;
+use super::worker_pool::SupervisorPolicy;
+
+/// Marks the synthetic requests of a [`HealthCheck`], so that a worker can
+/// tell them apart from the requests of the clients.
+pub static HEALTH_CHECK_HEADER: &str = "x-edge-runtime-health-check";
+
+/// Sends the synthetic request of `check` to the worker, and reports how it
+/// responded.
+pub(crate) async fn check_worker_health(
+    policy: SupervisorPolicy,
+    profile: UserWorkerProfile,
+    check: HealthCheck,
+) -> HealthReport {
+    let started_at = Instant::now();
+    let result = send_health_check(policy, profile, &check).await;
+    let (status, error) = match result {
+        Ok(status) => (Some(status.as_u16()), None),
+        Err(err) => (None, Some(format!("{err:#}"))),
+    };
+
+    HealthReport {
+        status,
+        latency_ms: started_at
+            .elapsed()
+            .as_millis()
+            .try_into()
+            .unwrap_or(u64::MAX),
+        error,
+    }
+}
+
+async fn send_health_check(
+    policy: SupervisorPolicy,
+    profile: UserWorkerProfile,
+    check: &HealthCheck,
+) -> Result<StatusCode, Error> {
+    if policy.is_oneshot() {
+        bail!("a oneshot worker can't be checked without being used up");
+    }
+
+    let req = Request::builder()
+        .method(check.method.as_str())
+        .uri(check.path.as_str())
+        .header(HEALTH_CHECK_HEADER, "1")
+        .body(Body::empty())?;
+
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(check.timeout_ms);
+    let (req_start_tx, req_end_tx) = profile.timing_tx_pair.clone();
+
+    // NOTE: The supervisor has to see the check as any other request, or it
+    // would tell the worker idle (or retire it) while the check is running.
+    if policy.is_per_worker() {
+        profile.status.demand.fetch_add(1, Ordering::Release);
+    } else {
+        let fence = Arc::new(Notify::const_new());
+
+        req_start_tx
+            .send(fence.clone())
+            .map_err(|_| anyhow!("failed to notify the fence to the supervisor"))?;
+
+        if tokio::time::timeout_at(deadline, fence.notified())
+            .await
+            .is_err()
+        {
+            // NOTE: The supervisor admits the check anyway, so it is ended as
+            // soon as it is to pass the slot on to the next request.
+            let cancel = profile.cancel.clone();
+
+            drop(tokio::spawn(async move {
+                tokio::select! {
+                    _ = fence.notified() => {
+                        let _ = req_end_tx.send(());
+                    }
+                    _ = cancel.cancelled() => {}
+                }
+            }));
+
+            bail!("the worker did not admit the health check in time");
+        }
+    }
+
+    let _req_end = scopeguard::guard(req_end_tx, |it| {
+        let _ = it.send(());
+    });
+
+    let respond = async move {
+        let res = send_user_worker_request(
+            profile.worker_request_msg_tx,
+            req,
+            profile.cancel,
+            profile.exit,
+            None,
+        )
+        .await?;
+
+        let status = res.status();
+
+        hyper_v014::body::to_bytes(res.into_body()).await?;
+        Ok(status)
+    };
+
+    match tokio::time::timeout_at(deadline, respond).await {
+        Ok(result) => result,
+        Err(_) => bail!("the worker did not respond to the health check in time"),
+    }
+}
@@ -0,0 +1,169 @@
+use std::net::SocketAddr;
+
+use http_v02::{header, StatusCode};
+use hyper_v014::{Body, Request};
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize, Serializer};
+
+/// What is known about the connection a request came in through. Attached to
+/// every request as an extension by the server.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnInfo {
+    pub remote_addr: Option<SocketAddr>,
+    /// Whether the client presented a certificate that was verified against
+    /// the configured client CA during the TLS handshake.
+    pub has_verified_client_cert: bool,
+    /// The negotiated TLS version, for connections through the TLS port.
+    pub tls_version: Option<&'static str>,
+}
+
+/// How the callers of the internal endpoints are authenticated.
+///
+/// Every configured method must be satisfied, so they can be combined (e.g. a
+/// bearer token that is only accepted from a private network).
+///
+/// The routes of the runtime config are authenticated the same way.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct InternalApiAuth {
+    // NOTE: The token must not show up in the changes of a config reload.
+    #[serde(serialize_with = "serialize_redacted")]
+    pub bearer_token: Option<String>,
+    /// Accepts only requests whose source address is in one of these ranges.
+    pub allowed_cidrs: Vec<IpNetwork>,
+    /// Accepts only requests sent over TLS with a verified client certificate.
+    pub require_client_cert: bool,
+}
+
+impl InternalApiAuth {
+    /// Returns `false` if no method is configured, in which case the internal
+    /// endpoints must stay disabled.
+    pub fn is_enabled(&self) -> bool {
+        self.bearer_token.is_some() || !self.allowed_cidrs.is_empty() || self.require_client_cert
+    }
+
+    /// Returns the status and the reason to reject the request with, if it is.
+    pub fn check(&self, req: &Request<Body>) -> Result<(), (StatusCode, &'static str)> {
+        let conn_info = req
+            .extensions()
+            .get::<ConnInfo>()
+            .copied()
+            .unwrap_or_default();
+
+        self.check_conn(conn_info, get_bearer_token(req))
+    }
+
+    /// Same as [`Self::check`], for callers that don't come in through the
+    /// HTTP server.
+    pub(crate) fn check_conn(
+        &self,
+        conn_info: ConnInfo,
+        maybe_token: Option<&str>,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        if self.require_client_cert && !conn_info.has_verified_client_cert {
+            return Err((StatusCode::FORBIDDEN, "client certificate required"));
+        }
+
+        if !self.allowed_cidrs.is_empty() {
+            let Some(addr) = conn_info.remote_addr else {
+                return Err((StatusCode::FORBIDDEN, "source address not allowed"));
+            };
+
+            let ip = addr.ip().to_canonical();
+
+            if !self.allowed_cidrs.iter().any(|it| it.contains(ip)) {
+                return Err((StatusCode::FORBIDDEN, "source address not allowed"));
+            }
+        }
+
+        if let Some(token) = self.bearer_token.as_deref() {
+            if !maybe_token.is_some_and(|it| is_token_valid(it, token)) {
+                return Err((StatusCode::UNAUTHORIZED, "invalid token"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn serialize_redacted<S: Serializer>(value: &Option<String>, s: S) -> Result<S::Ok, S::Error> {
+    value.as_ref().map(|_| "<redacted>").serialize(s)
+}
+
+pub(crate) fn get_bearer_token(req: &Request<Body>) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.strip_prefix("Bearer "))
+}
+
+fn is_token_valid(given: &str, token: &str) -> bool {
+    // NOTE: Compare in constant time so that the token cannot be guessed
+    // byte by byte from the response latency.
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod test {
+    use hyper_v014::{Body, Request};
+
+    use super::{get_bearer_token, is_token_valid, ConnInfo, InternalApiAuth};
+
+    fn is_authorized(req: &Request<Body>, token: &str) -> bool {
+        get_bearer_token(req).is_some_and(|it| is_token_valid(it, token))
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        let req = |value: &str| {
+            Request::builder()
+                .header("authorization", value)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        assert!(is_authorized(&req("Bearer secret"), "secret"));
+        assert!(!is_authorized(&req("Bearer secre"), "secret"));
+        assert!(!is_authorized(&req("Bearer secreT"), "secret"));
+        assert!(!is_authorized(&req("secret"), "secret"));
+        assert!(!is_authorized(
+            &Request::builder().body(Body::empty()).unwrap(),
+            "secret"
+        ));
+    }
+
+    #[test]
+    fn test_internal_api_auth_combines_methods() {
+        let auth = InternalApiAuth {
+            bearer_token: Some("secret".to_string()),
+            allowed_cidrs: vec!["10.0.0.0/8".parse().unwrap()],
+            require_client_cert: false,
+        };
+
+        let req = |addr: &str| {
+            let mut req = Request::builder()
+                .header("authorization", "Bearer secret")
+                .body(Body::empty())
+                .unwrap();
+
+            req.extensions_mut().insert(ConnInfo {
+                remote_addr: Some(addr.parse().unwrap()),
+                ..Default::default()
+            });
+
+            req
+        };
+
+        assert!(auth.check(&req("10.1.2.3:1234")).is_ok());
+        assert!(auth.check(&req("[::ffff:10.1.2.3]:1234")).is_ok());
+        assert!(auth.check(&req("192.168.0.1:1234")).is_err());
+        assert!(auth
+            .check(&Request::builder().body(Body::empty()).unwrap())
+            .is_err());
+    }
+}
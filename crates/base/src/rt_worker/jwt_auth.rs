@@ -0,0 +1,93 @@
+//! Verification of the bearer tokens of the requests to the functions that
+//! require a JWT (see `verifyJwt` of the function manifest).
+
+use std::fmt;
+
+use hyper_v014::{Body, Request};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::de::IgnoredAny;
+
+use super::internal_auth::get_bearer_token;
+
+/// Verifies that a JWT is signed with the secret of the runtime (HS256) and
+/// has not expired.
+#[derive(Clone)]
+pub struct JwtVerifier {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl fmt::Debug for JwtVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // NOTE: The key must not end up in the logs.
+        f.debug_struct("JwtVerifier").finish_non_exhaustive()
+    }
+}
+
+impl JwtVerifier {
+    pub fn from_secret(secret: &[u8]) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+
+        // NOTE: The audience of a token is up to the function to check; the
+        // runtime only vouches for its signature and its expiry.
+        validation.validate_aud = false;
+
+        Self {
+            key: DecodingKey::from_secret(secret),
+            validation,
+        }
+    }
+
+    pub fn verify_token(&self, token: &str) -> bool {
+        jsonwebtoken::decode::<IgnoredAny>(token.trim(), &self.key, &self.validation).is_ok()
+    }
+
+    /// Returns `true` if the request carries a bearer token that passes
+    /// [`Self::verify_token`].
+    pub fn verify(&self, req: &Request<Body>) -> bool {
+        get_bearer_token(req).is_some_and(|it| self.verify_token(it))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use deno_core::serde_json::json;
+    use hyper_v014::{Body, Request};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    use super::JwtVerifier;
+
+    fn token(secret: &[u8], exp_offset_sec: i64) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        encode(
+            &Header::default(),
+            &json!({ "sub": "user", "aud": "authenticated", "exp": now + exp_offset_sec }),
+            &EncodingKey::from_secret(secret),
+        )
+        .unwrap()
+    }
+
+    fn request(token: &str) -> Request<Body> {
+        Request::builder()
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_verify_jwt() {
+        let verifier = JwtVerifier::from_secret(b"secret");
+
+        assert!(verifier.verify(&request(&token(b"secret", 60))));
+        assert!(!verifier.verify(&request(&token(b"other", 60))));
+        assert!(!verifier.verify(&request(&token(b"secret", -3600))));
+        assert!(!verifier.verify(&request("a.b.c")));
+        assert!(!verifier.verify(&Request::new(Body::empty())));
+    }
+}
@@ -0,0 +1,162 @@
+//! Shedding of the requests that would be handed to a user worker close to
+//! its limits, so that they are answered with 503 and `Retry-After` right
+//! away instead of being started only to be killed mid-flight.
+//!
+//! A request is shed if the worker it was handed to has used a given share of
+//! its memory limit or of its CPU time soft limit, or if too many requests are
+//! already waiting for a worker of the service.
+
+use std::sync::atomic::Ordering;
+
+use sb_workers::context::{WorkerLimits, WorkerPressure};
+use sb_workers::errors::WorkerError;
+
+use crate::server::ServerFlags;
+use crate::utils::units::mib_to_bytes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShedReason {
+    Memory,
+    CpuTime,
+    QueueDepth,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadSheddingPolicy {
+    /// Share of the memory limit, in percent, past which a worker is shed.
+    pub memory_threshold_pct: Option<u8>,
+    /// Share of the CPU time soft limit, in percent, past which a worker is
+    /// shed.
+    pub cpu_time_threshold_pct: Option<u8>,
+    /// Number of requests waiting for a worker of a service past which the
+    /// requests to the service are shed.
+    pub max_queue_depth: Option<usize>,
+    pub retry_after_sec: u64,
+}
+
+impl LoadSheddingPolicy {
+    /// Returns the policy of the flags, if any threshold is set.
+    pub fn from_flags(flags: &ServerFlags) -> Option<Self> {
+        let policy = Self {
+            memory_threshold_pct: flags.shed_memory_threshold_pct,
+            cpu_time_threshold_pct: flags.shed_cpu_time_threshold_pct,
+            max_queue_depth: flags.shed_queue_depth,
+            retry_after_sec: flags.shed_retry_after_sec.unwrap_or(1),
+        };
+
+        (policy.memory_threshold_pct.is_some()
+            || policy.cpu_time_threshold_pct.is_some()
+            || policy.max_queue_depth.is_some())
+        .then_some(policy)
+    }
+
+    /// Returns why a request handed to a worker under the given pressure must
+    /// be shed, if it must.
+    pub fn check_worker(
+        &self,
+        pressure: &WorkerPressure,
+        limits: &WorkerLimits,
+    ) -> Option<ShedReason> {
+        let is_past = |used: u64, limit: u64, threshold_pct: Option<u8>| {
+            threshold_pct.is_some_and(|pct| {
+                limit > 0 && used.saturating_mul(100) >= limit.saturating_mul(u64::from(pct))
+            })
+        };
+
+        if is_past(
+            pressure.memory_used.load(Ordering::Acquire) as u64,
+            mib_to_bytes(limits.memory_limit_mb),
+            self.memory_threshold_pct,
+        ) {
+            return Some(ShedReason::Memory);
+        }
+
+        if is_past(
+            pressure.cpu_time_used_ms.load(Ordering::Acquire),
+            limits.cpu_time_soft_limit_ms,
+            self.cpu_time_threshold_pct,
+        ) {
+            return Some(ShedReason::CpuTime);
+        }
+
+        None
+    }
+
+    /// Returns whether a request must be shed rather than wait behind the
+    /// given number of requests for a worker.
+    pub fn check_queue(&self, queued: usize) -> Option<ShedReason> {
+        self.max_queue_depth
+            .is_some_and(|it| queued >= it)
+            .then_some(ShedReason::QueueDepth)
+    }
+
+    pub fn error(&self) -> WorkerError {
+        WorkerError::Shed {
+            retry_after_sec: self.retry_after_sec,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::Ordering;
+
+    use sb_workers::context::{WorkerLimits, WorkerPressure};
+
+    use crate::utils::units::mib_to_bytes;
+
+    use super::{LoadSheddingPolicy, ShedReason};
+
+    #[test]
+    fn test_load_shedding_policy() {
+        let policy = LoadSheddingPolicy {
+            memory_threshold_pct: Some(90),
+            cpu_time_threshold_pct: Some(80),
+            max_queue_depth: Some(4),
+            retry_after_sec: 2,
+        };
+
+        let limits = WorkerLimits {
+            memory_limit_mb: 100,
+            cpu_time_soft_limit_ms: 50,
+            ..Default::default()
+        };
+
+        let pressure = WorkerPressure::default();
+
+        assert_eq!(policy.check_worker(&pressure, &limits), None);
+
+        pressure
+            .memory_used
+            .store(mib_to_bytes(89) as usize, Ordering::Release);
+        pressure.cpu_time_used_ms.store(39, Ordering::Release);
+
+        assert_eq!(policy.check_worker(&pressure, &limits), None);
+
+        pressure.cpu_time_used_ms.store(40, Ordering::Release);
+
+        assert_eq!(
+            policy.check_worker(&pressure, &limits),
+            Some(ShedReason::CpuTime)
+        );
+
+        pressure
+            .memory_used
+            .store(mib_to_bytes(90) as usize, Ordering::Release);
+
+        assert_eq!(
+            policy.check_worker(&pressure, &limits),
+            Some(ShedReason::Memory)
+        );
+
+        // NOTE: Workers without a limit are never shed for it.
+        assert_eq!(
+            policy.check_worker(&pressure, &WorkerLimits::default()),
+            None
+        );
+
+        assert_eq!(policy.check_queue(3), None);
+        assert_eq!(policy.check_queue(4), Some(ShedReason::QueueDepth));
+        assert_eq!(LoadSheddingPolicy::default().check_queue(100), None);
+    }
+}
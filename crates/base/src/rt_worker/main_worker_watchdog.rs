@@ -0,0 +1,114 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use log::error;
+use sb_workers::context::{MainWorkerLiveness, WorkerContextInitOpts, WorkerRequestMsg};
+use sb_workers::errors::WorkerError;
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+use crate::inspector_server::Inspector;
+
+use super::worker_ctx::{create_worker, TerminationToken};
+
+type MainWorkerIncarnation = (
+    mpsc::UnboundedSender<WorkerRequestMsg>,
+    MainWorkerLiveness,
+    TerminationToken,
+);
+
+fn boot_main_worker<F>(
+    build_opts: &F,
+    termination_token: &TerminationToken,
+    inspector: Option<Inspector>,
+) -> impl Future<Output = Result<MainWorkerIncarnation, Error>>
+where
+    F: Fn(MainWorkerLiveness) -> Result<WorkerContextInitOpts, Error>,
+{
+    let liveness = MainWorkerLiveness::default();
+    let token = termination_token.child_token();
+    let maybe_opts = build_opts(liveness.clone());
+
+    async move {
+        let ctx = create_worker((maybe_opts?, token.clone()), inspector, None)
+            .await
+            .map_err(|err| anyhow!("main worker boot error: {}", err))?;
+
+        Ok((ctx.msg_tx, liveness, token))
+    }
+}
+
+/// Boots the main worker and keeps probing its event loop. If the event loop
+/// does not turn within `unresponsive_timeout`, the worker is terminated and
+/// replaced by a fresh one.
+///
+/// Requests are routed through the returned sender so that they always reach
+/// the current worker.
+pub async fn create_watched_main_worker<F>(
+    build_opts: F,
+    termination_token: Option<TerminationToken>,
+    inspector: Option<Inspector>,
+    unresponsive_timeout: Duration,
+) -> Result<mpsc::UnboundedSender<WorkerRequestMsg>, Error>
+where
+    F: Fn(MainWorkerLiveness) -> Result<WorkerContextInitOpts, Error> + Send + 'static,
+{
+    let termination_token = termination_token.unwrap_or_default();
+    let (mut worker_req_tx, mut liveness, mut token) =
+        boot_main_worker(&build_opts, &termination_token, inspector).await?;
+
+    let (req_tx, mut req_rx) = mpsc::unbounded_channel::<WorkerRequestMsg>();
+
+    drop(tokio::spawn(async move {
+        let mut probe_interval = tokio::time::interval(unresponsive_timeout);
+
+        probe_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = termination_token.inbound.cancelled() => {
+                    break;
+                }
+
+                msg = req_rx.recv() => {
+                    let Some(msg) = msg else {
+                        break;
+                    };
+
+                    // NOTE: If the worker is gone, the message is dropped
+                    // along with its response sender, so the caller sees the
+                    // request fail.
+                    let _ = worker_req_tx.send(msg);
+                }
+
+                _ = probe_interval.tick() => {
+                    if liveness.is_alive() {
+                        liveness.probe();
+                        continue;
+                    }
+
+                    error!(
+                        "{}: no event loop turn within {}ms, restarting",
+                        WorkerError::MainWorkerUnresponsive,
+                        unresponsive_timeout.as_millis()
+                    );
+
+                    token.cancel();
+
+                    // NOTE: The inspector stays with the first worker, since
+                    // it cannot be registered twice.
+                    match boot_main_worker(&build_opts, &termination_token, None).await {
+                        Ok(it) => (worker_req_tx, liveness, token) = it,
+                        Err(err) => error!("failed to restart the main worker: {err:#}"),
+                    }
+                }
+            }
+        }
+
+        token.cancel_and_wait().await;
+        termination_token.outbound.cancel();
+    }));
+
+    Ok(req_tx)
+}
@@ -0,0 +1,219 @@
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use deno_core::serde_json;
+use event_worker::events::BootWarning;
+use sb_core::cert::OutboundTlsOptions;
+use sb_workers::context::{
+    AutoscalePolicy, MirrorPolicy, WorkerContextInitOpts, WorkerRuntimeOpts,
+};
+use sb_workers::header_policy::HeaderPolicy;
+use sb_workers::rewrite_policy::RewritePolicy;
+use serde::Deserialize;
+
+static FUNCTION_MANIFEST_FILE_NAME: &str = "function.json";
+static DENO_CONFIG_FILE_NAME: &str = "deno.json";
+static DENO_CONFIG_FUNCTION_KEY: &str = "function";
+
+/// Per-function configuration that lives next to the code of a service.
+///
+/// It is read from `function.json` in the service directory, or from the
+/// `function` key of `deno.json` if the former does not exist. Values declared
+/// in the manifest take precedence over the options given by the caller.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FunctionManifest {
+    pub memory_limit_mb: Option<u64>,
+    pub low_memory_multiplier: Option<u64>,
+    pub heap_initial_mb: Option<u64>,
+    pub heap_max_mb: Option<u64>,
+    pub low_memory_notification_percent: Option<u64>,
+    pub worker_timeout_ms: Option<u64>,
+    pub cpu_time_soft_limit_ms: Option<u64>,
+    pub cpu_time_hard_limit_ms: Option<u64>,
+    pub env_allowlist: Option<Vec<String>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_path_prefixes: Option<Vec<String>>,
+    pub outbound_tls: Option<OutboundTlsOptions>,
+    #[serde(default, alias = "verify_jwt")]
+    pub verify_jwt: bool,
+    /// Rewrites the response headers of the service (see [`HeaderPolicy`]).
+    pub headers: Option<HeaderPolicy>,
+    /// Rewrites the requests to the service (see [`RewritePolicy`]).
+    pub rewrite: Option<RewritePolicy>,
+    /// Mirrors requests to a shadow worker (see [`MirrorPolicy`]).
+    pub mirror: Option<MirrorPolicy>,
+    /// Scales the number of workers of the service (see [`AutoscalePolicy`]).
+    pub autoscale: Option<AutoscalePolicy>,
+}
+
+impl FunctionManifest {
+    pub async fn load<P>(service_path: P) -> Result<Option<Self>, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let service_path = service_path.as_ref();
+        let manifest_path = service_path.join(FUNCTION_MANIFEST_FILE_NAME);
+
+        if manifest_path.is_file() {
+            let data = tokio::fs::read(&manifest_path).await?;
+
+            return Self::from_slice(&data)
+                .with_context(|| format!("invalid manifest: {}", manifest_path.display()))
+                .map(Some);
+        }
+
+        let deno_config_path = service_path.join(DENO_CONFIG_FILE_NAME);
+
+        if deno_config_path.is_file() {
+            let data = tokio::fs::read(&deno_config_path).await?;
+            let mut config = serde_json::from_slice::<serde_json::Value>(&data)
+                .with_context(|| format!("invalid config: {}", deno_config_path.display()))?;
+
+            let Some(value) = config
+                .as_object_mut()
+                .and_then(|it| it.remove(DENO_CONFIG_FUNCTION_KEY))
+            else {
+                return Ok(None);
+            };
+
+            return serde_json::from_value(value)
+                .with_context(|| format!("invalid manifest: {}", deno_config_path.display()))
+                .map(Some);
+        }
+
+        Ok(None)
+    }
+
+    pub fn from_slice(data: &[u8]) -> Result<Self, Error> {
+        Ok(serde_json::from_slice(data)?)
+    }
+
+    /// Merges the manifest into the options of a user worker.
+    pub fn apply(&self, opts: &mut WorkerContextInitOpts) {
+        if let WorkerRuntimeOpts::UserWorker(conf) = &mut opts.conf {
+            macro_rules! merge {
+                ($($field:ident),*) => {
+                    $(
+                        if let Some(value) = self.$field {
+                            conf.$field = value;
+                        }
+                    )*
+                };
+            }
+
+            merge!(
+                memory_limit_mb,
+                low_memory_multiplier,
+                heap_initial_mb,
+                heap_max_mb,
+                low_memory_notification_percent,
+                worker_timeout_ms,
+                cpu_time_soft_limit_ms,
+                cpu_time_hard_limit_ms
+            );
+
+            if self.allowed_methods.is_some() {
+                conf.allowed_methods.clone_from(&self.allowed_methods);
+            }
+            if self.allowed_path_prefixes.is_some() {
+                conf.allowed_path_prefixes
+                    .clone_from(&self.allowed_path_prefixes);
+            }
+            if self.outbound_tls.is_some() {
+                conf.outbound_tls.clone_from(&self.outbound_tls);
+            }
+        }
+
+        if let Some(allowlist) = self.env_allowlist.as_ref() {
+            let mut dropped = opts
+                .env_vars
+                .keys()
+                .filter(|key| !allowlist.contains(key))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            dropped.sort();
+            opts.env_vars.retain(|key, _| allowlist.contains(key));
+
+            if let WorkerRuntimeOpts::UserWorker(conf) = &mut opts.conf {
+                conf.boot_warnings.extend(dropped.into_iter().map(|key| {
+                    BootWarning::DegradedPermission {
+                        permission: "env".to_string(),
+                        reason: format!("{} is not in the env allowlist of the function", key),
+                    }
+                }));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use event_worker::events::BootWarning;
+    use sb_workers::context::{UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts};
+
+    use super::FunctionManifest;
+
+    #[test]
+    fn test_function_manifest_apply() {
+        let manifest = FunctionManifest::from_slice(
+            br#"{
+                "memoryLimitMb": 256,
+                "cpuTimeHardLimitMs": 200,
+                "envAllowlist": ["FOO"],
+                "verify_jwt": true
+            }"#,
+        )
+        .unwrap();
+
+        let mut opts = WorkerContextInitOpts {
+            service_path: PathBuf::from("./test_cases/main"),
+            no_module_cache: false,
+            import_map_path: None,
+            env_vars: HashMap::from([
+                ("FOO".to_string(), "1".to_string()),
+                ("BAR".to_string(), "2".to_string()),
+            ]),
+            timing: None,
+            conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts::default()),
+            maybe_eszip: None,
+            maybe_module_code: None,
+            maybe_module_map: None,
+            maybe_entrypoint: None,
+            maybe_decorator: None,
+            static_patterns: vec![],
+            maybe_jsx_import_source_config: None,
+            locale: Default::default(),
+        };
+
+        manifest.apply(&mut opts);
+
+        let conf = opts.conf.as_user_worker().unwrap();
+
+        assert!(manifest.verify_jwt);
+        assert_eq!(conf.memory_limit_mb, 256);
+        assert_eq!(conf.cpu_time_hard_limit_ms, 200);
+        assert_eq!(
+            conf.cpu_time_soft_limit_ms,
+            UserWorkerRuntimeOpts::default().cpu_time_soft_limit_ms
+        );
+        assert_eq!(opts.env_vars.len(), 1);
+        assert!(opts.env_vars.contains_key("FOO"));
+        assert_eq!(
+            conf.boot_warnings,
+            vec![BootWarning::DegradedPermission {
+                permission: "env".to_string(),
+                reason: "BAR is not in the env allowlist of the function".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_function_manifest_rejects_unknown_fields() {
+        assert!(FunctionManifest::from_slice(br#"{ "memoryLimit": 1 }"#).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use event_worker::events::{
+    EventMetadata, RequestMirroredEvent, WorkerEventWithMetadata, WorkerEvents,
+};
+use http_v02::{Method, Request, StatusCode};
+use hyper_v014::body::HttpBody;
+use hyper_v014::Body;
+use sb_workers::context::{CreateUserWorkerResult, UserWorkerMsgs, UserWorkerProfile};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use super::pool_state::PersistedService;
+use super::worker_pool::{replayable_copy, WorkerPool};
+use crate::utils::send_event_if_event_worker_available;
+
+/// Marks a request sent to a shadow worker, so that it is not mirrored again.
+#[derive(Clone, Copy)]
+struct MirroredRequest;
+
+/// Spreads the mirrored requests of a service evenly, so that exactly the
+/// configured share of them is mirrored.
+#[derive(Debug, Default)]
+pub struct MirrorSampler(AtomicU64);
+
+impl MirrorSampler {
+    fn sample(&self, percentage: u8) -> bool {
+        let percentage = u64::from(percentage.min(100));
+        let seq = self.0.fetch_add(1, Ordering::Relaxed);
+
+        (seq + 1) * percentage / 100 != seq * percentage / 100
+    }
+}
+
+/// Returns the copy of a request to send to a shadow worker, or `None` if it is
+/// not a `GET` request that can be replayed or was mirrored already.
+///
+/// NOTE: Even a `HEAD` request is left out, as the shadow worker may handle it
+/// like a `GET` one that has side effects.
+fn mirrorable_copy(req: &Request<Body>) -> Option<Request<Body>> {
+    if *req.method() != Method::GET || req.extensions().get::<MirroredRequest>().is_some() {
+        return None;
+    }
+
+    let mut copy = replayable_copy(req)?;
+
+    copy.extensions_mut().insert(MirroredRequest);
+
+    Some(copy)
+}
+
+/// A copy of a request to be sent to the shadow worker of a service.
+pub(crate) struct RequestMirror {
+    service: PersistedService,
+    req: Request<Body>,
+    worker_pool_msgs_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    events_msg_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    metadata: EventMetadata,
+}
+
+impl RequestMirror {
+    /// Returns `None` if the request is not sampled or is not a `GET` request
+    /// that can be replayed.
+    pub(crate) fn new(
+        pool: &WorkerPool,
+        key: &Uuid,
+        profile: &UserWorkerProfile,
+        req: &Request<Body>,
+    ) -> Option<Self> {
+        let policy = profile.mirror_policy.as_ref()?;
+        let mirror_req = mirrorable_copy(req)?;
+
+        if !pool
+            .mirror_samplers
+            .get(&profile.identity)?
+            .sample(policy.percentage)
+        {
+            return None;
+        }
+
+        let mut service = pool.persisted_services.get(&profile.identity)?.clone();
+        let service_path = policy
+            .service_path
+            .clone()
+            .unwrap_or_else(|| service.service_path.clone());
+
+        // NOTE: A shadow with the identity of the primary worker would be the
+        // primary worker itself.
+        if service_path == service.service_path && policy.revision == service.worker_revision {
+            return None;
+        }
+
+        service.service_path = service_path;
+        service.worker_revision.clone_from(&policy.revision);
+        service.key = None;
+
+        Some(Self {
+            service,
+            req: mirror_req,
+            worker_pool_msgs_tx: pool.worker_pool_msgs_tx.clone(),
+            events_msg_tx: pool.worker_event_sender.clone(),
+            metadata: EventMetadata {
+                service_path: Some(profile.service_path.clone()),
+                execution_id: Some(*key),
+                worker_id: Some(profile.identity.to_string()),
+            },
+        })
+    }
+
+    /// Sends the request to the shadow worker in the background, so that it
+    /// runs alongside the one sent to the primary worker.
+    pub(crate) fn start(self) -> MirrorHandle {
+        let method = self.req.method().to_string();
+        let path = self.req.uri().path().to_string();
+        let shadow_service_path = self.service.service_path.clone();
+        let events_msg_tx = self.events_msg_tx.clone();
+        let metadata = self.metadata.clone();
+
+        MirrorHandle {
+            method,
+            path,
+            shadow_service_path,
+            events_msg_tx,
+            metadata,
+            join: tokio::spawn(async move {
+                let mut shadow_key = None;
+                let result = self.send(&mut shadow_key).await;
+
+                (shadow_key, result)
+            }),
+        }
+    }
+
+    async fn send(self, shadow_key: &mut Option<Uuid>) -> Result<(StatusCode, Duration), Error> {
+        let (create_tx, create_rx) = oneshot::channel();
+
+        self.worker_pool_msgs_tx
+            .send(UserWorkerMsgs::Create(self.service.into_opts()?, create_tx))?;
+
+        let CreateUserWorkerResult { key, .. } = create_rx.await??;
+        let (res_tx, res_rx) = oneshot::channel();
+
+        *shadow_key = Some(key);
+
+        // NOTE: The boot of the shadow worker is left out of the latency, as
+        // the primary worker is usually warm already.
+        let started_at = Instant::now();
+
+        self.worker_pool_msgs_tx
+            .send(UserWorkerMsgs::SendRequest(key, self.req, res_tx, None))?;
+
+        let (res, req_end_tx) = res_rx.await??;
+        let status = res.status();
+        let latency = started_at.elapsed();
+        let mut body = res.into_body();
+
+        // NOTE: The body is drained so that the shadow worker is not
+        // interrupted, but nothing of it is kept.
+        while let Some(chunk) = body.data().await {
+            if chunk.is_err() {
+                break;
+            }
+        }
+
+        let _ = req_end_tx.send(());
+
+        Ok((status, latency))
+    }
+}
+
+type MirrorResult = (Option<Uuid>, Result<(StatusCode, Duration), Error>);
+
+pub(crate) struct MirrorHandle {
+    method: String,
+    path: String,
+    shadow_service_path: String,
+    events_msg_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    metadata: EventMetadata,
+    join: JoinHandle<MirrorResult>,
+}
+
+impl MirrorHandle {
+    /// Reports how the shadow worker diverged from the primary one once it has
+    /// responded, without holding up the response of the primary worker.
+    pub(crate) fn finish(self, primary_status: Option<StatusCode>, primary_latency: Duration) {
+        drop(tokio::spawn(async move {
+            let (shadow_key, result) = match self.join.await {
+                Ok(it) => it,
+                Err(err) => (None, Err(err.into())),
+            };
+
+            let (shadow_status, shadow_latency, shadow_error) = match result {
+                Ok((status, latency)) => (Some(status.as_u16()), latency, None),
+                Err(err) => (None, Duration::ZERO, Some(format!("{err:#}"))),
+            };
+
+            let primary_status = primary_status.map(|it| it.as_u16());
+            let as_ms = |it: Duration| it.as_millis().try_into().unwrap_or(u64::MAX);
+
+            send_event_if_event_worker_available(
+                self.events_msg_tx.as_ref(),
+                WorkerEvents::RequestMirrored(RequestMirroredEvent {
+                    method: self.method,
+                    path: self.path,
+                    shadow_service_path: self.shadow_service_path,
+                    shadow_key,
+                    primary_status,
+                    shadow_status,
+                    primary_latency_ms: as_ms(primary_latency),
+                    shadow_latency_ms: as_ms(shadow_latency),
+                    status_diverged: primary_status != shadow_status,
+                    shadow_error,
+                }),
+                self.metadata,
+            );
+        }));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http_v02::{Method, Request};
+    use hyper_v014::Body;
+
+    use super::{mirrorable_copy, MirrorSampler};
+
+    #[test]
+    fn test_mirrorable_copy() {
+        let request = |method: Method| {
+            Request::builder()
+                .method(method)
+                .uri("/hello")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let copy = mirrorable_copy(&request(Method::GET)).unwrap();
+
+        assert_eq!(copy.uri(), "/hello");
+        assert!(mirrorable_copy(&copy).is_none());
+        assert!(mirrorable_copy(&request(Method::HEAD)).is_none());
+        assert!(mirrorable_copy(&request(Method::POST)).is_none());
+    }
+
+    #[test]
+    fn test_mirror_sampler() {
+        let sampler = MirrorSampler::default();
+
+        assert_eq!((0..200).filter(|_| sampler.sample(25)).count(), 50);
+        assert_eq!((0..10).filter(|_| sampler.sample(0)).count(), 0);
+        assert_eq!((0..10).filter(|_| sampler.sample(100)).count(), 10);
+    }
+}
@@ -1,6 +1,41 @@
+pub mod autoscaler;
+pub mod boot_preflight;
+pub mod bundle_signature;
+pub mod client_ip;
+pub mod coalesce;
+#[cfg(feature = "grpc")]
+pub mod control_plane;
+pub mod create_dedup;
+pub mod deadline;
+pub mod health_check;
 pub mod implementation;
+pub mod internal_auth;
+pub mod jwt_auth;
+pub mod load_shedding;
+pub mod main_worker_watchdog;
+pub mod manifest;
+pub mod mirror;
+pub mod path_normalization;
+pub mod pool_state;
+pub mod priority_class;
+pub mod recording;
+pub mod request_filter;
+pub mod request_journal;
+pub mod request_log;
+pub mod request_meta;
+pub mod router;
+pub mod runtime_stats;
+pub mod service_bundle;
+pub mod service_stats;
+pub mod startup_gate;
+pub mod status_api;
 pub mod supervisor;
+pub mod task_dispatcher;
+pub mod tenant_scheduler;
+pub mod trace_buffer;
+pub mod traces_api;
 pub mod utils;
 pub mod worker;
 pub mod worker_ctx;
 pub mod worker_pool;
+pub mod workers_api;
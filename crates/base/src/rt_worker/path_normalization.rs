@@ -0,0 +1,299 @@
+//! Normalization of the head of the requests before they are routed, so that
+//! the router of the runtime and the routers of the workers see the same path.
+//!
+//! Percent-encoded unreserved characters are decoded, the remaining escapes are
+//! uppercased, duplicate slashes are collapsed and dot segments are removed.
+//! The lenient mode repairs what is ambiguous (backslashes, stray `%`), while
+//! the strict one rejects it along with encoded separators.
+
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use http_v02::header::HOST;
+use http_v02::uri::PathAndQuery;
+use http_v02::{HeaderValue, Request, Uri};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PathNormalization {
+    /// The requests are passed on as they are.
+    #[default]
+    Off,
+    Lenient,
+    Strict,
+}
+
+impl FromStr for PathNormalization {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "off" => Self::Off,
+            "lenient" => Self::Lenient,
+            "strict" => Self::Strict,
+            _ => bail!("unknown path normalization mode: {s}"),
+        })
+    }
+}
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+fn decode_hex(bytes: &[u8]) -> Option<u8> {
+    match bytes {
+        [hi, lo] if hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit() => {
+            u8::from_str_radix(std::str::from_utf8(bytes).ok()?, 16).ok()
+        }
+
+        _ => None,
+    }
+}
+
+/// Returns the normalized form of a request path, or the reason it is
+/// rejected.
+pub fn normalize_path(path: &str, mode: PathNormalization) -> Result<Cow<'_, str>, String> {
+    // NOTE: Only origin-form paths are normalized, which leaves `*` alone.
+    if mode == PathNormalization::Off || !path.starts_with('/') {
+        return Ok(Cow::Borrowed(path));
+    }
+
+    let strict = mode == PathNormalization::Strict;
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match bytes.get(i + 1..i + 3).and_then(decode_hex) {
+                Some(0) => return Err("request path contains an encoded NUL byte".to_string()),
+                Some(b) if is_unreserved(b) => {
+                    decoded.push(b);
+                    i += 3;
+                }
+
+                Some(b'/' | b'\\') if strict => {
+                    return Err("request path contains an encoded separator".to_string());
+                }
+
+                Some(b) => {
+                    decoded.extend_from_slice(format!("%{b:02X}").as_bytes());
+                    i += 3;
+                }
+
+                None if strict => {
+                    return Err("request path contains an invalid percent-encoding".to_string());
+                }
+
+                None => {
+                    decoded.extend_from_slice(b"%25");
+                    i += 1;
+                }
+            },
+
+            b'\\' if strict => return Err("request path contains a backslash".to_string()),
+            b'\\' => {
+                decoded.push(b'/');
+                i += 1;
+            }
+
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    let decoded = String::from_utf8(decoded)
+        .map_err(|_| "request path is not valid UTF-8 once decoded".to_string())?;
+    let mut segments = Vec::new();
+    let mut trailing_slash = false;
+
+    for segment in decoded.split('/').skip(1) {
+        trailing_slash = true;
+
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() && strict {
+                    return Err("request path climbs above the root".to_string());
+                }
+            }
+
+            segment => {
+                segments.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+
+    Ok(if normalized == path {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(normalized)
+    })
+}
+
+/// Returns `true` if the path is the prefix or lies under it, comparing whole
+/// segments of the normalized path: `/api` covers `/api` and `/api/users` but
+/// neither `/apix` nor `/api/../admin`.
+///
+/// NOTE: This holds whatever the normalization mode of the server is, since
+/// the worker may well decode the path itself.
+pub fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    let (Ok(path), Ok(prefix)) = (
+        normalize_path(path, PathNormalization::Lenient),
+        normalize_path(prefix, PathNormalization::Lenient),
+    ) else {
+        return false;
+    };
+
+    let prefix = prefix.trim_end_matches('/');
+
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Normalizes the path and the `Host` header of a request in place, or returns
+/// the reason it is rejected.
+pub fn normalize_request<B>(req: &mut Request<B>, mode: PathNormalization) -> Result<(), String> {
+    if mode == PathNormalization::Off {
+        return Ok(());
+    }
+
+    let strict = mode == PathNormalization::Strict;
+
+    let maybe_path = match normalize_path(req.uri().path(), mode)? {
+        Cow::Owned(it) => Some(it),
+        Cow::Borrowed(_) => None,
+    };
+
+    if let Some(path) = maybe_path {
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        };
+
+        let mut parts = req.uri().clone().into_parts();
+
+        parts.path_and_query = Some(
+            PathAndQuery::try_from(path_and_query)
+                .map_err(|_| "request path can't be normalized".to_string())?,
+        );
+
+        *req.uri_mut() =
+            Uri::from_parts(parts).map_err(|_| "request path can't be normalized".to_string())?;
+    }
+
+    let hosts = req
+        .headers()
+        .get_all(HOST)
+        .iter()
+        .map(|it| it.to_str().map(str::to_ascii_lowercase))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "request carries a malformed Host header".to_string())?;
+
+    if strict && hosts.len() > 1 {
+        return Err("request carries more than one Host header".to_string());
+    }
+
+    // NOTE: The authority of a request in absolute form takes precedence over
+    // its `Host` header, as in RFC 9112.
+    let maybe_host = match (req.uri().authority(), hosts.first()) {
+        (Some(authority), Some(host))
+            if strict && !authority.as_str().eq_ignore_ascii_case(host) =>
+        {
+            return Err("request target and Host header disagree".to_string());
+        }
+
+        (Some(authority), _) => Some(authority.as_str().to_ascii_lowercase()),
+        (None, host) => host.cloned(),
+    };
+
+    if let Some(host) = maybe_host {
+        let value = HeaderValue::from_str(&host)
+            .map_err(|_| "request carries a malformed Host header".to_string())?;
+
+        req.headers_mut().insert(HOST, value);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{normalize_path, normalize_request, path_has_prefix, PathNormalization};
+
+    #[test]
+    fn test_path_has_prefix() {
+        assert!(path_has_prefix("/api", "/api"));
+        assert!(path_has_prefix("/api/users", "/api"));
+        assert!(path_has_prefix("/api/users", "/api/"));
+        assert!(path_has_prefix("/anything", "/"));
+        assert!(path_has_prefix("/%61pi/users", "/api"));
+
+        assert!(!path_has_prefix("/apix", "/api"));
+        assert!(!path_has_prefix("/api/../admin", "/api"));
+        assert!(!path_has_prefix("/api/%2e%2e/admin", "/api"));
+        assert!(!path_has_prefix("/api%2Fusers", "/api/users"));
+        assert!(!path_has_prefix("*", "/"));
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let lenient = |it| normalize_path(it, PathNormalization::Lenient);
+        let strict = |it| normalize_path(it, PathNormalization::Strict);
+
+        assert_eq!(lenient("/hello/world").unwrap(), "/hello/world");
+        assert_eq!(lenient("//hello///world/").unwrap(), "/hello/world/");
+        assert_eq!(lenient("/hello/./a/../world").unwrap(), "/hello/world");
+        assert_eq!(lenient("/%2e%2e/%2E./secret").unwrap(), "/secret");
+        assert_eq!(lenient("/%68ello/%2f%3a").unwrap(), "/hello/%2F%3A");
+        assert_eq!(lenient("/hello\\..\\world").unwrap(), "/world");
+        assert_eq!(lenient("/100%").unwrap(), "/100%25");
+        assert_eq!(lenient("/").unwrap(), "/");
+        assert_eq!(lenient("*").unwrap(), "*");
+
+        assert_eq!(strict("//hello/./world").unwrap(), "/hello/world");
+        assert!(strict("/../secret").is_err());
+        assert!(strict("/hello%2fworld").is_err());
+        assert!(strict("/hello\\world").is_err());
+        assert!(strict("/100%").is_err());
+        assert!(lenient("/hello%00").is_err());
+
+        assert_eq!(
+            normalize_path("//a/../b", PathNormalization::Off).unwrap(),
+            "//a/../b"
+        );
+    }
+
+    #[test]
+    fn test_normalize_request() {
+        let mut req = http_v02::Request::builder()
+            .uri("/hello//world?a=../b")
+            .header("host", "Example.COM")
+            .body(())
+            .unwrap();
+
+        normalize_request(&mut req, PathNormalization::Lenient).unwrap();
+
+        assert_eq!(req.uri().to_string(), "/hello/world?a=../b");
+        assert_eq!(req.headers()["host"], "example.com");
+
+        let mut req = http_v02::Request::builder()
+            .uri("http://a.example.com/hello")
+            .header("host", "b.example.com")
+            .body(())
+            .unwrap();
+
+        assert!(normalize_request(&mut req, PathNormalization::Strict).is_err());
+        assert!(normalize_request(&mut req, PathNormalization::Lenient).is_ok());
+        assert_eq!(req.headers()["host"], "a.example.com");
+    }
+}
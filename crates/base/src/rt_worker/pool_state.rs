@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Error};
+use deno_core::serde_json;
+use ring::digest;
+use sb_core::cert::OutboundTlsOptions;
+use sb_core::feature_flags::FeatureFlags;
+use sb_graph::DecoratorType;
+use sb_workers::builder::UserWorkerBuilder;
+use sb_workers::context::{UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerLocale};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+/// A user worker service that was registered to the pool, persisted so that it
+/// can be pre-warmed on the next startup.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PersistedService {
+    pub key: Option<String>,
+    pub service_path: String,
+    pub revision: Option<u64>,
+    pub tenant: Option<String>,
+    /// The revision given by the caller, as opposed to `revision` which is
+    /// derived from the files of the service.
+    pub worker_revision: Option<String>,
+
+    pub no_module_cache: bool,
+    pub import_map_path: Option<String>,
+    /// NOTE: The env vars hold the secrets of the service, so they are only
+    /// kept in memory and never written to the pool state.
+    #[serde(skip)]
+    pub env_vars: HashMap<String, String>,
+    /// Whether the service was created with env vars. Such a service is not
+    /// pre-warmed, since it would boot without them.
+    #[serde(default)]
+    pub has_env_vars: bool,
+    pub decorator: Option<DecoratorType>,
+
+    pub memory_limit_mb: u64,
+    pub low_memory_multiplier: u64,
+    #[serde(default)]
+    pub memory_leak_trend_samples: u64,
+    #[serde(default)]
+    pub recycle_on_memory_leak: bool,
+    #[serde(default)]
+    pub heap_initial_mb: u64,
+    #[serde(default)]
+    pub heap_max_mb: u64,
+    #[serde(default)]
+    pub low_memory_notification_percent: u64,
+    pub worker_timeout_ms: u64,
+    pub cpu_time_soft_limit_ms: u64,
+    pub cpu_time_hard_limit_ms: u64,
+    #[serde(default)]
+    pub cpu_time_cap_ms: u64,
+    #[serde(default)]
+    pub request_cpu_time_cap_ms: u64,
+    #[serde(default)]
+    pub event_loop_lag_warn_ms: u64,
+    #[serde(default)]
+    pub event_loop_lag_limit_ms: u64,
+    #[serde(default)]
+    pub max_resources: u64,
+    #[serde(default = "default_max_websocket_connections")]
+    pub max_websocket_connections: u64,
+    #[serde(default = "default_websocket_idle_timeout_ms")]
+    pub websocket_idle_timeout_ms: u64,
+    #[serde(default)]
+    pub fetch_timeout_ms: u64,
+    #[serde(default)]
+    pub max_concurrent_fetches: u64,
+    #[serde(default)]
+    pub max_emails_per_minute: u64,
+    #[serde(default)]
+    pub clock_granularity_ms: u64,
+    #[serde(default = "default_log_sample_rate")]
+    pub log_sample_rate: f64,
+    #[serde(default)]
+    pub log_rate_limit: u64,
+    pub net_access_disabled: bool,
+    pub allow_net: Option<Vec<String>>,
+    #[serde(default)]
+    pub allow_sockets: Option<Vec<String>>,
+    pub allow_remote_modules: bool,
+    #[serde(default)]
+    pub allow_shared_array_buffer: bool,
+    #[serde(default)]
+    pub allow_wasm_threads: bool,
+    #[serde(default = "default_allow_atomics_wait")]
+    pub allow_atomics_wait: bool,
+    pub custom_module_root: Option<String>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_path_prefixes: Option<Vec<String>>,
+    #[serde(default)]
+    pub outbound_tls: Option<OutboundTlsOptions>,
+    #[serde(default)]
+    pub feature_flags: FeatureFlags,
+    #[serde(default)]
+    pub locale: WorkerLocale,
+}
+
+impl PersistedService {
+    /// Returns `None` if the worker cannot be recreated from disk alone (i.e.
+    /// it was created from an in-memory eszip or module code).
+    pub fn from_opts(opts: &WorkerContextInitOpts) -> Option<Self> {
+        if opts.maybe_eszip.is_some()
+            || opts.maybe_module_code.is_some()
+            || opts.maybe_module_map.is_some()
+        {
+            return None;
+        }
+
+        Self::describe(opts)
+    }
+
+    /// Returns the options of a user worker, whatever its code was given as.
+    pub(crate) fn describe(opts: &WorkerContextInitOpts) -> Option<Self> {
+        let conf = opts.conf.as_user_worker()?;
+
+        Some(Self {
+            key: None,
+            service_path: opts.service_path.to_string_lossy().to_string(),
+            revision: None,
+            tenant: conf.tenant.clone(),
+            worker_revision: conf.revision.clone(),
+            no_module_cache: opts.no_module_cache,
+            import_map_path: opts.import_map_path.clone(),
+            env_vars: opts.env_vars.clone(),
+            has_env_vars: !opts.env_vars.is_empty(),
+            decorator: opts.maybe_decorator,
+            memory_limit_mb: conf.memory_limit_mb,
+            low_memory_multiplier: conf.low_memory_multiplier,
+            memory_leak_trend_samples: conf.memory_leak_trend_samples,
+            recycle_on_memory_leak: conf.recycle_on_memory_leak,
+            heap_initial_mb: conf.heap_initial_mb,
+            heap_max_mb: conf.heap_max_mb,
+            low_memory_notification_percent: conf.low_memory_notification_percent,
+            worker_timeout_ms: conf.worker_timeout_ms,
+            cpu_time_soft_limit_ms: conf.cpu_time_soft_limit_ms,
+            cpu_time_hard_limit_ms: conf.cpu_time_hard_limit_ms,
+            cpu_time_cap_ms: conf.cpu_time_cap_ms,
+            request_cpu_time_cap_ms: conf.request_cpu_time_cap_ms,
+            event_loop_lag_warn_ms: conf.event_loop_lag_warn_ms,
+            event_loop_lag_limit_ms: conf.event_loop_lag_limit_ms,
+            max_resources: conf.max_resources,
+            max_websocket_connections: conf.max_websocket_connections,
+            websocket_idle_timeout_ms: conf.websocket_idle_timeout_ms,
+            fetch_timeout_ms: conf.fetch_timeout_ms,
+            max_concurrent_fetches: conf.max_concurrent_fetches,
+            max_emails_per_minute: conf.max_emails_per_minute,
+            clock_granularity_ms: conf.clock_granularity_ms,
+            log_sample_rate: conf.log_sample_rate,
+            log_rate_limit: conf.log_rate_limit,
+            net_access_disabled: conf.net_access_disabled,
+            allow_net: conf.allow_net.clone(),
+            allow_sockets: conf.allow_sockets.clone(),
+            allow_remote_modules: conf.allow_remote_modules,
+            allow_shared_array_buffer: conf.allow_shared_array_buffer,
+            allow_wasm_threads: conf.allow_wasm_threads,
+            allow_atomics_wait: conf.allow_atomics_wait,
+            custom_module_root: conf.custom_module_root.clone(),
+            allowed_methods: conf.allowed_methods.clone(),
+            allowed_path_prefixes: conf.allowed_path_prefixes.clone(),
+            outbound_tls: conf.outbound_tls.clone(),
+            feature_flags: conf.feature_flags.clone(),
+            locale: opts.locale.clone(),
+        })
+    }
+
+    /// Returns the names of the options that differ from the ones of `other`,
+    /// as they are named in the pool state.
+    pub fn changed_options(&self, other: &Self) -> Vec<String> {
+        let (Ok(serde_json::Value::Object(this)), Ok(serde_json::Value::Object(other))) =
+            (serde_json::to_value(self), serde_json::to_value(other))
+        else {
+            return vec![];
+        };
+
+        let mut changed = this
+            .into_iter()
+            .filter(|(name, value)| other.get(name) != Some(value))
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+
+        if self.env_vars != other.env_vars {
+            changed.push("envVars".to_string());
+            changed.sort();
+        }
+
+        changed
+    }
+
+    pub fn into_opts(self) -> Result<WorkerContextInitOpts, Error> {
+        Ok(UserWorkerBuilder::new(self.service_path)
+            .no_module_cache(self.no_module_cache)
+            .import_map_path(self.import_map_path)
+            .env_vars(self.env_vars)
+            .decorator(self.decorator)
+            .locale(self.locale.locale)
+            .timezone(self.locale.timezone)
+            .runtime_opts(UserWorkerRuntimeOpts {
+                tenant: self.tenant,
+                revision: self.worker_revision,
+                memory_limit_mb: self.memory_limit_mb,
+                low_memory_multiplier: self.low_memory_multiplier,
+                memory_leak_trend_samples: self.memory_leak_trend_samples,
+                recycle_on_memory_leak: self.recycle_on_memory_leak,
+                heap_initial_mb: self.heap_initial_mb,
+                heap_max_mb: self.heap_max_mb,
+                low_memory_notification_percent: self.low_memory_notification_percent,
+                worker_timeout_ms: self.worker_timeout_ms,
+                cpu_time_soft_limit_ms: self.cpu_time_soft_limit_ms,
+                cpu_time_hard_limit_ms: self.cpu_time_hard_limit_ms,
+                cpu_time_cap_ms: self.cpu_time_cap_ms,
+                request_cpu_time_cap_ms: self.request_cpu_time_cap_ms,
+                event_loop_lag_warn_ms: self.event_loop_lag_warn_ms,
+                event_loop_lag_limit_ms: self.event_loop_lag_limit_ms,
+                max_resources: self.max_resources,
+                max_websocket_connections: self.max_websocket_connections,
+                websocket_idle_timeout_ms: self.websocket_idle_timeout_ms,
+                fetch_timeout_ms: self.fetch_timeout_ms,
+                max_concurrent_fetches: self.max_concurrent_fetches,
+                max_emails_per_minute: self.max_emails_per_minute,
+                clock_granularity_ms: self.clock_granularity_ms,
+                log_sample_rate: self.log_sample_rate,
+                log_rate_limit: self.log_rate_limit,
+                net_access_disabled: self.net_access_disabled,
+                allow_net: self.allow_net,
+                allow_sockets: self.allow_sockets,
+                allow_remote_modules: self.allow_remote_modules,
+                allow_shared_array_buffer: self.allow_shared_array_buffer,
+                allow_wasm_threads: self.allow_wasm_threads,
+                allow_atomics_wait: self.allow_atomics_wait,
+                custom_module_root: self.custom_module_root,
+                allowed_methods: self.allowed_methods,
+                allowed_path_prefixes: self.allowed_path_prefixes,
+                outbound_tls: self.outbound_tls,
+                feature_flags: self.feature_flags,
+                ..Default::default()
+            })
+            .build()?)
+    }
+}
+
+fn default_max_websocket_connections() -> u64 {
+    UserWorkerRuntimeOpts::default().max_websocket_connections
+}
+
+fn default_websocket_idle_timeout_ms() -> u64 {
+    UserWorkerRuntimeOpts::default().websocket_idle_timeout_ms
+}
+
+fn default_log_sample_rate() -> f64 {
+    UserWorkerRuntimeOpts::default().log_sample_rate
+}
+
+fn default_allow_atomics_wait() -> bool {
+    UserWorkerRuntimeOpts::default().allow_atomics_wait
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolState {
+    pub services: Vec<PersistedService>,
+}
+
+impl PoolState {
+    pub async fn load(path: &Path) -> Result<Option<Self>, Error> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let data = tokio::fs::read(path).await?;
+
+        serde_json::from_slice(&data)
+            .with_context(|| format!("invalid pool state: {}", path.display()))
+            .map(Some)
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<(), Error> {
+        let data = serde_json::to_vec(self)?;
+        let mut options = tokio::fs::OpenOptions::new();
+
+        options.write(true).create(true).truncate(true);
+
+        // NOTE: The state tells which services the runtime hosts and with what
+        // options, so it must not be readable by others.
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(path).await?;
+
+        file.write_all(&data).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// Returns a digest of the whole tree of the service directory: the path, the
+/// size and the modification time of every file in it, at any depth.
+///
+/// NOTE: Hashing the contents would be more accurate but too slow for large
+/// services, and any edit or deploy touches the modification time anyway.
+pub async fn get_service_revision<P>(service_path: P) -> Option<u64>
+where
+    P: Into<PathBuf>,
+{
+    let service_path = service_path.into();
+
+    tokio::task::spawn_blocking(move || {
+        let mut entries = vec![];
+        let mut dirs = vec![service_path.clone()];
+
+        std::fs::metadata(&service_path).ok()?;
+
+        while let Some(dir) = dirs.pop() {
+            let Ok(read_dir) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+
+            for entry in read_dir.filter_map(|it| it.ok()) {
+                let path = entry.path();
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+
+                if metadata.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|it| it.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |it| it.as_nanos());
+                let relative = path
+                    .strip_prefix(&service_path)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .to_string();
+
+                entries.push((relative, metadata.len(), modified));
+            }
+        }
+
+        // NOTE: The order of the entries of a directory is up to the file
+        // system, so they are sorted for the digest to be stable.
+        entries.sort();
+
+        let mut ctx = digest::Context::new(&digest::SHA256);
+
+        for (path, len, modified) in entries {
+            ctx.update(path.as_bytes());
+            ctx.update(&[0]);
+            ctx.update(&len.to_le_bytes());
+            ctx.update(&modified.to_le_bytes());
+        }
+
+        let digest = ctx.finish();
+        let mut revision = [0; 8];
+
+        revision.copy_from_slice(&digest.as_ref()[..8]);
+
+        Some(u64::from_le_bytes(revision))
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use sb_workers::builder::UserWorkerBuilder;
+    use sb_workers::context::UserWorkerRuntimeOpts;
+
+    use deno_core::serde_json;
+
+    use super::{get_service_revision, PersistedService};
+
+    fn describe(memory_limit_mb: u64, env_vars: &[(&str, &str)]) -> PersistedService {
+        let opts = UserWorkerBuilder::new("./hello")
+            .env_vars(
+                env_vars
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            )
+            .runtime_opts(UserWorkerRuntimeOpts {
+                memory_limit_mb,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+
+        PersistedService::describe(&opts).unwrap()
+    }
+
+    #[test]
+    fn test_env_vars_are_not_persisted() {
+        let service = describe(150, &[("SECRET", "hunter2")]);
+        let data = serde_json::to_string(&service).unwrap();
+
+        assert!(!data.contains("hunter2"));
+        assert!(service.has_env_vars);
+
+        let restored = serde_json::from_str::<PersistedService>(&data).unwrap();
+
+        assert!(restored.env_vars.is_empty());
+        assert!(restored.has_env_vars);
+    }
+
+    #[tokio::test]
+    async fn test_service_revision_covers_the_whole_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("lib/utils");
+
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(dir.path().join("index.ts"), "export {}").unwrap();
+        std::fs::write(nested.join("mod.ts"), "export {}").unwrap();
+
+        let revision = get_service_revision(dir.path()).await;
+
+        assert!(revision.is_some());
+        assert_eq!(revision, get_service_revision(dir.path()).await);
+
+        std::fs::write(nested.join("mod.ts"), "export const a = 1;").unwrap();
+
+        assert_ne!(revision, get_service_revision(dir.path()).await);
+        assert_eq!(get_service_revision(dir.path().join("missing")).await, None);
+    }
+
+    #[test]
+    fn test_changed_options() {
+        let service = describe(150, &[("A", "1"), ("B", "2")]);
+
+        assert!(service
+            .changed_options(&describe(150, &[("B", "2"), ("A", "1")]))
+            .is_empty());
+        assert_eq!(
+            service.changed_options(&describe(256, &[("A", "1"), ("B", "3")])),
+            vec!["envVars", "memoryLimitMb"]
+        );
+    }
+}
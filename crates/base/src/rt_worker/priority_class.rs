@@ -0,0 +1,211 @@
+//! Priority classes of the requests dispatched to the user workers, so that
+//! bulk traffic (e.g. the import of webhooks) cannot degrade the latency of
+//! the requests users wait on.
+//!
+//! A request is `batch` if the route rule it matched says so, or else if it
+//! has `x-edge-runtime-priority: batch`, and `interactive` otherwise. Batch
+//! requests wait behind the interactive ones for a slot of the pool (see
+//! [`TenantScheduler`](super::tenant_scheduler::TenantScheduler)), and have
+//! their own concurrency limit, wait timeout and CPU budget.
+
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{bail, Error};
+use hyper_v014::{Body, Request};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::server::ServerFlags;
+
+pub static PRIORITY_HEADER: &str = "x-edge-runtime-priority";
+
+/// The window over which the CPU budget of the batch requests is spent.
+static CPU_BUDGET_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum PriorityClass {
+    #[default]
+    Interactive,
+    Batch,
+}
+
+impl FromStr for PriorityClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "interactive" => Self::Interactive,
+            "batch" => Self::Batch,
+            _ => bail!("unknown priority class: {s}"),
+        })
+    }
+}
+
+impl PriorityClass {
+    /// Returns the class of the request. The one the router set from a route
+    /// rule takes precedence over the header of the client.
+    pub fn of_request(req: &Request<Body>) -> Self {
+        req.extensions()
+            .get::<Self>()
+            .copied()
+            .or_else(|| {
+                req.headers()
+                    .get(PRIORITY_HEADER)
+                    .and_then(|it| it.to_str().ok())
+                    .and_then(|it| it.trim().to_ascii_lowercase().parse().ok())
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BatchPolicy {
+    /// How many batch requests the user workers handle at once, within the
+    /// limit of all requests.
+    pub max_concurrent_requests: Option<usize>,
+    /// How long a batch request waits for a slot, instead of the wait timeout
+    /// of the pool.
+    pub request_wait_timeout_ms: Option<u64>,
+    /// The CPU time in milliseconds the batch requests may use per second,
+    /// across the user workers.
+    pub cpu_budget_ms: Option<u64>,
+}
+
+impl BatchPolicy {
+    pub fn from_flags(flags: &ServerFlags) -> Self {
+        Self {
+            max_concurrent_requests: flags.batch_max_concurrent_requests.filter(|it| *it > 0),
+            request_wait_timeout_ms: flags.batch_request_wait_timeout_ms,
+            cpu_budget_ms: flags.batch_cpu_budget_ms.filter(|it| *it > 0),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Window {
+    started_at: Instant,
+    used_ms: u64,
+}
+
+/// The CPU time the batch requests may use per window. A batch request waits
+/// for the next window once the budget of the current one is spent.
+///
+/// NOTE: A request is charged with the CPU time its worker used until the body
+/// of the response was done, as the supervisor of the worker measured it, so
+/// the requests that share a worker are charged for each other.
+#[derive(Debug)]
+pub struct CpuBudget {
+    budget_ms: u64,
+    window: Mutex<Window>,
+}
+
+impl CpuBudget {
+    pub fn new(budget_ms: u64) -> Self {
+        Self {
+            budget_ms,
+            window: Mutex::new(Window {
+                started_at: Instant::now(),
+                used_ms: 0,
+            }),
+        }
+    }
+
+    /// Returns when the budget has some time left.
+    pub async fn wait(&self) {
+        loop {
+            let next_window_at = {
+                let mut window = self.window.lock().unwrap();
+                let now = Instant::now();
+
+                if now.duration_since(window.started_at) >= CPU_BUDGET_WINDOW {
+                    window.started_at = now;
+                    window.used_ms = 0;
+                }
+
+                if window.used_ms < self.budget_ms {
+                    return;
+                }
+
+                window.started_at + CPU_BUDGET_WINDOW
+            };
+
+            tokio::time::sleep_until(next_window_at).await;
+        }
+    }
+
+    pub fn charge(&self, cpu_time_ms: u64) {
+        let mut window = self.window.lock().unwrap();
+
+        window.used_ms = window.used_ms.saturating_add(cpu_time_ms);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use hyper_v014::{Body, Request};
+
+    use super::{CpuBudget, PriorityClass, PRIORITY_HEADER};
+
+    #[test]
+    fn test_priority_class_of_request() {
+        let req = |value: Option<&str>| {
+            let mut builder = Request::builder().uri("/foo");
+
+            if let Some(value) = value {
+                builder = builder.header(PRIORITY_HEADER, value);
+            }
+
+            builder.body(Body::empty()).unwrap()
+        };
+
+        assert_eq!(
+            PriorityClass::of_request(&req(None)),
+            PriorityClass::Interactive
+        );
+        assert_eq!(
+            PriorityClass::of_request(&req(Some("Batch"))),
+            PriorityClass::Batch
+        );
+        assert_eq!(
+            PriorityClass::of_request(&req(Some("urgent"))),
+            PriorityClass::Interactive
+        );
+
+        let mut routed = req(Some("interactive"));
+
+        routed.extensions_mut().insert(PriorityClass::Batch);
+
+        assert_eq!(PriorityClass::of_request(&routed), PriorityClass::Batch);
+    }
+
+    #[tokio::test]
+    async fn test_cpu_budget() {
+        let budget = CpuBudget::new(10);
+
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), budget.wait())
+                .await
+                .is_ok()
+        );
+
+        budget.charge(10);
+
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), budget.wait())
+                .await
+                .is_err()
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(1500), budget.wait())
+                .await
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Recording of the requests dispatched to the user workers along with their
+//! responses, to be replayed later on (see `sb_core::replay`).
+//!
+//! The bodies are passed on as they are streamed, and only their head is kept
+//! to be recorded (see `RecordingOptions::max_body_size`). An exchange is
+//! recorded once both bodies have been streamed, or dropped.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::{Bytes, BytesMut};
+use futures_util::Stream;
+use hyper_v014::body::HttpBody;
+use hyper_v014::{Body, Request, Response};
+use sb_core::replay::{RecordedExchange, Recorder, RecordingEntry};
+use tokio::sync::oneshot;
+
+use super::request_meta::get_request_meta;
+
+/// The head of a body, as it was streamed.
+#[derive(Default)]
+struct BodyHead {
+    data: BytesMut,
+    truncated: bool,
+    error: Option<String>,
+}
+
+/// Passes a body on, and sends its head once the body is streamed in full,
+/// fails, or is dropped.
+struct BodyTee {
+    body: Body,
+    max_size: usize,
+    head: BodyHead,
+    head_tx: Option<oneshot::Sender<BodyHead>>,
+}
+
+impl BodyTee {
+    fn wrap(body: Body, max_size: usize) -> (Body, oneshot::Receiver<BodyHead>) {
+        let (head_tx, head_rx) = oneshot::channel();
+        let tee = Self {
+            body,
+            max_size,
+            head: BodyHead::default(),
+            head_tx: Some(head_tx),
+        };
+
+        (Body::wrap_stream(tee), head_rx)
+    }
+
+    fn finish(&mut self) {
+        if let Some(tx) = self.head_tx.take() {
+            let _ = tx.send(std::mem::take(&mut self.head));
+        }
+    }
+}
+
+impl Stream for BodyTee {
+    type Item = Result<Bytes, hyper_v014::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let result = futures_util::ready!(Pin::new(&mut this.body).poll_next(cx));
+
+        match result.as_ref() {
+            Some(Ok(chunk)) => {
+                let len = chunk.len().min(this.max_size - this.head.data.len());
+
+                this.head.data.extend_from_slice(&chunk[..len]);
+                this.head.truncated |= len < chunk.len();
+            }
+
+            Some(Err(err)) => {
+                this.head.error = Some(format!("{err:#}"));
+                this.finish();
+            }
+
+            None => this.finish(),
+        }
+
+        Poll::Ready(result)
+    }
+}
+
+impl Drop for BodyTee {
+    fn drop(&mut self) {
+        if self.head_tx.is_some() && !self.body.is_end_stream() {
+            self.head.error = Some("the body was not streamed in full".to_string());
+        }
+
+        self.finish();
+    }
+}
+
+pub(crate) struct RequestRecording {
+    recorder: Recorder,
+    exchange: RecordedExchange,
+    body_rx: oneshot::Receiver<BodyHead>,
+}
+
+impl RequestRecording {
+    /// Returns the request to be dispatched in place of the original one,
+    /// whose body is recorded as it is streamed to the worker.
+    pub(crate) fn start(
+        recorder: Recorder,
+        service_path: &str,
+        req: Request<Body>,
+    ) -> (Request<Body>, Self) {
+        let (parts, body) = req.into_parts();
+        let (body, body_rx) = BodyTee::wrap(body, recorder.max_body_size());
+
+        let exchange = RecordedExchange {
+            service_path: service_path.to_string(),
+            request_id: get_request_meta(&parts.headers).map(|it| it.request_id),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |it| it.as_millis() as u64),
+            method: parts.method.to_string(),
+            url: parts.uri.to_string(),
+            headers: recorder.headers(
+                parts
+                    .headers
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_bytes())),
+            ),
+            body: String::new(),
+            body_truncated: false,
+            status: None,
+            response_headers: vec![],
+            response_body: String::new(),
+            response_body_truncated: false,
+            error: None,
+        };
+
+        (
+            Request::from_parts(parts, body),
+            Self {
+                recorder,
+                exchange,
+                body_rx,
+            },
+        )
+    }
+
+    /// Records the response, whose body is recorded as it is streamed to the
+    /// client.
+    pub(crate) fn finish(self, result: Result<&mut Response<Body>, &Error>) {
+        let Self {
+            recorder,
+            mut exchange,
+            body_rx,
+        } = self;
+
+        let maybe_res_body_rx = match result {
+            Ok(res) => {
+                let (body, body_rx) =
+                    BodyTee::wrap(std::mem::take(res.body_mut()), recorder.max_body_size());
+
+                exchange.status = Some(res.status().as_u16());
+                exchange.response_headers = recorder.headers(
+                    res.headers()
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.as_bytes())),
+                );
+                *res.body_mut() = body;
+
+                Some(body_rx)
+            }
+
+            Err(err) => {
+                exchange.error = Some(format!("{err:#}"));
+                None
+            }
+        };
+
+        drop(tokio::spawn(async move {
+            let head = body_rx.await.unwrap_or_default();
+
+            // NOTE: A request whose body the worker did not read in full can
+            // only be replayed with the part of it that was read.
+            exchange.body = STANDARD.encode(&head.data);
+            exchange.body_truncated = head.truncated || head.error.is_some();
+
+            if let Some(body_rx) = maybe_res_body_rx {
+                let head = body_rx.await.unwrap_or_default();
+
+                exchange.response_body = STANDARD.encode(&head.data);
+                exchange.response_body_truncated = head.truncated;
+
+                // NOTE: The client would have got a broken body anyway.
+                if head.error.is_some() {
+                    exchange.error = head.error;
+                }
+            }
+
+            recorder.record(RecordingEntry::Request(exchange));
+        }));
+    }
+}
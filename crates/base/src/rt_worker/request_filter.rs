@@ -0,0 +1,242 @@
+//! A hook that lets embedders turn down requests before they are dispatched
+//! to a user worker, so that simple WAF or abuse rules run once in Rust
+//! rather than in every function.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use http_v02::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use hyper_v014::Body;
+use uuid::Uuid;
+
+use super::client_ip::CLIENT_IP_HEADER;
+use super::request_meta::get_request_meta;
+
+/// What a [`RequestFilter`] knows about a request.
+#[derive(Debug)]
+pub struct RequestFilterInput<'a> {
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub headers: &'a HeaderMap,
+    /// The IP of the client, as resolved by the server.
+    pub client_ip: Option<IpAddr>,
+    /// `None` if the request is filtered before a worker is created for it,
+    /// which the function router does.
+    pub worker_key: Option<Uuid>,
+    pub service_path: &'a str,
+}
+
+impl<'a> RequestFilterInput<'a> {
+    pub(crate) fn new(
+        req: &'a Request<Body>,
+        worker_key: Option<Uuid>,
+        service_path: &'a str,
+    ) -> Self {
+        Self {
+            method: req.method(),
+            path: req.uri().path(),
+            headers: req.headers(),
+            client_ip: get_request_meta(req.headers())
+                .and_then(|it| it.client_ip)
+                .or_else(|| {
+                    req.headers()
+                        .get(CLIENT_IP_HEADER)
+                        .and_then(|it| it.to_str().ok())
+                        .and_then(|it| it.parse().ok())
+                }),
+            worker_key,
+            service_path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestFilterDecision {
+    Allow,
+    /// Responds with the given status and body without reaching the worker.
+    Reject(StatusCode, String),
+    /// Holds the request for the given delay before it is dispatched, which
+    /// slows down abusive clients without telling them.
+    Tarpit(Duration),
+}
+
+/// Decides whether a request may be dispatched to a user worker.
+///
+/// Filters are evaluated for every request on the worker pool, so they must
+/// not block.
+pub trait RequestFilter: Send + Sync + 'static {
+    fn evaluate(&self, input: &RequestFilterInput<'_>) -> RequestFilterDecision;
+}
+
+impl<F> RequestFilter for F
+where
+    F: Fn(&RequestFilterInput<'_>) -> RequestFilterDecision + Send + Sync + 'static,
+{
+    fn evaluate(&self, input: &RequestFilterInput<'_>) -> RequestFilterDecision {
+        self(input)
+    }
+}
+
+/// Evaluates the filters in the order they were registered. The first
+/// rejection wins, and otherwise the request is held for the longest tarpit
+/// delay, if any.
+pub(crate) fn evaluate_request_filters(
+    filters: &[Arc<dyn RequestFilter>],
+    input: &RequestFilterInput<'_>,
+) -> RequestFilterDecision {
+    let mut delay = None::<Duration>;
+
+    for filter in filters {
+        match filter.evaluate(input) {
+            RequestFilterDecision::Allow => {}
+            RequestFilterDecision::Reject(status, body) => {
+                return RequestFilterDecision::Reject(status, body);
+            }
+
+            RequestFilterDecision::Tarpit(it) => {
+                delay = Some(delay.map_or(it, |delay| delay.max(it)));
+            }
+        }
+    }
+
+    delay.map_or(RequestFilterDecision::Allow, RequestFilterDecision::Tarpit)
+}
+
+/// Marks a request that has been let through by the filters already, so that
+/// they are not evaluated a second time once it reaches the worker pool.
+#[derive(Debug, Clone, Copy)]
+struct RequestFiltered;
+
+/// Evaluates the filters for a request that has not been let through by them
+/// yet, and marks it if it is.
+pub(crate) fn filter_request(
+    filters: &[Arc<dyn RequestFilter>],
+    req: &mut Request<Body>,
+    worker_key: Option<Uuid>,
+    service_path: &str,
+) -> RequestFilterDecision {
+    if filters.is_empty() || req.extensions().get::<RequestFiltered>().is_some() {
+        return RequestFilterDecision::Allow;
+    }
+
+    let decision = evaluate_request_filters(
+        filters,
+        &RequestFilterInput::new(req, worker_key, service_path),
+    );
+
+    if !matches!(decision, RequestFilterDecision::Reject(..)) {
+        req.extensions_mut().insert(RequestFiltered);
+    }
+
+    decision
+}
+
+pub(crate) fn emit_rejection(status: StatusCode, body: String) -> Response<Body> {
+    let mut res = Response::new(Body::from(body));
+
+    *res.status_mut() = status;
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use http_v02::{Request, StatusCode};
+    use hyper_v014::Body;
+    use uuid::Uuid;
+
+    use super::{
+        evaluate_request_filters, filter_request, RequestFilter, RequestFilterDecision,
+        RequestFilterInput,
+    };
+
+    #[test]
+    fn test_evaluate_request_filters() {
+        let req = Request::builder()
+            .uri("/admin/users")
+            .header("x-client-ip", "203.0.113.7")
+            .body(Body::empty())
+            .unwrap();
+
+        let input = RequestFilterInput::new(&req, Some(Uuid::nil()), "./hello");
+
+        assert_eq!(input.client_ip, Some("203.0.113.7".parse().unwrap()));
+
+        let tarpit = |ms| -> Arc<dyn RequestFilter> {
+            Arc::new(move |_: &RequestFilterInput<'_>| {
+                RequestFilterDecision::Tarpit(Duration::from_millis(ms))
+            })
+        };
+
+        let deny_admin: Arc<dyn RequestFilter> = Arc::new(|it: &RequestFilterInput<'_>| {
+            if it.path.starts_with("/admin") {
+                RequestFilterDecision::Reject(StatusCode::FORBIDDEN, "forbidden".to_string())
+            } else {
+                RequestFilterDecision::Allow
+            }
+        });
+
+        assert_eq!(
+            evaluate_request_filters(&[], &input),
+            RequestFilterDecision::Allow
+        );
+        assert_eq!(
+            evaluate_request_filters(&[tarpit(100), tarpit(300), tarpit(200)], &input),
+            RequestFilterDecision::Tarpit(Duration::from_millis(300))
+        );
+        assert_eq!(
+            evaluate_request_filters(&[tarpit(100), deny_admin], &input),
+            RequestFilterDecision::Reject(StatusCode::FORBIDDEN, "forbidden".to_string())
+        );
+    }
+
+    #[test]
+    fn test_filter_request_once() {
+        let tarpit: Arc<dyn RequestFilter> = Arc::new(|_: &RequestFilterInput<'_>| {
+            RequestFilterDecision::Tarpit(Duration::from_millis(100))
+        });
+
+        let deny_unknown: Arc<dyn RequestFilter> = Arc::new(|it: &RequestFilterInput<'_>| {
+            if it.worker_key.is_none() && it.path == "/unknown" {
+                RequestFilterDecision::Reject(StatusCode::NOT_FOUND, "not found".to_string())
+            } else {
+                RequestFilterDecision::Allow
+            }
+        });
+
+        let filters = [tarpit, deny_unknown];
+        let mut req = Request::builder()
+            .uri("/hello")
+            .body(Body::empty())
+            .unwrap();
+
+        // NOTE: The request is filtered by the router first, and then reaches
+        // the pool.
+        assert_eq!(
+            filter_request(&filters, &mut req, None, "./hello"),
+            RequestFilterDecision::Tarpit(Duration::from_millis(100))
+        );
+        assert_eq!(
+            filter_request(&filters, &mut req, Some(Uuid::nil()), "./hello"),
+            RequestFilterDecision::Allow
+        );
+
+        let mut req = Request::builder()
+            .uri("/unknown")
+            .body(Body::empty())
+            .unwrap();
+
+        assert_eq!(
+            filter_request(&filters, &mut req, None, "./unknown"),
+            RequestFilterDecision::Reject(StatusCode::NOT_FOUND, "not found".to_string())
+        );
+    }
+}
@@ -0,0 +1,410 @@
+//! An append-only journal of the requests dispatched to the user workers, so
+//! that the requests that were in flight when the process died (e.g. of an
+//! OOM or an abort) can be told after the fact.
+//!
+//! Only the metadata of the requests is journaled, never their bodies. The
+//! journal of the previous run is kept next to the new one, with a `.prev`
+//! extension.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Error};
+use deno_core::serde_json;
+use http_v02::Request;
+use hyper_v014::Body;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// The records are written and synced to the disk at least this often.
+static SYNC_INTERVAL: Duration = Duration::from_millis(100);
+/// The records are written and synced to the disk as soon as this many are
+/// pending.
+static MAX_PENDING_RECORDS: usize = 256;
+/// The journal is compacted down to the requests in flight past this size.
+static MAX_JOURNAL_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestStarted {
+    pub id: u64,
+    pub worker_key: Uuid,
+    pub service_path: String,
+    pub method: String,
+    pub path: String,
+    /// Milliseconds since the unix epoch.
+    pub started_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestEnded {
+    pub id: u64,
+    /// `None` if the request failed.
+    pub status: Option<u16>,
+    /// Milliseconds since the unix epoch.
+    pub ended_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum JournalRecord {
+    Start(RequestStarted),
+    End(RequestEnded),
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |it| it.as_millis() as u64)
+}
+
+/// Returns the requests that were started but never ended, by worker.
+pub fn get_in_flight_requests(
+    records: impl IntoIterator<Item = JournalRecord>,
+) -> BTreeMap<Uuid, Vec<RequestStarted>> {
+    let mut in_flight = HashMap::new();
+
+    for record in records {
+        match record {
+            JournalRecord::Start(it) => {
+                in_flight.insert(it.id, it);
+            }
+
+            JournalRecord::End(it) => {
+                in_flight.remove(&it.id);
+            }
+        }
+    }
+
+    let mut by_worker = BTreeMap::<_, Vec<_>>::new();
+
+    for it in in_flight.into_values() {
+        by_worker.entry(it.worker_key).or_default().push(it);
+    }
+
+    for requests in by_worker.values_mut() {
+        requests.sort_by_key(|it| it.id);
+    }
+
+    by_worker
+}
+
+/// Reads the records of a journal.
+///
+/// NOTE: The last record may have been cut short by the death of the process,
+/// so unreadable lines are skipped.
+pub fn read_journal(path: &Path) -> Result<Vec<JournalRecord>, Error> {
+    let file = fs::File::open(path)?;
+
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect())
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestJournal {
+    next_id: Arc<AtomicU64>,
+    records_tx: mpsc::UnboundedSender<JournalRecord>,
+}
+
+impl RequestJournal {
+    /// Opens a new journal at the given path, after reporting the requests
+    /// the journal of the previous run left in flight.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut prev_path = path.as_os_str().to_owned();
+
+        prev_path.push(".prev");
+
+        let prev_path = PathBuf::from(prev_path);
+
+        if path.exists() {
+            let in_flight = get_in_flight_requests(read_journal(path)?);
+
+            for (worker_key, requests) in in_flight.iter() {
+                for it in requests {
+                    warn!(
+                        "request was in flight when the runtime stopped: {} {} (worker: {}, service: {})",
+                        it.method, it.path, worker_key, it.service_path
+                    );
+                }
+            }
+
+            fs::rename(path, &prev_path).with_context(|| {
+                format!("failed to rotate the request journal: {}", path.display())
+            })?;
+        }
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open the request journal: {}", path.display()))?;
+
+        let (records_tx, records_rx) = mpsc::unbounded_channel();
+
+        drop(tokio::spawn(write_journal(
+            path.to_path_buf(),
+            tokio::fs::File::from_std(file),
+            records_rx,
+        )));
+
+        Ok(Self {
+            next_id: Arc::default(),
+            records_tx,
+        })
+    }
+
+    /// Journals the start of a request, and returns its id.
+    pub fn start(&self, worker_key: Uuid, service_path: &str, req: &Request<Body>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let _ = self.records_tx.send(JournalRecord::Start(RequestStarted {
+            id,
+            worker_key,
+            service_path: service_path.to_string(),
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            started_at: now_ms(),
+        }));
+
+        id
+    }
+
+    pub fn end(&self, id: u64, status: Option<u16>) {
+        let _ = self.records_tx.send(JournalRecord::End(RequestEnded {
+            id,
+            status,
+            ended_at: now_ms(),
+        }));
+    }
+
+    /// Returns a guard that journals the end of a request once dropped, so
+    /// that it can be tied to the response body.
+    pub fn end_on_drop(&self, id: u64, status: Option<u16>) -> RequestEndGuard {
+        RequestEndGuard {
+            journal: self.clone(),
+            id,
+            status,
+        }
+    }
+}
+
+/// Journals the end of a request once dropped. See
+/// [`RequestJournal::end_on_drop`].
+#[derive(Debug)]
+pub struct RequestEndGuard {
+    journal: RequestJournal,
+    id: u64,
+    status: Option<u16>,
+}
+
+impl Drop for RequestEndGuard {
+    fn drop(&mut self) {
+        self.journal.end(self.id, self.status);
+    }
+}
+
+async fn write_journal(
+    path: PathBuf,
+    mut file: tokio::fs::File,
+    mut records_rx: mpsc::UnboundedReceiver<JournalRecord>,
+) {
+    let mut buf = Vec::new();
+    let mut pending = 0;
+    let mut size = 0;
+    let mut in_flight = HashMap::new();
+    let mut ticker = tokio::time::interval(SYNC_INTERVAL);
+
+    loop {
+        let closed = tokio::select! {
+            record = records_rx.recv() => match record {
+                Some(record) => {
+                    match &record {
+                        JournalRecord::Start(it) => {
+                            in_flight.insert(it.id, record.clone());
+                        }
+
+                        JournalRecord::End(it) => {
+                            in_flight.remove(&it.id);
+                        }
+                    }
+
+                    if serde_json::to_writer(&mut buf, &record).is_ok() {
+                        buf.push(b'\n');
+                        pending += 1;
+                    }
+
+                    if pending < MAX_PENDING_RECORDS {
+                        continue;
+                    }
+
+                    false
+                }
+
+                None => true,
+            },
+
+            _ = ticker.tick() => false,
+        };
+
+        if !buf.is_empty() {
+            size += buf.len() as u64;
+
+            if let Err(err) = sync(&mut file, &buf).await {
+                error!("failed to write the request journal: {:#}", err);
+            }
+
+            buf.clear();
+            pending = 0;
+        }
+
+        // NOTE: Only the requests in flight matter, so a journal that grew
+        // too large is rewritten with them alone.
+        if size > MAX_JOURNAL_BYTES {
+            for record in in_flight.values() {
+                if serde_json::to_writer(&mut buf, record).is_ok() {
+                    buf.push(b'\n');
+                }
+            }
+
+            match compact(&path, &buf).await {
+                Ok(it) => {
+                    file = it;
+                    size = buf.len() as u64;
+                }
+
+                Err(err) => {
+                    error!("failed to compact the request journal: {:#}", err);
+                }
+            }
+
+            buf.clear();
+        }
+
+        if closed {
+            break;
+        }
+    }
+}
+
+async fn sync(file: &mut tokio::fs::File, buf: &[u8]) -> Result<(), Error> {
+    file.write_all(buf).await?;
+    file.sync_data().await?;
+    Ok(())
+}
+
+async fn compact(path: &Path, buf: &[u8]) -> Result<tokio::fs::File, Error> {
+    let mut tmp_path = path.as_os_str().to_owned();
+
+    tmp_path.push(".tmp");
+
+    let tmp_path = PathBuf::from(tmp_path);
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+
+    sync(&mut file, buf).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+
+    Ok(tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .await?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    use super::{
+        get_in_flight_requests, JournalRecord, RequestEnded, RequestJournal, RequestStarted,
+    };
+
+    fn start(id: u64, worker_key: Uuid) -> JournalRecord {
+        JournalRecord::Start(RequestStarted {
+            id,
+            worker_key,
+            service_path: "./hello".to_string(),
+            method: "GET".to_string(),
+            path: format!("/{id}"),
+            started_at: 0,
+        })
+    }
+
+    fn end(id: u64) -> JournalRecord {
+        JournalRecord::End(RequestEnded {
+            id,
+            status: Some(200),
+            ended_at: 0,
+        })
+    }
+
+    #[test]
+    fn test_get_in_flight_requests() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let in_flight = get_in_flight_requests(vec![
+            start(0, a),
+            start(1, b),
+            start(2, a),
+            end(0),
+            start(3, a),
+            end(1),
+        ]);
+
+        assert_eq!(in_flight.len(), 1);
+        assert_eq!(
+            in_flight[&a].iter().map(|it| it.id).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn test_request_end_guard() {
+        let (records_tx, mut records_rx) = mpsc::unbounded_channel();
+        let journal = RequestJournal {
+            next_id: Arc::default(),
+            records_tx,
+        };
+
+        let guard = journal.end_on_drop(7, Some(200));
+
+        assert!(records_rx.try_recv().is_err());
+
+        drop(guard);
+
+        assert!(matches!(
+            records_rx.try_recv(),
+            Ok(JournalRecord::End(RequestEnded {
+                id: 7,
+                status: Some(200),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_journal_record_format() {
+        let line = r#"{"type":"end","id":1,"status":null,"endedAt":2}"#;
+
+        assert_eq!(
+            deno_core::serde_json::from_str::<JournalRecord>(line).unwrap(),
+            JournalRecord::End(RequestEnded {
+                id: 1,
+                status: None,
+                ended_at: 2,
+            })
+        );
+    }
+}
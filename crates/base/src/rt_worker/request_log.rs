@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use sb_workers::context::RequestSummary;
+
+/// Keeps the summaries of the last requests handled by a user worker, so that
+/// they can be inspected through the workers API.
+#[derive(Debug, Clone)]
+pub struct RequestLog {
+    capacity: usize,
+    entries: Arc<Mutex<VecDeque<RequestSummary>>>,
+}
+
+impl RequestLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Appends a summary, evicting the oldest one if the log is full.
+    pub fn push(&self, summary: RequestSummary) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+
+        entries.push_back(summary);
+    }
+
+    /// Returns the summaries from the oldest to the newest.
+    pub fn snapshot(&self) -> Vec<RequestSummary> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sb_workers::context::RequestSummary;
+
+    use super::RequestLog;
+
+    fn summary(path: &str) -> RequestSummary {
+        RequestSummary {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            status: Some(200),
+            latency_ms: 1,
+            request_size: None,
+            response_size: Some(2),
+            error: None,
+            started_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_request_log_evicts_oldest() {
+        let log = RequestLog::new(2);
+
+        log.push(summary("/a"));
+        log.push(summary("/b"));
+        log.push(summary("/c"));
+
+        let paths = log
+            .snapshot()
+            .into_iter()
+            .map(|it| it.path)
+            .collect::<Vec<_>>();
+
+        assert_eq!(paths, vec!["/b", "/c"]);
+    }
+}
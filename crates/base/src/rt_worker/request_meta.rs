@@ -0,0 +1,41 @@
+//! Reading and writing the [`RequestMeta`] of a request, which the server
+//! sets on every request it receives and the pool completes with what it
+//! knows once the request is handed to a user worker.
+
+use http_v02::header::HeaderMap;
+use http_v02::HeaderValue;
+use sb_core::request_meta::REQUEST_META_HEADER;
+use uuid::Uuid;
+
+pub use sb_core::request_meta::{RequestMeta, TaskMeta, TlsMeta};
+
+/// Returns the metadata of a request, if it carries valid metadata.
+pub fn get_request_meta(headers: &HeaderMap) -> Option<RequestMeta> {
+    RequestMeta::decode(headers.get(REQUEST_META_HEADER)?.as_bytes())
+}
+
+/// Replaces the metadata of a request, whatever it carried before.
+pub fn set_request_meta(headers: &mut HeaderMap, meta: &RequestMeta) {
+    headers.remove(REQUEST_META_HEADER);
+
+    // NOTE: The value is base64, which is always a valid header value.
+    if let Ok(value) = HeaderValue::from_str(&meta.encode()) {
+        headers.insert(REQUEST_META_HEADER, value);
+    }
+}
+
+/// Sets the tenant of the user worker a request is handed to.
+///
+/// A request that lost its metadata on the way, e.g. because the main worker
+/// built a new one without copying its headers, or that carries metadata not
+/// signed by this process gets a fresh request ID and nothing else.
+pub(crate) fn assign_tenant(headers: &mut HeaderMap, tenant: Option<&str>) {
+    let mut meta = get_request_meta(headers).unwrap_or_else(|| RequestMeta {
+        request_id: Uuid::new_v4().to_string(),
+        ..Default::default()
+    });
+
+    meta.tenant = tenant.map(str::to_string);
+
+    set_request_meta(headers, &meta);
+}
@@ -0,0 +1,537 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::pending;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use futures_util::Stream;
+use http_utils::utils::emit_status_code;
+use http_v02::uri::Authority;
+use http_v02::{StatusCode, Uri};
+use hyper_v014::{Body, Request, Response};
+use log::error;
+use sb_graph::DecoratorType;
+use sb_workers::context::{
+    CreateUserWorkerResult, SendRequestResult, UserWorkerMsgs, UserWorkerRuntimeOpts,
+    WorkerContextInitOpts, WorkerRequestMsg, WorkerRuntimeOpts,
+};
+use sb_workers::errors::{emit_worker_error, WorkerError};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+use crate::runtime_config::{
+    handle_config_api, ConfigReloader, Route, RuntimeConfig, CONFIG_API_PATH,
+};
+
+use super::internal_auth::InternalApiAuth;
+use super::request_filter::{emit_rejection, filter_request, RequestFilter, RequestFilterDecision};
+use super::startup_gate::ServiceStartupGate;
+use super::status_api::{handle_status_api, STATUS_API_PATH};
+use super::traces_api::{handle_traces_api, TRACES_API_PATH};
+use super::worker_ctx::TerminationToken;
+use super::workers_api::{handle_workers_api, WORKERS_API_PATH};
+
+/// Signals the end of a request to the supervisor of the user worker once the
+/// response body has been consumed (or dropped) by the client.
+pub(crate) struct ReqEndOnDrop<S> {
+    pub(crate) inner: S,
+    pub(crate) req_end_tx: Option<mpsc::UnboundedSender<()>>,
+}
+
+impl<S> Drop for ReqEndOnDrop<S> {
+    fn drop(&mut self) {
+        if let Some(tx) = self.req_end_tx.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+impl<S: Stream + Unpin> Stream for ReqEndOnDrop<S> {
+    type Item = S::Item;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.as_mut().inner).poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionRouterOpts {
+    /// Routes requests by function name onto its subdirectories, if set.
+    /// Otherwise only the routes of the runtime config are served.
+    pub functions_dir: Option<PathBuf>,
+    pub import_map_path: Option<String>,
+    pub no_module_cache: bool,
+    pub maybe_decorator: Option<DecoratorType>,
+    /// Enables the internal workers API (see [`handle_workers_api`]), the
+    /// status page (see [`handle_status_api`]) and the recent traces (see
+    /// [`handle_traces_api`]) if set.
+    pub internal_api_auth: Option<InternalApiAuth>,
+    /// Supplies the routes and the default limits of the workers, if set.
+    pub config_reloader: Option<ConfigReloader>,
+    /// The filters of the worker pool, evaluated before a worker is created
+    /// for a request.
+    pub request_filters: Vec<Arc<dyn RequestFilter>>,
+    /// Holds the requests of a service until a worker of it has booted, if
+    /// set. Otherwise they wait for the boot unbounded.
+    pub startup_gate: Option<ServiceStartupGate>,
+}
+
+impl FunctionRouterOpts {
+    pub(crate) fn runtime_config(&self) -> Arc<RuntimeConfig> {
+        self.config_reloader
+            .as_ref()
+            .map(|it| it.config().get())
+            .unwrap_or_default()
+    }
+}
+
+/// Resolves the service directory of a function from the first segment of the
+/// request path (`/:function_name/*`). The routes of the runtime config take
+/// precedence over the functions directory.
+///
+/// Returns `Ok(None)` if the path does not contain a function name.
+fn resolve_function_path(
+    maybe_functions_dir: Option<&Path>,
+    routes: &BTreeMap<String, Route>,
+    path: &str,
+) -> Result<Option<PathBuf>, ()> {
+    let Some(name) = path
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .filter(|it| !it.is_empty())
+    else {
+        return Ok(None);
+    };
+
+    // NOTE: The function name is used as a path component, so anything that
+    // could escape from the functions directory must be rejected.
+    if name == "." || name == ".." || name.contains(['\\', '%', '\0']) {
+        return Err(());
+    }
+
+    if let Some(service_path) = routes.get(name).and_then(Route::as_function) {
+        return Ok(Some(service_path.to_path_buf()));
+    }
+
+    let Some(service_path) = maybe_functions_dir.map(|it| it.join(name)) else {
+        return Err(());
+    };
+
+    if !service_path.is_dir() {
+        return Err(());
+    }
+
+    Ok(Some(service_path))
+}
+
+/// Returns `true` if the path is the given one or lies under it, so that
+/// `/_internal/workersX` is not taken for `/_internal/workers`.
+fn is_under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Returns the host the request was sent to, without the port.
+fn get_request_host(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get(http_v02::header::HOST)
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.parse::<Authority>().ok())
+        .or_else(|| req.uri().authority().cloned())
+        .map(|it| it.host().to_string())
+}
+
+/// Replaces the path of the request, keeping its query.
+fn set_request_path(req: &mut Request<Body>, path: &str) {
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+
+    parts.path_and_query = path_and_query.parse().ok();
+
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+}
+
+/// Reads the env of a service from the `.env` file in its directory, if any.
+///
+/// NOTE: Nothing of the env of the runtime itself is passed on, since it holds
+/// the secrets of the operator rather than of the service.
+fn load_service_env(service_path: &Path) -> HashMap<String, String> {
+    let Ok(content) = std::fs::read_to_string(service_path.join(".env")) else {
+        return HashMap::new();
+    };
+
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|it| !it.is_empty() && !it.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let key = key.trim();
+            let value = value.trim();
+
+            if key.is_empty() {
+                return None;
+            }
+
+            let value = [('"', '"'), ('\'', '\'')]
+                .into_iter()
+                .find_map(|(l, r)| value.strip_prefix(l).and_then(|it| it.strip_suffix(r)))
+                .unwrap_or(value);
+
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+pub(crate) fn emit_json_error(status: StatusCode, msg: &str) -> Response<Body> {
+    let mut res = emit_status_code(
+        status,
+        Some(Body::from(serde_json::json!({ "msg": msg }).to_string())),
+        false,
+    );
+
+    res.headers_mut().insert(
+        http_v02::header::CONTENT_TYPE,
+        http_v02::HeaderValue::from_static("application/json"),
+    );
+
+    res
+}
+
+async fn forward_request(
+    opts: &FunctionRouterOpts,
+    conf: UserWorkerRuntimeOpts,
+    service_path: PathBuf,
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    mut req: Request<Body>,
+    conn_token: Option<CancellationToken>,
+) -> Result<Response<Body>, Error> {
+    match filter_request(
+        &opts.request_filters,
+        &mut req,
+        None,
+        &service_path.to_string_lossy(),
+    ) {
+        RequestFilterDecision::Allow => {}
+        RequestFilterDecision::Reject(status, body) => return Ok(emit_rejection(status, body)),
+        RequestFilterDecision::Tarpit(delay) => tokio::time::sleep(delay).await,
+    }
+
+    let (create_tx, create_rx) = oneshot::channel::<Result<CreateUserWorkerResult, Error>>();
+    let env_vars = load_service_env(&service_path);
+
+    worker_pool_tx.send(UserWorkerMsgs::Create(
+        WorkerContextInitOpts {
+            service_path: service_path.clone(),
+            no_module_cache: opts.no_module_cache,
+            import_map_path: opts.import_map_path.clone(),
+            env_vars,
+            timing: None,
+            conf: WorkerRuntimeOpts::UserWorker(conf),
+            maybe_eszip: None,
+            maybe_module_code: None,
+            maybe_module_map: None,
+            maybe_entrypoint: None,
+            maybe_decorator: opts.maybe_decorator,
+            static_patterns: vec![],
+            maybe_jsx_import_source_config: None,
+            locale: Default::default(),
+        },
+        create_tx,
+    ))?;
+
+    let created = async { create_rx.await.map_err(|_| WorkerError::WorkerGone)? };
+    let CreateUserWorkerResult { key, .. } = match opts.startup_gate.as_ref() {
+        Some(gate) => {
+            gate.admit(&service_path, conn_token.as_ref(), created)
+                .await?
+        }
+
+        None => created.await?,
+    };
+    let (res_tx, res_rx) = oneshot::channel::<Result<SendRequestResult, Error>>();
+
+    worker_pool_tx.send(UserWorkerMsgs::SendRequest(key, req, res_tx, conn_token))?;
+
+    let (res, req_end_tx) = res_rx.await.map_err(|_| WorkerError::WorkerGone)??;
+    let (parts, body) = res.into_parts();
+
+    Ok(Response::from_parts(
+        parts,
+        Body::wrap_stream(ReqEndOnDrop {
+            inner: body,
+            req_end_tx: Some(req_end_tx),
+        }),
+    ))
+}
+
+async fn route_request(
+    opts: Arc<FunctionRouterOpts>,
+    worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    msg: WorkerRequestMsg,
+) {
+    let WorkerRequestMsg {
+        mut req,
+        res_tx,
+        conn_token,
+    } = msg;
+
+    if let Some(auth) = opts.internal_api_auth.as_ref() {
+        let path = req.uri().path();
+
+        if is_under(path, WORKERS_API_PATH)
+            || is_under(path, CONFIG_API_PATH)
+            || is_under(path, STATUS_API_PATH)
+            || is_under(path, TRACES_API_PATH)
+        {
+            let res = if is_under(path, WORKERS_API_PATH) {
+                handle_workers_api(&opts, auth, &worker_pool_tx, req).await
+            } else if is_under(path, STATUS_API_PATH) {
+                handle_status_api(auth, &worker_pool_tx, req).await
+            } else if is_under(path, TRACES_API_PATH) {
+                handle_traces_api(auth, &worker_pool_tx, req).await
+            } else {
+                handle_config_api(opts.config_reloader.as_ref(), auth, req).await
+            };
+
+            if res_tx.send(Ok(res)).is_err() {
+                error!("request receiver dropped");
+            }
+
+            return;
+        }
+    }
+
+    let config = opts.runtime_config();
+    let mut conf = UserWorkerRuntimeOpts::default();
+
+    config.limits.apply(&mut conf);
+    conf.session_key = config
+        .sticky_session
+        .as_ref()
+        .and_then(|it| it.session_key(req.headers()));
+
+    let maybe_host = get_request_host(&req);
+    let resolved = match config.match_rule(maybe_host.as_deref(), req.uri().path()) {
+        Some(rule) => {
+            if let Some(Err((status, msg))) = rule.auth.as_ref().map(|it| it.check(&req)) {
+                if res_tx.send(Ok(emit_json_error(status, msg))).is_err() {
+                    error!("request receiver dropped");
+                }
+
+                return;
+            }
+
+            let path = rule.rewrite_path(req.uri().path()).to_string();
+
+            set_request_path(&mut req, &path);
+            rule.limits.apply(&mut conf);
+
+            if let Some(class) = rule.priority {
+                req.extensions_mut().insert(class);
+            }
+
+            Ok(Some(rule.service_path.clone()))
+        }
+
+        None => resolve_function_path(
+            opts.functions_dir.as_deref(),
+            &config.routes,
+            req.uri().path(),
+        ),
+    };
+
+    let res = match resolved {
+        Ok(Some(service_path)) => {
+            match forward_request(&opts, conf, service_path, &worker_pool_tx, req, conn_token).await
+            {
+                Ok(res) => res,
+                Err(err) => {
+                    error!("failed to route request to user worker: {err:#}");
+                    emit_worker_error(&err)
+                }
+            }
+        }
+
+        Ok(None) => emit_json_error(StatusCode::BAD_REQUEST, "missing function name in request"),
+        Err(_) => emit_json_error(StatusCode::NOT_FOUND, "function not found"),
+    };
+
+    if res_tx.send(Ok(res)).is_err() {
+        error!("request receiver dropped");
+    }
+}
+
+/// Creates a request router that maps the requests onto user workers directly,
+/// taking the place of the main worker: by the routes of the runtime config
+/// first, then `/:function_name/*` onto `<functions_dir>/<function_name>`.
+pub fn create_function_router(
+    opts: FunctionRouterOpts,
+    worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    termination_token: Option<TerminationToken>,
+) -> Result<mpsc::UnboundedSender<WorkerRequestMsg>, Error> {
+    if let Some(functions_dir) = opts.functions_dir.as_ref() {
+        if !functions_dir.is_dir() {
+            bail!(
+                "functions directory does not exist ({})",
+                functions_dir.display()
+            );
+        }
+    }
+
+    let opts = Arc::new(opts);
+    let (req_tx, mut req_rx) = mpsc::unbounded_channel::<WorkerRequestMsg>();
+
+    drop(tokio::spawn(async move {
+        let token = termination_token.as_ref();
+
+        loop {
+            tokio::select! {
+                _ = async {
+                    if let Some(token) = token {
+                        token.inbound.cancelled().await;
+                    } else {
+                        pending::<()>().await;
+                    }
+                } => {
+                    break;
+                }
+
+                msg = req_rx.recv() => {
+                    let Some(msg) = msg else {
+                        break;
+                    };
+
+                    tokio::spawn(route_request(opts.clone(), worker_pool_tx.clone(), msg));
+                }
+            }
+        }
+
+        if let Some(token) = token {
+            token.outbound.cancel();
+        }
+    }));
+
+    Ok(req_tx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_function_path_rejects_escapes() {
+        let dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir.path().join("hello")).unwrap();
+
+        let routes = BTreeMap::new();
+        let resolve = |path| resolve_function_path(Some(dir.path()), &routes, path);
+
+        assert_eq!(resolve("/hello/world"), Ok(Some(dir.path().join("hello"))));
+        assert_eq!(resolve("/"), Ok(None));
+        assert_eq!(resolve("/../etc"), Err(()));
+        assert_eq!(resolve("/%2e%2e/etc"), Err(()));
+        assert_eq!(resolve("/missing"), Err(()));
+    }
+
+    #[test]
+    fn test_resolve_function_path_prefers_routes() {
+        let dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir.path().join("hello")).unwrap();
+
+        let routes = BTreeMap::from([(
+            "hello".to_string(),
+            Route::Function(PathBuf::from("/srv/hello")),
+        )]);
+
+        assert_eq!(
+            resolve_function_path(Some(dir.path()), &routes, "/hello"),
+            Ok(Some(PathBuf::from("/srv/hello")))
+        );
+        assert_eq!(resolve_function_path(None, &routes, "/other"), Err(()));
+    }
+
+    #[test]
+    fn test_is_under() {
+        assert!(is_under("/_internal/workers", WORKERS_API_PATH));
+        assert!(is_under("/_internal/workers/", WORKERS_API_PATH));
+        assert!(is_under("/_internal/workers/abc/health", WORKERS_API_PATH));
+        assert!(!is_under("/_internal/workersX", WORKERS_API_PATH));
+        assert!(!is_under("/_internal/workers-evil/abc", WORKERS_API_PATH));
+    }
+
+    #[test]
+    fn test_set_request_path_keeps_query() {
+        let mut req = Request::builder()
+            .uri("http://localhost/hello/world?foo=bar")
+            .body(Body::empty())
+            .unwrap();
+
+        set_request_path(&mut req, "/world");
+
+        assert_eq!(req.uri().path(), "/world");
+        assert_eq!(req.uri().query(), Some("foo=bar"));
+    }
+
+    #[test]
+    fn test_load_service_env_reads_only_the_env_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        std::fs::write(
+            dir.path().join(".env"),
+            "# comment\nFOO=bar\nexport BAZ=\"qux quux\"\n\nINVALID\n",
+        )
+        .unwrap();
+
+        let env = load_service_env(dir.path());
+
+        assert_eq!(env.len(), 2);
+        assert_eq!(env.get("FOO").map(String::as_str), Some("bar"));
+        assert_eq!(env.get("BAZ").map(String::as_str), Some("qux quux"));
+        assert!(load_service_env(&dir.path().join("missing")).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_worker_error_does_not_leak_detail() {
+        let err = anyhow::anyhow!("failed to read /etc/secret").context(WorkerError::WorkerGone);
+        let res = emit_worker_error(&err);
+
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+
+        let body = hyper_v014::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains("worker is no longer available"));
+        assert!(!body.contains("/etc/secret"));
+
+        let res = emit_worker_error(&anyhow::anyhow!("failed to read /etc/secret"));
+
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = hyper_v014::body::to_bytes(res.into_body()).await.unwrap();
+
+        assert!(!String::from_utf8(body.to_vec())
+            .unwrap()
+            .contains("/etc/secret"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use event_worker::backlog::EVENTS_BACKLOG;
+use event_worker::events::{
+    EventMetadata, PoolStatsEvent, RuntimeStatsEvent, TokioRuntimeStats, WorkerEventWithMetadata,
+    WorkerEvents,
+};
+use sb_core::SharedMetricSource;
+use tokio::sync::mpsc;
+
+use crate::utils::send_event_if_event_worker_available;
+
+fn get_pool_stats(src: &SharedMetricSource, interval: Duration) -> PoolStatsEvent {
+    PoolStatsEvent {
+        interval_ms: interval.as_millis() as u64,
+        active_user_workers: src.active_user_workers(),
+        retired_user_workers: src.retired_user_workers(),
+        pending_boots: src.pending_user_worker_boots(),
+        queued_boots: src.queued_user_worker_boots(),
+        received_requests: src.received_requests(),
+        handled_requests: src.handled_requests(),
+        active_io: src.active_io(),
+    }
+}
+
+/// Returns the resident set size of the process.
+fn get_rss_bytes() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        // NOTE: The second field of `statm` is the resident set size, in
+        // pages.
+        let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+        let pages = statm.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+        // SAFETY: `sysconf` has no preconditions.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+
+        u64::try_from(page_size).ok().map(|it| it * pages)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+fn get_tokio_runtime_stats() -> Option<TokioRuntimeStats> {
+    #[cfg(tokio_unstable)]
+    {
+        let metrics = tokio::runtime::Handle::try_current().ok()?.metrics();
+
+        Some(TokioRuntimeStats {
+            workers: metrics.num_workers(),
+            alive_tasks: metrics.active_tasks_count(),
+            blocking_threads: metrics.num_blocking_threads(),
+            idle_blocking_threads: metrics.num_idle_blocking_threads(),
+            injection_queue_depth: metrics.injection_queue_depth(),
+            blocking_queue_depth: metrics.blocking_queue_depth(),
+        })
+    }
+
+    #[cfg(not(tokio_unstable))]
+    None
+}
+
+/// Sends a [`WorkerEvents::PoolStats`] and a [`WorkerEvents::RuntimeStats`]
+/// event once per interval, until the events worker is gone.
+pub async fn report_runtime_stats(
+    metric_src: SharedMetricSource,
+    interval: Duration,
+    events_msg_tx: mpsc::WeakUnboundedSender<WorkerEventWithMetadata>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+
+    // NOTE: The first tick completes immediately.
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        // NOTE: Only a weak sender is held here so that this task does not
+        // keep the events worker alive once the pool has been shut down.
+        let Some(events_msg_tx) = events_msg_tx.upgrade() else {
+            break;
+        };
+
+        send_event_if_event_worker_available(
+            Some(&events_msg_tx),
+            WorkerEvents::PoolStats(get_pool_stats(&metric_src, interval)),
+            EventMetadata::default(),
+        );
+
+        send_event_if_event_worker_available(
+            Some(&events_msg_tx),
+            WorkerEvents::RuntimeStats(RuntimeStatsEvent {
+                interval_ms: interval.as_millis() as u64,
+                rss_bytes: get_rss_bytes(),
+                events_backlog: EVENTS_BACKLOG.pending(),
+                events_dropped: EVENTS_BACKLOG.dropped(),
+                tokio: get_tokio_runtime_stats(),
+            }),
+            EventMetadata::default(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use sb_core::SharedMetricSource;
+
+    use super::get_pool_stats;
+
+    #[test]
+    fn test_runtime_stats() {
+        let src = SharedMetricSource::default();
+
+        src.incl_active_user_workers();
+        src.incl_pending_user_worker_boots();
+        src.incl_pending_user_worker_boots();
+        src.decl_pending_user_worker_boots();
+
+        let stats = get_pool_stats(&src, Duration::from_secs(10));
+
+        assert_eq!(stats.interval_ms, 10000);
+        assert_eq!(stats.active_user_workers, 1);
+        assert_eq!(stats.pending_boots, 1);
+
+        #[cfg(target_os = "linux")]
+        assert!(super::get_rss_bytes().unwrap() > 0);
+    }
+}
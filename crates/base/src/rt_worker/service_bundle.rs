@@ -0,0 +1,675 @@
+//! Services shipped as `.tar.gz` or `.zip` bundles, e.g. the artifacts of a
+//! CI pipeline, which are booted from a directory they are extracted into.
+//!
+//! A bundle is extracted once into a cache directory named after the SHA-256
+//! digest of its content, so booting the same bundle again reuses it, while a
+//! new build of a bundle under the same path or URL is extracted anew.
+//!
+//! A bundle fetched from a URL is kept in the cache along with its `ETag`, and
+//! is only downloaded again once the server says it changed. The cache keeps
+//! the most recently used bundles and drops the others.
+
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Error};
+use faster_hex::hex_string;
+use flate2::read::GzDecoder;
+use log::{debug, warn};
+use once_cell::sync::OnceCell;
+use reqwest_v011::header::{ETAG, IF_NONE_MATCH};
+use reqwest_v011::StatusCode;
+use ring::digest::{digest, SHA256};
+use sb_core::util::path::get_atomic_dir_path;
+use sb_graph::EszipPayloadKind;
+use sb_workers::context::WorkerContextInitOpts;
+use tar::EntryType;
+
+use super::bundle_signature::{bundle_verifier, find_signature, verify_bundle};
+use super::utils::write_body_bounded;
+
+static BUNDLE_CACHE_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+/// The directory of the cache the bundles fetched from URLs are kept in, one
+/// directory per URL.
+static DOWNLOADS_DIR: &str = "downloads";
+static DOWNLOADED_BUNDLE_FILE: &str = "bundle";
+static DOWNLOADED_ETAG_FILE: &str = "etag";
+
+/// Largest bundle that is fetched from a URL.
+static MAX_BUNDLE_SIZE: u64 = 256 * 1024 * 1024;
+static BUNDLE_FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many extracted and downloaded bundles the cache keeps each.
+static MAX_CACHED_BUNDLES: usize = 32;
+
+/// How old an extraction left behind half-done, e.g. by a crash, must be
+/// before it is removed, so that one still in progress is not.
+static STALE_EXTRACTION_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// How many bytes a bundle may extract to, so that a compression bomb can't
+/// fill up the disk of the host.
+static MAX_BUNDLE_EXTRACTED_SIZE: u64 = 512 * 1024 * 1024;
+
+static ENTRYPOINT_EXTS: &[&str] = &["ts", "tsx", "js", "mjs", "jsx"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BundleKind {
+    TarGz,
+    Zip,
+}
+
+impl BundleKind {
+    /// Returns the kind of the bundle at the given path or URL, if it is one.
+    pub fn detect(path: &str) -> Option<Self> {
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if path.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Sets the directory bundles are extracted into. It defaults to a directory
+/// in the temporary directory of the host.
+pub fn set_cache_dir(dir: PathBuf) {
+    if BUNDLE_CACHE_DIR.set(dir).is_err() {
+        debug!("the bundle cache directory is already set");
+    }
+}
+
+fn cache_dir() -> &'static Path {
+    BUNDLE_CACHE_DIR.get_or_init(|| std::env::temp_dir().join("edge-runtime").join("bundles"))
+}
+
+/// Returns the path a service is booted from: the directory a bundle is
+/// extracted into, or the given path as is if it is not a bundle.
+pub async fn resolve_service_path(service_path: &Path) -> Result<PathBuf, Error> {
+    let Some(path) = service_path.to_str() else {
+        return Ok(service_path.to_path_buf());
+    };
+
+    let Some(kind) = BundleKind::detect(path) else {
+        return Ok(service_path.to_path_buf());
+    };
+
+    let cache_dir = cache_dir().to_path_buf();
+    let data = if path.starts_with("http://") || path.starts_with("https://") {
+        fetch_bundle_cached(path, &cache_dir)
+            .await
+            .with_context(|| format!("failed to fetch bundle: {path}"))?
+    } else {
+        tokio::fs::read(service_path)
+            .await
+            .with_context(|| format!("failed to read bundle: {path}"))?
+    };
+
+    verify_bundle(path, &data).await?;
+
+    let path = path.to_string();
+
+    tokio::task::spawn_blocking(move || extract_bundle_cached(kind, &data, &cache_dir))
+        .await?
+        .with_context(|| format!("invalid bundle: {path}"))
+}
+
+/// Loads a user worker whose service path is a local `.eszip` from it, like
+/// the main worker is, along with the signature next to it unless one was
+/// given.
+pub async fn load_local_eszip(opts: &mut WorkerContextInitOpts) -> Result<(), Error> {
+    if opts.maybe_eszip.is_some()
+        || opts
+            .service_path
+            .extension()
+            .map_or(true, |it| it != "eszip")
+    {
+        return Ok(());
+    }
+
+    let path = opts.service_path.to_string_lossy().to_string();
+    let data = tokio::fs::read(&opts.service_path)
+        .await
+        .with_context(|| format!("failed to read eszip: {path}"))?;
+
+    if let Some(conf) = opts.conf.as_user_worker_mut() {
+        if conf.eszip_signature.is_none() && bundle_verifier().is_some() {
+            conf.eszip_signature = find_signature(&path).await?;
+        }
+    }
+
+    opts.service_path = opts
+        .service_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    opts.maybe_eszip = Some(EszipPayloadKind::VecKind(data));
+
+    Ok(())
+}
+
+/// Fetches the bundle at the given URL, or reads the copy kept in the cache if
+/// the server answers that it has not changed since.
+async fn fetch_bundle_cached(url: &str, cache_dir: &Path) -> Result<Vec<u8>, Error> {
+    let downloads_dir = cache_dir.join(DOWNLOADS_DIR);
+    let entry_dir = downloads_dir.join(hex_string(digest(&SHA256, url.as_bytes()).as_ref()));
+    let bundle_path = entry_dir.join(DOWNLOADED_BUNDLE_FILE);
+    let etag_path = entry_dir.join(DOWNLOADED_ETAG_FILE);
+
+    let maybe_etag = match tokio::fs::read_to_string(&etag_path).await {
+        Ok(it) if tokio::fs::metadata(&bundle_path).await.is_ok() => Some(it),
+        _ => None,
+    };
+
+    let mut req = reqwest_v011::Client::new().get(url);
+
+    if let Some(etag) = maybe_etag.as_deref() {
+        req = req.header(IF_NONE_MATCH, etag);
+    }
+
+    let mut data = vec![];
+    let fetched = tokio::time::timeout(BUNDLE_FETCH_TIMEOUT, async {
+        let res = req.send().await?;
+
+        if res.status() == StatusCode::NOT_MODIFIED && maybe_etag.is_some() {
+            return Ok(None);
+        }
+
+        let res = res.error_for_status()?;
+        let maybe_etag = res
+            .headers()
+            .get(ETAG)
+            .and_then(|it| it.to_str().ok())
+            .map(str::to_string);
+
+        write_body_bounded(res, MAX_BUNDLE_SIZE, &mut data).await?;
+        Ok::<_, Error>(Some(maybe_etag))
+    })
+    .await
+    .map_err(|_| anyhow!("timed out"))
+    .and_then(|it| it)?;
+
+    let url = url.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        let data = match fetched {
+            Some(maybe_etag) => {
+                if let Err(err) = store_download(&entry_dir, &data, maybe_etag.as_deref()) {
+                    warn!("failed to keep bundle in cache: {url}: {err:#}");
+                }
+
+                data
+            }
+
+            None => {
+                debug!("bundle not modified: {url}");
+                fs::read(&bundle_path)?
+            }
+        };
+
+        touch(&entry_dir);
+
+        if let Err(err) = prune_cache_dir(&downloads_dir, MAX_CACHED_BUNDLES) {
+            warn!("failed to prune bundle cache: {err:#}");
+        }
+
+        Ok::<_, Error>(data)
+    })
+    .await?
+}
+
+/// Keeps a downloaded bundle along with its `ETag`. A bundle without one is
+/// not kept, since there is no way to tell whether it changed.
+fn store_download(entry_dir: &Path, data: &[u8], maybe_etag: Option<&str>) -> Result<(), Error> {
+    let etag_path = entry_dir.join(DOWNLOADED_ETAG_FILE);
+
+    // NOTE: The `ETag` goes first and last, so that it never describes a
+    // bundle other than the one next to it.
+    match fs::remove_file(&etag_path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    let Some(etag) = maybe_etag else {
+        let _ = fs::remove_dir_all(entry_dir);
+        return Ok(());
+    };
+
+    let bundle_path = entry_dir.join(DOWNLOADED_BUNDLE_FILE);
+    let temp_path = get_atomic_dir_path(&bundle_path);
+
+    fs::create_dir_all(entry_dir)?;
+    fs::write(&temp_path, data)?;
+
+    if let Err(err) = fs::rename(&temp_path, &bundle_path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err.into());
+    }
+
+    fs::write(etag_path, etag)?;
+
+    Ok(())
+}
+
+/// Marks an entry of the cache as just used.
+fn touch(path: &Path) {
+    if let Err(err) = fs::File::open(path).and_then(|it| it.set_modified(SystemTime::now())) {
+        debug!("failed to touch {}: {err}", path.display());
+    }
+}
+
+/// Removes the entries of a cache directory but the `keep` most recently used
+/// ones, along with extractions left behind half-done.
+fn prune_cache_dir(dir: &Path, keep: usize) -> Result<(), Error> {
+    let mut entries = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+
+        if name == DOWNLOADS_DIR {
+            continue;
+        }
+
+        let modified = entry.metadata()?.modified()?;
+
+        if name.to_string_lossy().starts_with('.') {
+            if modified.elapsed().unwrap_or_default() > STALE_EXTRACTION_AGE {
+                remove_entry(&entry.path());
+            }
+
+            continue;
+        }
+
+        entries.push((modified, entry.path()));
+    }
+
+    entries.sort_by(|a, b| b.0.cmp(&a.0));
+
+    for (_, path) in entries.into_iter().skip(keep) {
+        debug!("pruning bundle cache entry: {}", path.display());
+        remove_entry(&path);
+    }
+
+    Ok(())
+}
+
+fn remove_entry(path: &Path) {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    if let Err(err) = result {
+        debug!("failed to remove {}: {err}", path.display());
+    }
+}
+
+fn extract_bundle_cached(
+    kind: BundleKind,
+    data: &[u8],
+    cache_dir: &Path,
+) -> Result<PathBuf, Error> {
+    let output_dir = cache_dir.join(hex_string(digest(&SHA256, data).as_ref()));
+
+    if !output_dir.is_dir() {
+        fs::create_dir_all(cache_dir).with_context(|| {
+            format!(
+                "can't create bundle cache directory {}",
+                cache_dir.display()
+            )
+        })?;
+
+        // NOTE: The bundle is extracted into a sibling directory first, so
+        // that a directory in the cache is never seen half-extracted by
+        // another worker or process.
+        let temp_dir = get_atomic_dir_path(&output_dir);
+        let result = match kind {
+            BundleKind::TarGz => extract_tar_gz(data, &temp_dir),
+            BundleKind::Zip => extract_zip(data, &temp_dir),
+        };
+
+        if let Err(err) = result.and_then(|_| find_service_root(&temp_dir).map(drop)) {
+            let _ = fs::remove_dir_all(&temp_dir);
+            return Err(err);
+        }
+
+        if let Err(err) = fs::rename(&temp_dir, &output_dir) {
+            let _ = fs::remove_dir_all(&temp_dir);
+
+            // NOTE: Another worker extracted the same bundle meanwhile.
+            if !output_dir.is_dir() {
+                return Err(err.into());
+            }
+        }
+    }
+
+    touch(&output_dir);
+
+    if let Err(err) = prune_cache_dir(cache_dir, MAX_CACHED_BUNDLES) {
+        warn!("failed to prune bundle cache: {err:#}");
+    }
+
+    find_service_root(&output_dir)
+}
+
+/// Returns the directory of the entrypoint of an extracted bundle, which is
+/// either its root or, as archivers tend to produce, its only directory.
+fn find_service_root(dir: &Path) -> Result<PathBuf, Error> {
+    if has_entrypoint(dir) {
+        return Ok(dir.to_path_buf());
+    }
+
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+
+    if entries.len() == 1 && entries[0].file_type()?.is_dir() {
+        let nested = entries.remove(0).path();
+
+        if has_entrypoint(&nested) {
+            return Ok(nested);
+        }
+    }
+
+    Err(anyhow!(
+        "could not find an entrypoint (index.{{{}}})",
+        ENTRYPOINT_EXTS.join(",")
+    ))
+}
+
+fn has_entrypoint(dir: &Path) -> bool {
+    ENTRYPOINT_EXTS
+        .iter()
+        .any(|ext| dir.join(format!("index.{}", ext)).is_file())
+}
+
+/// Returns the path of an entry of an archive relative to the directory it is
+/// extracted into, refusing any that would escape it.
+fn sanitize_entry_path(path: &Path) -> Result<PathBuf, Error> {
+    let mut sanitized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(it) => sanitized.push(it),
+            Component::CurDir => {}
+            _ => bail!("entry escapes the bundle: {}", path.display()),
+        }
+    }
+
+    Ok(sanitized)
+}
+
+fn copy_limited(
+    reader: &mut impl Read,
+    path: &Path,
+    extracted_size: &mut u64,
+) -> Result<(), Error> {
+    let mut file = fs::File::create(path)?;
+    let copied = io::copy(
+        &mut reader.take(MAX_BUNDLE_EXTRACTED_SIZE - *extracted_size + 1),
+        &mut file,
+    )?;
+
+    *extracted_size += copied;
+
+    if *extracted_size > MAX_BUNDLE_EXTRACTED_SIZE {
+        bail!(
+            "bundle extracts to more than {} bytes",
+            MAX_BUNDLE_EXTRACTED_SIZE
+        );
+    }
+
+    Ok(())
+}
+
+fn extract_tar_gz(data: &[u8], output_dir: &Path) -> Result<(), Error> {
+    let mut archive = tar::Archive::new(GzDecoder::new(data));
+    let mut extracted_size = 0;
+
+    fs::create_dir_all(output_dir)?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = output_dir.join(sanitize_entry_path(&entry.path()?)?);
+
+        // NOTE: Links are skipped, as they could point out of the bundle.
+        match entry.header().entry_type() {
+            EntryType::Directory => fs::create_dir_all(&path)?,
+            EntryType::Regular => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                copy_limited(&mut entry, &path, &mut extracted_size)?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_zip(data: &[u8], output_dir: &Path) -> Result<(), Error> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+    let mut extracted_size = 0;
+
+    fs::create_dir_all(output_dir)?;
+
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        let path = output_dir.join(sanitize_entry_path(Path::new(file.name()))?);
+
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+        } else if file.is_file() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            copy_limited(&mut file, &path, &mut extracted_size)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::fs;
+    use std::io::{Cursor, Write};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use hyper_v014::service::{make_service_fn, service_fn};
+    use hyper_v014::{Body, Response, Server, StatusCode};
+
+    use super::{
+        extract_bundle_cached, fetch_bundle_cached, prune_cache_dir, sanitize_entry_path,
+        BundleKind, STALE_EXTRACTION_AGE,
+    };
+
+    fn tar_gz(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (path, content) in files {
+            writer
+                .start_file(*path, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_detect_bundle_kind() {
+        assert_eq!(
+            BundleKind::detect("./hello.tar.gz"),
+            Some(BundleKind::TarGz)
+        );
+        assert_eq!(
+            BundleKind::detect("https://ci.example.com/hello.zip?token=1"),
+            Some(BundleKind::Zip)
+        );
+        assert_eq!(BundleKind::detect("./hello"), None);
+        assert_eq!(BundleKind::detect("./hello.eszip"), None);
+    }
+
+    #[test]
+    fn test_extract_bundle() {
+        let cache = tempfile::tempdir().unwrap();
+        let bundle = tar_gz(&[
+            ("hello/index.ts", "Deno.serve(() => new Response());"),
+            ("hello/deno.json", "{}"),
+        ]);
+
+        let root = extract_bundle_cached(BundleKind::TarGz, &bundle, cache.path()).unwrap();
+
+        assert!(root.ends_with("hello"));
+        assert!(root.join("index.ts").is_file());
+
+        // NOTE: The same bundle is extracted only once.
+        assert_eq!(
+            extract_bundle_cached(BundleKind::TarGz, &bundle, cache.path()).unwrap(),
+            root
+        );
+
+        let root = extract_bundle_cached(
+            BundleKind::Zip,
+            &zip(&[("index.js", "export {};")]),
+            cache.path(),
+        )
+        .unwrap();
+
+        assert!(root.join("index.js").is_file());
+
+        assert!(
+            extract_bundle_cached(BundleKind::Zip, &zip(&[("README.md", "")]), cache.path())
+                .is_err()
+        );
+
+        assert!(sanitize_entry_path(Path::new("../index.ts")).is_err());
+        assert!(sanitize_entry_path(Path::new("/etc/passwd")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_bundle_cached() {
+        static BUNDLE: &[u8] = b"bundle";
+
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let make_svc = make_service_fn({
+            let fetched = fetched.clone();
+
+            move |_| {
+                let fetched = fetched.clone();
+
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: hyper_v014::Request<Body>| {
+                        let fetched = fetched.clone();
+
+                        async move {
+                            if req
+                                .headers()
+                                .get("if-none-match")
+                                .is_some_and(|it| it == "\"v1\"")
+                            {
+                                return Ok::<_, Infallible>(
+                                    Response::builder()
+                                        .status(StatusCode::NOT_MODIFIED)
+                                        .body(Body::empty())
+                                        .unwrap(),
+                                );
+                            }
+
+                            fetched.fetch_add(1, Ordering::SeqCst);
+                            Ok(Response::builder()
+                                .header("etag", "\"v1\"")
+                                .body(Body::from(BUNDLE))
+                                .unwrap())
+                        }
+                    }))
+                }
+            }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}/hello.tar.gz", server.local_addr());
+
+        drop(tokio::spawn(server));
+
+        let cache = tempfile::tempdir().unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(
+                fetch_bundle_cached(&url, cache.path()).await.unwrap(),
+                BUNDLE
+            );
+        }
+
+        // NOTE: The second boot reads the bundle kept in the cache.
+        assert_eq!(fetched.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_prune_cache_dir() {
+        let cache = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        let entries = [
+            ("a", now - Duration::from_secs(30)),
+            ("b", now - Duration::from_secs(20)),
+            ("c", now - Duration::from_secs(10)),
+            (".c_0000", now),
+            (".a_0000", now - STALE_EXTRACTION_AGE * 2),
+        ];
+
+        fs::create_dir(cache.path().join("downloads")).unwrap();
+
+        for (name, modified) in entries {
+            let path = cache.path().join(name);
+
+            fs::create_dir(&path).unwrap();
+            fs::File::open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        prune_cache_dir(cache.path(), 2).unwrap();
+
+        let mut names = fs::read_dir(cache.path())
+            .unwrap()
+            .map(|it| it.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+
+        names.sort();
+        assert_eq!(names, [".c_0000", "b", "c", "downloads"]);
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use event_worker::events::{
+    EventMetadata, HistogramSnapshot, ServiceStatsEvent, WorkerEventWithMetadata, WorkerEvents,
+};
+use futures_util::StreamExt;
+use hyper_v014::{Body, Request, Response};
+use tokio::sync::mpsc;
+
+use crate::utils::send_event_if_event_worker_available;
+
+/// Bucket bounds (in bytes) for request and response sizes.
+static SIZE_BOUNDS: &[u64] = &[
+    1 << 10,
+    4 << 10,
+    16 << 10,
+    64 << 10,
+    256 << 10,
+    1 << 20,
+    4 << 20,
+    16 << 20,
+];
+
+/// Bucket bounds (in milliseconds) for latencies.
+static LATENCY_BOUNDS_MS: &[u64] = &[5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [u64],
+    counts: Vec<u64>,
+    count: u64,
+    sum: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            count: 0,
+            sum: 0,
+        }
+    }
+
+    fn observe(&mut self, value: u64) {
+        let idx = self.bounds.partition_point(|it| *it < value);
+
+        self.counts[idx] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            bounds: self.bounds.to_vec(),
+            counts: self.counts.clone(),
+            count: self.count,
+            sum: self.sum,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ServiceStatsEntry {
+    request_count: u64,
+    error_count: u64,
+    request_size: Histogram,
+    response_size: Histogram,
+    latency_ms: Histogram,
+}
+
+impl Default for ServiceStatsEntry {
+    fn default() -> Self {
+        Self {
+            request_count: 0,
+            error_count: 0,
+            request_size: Histogram::new(SIZE_BOUNDS),
+            response_size: Histogram::new(SIZE_BOUNDS),
+            latency_ms: Histogram::new(LATENCY_BOUNDS_MS),
+        }
+    }
+}
+
+/// Aggregates the requests sent to user workers per service, so that they can
+/// be reported to the events worker periodically instead of one by one.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceStats {
+    services: Arc<Mutex<HashMap<String, ServiceStatsEntry>>>,
+}
+
+impl ServiceStats {
+    /// Records a request along with the number of bytes of its body that
+    /// were read. `response_size` is `None` if the request failed, in which
+    /// case it is counted as an error.
+    pub fn record(
+        &self,
+        service_path: &str,
+        request_size: u64,
+        response_size: Option<u64>,
+        latency: Duration,
+    ) {
+        let mut services = self.services.lock().unwrap();
+        let entry = services.entry(service_path.to_string()).or_default();
+
+        entry.request_count += 1;
+        entry.request_size.observe(request_size);
+        entry
+            .latency_ms
+            .observe(latency.as_millis().try_into().unwrap_or(u64::MAX));
+
+        match response_size {
+            Some(size) => entry.response_size.observe(size),
+            None => entry.error_count += 1,
+        }
+    }
+
+    /// Takes the statistics collected so far, resetting them.
+    pub fn drain(&self, interval: Duration) -> Vec<(String, ServiceStatsEvent)> {
+        let services = std::mem::take(&mut *self.services.lock().unwrap());
+        let interval_ms = interval.as_millis().try_into().unwrap_or(u64::MAX);
+
+        services
+            .into_iter()
+            .map(|(service_path, entry)| {
+                (
+                    service_path,
+                    ServiceStatsEvent {
+                        interval_ms,
+                        request_count: entry.request_count,
+                        error_count: entry.error_count,
+                        request_size: entry.request_size.snapshot(),
+                        response_size: entry.response_size.snapshot(),
+                        latency_ms: entry.latency_ms.snapshot(),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+/// A request whose statistics are yet to be recorded.
+///
+/// The bodies are counted as they are streamed, since their size is rarely
+/// known in advance, and the request is only recorded once its response body
+/// is done, or dropped.
+pub(crate) struct RequestStats {
+    stats: ServiceStats,
+    service_path: String,
+    request_size: Arc<AtomicU64>,
+    started_at: Instant,
+}
+
+impl RequestStats {
+    /// Returns the request to be dispatched in place of the given one.
+    pub(crate) fn start(
+        stats: ServiceStats,
+        service_path: String,
+        req: Request<Body>,
+    ) -> (Request<Body>, Self) {
+        let request_size = Arc::<AtomicU64>::default();
+        let (parts, body) = req.into_parts();
+        let body = Body::wrap_stream(body.map({
+            let request_size = request_size.clone();
+
+            move |it| {
+                if let Ok(chunk) = it.as_ref() {
+                    request_size.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                }
+
+                it
+            }
+        }));
+
+        (
+            Request::from_parts(parts, body),
+            Self {
+                stats,
+                service_path,
+                request_size,
+                started_at: Instant::now(),
+            },
+        )
+    }
+
+    /// Records the request once the body of its response is done, or right
+    /// away if it failed.
+    pub(crate) fn finish(self, result: Result<&mut Response<Body>, &Error>) {
+        let res = match result {
+            Ok(res) => res,
+            Err(_) => return self.record(None),
+        };
+
+        let body = std::mem::take(res.body_mut());
+        let mut guard = scopeguard::guard((self, 0u64), |(it, response_size)| {
+            it.record(Some(response_size))
+        });
+
+        *res.body_mut() = Body::wrap_stream(body.map(move |it| {
+            if let Ok(chunk) = it.as_ref() {
+                guard.1 += chunk.len() as u64;
+            }
+
+            it
+        }));
+    }
+
+    fn record(self, response_size: Option<u64>) {
+        self.stats.record(
+            &self.service_path,
+            self.request_size.load(Ordering::Relaxed),
+            response_size,
+            self.started_at.elapsed(),
+        );
+    }
+}
+
+/// Sends a [`WorkerEvents::ServiceStats`] event for every service that has
+/// received requests, once per interval, until the events worker is gone.
+pub async fn report_service_stats(
+    stats: ServiceStats,
+    interval: Duration,
+    events_msg_tx: mpsc::WeakUnboundedSender<WorkerEventWithMetadata>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+
+    // NOTE: The first tick completes immediately.
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        // NOTE: Only a weak sender is held here so that this task does not
+        // keep the events worker alive once the pool has been shut down.
+        let Some(events_msg_tx) = events_msg_tx.upgrade() else {
+            break;
+        };
+
+        for (service_path, event) in stats.drain(interval) {
+            send_event_if_event_worker_available(
+                Some(&events_msg_tx),
+                WorkerEvents::ServiceStats(event),
+                EventMetadata {
+                    service_path: Some(service_path),
+                    execution_id: None,
+                    worker_id: None,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+    use futures_util::StreamExt;
+    use hyper_v014::{Body, Request, Response};
+
+    use super::{Histogram, RequestStats, ServiceStats, LATENCY_BOUNDS_MS};
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = Histogram::new(LATENCY_BOUNDS_MS);
+
+        histogram.observe(0);
+        histogram.observe(5);
+        histogram.observe(6);
+        histogram.observe(u64::MAX);
+
+        let snapshot = histogram.snapshot();
+
+        assert_eq!(snapshot.count, 4);
+        assert_eq!(snapshot.sum, u64::MAX);
+        assert_eq!(snapshot.counts[0], 2);
+        assert_eq!(snapshot.counts[1], 1);
+        assert_eq!(snapshot.counts[LATENCY_BOUNDS_MS.len()], 1);
+    }
+
+    #[test]
+    fn test_drain_resets_stats() {
+        let stats = ServiceStats::default();
+
+        stats.record("./foo", 10, None, Duration::from_millis(3));
+        stats.record("./foo", 0, Some(2048), Duration::from_millis(30));
+        stats.record("./bar", 0, Some(0), Duration::from_millis(3));
+
+        let mut drained = stats.drain(Duration::from_secs(1));
+
+        drained.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(drained.len(), 2);
+        assert_eq!(drained[1].0, "./foo");
+        assert_eq!(drained[1].1.request_count, 2);
+        assert_eq!(drained[1].1.error_count, 1);
+        assert_eq!(drained[1].1.request_size.count, 2);
+        assert_eq!(drained[1].1.response_size.count, 1);
+        assert_eq!(drained[1].1.latency_ms.count, 2);
+        assert!(stats.drain(Duration::from_secs(1)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_request_stats_count_streamed_bodies() {
+        let stats = ServiceStats::default();
+        let chunks =
+            || futures_util::stream::iter(["foo", "bar", "baz"].map(Ok::<_, std::io::Error>));
+
+        let (req, request_stats) = RequestStats::start(
+            stats.clone(),
+            "./foo".to_string(),
+            Request::new(Body::wrap_stream(chunks())),
+        );
+
+        assert_eq!(req.into_body().count().await, 3);
+
+        let mut res = Response::new(Body::wrap_stream(chunks().take(2)));
+
+        request_stats.finish(Ok(&mut res));
+
+        // NOTE: The request is only recorded once its response body is done.
+        assert!(stats.drain(Duration::from_secs(1)).is_empty());
+        assert_eq!(res.into_body().count().await, 2);
+
+        let (req, request_stats) = RequestStats::start(
+            stats.clone(),
+            "./foo".to_string(),
+            Request::new(Body::empty()),
+        );
+
+        drop(req);
+        request_stats.finish(Err(&anyhow!("worker gone")));
+
+        let drained = stats.drain(Duration::from_secs(1));
+
+        assert_eq!(drained.len(), 1);
+        assert_eq!(drained[0].1.request_count, 2);
+        assert_eq!(drained[0].1.error_count, 1);
+        assert_eq!(drained[0].1.request_size.sum, 9);
+        assert_eq!(drained[0].1.request_size.count, 2);
+        assert_eq!(drained[0].1.response_size.sum, 6);
+        assert_eq!(drained[0].1.response_size.count, 1);
+        assert_eq!(drained[0].1.latency_ms.count, 2);
+    }
+}
@@ -0,0 +1,412 @@
+//! Admission of the requests that arrive while the main worker, or the
+//! service they are routed to, is still booting, for servers that start
+//! listening before it is ready.
+//!
+//! Instead of failing, such requests are held in a bounded queue until the
+//! worker is up, so that a rolling restart does not show up as a burst of
+//! errors to the clients. A request is only failed if the queue is full, or
+//! if the worker takes longer than the timeout to boot. A request whose
+//! client has gone meanwhile is dropped from the queue.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Error;
+use futures_util::future::BoxFuture;
+use futures_util::stream::FuturesUnordered;
+use futures_util::{FutureExt, StreamExt};
+use log::{debug, error};
+use sb_workers::context::WorkerRequestMsg;
+use sb_workers::errors::{emit_worker_error, WorkerError};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, Instant};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Copy)]
+pub struct StartupGateOpts {
+    /// How many requests may be held at once.
+    pub queue_size: usize,
+    /// How long a request may be held before it is failed.
+    pub timeout: Duration,
+}
+
+impl Default for StartupGateOpts {
+    fn default() -> Self {
+        Self {
+            queue_size: 1024,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+fn is_cancelled(msg: &WorkerRequestMsg) -> bool {
+    msg.conn_token
+        .as_ref()
+        .is_some_and(CancellationToken::is_cancelled)
+}
+
+fn reject(msg: WorkerRequestMsg) {
+    let _ = msg
+        .res_tx
+        .send(Ok(emit_worker_error(&WorkerError::Starting.into())));
+}
+
+/// Returns a sender for the requests of the main worker booted by `boot`,
+/// which holds them until the boot is done, along with a future resolving
+/// once it is.
+pub fn gate_main_worker<F>(
+    boot: F,
+    opts: StartupGateOpts,
+) -> (
+    mpsc::UnboundedSender<WorkerRequestMsg>,
+    BoxFuture<'static, Result<(), Error>>,
+)
+where
+    F: Future<Output = Result<mpsc::UnboundedSender<WorkerRequestMsg>, Error>> + Send + 'static,
+{
+    let (req_tx, mut req_rx) = mpsc::unbounded_channel::<WorkerRequestMsg>();
+    let (boot_tx, boot_rx) = oneshot::channel::<Result<(), Error>>();
+
+    drop(tokio::spawn(async move {
+        let mut boot = boot.boxed();
+        let mut queue = VecDeque::<(u64, WorkerRequestMsg, Instant)>::new();
+        let mut cancellations = FuturesUnordered::<BoxFuture<'static, u64>>::new();
+        let mut next_id = 0u64;
+
+        let worker_req_tx = loop {
+            let deadline = queue.front().map(|(_, _, at)| *at + opts.timeout);
+
+            tokio::select! {
+                res = &mut boot => match res {
+                    Ok(it) => break it,
+                    Err(err) => {
+                        queue.drain(..).for_each(|(_, msg, _)| reject(msg));
+                        let _ = boot_tx.send(Err(err));
+                        return;
+                    }
+                },
+
+                msg = req_rx.recv() => {
+                    let Some(msg) = msg else {
+                        return;
+                    };
+
+                    if queue.len() >= opts.queue_size {
+                        debug!("request rejected: the startup queue is full");
+                        reject(msg);
+                        continue;
+                    }
+
+                    let id = next_id;
+
+                    next_id += 1;
+
+                    if let Some(token) = msg.conn_token.clone() {
+                        cancellations.push(async move {
+                            token.cancelled().await;
+                            id
+                        }.boxed());
+                    }
+
+                    queue.push_back((id, msg, Instant::now()));
+                }
+
+                Some(id) = cancellations.next(), if !cancellations.is_empty() => {
+                    // NOTE: The response sender is dropped along with the
+                    // message, as there is no one to send the response to.
+                    debug!("held request dropped: the client has disconnected");
+                    queue.retain(|(held_id, _, _)| *held_id != id);
+                }
+
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    let now = Instant::now();
+
+                    while let Some((_, _, at)) = queue.front() {
+                        if *at + opts.timeout > now {
+                            break;
+                        }
+
+                        debug!("request rejected: the main worker did not boot in time");
+                        reject(queue.pop_front().unwrap().1);
+                    }
+                }
+            }
+        };
+
+        let _ = boot_tx.send(Ok(()));
+
+        // NOTE: If the worker is gone, a message is dropped along with its
+        // response sender, so the caller sees the request fail.
+        for (_, msg, _) in queue.drain(..) {
+            if !is_cancelled(&msg) {
+                let _ = worker_req_tx.send(msg);
+            }
+        }
+
+        while let Some(msg) = req_rx.recv().await {
+            let _ = worker_req_tx.send(msg);
+        }
+    }));
+
+    let boot = async move {
+        match boot_rx.await {
+            Ok(res) => res,
+            Err(_) => {
+                error!("the startup gate of the main worker is gone");
+                Ok(())
+            }
+        }
+    };
+
+    (req_tx, boot.boxed())
+}
+
+/// Holds the requests of the services the router boots a worker for, like
+/// [`gate_main_worker`] does for the main worker, with a queue of its own for
+/// each service.
+#[derive(Debug, Clone)]
+pub struct ServiceStartupGate {
+    opts: StartupGateOpts,
+    held: Arc<Mutex<HashMap<PathBuf, usize>>>,
+}
+
+impl ServiceStartupGate {
+    pub fn new(opts: StartupGateOpts) -> Self {
+        Self {
+            opts,
+            held: Arc::default(),
+        }
+    }
+
+    /// Waits for `boot`, which creates a worker for the service or returns
+    /// one of its workers that is up already, holding the request meanwhile.
+    pub async fn admit<F, T>(
+        &self,
+        service_path: &Path,
+        conn_token: Option<&CancellationToken>,
+        boot: F,
+    ) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        {
+            let mut held = self.held.lock().unwrap();
+            let count = held.entry(service_path.to_path_buf()).or_default();
+
+            if *count >= self.opts.queue_size {
+                debug!(
+                    "request rejected: the startup queue of {} is full",
+                    service_path.display()
+                );
+                return Err(WorkerError::Starting.into());
+            }
+
+            *count += 1;
+        }
+
+        let _release = scopeguard::guard((), |_| {
+            let mut held = self.held.lock().unwrap();
+
+            if let Some(count) = held.get_mut(service_path) {
+                *count -= 1;
+
+                if *count == 0 {
+                    held.remove(service_path);
+                }
+            }
+        });
+
+        let cancelled = async {
+            match conn_token {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            res = boot => res,
+            _ = cancelled => Err(WorkerError::ClientDisconnected.into()),
+            _ = tokio::time::sleep(self.opts.timeout) => {
+                debug!(
+                    "request rejected: {} did not boot in time",
+                    service_path.display()
+                );
+                Err(WorkerError::Starting.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use std::path::Path;
+
+    use hyper_v014::{Body, Request, StatusCode};
+    use sb_workers::context::WorkerRequestMsg;
+    use sb_workers::errors::WorkerError;
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_util::sync::CancellationToken;
+
+    use super::{gate_main_worker, ServiceStartupGate, StartupGateOpts};
+
+    fn request_with(
+        conn_token: Option<CancellationToken>,
+    ) -> (
+        WorkerRequestMsg,
+        oneshot::Receiver<Result<hyper_v014::Response<Body>, hyper_v014::Error>>,
+    ) {
+        let (res_tx, res_rx) = oneshot::channel();
+
+        (
+            WorkerRequestMsg {
+                req: Request::new(Body::empty()),
+                res_tx,
+                conn_token,
+            },
+            res_rx,
+        )
+    }
+
+    fn request() -> (
+        WorkerRequestMsg,
+        oneshot::Receiver<Result<hyper_v014::Response<Body>, hyper_v014::Error>>,
+    ) {
+        request_with(None)
+    }
+
+    #[tokio::test]
+    async fn test_startup_gate() {
+        let (booted_tx, booted_rx) = oneshot::channel();
+        let (worker_req_tx, mut worker_req_rx) = mpsc::unbounded_channel();
+        let (req_tx, boot) = gate_main_worker(
+            async move {
+                booted_rx.await.unwrap();
+                Ok(worker_req_tx)
+            },
+            StartupGateOpts {
+                queue_size: 1,
+                timeout: Duration::from_secs(30),
+            },
+        );
+
+        let (held, _held_res_rx) = request();
+        let (overflown, overflown_res_rx) = request();
+
+        req_tx.send(held).unwrap();
+        req_tx.send(overflown).unwrap();
+
+        assert_eq!(
+            overflown_res_rx.await.unwrap().unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        booted_tx.send(()).unwrap();
+        boot.await.unwrap();
+
+        assert!(worker_req_rx.recv().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_startup_gate_drops_cancelled_requests() {
+        let (booted_tx, booted_rx) = oneshot::channel();
+        let (worker_req_tx, mut worker_req_rx) = mpsc::unbounded_channel();
+        let (req_tx, boot) = gate_main_worker(
+            async move {
+                booted_rx.await.unwrap();
+                Ok(worker_req_tx)
+            },
+            StartupGateOpts {
+                queue_size: 1,
+                timeout: Duration::from_secs(30),
+            },
+        );
+
+        let conn_token = CancellationToken::new();
+        let (cancelled, cancelled_res_rx) = request_with(Some(conn_token.clone()));
+
+        req_tx.send(cancelled).unwrap();
+        conn_token.cancel();
+
+        // NOTE: The request is dropped from the queue, which makes room for
+        // the next one.
+        assert!(cancelled_res_rx.await.is_err());
+
+        let (held, _held_res_rx) = request();
+
+        req_tx.send(held).unwrap();
+        booted_tx.send(()).unwrap();
+        boot.await.unwrap();
+
+        assert!(worker_req_rx.recv().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_service_startup_gate() {
+        let gate = ServiceStartupGate::new(StartupGateOpts {
+            queue_size: 1,
+            timeout: Duration::from_millis(100),
+        });
+        let service_path = Path::new("./hello");
+        let (booted_tx, booted_rx) = oneshot::channel::<()>();
+
+        let held = tokio::spawn({
+            let gate = gate.clone();
+
+            async move {
+                gate.admit(service_path, None, async move {
+                    booted_rx.await?;
+                    Ok(())
+                })
+                .await
+            }
+        });
+
+        tokio::task::yield_now().await;
+
+        let err = gate
+            .admit(service_path, None, async { Ok(()) })
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<WorkerError>(),
+            Some(WorkerError::Starting)
+        ));
+
+        booted_tx.send(()).unwrap();
+        held.await.unwrap().unwrap();
+
+        let conn_token = CancellationToken::new();
+
+        conn_token.cancel();
+
+        let err = gate
+            .admit(
+                service_path,
+                Some(&conn_token),
+                std::future::pending::<Result<(), _>>(),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<WorkerError>(),
+            Some(WorkerError::ClientDisconnected)
+        ));
+
+        let err = gate
+            .admit(service_path, None, std::future::pending::<Result<(), _>>())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<WorkerError>(),
+            Some(WorkerError::Starting)
+        ));
+    }
+}
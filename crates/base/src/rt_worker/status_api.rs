@@ -0,0 +1,154 @@
+use anyhow::Error;
+use deno_core::serde_json;
+use http_v02::{header, Method, StatusCode};
+use hyper_v014::{Body, Request, Response};
+use log::error;
+use sb_workers::context::{ServiceStatus, UserWorkerMsgs};
+use tokio::sync::{mpsc, oneshot};
+
+use super::internal_auth::InternalApiAuth;
+use super::router::emit_json_error;
+
+pub static STATUS_API_PATH: &str = "/_internal/status";
+
+async fn get_status(
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+) -> Result<Vec<ServiceStatus>, Error> {
+    let (tx, rx) = oneshot::channel();
+
+    worker_pool_tx.send(UserWorkerMsgs::GetStatus(tx))?;
+
+    Ok(rx.await?)
+}
+
+/// Serves `GET /_internal/status`, which responds with the status of every
+/// service of the pool for dashboards: its active workers, in-flight and
+/// queued requests, limits, last termination and uptime.
+///
+/// Every request must satisfy the configured [`InternalApiAuth`].
+pub(crate) async fn handle_status_api(
+    auth: &InternalApiAuth,
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    req: Request<Body>,
+) -> Response<Body> {
+    if let Err((status, msg)) = auth.check(&req) {
+        return emit_json_error(status, msg);
+    }
+
+    if req.uri().path() != STATUS_API_PATH {
+        return emit_json_error(StatusCode::NOT_FOUND, "not found");
+    }
+
+    if req.method() != Method::GET {
+        return emit_json_error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+
+    match get_status(worker_pool_tx).await {
+        Ok(services) => {
+            let mut res = Response::new(Body::from(
+                serde_json::json!({ "services": services }).to_string(),
+            ));
+
+            res.headers_mut().insert(
+                header::CONTENT_TYPE,
+                http_v02::HeaderValue::from_static("application/json"),
+            );
+
+            res
+        }
+
+        Err(err) => {
+            error!("failed to handle status api request: {err:#}");
+            emit_json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use deno_core::serde_json;
+    use http_v02::StatusCode;
+    use hyper_v014::{Body, Request};
+    use sb_workers::context::{ServiceStatus, WorkerIdentity, WorkerLimits};
+    use tokio::sync::mpsc;
+
+    use super::{handle_status_api, STATUS_API_PATH};
+    use crate::rt_worker::internal_auth::InternalApiAuth;
+
+    #[test]
+    fn test_service_status_json() {
+        let identity = WorkerIdentity {
+            tenant: Some("acme".to_string()),
+            service: "./hello".to_string(),
+            revision: None,
+        };
+
+        let status = ServiceStatus {
+            key: identity.to_string(),
+            identity,
+            active_instances: 2,
+            in_flight_requests: 3,
+            queue_depth: 0,
+            limits: Some(WorkerLimits {
+                memory_limit_mb: 150,
+                ..Default::default()
+            }),
+            last_termination: None,
+            uptime_ms: Some(1000),
+        };
+
+        let value = serde_json::to_value(&status).unwrap();
+
+        assert_eq!(value["key"], "acme/./hello");
+        assert_eq!(value["tenant"], "acme");
+        assert_eq!(value["service"], "./hello");
+        assert_eq!(value["activeInstances"], 2);
+        assert_eq!(value["inFlightRequests"], 3);
+        assert_eq!(value["limits"]["memoryLimitMb"], 150);
+        assert!(value["lastTermination"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_status_api_hides_error_detail() {
+        // NOTE: The pool is gone, so the request fails with an error whose
+        // chain only belongs in the logs.
+        let (worker_pool_tx, _) = mpsc::unbounded_channel();
+        let req = Request::builder()
+            .uri(STATUS_API_PATH)
+            .body(Body::empty())
+            .unwrap();
+
+        let res = handle_status_api(&InternalApiAuth::default(), &worker_pool_tx, req).await;
+
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = hyper_v014::body::to_bytes(res.into_body()).await.unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(value["msg"], "internal server error");
+    }
+
+    #[tokio::test]
+    async fn test_status_api_unknown_path() {
+        let (worker_pool_tx, _) = mpsc::unbounded_channel();
+        let auth = InternalApiAuth::default();
+        let req = Request::builder()
+            .uri(format!("{}/nope", STATUS_API_PATH))
+            .body(Body::empty())
+            .unwrap();
+
+        let res = handle_status_api(&auth, &worker_pool_tx, req).await;
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(STATUS_API_PATH)
+            .body(Body::empty())
+            .unwrap();
+
+        let res = handle_status_api(&auth, &worker_pool_tx, req).await;
+
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
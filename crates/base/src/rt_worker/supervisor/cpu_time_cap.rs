@@ -0,0 +1,67 @@
+use sb_workers::context::UserWorkerRuntimeOpts;
+
+/// Which of the caps of [`CpuTimeCap`] was exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuTimeCapKind {
+    Worker,
+    Request,
+}
+
+/// Absolute caps on the CPU time a worker consumes, over its lifetime and
+/// for a single request.
+///
+/// Unlike the CPU timer, which only catches bursts of CPU usage, these are
+/// checked against the cumulative usage, so that a worker that keeps using a
+/// little more CPU time on every turn of its event loop is eventually
+/// terminated as well.
+pub struct CpuTimeCap {
+    worker_ms: Option<i64>,
+    request_ms: Option<i64>,
+}
+
+impl CpuTimeCap {
+    pub fn new(runtime_opts: &UserWorkerRuntimeOpts) -> Self {
+        let to_cap = |ms: u64| (ms > 0).then(|| i64::try_from(ms).unwrap_or(i64::MAX));
+
+        Self {
+            worker_ms: to_cap(runtime_opts.cpu_time_cap_ms),
+            request_ms: to_cap(runtime_opts.request_cpu_time_cap_ms),
+        }
+    }
+
+    /// Returns the cap exceeded by the CPU time the worker and its current
+    /// request have used so far, if any.
+    pub fn exceeded(&self, worker_cpu_ms: i64, request_cpu_ms: i64) -> Option<CpuTimeCapKind> {
+        if self.worker_ms.is_some_and(|cap| worker_cpu_ms >= cap) {
+            Some(CpuTimeCapKind::Worker)
+        } else if self.request_ms.is_some_and(|cap| request_cpu_ms >= cap) {
+            Some(CpuTimeCapKind::Request)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sb_workers::context::UserWorkerRuntimeOpts;
+
+    use super::{CpuTimeCap, CpuTimeCapKind};
+
+    #[test]
+    fn test_cpu_time_cap() {
+        let cap = CpuTimeCap::new(&UserWorkerRuntimeOpts {
+            cpu_time_cap_ms: 1000,
+            request_cpu_time_cap_ms: 200,
+            ..Default::default()
+        });
+
+        assert_eq!(cap.exceeded(500, 100), None);
+        assert_eq!(cap.exceeded(500, 200), Some(CpuTimeCapKind::Request));
+        assert_eq!(cap.exceeded(1000, 0), Some(CpuTimeCapKind::Worker));
+
+        let uncapped = CpuTimeCap::new(&UserWorkerRuntimeOpts::default());
+
+        assert_eq!(uncapped.exceeded(i64::MAX, i64::MAX), None);
+    }
+}
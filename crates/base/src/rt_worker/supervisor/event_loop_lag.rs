@@ -0,0 +1,114 @@
+use std::future::pending;
+use std::time::Duration;
+
+use event_worker::events::{EventLoopLagEvent, EventMetadata, WorkerEvents};
+use sb_workers::context::UserWorkerRuntimeOpts;
+use tokio::time::Instant;
+
+use crate::utils::send_event_if_event_worker_available;
+
+/// Measures how long each turn of the event loop of a worker keeps its thread
+/// busy, from the [`Enter`] and [`Leave`] signals sent by the worker.
+///
+/// Comparing the lag of a turn with the CPU time it consumed tells CPU-bound
+/// code (both are close) apart from a blocked event loop (the lag is much
+/// larger than the CPU time).
+///
+/// [`Enter`]: super::CPUUsageMetrics::Enter
+/// [`Leave`]: super::CPUUsageMetrics::Leave
+pub struct EventLoopLagMonitor {
+    warn_threshold: Option<Duration>,
+    limit: Option<Duration>,
+    entered_at: Option<Instant>,
+}
+
+impl EventLoopLagMonitor {
+    pub fn new(runtime_opts: &UserWorkerRuntimeOpts) -> Self {
+        let to_duration = |ms: u64| (ms > 0).then(|| Duration::from_millis(ms));
+
+        Self {
+            warn_threshold: to_duration(runtime_opts.event_loop_lag_warn_ms),
+            limit: to_duration(runtime_opts.event_loop_lag_limit_ms),
+            entered_at: None,
+        }
+    }
+
+    pub fn enter(&mut self) {
+        self.entered_at = Some(Instant::now());
+    }
+
+    /// Returns the lag of the turn that has just ended if it exceeds the warn
+    /// threshold.
+    pub fn leave(&mut self) -> Option<Duration> {
+        let lag = self.entered_at.take()?.elapsed();
+
+        self.warn_threshold
+            .filter(|threshold| lag >= *threshold)
+            .map(|_| lag)
+    }
+
+    /// Resolves once the current turn has kept the thread busy for longer
+    /// than the limit.
+    pub async fn limit_exceeded(&self) {
+        match self.entered_at.zip(self.limit) {
+            Some((entered_at, limit)) => tokio::time::sleep_until(entered_at + limit).await,
+            None => pending().await,
+        }
+    }
+}
+
+/// Reports a turn of the event loop that exceeded the warn threshold as a
+/// [`WorkerEvents::EventLoopLag`] event.
+pub fn report_event_loop_lag(
+    runtime_opts: &UserWorkerRuntimeOpts,
+    lag: Duration,
+    cpu_time_ns: i64,
+) {
+    send_event_if_event_worker_available(
+        runtime_opts.events_msg_tx.as_ref(),
+        WorkerEvents::EventLoopLag(EventLoopLagEvent {
+            lag_ms: lag.as_millis().try_into().unwrap_or(u64::MAX),
+            cpu_time_ms: (cpu_time_ns.max(0) / 1_000_000) as u64,
+        }),
+        EventMetadata {
+            service_path: runtime_opts.service_path.clone(),
+            execution_id: runtime_opts.key,
+            worker_id: runtime_opts.identity.as_ref().map(ToString::to_string),
+        },
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use sb_workers::context::UserWorkerRuntimeOpts;
+
+    use super::EventLoopLagMonitor;
+
+    #[tokio::test]
+    async fn test_event_loop_lag_monitor() {
+        let mut monitor = EventLoopLagMonitor::new(&UserWorkerRuntimeOpts {
+            event_loop_lag_warn_ms: 20,
+            event_loop_lag_limit_ms: 50,
+            ..Default::default()
+        });
+
+        monitor.enter();
+        assert!(monitor.leave().is_none());
+
+        monitor.enter();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(monitor.leave().unwrap() >= Duration::from_millis(20));
+
+        monitor.enter();
+        tokio::time::timeout(Duration::from_secs(1), monitor.limit_exceeded())
+            .await
+            .unwrap();
+
+        let _ = monitor.leave();
+        tokio::time::timeout(Duration::from_millis(100), monitor.limit_exceeded())
+            .await
+            .unwrap_err();
+    }
+}
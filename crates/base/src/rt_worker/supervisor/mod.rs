@@ -1,21 +1,31 @@
+pub mod cpu_time_cap;
+pub mod event_loop_lag;
 pub mod strategy_per_request;
 pub mod strategy_per_worker;
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use cpu_timer::{CPUAlarmVal, CPUTimer};
 use deno_core::v8::IsolateHandle;
 use enum_as_inner::EnumAsInner;
+use event_worker::events::{CpuBurstEvent, EventMetadata, ShutdownReason, WorkerEvents};
 use futures_util::task::AtomicWaker;
 use log::error;
+use once_cell::sync::Lazy;
 use sb_workers::context::{Timing, UserWorkerMsgs, UserWorkerRuntimeOpts};
+use sb_workers::failure_injection::InjectedFailure;
 use tokio::sync::{
     mpsc::{self, UnboundedReceiver},
-    oneshot,
+    oneshot, watch,
 };
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+use crate::utils::send_event_if_event_worker_available;
+
 use super::{worker_ctx::TerminationToken, worker_pool::SupervisorPolicy};
 
 #[repr(C)]
@@ -55,6 +65,141 @@ pub extern "C" fn handle_interrupt(
     }
 }
 
+/// Maximum number of frames included in a [`CpuBurstEvent`].
+static CPU_BURST_FRAME_LIMIT: usize = 10;
+
+/// Maximum time to wait for the isolate to service the interrupt that
+/// captures its stack.
+static CPU_BURST_CAPTURE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct StackCaptureData {
+    pub frame_limit: usize,
+    pub frames_tx: oneshot::Sender<Vec<String>>,
+}
+
+/// The stack captures whose interrupt has been requested but not serviced yet,
+/// by id.
+///
+/// NOTE: The interrupt is only given the id. An isolate that is terminated or
+/// dropped never services it, and the capture must still be freed then, which
+/// can't be done safely with a pointer the isolate may yet dereference.
+static PENDING_STACK_CAPTURES: Lazy<Mutex<HashMap<usize, StackCaptureData>>> =
+    Lazy::new(Mutex::default);
+static NEXT_STACK_CAPTURE_ID: AtomicUsize = AtomicUsize::new(1);
+
+fn register_stack_capture(data: StackCaptureData) -> usize {
+    let id = NEXT_STACK_CAPTURE_ID.fetch_add(1, Ordering::Relaxed);
+
+    PENDING_STACK_CAPTURES.lock().unwrap().insert(id, data);
+    id
+}
+
+fn take_stack_capture(id: usize) -> Option<StackCaptureData> {
+    PENDING_STACK_CAPTURES.lock().unwrap().remove(&id)
+}
+
+pub extern "C" fn handle_stack_capture_interrupt(
+    isolate: &mut deno_core::v8::Isolate,
+    data: *mut std::ffi::c_void,
+) {
+    // NOTE: The capture is gone if it has timed out in the meantime.
+    let Some(capture) = take_stack_capture(data as usize) else {
+        return;
+    };
+
+    // NOTE: Interrupts are only serviced while JS is running, so there is
+    // always an entered context here.
+    let scope = &mut deno_core::v8::HandleScope::new(isolate);
+    let context = scope.get_current_context();
+    let scope = &mut deno_core::v8::ContextScope::new(scope, context);
+    let mut frames = vec![];
+
+    if let Some(trace) = deno_core::v8::StackTrace::current_stack_trace(scope, capture.frame_limit)
+    {
+        for idx in 0..trace.get_frame_count() {
+            let Some(frame) = trace.get_frame(scope, idx) else {
+                continue;
+            };
+
+            let function_name = frame
+                .get_function_name(scope)
+                .map(|it| it.to_rust_string_lossy(scope))
+                .filter(|it| !it.is_empty())
+                .unwrap_or_else(|| String::from("<anonymous>"));
+
+            let script_name = frame
+                .get_script_name_or_source_url(scope)
+                .map(|it| it.to_rust_string_lossy(scope))
+                .unwrap_or_default();
+
+            frames.push(format!(
+                "{} ({}:{}:{})",
+                function_name,
+                script_name,
+                frame.get_line_number(),
+                frame.get_column()
+            ));
+        }
+    }
+
+    let _ = capture.frames_tx.send(frames);
+}
+
+/// Captures the current JS stack of the isolate and reports it to the events
+/// worker as a [`WorkerEvents::CpuBurst`] event.
+///
+/// The stack can only be captured while the isolate is running JS, so this
+/// should be called while the worker is entered.
+pub fn report_cpu_burst(
+    thread_safe_handle: &IsolateHandle,
+    runtime_opts: &UserWorkerRuntimeOpts,
+    cpu_time_used_ms: i64,
+) {
+    let Some(events_msg_tx) = runtime_opts.events_msg_tx.clone() else {
+        return;
+    };
+
+    let (frames_tx, frames_rx) = oneshot::channel();
+    let id = register_stack_capture(StackCaptureData {
+        frame_limit: CPU_BURST_FRAME_LIMIT,
+        frames_tx,
+    });
+
+    if !thread_safe_handle
+        .request_interrupt(handle_stack_capture_interrupt, id as *mut std::ffi::c_void)
+    {
+        drop(take_stack_capture(id));
+        return;
+    }
+
+    let metadata = EventMetadata {
+        service_path: runtime_opts.service_path.clone(),
+        execution_id: runtime_opts.key,
+        worker_id: runtime_opts.identity.as_ref().map(ToString::to_string),
+    };
+
+    drop(tokio::spawn(async move {
+        let result = tokio::time::timeout(CPU_BURST_CAPTURE_TIMEOUT, frames_rx).await;
+
+        // NOTE: Frees the capture if the isolate has not serviced the
+        // interrupt in time, e.g. because it has been terminated.
+        drop(take_stack_capture(id));
+
+        let Ok(Ok(frames)) = result else {
+            return;
+        };
+
+        send_event_if_event_worker_available(
+            Some(&events_msg_tx),
+            WorkerEvents::CpuBurst(CpuBurstEvent {
+                cpu_time_used: cpu_time_used_ms as usize,
+                frames,
+            }),
+            metadata,
+        );
+    }));
+}
+
 #[repr(C)]
 pub struct IsolateMemoryStats {
     pub used_heap_size: usize,
@@ -151,3 +296,45 @@ async fn wait_cpu_alarm(maybe_alarm: Option<&mut UnboundedReceiver<()>>) -> Opti
         None => None,
     }
 }
+
+/// Resolves to the reason the worker must be terminated for once the pool
+/// injects a limit failure into it.
+async fn wait_injected_limit(
+    maybe_rx: Option<&mut watch::Receiver<Option<InjectedFailure>>>,
+) -> Option<ShutdownReason> {
+    let rx = maybe_rx?;
+
+    rx.changed().await.ok()?;
+
+    let failure = *rx.borrow_and_update();
+
+    match failure? {
+        InjectedFailure::MemoryLimit => Some(ShutdownReason::Memory),
+        InjectedFailure::CpuTimeLimit => Some(ShutdownReason::CPUTime),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::oneshot;
+
+    use super::{register_stack_capture, take_stack_capture, StackCaptureData};
+
+    #[test]
+    fn test_stack_capture_is_taken_once() {
+        let (frames_tx, frames_rx) = oneshot::channel();
+        let id = register_stack_capture(StackCaptureData {
+            frame_limit: 1,
+            frames_tx,
+        });
+
+        assert_ne!(id, 0);
+        assert!(take_stack_capture(id).is_some());
+
+        // NOTE: The interrupt being serviced after the capture timed out finds
+        // nothing, and the sender has been dropped with the capture.
+        assert!(take_stack_capture(id).is_none());
+        assert!(frames_rx.blocking_recv().is_err());
+    }
+}
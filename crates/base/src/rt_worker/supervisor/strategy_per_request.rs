@@ -8,8 +8,11 @@ use log::error;
 use sb_workers::context::{Timing, TimingStatus, UserWorkerMsgs};
 use tokio::time::Instant;
 
+use crate::rt_worker::supervisor::cpu_time_cap::CpuTimeCap;
+use crate::rt_worker::supervisor::event_loop_lag::{report_event_loop_lag, EventLoopLagMonitor};
 use crate::rt_worker::supervisor::{
-    handle_interrupt, wait_cpu_alarm, CPUUsage, CPUUsageMetrics, IsolateInterruptData, Tokens,
+    handle_interrupt, report_cpu_burst, wait_cpu_alarm, wait_injected_limit, CPUUsage,
+    CPUUsageMetrics, IsolateInterruptData, Tokens,
 };
 
 use super::Arguments;
@@ -42,7 +45,7 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
     let (cpu_timer, mut cpu_alarms_rx) = cpu_timer.unzip();
     let (_, hard_limit_ms) = cpu_timer_param.limits();
 
-    let _guard = scopeguard::guard(is_retired, |v| {
+    let guard = scopeguard::guard(is_retired, |v| {
         v.raise();
     });
 
@@ -53,10 +56,20 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
     let mut cpu_usage_metrics_rx = cpu_usage_metrics_rx.unwrap();
     let mut cpu_usage_ms = 0i64;
     let mut cpu_usage_accumulated_ms = 0i64;
+    let mut lag_monitor = EventLoopLagMonitor::new(&runtime_opts);
+    let cpu_time_cap = CpuTimeCap::new(&runtime_opts);
+    let mut injected_limit_rx = runtime_opts
+        .failure_injection
+        .as_ref()
+        .map(|it| it.limit_tx.subscribe());
 
     let mut complete_reason = None::<ShutdownReason>;
     let mut req_ack_count = 0usize;
     let mut req_start_ack = false;
+    let mut is_draining = false;
+
+    let drain_timeout = Duration::from_millis(runtime_opts.drain_timeout_ms);
+    let drain_deadline = tokio::time::sleep(Duration::ZERO);
 
     let wall_clock_limit_ms = runtime_opts.worker_timeout_ms;
     let is_wall_clock_limit_disabled = wall_clock_limit_ms == 0;
@@ -70,6 +83,7 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
     let wall_clock_duration_alert = tokio::time::sleep(wall_clock_duration);
 
     tokio::pin!(wall_clock_duration_alert);
+    tokio::pin!(drain_deadline);
 
     loop {
         tokio::select! {
@@ -82,7 +96,21 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
                     Some(token) => token.inbound.cancelled().await,
                     None => pending().await,
                 }
-            } => {
+            }, if !is_draining => {
+                // NOTE: Let the requests the worker has already taken over
+                // complete before terminating it.
+                guard.raise();
+
+                if drain_timeout.is_zero() || req_ack_count == demand.load(Ordering::Acquire) {
+                    complete_reason = Some(ShutdownReason::TerminationRequested);
+                } else {
+                    is_draining = true;
+                    drain_deadline.as_mut().reset(Instant::now() + drain_timeout);
+                }
+            }
+
+            _ = &mut drain_deadline, if is_draining => {
+                error!("drain timeout reached: isolate: {:?}", key);
                 complete_reason = Some(ShutdownReason::TerminationRequested);
             }
 
@@ -99,6 +127,7 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
 
                         assert!(!is_worker_entered);
                         is_worker_entered = true;
+                        lag_monitor.enter();
 
                         if !cpu_timer_param.is_disabled() {
                             if let Some(Err(err)) = cpu_timer.as_ref().map(|it| it.reset()) {
@@ -114,6 +143,19 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
                         cpu_usage_ms += diff / 1_000_000;
                         cpu_usage_accumulated_ms = accumulated / 1_000_000;
 
+                        if let Some(pressure) = runtime_opts.pressure.as_ref() {
+                            pressure.cpu_time_accumulated_ms.store(cpu_usage_accumulated_ms.max(0) as u64, Ordering::Release);
+                        }
+
+                        if let Some(lag) = lag_monitor.leave() {
+                            report_event_loop_lag(&runtime_opts, lag, diff);
+                        }
+
+                        if let Some(kind) = cpu_time_cap.exceeded(cpu_usage_accumulated_ms, cpu_usage_ms) {
+                            error!("CPU time cap reached: isolate: {:?}, cap: {:?}", key, kind);
+                            complete_reason = Some(ShutdownReason::CPUTimeCap);
+                        }
+
                         if !cpu_timer_param.is_disabled() {
                             if cpu_usage_ms >= hard_limit_ms as i64 {
                                 error!("CPU time limit reached: isolate: {:?}", key);
@@ -130,11 +172,17 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
 
             Some(_) = wait_cpu_alarm(cpu_alarms_rx.as_mut()) => {
                 if is_worker_entered && req_start_ack {
+                    report_cpu_burst(&thread_safe_handle, &runtime_opts, cpu_usage_ms);
                     error!("CPU time limit reached: isolate: {:?}", key);
                     complete_reason = Some(ShutdownReason::CPUTime);
                 }
             }
 
+            _ = lag_monitor.limit_exceeded(), if is_worker_entered => {
+                error!("event loop lag limit reached: isolate: {:?}", key);
+                complete_reason = Some(ShutdownReason::EventLoopLag);
+            }
+
             Some(notify) = req_start_rx.recv() => {
                 // INVARIANT: This branch MUST not be satisfied more than once
                 // during the same request cycle.
@@ -160,7 +208,13 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
                 assert!(req_start_ack, "supervisor observed the request end signal but did not see request start signal");
 
                 req_ack_count += 1;
-                complete_reason = Some(ShutdownReason::EarlyDrop);
+                complete_reason = Some(
+                    if is_draining && req_ack_count == demand.load(Ordering::Acquire) {
+                        ShutdownReason::TerminationRequested
+                    } else {
+                        ShutdownReason::EarlyDrop
+                    },
+                );
             }
 
             _ = &mut wall_clock_duration_alert, if !is_wall_clock_limit_disabled => {
@@ -180,6 +234,11 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
                 error!("memory limit reached for the worker: isolate: {:?}", key);
                 complete_reason = Some(ShutdownReason::Memory);
             }
+
+            Some(reason) = wait_injected_limit(injected_limit_rx.as_mut()) => {
+                error!("injected limit reached for the worker: isolate: {:?}, reason: {:?}", key, reason);
+                complete_reason = Some(reason);
+            }
         }
 
         match complete_reason.take() {
@@ -6,11 +6,45 @@ use std::thread::ThreadId;
 use event_worker::events::ShutdownReason;
 use log::error;
 use sb_workers::context::{Timing, TimingStatus, UserWorkerMsgs};
+use tokio::time::Instant;
 
-use crate::rt_worker::supervisor::{wait_cpu_alarm, CPUUsage, Tokens};
+use crate::rt_worker::supervisor::cpu_time_cap::CpuTimeCap;
+use crate::rt_worker::supervisor::event_loop_lag::{report_event_loop_lag, EventLoopLagMonitor};
+use crate::rt_worker::supervisor::{
+    report_cpu_burst, wait_cpu_alarm, wait_injected_limit, CPUUsage, Tokens,
+};
 
 use super::{handle_interrupt, Arguments, CPUUsageMetrics, IsolateInterruptData};
 
+/// The CPU time of a worker that is charged against its limits.
+///
+/// NOTE: The housekeeping done while no request is in flight (e.g. timers and
+/// memory checks) is not charged to the worker, so that a warm worker does not
+/// slowly run into the CPU time limits without serving any traffic.
+#[derive(Debug, Default)]
+struct ChargedCpuUsage {
+    is_idle_on_enter: bool,
+    idle_ns: i64,
+}
+
+impl ChargedCpuUsage {
+    fn enter(&mut self, is_idle: bool) {
+        self.is_idle_on_enter = is_idle;
+    }
+
+    /// Returns whether the worker was idle for the whole turn it just left,
+    /// along with the CPU time in milliseconds charged to it so far.
+    fn leave(&mut self, is_idle: bool, usage: CPUUsage) -> (bool, i64) {
+        let is_idle = self.is_idle_on_enter && is_idle;
+
+        if is_idle {
+            self.idle_ns += usage.diff;
+        }
+
+        (is_idle, (usage.accumulated - self.idle_ns) / 1_000_000)
+    }
+}
+
 pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
     let Arguments {
         key,
@@ -46,12 +80,24 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
     let mut current_thread_id = Option::<ThreadId>::None;
 
     let mut is_worker_entered = false;
+    let mut charged_cpu_usage = ChargedCpuUsage::default();
     let mut cpu_usage_metrics_rx = cpu_usage_metrics_rx.unwrap();
     let mut cpu_usage_ms = 0i64;
+    let mut busy_since_cpu_usage_ms = 0i64;
+    let mut lag_monitor = EventLoopLagMonitor::new(&runtime_opts);
+    let cpu_time_cap = CpuTimeCap::new(&runtime_opts);
+    let mut injected_limit_rx = runtime_opts
+        .failure_injection
+        .as_ref()
+        .map(|it| it.limit_tx.subscribe());
 
     let mut cpu_time_soft_limit_reached = false;
     let mut wall_clock_alerts = 0;
     let mut req_ack_count = 0usize;
+    let mut is_draining = false;
+
+    let drain_timeout = Duration::from_millis(runtime_opts.drain_timeout_ms);
+    let drain_deadline = tokio::time::sleep(Duration::ZERO);
 
     let wall_clock_limit_ms = runtime_opts.worker_timeout_ms;
     let is_wall_clock_limit_disabled = wall_clock_limit_ms == 0;
@@ -93,6 +139,7 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
     };
 
     tokio::pin!(wall_clock_duration_alert);
+    tokio::pin!(drain_deadline);
 
     loop {
         tokio::select! {
@@ -105,8 +152,24 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                     Some(token) => token.inbound.cancelled().await,
                     None => pending().await,
                 }
-            } => {
+            }, if !is_draining => {
+                // NOTE: The pool no longer routes requests to a retired
+                // worker, so the requests it has already taken over are
+                // allowed to complete before it is terminated.
+                early_retire_fn();
+
+                if drain_timeout.is_zero() || req_ack_count == demand.load(Ordering::Acquire) {
+                    terminate_fn();
+                    return (ShutdownReason::TerminationRequested, cpu_usage_ms);
+                }
+
+                is_draining = true;
+                drain_deadline.as_mut().reset(Instant::now() + drain_timeout);
+            }
+
+            _ = &mut drain_deadline, if is_draining => {
                 terminate_fn();
+                error!("drain timeout reached: isolate: {:?}", key);
                 return (ShutdownReason::TerminationRequested, cpu_usage_ms);
             }
 
@@ -123,6 +186,8 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
 
                         assert!(!is_worker_entered);
                         is_worker_entered = true;
+                        charged_cpu_usage.enter(req_ack_count == demand.load(Ordering::Acquire));
+                        lag_monitor.enter();
 
                         if !cpu_timer_param.is_disabled() {
                             if let Some(Err(err)) = cpu_timer.as_ref().map(|it| it.reset()) {
@@ -131,11 +196,43 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                         }
                     }
 
-                    CPUUsageMetrics::Leave(CPUUsage { accumulated, .. }) => {
+                    CPUUsageMetrics::Leave(usage) => {
                         assert!(is_worker_entered);
 
                         is_worker_entered = false;
-                        cpu_usage_ms = accumulated / 1_000_000;
+
+                        let diff = usage.diff;
+                        let accumulated_ms = usage.accumulated / 1_000_000;
+                        let (is_idle, charged_ms) = charged_cpu_usage.leave(
+                            req_ack_count == demand.load(Ordering::Acquire),
+                            usage,
+                        );
+
+                        cpu_usage_ms = charged_ms;
+
+                        if is_idle {
+                            if let Some(Err(err)) = cpu_timer.as_ref().map(|it| it.suspend()) {
+                                error!("can't suspend cpu timer: {}", err);
+                            }
+                        }
+
+                        if let Some(pressure) = runtime_opts.pressure.as_ref() {
+                            pressure.cpu_time_used_ms.store(cpu_usage_ms.max(0) as u64, Ordering::Release);
+                            pressure.cpu_time_accumulated_ms.store(accumulated_ms.max(0) as u64, Ordering::Release);
+                        }
+
+                        if let Some(lag) = lag_monitor.leave() {
+                            report_event_loop_lag(&runtime_opts, lag, diff);
+                        }
+
+                        // NOTE: The requests of a worker overlap, so the CPU
+                        // time of a request is taken as the one used since
+                        // the worker was last idle.
+                        if let Some(kind) = cpu_time_cap.exceeded(cpu_usage_ms, cpu_usage_ms - busy_since_cpu_usage_ms) {
+                            terminate_fn();
+                            error!("CPU time cap reached: isolate: {:?}, cap: {:?}", key, kind);
+                            return (ShutdownReason::CPUTimeCap, cpu_usage_ms);
+                        }
 
                         if !cpu_timer_param.is_disabled() {
                             if cpu_usage_ms >= hard_limit_ms as i64 {
@@ -160,6 +257,8 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
 
             Some(_) = wait_cpu_alarm(cpu_alarms_rx.as_mut()) => {
                 if is_worker_entered {
+                    report_cpu_burst(&thread_safe_handle, &runtime_opts, cpu_usage_ms);
+
                     if !cpu_time_soft_limit_reached {
                         early_retire_fn();
                         error!("CPU time soft limit reached: isolate: {:?}", key);
@@ -178,9 +277,28 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                 }
             }
 
+            _ = lag_monitor.limit_exceeded(), if is_worker_entered => {
+                terminate_fn();
+                error!("event loop lag limit reached: isolate: {:?}", key);
+                return (ShutdownReason::EventLoopLag, cpu_usage_ms);
+            }
+
             Some(_) = req_end_rx.recv() => {
                 req_ack_count += 1;
 
+                if req_ack_count == demand.load(Ordering::Acquire) {
+                    busy_since_cpu_usage_ms = cpu_usage_ms;
+                }
+
+                if is_draining {
+                    if req_ack_count != demand.load(Ordering::Acquire) {
+                        continue;
+                    }
+
+                    terminate_fn();
+                    return (ShutdownReason::TerminationRequested, cpu_usage_ms);
+                }
+
                 if !cpu_time_soft_limit_reached {
                     if let Some(tx) = pool_msg_tx.clone() {
                         if tx.send(UserWorkerMsgs::Idle(key)).is_err() {
@@ -222,6 +340,44 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                 error!("memory limit reached for the worker: isolate: {:?}", key);
                 return (ShutdownReason::Memory, cpu_usage_ms);
             }
+
+            Some(reason) = wait_injected_limit(injected_limit_rx.as_mut()) => {
+                terminate_fn();
+                error!("injected limit reached for the worker: isolate: {:?}, reason: {:?}", key, reason);
+                return (reason, cpu_usage_ms);
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{CPUUsage, ChargedCpuUsage};
+
+    #[test]
+    fn test_charged_cpu_usage_leaves_out_idle_turns() {
+        let mut usage = ChargedCpuUsage::default();
+        let ms = |accumulated: i64, diff: i64| CPUUsage {
+            accumulated: accumulated * 1_000_000,
+            diff: diff * 1_000_000,
+        };
+
+        // NOTE: Housekeeping between requests.
+        usage.enter(true);
+        assert_eq!(usage.leave(true, ms(3, 3)), (true, 0));
+
+        // NOTE: A request came in while the worker was running.
+        usage.enter(true);
+        assert_eq!(usage.leave(false, ms(13, 10)), (false, 10));
+
+        usage.enter(false);
+        assert_eq!(usage.leave(false, ms(20, 7)), (false, 17));
+
+        // NOTE: The last request was answered during the turn.
+        usage.enter(false);
+        assert_eq!(usage.leave(true, ms(25, 5)), (false, 22));
+
+        usage.enter(true);
+        assert_eq!(usage.leave(true, ms(125, 100)), (true, 22));
+    }
+}
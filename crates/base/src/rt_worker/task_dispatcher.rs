@@ -0,0 +1,214 @@
+//! Delivers the tasks of the task queue to their services, through the main
+//! worker like the requests of the clients, so that it applies the same
+//! routing and configuration (e.g. the environment of the services) to them.
+//!
+//! A task is sent as a `POST` to `/<function><path>`, and the request carries
+//! its ID and attempt in the [`TaskMeta`] of its metadata, which clients can't
+//! spoof. Tasks are deferred work, so they are delivered as batch requests,
+//! and only so many at once.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Error};
+use http_v02::{header, HeaderValue, Method};
+use hyper_v014::{Body, Request};
+use log::{debug, error, warn};
+use sb_workers::context::WorkerRequestMsg;
+use sb_workers::task_queue::{now_ms, LeasedTask, TaskQueue, DEAD_TASK_RETENTION};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use super::deadline::DEADLINE_HEADER;
+use super::priority_class::PRIORITY_HEADER;
+use super::request_meta::{set_request_meta, RequestMeta, TaskMeta};
+
+pub static DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the due tasks are looked for, besides whenever one is enqueued.
+static TASK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How many tasks are leased at once.
+static TASK_LEASE_BATCH_SIZE: usize = 16;
+/// How many tasks are delivered at once.
+static MAX_IN_FLIGHT_TASKS: usize = 64;
+/// How often the tasks that used up their attempts are swept.
+static DEAD_TASK_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Delivers the tasks until the token is cancelled. A delivery is given up,
+/// and retried later, once it takes longer than the visibility timeout.
+pub fn spawn_task_dispatcher(
+    queue: Arc<TaskQueue>,
+    worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    visibility_timeout: Duration,
+    token: CancellationToken,
+) {
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT_TASKS));
+    let mut sweep_interval = tokio::time::interval(DEAD_TASK_SWEEP_INTERVAL);
+
+    drop(tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = queue.enqueued.notified() => {}
+                _ = tokio::time::sleep(TASK_POLL_INTERVAL) => {}
+                _ = sweep_interval.tick() => {
+                    sweep(&queue).await;
+                    continue;
+                }
+            }
+
+            // NOTE: The tasks are only leased once they can be delivered, so
+            // that their lease does not run out while they wait.
+            let limit = in_flight.available_permits().min(TASK_LEASE_BATCH_SIZE);
+
+            if limit == 0 {
+                continue;
+            }
+
+            let tasks = match queue
+                .run_blocking(move |it| it.lease(limit, visibility_timeout, now_ms()))
+                .await
+            {
+                Ok(it) => it,
+                Err(err) => {
+                    error!("failed to lease tasks: {err:#}");
+                    continue;
+                }
+            };
+
+            for task in tasks {
+                let queue = queue.clone();
+                let worker_req_tx = worker_req_tx.clone();
+
+                // NOTE: This loop is the only one to acquire the permits, so
+                // the ones counted above are still available.
+                let permit = in_flight.clone().try_acquire_owned().unwrap();
+
+                drop(tokio::spawn(async move {
+                    let _permit = permit;
+                    let result = if task.attempt > task.max_attempts {
+                        // NOTE: The lease of the task expired without it being
+                        // failed, e.g. because the runtime went away during
+                        // its last attempt.
+                        Err(anyhow!("the task has used up its attempts"))
+                    } else {
+                        deliver(&worker_req_tx, &task, visibility_timeout).await
+                    };
+
+                    finish(&queue, task, result).await;
+                }));
+            }
+        }
+    }));
+}
+
+async fn sweep(queue: &Arc<TaskQueue>) {
+    match queue
+        .run_blocking(|it| it.sweep(DEAD_TASK_RETENTION, now_ms()))
+        .await
+    {
+        Ok(0) => {}
+        Ok(count) => debug!("swept {count} tasks that used up their attempts"),
+        Err(err) => error!("failed to sweep the tasks: {err:#}"),
+    }
+}
+
+async fn deliver(
+    worker_req_tx: &mpsc::UnboundedSender<WorkerRequestMsg>,
+    task: &LeasedTask,
+    visibility_timeout: Duration,
+) -> Result<(), Error> {
+    let deadline_ms = now_ms().saturating_add(visibility_timeout.as_millis() as u64);
+    let mut req = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://localhost/{}{}", task.service, task.path))
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(DEADLINE_HEADER, HeaderValue::from(deadline_ms))
+        .header(PRIORITY_HEADER, "batch")
+        .body(Body::from(task.payload.clone()))?;
+
+    set_request_meta(
+        req.headers_mut(),
+        &RequestMeta {
+            request_id: Uuid::new_v4().to_string(),
+            deadline_ms: Some(deadline_ms),
+            task: Some(TaskMeta {
+                id: task.id.clone(),
+                attempt: task.attempt,
+                max_attempts: task.max_attempts,
+            }),
+            ..Default::default()
+        },
+    );
+
+    let cancel = CancellationToken::new();
+    let _cancel_guard = cancel.clone().drop_guard();
+    let (res_tx, res_rx) = oneshot::channel();
+
+    if worker_req_tx
+        .send(WorkerRequestMsg {
+            req,
+            res_tx,
+            conn_token: Some(cancel),
+        })
+        .is_err()
+    {
+        bail!("the main worker is gone");
+    }
+
+    let status = tokio::time::timeout(visibility_timeout, async move {
+        let res = res_rx.await??;
+        let status = res.status();
+
+        // NOTE: The body is drained so that the handler runs to completion.
+        hyper_v014::body::to_bytes(res.into_body()).await?;
+
+        Ok::<_, Error>(status)
+    })
+    .await
+    .map_err(|_| anyhow!("the delivery timed out"))??;
+
+    if !status.is_success() {
+        bail!("the service responded with {status}");
+    }
+
+    Ok(())
+}
+
+async fn finish(queue: &Arc<TaskQueue>, task: LeasedTask, result: Result<(), Error>) {
+    let id = task.id.clone();
+    let outcome = match result {
+        Ok(()) => queue
+            .run_blocking(move |it| it.complete(&task.id))
+            .await
+            .map(|_| {
+                debug!("task {} delivered", id);
+            }),
+
+        Err(err) => queue
+            .run_blocking(move |it| {
+                let retry = it.fail(&task, &format!("{err:#}"), now_ms())?;
+
+                Ok((task, err, retry))
+            })
+            .await
+            .map(|(task, err, retry)| {
+                if retry {
+                    warn!(
+                        "task {} failed (attempt {}/{}), will be retried: {err:#}",
+                        task.id, task.attempt, task.max_attempts
+                    );
+                } else {
+                    error!(
+                        "task {} failed (attempt {}/{}), given up: {err:#}",
+                        task.id, task.attempt, task.max_attempts
+                    );
+                }
+            }),
+    };
+
+    if let Err(err) = outcome {
+        error!("failed to update task {}: {err:#}", id);
+    }
+}
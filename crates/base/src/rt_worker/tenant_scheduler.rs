@@ -0,0 +1,345 @@
+//! Admission of the requests dispatched to the user workers, so that the
+//! tenants share the dispatcher fairly when it is saturated.
+//!
+//! At most `max_concurrent` requests are handled at once. Past that, requests
+//! wait in a queue per tenant, and the queues are served by stride scheduling:
+//! every tenant advances its pass by the inverse of its weight for each
+//! request it is admitted, and the tenant with the lowest pass goes next. A
+//! tenant of weight 2 thus gets twice as many slots as one of weight 1.
+//!
+//! The interactive and the batch requests of a tenant wait in separate queues,
+//! and a batch request is only admitted once no interactive request is
+//! waiting. The batch requests may also be given a lower limit of their own.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+use super::priority_class::PriorityClass;
+
+/// The pass advances by `STRIDE / weight` per admitted request.
+static STRIDE: u64 = 1 << 20;
+
+#[derive(Debug, Default)]
+struct TenantQueue {
+    pass: u64,
+    waiters: VecDeque<oneshot::Sender<TenantPermit>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_use: usize,
+    batch_in_use: usize,
+    weights: HashMap<String, u32>,
+    /// The queues by class and tenant, so that the interactive ones come
+    /// first.
+    tenants: BTreeMap<(PriorityClass, String), TenantQueue>,
+}
+
+impl State {
+    fn stride(&self, tenant: &str) -> u64 {
+        STRIDE / u64::from(self.weights.get(tenant).copied().unwrap_or(1).max(1))
+    }
+
+    /// Returns the lowest pass of the tenants that have requests of the class
+    /// waiting.
+    fn min_pass(&self, class: PriorityClass) -> Option<u64> {
+        self.tenants
+            .iter()
+            .filter(|((it, _), queue)| *it == class && !queue.waiters.is_empty())
+            .map(|(_, queue)| queue.pass)
+            .min()
+    }
+
+    fn next_waiter(
+        &mut self,
+        max_batch: usize,
+    ) -> Option<(oneshot::Sender<TenantPermit>, PriorityClass)> {
+        let is_batch_full = self.batch_in_use >= max_batch;
+        let key = self
+            .tenants
+            .iter()
+            .filter(|((class, _), it)| {
+                !it.waiters.is_empty() && !(*class == PriorityClass::Batch && is_batch_full)
+            })
+            .min_by_key(|((class, _), it)| (*class, it.pass))
+            .map(|(key, _)| key.clone())?;
+
+        let stride = self.stride(&key.1);
+        let queue = self.tenants.get_mut(&key)?;
+        let waiter = queue.waiters.pop_front();
+
+        queue.pass += stride;
+
+        // NOTE: Tenants without waiters are dropped, so the ones that have
+        // gone idle do not pile up.
+        self.tenants.retain(|_, it| !it.waiters.is_empty());
+
+        waiter.map(|it| (it, key.0))
+    }
+
+    /// Drops the requests that have given up waiting.
+    fn prune(&mut self) {
+        for queue in self.tenants.values_mut() {
+            queue.waiters.retain(|it| !it.is_closed());
+        }
+
+        self.tenants.retain(|_, it| !it.waiters.is_empty());
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    max_concurrent: usize,
+    max_batch: usize,
+    state: Mutex<State>,
+}
+
+impl Inner {
+    fn release(self: &Arc<Self>, mut class: PriorityClass) {
+        loop {
+            let (waiter, next_class) = {
+                let mut state = self.state.lock().unwrap();
+
+                if class == PriorityClass::Batch {
+                    state.batch_in_use -= 1;
+                }
+
+                match state.next_waiter(self.max_batch) {
+                    Some((waiter, next_class)) => {
+                        if next_class == PriorityClass::Batch {
+                            state.batch_in_use += 1;
+                        }
+
+                        (waiter, next_class)
+                    }
+
+                    None => {
+                        state.in_use -= 1;
+                        return;
+                    }
+                }
+            };
+
+            // NOTE: The slot passes on to the waiter as is. If it has given up
+            // waiting, the slot goes to the next one instead.
+            match waiter.send(TenantPermit(Some((self.clone(), next_class)))) {
+                Ok(()) => return,
+                Err(mut permit) => {
+                    permit.0.take();
+                    class = next_class;
+                }
+            }
+        }
+    }
+}
+
+/// A slot of the scheduler, which is given back once dropped.
+#[derive(Debug)]
+pub struct TenantPermit(Option<(Arc<Inner>, PriorityClass)>);
+
+impl Drop for TenantPermit {
+    fn drop(&mut self) {
+        if let Some((inner, class)) = self.0.take() {
+            inner.release(class);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TenantScheduler(Arc<Inner>);
+
+impl TenantScheduler {
+    /// Admits `max_concurrent` requests at once, of which `max_batch` batch
+    /// requests at most, if given.
+    pub fn new(max_concurrent: usize, max_batch: Option<usize>) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+
+        Self(Arc::new(Inner {
+            max_concurrent,
+            max_batch: max_batch.unwrap_or(max_concurrent).clamp(1, max_concurrent),
+            state: Mutex::default(),
+        }))
+    }
+
+    /// Sets the weights of the tenants. The tenants missing from the map have
+    /// a weight of 1.
+    pub fn set_weights(&self, weights: HashMap<String, u32>) {
+        self.0.state.lock().unwrap().weights = weights;
+    }
+
+    /// Waits for a slot for a request of the tenant and class. The requests
+    /// without a tenant share the same queues.
+    pub async fn acquire(&self, tenant: Option<&str>, class: PriorityClass) -> TenantPermit {
+        let rx = {
+            let mut state = self.0.state.lock().unwrap();
+
+            state.prune();
+
+            let is_admissible = match class {
+                PriorityClass::Interactive => state.min_pass(PriorityClass::Interactive).is_none(),
+
+                PriorityClass::Batch => {
+                    state.batch_in_use < self.0.max_batch
+                        && state.min_pass(PriorityClass::Interactive).is_none()
+                        && state.min_pass(PriorityClass::Batch).is_none()
+                }
+            };
+
+            if state.in_use < self.0.max_concurrent && is_admissible {
+                state.in_use += 1;
+
+                if class == PriorityClass::Batch {
+                    state.batch_in_use += 1;
+                }
+
+                return TenantPermit(Some((self.0.clone(), class)));
+            }
+
+            let tenant = tenant.unwrap_or_default();
+            let min_pass = state.min_pass(class).unwrap_or(0);
+            let (tx, rx) = oneshot::channel();
+            let queue = state
+                .tenants
+                .entry((class, tenant.to_string()))
+                .or_default();
+
+            // NOTE: A tenant that was idle starts from the pass of the busiest
+            // ones, rather than catching up on the slots it did not use.
+            if queue.waiters.is_empty() {
+                queue.pass = queue.pass.max(min_pass);
+            }
+
+            queue.waiters.push_back(tx);
+            rx
+        };
+
+        // NOTE: The sender is only dropped along with the scheduler.
+        rx.await.unwrap_or(TenantPermit(None))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{PriorityClass, TenantScheduler};
+
+    #[tokio::test]
+    async fn test_tenant_scheduler_limits_concurrency() {
+        let scheduler = TenantScheduler::new(1, None);
+        let permit = scheduler
+            .acquire(Some("a"), PriorityClass::Interactive)
+            .await;
+
+        assert!(tokio::time::timeout(
+            Duration::from_millis(50),
+            scheduler.acquire(Some("b"), PriorityClass::Interactive)
+        )
+        .await
+        .is_err());
+
+        drop(permit);
+
+        assert!(tokio::time::timeout(
+            Duration::from_millis(50),
+            scheduler.acquire(Some("b"), PriorityClass::Interactive)
+        )
+        .await
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_scheduler_weights() {
+        let scheduler = TenantScheduler::new(1, None);
+        let order = Arc::new(Mutex::new(vec![]));
+
+        scheduler.set_weights(HashMap::from([("a".to_string(), 2)]));
+
+        let permit = scheduler.acquire(None, PriorityClass::Interactive).await;
+        let mut tasks = vec![];
+
+        for tenant in ["a", "a", "a", "a", "b", "b"] {
+            let scheduler = scheduler.clone();
+            let order = order.clone();
+
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler
+                    .acquire(Some(tenant), PriorityClass::Interactive)
+                    .await;
+
+                order.lock().unwrap().push(tenant);
+            }));
+
+            tokio::task::yield_now().await;
+        }
+
+        drop(permit);
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(*order.lock().unwrap(), vec!["a", "b", "a", "a", "b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_scheduler_priority_classes() {
+        let scheduler = TenantScheduler::new(2, Some(1));
+        let order = Arc::new(Mutex::new(vec![]));
+
+        let batch = scheduler.acquire(Some("a"), PriorityClass::Batch).await;
+
+        // NOTE: The batch requests are limited to one slot, so the other one
+        // is left to the interactive requests.
+        assert!(tokio::time::timeout(
+            Duration::from_millis(50),
+            scheduler.acquire(Some("a"), PriorityClass::Batch)
+        )
+        .await
+        .is_err());
+
+        let interactive = scheduler
+            .acquire(Some("a"), PriorityClass::Interactive)
+            .await;
+        let mut tasks = vec![];
+
+        for (tenant, class) in [
+            ("a", PriorityClass::Batch),
+            ("b", PriorityClass::Batch),
+            ("a", PriorityClass::Interactive),
+            ("b", PriorityClass::Interactive),
+        ] {
+            let scheduler = scheduler.clone();
+            let order = order.clone();
+
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler.acquire(Some(tenant), class).await;
+
+                order.lock().unwrap().push((tenant, class));
+            }));
+
+            tokio::task::yield_now().await;
+        }
+
+        drop(interactive);
+        drop(batch);
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec![
+                ("a", PriorityClass::Interactive),
+                ("b", PriorityClass::Interactive),
+                ("a", PriorityClass::Batch),
+                ("b", PriorityClass::Batch),
+            ]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use http_v02::{HeaderMap, HeaderValue};
+use sb_workers::context::{TraceQuery, TraceRecord};
+use uuid::Uuid;
+
+/// The header of the W3C trace context.
+pub static TRACEPARENT_HEADER: &str = "traceparent";
+
+/// The W3C trace context a request is forwarded to its worker with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    flags: String,
+}
+
+impl TraceContext {
+    /// Continues the trace of the `traceparent` header if it is valid, or
+    /// starts a new one, with a new span for the pool either way.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let span_id = Uuid::new_v4().simple().to_string()[..16].to_string();
+
+        match headers
+            .get(TRACEPARENT_HEADER)
+            .and_then(|it| it.to_str().ok())
+            .and_then(parse_traceparent)
+        {
+            Some((trace_id, parent_span_id, flags)) => Self {
+                trace_id: trace_id.to_string(),
+                span_id,
+                parent_span_id: Some(parent_span_id.to_string()),
+                flags: flags.to_string(),
+            },
+
+            None => Self {
+                trace_id: Uuid::new_v4().simple().to_string(),
+                span_id,
+                parent_span_id: None,
+                flags: "01".to_string(),
+            },
+        }
+    }
+
+    /// Replaces the `traceparent` header so that the worker sees the span of
+    /// the pool as its parent.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        let value = format!("00-{}-{}-{}", self.trace_id, self.span_id, self.flags);
+
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(TRACEPARENT_HEADER, value);
+        }
+    }
+}
+
+/// Splits a `traceparent` header into its trace id, parent id and flags.
+fn parse_traceparent(value: &str) -> Option<(&str, &str, &str)> {
+    let is_hex = |it: &str, len: usize| {
+        it.len() == len && it.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let is_id = |it: &str, len: usize| is_hex(it, len) && it.bytes().any(|b| b != b'0');
+
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+
+    // NOTE: Later versions may append fields, but must keep these ones.
+    if !is_hex(version, 2)
+        || version == "ff"
+        || (version == "00" && parts.next().is_some())
+        || !is_id(trace_id, 32)
+        || !is_id(parent_id, 16)
+        || !is_hex(flags, 2)
+    {
+        return None;
+    }
+
+    Some((trace_id, parent_id, flags))
+}
+
+/// Keeps the spans of the last requests dispatched by the pool, so that they
+/// can be inspected through the traces API when no collector is configured.
+#[derive(Debug, Clone)]
+pub struct TraceBuffer {
+    capacity: usize,
+    records: Arc<Mutex<VecDeque<TraceRecord>>>,
+}
+
+impl TraceBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Appends a record, evicting the oldest one if the buffer is full.
+    pub fn push(&self, record: TraceRecord) {
+        let mut records = self.records.lock().unwrap();
+
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+
+        records.push_back(record);
+    }
+
+    /// Returns the records matching `query`, from the newest to the oldest.
+    pub fn query(&self, query: &TraceQuery) -> Vec<TraceRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|it| query.matches(it))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http_v02::HeaderMap;
+    use sb_workers::context::{TraceQuery, TraceRecord, TraceStatusFilter};
+    use uuid::Uuid;
+
+    use super::{TraceBuffer, TraceContext};
+
+    fn record(service_path: &str, status: Option<u16>) -> TraceRecord {
+        TraceRecord {
+            trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+            span_id: "00f067aa0ba902b7".to_string(),
+            parent_span_id: None,
+            service_path: service_path.to_string(),
+            worker_key: Uuid::nil(),
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            status,
+            error: None,
+            started_at: 0,
+            duration_ms: 1,
+        }
+    }
+
+    #[test]
+    fn test_trace_context() {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+
+        let ctx = TraceContext::from_headers(&headers);
+
+        assert_eq!(ctx.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(ctx.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert_eq!(ctx.span_id.len(), 16);
+
+        ctx.inject(&mut headers);
+        assert_eq!(
+            headers["traceparent"],
+            format!("00-4bf92f3577b34da6a3ce929d0e0e4736-{}-01", ctx.span_id)
+        );
+
+        headers.insert(
+            "traceparent",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+
+        let ctx = TraceContext::from_headers(&headers);
+
+        assert_eq!(ctx.trace_id.len(), 32);
+        assert_ne!(ctx.trace_id, "00000000000000000000000000000000");
+        assert_eq!(ctx.parent_span_id, None);
+    }
+
+    #[test]
+    fn test_trace_buffer_query() {
+        let buffer = TraceBuffer::new(3);
+
+        buffer.push(record("./a", Some(200)));
+        buffer.push(record("./b", Some(503)));
+        buffer.push(record("./a", None));
+        buffer.push(record("./a", Some(500)));
+
+        let statuses = |query: TraceQuery| {
+            buffer
+                .query(&query)
+                .into_iter()
+                .map(|it| it.status)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            statuses(TraceQuery::default()),
+            vec![Some(500), None, Some(503)]
+        );
+        assert_eq!(
+            statuses(TraceQuery {
+                service_path: Some("./a".to_string()),
+                ..Default::default()
+            }),
+            vec![Some(500), None]
+        );
+        assert_eq!(
+            statuses(TraceQuery {
+                status: Some(TraceStatusFilter::Class(5)),
+                limit: Some(1),
+                ..Default::default()
+            }),
+            vec![Some(500)]
+        );
+        assert_eq!(
+            statuses(TraceQuery {
+                status: Some(TraceStatusFilter::Error),
+                ..Default::default()
+            }),
+            vec![None]
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Context, Error};
+use deno_core::serde_json;
+use http_v02::{header, Method, StatusCode};
+use hyper_v014::{Body, Request, Response};
+use log::{debug, error};
+use sb_workers::context::{TraceQuery, TraceRecord, UserWorkerMsgs};
+use tokio::sync::{mpsc, oneshot};
+
+use super::internal_auth::InternalApiAuth;
+use super::router::emit_json_error;
+
+pub static TRACES_API_PATH: &str = "/_internal/traces";
+
+fn parse_query(query: Option<&str>) -> Result<TraceQuery, Error> {
+    let mut trace_query = TraceQuery::default();
+
+    for (name, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        match name.as_ref() {
+            "service" => trace_query.service_path = Some(value.into_owned()),
+            "status" => trace_query.status = Some(value.parse()?),
+            "limit" => {
+                trace_query.limit = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid limit: {value}"))?,
+                )
+            }
+
+            _ => {}
+        }
+    }
+
+    Ok(trace_query)
+}
+
+async fn get_traces(
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    query: TraceQuery,
+) -> Result<Vec<TraceRecord>, Error> {
+    let (tx, rx) = oneshot::channel();
+
+    worker_pool_tx.send(UserWorkerMsgs::GetTraces(query, tx))?;
+
+    Ok(rx.await?)
+}
+
+/// Serves `GET /_internal/traces`, which responds with the spans of the last
+/// requests dispatched by the pool, from the newest to the oldest.
+///
+/// The spans can be filtered with the `service`, `status` (e.g. `404`, `5xx`
+/// or `error`) and `limit` query parameters.
+///
+/// Every request must satisfy the configured [`InternalApiAuth`].
+pub(crate) async fn handle_traces_api(
+    auth: &InternalApiAuth,
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    req: Request<Body>,
+) -> Response<Body> {
+    if let Err((status, msg)) = auth.check(&req) {
+        return emit_json_error(status, msg);
+    }
+
+    if req.uri().path() != TRACES_API_PATH {
+        return emit_json_error(StatusCode::NOT_FOUND, "not found");
+    }
+
+    if req.method() != Method::GET {
+        return emit_json_error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+
+    let query = match parse_query(req.uri().query()) {
+        Ok(query) => query,
+        Err(err) => {
+            debug!("invalid traces api query: {err:#}");
+            return emit_json_error(StatusCode::BAD_REQUEST, "invalid query");
+        }
+    };
+
+    match get_traces(worker_pool_tx, query).await {
+        Ok(traces) => {
+            let mut res = Response::new(Body::from(
+                serde_json::json!({ "traces": traces }).to_string(),
+            ));
+
+            res.headers_mut().insert(
+                header::CONTENT_TYPE,
+                http_v02::HeaderValue::from_static("application/json"),
+            );
+
+            res
+        }
+
+        Err(err) => {
+            error!("failed to handle traces api request: {err:#}");
+            emit_json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http_v02::StatusCode;
+    use hyper_v014::{Body, Request};
+    use tokio::sync::mpsc;
+
+    use super::{handle_traces_api, TRACES_API_PATH};
+    use crate::rt_worker::internal_auth::InternalApiAuth;
+
+    #[tokio::test]
+    async fn test_traces_api_hides_error_detail() {
+        let (worker_pool_tx, _) = mpsc::unbounded_channel();
+        let req = |query: &str| {
+            Request::builder()
+                .uri(format!("{TRACES_API_PATH}?{query}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let auth = InternalApiAuth::default();
+        let res = handle_traces_api(&auth, &worker_pool_tx, req("limit=lots")).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let body = hyper_v014::body::to_bytes(res.into_body()).await.unwrap();
+
+        assert_eq!(&body[..], br#"{"msg":"invalid query"}"#);
+
+        let res = handle_traces_api(&auth, &worker_pool_tx, req("limit=1")).await;
+
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = hyper_v014::body::to_bytes(res.into_body()).await.unwrap();
+
+        assert_eq!(&body[..], br#"{"msg":"internal server error"}"#);
+    }
+}
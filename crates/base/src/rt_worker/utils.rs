@@ -1,5 +1,10 @@
+use std::any::Any;
+use std::path::Path;
+
+use anyhow::{bail, Error};
 use event_worker::events::{EventMetadata, WorkerEventWithMetadata};
-use sb_workers::context::{UserWorkerMsgs, WorkerRuntimeOpts};
+use sb_workers::context::{UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerRuntimeOpts};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
@@ -31,18 +36,145 @@ pub fn parse_worker_conf(conf: &WorkerRuntimeOpts) -> WorkerCoreConfig {
     worker_core
 }
 
+/// Returns the name of the thread of a worker, which leads with the identity
+/// of its service as the OS only keeps the first 15 bytes of it.
+pub fn get_thread_name(conf: &WorkerRuntimeOpts) -> String {
+    match conf {
+        WorkerRuntimeOpts::UserWorker(worker_opts) => {
+            format!("sb-w-{}", get_service_name(worker_opts))
+        }
+
+        WorkerRuntimeOpts::MainWorker(_) => "sb-main-worker".to_string(),
+        WorkerRuntimeOpts::EventsWorker(_) => "sb-events-worker".to_string(),
+    }
+}
+
+/// Returns the identity of the service of a user worker, or the name of its
+/// directory if it has none.
+pub fn get_service_name(worker_opts: &UserWorkerRuntimeOpts) -> String {
+    worker_opts
+        .identity
+        .as_ref()
+        .map(ToString::to_string)
+        .or_else(|| {
+            worker_opts
+                .service_path
+                .as_deref()
+                .map(Path::new)
+                .and_then(Path::file_name)
+                .map(|it| it.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
 pub fn get_event_metadata(conf: &WorkerRuntimeOpts) -> EventMetadata {
     let mut event_metadata = EventMetadata {
         service_path: None,
         execution_id: None,
+        worker_id: None,
     };
     if conf.is_user_worker() {
         let conf = conf.as_user_worker().unwrap();
         event_metadata = EventMetadata {
             service_path: conf.service_path.clone(),
             execution_id: conf.key,
+            worker_id: conf.identity.as_ref().map(ToString::to_string),
         };
     }
 
     event_metadata
 }
+
+/// Returns the message a panic was raised with.
+pub fn get_panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|it| it.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// Writes the body of the response to the writer as it arrives, and fails once
+/// it grows past `max_size` bytes, whatever its `Content-Length` claims.
+///
+/// Returns the size of the body.
+pub async fn write_body_bounded<W>(
+    mut res: reqwest_v011::Response,
+    max_size: u64,
+    writer: &mut W,
+) -> Result<u64, Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    if res.content_length().is_some_and(|it| it > max_size) {
+        bail!("response body is larger than {max_size} bytes");
+    }
+
+    let mut size = 0u64;
+
+    while let Some(chunk) = res.chunk().await? {
+        size += chunk.len() as u64;
+
+        if size > max_size {
+            bail!("response body is larger than {max_size} bytes");
+        }
+
+        writer.write_all(&chunk).await?;
+    }
+
+    writer.flush().await?;
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    use hyper_v014::service::{make_service_fn, service_fn};
+    use hyper_v014::{Body, Response, Server};
+
+    use super::write_body_bounded;
+
+    async fn serve(body: &'static [u8], chunked: bool) -> SocketAddr {
+        let make_svc = make_service_fn(move |_| async move {
+            Ok::<_, Infallible>(service_fn(move |_| async move {
+                let body = if chunked {
+                    Body::wrap_stream(futures_util::stream::iter(
+                        body.chunks(256).map(Ok::<_, Infallible>),
+                    ))
+                } else {
+                    Body::from(body)
+                };
+
+                Ok::<_, Infallible>(Response::new(body))
+            }))
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+
+        drop(tokio::spawn(server));
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_write_body_bounded() {
+        static BODY: [u8; 2048] = [7; 2048];
+
+        for chunked in [false, true] {
+            let addr = serve(&BODY, chunked).await;
+            let url = format!("http://{addr}/");
+            let mut buf = vec![];
+            let res = reqwest_v011::get(&url).await.unwrap();
+
+            assert_eq!(write_body_bounded(res, 4096, &mut buf).await.unwrap(), 2048);
+            assert_eq!(buf, BODY);
+
+            let res = reqwest_v011::get(&url).await.unwrap();
+
+            assert!(write_body_bounded(res, 1024, &mut vec![]).await.is_err());
+        }
+    }
+}
@@ -1,15 +1,18 @@
 use crate::deno_runtime::DenoRuntime;
 use crate::inspector_server::Inspector;
 use crate::rt_worker::supervisor;
-use crate::rt_worker::utils::{get_event_metadata, parse_worker_conf};
+use crate::rt_worker::utils::{
+    get_event_metadata, get_panic_message, get_thread_name, parse_worker_conf,
+};
 use crate::rt_worker::worker_ctx::create_supervisor;
 use crate::utils::send_event_if_event_worker_available;
 use anyhow::Error;
 use base_mem_check::MemCheckState;
 use base_rt::error::CloneableError;
 use event_worker::events::{
-    EventLoopCompletedEvent, EventMetadata, ShutdownEvent, ShutdownReason, UncaughtExceptionEvent,
-    WorkerEventWithMetadata, WorkerEvents, WorkerMemoryUsed,
+    BootStages, BootWarning, CrashedEvent, EventLoopCompletedEvent, EventMetadata, ShutdownEvent,
+    ShutdownReason, UncaughtExceptionEvent, WorkerEventWithMetadata, WorkerEvents,
+    WorkerMemoryUsed,
 };
 use futures_util::FutureExt;
 use log::{debug, error};
@@ -17,6 +20,7 @@ use sb_core::{MetricSource, RuntimeMetricSource, WorkerMetricSource};
 use sb_workers::context::{UserWorkerMsgs, WorkerContextInitOpts, WorkerExit, WorkerExitStatus};
 use std::any::Any;
 use std::future::{pending, Future};
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
 use std::time::Duration;
 use tokio::io;
@@ -88,18 +92,78 @@ impl Worker {
         self.supervisor_policy = supervisor_policy.unwrap_or_default();
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn start(
         &self,
+        opts: WorkerContextInitOpts,
+        duplex_stream_pair: (
+            UnboundedSender<DuplexStreamEntry>,
+            UnboundedReceiver<DuplexStreamEntry>,
+        ),
+        booter_signal: Sender<Result<(MetricSource, Vec<BootWarning>), Error>>,
+        boot_stages_tx: Sender<BootStages>,
+        exit: WorkerExit,
+        termination_token: Option<TerminationToken>,
+        inspector: Option<Inspector>,
+    ) {
+        let worker = self.clone();
+        let worker_kind = opts.conf.to_worker_kind();
+        let thread_name = get_thread_name(&opts.conf);
+        let rt = if worker_kind.is_user_worker() {
+            &base_rt::USER_WORKER_RT
+        } else {
+            &base_rt::PRIMARY_WORKER_RT
+        };
+
+        let crash_ctx = CrashContext {
+            exit: exit.clone(),
+            cancel: self.cancel.clone(),
+            maybe_pool_msg_tx: self.worker_key.zip(self.pool_msg_tx.clone()),
+            events_msg_tx: self.events_msg_tx.clone(),
+            event_metadata: self.event_metadata.clone(),
+        };
+
+        let create_task = move || {
+            tokio::task::spawn_local(crash_ctx.catch_panic(worker.run(
+                opts,
+                duplex_stream_pair,
+                booter_signal,
+                boot_stages_tx,
+                exit,
+                termination_token,
+                inspector,
+            )))
+        };
+
+        // NOTE: If the thread could not be spawned, the booter gives up on the
+        // worker as its signal is dropped along with the task.
+        if let Err(err) = base_rt::topology::spawn_isolate(
+            rt,
+            worker_kind.is_user_worker(),
+            thread_name,
+            create_task,
+        ) {
+            error!("failed to spawn the worker thread: {}", err);
+        }
+    }
+
+    /// Boots the runtime of the worker and runs it until it exits, on the
+    /// thread of the worker.
+    #[allow(clippy::too_many_arguments)]
+    async fn run(
+        self,
         mut opts: WorkerContextInitOpts,
         duplex_stream_pair: (
             UnboundedSender<DuplexStreamEntry>,
             UnboundedReceiver<DuplexStreamEntry>,
         ),
-        booter_signal: Sender<Result<MetricSource, Error>>,
+        booter_signal: Sender<Result<(MetricSource, Vec<BootWarning>), Error>>,
+        boot_stages_tx: Sender<BootStages>,
         exit: WorkerExit,
         termination_token: Option<TerminationToken>,
         inspector: Option<Inspector>,
     ) {
+        let worker_boot_start_time = self.worker_boot_start_time;
         let worker_name = self.worker_name.clone();
         let worker_key = self.worker_key;
         let event_metadata = self.event_metadata.clone();
@@ -109,266 +173,382 @@ impl Worker {
         let events_msg_tx = self.events_msg_tx.clone();
         let pool_msg_tx = self.pool_msg_tx.clone();
 
-        let method_cloner = self.clone();
         let timing = opts.timing.take();
         let worker_kind = opts.conf.to_worker_kind();
-
         let cancel = self.cancel.clone();
-        let rt = if worker_kind.is_user_worker() {
-            &base_rt::USER_WORKER_RT
-        } else {
-            &base_rt::PRIMARY_WORKER_RT
-        };
-
-        let _worker_handle = rt.spawn_pinned(move || {
-            tokio::task::spawn_local(async move {
-                let (maybe_cpu_usage_metrics_tx, maybe_cpu_usage_metrics_rx) = worker_kind
-                    .is_user_worker()
-                    .then(unbounded_channel::<CPUUsageMetrics>)
-                    .unzip();
-
-                let permit = DenoRuntime::acquire().await;
-                let result = match DenoRuntime::new(opts, inspector).await {
-                    Ok(new_runtime) => {
-                        let mut runtime = scopeguard::guard(new_runtime, |mut runtime| unsafe {
-                            runtime.js_runtime.v8_isolate().enter();
-                        });
 
-                        unsafe {
-                            runtime.js_runtime.v8_isolate().exit();
-                        }
-
-                        drop(permit);
-
-                        let metric_src = {
-                            let metric_src =
-                                WorkerMetricSource::from_js_runtime(&mut runtime.js_runtime);
-                            if let Some(opts) = runtime.conf.as_main_worker().cloned() {
-                                let state = runtime.js_runtime.op_state();
-                                let mut state_mut = state.borrow_mut();
-                                let metric_src = RuntimeMetricSource::new(
-                                    metric_src.clone(),
-                                    opts.event_worker_metric_src
-                                        .and_then(|it| it.into_worker().ok()),
-                                    opts.shared_metric_src,
-                                );
-
-                                state_mut.put(metric_src.clone());
-                                MetricSource::Runtime(metric_src)
-                            } else {
-                                MetricSource::Worker(metric_src)
-                            }
-                        };
-
-                        let _ = booter_signal.send(Ok(metric_src));
-
-                        // CPU TIMER
-                        let (termination_event_tx, termination_event_rx) =
-                            oneshot::channel::<WorkerEvents>();
-
-                        let _cpu_timer;
-                        let mut supervise_cancel_token = None;
-
-                        let termination_fut = if worker_kind.is_user_worker() {
-                            // cputimer is returned from supervisor and assigned here to keep it in scope.
-                            let Ok((maybe_timer, cancel_token)) = create_supervisor(
-                                worker_key.unwrap_or(Uuid::nil()),
-                                &mut runtime,
-                                supervisor_policy,
-                                termination_event_tx,
-                                pool_msg_tx.clone(),
-                                maybe_cpu_usage_metrics_rx,
-                                cancel,
-                                timing,
-                                termination_token.clone(),
-                            ) else {
-                                return;
-                            };
-
-                            _cpu_timer = maybe_timer;
-                            supervise_cancel_token = Some(cancel_token);
-
-                            pending().boxed()
-                        } else if let Some(token) = termination_token.clone() {
-                            let is_terminated = runtime.is_terminated.clone();
-                            let termination_request_token =
-                                runtime.termination_request_token.clone();
-
-                            let (waker, thread_safe_handle) = {
-                                let js_runtime = &mut runtime.js_runtime;
-                                (
-                                    js_runtime.op_state().borrow().waker.clone(),
-                                    js_runtime.v8_isolate().thread_safe_handle(),
-                                )
-                            };
-
-                            let maybe_event_worker_ctx =
-                                runtime.conf.as_events_worker().map(|it| {
-                                    Duration::from_secs(
-                                        it.event_worker_exit_deadline_sec.unwrap_or(10),
-                                    )
-                                });
-
-                            base_rt::SUPERVISOR_RT
-                                .spawn(async move {
-                                    token.inbound.cancelled().await;
-
-                                    let mut already_terminated = false;
-                                    if let Some(dur) = maybe_event_worker_ctx {
-                                        already_terminated = tokio::time::timeout(dur, async {
-                                            while !is_terminated.is_raised() {
-                                                waker.wake();
-                                                tokio::task::yield_now().await;
-                                            }
-                                        })
-                                        .await
-                                        .is_ok();
-                                    }
+        let thread_spawn_us = worker_boot_start_time.elapsed().as_micros() as u64;
+        let boot_queue_us = opts
+            .conf
+            .as_user_worker()
+            .map(|it| it.boot_queue_us)
+            .unwrap_or_default();
+        let (maybe_cpu_usage_metrics_tx, maybe_cpu_usage_metrics_rx) = worker_kind
+            .is_user_worker()
+            .then(unbounded_channel::<CPUUsageMetrics>)
+            .unzip();
+
+        let creation_queue_start_time = Instant::now();
+        let permit = DenoRuntime::acquire().await;
+        let creation_queue_us = creation_queue_start_time.elapsed().as_micros() as u64;
+
+        let result = match DenoRuntime::new(opts, inspector).await {
+            Ok(mut new_runtime) => {
+                new_runtime.boot_stages.boot_queue_us = boot_queue_us;
+                new_runtime.boot_stages.thread_spawn_us = thread_spawn_us;
+                new_runtime.boot_stages.creation_queue_us = creation_queue_us;
+                new_runtime.boot_stages_tx = Some(boot_stages_tx);
+
+                let mut runtime = scopeguard::guard(new_runtime, |mut runtime| unsafe {
+                    runtime.js_runtime.v8_isolate().enter();
+                });
+
+                unsafe {
+                    runtime.js_runtime.v8_isolate().exit();
+                }
+
+                drop(permit);
+
+                let metric_src = {
+                    let metric_src = WorkerMetricSource::from_js_runtime(&mut runtime.js_runtime);
+                    if let Some(opts) = runtime.conf.as_main_worker().cloned() {
+                        let state = runtime.js_runtime.op_state();
+                        let mut state_mut = state.borrow_mut();
+                        let metric_src = RuntimeMetricSource::new(
+                            metric_src.clone(),
+                            opts.event_worker_metric_src
+                                .and_then(|it| it.into_worker().ok()),
+                            opts.shared_metric_src,
+                        );
 
-                                    if !already_terminated {
-                                        termination_request_token.cancel();
-
-                                        let data_ptr_mut = Box::into_raw(Box::new(
-                                            supervisor::IsolateInterruptData {
-                                                should_terminate: true,
-                                                isolate_memory_usage_tx: None,
-                                            },
-                                        ));
-
-                                        if !thread_safe_handle.request_interrupt(
-                                            supervisor::handle_interrupt,
-                                            data_ptr_mut as *mut std::ffi::c_void,
-                                        ) {
-                                            drop(unsafe { Box::from_raw(data_ptr_mut) });
-                                        }
-
-                                        while !is_terminated.is_raised() {
-                                            waker.wake();
-                                            tokio::task::yield_now().await;
-                                        }
-                                    }
+                        state_mut.put(metric_src.clone());
+                        MetricSource::Runtime(metric_src)
+                    } else {
+                        MetricSource::Worker(metric_src)
+                    }
+                };
 
-                                    let _ = termination_event_tx.send(WorkerEvents::Shutdown(
-                                        ShutdownEvent {
-                                            reason: ShutdownReason::TerminationRequested,
-                                            cpu_time_used: 0,
-                                            memory_used: WorkerMemoryUsed {
-                                                total: 0,
-                                                heap: 0,
-                                                external: 0,
-                                                mem_check_captured: MemCheckState::default(),
-                                            },
-                                        },
-                                    ));
-                                })
-                                .boxed()
-                        } else {
-                            pending().boxed()
-                        };
-
-                        let _guard = scopeguard::guard((), |_| {
-                            worker_key.and_then(|worker_key_unwrapped| {
-                                pool_msg_tx.map(|tx| {
-                                    if let Err(err) =
-                                        tx.send(UserWorkerMsgs::Shutdown(worker_key_unwrapped))
-                                    {
-                                        error!(
-                                            "failed to send the shutdown signal to user worker pool: {:?}",
-                                            err
-                                        );
+                let boot_warnings = std::mem::take(&mut runtime.boot_warnings);
+                let _ = booter_signal.send(Ok((metric_src, boot_warnings)));
+
+                // CPU TIMER
+                let (termination_event_tx, termination_event_rx) =
+                    oneshot::channel::<WorkerEvents>();
+
+                let _cpu_timer;
+                let mut supervise_cancel_token = None;
+
+                let termination_fut = if worker_kind.is_user_worker() {
+                    // cputimer is returned from supervisor and assigned here to keep it in scope.
+                    let Ok((maybe_timer, cancel_token)) = create_supervisor(
+                        worker_key.unwrap_or(Uuid::nil()),
+                        &mut runtime,
+                        supervisor_policy,
+                        termination_event_tx,
+                        pool_msg_tx.clone(),
+                        maybe_cpu_usage_metrics_rx,
+                        cancel,
+                        timing,
+                        termination_token.clone(),
+                    ) else {
+                        return;
+                    };
+
+                    _cpu_timer = maybe_timer;
+                    supervise_cancel_token = Some(cancel_token);
+
+                    pending().boxed()
+                } else if let Some(token) = termination_token.clone() {
+                    let is_terminated = runtime.is_terminated.clone();
+                    let termination_request_token = runtime.termination_request_token.clone();
+
+                    let (waker, thread_safe_handle) = {
+                        let js_runtime = &mut runtime.js_runtime;
+                        (
+                            js_runtime.op_state().borrow().waker.clone(),
+                            js_runtime.v8_isolate().thread_safe_handle(),
+                        )
+                    };
+
+                    let maybe_event_worker_ctx = runtime.conf.as_events_worker().map(|it| {
+                        Duration::from_secs(it.event_worker_exit_deadline_sec.unwrap_or(10))
+                    });
+
+                    base_rt::SUPERVISOR_RT
+                        .spawn(async move {
+                            token.inbound.cancelled().await;
+
+                            let mut already_terminated = false;
+                            if let Some(dur) = maybe_event_worker_ctx {
+                                already_terminated = tokio::time::timeout(dur, async {
+                                    while !is_terminated.is_raised() {
+                                        waker.wake();
+                                        tokio::task::yield_now().await;
                                     }
                                 })
-                            });
-                        });
+                                .await
+                                .is_ok();
+                            }
 
-                        let result = {
-                            let supervise_cancel_token =
-                                scopeguard::guard_on_unwind(supervise_cancel_token, |token| {
-                                    if let Some(token) = token {
-                                        token.cancel();
-                                    }
-                                });
-
-                            let result = method_cloner
-                                .handle_creation(
-                                    &mut runtime,
-                                    duplex_stream_rx,
-                                    termination_event_rx,
-                                    maybe_cpu_usage_metrics_tx,
-                                    Some(worker_name),
-                                )
-                                .await;
-
-                            let maybe_uncaught_exception_event = match result.as_ref() {
-                                Ok(WorkerEvents::UncaughtException(ev)) => Some(ev.clone()),
-                                Err(err) => Some(UncaughtExceptionEvent {
-                                    cpu_time_used: 0,
-                                    exception: err.to_string(),
-                                }),
+                            if !already_terminated {
+                                termination_request_token.cancel();
 
-                                _ => None,
-                            };
+                                let data_ptr_mut =
+                                    Box::into_raw(Box::new(supervisor::IsolateInterruptData {
+                                        should_terminate: true,
+                                        isolate_memory_usage_tx: None,
+                                    }));
 
-                            if let Some(ev) = maybe_uncaught_exception_event {
-                                exit.set(WorkerExitStatus::WithUncaughtException(ev)).await;
+                                if !thread_safe_handle.request_interrupt(
+                                    supervisor::handle_interrupt,
+                                    data_ptr_mut as *mut std::ffi::c_void,
+                                ) {
+                                    drop(unsafe { Box::from_raw(data_ptr_mut) });
+                                }
 
-                                if let Some(token) = supervise_cancel_token.as_ref() {
-                                    token.cancel();
+                                while !is_terminated.is_raised() {
+                                    waker.wake();
+                                    tokio::task::yield_now().await;
                                 }
                             }
 
-                            result
-                        };
+                            let _ =
+                                termination_event_tx.send(WorkerEvents::Shutdown(ShutdownEvent {
+                                    reason: ShutdownReason::TerminationRequested,
+                                    cpu_time_used: 0,
+                                    memory_used: WorkerMemoryUsed {
+                                        total: 0,
+                                        heap: 0,
+                                        external: 0,
+                                        mem_check_captured: MemCheckState::default(),
+                                    },
+                                }));
+                        })
+                        .boxed()
+                } else {
+                    pending().boxed()
+                };
 
-                        if let Some(token) = termination_token.as_ref() {
-                            if !worker_kind.is_user_worker() {
-                                let _ = termination_fut.await;
-                                token.outbound.cancel();
+                let _guard = scopeguard::guard((), |_| {
+                    worker_key.and_then(|worker_key_unwrapped| {
+                        pool_msg_tx.map(|tx| {
+                            if let Err(err) =
+                                tx.send(UserWorkerMsgs::Shutdown(worker_key_unwrapped))
+                            {
+                                error!(
+                                    "failed to send the shutdown signal to user worker pool: {:?}",
+                                    err
+                                );
                             }
+                        })
+                    });
+                });
+
+                let result = {
+                    let supervise_cancel_token =
+                        scopeguard::guard_on_unwind(supervise_cancel_token, |token| {
+                            if let Some(token) = token {
+                                token.cancel();
+                            }
+                        });
+
+                    let result = self
+                        .handle_creation(
+                            &mut runtime,
+                            duplex_stream_rx,
+                            termination_event_rx,
+                            maybe_cpu_usage_metrics_tx,
+                            Some(worker_name),
+                        )
+                        .await;
+
+                    let maybe_uncaught_exception_event = match result.as_ref() {
+                        Ok(WorkerEvents::UncaughtException(ev)) => Some(ev.clone()),
+                        Err(err) => Some(UncaughtExceptionEvent {
+                            cpu_time_used: 0,
+                            exception: err.to_string(),
+                        }),
+
+                        _ => None,
+                    };
+
+                    if let Some(ev) = maybe_uncaught_exception_event {
+                        exit.set(WorkerExitStatus::WithUncaughtException(ev)).await;
+
+                        if let Some(token) = supervise_cancel_token.as_ref() {
+                            token.cancel();
                         }
+                    }
+
+                    result
+                };
 
-                        result
+                if let Some(token) = termination_token.as_ref() {
+                    if !worker_kind.is_user_worker() {
+                        let _ = termination_fut.await;
+                        token.outbound.cancel();
                     }
+                }
+
+                result
+            }
 
-                    Err(err) => {
-                        drop(permit);
+            Err(err) => {
+                drop(permit);
 
-                        let err = CloneableError::from(err.context("worker boot error"));
-                        let _ = booter_signal.send(Err(err.clone().into()));
+                let err = CloneableError::from(err.context("worker boot error"));
+                let _ = booter_signal.send(Err(err.clone().into()));
 
-                        method_cloner.handle_error(err.into())
+                self.handle_error(err.into())
+            }
+        };
+
+        drop(duplex_stream_tx);
+
+        match result {
+            Ok(event) => {
+                match event {
+                    WorkerEvents::Shutdown(ShutdownEvent { cpu_time_used, .. })
+                    | WorkerEvents::UncaughtException(UncaughtExceptionEvent {
+                        cpu_time_used,
+                        ..
+                    })
+                    | WorkerEvents::EventLoopCompleted(EventLoopCompletedEvent {
+                        cpu_time_used,
+                        ..
+                    }) => {
+                        debug!("CPU time used: {:?}ms", cpu_time_used);
                     }
+
+                    _ => {}
                 };
 
-                drop(duplex_stream_tx);
-
-                match result {
-                    Ok(event) => {
-                        match event {
-                            WorkerEvents::Shutdown(ShutdownEvent { cpu_time_used, .. })
-                            | WorkerEvents::UncaughtException(UncaughtExceptionEvent {
-                                cpu_time_used,
-                                ..
-                            })
-                            | WorkerEvents::EventLoopCompleted(EventLoopCompletedEvent {
-                                cpu_time_used,
-                                ..
-                            }) => {
-                                debug!("CPU time used: {:?}ms", cpu_time_used);
-                            }
+                send_event_if_event_worker_available(
+                    events_msg_tx.as_ref(),
+                    event,
+                    event_metadata.clone(),
+                );
+            }
+            Err(err) => error!("unexpected worker error {}", err),
+        };
+    }
+}
+
+/// What is needed to clean up after a worker whose thread panicked.
+struct CrashContext {
+    exit: WorkerExit,
+    cancel: Option<CancellationToken>,
+    maybe_pool_msg_tx: Option<(Uuid, UnboundedSender<UserWorkerMsgs>)>,
+    events_msg_tx: Option<UnboundedSender<WorkerEventWithMetadata>>,
+    event_metadata: EventMetadata,
+}
 
-                            _ => {}
-                        };
+impl CrashContext {
+    /// Runs the future of a worker, and reports the worker as crashed if it
+    /// panics.
+    ///
+    /// NOTE: A panic is caught at the boundary of the thread of the worker so
+    /// that it only takes the worker down. This is not possible if the runtime
+    /// is built with `panic = "abort"`.
+    async fn catch_panic(self, worker_fut: impl Future<Output = ()>) {
+        let Err(payload) = AssertUnwindSafe(worker_fut).catch_unwind().await else {
+            return;
+        };
 
-                        send_event_if_event_worker_available(
-                            events_msg_tx.as_ref(),
-                            event,
-                            event_metadata.clone(),
-                        );
-                    }
-                    Err(err) => error!("unexpected worker error {}", err),
-                };
+        let message = get_panic_message(&*payload);
+
+        error!("worker thread panicked: {message}");
+        self.exit
+            .set(WorkerExitStatus::Crashed(CrashedEvent {
+                message: message.clone(),
+            }))
+            .await;
+
+        if let Some(token) = self.cancel {
+            token.cancel();
+        }
+
+        // NOTE: The worker may have panicked before it was added to the pool,
+        // in which case the shutdown is a no-op.
+        if let Some((key, tx)) = self.maybe_pool_msg_tx {
+            let _ = tx.send(UserWorkerMsgs::Shutdown(key));
+        }
+
+        send_event_if_event_worker_available(
+            self.events_msg_tx.as_ref(),
+            WorkerEvents::Crashed(CrashedEvent { message }),
+            self.event_metadata,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use event_worker::events::{EventMetadata, WorkerEvents};
+    use sb_workers::context::{UserWorkerMsgs, WorkerExit};
+    use tokio::sync::mpsc::unbounded_channel;
+    use tokio_util::sync::CancellationToken;
+    use uuid::Uuid;
+
+    use super::CrashContext;
+
+    #[tokio::test]
+    async fn test_panic_is_reported_as_crash() {
+        let exit = WorkerExit::default();
+        let cancel = CancellationToken::new();
+        let (pool_tx, mut pool_rx) = unbounded_channel();
+        let (events_tx, mut events_rx) = unbounded_channel();
+        let key = Uuid::new_v4();
+
+        let crash_ctx = CrashContext {
+            exit: exit.clone(),
+            cancel: Some(cancel.clone()),
+            maybe_pool_msg_tx: Some((key, pool_tx)),
+            events_msg_tx: Some(events_tx),
+            event_metadata: EventMetadata::default(),
+        };
+
+        crash_ctx
+            .catch_panic(async {
+                panic!("boom");
             })
-        });
+            .await;
+
+        assert_eq!(
+            exit.error().await.unwrap().to_string(),
+            "worker crashed: boom"
+        );
+        assert!(cancel.is_cancelled());
+        assert!(matches!(
+            pool_rx.recv().await,
+            Some(UserWorkerMsgs::Shutdown(it)) if it == key
+        ));
+
+        let WorkerEvents::Crashed(event) = events_rx.recv().await.unwrap().event else {
+            panic!("expected a crash event");
+        };
+
+        assert_eq!(event.message, "boom");
+    }
+
+    #[tokio::test]
+    async fn test_worker_without_panic_is_left_alone() {
+        let exit = WorkerExit::default();
+        let cancel = CancellationToken::new();
+        let (events_tx, mut events_rx) = unbounded_channel();
+
+        CrashContext {
+            exit: exit.clone(),
+            cancel: Some(cancel.clone()),
+            maybe_pool_msg_tx: None,
+            events_msg_tx: Some(events_tx),
+            event_metadata: EventMetadata::default(),
+        }
+        .catch_panic(async {})
+        .await;
+
+        assert!(exit.error().await.is_none());
+        assert!(!cancel.is_cancelled());
+        assert!(events_rx.try_recv().is_err());
     }
 }
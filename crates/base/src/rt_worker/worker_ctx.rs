@@ -8,12 +8,16 @@ use crate::rt_worker::worker::{Worker, WorkerHandler};
 use crate::rt_worker::worker_pool::WorkerPool;
 use anyhow::{anyhow, bail, Error};
 use base_mem_check::MemCheckState;
+use base_rt::thread_name::OsThreadNameGuard;
 use cpu_timer::CPUTimer;
 use deno_config::JsxImportSourceConfig;
 use deno_core::{InspectorSessionProxy, LocalInspectorSession};
+use event_worker::backlog::EVENTS_BACKLOG;
 use event_worker::events::{
-    BootEvent, ShutdownEvent, WorkerEventWithMetadata, WorkerEvents, WorkerMemoryUsed,
+    BootEvent, BootStages, BootWarning, ShutdownEvent, WorkerEventWithMetadata, WorkerEvents,
+    WorkerMemoryUsed,
 };
+use event_worker::history::LIFECYCLE_HISTORY;
 use futures_util::pin_mut;
 use http_utils::io::Upgraded2;
 use http_utils::utils::{emit_status_code, get_upgrade_type};
@@ -24,26 +28,39 @@ use hyper_v014::{Body, Request, Response};
 use log::{debug, error};
 use sb_core::{MetricSource, SharedMetricSource};
 use sb_graph::{DecoratorType, EszipPayloadKind};
+use sb_workers::builder::{EventsWorkerBuilder, MainWorkerBuilder};
 use sb_workers::context::{
-    EventWorkerRuntimeOpts, MainWorkerRuntimeOpts, Timing, UserWorkerMsgs, WorkerContextInitOpts,
-    WorkerExit, WorkerKind, WorkerRequestMsg, WorkerRuntimeOpts,
+    CreateUserWorkerResult, EventWorkerRuntimeOpts, MainWorkerLiveness, MainWorkerRuntimeOpts,
+    SendRequestResult, Timing, UserWorkerMsgs, WorkerContextInitOpts, WorkerExit, WorkerKind,
+    WorkerRequestMsg,
 };
 use sb_workers::errors::WorkerError;
+use sb_workers::invoke::{InvokeChain, InvokeChains};
+use sb_workers::request_limits::{take_request_limits, RequestLimits};
 use std::future::pending;
 use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{self, copy_bidirectional};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::{mpsc, oneshot, Mutex};
-use tokio::time::sleep;
+use tokio::time::{sleep, MissedTickBehavior};
 use tokio_rustls::server::TlsStream;
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+use super::autoscaler::AUTOSCALE_INTERVAL;
+use super::bundle_signature::verify_bundle_file;
+use super::deadline::{get_deadline, get_time_left};
+use super::main_worker_watchdog::create_watched_main_worker;
+use super::pool_state::{get_service_revision, PoolState};
+use super::runtime_stats::report_runtime_stats;
+use super::service_stats::report_service_stats;
 use super::supervisor::{self, CPUTimerParam, CPUUsageMetrics};
+use super::utils::get_service_name;
 use super::worker::DuplexStreamEntry;
 use super::worker_pool::{SupervisorPolicy, WorkerPoolPolicy};
 
@@ -99,6 +116,7 @@ async fn handle_request(
     duplex_stream_tx: mpsc::UnboundedSender<DuplexStreamEntry>,
     msg: WorkerRequestMsg,
     maybe_request_idle_timeout: Option<u64>,
+    invoke_chains: InvokeChains,
 ) -> Result<(), Error> {
     let (ours, theirs) = io::duplex(1024);
     let WorkerRequestMsg {
@@ -107,6 +125,34 @@ async fn handle_request(
         conn_token,
     } = msg;
 
+    let maybe_deadline = get_deadline(&req);
+
+    // NOTE: The worker is handling the invocation until the exchange is over,
+    // its response body included.
+    let maybe_invoke_chain_guard = req
+        .extensions_mut()
+        .remove::<Arc<InvokeChain>>()
+        .map(|it| invoke_chains.enter(it));
+
+    // NOTE: The limits header is always taken off the request, but it is only
+    // honored by the user workers.
+    let maybe_request_idle_timeout = match take_request_limits(&mut req) {
+        Some(RequestLimits {
+            request_idle_timeout_ms: Some(timeout_ms),
+        }) if worker_kind.is_user_worker() => Some(timeout_ms).filter(|it| *it > 0),
+        _ => maybe_request_idle_timeout,
+    };
+
+    if maybe_deadline.is_some_and(|it| get_time_left(it).is_zero()) {
+        drop(res_tx.send(Ok(emit_status_code(
+            StatusCode::GATEWAY_TIMEOUT,
+            None,
+            false,
+        ))));
+
+        return Ok(());
+    }
+
     let _ = duplex_stream_tx.send((theirs, conn_token.clone()));
     let req_upgrade_type = get_upgrade_type(req.headers());
     let req_upgrade = req_upgrade_type
@@ -122,6 +168,8 @@ async fn handle_request(
     // spawn a task to poll the connection and drive the HTTP state
     tokio::task::spawn({
         async move {
+            let _invoke_chain_guard = maybe_invoke_chain_guard;
+
             match connection.without_shutdown().await {
                 Err(e) => {
                     error!(
@@ -165,11 +213,21 @@ async fn handle_request(
         }
     };
 
+    let deadline_fut = async move {
+        match maybe_deadline {
+            Some(deadline) => sleep(get_time_left(deadline)).await,
+            None => pending().await,
+        }
+    };
+
     let res = tokio::select! {
         resp = request_sender.send_request(req) => resp,
         _ = maybe_cancel_fut => {
             Ok(emit_status_code(http_v02::StatusCode::GATEWAY_TIMEOUT, None, false))
         }
+        _ = deadline_fut => {
+            Ok(emit_status_code(http_v02::StatusCode::GATEWAY_TIMEOUT, None, false))
+        }
     };
 
     let Ok(res) = res else {
@@ -274,6 +332,27 @@ pub fn create_supervisor(
     let mem_check_state = worker_runtime.mem_check_state();
     let termination_request_token = worker_runtime.termination_request_token.clone();
 
+    // NOTE: A worker that seems to leak memory is drained as if the pool had
+    // asked for it, so that a fresh one takes over its next requests.
+    if let Some(inbound) = termination_token
+        .as_ref()
+        .filter(|_| conf.recycle_on_memory_leak)
+        .map(|it| it.inbound.clone())
+    {
+        let leak_token = worker_runtime.memory_leak_token();
+        let drop_token = worker_runtime.drop_token.clone();
+
+        drop(base_rt::SUPERVISOR_RT.spawn(async move {
+            tokio::select! {
+                _ = leak_token.cancelled() => {
+                    debug!("recycling a worker that seems to leak memory: isolate: {:?}", key);
+                    inbound.cancel();
+                }
+                _ = drop_token.cancelled() => {}
+            }
+        }));
+    }
+
     let giveup_process_requests_token = cancel.clone();
     let supervise_cancel_token = CancellationToken::new();
     let tokens = supervisor::Tokens {
@@ -378,9 +457,11 @@ pub fn create_supervisor(
                 use deno_core::serde_json::Value;
 
                 let termination_request_token = termination_request_token.clone();
+                let thread_name = format!("sb-sup-{}", get_service_name(&conf));
 
                 base_rt::SUPERVISOR_RT
                     .spawn_blocking(move || {
+                        let _thread_name_guard = OsThreadNameGuard::new(&thread_name);
                         let wait_inspector_disconnect_fut = async move {
                             let ls = tokio::task::LocalSet::new();
                             ls.run_until(async move {
@@ -573,6 +654,7 @@ pub struct WorkerCtx {
     pub metric: MetricSource,
     pub msg_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
     pub exit: WorkerExit,
+    pub boot_warnings: Vec<BootWarning>,
 }
 
 pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
@@ -582,12 +664,19 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
 ) -> Result<WorkerCtx, Error> {
     let (duplex_stream_tx, duplex_stream_rx) = mpsc::unbounded_channel::<DuplexStreamEntry>();
     let (worker_boot_result_tx, worker_boot_result_rx) =
-        oneshot::channel::<Result<MetricSource, Error>>();
+        oneshot::channel::<Result<(MetricSource, Vec<BootWarning>), Error>>();
+    let (boot_stages_tx, boot_stages_rx) = oneshot::channel::<BootStages>();
 
-    let CreateWorkerArgs(worker_init_opts, maybe_supervisor_policy, maybe_termination_token) =
+    let CreateWorkerArgs(mut worker_init_opts, maybe_supervisor_policy, maybe_termination_token) =
         init_opts.into();
 
     let worker_kind = worker_init_opts.conf.to_worker_kind();
+    let invoke_chains = InvokeChains::default();
+
+    if let Some(conf) = worker_init_opts.conf.as_user_worker_mut() {
+        conf.invoke_chains = Some(invoke_chains.clone());
+    }
+
     let exit = WorkerExit::default();
     let mut worker = Worker::new(&worker_init_opts)?;
 
@@ -607,6 +696,7 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
             worker_init_opts,
             (duplex_stream_tx.clone(), duplex_stream_rx),
             worker_boot_result_tx,
+            boot_stages_tx,
             exit.clone(),
             maybe_termination_token.clone(),
             inspector,
@@ -621,12 +711,14 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
                 while let Some(msg) = worker_req_rx.recv().await {
                     tokio::task::spawn({
                         let stream_tx_inner = stream_tx.clone();
+                        let invoke_chains = invoke_chains.clone();
                         async move {
                             if let Err(err) = handle_request(
                                 worker_kind,
                                 stream_tx_inner,
                                 msg,
                                 maybe_request_idle_timeout,
+                                invoke_chains,
                             )
                             .await
                             {
@@ -642,24 +734,42 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
 
         // wait for worker to be successfully booted
         match worker_boot_result_rx.await? {
-            Ok(metric) => {
-                let elapsed = worker_struct_ref
-                    .worker_boot_start_time
-                    .elapsed()
-                    .as_millis();
-
-                send_event_if_event_worker_available(
-                    worker_struct_ref.events_msg_tx.as_ref(),
-                    WorkerEvents::Boot(BootEvent {
-                        boot_time: elapsed as usize,
-                    }),
-                    worker_struct_ref.event_metadata.clone(),
-                );
+            Ok((metric, boot_warnings)) => {
+                let boot_start_time = worker_struct_ref.worker_boot_start_time;
+                let elapsed = boot_start_time.elapsed().as_millis();
+
+                // NOTE: The event is held until the main module has been
+                // evaluated, so that the whole cold start can be accounted
+                // for.
+                if let Some(events_msg_tx) = worker_struct_ref.events_msg_tx.as_ref() {
+                    let events_msg_tx = events_msg_tx.downgrade();
+                    let event_metadata = worker_struct_ref.event_metadata.clone();
+                    let warnings = boot_warnings.clone();
+
+                    drop(tokio::spawn(async move {
+                        let stages = boot_stages_rx.await.ok();
+                        let first_byte_ready_ms = stages
+                            .is_some()
+                            .then(|| boot_start_time.elapsed().as_millis() as u64);
+
+                        send_event_if_event_worker_available(
+                            events_msg_tx.upgrade().as_ref(),
+                            WorkerEvents::Boot(BootEvent {
+                                boot_time: elapsed as usize,
+                                stages,
+                                first_byte_ready_ms,
+                                warnings,
+                            }),
+                            event_metadata,
+                        );
+                    }));
+                }
 
                 Ok(WorkerCtx {
                     metric,
                     msg_tx: worker_req_tx,
                     exit,
+                    boot_warnings,
                 })
             }
             Err(err) => {
@@ -692,7 +802,9 @@ pub async fn send_user_worker_request(
     };
 
     // send the message to worker
-    worker_request_msg_tx.send(msg)?;
+    worker_request_msg_tx
+        .send(msg)
+        .map_err(|_| WorkerError::WorkerGone)?;
 
     // wait for the response back from the worker
     let res = tokio::select! {
@@ -703,7 +815,7 @@ pub async fn send_user_worker_request(
                 .unwrap_or(anyhow!(WorkerError::RequestCancelledBySupervisor)))
         }
 
-        res = res_rx => res,
+        res = res_rx => res.map_err(|_| WorkerError::WorkerGone),
     }?;
 
     match res {
@@ -735,38 +847,49 @@ pub async fn create_main_worker(
     inspector: Option<Inspector>,
     jsx: Option<JsxImportSourceConfig>,
 ) -> Result<mpsc::UnboundedSender<WorkerRequestMsg>, Error> {
-    let mut service_path = main_worker_path.clone();
-    let mut maybe_eszip = None;
-    if let Some(ext) = main_worker_path.extension() {
-        if ext == "eszip" {
-            service_path = main_worker_path.parent().unwrap().to_path_buf();
-            maybe_eszip = Some(EszipPayloadKind::VecKind(std::fs::read(main_worker_path)?));
+    let unresponsive_timeout_ms = runtime_opts.limits.unresponsive_timeout_ms;
+    let build_opts = move |maybe_liveness: Option<MainWorkerLiveness>| -> Result<_, Error> {
+        let mut service_path = main_worker_path.clone();
+        let mut maybe_eszip = None;
+        if let Some(ext) = main_worker_path.extension() {
+            if ext == "eszip" {
+                service_path = main_worker_path.parent().unwrap().to_path_buf();
+                let data = std::fs::read(&main_worker_path)?;
+
+                verify_bundle_file(&main_worker_path, &data)?;
+                maybe_eszip = Some(EszipPayloadKind::VecKind(data));
+            }
         }
-    }
 
-    let ctx = create_worker(
-        (
-            WorkerContextInitOpts {
-                service_path,
-                import_map_path,
-                no_module_cache,
-                timing: None,
-                maybe_eszip,
-                maybe_entrypoint,
-                maybe_decorator,
-                maybe_module_code: None,
-                conf: WorkerRuntimeOpts::MainWorker(runtime_opts),
-                env_vars: std::env::vars().collect(),
-                static_patterns: vec![],
-                maybe_jsx_import_source_config: jsx,
-            },
+        let runtime_opts = MainWorkerRuntimeOpts {
+            liveness: maybe_liveness,
+            ..runtime_opts.clone()
+        };
+
+        Ok(MainWorkerBuilder::new(service_path, runtime_opts)
+            .import_map_path(import_map_path.clone())
+            .no_module_cache(no_module_cache)
+            .eszip(maybe_eszip)
+            .entrypoint(maybe_entrypoint.clone())
+            .decorator(maybe_decorator)
+            .env_vars(std::env::vars().collect())
+            .jsx_import_source_config(jsx.clone())
+            .build()?)
+    };
+
+    if unresponsive_timeout_ms > 0 {
+        return create_watched_main_worker(
+            move |liveness| build_opts(Some(liveness)),
             termination_token,
-        ),
-        inspector,
-        None,
-    )
-    .await
-    .map_err(|err| anyhow!("main worker boot error: {}", err))?;
+            inspector,
+            Duration::from_millis(unresponsive_timeout_ms),
+        )
+        .await;
+    }
+
+    let ctx = create_worker((build_opts(None)?, termination_token), inspector, None)
+        .await
+        .map_err(|err| anyhow!("main worker boot error: {}", err))?;
 
     Ok(ctx.msg_tx)
 }
@@ -781,43 +904,42 @@ pub async fn create_events_worker(
 ) -> Result<(WorkerCtx, mpsc::UnboundedSender<WorkerEventWithMetadata>), Error> {
     let (events_tx, events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
 
+    EVENTS_BACKLOG.set_limit(flags.events_backlog_limit.unwrap_or_default());
+
+    if let Some(capacity) = flags.lifecycle_history_size {
+        LIFECYCLE_HISTORY.set_capacity(capacity);
+    }
+
     let mut service_path = events_worker_path.clone();
     let mut maybe_eszip = None;
     if let Some(ext) = events_worker_path.extension() {
         if ext == "eszip" {
             service_path = events_worker_path.parent().unwrap().to_path_buf();
-            maybe_eszip = Some(EszipPayloadKind::VecKind(std::fs::read(
-                events_worker_path,
-            )?));
+            let data = std::fs::read(&events_worker_path)?;
+
+            verify_bundle_file(&events_worker_path, &data)?;
+            maybe_eszip = Some(EszipPayloadKind::VecKind(data));
         }
     }
 
-    let ctx = create_worker(
-        (
-            WorkerContextInitOpts {
-                service_path,
-                no_module_cache: flags.no_module_cache,
-                import_map_path,
-                env_vars: std::env::vars().collect(),
-                timing: None,
-                maybe_eszip,
-                maybe_entrypoint,
-                maybe_decorator,
-                maybe_module_code: None,
-                conf: WorkerRuntimeOpts::EventsWorker(EventWorkerRuntimeOpts {
-                    events_msg_rx: Some(events_rx),
-                    event_worker_exit_deadline_sec: Some(flags.event_worker_exit_deadline_sec),
-                }),
-                static_patterns: vec![],
-                maybe_jsx_import_source_config: None,
-            },
-            termination_token,
-        ),
-        None,
-        None,
+    let opts = EventsWorkerBuilder::new(
+        service_path,
+        EventWorkerRuntimeOpts {
+            events_msg_rx: Some(events_rx),
+            event_worker_exit_deadline_sec: Some(flags.event_worker_exit_deadline_sec),
+        },
     )
-    .await
-    .map_err(|err| anyhow!("events worker boot error: {}", err))?;
+    .no_module_cache(flags.no_module_cache)
+    .import_map_path(import_map_path)
+    .env_vars(std::env::vars().collect())
+    .eszip(maybe_eszip)
+    .entrypoint(maybe_entrypoint)
+    .decorator(maybe_decorator)
+    .build()?;
+
+    let ctx = create_worker((opts, termination_token), None, None)
+        .await
+        .map_err(|err| anyhow!("events worker boot error: {}", err))?;
 
     Ok((ctx, events_tx))
 }
@@ -832,16 +954,28 @@ pub async fn create_user_worker_pool(
     request_idle_timeout: Option<u64>,
 ) -> Result<(SharedMetricSource, mpsc::UnboundedSender<UserWorkerMsgs>), Error> {
     let metric_src = SharedMetricSource::default();
-    let (user_worker_msgs_tx, mut user_worker_msgs_rx) =
-        mpsc::unbounded_channel::<UserWorkerMsgs>();
+    let (user_worker_msgs_tx, user_worker_msgs_rx) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+    let (mut control_lane_rx, mut data_lane_rx) = split_user_worker_msgs(user_worker_msgs_rx);
 
     let user_worker_msgs_tx_clone = user_worker_msgs_tx.clone();
+    let maybe_state_path = policy.state_path().map(Path::to_path_buf);
+    let maybe_service_stats_interval = policy.service_stats_interval();
+    let maybe_runtime_stats_interval = policy.runtime_stats_interval();
+    let maybe_concurrency_sample_interval = policy.concurrency_sample_interval();
+
+    if let Some(state_path) = maybe_state_path.clone() {
+        drop(tokio::spawn(prewarm_user_workers(
+            state_path,
+            user_worker_msgs_tx.clone(),
+        )));
+    }
 
     let _handle: tokio::task::JoinHandle<Result<(), Error>> = tokio::spawn({
         let metric_src_inner = metric_src.clone();
         async move {
             let token = termination_token.as_ref();
             let mut termination_requested = false;
+            let mut persisted_services = vec![];
             let mut worker_pool = WorkerPool::new(
                 policy,
                 metric_src_inner,
@@ -851,10 +985,53 @@ pub async fn create_user_worker_pool(
                 request_idle_timeout,
             );
 
+            if let Some((stats, interval)) = worker_pool
+                .service_stats
+                .clone()
+                .zip(maybe_service_stats_interval)
+            {
+                if let Some(events_msg_tx) = worker_pool.worker_event_sender.as_ref() {
+                    drop(tokio::spawn(report_service_stats(
+                        stats,
+                        interval,
+                        events_msg_tx.downgrade(),
+                    )));
+                }
+            }
+
+            if let Some(interval) = maybe_runtime_stats_interval {
+                if let Some(events_msg_tx) = worker_pool.worker_event_sender.as_ref() {
+                    drop(tokio::spawn(report_runtime_stats(
+                        worker_pool.metric_src.clone(),
+                        interval,
+                        events_msg_tx.downgrade(),
+                    )));
+                }
+            }
+
+            let mut autoscale_ticker = tokio::time::interval(AUTOSCALE_INTERVAL);
+
+            autoscale_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            // NOTE: Unlike the one of the autoscaler, the first tick is one
+            // interval away, as a sample covers the interval before it.
+            let concurrency_sample_interval =
+                maybe_concurrency_sample_interval.unwrap_or(AUTOSCALE_INTERVAL);
+            let mut concurrency_sample_ticker = tokio::time::interval_at(
+                tokio::time::Instant::now() + concurrency_sample_interval,
+                concurrency_sample_interval,
+            );
+
+            concurrency_sample_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
             // Note: Keep this loop non-blocking. Spawn a task to run blocking calls.
             // Handle errors within tasks and log them - do not bubble up errors.
             loop {
                 tokio::select! {
+                    // NOTE: Control messages must be handled before requests so
+                    // that a flood of requests cannot starve them.
+                    biased;
+
                     _ = async {
                         if let Some(token) = token {
                             token.inbound.cancelled().await;
@@ -864,6 +1041,15 @@ pub async fn create_user_worker_pool(
                     }, if !termination_requested => {
                         termination_requested = true;
 
+                        // NOTE: The services are taken before the workers are
+                        // drained, since they are pruned as their last worker
+                        // retires.
+                        persisted_services = worker_pool
+                            .persisted_services
+                            .values()
+                            .cloned()
+                            .collect::<Vec<_>>();
+
                         if worker_pool.user_workers.is_empty() {
                             if let Some(token) = token {
                                 token.outbound.cancel();
@@ -873,7 +1059,17 @@ pub async fn create_user_worker_pool(
                         }
                     }
 
-                    msg = user_worker_msgs_rx.recv() => {
+                    msg = control_lane_rx.recv() => {
+                        let msg = match msg {
+                            Some(PoolLaneMsg::Overloaded(key, res_tx)) => {
+                                worker_pool.reject_request(&key, res_tx);
+                                continue;
+                            }
+
+                            Some(PoolLaneMsg::Control(msg)) => Some(msg),
+                            None => None,
+                        };
+
                         match msg {
                             None => break,
                             Some(UserWorkerMsgs::Create(worker_options, tx)) => {
@@ -902,6 +1098,58 @@ pub async fn create_user_worker_pool(
                                 worker_pool.idle(&key);
                             }
 
+                            Some(UserWorkerMsgs::Terminate(key, tx)) => {
+                                if tx.send(worker_pool.terminate(&key)).is_err() {
+                                    error!("main worker receiver dropped");
+                                }
+                            }
+
+                            Some(UserWorkerMsgs::GetRequestLog(key, tx)) => {
+                                if tx.send(worker_pool.request_log(&key)).is_err() {
+                                    error!("main worker receiver dropped");
+                                }
+                            }
+
+                            Some(UserWorkerMsgs::GetTraces(query, tx)) => {
+                                if tx.send(worker_pool.traces(&query)).is_err() {
+                                    error!("main worker receiver dropped");
+                                }
+                            }
+
+                            Some(UserWorkerMsgs::HealthCheck(key, check, tx)) => {
+                                worker_pool.health_check(&key, check, tx);
+                            }
+
+                            Some(UserWorkerMsgs::GetStatus(tx)) => {
+                                if tx.send(worker_pool.status()).is_err() {
+                                    error!("main worker receiver dropped");
+                                }
+                            }
+
+                            Some(UserWorkerMsgs::UpdateEnv(service_path, env_vars, tx)) => {
+                                let count = worker_pool.update_env(&service_path, env_vars);
+
+                                if tx.send(count).is_err() {
+                                    error!("main worker receiver dropped");
+                                }
+                            }
+
+                            Some(UserWorkerMsgs::UpdateFeatureFlags(target, flags, tx)) => {
+                                let count = worker_pool.update_feature_flags(&target, flags);
+
+                                if tx.send(count).is_err() {
+                                    error!("main worker receiver dropped");
+                                }
+                            }
+
+                            Some(UserWorkerMsgs::UpdatePolicy(update)) => {
+                                worker_pool.update_policy(update);
+                            }
+
+                            Some(UserWorkerMsgs::UpdateTenantWeights(weights)) => {
+                                worker_pool.update_tenant_weights(weights);
+                            }
+
                             Some(UserWorkerMsgs::Shutdown(key)) => {
                                 worker_pool.shutdown(&key);
 
@@ -915,14 +1163,192 @@ pub async fn create_user_worker_pool(
                             }
                         }
                     }
+
+                    _ = autoscale_ticker.tick() => {
+                        worker_pool.autoscale();
+                    }
+
+                    _ = concurrency_sample_ticker.tick(), if maybe_concurrency_sample_interval.is_some() => {
+                        worker_pool.sample_concurrency(concurrency_sample_interval);
+                    }
+
+                    Some((key, req, res_tx, conn_token)) = data_lane_rx.recv() => {
+                        worker_pool.send_request(&key, req, res_tx, conn_token);
+                    }
                 }
             }
 
             worker_pool.worker_event_sender.take();
 
+            if let Some(state_path) = maybe_state_path.filter(|_| termination_requested) {
+                let mut services = persisted_services;
+
+                for service in services.iter_mut() {
+                    service.revision = get_service_revision(&service.service_path).await;
+                }
+
+                if let Err(err) = (PoolState { services }).save(&state_path).await {
+                    error!("failed to persist the worker pool state: {err:#}");
+                }
+            }
+
             Ok(())
         }
     });
 
     Ok((metric_src, user_worker_msgs_tx))
 }
+
+/// Maximum number of requests that can be queued in the data lane of the
+/// worker pool. Requests beyond this are rejected right away.
+static MAX_PENDING_REQUESTS: usize = 1024;
+
+type SendRequestMsg = (
+    Uuid,
+    Request<Body>,
+    oneshot::Sender<Result<SendRequestResult, Error>>,
+    Option<CancellationToken>,
+);
+
+enum PoolLaneMsg {
+    Control(UserWorkerMsgs),
+    Overloaded(Uuid, oneshot::Sender<Result<SendRequestResult, Error>>),
+}
+
+/// Splits the mailbox of the worker pool into an unbounded control lane and a
+/// bounded data lane for requests.
+fn split_user_worker_msgs(
+    mut user_worker_msgs_rx: mpsc::UnboundedReceiver<UserWorkerMsgs>,
+) -> (
+    mpsc::UnboundedReceiver<PoolLaneMsg>,
+    mpsc::Receiver<SendRequestMsg>,
+) {
+    let (control_lane_tx, control_lane_rx) = mpsc::unbounded_channel();
+    let (data_lane_tx, data_lane_rx) = mpsc::channel(MAX_PENDING_REQUESTS);
+
+    drop(tokio::spawn(async move {
+        while let Some(msg) = user_worker_msgs_rx.recv().await {
+            let msg = match msg {
+                UserWorkerMsgs::SendRequest(key, req, res_tx, conn_token) => {
+                    match data_lane_tx.try_send((key, req, res_tx, conn_token)) {
+                        Ok(()) => continue,
+                        Err(TrySendError::Full((key, _, res_tx, _))) => {
+                            PoolLaneMsg::Overloaded(key, res_tx)
+                        }
+                        Err(TrySendError::Closed(_)) => break,
+                    }
+                }
+
+                msg => PoolLaneMsg::Control(msg),
+            };
+
+            if control_lane_tx.send(msg).is_err() {
+                break;
+            }
+        }
+    }));
+
+    (control_lane_rx, data_lane_rx)
+}
+
+async fn prewarm_user_workers(
+    state_path: PathBuf,
+    user_worker_msgs_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+) {
+    let services = match PoolState::load(&state_path).await {
+        Ok(Some(state)) => state.services,
+        Ok(None) => return,
+        Err(err) => {
+            error!("failed to load the worker pool state: {err:#}");
+            return;
+        }
+    };
+
+    for service in services {
+        let service_path = service.service_path.clone();
+
+        // NOTE: The code of the service has been changed since the state was
+        // persisted, so it should be booted by a request as usual.
+        if service.revision.is_none()
+            || service.revision != get_service_revision(&service_path).await
+        {
+            continue;
+        }
+
+        // NOTE: The env vars are not persisted, so the service is left to be
+        // booted by its creator as usual.
+        if service.has_env_vars {
+            debug!("not pre-warming user worker ({service_path}) since it needs env vars");
+            continue;
+        }
+
+        let opts = match service.into_opts() {
+            Ok(opts) => opts,
+            Err(err) => {
+                error!("failed to pre-warm user worker ({service_path}): {err:#}");
+                continue;
+            }
+        };
+
+        let (tx, rx) = oneshot::channel::<Result<CreateUserWorkerResult, Error>>();
+
+        if user_worker_msgs_tx
+            .send(UserWorkerMsgs::Create(opts, tx))
+            .is_err()
+        {
+            return;
+        }
+
+        match rx.await {
+            Ok(Ok(_)) => debug!("pre-warmed user worker ({service_path})"),
+            Ok(Err(err)) => error!("failed to pre-warm user worker ({service_path}): {err:#}"),
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper_v014::{Body, Request};
+    use sb_workers::context::UserWorkerMsgs;
+    use tokio::sync::{mpsc, oneshot};
+    use uuid::Uuid;
+
+    use super::{split_user_worker_msgs, PoolLaneMsg, MAX_PENDING_REQUESTS};
+
+    #[tokio::test]
+    async fn test_requests_past_the_data_lane_are_rejected() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (mut control_lane_rx, mut data_lane_rx) = split_user_worker_msgs(rx);
+
+        for _ in 0..=MAX_PENDING_REQUESTS {
+            let (res_tx, _) = oneshot::channel();
+
+            tx.send(UserWorkerMsgs::SendRequest(
+                Uuid::nil(),
+                Request::new(Body::empty()),
+                res_tx,
+                None,
+            ))
+            .unwrap();
+        }
+
+        tx.send(UserWorkerMsgs::Idle(Uuid::nil())).unwrap();
+
+        // NOTE: The control messages are not held up by the full data lane.
+        assert!(matches!(
+            control_lane_rx.recv().await,
+            Some(PoolLaneMsg::Overloaded(..))
+        ));
+        assert!(matches!(
+            control_lane_rx.recv().await,
+            Some(PoolLaneMsg::Control(UserWorkerMsgs::Idle(_)))
+        ));
+
+        for _ in 0..MAX_PENDING_REQUESTS {
+            assert!(data_lane_rx.recv().await.is_some());
+        }
+
+        assert!(data_lane_rx.try_recv().is_err());
+    }
+}
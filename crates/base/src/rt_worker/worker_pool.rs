@@ -1,32 +1,345 @@
 use crate::inspector_server::Inspector;
+use crate::readiness::PoolReport;
 use crate::rt_worker::worker_ctx::{create_worker, send_user_worker_request};
 use crate::server::ServerFlags;
 use anyhow::{anyhow, bail, Context, Error};
 use enum_as_inner::EnumAsInner;
-use event_worker::events::WorkerEventWithMetadata;
-use http_v02::Request;
+use event_worker::events::{
+    BootRejectedEvent, BundleRejectedEvent, ConcurrencySampleEvent, DuplicateCreateEvent,
+    EventMetadata, RequestRetriedEvent, WorkerEventWithMetadata, WorkerEvents, WorkerScaledEvent,
+};
+use event_worker::history::{LifecycleHistoryQuery, LifecycleKind, LIFECYCLE_HISTORY};
+use futures_util::StreamExt;
+use http_utils::utils::{emit_status_code, get_upgrade_type};
+use http_v02::{header, HeaderValue, Method, Request, Response, StatusCode};
+use hyper_v014::body::HttpBody;
 use hyper_v014::Body;
-use log::error;
+use log::{error, warn};
+use lru::LruCache;
+use sb_core::cache::code_cache::ModuleCodeCache;
+use sb_core::feature_flags::{self, FeatureFlagProvider, FeatureFlags};
+use sb_core::replay::{Recorder, RecordingOptions, ReplayMode};
 use sb_core::util::sync::AtomicFlag;
 use sb_core::SharedMetricSource;
+use sb_env::EnvProvider;
 use sb_workers::context::{
-    CreateUserWorkerResult, SendRequestResult, Timing, TimingStatus, UserWorkerMsgs,
-    UserWorkerProfile, WorkerContextInitOpts, WorkerRuntimeOpts,
+    CreateDecision, CreateUserWorkerResult, FeatureFlagsTarget, HealthCheck, HealthReport,
+    PoolPolicyUpdate, RequestSummary, SendRequestResult, ServiceStatus, Timing, TimingStatus,
+    TraceQuery, TraceRecord, UserWorkerMsgs, UserWorkerProfile, UserWorkerRuntimeOpts,
+    WorkerContextInitOpts, WorkerIdentity, WorkerLimits, WorkerPressure, WorkerRuntimeOpts,
+};
+use sb_workers::errors::{
+    emit_worker_error, BundleSignatureError, WorkerError, WorkerOptionsChangedError,
 };
-use sb_workers::errors::WorkerError;
+use sb_workers::failure_injection::{FailureInjector, InjectedFailure, WorkerFailureInjection};
 use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
+use std::future::pending;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot::Sender;
-use tokio::sync::{mpsc, Notify, OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tokio::sync::{mpsc, oneshot, Notify, OwnedSemaphorePermit, Semaphore, TryAcquireError};
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+use super::autoscaler::{decide, Autoscaler, Scale, ServiceLoad};
+use super::boot_preflight::{BootPreflightPolicy, HostResources, MemoryBudget};
+use super::bundle_signature::verify_user_worker_code;
+use super::coalesce::{CoalesceKey, RequestCoalescer};
+use super::create_dedup::{CreateDeduplicator, CreateKey};
+use super::health_check::check_worker_health;
+use super::jwt_auth::JwtVerifier;
+use super::load_shedding::LoadSheddingPolicy;
+use super::manifest::FunctionManifest;
+use super::mirror::{MirrorSampler, RequestMirror};
+use super::path_normalization::path_has_prefix;
+use super::pool_state::PersistedService;
+use super::priority_class::{BatchPolicy, CpuBudget, PriorityClass};
+use super::recording::RequestRecording;
+use super::request_filter::{emit_rejection, filter_request, RequestFilter, RequestFilterDecision};
+use super::request_journal::RequestJournal;
+use super::request_log::RequestLog;
+use super::request_meta::assign_tenant;
+use super::service_bundle::{load_local_eszip, resolve_service_path};
+use super::service_stats::{RequestStats, ServiceStats};
+use super::tenant_scheduler::TenantScheduler;
+use super::trace_buffer::{TraceBuffer, TraceContext};
 use super::worker_ctx::TerminationToken;
+use crate::utils::send_event_if_event_worker_available;
+
+/// The header added to the response of a request that has been retried
+/// against a fresh worker.
+static RETRIED_HEADER_NAME: &str = "x-sb-edge-retried";
+static SERVER_TIMING_HEADER_NAME: &str = "server-timing";
+
+/// How many sessions may stick to the workers of a service at once. The
+/// least recently used one is forgotten to make room for a new one.
+static MAX_STICKY_SESSIONS: usize = 10_000;
+
+/// How long a session sticks to its worker since it was last used.
+static STICKY_SESSION_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// The worker each session sticks to, forgotten once the session has not
+/// been used for a while or the least recently used one when full.
+struct StickySessions {
+    inner: LruCache<String, (Uuid, Instant)>,
+    ttl: Duration,
+}
+
+impl StickySessions {
+    fn new(cap: usize, ttl: Duration) -> Self {
+        Self {
+            inner: LruCache::new(NonZeroUsize::new(cap).unwrap_or(NonZeroUsize::MIN)),
+            ttl,
+        }
+    }
+
+    /// Returns the worker the session sticks to unless it has expired.
+    fn get(&mut self, session_key: &str) -> Option<Uuid> {
+        let (key, last_used) = *self.inner.peek(session_key)?;
+
+        if last_used.elapsed() >= self.ttl {
+            self.inner.pop(session_key);
+            return None;
+        }
+
+        Some(key)
+    }
+
+    /// Marks the session as used now.
+    fn touch(&mut self, session_key: &str) {
+        if let Some((_, last_used)) = self.inner.get_mut(session_key) {
+            *last_used = Instant::now();
+        }
+    }
+
+    /// Sticks the session to the worker unless it sticks to one already.
+    fn stick(&mut self, session_key: &str, key: Uuid) {
+        if self.inner.contains(session_key) {
+            return;
+        }
+
+        self.inner
+            .push(session_key.to_string(), (key, Instant::now()));
+    }
+
+    fn remove(&mut self, session_key: &str) {
+        self.inner.pop(session_key);
+    }
+
+    /// Forgets the sessions that stick to the worker.
+    fn forget_worker(&mut self, key: &Uuid) {
+        let session_keys = self
+            .inner
+            .iter()
+            .filter(|(_, (it, _))| it == key)
+            .map(|(it, _)| it.clone())
+            .collect::<Vec<_>>();
+
+        for session_key in session_keys {
+            self.inner.pop(&session_key);
+        }
+    }
+}
+
+/// Marks a request that is already a retry, so that it is not retried again.
+#[derive(Clone, Copy)]
+struct RetriedRequest;
+
+/// What is needed to retry a request against a fresh worker of the same
+/// service.
+struct RequestRetry {
+    service: PersistedService,
+    req: Request<Body>,
+    conn_token: Option<CancellationToken>,
+    worker_pool_msgs_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    events_msg_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    metadata: EventMetadata,
+}
+
+/// Returns a copy of the request that can be sent again, or `None` if it may
+/// have side effects or carries a body or a connection upgrade.
+pub(super) fn replayable_copy(req: &Request<Body>) -> Option<Request<Body>> {
+    if !matches!(*req.method(), Method::GET | Method::HEAD)
+        || !req.body().is_end_stream()
+        || get_upgrade_type(req.headers()).is_some()
+    {
+        return None;
+    }
+
+    let mut copy = Request::new(Body::empty());
+
+    *copy.method_mut() = req.method().clone();
+    *copy.uri_mut() = req.uri().clone();
+    *copy.version_mut() = req.version();
+    *copy.headers_mut() = req.headers().clone();
+
+    Some(copy)
+}
+
+impl RequestRetry {
+    /// Returns `None` if the request cannot be replayed, i.e. it may have side
+    /// effects or carries a body or a connection upgrade.
+    fn new(
+        pool: &WorkerPool,
+        key: &Uuid,
+        profile: &UserWorkerProfile,
+        req: &Request<Body>,
+        conn_token: Option<CancellationToken>,
+    ) -> Option<Self> {
+        if req.extensions().get::<RetriedRequest>().is_some() {
+            return None;
+        }
+
+        let mut retry_req = replayable_copy(req)?;
+        let service = pool.persisted_services.get(&profile.identity)?.clone();
+
+        retry_req.extensions_mut().insert(RetriedRequest);
+
+        Some(Self {
+            service,
+            req: retry_req,
+            conn_token,
+            worker_pool_msgs_tx: pool.worker_pool_msgs_tx.clone(),
+            events_msg_tx: pool.worker_event_sender.clone(),
+            metadata: EventMetadata {
+                service_path: Some(profile.service_path.clone()),
+                execution_id: Some(*key),
+                worker_id: Some(profile.identity.to_string()),
+            },
+        })
+    }
+
+    async fn run(self, err: Error) -> Result<SendRequestResult, Error> {
+        let method = self.req.method().to_string();
+        let path = self.req.uri().path().to_string();
+        let reason = format!("{err:#}");
+        let events_msg_tx = self.events_msg_tx.clone();
+        let metadata = self.metadata.clone();
+
+        let (retry_key, result) = match self.send().await {
+            Ok((key, result)) => (Some(key), result),
+            Err(retry_err) => {
+                error!("failed to retry the request: {retry_err:#}");
+                (None, Err(err))
+            }
+        };
+
+        send_event_if_event_worker_available(
+            events_msg_tx.as_ref(),
+            WorkerEvents::RequestRetried(RequestRetriedEvent {
+                method,
+                path,
+                reason,
+                retry_key,
+                succeeded: result.is_ok(),
+            }),
+            metadata,
+        );
+
+        let (mut res, req_end_tx) = result?;
+
+        res.headers_mut()
+            .insert(RETRIED_HEADER_NAME, HeaderValue::from_static("1"));
+
+        Ok((res, req_end_tx))
+    }
+
+    async fn send(self) -> Result<(Uuid, Result<SendRequestResult, Error>), Error> {
+        let mut opts = self.service.into_opts()?;
+
+        // NOTE: Force the creation so that the pool does not hand out the
+        // worker that has just died.
+        if let Some(conf) = opts.conf.as_user_worker_mut() {
+            conf.force_create = true;
+        }
+
+        let (create_tx, create_rx) = oneshot::channel();
+
+        self.worker_pool_msgs_tx
+            .send(UserWorkerMsgs::Create(opts, create_tx))?;
+
+        let CreateUserWorkerResult { key, .. } = create_rx.await??;
+        let (res_tx, res_rx) = oneshot::channel();
+
+        self.worker_pool_msgs_tx.send(UserWorkerMsgs::SendRequest(
+            key,
+            self.req,
+            res_tx,
+            self.conn_token,
+        ))?;
+
+        Ok((key, res_rx.await?))
+    }
+}
+
+/// Appends the durations of the phases of a request to the `Server-Timing`
+/// header of its response: the boot of the worker if the request was its
+/// first, the wait for the worker, and the time until the head of the response
+/// arrived.
+fn append_server_timing(
+    res: &mut Response<Body>,
+    maybe_boot: Option<Duration>,
+    queue: Duration,
+    exec: Duration,
+) {
+    let metric = |name: &str, it: Duration| format!("{name};dur={:.3}", it.as_secs_f64() * 1000.0);
+    let value = maybe_boot
+        .map(|it| metric("boot", it))
+        .into_iter()
+        .chain([metric("queue", queue), metric("exec", exec)])
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        res.headers_mut().append(SERVER_TIMING_HEADER_NAME, value);
+    }
+}
+
+/// Resolves once the client of the request has hung up, if it is known.
+async fn wait_conn_closed(conn_token: Option<CancellationToken>) {
+    match conn_token {
+        Some(token) => token.cancelled_owned().await,
+        None => pending().await,
+    }
+}
+
+/// Rejects the request if its method or path is not accepted by the worker,
+/// so that it never enters the isolate.
+fn reject_disallowed_request(
+    profile: &UserWorkerProfile,
+    req: &Request<Body>,
+) -> Option<Response<Body>> {
+    if let Some(methods) = profile.allowed_methods.as_ref() {
+        if !methods
+            .iter()
+            .any(|it| it.eq_ignore_ascii_case(req.method().as_str()))
+        {
+            let mut res = emit_status_code(StatusCode::METHOD_NOT_ALLOWED, None, false);
+
+            if let Ok(value) = HeaderValue::from_str(&methods.join(", ")) {
+                res.headers_mut().insert(header::ALLOW, value);
+            }
+
+            return Some(res);
+        }
+    }
+
+    if let Some(prefixes) = profile.allowed_path_prefixes.as_ref() {
+        let path = req.uri().path();
+
+        if !prefixes.iter().any(|it| path_has_prefix(path, it)) {
+            return Some(emit_status_code(StatusCode::NOT_FOUND, None, false));
+        }
+    }
+
+    None
+}
 
 #[derive(Debug, Clone, Copy, EnumAsInner)]
 pub enum SupervisorPolicy {
@@ -63,11 +376,75 @@ impl SupervisorPolicy {
     }
 }
 
+/// What the pool does when a worker is created for a service whose active
+/// workers were created with other limits or environment variables.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateCreatePolicy {
+    /// The creation fails, leaving the active workers alone.
+    Reject,
+    /// An active worker is handed out, as if the options were the same.
+    #[default]
+    Reuse,
+    /// The active workers are drained, and a new worker is booted with the
+    /// new options.
+    Recreate,
+}
+
+impl FromStr for DuplicateCreatePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "reject" => Self::Reject,
+            "reuse" => Self::Reuse,
+            "recreate" => Self::Recreate,
+            _ => bail!("unknown duplicate create policy: {s}"),
+        })
+    }
+}
+
+impl DuplicateCreatePolicy {
+    fn decision(&self) -> &'static str {
+        match self {
+            Self::Reject => "rejected",
+            Self::Reuse => "reused",
+            Self::Recreate => "recreated",
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct WorkerPoolPolicy {
     supervisor_policy: SupervisorPolicy,
     max_parallelism: usize,
     request_wait_timeout_ms: u64,
+    drain_timeout_ms: u64,
+    request_log_size: usize,
+    trace_buffer_size: usize,
+    request_coalescing: bool,
+    duplicate_create: DuplicateCreatePolicy,
+    max_concurrent_boots: Option<usize>,
+    max_concurrent_requests: Option<usize>,
+    batch: BatchPolicy,
+    share_code_cache: bool,
+    server_timing: bool,
+    load_shedding: Option<LoadSheddingPolicy>,
+    failure_injection: bool,
+    /// The clocks of the user workers are coarsened to at least this many
+    /// milliseconds, whatever they are created with.
+    clock_granularity_ms: u64,
+    boot_preflight: Option<BootPreflightPolicy>,
+    service_stats_interval_ms: Option<u64>,
+    runtime_stats_interval_ms: Option<u64>,
+    concurrency_sample_interval_ms: Option<u64>,
+    state_path: Option<PathBuf>,
+    request_journal_path: Option<PathBuf>,
+    recording_path: Option<PathBuf>,
+    recording_opts: RecordingOptions,
+    request_filters: Vec<Arc<dyn RequestFilter>>,
+    /// Verifies the bearer tokens of the requests to the functions that
+    /// require a JWT. Such requests are rejected if it is not set.
+    jwt_verifier: Option<JwtVerifier>,
 }
 
 impl Default for WorkerPoolPolicy {
@@ -81,6 +458,29 @@ impl Default for WorkerPoolPolicy {
             supervisor_policy: SupervisorPolicy::default(),
             max_parallelism: available_parallelism,
             request_wait_timeout_ms: 10000,
+            drain_timeout_ms: 5000,
+            request_log_size: 32,
+            trace_buffer_size: 256,
+            request_coalescing: false,
+            duplicate_create: DuplicateCreatePolicy::default(),
+            max_concurrent_boots: None,
+            max_concurrent_requests: None,
+            batch: BatchPolicy::default(),
+            share_code_cache: false,
+            server_timing: false,
+            load_shedding: None,
+            failure_injection: false,
+            clock_granularity_ms: 0,
+            boot_preflight: None,
+            service_stats_interval_ms: None,
+            runtime_stats_interval_ms: None,
+            concurrency_sample_interval_ms: None,
+            state_path: None,
+            request_journal_path: None,
+            recording_path: None,
+            recording_opts: RecordingOptions::default(),
+            request_filters: vec![],
+            jwt_verifier: None,
         }
     }
 }
@@ -99,6 +499,138 @@ impl WorkerPoolPolicy {
             request_wait_timeout_ms: server_flags
                 .request_wait_timeout_ms
                 .unwrap_or(default.request_wait_timeout_ms),
+            drain_timeout_ms: server_flags
+                .worker_drain_timeout_ms
+                .unwrap_or(default.drain_timeout_ms),
+            request_log_size: server_flags
+                .request_log_size
+                .unwrap_or(default.request_log_size),
+            trace_buffer_size: server_flags
+                .trace_buffer_size
+                .unwrap_or(default.trace_buffer_size),
+            request_coalescing: server_flags.request_coalescing,
+            duplicate_create: server_flags.duplicate_create,
+            max_concurrent_boots: server_flags.max_concurrent_boots,
+            max_concurrent_requests: server_flags.max_concurrent_requests,
+            batch: BatchPolicy::from_flags(&server_flags),
+            share_code_cache: server_flags.share_code_cache,
+            server_timing: server_flags.server_timing,
+            load_shedding: LoadSheddingPolicy::from_flags(&server_flags),
+            failure_injection: server_flags.failure_injection,
+            clock_granularity_ms: server_flags.clock_granularity_ms.unwrap_or_default(),
+            boot_preflight: BootPreflightPolicy::from_flags(&server_flags),
+            service_stats_interval_ms: server_flags.service_stats_interval_ms,
+            runtime_stats_interval_ms: server_flags.runtime_stats_interval_ms,
+            concurrency_sample_interval_ms: server_flags.concurrency_sample_interval_ms,
+            state_path: None,
+            request_journal_path: None,
+            recording_path: None,
+            recording_opts: RecordingOptions::default(),
+            request_filters: vec![],
+            jwt_verifier: None,
+        }
+    }
+
+    /// Persists the registered services to the given path on graceful
+    /// shutdown, and pre-warms them from it on startup.
+    pub fn with_state_path(mut self, state_path: impl Into<Option<PathBuf>>) -> Self {
+        self.state_path = state_path.into();
+        self
+    }
+
+    /// Journals the metadata of the requests dispatched to the user workers
+    /// to the given path, so that the requests in flight can be told after a
+    /// crash.
+    pub fn with_request_journal_path(
+        mut self,
+        request_journal_path: impl Into<Option<PathBuf>>,
+    ) -> Self {
+        self.request_journal_path = request_journal_path.into();
+        self
+    }
+
+    /// Records the requests dispatched to the user workers, their responses
+    /// and the responses of the fetches the workers make to the given path,
+    /// so that they can be replayed against another version of a function.
+    pub fn with_recording_path(mut self, recording_path: impl Into<Option<PathBuf>>) -> Self {
+        self.recording_path = recording_path.into();
+        self
+    }
+
+    /// Sets how much of the bodies is recorded, and whether the headers that
+    /// carry credentials are recorded as they are.
+    pub fn with_recording_options(mut self, recording_opts: RecordingOptions) -> Self {
+        self.recording_opts = recording_opts;
+        self
+    }
+
+    /// Evaluates the filter before every request is dispatched to a user
+    /// worker. Filters are evaluated in the order they were added.
+    pub fn with_request_filter(mut self, filter: impl RequestFilter) -> Self {
+        self.request_filters.push(Arc::new(filter));
+        self
+    }
+
+    pub(crate) fn request_filters(&self) -> &[Arc<dyn RequestFilter>] {
+        &self.request_filters
+    }
+
+    /// Verifies the bearer tokens of the requests to the functions that
+    /// require a JWT against the given secret (HS256).
+    pub fn with_jwt_secret(mut self, jwt_secret: Option<&str>) -> Self {
+        self.jwt_verifier = jwt_secret.map(|it| JwtVerifier::from_secret(it.as_bytes()));
+        self
+    }
+
+    pub fn state_path(&self) -> Option<&Path> {
+        self.state_path.as_deref()
+    }
+
+    /// Applies the settings of the pool to the options of a user worker, as
+    /// the pool does to every worker it boots.
+    pub fn apply_to_runtime_opts(&self, opts: &mut UserWorkerRuntimeOpts) {
+        opts.drain_timeout_ms = self.drain_timeout_ms;
+        opts.clock_granularity_ms = opts.clock_granularity_ms.max(self.clock_granularity_ms);
+    }
+
+    pub fn service_stats_interval(&self) -> Option<Duration> {
+        self.service_stats_interval_ms
+            .filter(|it| *it > 0)
+            .map(Duration::from_millis)
+    }
+
+    pub fn runtime_stats_interval(&self) -> Option<Duration> {
+        self.runtime_stats_interval_ms
+            .filter(|it| *it > 0)
+            .map(Duration::from_millis)
+    }
+
+    pub fn concurrency_sample_interval(&self) -> Option<Duration> {
+        self.concurrency_sample_interval_ms
+            .filter(|it| *it > 0)
+            .map(Duration::from_millis)
+    }
+
+    /// Returns the settings that may change while the pool runs.
+    pub(crate) fn as_update(&self) -> PoolPolicyUpdate {
+        PoolPolicyUpdate {
+            max_parallelism: self.max_parallelism,
+            request_wait_timeout_ms: self.request_wait_timeout_ms,
+            drain_timeout_ms: self.drain_timeout_ms,
+        }
+    }
+
+    pub(crate) fn report(&self) -> PoolReport {
+        PoolReport {
+            supervisor_policy: match self.supervisor_policy {
+                SupervisorPolicy::PerWorker => "per_worker",
+                SupervisorPolicy::PerRequest { oneshot: false } => "per_request",
+                SupervisorPolicy::PerRequest { oneshot: true } => "oneshot",
+            },
+            max_parallelism: self.max_parallelism,
+            request_wait_timeout_ms: self.request_wait_timeout_ms,
+            drain_timeout_ms: self.drain_timeout_ms,
+            request_coalescing: self.request_coalescing,
         }
     }
 }
@@ -132,6 +664,20 @@ pub struct ActiveWorkerRegistry {
     next: Option<usize>,
     notify_pair: (flume::Sender<Option<Uuid>>, flume::Receiver<Option<Uuid>>),
     sem: Arc<Semaphore>,
+    /// Filled by the first worker of the service, so that the ones booted
+    /// after it, e.g. to scale out, don't compile its modules again.
+    code_cache: ModuleCodeCache,
+    /// Requests sent to the workers that have not been responded to yet.
+    in_flight: Arc<AtomicUsize>,
+    /// Creations waiting for a worker since the service has as many as the
+    /// maximum parallelism allows.
+    queued: Arc<AtomicUsize>,
+    /// The worker each session sticks to.
+    sessions: StickySessions,
+    /// The failures the workers of the service asked to be simulated.
+    failure_injector: Arc<FailureInjector>,
+    /// The options the newest worker of the service was created with.
+    create_options: Option<PersistedService>,
 }
 
 impl ActiveWorkerRegistry {
@@ -141,9 +687,46 @@ impl ActiveWorkerRegistry {
             next: Option::default(),
             notify_pair: flume::unbounded(),
             sem: Arc::new(Semaphore::const_new(max_parallelism)),
+            code_cache: ModuleCodeCache::default(),
+            in_flight: Arc::default(),
+            queued: Arc::default(),
+            sessions: StickySessions::new(MAX_STICKY_SESSIONS, STICKY_SESSION_TTL),
+            failure_injector: Arc::default(),
+            create_options: None,
         }
     }
 
+    /// Returns the worker the session sticks to if it can take the request,
+    /// marking it as used.
+    fn try_use_session(&mut self, session_key: &str, policy: SupervisorPolicy) -> Option<Uuid> {
+        let key = self.sessions.get(session_key)?;
+
+        match self.workers.get(&key).cloned() {
+            Some(WorkerId(key, true)) => {
+                if policy.is_per_request() {
+                    let _ = self.workers.replace(WorkerId(key, false));
+                    self.next = self.workers.iter().position(|it| it.1);
+                }
+
+                self.sessions.touch(session_key);
+                Some(key)
+            }
+
+            // NOTE: The worker is busy with another request under the
+            // per-request policy, so the session is served by another one
+            // this time without losing its worker.
+            Some(_) => None,
+            None => {
+                self.sessions.remove(session_key);
+                None
+            }
+        }
+    }
+
+    fn stick_session(&mut self, session_key: &str, key: Uuid) {
+        self.sessions.stick(session_key, key);
+    }
+
     fn mark_used_and_try_advance(&mut self, policy: SupervisorPolicy) -> Option<&Uuid> {
         if self.workers.is_empty() {
             let _ = self.next.take();
@@ -199,20 +782,39 @@ impl ActiveWorkerRegistry {
 
 // every new worker gets a new UUID (can reuse execution_id)
 // user_workers - maintain a hashmap of (uuid - workerProfile (include service path))
-// active_workers - hashmap of (service_path - uuid)
+// active_workers - hashmap of (identity - uuid), where the identity is the service path
+// namespaced by the tenant and the revision of the worker
 // retire removed entry for uuid from active
 // shutdown removes uuid from both active and user_workers
-// create_worker returns true if an active_worker is available for service_path (force create
+// create_worker returns true if an active_worker is available for the identity (force create
 // retires current one adds new one)
 // send_request is called with UUID
 pub struct WorkerPool {
     pub policy: WorkerPoolPolicy,
     pub metric_src: SharedMetricSource,
     pub user_workers: HashMap<Uuid, UserWorkerProfile>,
-    pub active_workers: HashMap<String, ActiveWorkerRegistry>,
+    pub active_workers: HashMap<WorkerIdentity, ActiveWorkerRegistry>,
     pub worker_pool_msgs_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
     pub maybe_inspector: Option<Inspector>,
     pub maybe_request_idle_timeout: Option<u64>,
+    pub persisted_services: HashMap<WorkerIdentity, PersistedService>,
+    pub coalescer: Option<RequestCoalescer>,
+    pub create_dedup: CreateDeduplicator,
+    pub service_stats: Option<ServiceStats>,
+    pub request_logs: HashMap<Uuid, RequestLog>,
+    pub request_journal: Option<RequestJournal>,
+    pub recorder: Option<Recorder>,
+    pub trace_buffer: Option<TraceBuffer>,
+    /// The samplers of the requests mirrored to a shadow worker, by service.
+    pub mirror_samplers: HashMap<WorkerIdentity, MirrorSampler>,
+    pub autoscaler: Autoscaler,
+    /// Limits how many workers may boot at once, if set.
+    pub boot_sem: Option<Arc<Semaphore>>,
+    /// What the user workers may commit to in memory in total, if set.
+    pub boot_memory_budget: Option<MemoryBudget>,
+    /// Limits how many requests are handled at once, if set.
+    pub tenant_scheduler: Option<TenantScheduler>,
+    pub batch_cpu_budget: Option<Arc<CpuBudget>>,
 
     // TODO: refactor this out of worker pool
     pub worker_event_sender: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
@@ -227,6 +829,46 @@ impl WorkerPool {
         inspector: Option<Inspector>,
         request_idle_timeout: Option<u64>,
     ) -> Self {
+        let coalescer = policy.request_coalescing.then(RequestCoalescer::default);
+        let boot_sem = policy
+            .max_concurrent_boots
+            .filter(|it| *it > 0)
+            .map(|it| Arc::new(Semaphore::new(it)));
+        let boot_memory_budget = policy
+            .boot_preflight
+            .and_then(|it| it.memory_budget_mb)
+            .map(MemoryBudget::new);
+        let tenant_scheduler = match (
+            policy.max_concurrent_requests.filter(|it| *it > 0),
+            policy.batch.max_concurrent_requests,
+        ) {
+            (None, None) => None,
+            (maybe_max, maybe_max_batch) => Some(TenantScheduler::new(
+                maybe_max.unwrap_or(usize::MAX),
+                maybe_max_batch,
+            )),
+        };
+        let batch_cpu_budget = policy
+            .batch
+            .cpu_budget_ms
+            .map(|it| Arc::new(CpuBudget::new(it)));
+        let service_stats = worker_event_sender
+            .as_ref()
+            .zip(policy.service_stats_interval())
+            .map(|_| ServiceStats::default());
+        let request_journal = policy.request_journal_path.as_deref().and_then(|path| {
+            RequestJournal::open(path)
+                .map_err(|err| error!("request journal is disabled: {:#}", err))
+                .ok()
+        });
+        let recorder = policy.recording_path.as_deref().and_then(|path| {
+            Recorder::open(path, policy.recording_opts)
+                .map_err(|err| error!("recording is disabled: {:#}", err))
+                .ok()
+        });
+        let trace_buffer =
+            (policy.trace_buffer_size > 0).then(|| TraceBuffer::new(policy.trace_buffer_size));
+
         Self {
             policy,
             metric_src,
@@ -235,6 +877,20 @@ impl WorkerPool {
             active_workers: HashMap::new(),
             maybe_inspector: inspector,
             maybe_request_idle_timeout: request_idle_timeout,
+            persisted_services: HashMap::new(),
+            coalescer,
+            create_dedup: CreateDeduplicator::default(),
+            service_stats,
+            request_logs: HashMap::new(),
+            request_journal,
+            recorder,
+            trace_buffer,
+            mirror_samplers: HashMap::new(),
+            autoscaler: Autoscaler::default(),
+            boot_sem,
+            boot_memory_budget,
+            tenant_scheduler,
+            batch_cpu_budget,
             worker_pool_msgs_tx,
         }
     }
@@ -251,6 +907,28 @@ impl WorkerPool {
             .unwrap_or("")
             .to_string();
 
+        // NOTE: A worker booted by an invocation gets the env vars its service
+        // was last created with, or none if it never was, rather than those of
+        // the worker that invoked it.
+        if let Some(conf) = worker_options
+            .conf
+            .as_user_worker()
+            .filter(|it| it.uses_service_env)
+        {
+            worker_options.env_vars = self
+                .persisted_services
+                .values()
+                .find(|it| it.service_path == service_path && it.tenant == conf.tenant)
+                .map(|it| it.env_vars.clone())
+                .unwrap_or_default();
+        }
+
+        let identity = WorkerIdentity::of(&worker_options);
+
+        if let Some(conf) = worker_options.conf.as_user_worker_mut() {
+            self.policy.apply_to_runtime_opts(conf);
+        }
+
         let is_oneshot_policy = self.policy.supervisor_policy.is_oneshot();
         let inspector = self.maybe_inspector.clone();
         let request_idle_timeout = self.maybe_request_idle_timeout;
@@ -260,11 +938,100 @@ impl WorkerPool {
             .as_user_worker()
             .map_or(false, |it| !is_oneshot_policy && it.force_create);
 
-        if let Some(ref active_worker_uuid) = self.maybe_active_worker(&service_path, force_create)
+        let idempotency_key = worker_options
+            .conf
+            .as_user_worker()
+            .and_then(|it| it.idempotency_key.clone());
+
+        // NOTE: Without an idempotency key, creations are only deduplicated
+        // under the per-worker policy since other policies need a worker per
+        // request.
+        let maybe_create_key = (idempotency_key.is_some()
+            || (!force_create && self.policy.supervisor_policy.is_per_worker()))
+        .then(|| CreateKey {
+            identity: identity.clone(),
+            idempotency_key,
+        });
+
+        let tx = match maybe_create_key.as_ref() {
+            Some(create_key) => match self.create_dedup.join(create_key, tx) {
+                Some(tx) => tx,
+                None => return,
+            },
+
+            None => tx,
+        };
+
+        let maybe_session_key = worker_options
+            .conf
+            .as_user_worker()
+            .and_then(|it| it.session_key.clone());
+
+        let maybe_create_options = PersistedService::describe(&worker_options);
+        let mut decision = CreateDecision::Created;
+        let changed_options = maybe_create_options
+            .as_ref()
+            .filter(|_| !force_create)
+            .map(|it| self.changed_create_options(&identity, it))
+            .unwrap_or_default();
+
+        if !changed_options.is_empty() {
+            let policy = self.policy.duplicate_create;
+            let drained_workers = match policy {
+                DuplicateCreatePolicy::Recreate => self.drain_identity(&identity),
+                _ => 0,
+            };
+
+            warn!(
+                "{} was created with other options than its active workers ({}): {}",
+                identity,
+                changed_options.join(", "),
+                policy.decision()
+            );
+
+            send_event_if_event_worker_available(
+                self.worker_event_sender.as_ref(),
+                WorkerEvents::DuplicateCreate(DuplicateCreateEvent {
+                    decision: policy.decision().to_string(),
+                    changed_options: changed_options.clone(),
+                    drained_workers,
+                }),
+                EventMetadata {
+                    service_path: Some(service_path.clone()),
+                    ..Default::default()
+                },
+            );
+
+            match policy {
+                DuplicateCreatePolicy::Reject => {
+                    if tx
+                        .send(Err(anyhow!(WorkerOptionsChangedError { changed_options })))
+                        .is_err()
+                    {
+                        error!("main worker receiver dropped")
+                    }
+                    return;
+                }
+
+                DuplicateCreatePolicy::Reuse => {}
+                DuplicateCreatePolicy::Recreate => decision = CreateDecision::Recreated,
+            }
+        }
+
+        if let Some(ref active_worker_uuid) =
+            self.maybe_active_worker(&identity, force_create, maybe_session_key.as_deref())
         {
+            let warnings = self
+                .user_workers
+                .get(active_worker_uuid)
+                .map(|it| it.boot_warnings.clone())
+                .unwrap_or_default();
+
             if tx
                 .send(Ok(CreateUserWorkerResult {
                     key: *active_worker_uuid,
+                    warnings,
+                    decision: CreateDecision::Reused,
                 }))
                 .is_err()
             {
@@ -273,6 +1040,13 @@ impl WorkerPool {
             return;
         }
 
+        // NOTE: The service is remembered even without a state path since it
+        // is also needed to retry requests against a fresh worker.
+        if let Some(service) = PersistedService::from_opts(&worker_options) {
+            self.persisted_services.insert(identity.clone(), service);
+            self.mirror_samplers.entry(identity.clone()).or_default();
+        }
+
         enum FlowAfterFence {
             Stop,
             Resend(Sender<Result<CreateUserWorkerResult, Error>>),
@@ -282,39 +1056,83 @@ impl WorkerPool {
             ),
         }
 
+        let maybe_pending;
+        let maybe_code_cache;
+        let maybe_failure_injector;
         let wait_fence_fut = {
             let registry = self
                 .active_workers
-                .entry(service_path.clone())
+                .entry(identity.clone())
                 .or_insert_with(|| ActiveWorkerRegistry::new(self.policy.max_parallelism));
 
+            if maybe_create_options.is_some() {
+                registry.create_options = maybe_create_options;
+            }
+
             let sem = registry.sem.clone();
+            let queued = registry.queued.clone();
+            let maybe_load_shedding = self.policy.load_shedding;
             let (_, notify_rx) = registry.notify_pair.clone();
+
+            maybe_code_cache = self
+                .policy
+                .share_code_cache
+                .then(|| registry.code_cache.clone());
+            maybe_failure_injector = self
+                .policy
+                .failure_injection
+                .then(|| registry.failure_injector.clone());
+
             let wait_timeout =
                 tokio::time::sleep(Duration::from_millis(self.policy.request_wait_timeout_ms));
 
+            // NOTE: The permit is tried at once, so that the creations that
+            // follow one booting a worker right away can join it.
+            let maybe_permit = match sem.clone().try_acquire_owned() {
+                Ok(permit) => Some(Some(permit)),
+                Err(TryAcquireError::NoPermits) if force_create => {
+                    // NOTE(Nyannyacha): Do we need to consider counting the
+                    // permit count (that means it affects maximum
+                    // parallelism) if in the force creation mode?
+                    Some(None)
+                }
+
+                _ => None,
+            };
+
+            maybe_pending = maybe_create_key
+                .filter(|_| maybe_permit.is_some())
+                .map(|it| self.create_dedup.lead(it));
+
             async move {
                 use FlowAfterFence::*;
 
-                match sem.clone().try_acquire_owned() {
-                    Ok(permit) => return Create(Some(permit), tx),
-                    Err(TryAcquireError::NoPermits) if force_create => {
-                        // NOTE(Nyannyacha): Do we need to consider counting the
-                        // permit count (that means it affects maximum
-                        // parallelism) if in the force creation mode?
-                        return Create(None, tx);
-                    }
+                if let Some(permit) = maybe_permit {
+                    return Create(permit, tx);
+                }
 
-                    _ => {}
+                if let Some(policy) = maybe_load_shedding
+                    .filter(|it| it.check_queue(queued.load(Ordering::Acquire)).is_some())
+                {
+                    if tx.send(Err(anyhow!(policy.error()))).is_err() {
+                        error!("main worker receiver dropped");
+                    }
+                    return Stop;
                 }
 
+                queued.fetch_add(1, Ordering::Release);
+
+                let _queued_guard = scopeguard::guard(queued, |it| {
+                    it.fetch_sub(1, Ordering::Release);
+                });
+
                 tokio::pin!(wait_timeout);
                 loop {
                     tokio::select! {
                         maybe_key = notify_rx.recv_async() => {
                             match maybe_key {
                                 Err(x) => {
-                                    if tx.send(Err(anyhow!("worker channel is no longer valid: {}", x))).is_err() {
+                                    if tx.send(Err(anyhow!(x).context(WorkerError::WorkerGone))).is_err() {
                                         error!("main worker receiver dropped");
                                     }
                                     return Stop;
@@ -330,7 +1148,7 @@ impl WorkerPool {
                         },
 
                         () = &mut wait_timeout => {
-                            if tx.send(Err(anyhow!("worker did not respond in time"))).is_err() {
+                            if tx.send(Err(anyhow!(WorkerError::TimedOut))).is_err() {
                                 error!("main worker receiver dropped");
                             }
                             return Stop;
@@ -342,8 +1160,14 @@ impl WorkerPool {
 
         let worker_pool_msgs_tx = self.worker_pool_msgs_tx.clone();
         let events_msg_tx = self.worker_event_sender.clone();
+        let maybe_recorder = self.recorder.clone();
+        let metric_src = self.metric_src.clone();
+        let boot_sem = self.boot_sem.clone();
         let supervisor_policy = self.policy.supervisor_policy;
 
+        let maybe_boot_preflight = self.policy.boot_preflight;
+        let maybe_boot_memory_budget = self.boot_memory_budget.clone();
+
         drop(tokio::spawn(async move {
             let (permit, tx) = match wait_fence_fut.await {
                 FlowAfterFence::Stop => return,
@@ -356,9 +1180,11 @@ impl WorkerPool {
                         conf,
                         maybe_eszip,
                         maybe_module_code,
+                        maybe_module_map,
                         maybe_entrypoint,
                         maybe_decorator,
                         maybe_jsx_import_source_config,
+                        locale,
                         ..
                     } = worker_options;
 
@@ -373,10 +1199,12 @@ impl WorkerPool {
                                 conf,
                                 maybe_eszip,
                                 maybe_module_code,
+                                maybe_module_map,
                                 maybe_entrypoint,
                                 maybe_decorator,
                                 static_patterns: vec![],
                                 maybe_jsx_import_source_config,
+                                locale,
                             },
                             tx,
                         ))
@@ -391,86 +1219,346 @@ impl WorkerPool {
                 FlowAfterFence::Create(permit, tx) => (permit, tx),
             };
 
-            let Ok(mut user_worker_rt_opts) = worker_options.conf.into_user_worker() else {
-                return;
-            };
+            metric_src.incl_pending_user_worker_boots();
 
-            let uuid = uuid::Uuid::new_v4();
-            let cancel = CancellationToken::new();
-            let (req_start_timing_tx, req_start_timing_rx) =
-                mpsc::unbounded_channel::<Arc<Notify>>();
+            let _pending_boot_guard = scopeguard::guard(metric_src.clone(), |it| {
+                it.decl_pending_user_worker_boots();
+            });
 
-            let status = TimingStatus {
-                demand: Arc::new(AtomicUsize::new(0)),
-                is_retired: Arc::new(AtomicFlag::default()),
+            // NOTE: A bundle is extracted before its manifest is looked for,
+            // as the manifest is shipped in it.
+            let resolved = match load_local_eszip(&mut worker_options)
+                .await
+                .and_then(|_| verify_user_worker_code(&worker_options))
+            {
+                Ok(()) => resolve_service_path(&worker_options.service_path).await,
+                Err(err) => Err(err),
             };
 
-            let (req_end_timing_tx, req_end_timing_rx) = mpsc::unbounded_channel::<()>();
-
-            user_worker_rt_opts.service_path = Some(service_path.clone());
-            user_worker_rt_opts.key = Some(uuid);
-
-            user_worker_rt_opts.pool_msg_tx = Some(worker_pool_msgs_tx.clone());
-            user_worker_rt_opts.events_msg_tx = events_msg_tx;
-            user_worker_rt_opts.cancel = Some(cancel.clone());
-
-            worker_options.timing = Some(Timing {
-                status: status.clone(),
-                req: (req_start_timing_rx, req_end_timing_rx),
-            });
+            worker_options.service_path = match resolved {
+                Ok(it) => it,
+                Err(err) => {
+                    error!("{err:#}");
 
-            worker_options.conf = WorkerRuntimeOpts::UserWorker(user_worker_rt_opts);
+                    if err.downcast_ref::<BundleSignatureError>().is_some() {
+                        send_event_if_event_worker_available(
+                            events_msg_tx.as_ref(),
+                            WorkerEvents::BundleRejected(BundleRejectedEvent {
+                                reason: format!("{err:#}"),
+                            }),
+                            EventMetadata {
+                                service_path: Some(service_path.clone()),
+                                ..Default::default()
+                            },
+                        );
+                    }
 
-            match create_worker(
-                (worker_options, supervisor_policy, termination_token.clone()),
-                inspector,
-                request_idle_timeout,
-            )
-            .await
-            {
-                Ok(ctx) => {
-                    let profile = UserWorkerProfile {
-                        worker_request_msg_tx: ctx.msg_tx,
-                        timing_tx_pair: (req_start_timing_tx, req_end_timing_tx),
-                        service_path,
-                        permit: permit.map(Arc::new),
-                        status: status.clone(),
-                        exit: ctx.exit,
-                        cancel,
-                    };
+                    let result = Err(err);
 
-                    if worker_pool_msgs_tx
-                        .send(UserWorkerMsgs::Created(uuid, profile))
-                        .is_err()
-                    {
-                        error!("user worker msgs receiver dropped")
+                    if let Some(pending) = maybe_pending {
+                        pending.settle(&result);
                     }
-                    if tx.send(Ok(CreateUserWorkerResult { key: uuid })).is_err() {
+                    if tx.send(result).is_err() {
                         error!("main worker receiver dropped")
-                    };
-
-                    status.demand.fetch_add(1, Ordering::Release);
+                    }
+                    return;
                 }
+            };
+
+            let maybe_manifest = match FunctionManifest::load(&worker_options.service_path).await {
+                Ok(it) => it,
                 Err(err) => {
                     error!("{err:#}");
-                    if tx.send(Err(err)).is_err() {
+                    let result = Err(err);
+
+                    if let Some(pending) = maybe_pending {
+                        pending.settle(&result);
+                    }
+                    if tx.send(result).is_err() {
                         error!("main worker receiver dropped")
                     }
+                    return;
                 }
+            };
+
+            if let Some(manifest) = maybe_manifest.as_ref() {
+                manifest.apply(&mut worker_options);
             }
-        }));
+
+            let Ok(mut user_worker_rt_opts) = worker_options.conf.into_user_worker() else {
+                return;
+            };
+
+            let boot_started_at = Instant::now();
+
+            // NOTE: The boot slot is held until the worker has booted, so that
+            // a storm of creations does not make every boot slow.
+            let _boot_permit = match boot_sem {
+                Some(sem) => {
+                    let queue_start_time = Instant::now();
+
+                    metric_src.incl_queued_user_worker_boots();
+
+                    let queued_boot_guard = scopeguard::guard(metric_src, |it| {
+                        it.decl_queued_user_worker_boots();
+                    });
+
+                    let permit = sem.acquire_owned().await.ok();
+
+                    drop(queued_boot_guard);
+                    user_worker_rt_opts.boot_queue_us =
+                        queue_start_time.elapsed().as_micros() as u64;
+
+                    permit
+                }
+
+                None => None,
+            };
+
+            user_worker_rt_opts.code_cache = maybe_code_cache;
+
+            let maybe_failure_injection = maybe_failure_injector.map(WorkerFailureInjection::new);
+
+            // NOTE: An injected delay counts towards the boot duration, as a
+            // slow boot would.
+            if let Some(delay) = maybe_failure_injection
+                .as_ref()
+                .and_then(|it| it.injector.take_boot_delay())
+            {
+                tokio::time::sleep(delay).await;
+            }
+
+            let limits = WorkerLimits::from(&user_worker_rt_opts);
+
+            let mut maybe_memory_reservation = None;
+
+            if let Some(policy) = maybe_boot_preflight {
+                let host = HostResources::read();
+                let result = policy.check(&host, limits.memory_limit_mb).and_then(|_| {
+                    maybe_memory_reservation = maybe_boot_memory_budget
+                        .as_ref()
+                        .map(|it| it.try_reserve(limits.memory_limit_mb))
+                        .transpose()?
+                        .map(Arc::new);
+
+                    Ok(())
+                });
+
+                if let Err(reason) = result {
+                    let err = WorkerError::InsufficientResources { reason };
+                    let committed_memory_mb = maybe_boot_memory_budget
+                        .as_ref()
+                        .map_or(0, MemoryBudget::committed_mb);
+
+                    warn!("refused to boot a user worker ({}): {}", service_path, err);
+                    send_event_if_event_worker_available(
+                        events_msg_tx.as_ref(),
+                        WorkerEvents::BootRejected(BootRejectedEvent {
+                            reason: reason.to_string(),
+                            available_memory_mb: host.available_memory_mb,
+                            load_avg_per_core: host.load_avg_per_core,
+                            committed_memory_mb,
+                        }),
+                        EventMetadata {
+                            service_path: Some(service_path.clone()),
+                            ..Default::default()
+                        },
+                    );
+
+                    let result = Err(anyhow!(err));
+
+                    if let Some(pending) = maybe_pending {
+                        pending.settle(&result);
+                    }
+                    if tx.send(result).is_err() {
+                        error!("main worker receiver dropped")
+                    }
+                    return;
+                }
+            }
+
+            let uuid = uuid::Uuid::new_v4();
+            let cancel = CancellationToken::new();
+
+            // NOTE: Each worker needs a termination token of its own so that
+            // it can be terminated individually.
+            let termination_token = termination_token.unwrap_or_default();
+            let (req_start_timing_tx, req_start_timing_rx) =
+                mpsc::unbounded_channel::<Arc<Notify>>();
+
+            let status = TimingStatus {
+                demand: Arc::new(AtomicUsize::new(0)),
+                is_retired: Arc::new(AtomicFlag::default()),
+            };
+
+            let (req_end_timing_tx, req_end_timing_rx) = mpsc::unbounded_channel::<()>();
+
+            let session_key = user_worker_rt_opts.session_key.clone();
+            let pressure = Arc::new(WorkerPressure::default());
+            let allowed_methods = user_worker_rt_opts.allowed_methods.clone();
+            let allowed_path_prefixes = user_worker_rt_opts.allowed_path_prefixes.clone();
+            let env_provider = EnvProvider::default();
+            let feature_flag_provider =
+                FeatureFlagProvider::new(user_worker_rt_opts.feature_flags.clone());
+
+            user_worker_rt_opts.service_path = Some(service_path.clone());
+            user_worker_rt_opts.key = Some(uuid);
+            user_worker_rt_opts.identity = Some(identity.clone());
+            user_worker_rt_opts.pool_msg_tx = Some(worker_pool_msgs_tx.clone());
+            user_worker_rt_opts.events_msg_tx = events_msg_tx;
+            user_worker_rt_opts.cancel = Some(cancel.clone());
+            user_worker_rt_opts.pressure = Some(pressure.clone());
+            user_worker_rt_opts.failure_injection = maybe_failure_injection.clone();
+
+            if let Some(recorder) = maybe_recorder {
+                user_worker_rt_opts.replay = Some(ReplayMode::Record(
+                    recorder.with_service_path(&service_path),
+                ));
+            }
+            user_worker_rt_opts.env_provider = Some(env_provider.clone());
+            user_worker_rt_opts.feature_flag_provider = Some(feature_flag_provider.clone());
+
+            worker_options.timing = Some(Timing {
+                status: status.clone(),
+                req: (req_start_timing_rx, req_end_timing_rx),
+            });
+
+            worker_options.conf = WorkerRuntimeOpts::UserWorker(user_worker_rt_opts);
+
+            match create_worker(
+                (
+                    worker_options,
+                    supervisor_policy,
+                    Some(termination_token.clone()),
+                ),
+                inspector,
+                request_idle_timeout,
+            )
+            .await
+            {
+                Ok(ctx) => {
+                    if !ctx.boot_warnings.is_empty() {
+                        warn!(
+                            "user worker booted with warnings ({}): {}",
+                            service_path,
+                            ctx.boot_warnings
+                                .iter()
+                                .map(ToString::to_string)
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        );
+                    }
+
+                    let profile = UserWorkerProfile {
+                        worker_request_msg_tx: ctx.msg_tx,
+                        timing_tx_pair: (req_start_timing_tx, req_end_timing_tx),
+                        service_path,
+                        identity,
+                        permit: permit.map(Arc::new),
+                        status: status.clone(),
+                        exit: ctx.exit,
+                        cancel,
+                        termination: termination_token.inbound.clone(),
+                        verify_jwt: maybe_manifest.as_ref().map_or(false, |it| it.verify_jwt),
+                        allowed_methods,
+                        allowed_path_prefixes,
+                        env_provider,
+                        header_policy: maybe_manifest
+                            .as_ref()
+                            .and_then(|it| it.headers.clone())
+                            .map(Arc::new),
+                        rewrite_policy: maybe_manifest
+                            .as_ref()
+                            .and_then(|it| it.rewrite.clone())
+                            .map(Arc::new),
+                        mirror_policy: maybe_manifest
+                            .as_ref()
+                            .and_then(|it| it.mirror.clone())
+                            .map(Arc::new),
+                        autoscale_policy: maybe_manifest
+                            .as_ref()
+                            .and_then(|it| it.autoscale.clone())
+                            .map(Arc::new),
+                        env_allowlist: maybe_manifest.and_then(|it| it.env_allowlist),
+                        feature_flag_provider,
+                        boot_warnings: ctx.boot_warnings.clone(),
+                        limits,
+                        booted_at: Instant::now(),
+                        boot_duration: boot_started_at.elapsed(),
+                        has_served: Arc::default(),
+                        session_key,
+                        pressure,
+                        failure_injection: maybe_failure_injection,
+                        memory_reservation: maybe_memory_reservation,
+                    };
+
+                    if worker_pool_msgs_tx
+                        .send(UserWorkerMsgs::Created(uuid, profile))
+                        .is_err()
+                    {
+                        error!("user worker msgs receiver dropped")
+                    }
+
+                    // NOTE: Every creation that joined this one is a demand
+                    // for the worker too.
+                    let result = CreateUserWorkerResult {
+                        key: uuid,
+                        warnings: ctx.boot_warnings,
+                        decision,
+                    };
+
+                    let joined = maybe_pending.map_or(0, |it| {
+                        it.settle(&Ok(CreateUserWorkerResult {
+                            key: uuid,
+                            warnings: result.warnings.clone(),
+                            decision,
+                        }))
+                    });
+
+                    if tx.send(Ok(result)).is_err() {
+                        error!("main worker receiver dropped")
+                    };
+
+                    status.demand.fetch_add(1 + joined, Ordering::Release);
+                }
+                Err(err) => {
+                    error!("{err:#}");
+                    let result = Err(err);
+
+                    if let Some(pending) = maybe_pending {
+                        pending.settle(&result);
+                    }
+                    if tx.send(result).is_err() {
+                        error!("main worker receiver dropped")
+                    }
+                }
+            }
+        }));
     }
 
     pub fn add_user_worker(&mut self, key: Uuid, profile: UserWorkerProfile) {
         let registry = self
             .active_workers
-            .entry(profile.service_path.clone())
+            .entry(profile.identity.clone())
             .or_insert_with(|| ActiveWorkerRegistry::new(self.policy.max_parallelism));
 
         registry
             .workers
             .insert(WorkerId(key, self.policy.supervisor_policy.is_per_worker()));
 
+        if let Some(session_key) = profile.session_key.as_deref() {
+            registry.stick_session(session_key, key);
+        }
+
+        if let Some(service) = self.persisted_services.get_mut(&profile.identity) {
+            service.key = Some(key.to_string());
+        }
+
+        if self.policy.request_log_size > 0 {
+            self.request_logs
+                .insert(key, RequestLog::new(self.policy.request_log_size));
+        }
+
         self.user_workers.insert(key, profile);
         self.metric_src.incl_active_user_workers();
     }
@@ -478,20 +1566,167 @@ impl WorkerPool {
     pub fn send_request(
         &self,
         key: &Uuid,
-        req: Request<Body>,
+        mut req: Request<Body>,
         res_tx: Sender<Result<SendRequestResult, Error>>,
         conn_token: Option<CancellationToken>,
     ) {
         let _: Result<(), Error> = match self.user_workers.get(key) {
             Some(worker) => {
+                assign_tenant(req.headers_mut(), worker.identity.tenant.as_deref());
+
+                // NOTE: Filters are evaluated before the request takes a slot
+                // of the worker, so that a rejected request does not wait for
+                // the fence or cause another worker to be created.
+                let maybe_tarpit = match filter_request(
+                    &self.policy.request_filters,
+                    &mut req,
+                    Some(*key),
+                    &worker.service_path,
+                ) {
+                    RequestFilterDecision::Allow => None,
+                    RequestFilterDecision::Reject(status, body) => {
+                        self.release_rejected_request(worker);
+
+                        if res_tx
+                            .send(Ok((
+                                emit_rejection(status, body),
+                                mpsc::unbounded_channel().0,
+                            )))
+                            .is_err()
+                        {
+                            error!("main worker receiver dropped")
+                        }
+
+                        return;
+                    }
+
+                    RequestFilterDecision::Tarpit(delay) => Some(delay),
+                };
+
+                let maybe_injected_failure = worker.failure_injection.as_ref().and_then(|it| {
+                    it.injector
+                        .take_request_failure()
+                        .map(|failure| (it.limit_tx.clone(), failure))
+                });
+
+                if let Some((_, InjectedFailure::DropResponse)) = maybe_injected_failure {
+                    warn!("dropping request to {} (injected failure)", worker.identity);
+
+                    // NOTE: The request is acknowledged as if the worker had
+                    // handled it, see `reject_request`.
+                    if self.policy.supervisor_policy.is_per_worker() {
+                        let _ = worker.timing_tx_pair.1.send(());
+                    }
+
+                    drop(res_tx);
+                    return;
+                }
+
                 let policy = self.policy.supervisor_policy;
                 let profile = worker.clone();
                 let exit = worker.exit.clone();
                 let cancel = worker.cancel.clone();
                 let (req_start_tx, req_end_tx) = profile.timing_tx_pair.clone();
+                let maybe_stats = self
+                    .service_stats
+                    .clone()
+                    .map(|it| (it, profile.service_path.clone()));
+
+                let maybe_registry_in_flight = self
+                    .active_workers
+                    .get(&profile.identity)
+                    .map(|it| it.in_flight.clone());
+
+                let maybe_in_flight = profile
+                    .autoscale_policy
+                    .as_ref()
+                    .map(|_| self.autoscaler.load.start(&profile.identity));
+
+                let maybe_retry = RequestRetry::new(self, key, worker, &req, conn_token.clone());
+                let maybe_mirror =
+                    RequestMirror::new(self, key, worker, &req).map(RequestMirror::start);
+                let maybe_request_log = self
+                    .request_logs
+                    .get(key)
+                    .cloned()
+                    .map(|it| (it, req.method().to_string(), req.uri().path().to_string()));
+                let maybe_trace = self.trace_buffer.clone().map(|buffer| {
+                    let ctx = TraceContext::from_headers(req.headers());
+
+                    ctx.inject(req.headers_mut());
+                    (
+                        buffer,
+                        ctx,
+                        profile.service_path.clone(),
+                        req.method().to_string(),
+                        req.uri().path().to_string(),
+                    )
+                });
+                let maybe_recording = self
+                    .recorder
+                    .clone()
+                    .map(|recorder| (recorder, profile.service_path.clone()));
+                let maybe_journal = self.request_journal.clone().map(|journal| {
+                    let id = journal.start(*key, &profile.service_path, &req);
+                    (journal, id)
+                });
+                let class = PriorityClass::of_request(&req);
+                let wait_timeout = Duration::from_millis(match class {
+                    PriorityClass::Interactive => self.policy.request_wait_timeout_ms,
+                    PriorityClass::Batch => self
+                        .policy
+                        .batch
+                        .request_wait_timeout_ms
+                        .unwrap_or(self.policy.request_wait_timeout_ms),
+                });
+                let maybe_scheduler = self
+                    .tenant_scheduler
+                    .clone()
+                    .map(|it| (it, profile.identity.tenant.clone()));
+                let maybe_cpu_budget = self
+                    .batch_cpu_budget
+                    .clone()
+                    .filter(|_| class == PriorityClass::Batch);
+                let worker_cancel = worker.cancel.clone();
+                let worker_key = *key;
+                let server_timing = self.policy.server_timing;
+                let maybe_load_shedding = self.policy.load_shedding;
+                let maybe_jwt_verifier = self.policy.jwt_verifier.clone();
+                let maybe_header_policy = profile
+                    .header_policy
+                    .clone()
+                    .map(|it| (it, req.headers().get(header::ORIGIN).cloned()));
+                let maybe_rewrite_policy = profile.rewrite_policy.clone();
+
+                // NOTE: Requests are only coalesced under the per-worker policy
+                // since other policies need every request to pass the fence.
+                let maybe_coalesce = self
+                    .coalescer
+                    .clone()
+                    .filter(|_| policy.is_per_worker())
+                    .zip(CoalesceKey::from_request(*key, &req))
+                    .map(|(coalescer, coalesce_key)| (coalescer, coalesce_key, req_end_tx.clone()));
 
                 // Create a closure to handle the request and send the response
-                let request_handler = async move {
+                let request_handler = move |mut req: Request<Body>| async move {
+                    let received_at = Instant::now();
+
+                    if let Some(delay) = maybe_tarpit {
+                        tokio::select! {
+                            _ = tokio::time::sleep(delay) => {}
+                            _ = cancel.cancelled() => {
+                                if policy.is_per_worker() {
+                                    let _ = req_end_tx.send(());
+                                }
+
+                                bail!(exit
+                                    .error()
+                                    .await
+                                    .unwrap_or(anyhow!(WorkerError::RequestCancelledBySupervisor)))
+                            }
+                        }
+                    }
+
                     if !policy.is_per_worker() {
                         if cancel.is_cancelled() {
                             bail!(exit
@@ -527,11 +1762,145 @@ impl WorkerPool {
                                     .await
                                     .unwrap_or(anyhow!(WorkerError::RequestCancelledBySupervisor)))
                             }
+                            _ = wait_conn_closed(conn_token.clone()) => {
+                                // NOTE: The supervisor admits the request
+                                // anyway, so it is ended as soon as it is to
+                                // pass the slot on to the next one.
+                                drop(tokio::spawn(async move {
+                                    tokio::select! {
+                                        _ = fence.notified() => {
+                                            let _ = req_end_tx.send(());
+                                        }
+                                        _ = cancel.cancelled() => {}
+                                    }
+                                }));
+
+                                bail!(WorkerError::ClientDisconnected)
+                            }
                         }
                     }
 
+                    // NOTE: The worker is pushed past the limit, and the
+                    // request fails as if the worker had hit it while handling
+                    // the request.
+                    if let Some((limit_tx, failure)) = maybe_injected_failure {
+                        warn!("{} hits {failure:?} (injected failure)", profile.identity);
+
+                        limit_tx.send_replace(Some(failure));
+                        cancel.cancelled().await;
+
+                        bail!(exit
+                            .error()
+                            .await
+                            .unwrap_or(anyhow!(WorkerError::RequestCancelledBySupervisor)))
+                    }
+
+                    // NOTE: A worker close to its limits would likely be killed
+                    // while handling the request, so it is answered at once
+                    // and the worker is drained to make room for a fresh one.
+                    if let Some((policy, reason)) = maybe_load_shedding.and_then(|it| {
+                        it.check_worker(&profile.pressure, &profile.limits)
+                            .map(|reason| (it, reason))
+                    }) {
+                        warn!(
+                            "shedding request to {} ({reason:?} near its limit)",
+                            profile.identity
+                        );
+
+                        profile.status.is_retired.raise();
+                        profile.termination.cancel();
+
+                        return Ok((emit_worker_error(&anyhow!(policy.error())), req_end_tx));
+                    }
+
+                    // NOTE: Preflights carry neither credentials nor the
+                    // actual method, so they must be answered before the
+                    // request is checked against them.
+                    if let Some(res) = profile
+                        .header_policy
+                        .as_ref()
+                        .and_then(|it| it.preflight(&req))
+                    {
+                        return Ok((res, req_end_tx));
+                    }
+
+                    if let Some(res) = reject_disallowed_request(&profile, &req) {
+                        return Ok((res, req_end_tx));
+                    }
+
+                    if profile.verify_jwt
+                        && !maybe_jwt_verifier
+                            .as_ref()
+                            .is_some_and(|it| it.verify(&req))
+                    {
+                        return Ok((
+                            emit_status_code(StatusCode::UNAUTHORIZED, None, false),
+                            req_end_tx,
+                        ));
+                    }
+
+                    if let Some(rewrite_policy) = profile.rewrite_policy.as_ref() {
+                        rewrite_policy.rewrite_request(&mut req);
+                    }
+
+                    // NOTE: The slot is held until the head of the response
+                    // has arrived.
+                    let _permit = if maybe_scheduler.is_some() || maybe_cpu_budget.is_some() {
+                        let acquire = tokio::time::timeout(wait_timeout, async {
+                            if let Some(budget) = maybe_cpu_budget.as_ref() {
+                                budget.wait().await;
+                            }
+
+                            match maybe_scheduler {
+                                Some((scheduler, tenant)) => {
+                                    Some(scheduler.acquire(tenant.as_deref(), class).await)
+                                }
+
+                                None => None,
+                            }
+                        });
+
+                        // NOTE: A request that gives up waiting leaves the
+                        // queue of its tenant.
+                        let acquired = tokio::select! {
+                            res = acquire => res,
+                            _ = wait_conn_closed(conn_token.clone()) => {
+                                let _ = req_end_tx.send(());
+                                bail!(WorkerError::ClientDisconnected)
+                            }
+                        };
+
+                        match acquired {
+                            Ok(permit) => permit,
+                            Err(_) => {
+                                return Ok((
+                                    emit_status_code(StatusCode::SERVICE_UNAVAILABLE, None, false),
+                                    req_end_tx,
+                                ));
+                            }
+                        }
+                    } else {
+                        None
+                    };
+
+                    let queue_duration = received_at.elapsed();
+                    let is_cold = !profile.has_served.swap(true, Ordering::AcqRel);
+                    let exec_started_at = Instant::now();
+                    let pressure = profile.pressure.clone();
+                    let maybe_cpu_charge = maybe_cpu_budget.map(|budget| {
+                        let before = pressure.cpu_time_accumulated_ms.load(Ordering::Acquire);
+
+                        // NOTE: The worker keeps running the handler while the
+                        // body of the response is streamed, so the request is
+                        // only charged once the body is done.
+                        scopeguard::guard(budget, move |budget| {
+                            let after = pressure.cpu_time_accumulated_ms.load(Ordering::Acquire);
+
+                            budget.charge(after.saturating_sub(before));
+                        })
+                    });
                     let result = send_user_worker_request(
-                        profile.worker_request_msg_tx,
+                        profile.worker_request_msg_tx.clone(),
                         req,
                         cancel,
                         exit,
@@ -540,7 +1909,27 @@ impl WorkerPool {
                     .await;
 
                     match result {
-                        Ok(req) => Ok((req, req_end_tx)),
+                        Ok(mut res) => {
+                            if let Some(charge) = maybe_cpu_charge {
+                                let body = std::mem::take(res.body_mut());
+
+                                *res.body_mut() = Body::wrap_stream(body.map(move |it| {
+                                    let _ = &charge;
+                                    it
+                                }));
+                            }
+
+                            if server_timing {
+                                append_server_timing(
+                                    &mut res,
+                                    is_cold.then_some(profile.boot_duration),
+                                    queue_duration,
+                                    exec_started_at.elapsed(),
+                                );
+                            }
+
+                            Ok((res, req_end_tx))
+                        }
                         Err(err) => {
                             let _ = req_end_tx.send(());
                             error!("failed to send request to user worker: {}", err.to_string());
@@ -551,7 +1940,161 @@ impl WorkerPool {
 
                 // Spawn the closure as an async task
                 tokio::task::spawn(async move {
-                    if res_tx.send(request_handler.await).is_err() {
+                    let started_at = Instant::now();
+                    let req_size = req.body().size_hint().exact();
+                    let registry_in_flight_guard = maybe_registry_in_flight.map(|it| {
+                        it.fetch_add(1, Ordering::Release);
+                        scopeguard::guard(it, |it| {
+                            it.fetch_sub(1, Ordering::Release);
+                        })
+                    });
+
+                    let (req, maybe_recording) = match maybe_recording {
+                        Some((recorder, service_path)) => {
+                            let (req, recording) =
+                                RequestRecording::start(recorder, &service_path, req);
+
+                            (req, Some(recording))
+                        }
+
+                        None => (req, None),
+                    };
+
+                    let (req, maybe_stats) = match maybe_stats {
+                        Some((stats, service_path)) => {
+                            let (req, request_stats) =
+                                RequestStats::start(stats, service_path, req);
+
+                            (req, Some(request_stats))
+                        }
+
+                        None => (req, None),
+                    };
+
+                    let result = match maybe_coalesce {
+                        Some((coalescer, coalesce_key, req_end_tx)) => {
+                            coalescer
+                                .run(coalesce_key, req, req_end_tx, request_handler)
+                                .await
+                        }
+
+                        None => request_handler(req).await,
+                    };
+
+                    // NOTE: A cancelled worker means that the isolate died
+                    // while handling the request (e.g. it hit a limit).
+                    let mut result = match (result, maybe_retry) {
+                        (Err(err), Some(retry)) if worker_cancel.is_cancelled() => {
+                            retry.run(err).await
+                        }
+
+                        (result, _) => result,
+                    };
+
+                    if let Some(recording) = maybe_recording {
+                        recording.finish(result.as_mut().map(|(res, _)| res).map_err(|err| &*err));
+                    }
+
+                    drop(registry_in_flight_guard);
+
+                    if let Some(in_flight) = maybe_in_flight {
+                        in_flight.finish(started_at.elapsed());
+                    }
+
+                    if let Some(mirror) = maybe_mirror {
+                        mirror.finish(
+                            result.as_ref().ok().map(|(res, _)| res.status()),
+                            started_at.elapsed(),
+                        );
+                    }
+
+                    if let Some(rewrite_policy) = maybe_rewrite_policy {
+                        if let Ok((res, _)) = result.as_mut() {
+                            rewrite_policy.rewrite_response(res);
+                        }
+                    }
+
+                    if let Some((header_policy, origin)) = maybe_header_policy {
+                        if let Ok((res, _)) = result.as_mut() {
+                            header_policy.apply(origin.as_ref(), res);
+                        }
+                    }
+
+                    if let Some((request_log, method, path)) = maybe_request_log {
+                        let latency = started_at.elapsed();
+                        let (status, response_size, error) = match result.as_ref() {
+                            Ok((res, _)) => (
+                                Some(res.status().as_u16()),
+                                res.body().size_hint().exact(),
+                                None,
+                            ),
+
+                            Err(err) => (None, None, Some(format!("{err:#}"))),
+                        };
+
+                        request_log.push(RequestSummary {
+                            method,
+                            path,
+                            status,
+                            latency_ms: latency.as_millis().try_into().unwrap_or(u64::MAX),
+                            request_size: req_size,
+                            response_size,
+                            error,
+                            started_at: (SystemTime::now() - latency)
+                                .duration_since(UNIX_EPOCH)
+                                .map_or(0, |it| it.as_millis() as u64),
+                        });
+                    }
+
+                    if let Some((buffer, ctx, service_path, method, path)) = maybe_trace {
+                        let duration = started_at.elapsed();
+                        let (status, error) = match result.as_ref() {
+                            Ok((res, _)) => (Some(res.status().as_u16()), None),
+                            Err(err) => (None, Some(format!("{err:#}"))),
+                        };
+
+                        buffer.push(TraceRecord {
+                            trace_id: ctx.trace_id,
+                            span_id: ctx.span_id,
+                            parent_span_id: ctx.parent_span_id,
+                            service_path,
+                            worker_key,
+                            method,
+                            path,
+                            status,
+                            error,
+                            started_at: (SystemTime::now() - duration)
+                                .duration_since(UNIX_EPOCH)
+                                .map_or(0, |it| it.as_millis() as u64),
+                            duration_ms: duration.as_millis().try_into().unwrap_or(u64::MAX),
+                        });
+                    }
+
+                    // NOTE: The bodies are counted as they are streamed, so the
+                    // request is only recorded once its response body is done.
+                    if let Some(request_stats) = maybe_stats {
+                        request_stats.finish(result.as_mut().map(|(res, _)| res).map_err(|err| &*err));
+                    }
+
+                    // NOTE: The request only ends once its response body is
+                    // done, which can be well after its head was sent.
+                    if let Some((journal, id)) = maybe_journal {
+                        match result.as_mut() {
+                            Ok((res, _)) => {
+                                let guard = journal.end_on_drop(id, Some(res.status().as_u16()));
+                                let body = std::mem::take(res.body_mut());
+
+                                *res.body_mut() = Body::wrap_stream(body.map(move |it| {
+                                    let _ = &guard;
+                                    it
+                                }));
+                            }
+
+                            Err(_) => journal.end(id, None),
+                        }
+                    }
+
+                    if res_tx.send(result).is_err() {
                         error!("main worker receiver dropped")
                     }
                 });
@@ -560,23 +2103,153 @@ impl WorkerPool {
             }
 
             None => {
-                if res_tx
-                    .send(Err(anyhow!("user worker not available")))
-                    .is_err()
-                {
+                if res_tx.send(Err(anyhow!(WorkerError::WorkerGone))).is_err() {
                     error!("main worker receiver dropped")
                 }
 
-                Err(anyhow!("user worker not available"))
+                Err(anyhow!(WorkerError::WorkerGone))
             }
         };
     }
 
+    /// Rejects a request that could not be queued since too many requests
+    /// are already pending in the pool.
+    pub fn reject_request(&self, key: &Uuid, res_tx: Sender<Result<SendRequestResult, Error>>) {
+        // NOTE: The per-worker supervisor already counted the request as a
+        // demand when the worker was handed out, so it must be acknowledged.
+        if let Some(profile) = self
+            .user_workers
+            .get(key)
+            .filter(|_| self.policy.supervisor_policy.is_per_worker())
+        {
+            let _ = profile.timing_tx_pair.1.send(());
+        }
+
+        if res_tx
+            .send(Err(anyhow!(WorkerError::PoolOverloaded)))
+            .is_err()
+        {
+            error!("main worker receiver dropped")
+        }
+    }
+
+    /// Lets the supervisor of the worker know that a request handed to it was
+    /// rejected by the request filters before it was sent.
+    fn release_rejected_request(&self, profile: &UserWorkerProfile) {
+        let (req_start_tx, req_end_tx) = profile.timing_tx_pair.clone();
+
+        // NOTE: The per-worker supervisor already counted the request as a
+        // demand when the worker was handed out, see `reject_request`.
+        if self.policy.supervisor_policy.is_per_worker() {
+            let _ = req_end_tx.send(());
+            return;
+        }
+
+        // NOTE: Other supervisors expect every request to pass the fence, so
+        // the request is ended as soon as it is admitted.
+        let fence = Arc::new(Notify::const_new());
+
+        if req_start_tx.send(fence.clone()).is_err() {
+            return;
+        }
+
+        let cancel = profile.cancel.clone();
+
+        drop(tokio::spawn(async move {
+            tokio::select! {
+                _ = fence.notified() => {
+                    let _ = req_end_tx.send(());
+                }
+                _ = cancel.cancelled() => {}
+            }
+        }));
+    }
+
+    /// Replaces the env vars of every running worker of the service, and
+    /// returns how many workers have been updated.
+    pub fn update_env(&mut self, service_path: &str, env_vars: HashMap<String, String>) -> usize {
+        for service in self
+            .persisted_services
+            .values_mut()
+            .filter(|it| it.service_path == service_path)
+        {
+            service.env_vars.clone_from(&env_vars);
+            service.has_env_vars = !env_vars.is_empty();
+        }
+
+        let mut count = 0;
+
+        for (key, profile) in self.user_workers.iter() {
+            if profile.service_path != service_path {
+                continue;
+            }
+
+            let mut env_vars = env_vars.clone();
+
+            if let Some(allowlist) = profile.env_allowlist.as_ref() {
+                env_vars.retain(|name, _| allowlist.contains(name));
+            }
+
+            env_vars.insert("SB_EXECUTION_ID".to_string(), key.to_string());
+            profile.env_provider.update(env_vars);
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Merges the flags into the feature flags of every running worker that
+    /// matches the target, and returns how many workers have been updated.
+    pub fn update_feature_flags(
+        &mut self,
+        target: &FeatureFlagsTarget,
+        flags: FeatureFlags,
+    ) -> usize {
+        for service in self
+            .persisted_services
+            .values_mut()
+            .filter(|it| target.matches(it.tenant.as_deref(), &it.service_path))
+        {
+            feature_flags::merge(&mut service.feature_flags, flags.clone());
+        }
+
+        let mut count = 0;
+
+        for profile in self
+            .user_workers
+            .values()
+            .filter(|it| target.matches(it.identity.tenant.as_deref(), &it.service_path))
+        {
+            profile.feature_flag_provider.update(flags.clone());
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Applies the settings that may change while the pool runs. The
+    /// services that are already running keep their parallelism.
+    pub fn update_policy(&mut self, update: PoolPolicyUpdate) {
+        // NOTE: The parallelism is fixed to 1 under the oneshot policy.
+        if !self.policy.supervisor_policy.is_oneshot() {
+            self.policy.max_parallelism = update.max_parallelism;
+        }
+
+        self.policy.request_wait_timeout_ms = update.request_wait_timeout_ms;
+        self.policy.drain_timeout_ms = update.drain_timeout_ms;
+    }
+
+    pub fn update_tenant_weights(&self, weights: HashMap<String, u32>) {
+        if let Some(scheduler) = self.tenant_scheduler.as_ref() {
+            scheduler.set_weights(weights);
+        }
+    }
+
     pub fn idle(&mut self, key: &Uuid) {
         if let Some(registry) = self
             .user_workers
             .get_mut(key)
-            .and_then(|it| self.active_workers.get_mut(&it.service_path))
+            .and_then(|it| self.active_workers.get_mut(&it.identity))
         {
             registry.mark_idle(key, self.policy.supervisor_policy);
         }
@@ -584,11 +2257,12 @@ impl WorkerPool {
 
     pub fn shutdown(&mut self, key: &Uuid) {
         self.retire(key);
+        self.request_logs.remove(key);
 
         let Some((notify_tx, _)) = self
             .user_workers
             .remove(key)
-            .and_then(|it| self.active_workers.get(&it.service_path))
+            .and_then(|it| self.active_workers.get(&it.identity))
             .map(|it| it.notify_pair.clone())
         else {
             return;
@@ -599,11 +2273,365 @@ impl WorkerPool {
         self.metric_src.decl_active_user_workers();
     }
 
+    /// Stops routing requests to the worker and terminates it once its
+    /// in-flight requests have completed, or the drain timeout has elapsed.
+    ///
+    /// Returns `false` if there is no such worker.
+    pub fn terminate(&mut self, key: &Uuid) -> bool {
+        let Some(termination) = self.user_workers.get(key).map(|it| it.termination.clone()) else {
+            return false;
+        };
+
+        self.retire(key);
+        termination.cancel();
+
+        true
+    }
+
+    /// Returns the summaries of the last requests handled by the worker, or
+    /// `None` if there is no such worker.
+    pub fn request_log(&self, key: &Uuid) -> Option<Vec<RequestSummary>> {
+        if !self.user_workers.contains_key(key) {
+            return None;
+        }
+
+        Some(
+            self.request_logs
+                .get(key)
+                .map(RequestLog::snapshot)
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Returns the traces of the last requests dispatched by the pool that
+    /// match `query`, from the newest to the oldest.
+    pub fn traces(&self, query: &TraceQuery) -> Vec<TraceRecord> {
+        self.trace_buffer
+            .as_ref()
+            .map(|it| it.query(query))
+            .unwrap_or_default()
+    }
+
+    /// Sends the synthetic request of `check` to the worker, bypassing the
+    /// filters, the schedulers and the metrics of the pool. The report is
+    /// `None` if there is no such worker.
+    pub fn health_check(
+        &self,
+        key: &Uuid,
+        check: HealthCheck,
+        tx: oneshot::Sender<Option<HealthReport>>,
+    ) {
+        let Some(profile) = self.user_workers.get(key).cloned() else {
+            if tx.send(None).is_err() {
+                error!("main worker receiver dropped");
+            }
+
+            return;
+        };
+
+        let policy = self.policy.supervisor_policy;
+
+        drop(tokio::spawn(async move {
+            let report = check_worker_health(policy, profile, check).await;
+
+            if tx.send(Some(report)).is_err() {
+                error!("main worker receiver dropped");
+            }
+        }));
+    }
+
+    /// Boots or retires workers of the services that have an autoscale policy,
+    /// depending on their load since the last call.
+    /// Returns the status of every service that has had workers in the pool,
+    /// ordered by their identity.
+    pub fn status(&self) -> Vec<ServiceStatus> {
+        let now = Instant::now();
+        let mut statuses = self
+            .active_workers
+            .iter()
+            .map(|(identity, registry)| {
+                let workers = registry
+                    .workers
+                    .iter()
+                    .filter_map(|it| self.user_workers.get(&it.0))
+                    .filter(|it| !it.status.is_retired.is_raised())
+                    .collect::<Vec<_>>();
+
+                let last_termination = LIFECYCLE_HISTORY
+                    .query(&LifecycleHistoryQuery {
+                        worker_id: Some(identity.to_string()),
+                        kinds: vec![
+                            LifecycleKind::BootFailure,
+                            LifecycleKind::UncaughtException,
+                            LifecycleKind::Crashed,
+                            LifecycleKind::Shutdown,
+                            LifecycleKind::EventLoopCompleted,
+                        ],
+                        limit: Some(1),
+                        ..Default::default()
+                    })
+                    .pop();
+
+                ServiceStatus {
+                    key: identity.to_string(),
+                    identity: identity.clone(),
+                    active_instances: workers.len(),
+                    in_flight_requests: registry.in_flight.load(Ordering::Acquire),
+                    queue_depth: registry.queued.load(Ordering::Acquire),
+                    limits: workers
+                        .iter()
+                        .max_by_key(|it| it.booted_at)
+                        .map(|it| it.limits),
+                    last_termination,
+                    uptime_ms: workers
+                        .iter()
+                        .map(|it| it.booted_at)
+                        .min()
+                        .map(|it| now.duration_since(it).as_millis() as u64),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        statuses.sort_by(|a, b| a.key.cmp(&b.key));
+        statuses
+    }
+
+    /// Sends a [`WorkerEvents::ConcurrencySample`] event for every service
+    /// that has active workers.
+    ///
+    /// NOTE: The workers are counted as of the sample, so one that boots or
+    /// retires in between two samples is billed for the whole interval or not
+    /// at all. Shorter intervals make this more accurate.
+    pub fn sample_concurrency(&self, interval: Duration) {
+        if self.worker_event_sender.is_none() {
+            return;
+        }
+
+        for (identity, registry) in self.active_workers.iter() {
+            let workers = registry
+                .workers
+                .iter()
+                .filter_map(|it| self.user_workers.get(&it.0))
+                .filter(|it| !it.status.is_retired.is_raised())
+                .collect::<Vec<_>>();
+
+            if workers.is_empty() {
+                continue;
+            }
+
+            let memory_limit_mb = workers
+                .iter()
+                .map(|it| it.limits.memory_limit_mb)
+                .sum::<u64>();
+
+            send_event_if_event_worker_available(
+                self.worker_event_sender.as_ref(),
+                WorkerEvents::ConcurrencySample(ConcurrencySampleEvent {
+                    interval_ms: interval.as_millis() as u64,
+                    in_flight_requests: registry.in_flight.load(Ordering::Acquire),
+                    instances: workers.len(),
+                    memory_limit_mb,
+                    gb_seconds: memory_limit_mb as f64 / 1024.0 * interval.as_secs_f64(),
+                }),
+                EventMetadata {
+                    service_path: Some(identity.service.clone()),
+                    execution_id: None,
+                    // NOTE: The sample is of the service rather than of any
+                    // of its workers.
+                    worker_id: None,
+                },
+            );
+        }
+    }
+
+    pub fn autoscale(&mut self) {
+        // NOTE: The other policies already boot a worker for every concurrent
+        // request.
+        if !self.policy.supervisor_policy.is_per_worker() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut loads = self.autoscaler.load.take();
+        let mut decisions = vec![];
+
+        for (identity, registry) in self.active_workers.iter() {
+            let workers = registry
+                .workers
+                .iter()
+                .filter_map(|it| self.user_workers.get(&it.0).map(|profile| (it.0, profile)))
+                .filter(|(_, it)| !it.status.is_retired.is_raised())
+                .collect::<Vec<_>>();
+
+            let maybe_policy = workers
+                .iter()
+                .find_map(|(_, it)| it.autoscale_policy.clone());
+
+            let Some(state) = self.autoscaler.state(identity, maybe_policy) else {
+                continue;
+            };
+
+            let Some(policy) = state.policy.clone() else {
+                continue;
+            };
+
+            let (queue_depth, p95_latency_ms) = loads.remove(identity).unwrap_or_default();
+            let load = ServiceLoad {
+                instances: workers.len(),
+                booting: state.booting.load(Ordering::Acquire),
+                queue_depth,
+                p95_latency_ms,
+            };
+
+            // NOTE: The worker with the fewest requests ever sent to it is the
+            // one retired, as it is the least likely to be busy.
+            let maybe_idlest = workers
+                .iter()
+                .min_by_key(|(_, it)| it.status.demand.load(Ordering::Acquire))
+                .map(|(key, _)| *key);
+
+            match decide(&policy, &load, state, now) {
+                Scale::Hold => {}
+                scale => decisions.push((identity.clone(), load, scale, maybe_idlest)),
+            }
+        }
+
+        for (identity, load, scale, maybe_idlest) in decisions {
+            let mut maybe_key = None;
+            let (to_instances, reason) = match scale {
+                Scale::Up(count, reason) => {
+                    let booted = self.boot_workers(&identity, count);
+
+                    if booted == 0 {
+                        continue;
+                    }
+
+                    (load.instances + load.booting + booted, reason)
+                }
+
+                Scale::Down(reason) => {
+                    let Some(key) = maybe_idlest else {
+                        continue;
+                    };
+
+                    if !self.terminate(&key) {
+                        continue;
+                    }
+
+                    maybe_key = Some(key);
+                    (load.instances + load.booting - 1, reason)
+                }
+
+                Scale::Hold => continue,
+            };
+
+            send_event_if_event_worker_available(
+                self.worker_event_sender.as_ref(),
+                WorkerEvents::WorkerScaled(WorkerScaledEvent {
+                    from_instances: load.instances + load.booting,
+                    to_instances,
+                    reason,
+                    queue_depth: load.queue_depth,
+                    p95_latency_ms: load.p95_latency_ms,
+                }),
+                EventMetadata {
+                    service_path: Some(identity.service.clone()),
+                    execution_id: maybe_key,
+                    worker_id: Some(identity.to_string()),
+                },
+            );
+        }
+    }
+
+    /// Boots new workers of the service, and returns how many are booting.
+    fn boot_workers(&self, identity: &WorkerIdentity, count: usize) -> usize {
+        let (Some(service), Some(state)) = (
+            self.persisted_services.get(identity),
+            self.autoscaler.services.get(identity),
+        ) else {
+            return 0;
+        };
+
+        let mut booted = 0;
+
+        for _ in 0..count {
+            let mut opts = match service.clone().into_opts() {
+                Ok(it) => it,
+                Err(err) => {
+                    error!("failed to boot a worker of {identity}: {err:#}");
+                    break;
+                }
+            };
+
+            // NOTE: Force the creation so that the pool does not hand out one
+            // of the running workers.
+            if let Some(conf) = opts.conf.as_user_worker_mut() {
+                conf.force_create = true;
+            }
+
+            let (create_tx, create_rx) = oneshot::channel();
+
+            if self
+                .worker_pool_msgs_tx
+                .send(UserWorkerMsgs::Create(opts, create_tx))
+                .is_err()
+            {
+                break;
+            }
+
+            let booting = state.booting.clone();
+            let maybe_health_check = state
+                .policy
+                .as_ref()
+                .and_then(|it| it.health_check.clone())
+                .map(|it| (it, self.worker_pool_msgs_tx.clone()));
+
+            booting.fetch_add(1, Ordering::Release);
+            booted += 1;
+
+            drop(tokio::spawn(async move {
+                let maybe_key = match create_rx.await {
+                    Ok(Ok(it)) => Some(it.key),
+                    Ok(Err(err)) => {
+                        error!("failed to boot a worker: {err:#}");
+                        None
+                    }
+                    Err(_) => None,
+                };
+
+                // NOTE: The worker still counts as booting while it is being
+                // checked, so that the autoscaler does not boot another one
+                // in the meantime.
+                if let Some((key, (check, msgs_tx))) = maybe_key.zip(maybe_health_check) {
+                    let (tx, rx) = oneshot::channel();
+
+                    if msgs_tx
+                        .send(UserWorkerMsgs::HealthCheck(key, check, tx))
+                        .is_ok()
+                    {
+                        if let Ok(Some(report)) = rx.await {
+                            if !report.is_healthy() {
+                                warn!("terminating an unhealthy worker ({key}): {report:?}");
+
+                                let (tx, _) = oneshot::channel();
+                                let _ = msgs_tx.send(UserWorkerMsgs::Terminate(key, tx));
+                            }
+                        }
+                    }
+                }
+
+                booting.fetch_sub(1, Ordering::Release);
+            }));
+        }
+
+        booted
+    }
+
     fn retire(&mut self, key: &Uuid) {
         if let Some(profile) = self.user_workers.get_mut(key) {
             let registry = self
                 .active_workers
-                .get_mut(&profile.service_path)
+                .get_mut(&profile.identity)
                 .expect("registry must be initialized at this point");
 
             let _ = profile.permit.take();
@@ -613,23 +2641,93 @@ impl WorkerPool {
                 let _ = notify_tx.send(None);
             }
 
+            registry.sessions.forget_worker(key);
+
             if registry.workers.contains(key) {
                 registry.workers.remove(key);
                 self.metric_src.incl_retired_user_worker();
             }
+
+            // NOTE: A service whose workers are all gone is no longer hosted,
+            // so it must be neither retried against nor pre-warmed.
+            if registry.workers.is_empty() {
+                self.persisted_services.remove(&profile.identity);
+                self.mirror_samplers.remove(&profile.identity);
+                self.autoscaler.remove(&profile.identity);
+            }
+        }
+    }
+
+    /// Returns the key of the worker that currently serves the identity.
+    pub fn resolve_identity(&self, identity: &WorkerIdentity) -> Option<Uuid> {
+        self.active_workers
+            .get(identity)?
+            .workers
+            .iter()
+            .map(|it| it.0)
+            .find(|it| {
+                self.user_workers
+                    .get(it)
+                    .map_or(false, |it| !it.status.is_retired.is_raised())
+            })
+    }
+
+    /// Returns the options that differ from the ones the active workers of
+    /// the identity were created with, if it has any.
+    fn changed_create_options(
+        &self,
+        identity: &WorkerIdentity,
+        create_options: &PersistedService,
+    ) -> Vec<String> {
+        if self.resolve_identity(identity).is_none() {
+            return vec![];
         }
+
+        self.active_workers
+            .get(identity)
+            .and_then(|it| it.create_options.as_ref())
+            .map(|it| it.changed_options(create_options))
+            .unwrap_or_default()
+    }
+
+    /// Drains every active worker of the identity, returning how many there
+    /// were.
+    fn drain_identity(&mut self, identity: &WorkerIdentity) -> usize {
+        let keys = self
+            .active_workers
+            .get(identity)
+            .map(|it| it.workers.iter().map(|it| it.0).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        keys.iter().filter(|it| self.terminate(it)).count()
     }
 
-    fn maybe_active_worker(&mut self, service_path: &String, force_create: bool) -> Option<Uuid> {
+    fn maybe_active_worker(
+        &mut self,
+        identity: &WorkerIdentity,
+        force_create: bool,
+        maybe_session_key: Option<&str>,
+    ) -> Option<Uuid> {
         if force_create {
             return None;
         }
 
-        let registry = self.active_workers.get_mut(service_path)?;
+        let registry = self.active_workers.get_mut(identity)?;
         let policy = self.policy.supervisor_policy;
 
-        let mut advance_fn = move || registry.mark_used_and_try_advance(policy).copied();
-        let worker_uuid = advance_fn()?;
+        let worker_uuid =
+            match maybe_session_key.and_then(|it| registry.try_use_session(it, policy)) {
+                Some(it) => it,
+                None => {
+                    let worker_uuid = registry.mark_used_and_try_advance(policy).copied()?;
+
+                    if let Some(session_key) = maybe_session_key {
+                        registry.stick_session(session_key, worker_uuid);
+                    }
+
+                    worker_uuid
+                }
+            };
 
         match self
             .user_workers
@@ -648,8 +2746,254 @@ impl WorkerPool {
 
             _ => {
                 self.retire(&worker_uuid);
-                self.maybe_active_worker(service_path, force_create)
+                self.maybe_active_worker(identity, force_create, maybe_session_key)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use event_worker::events::{EventMetadata, WorkerEvents};
+    use http_v02::{header, Method, Request, Response};
+    use hyper_v014::Body;
+    use sb_core::SharedMetricSource;
+    use sb_workers::builder::UserWorkerBuilder;
+    use sb_workers::context::{
+        CreateDecision, CreateUserWorkerResult, UserWorkerMsgs, UserWorkerProfile, WorkerIdentity,
+        WorkerLimits,
+    };
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+    use uuid::Uuid;
+
+    use super::{
+        replayable_copy, PersistedService, RequestRetry, StickySessions, WorkerPool,
+        WorkerPoolPolicy, RETRIED_HEADER_NAME,
+    };
+
+    fn profile(service: &str, memory_limit_mb: u64) -> UserWorkerProfile {
+        UserWorkerProfile {
+            worker_request_msg_tx: mpsc::unbounded_channel().0,
+            timing_tx_pair: (mpsc::unbounded_channel().0, mpsc::unbounded_channel().0),
+            service_path: service.to_string(),
+            identity: WorkerIdentity {
+                tenant: None,
+                service: service.to_string(),
+                revision: None,
+            },
+            permit: None,
+            cancel: CancellationToken::new(),
+            termination: CancellationToken::new(),
+            status: Default::default(),
+            exit: Default::default(),
+            verify_jwt: false,
+            allowed_methods: None,
+            allowed_path_prefixes: None,
+            header_policy: None,
+            rewrite_policy: None,
+            mirror_policy: None,
+            autoscale_policy: None,
+            env_provider: Default::default(),
+            env_allowlist: None,
+            feature_flag_provider: Default::default(),
+            boot_warnings: vec![],
+            limits: WorkerLimits {
+                memory_limit_mb,
+                ..Default::default()
+            },
+            booted_at: Instant::now(),
+            boot_duration: Duration::ZERO,
+            has_served: Arc::default(),
+            session_key: None,
+            pressure: Arc::default(),
+            failure_injection: None,
+            memory_reservation: None,
+        }
+    }
+
+    fn request(method: Method, body: Body) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri("/hello?name=world")
+            .header("x-foo", "bar")
+            .body(body)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sticky_sessions_evict_least_recently_used() {
+        let mut sessions = StickySessions::new(2, std::time::Duration::from_secs(60));
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        sessions.stick("a", a);
+        sessions.stick("b", b);
+        sessions.stick("a", b);
+        sessions.touch("a");
+        sessions.stick("c", c);
+
+        assert_eq!(sessions.get("a"), Some(a));
+        assert_eq!(sessions.get("b"), None);
+        assert_eq!(sessions.get("c"), Some(c));
+
+        sessions.forget_worker(&a);
+
+        assert_eq!(sessions.get("a"), None);
+        assert_eq!(sessions.get("c"), Some(c));
+    }
+
+    #[test]
+    fn test_sticky_sessions_expire() {
+        let mut sessions = StickySessions::new(2, std::time::Duration::ZERO);
+
+        sessions.stick("a", Uuid::new_v4());
+
+        assert_eq!(sessions.get("a"), None);
+        assert_eq!(sessions.inner.len(), 0);
+    }
+
+    #[test]
+    fn test_replayable_copy() {
+        let copy = replayable_copy(&request(Method::GET, Body::empty())).unwrap();
+
+        assert_eq!(copy.method(), Method::GET);
+        assert_eq!(copy.uri(), "/hello?name=world");
+        assert_eq!(copy.headers()["x-foo"], "bar");
+
+        assert!(replayable_copy(&request(Method::HEAD, Body::empty())).is_some());
+        assert!(replayable_copy(&request(Method::POST, Body::empty())).is_none());
+        assert!(replayable_copy(&request(Method::GET, Body::from("meow"))).is_none());
+
+        let mut upgrade = request(Method::GET, Body::empty());
+
+        upgrade
+            .headers_mut()
+            .insert(header::CONNECTION, "upgrade".parse().unwrap());
+        upgrade
+            .headers_mut()
+            .insert(header::UPGRADE, "websocket".parse().unwrap());
+
+        assert!(replayable_copy(&upgrade).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_request_retry_against_fresh_worker() {
+        let (pool_tx, mut pool_rx) = mpsc::unbounded_channel();
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let retry_key = Uuid::new_v4();
+
+        let opts = UserWorkerBuilder::new("./hello").build().unwrap();
+        let retry = RequestRetry {
+            service: PersistedService::describe(&opts).unwrap(),
+            req: replayable_copy(&request(Method::GET, Body::empty())).unwrap(),
+            conn_token: None,
+            worker_pool_msgs_tx: pool_tx,
+            events_msg_tx: Some(events_tx),
+            metadata: EventMetadata::default(),
+        };
+
+        let pool = tokio::spawn(async move {
+            let Some(UserWorkerMsgs::Create(opts, create_tx)) = pool_rx.recv().await else {
+                panic!("expected a worker to be created");
+            };
+
+            assert!(opts.conf.as_user_worker().unwrap().force_create);
+
+            create_tx
+                .send(Ok(CreateUserWorkerResult {
+                    key: retry_key,
+                    warnings: vec![],
+                    decision: CreateDecision::Created,
+                }))
+                .unwrap();
+
+            let Some(UserWorkerMsgs::SendRequest(key, req, res_tx, _)) = pool_rx.recv().await
+            else {
+                panic!("expected the request to be sent again");
+            };
+
+            assert_eq!(key, retry_key);
+            assert_eq!(req.headers()["x-foo"], "bar");
+
+            res_tx
+                .send(Ok((
+                    Response::new(Body::from("ok")),
+                    mpsc::unbounded_channel().0,
+                )))
+                .unwrap();
+        });
+
+        let (res, _) = retry.run(anyhow::anyhow!("worker died")).await.unwrap();
+
+        pool.await.unwrap();
+
+        assert_eq!(res.headers()[RETRIED_HEADER_NAME], "1");
+
+        let WorkerEvents::RequestRetried(event) = events_rx.recv().await.unwrap().event else {
+            panic!("expected a retry event");
+        };
+
+        assert_eq!(event.method, "GET");
+        assert_eq!(event.path, "/hello");
+        assert_eq!(event.retry_key, Some(retry_key));
+        assert!(event.succeeded);
+    }
+
+    #[test]
+    fn test_sample_concurrency() {
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let mut pool = WorkerPool::new(
+            WorkerPoolPolicy::default(),
+            SharedMetricSource::default(),
+            Some(events_tx),
+            mpsc::unbounded_channel().0,
+            None,
+            None,
+        );
+
+        let retired = profile("./hello", 512);
+
+        retired.status.is_retired.raise();
+
+        pool.add_user_worker(Uuid::new_v4(), profile("./hello", 256));
+        pool.add_user_worker(Uuid::new_v4(), profile("./hello", 256));
+        pool.add_user_worker(Uuid::new_v4(), retired);
+        pool.add_user_worker(Uuid::new_v4(), profile("./idle", 128));
+        pool.user_workers
+            .values()
+            .filter(|it| it.service_path == "./idle")
+            .for_each(|it| it.status.is_retired.raise());
+
+        let hello = pool
+            .active_workers
+            .keys()
+            .find(|it| it.service == "./hello")
+            .unwrap()
+            .clone();
+
+        pool.active_workers[&hello]
+            .in_flight
+            .store(3, Ordering::Release);
+        pool.sample_concurrency(Duration::from_secs(2));
+
+        let event = events_rx.try_recv().unwrap();
+        let WorkerEvents::ConcurrencySample(sample) = event.event else {
+            panic!("expected a concurrency sample");
+        };
+
+        assert_eq!(event.metadata.service_path.as_deref(), Some("./hello"));
+        assert_eq!(event.metadata.worker_id, None);
+        assert_eq!(sample.interval_ms, 2000);
+        assert_eq!(sample.in_flight_requests, 3);
+        assert_eq!(sample.instances, 2);
+        assert_eq!(sample.memory_limit_mb, 512);
+        assert_eq!(sample.gb_seconds, 1.0);
+
+        // NOTE: A service whose workers are all retired is not sampled.
+        assert!(events_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Error};
+use deno_core::serde_json;
+use http_v02::{header, Method, StatusCode};
+use hyper_v014::{Body, Request, Response};
+use log::error;
+use sb_core::cert::OutboundTlsOptions;
+use sb_core::feature_flags::FeatureFlags;
+use sb_graph::EszipPayloadKind;
+use sb_workers::context::{
+    CreateUserWorkerResult, FeatureFlagsTarget, HealthCheck, UserWorkerMsgs, UserWorkerRuntimeOpts,
+    WorkerContextInitOpts, WorkerLocale, WorkerRuntimeOpts,
+};
+use sb_workers::errors::WorkerOptionsChangedError;
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::runtime_config::LimitsConfig;
+
+use super::internal_auth::InternalApiAuth;
+use super::router::{emit_json_error, FunctionRouterOpts};
+use super::utils::write_body_bounded;
+
+pub static WORKERS_API_PATH: &str = "/_internal/workers";
+
+/// Largest eszip that is fetched from the `eszipUrl` of a creation.
+static MAX_ESZIP_SIZE: u64 = 256 * 1024 * 1024;
+static ESZIP_FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Used as the idempotency key of a creation if the body does not have one.
+static IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// The options accepted by `POST /_internal/workers`.
+///
+/// Omitted limits fall back to the limits of the runtime config, then to the
+/// defaults of [`UserWorkerRuntimeOpts`].
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct CreateWorkerRequest {
+    service_path: String,
+    eszip_url: Option<String>,
+    #[serde(default)]
+    env_vars: HashMap<String, String>,
+    import_map_path: Option<String>,
+    no_module_cache: Option<bool>,
+    #[serde(default)]
+    force_create: bool,
+    idempotency_key: Option<String>,
+    tenant: Option<String>,
+    revision: Option<String>,
+    #[serde(default)]
+    feature_flags: FeatureFlags,
+
+    memory_limit_mb: Option<u64>,
+    low_memory_multiplier: Option<u64>,
+    memory_leak_trend_samples: Option<u64>,
+    recycle_on_memory_leak: Option<bool>,
+    heap_initial_mb: Option<u64>,
+    heap_max_mb: Option<u64>,
+    low_memory_notification_percent: Option<u64>,
+    worker_timeout_ms: Option<u64>,
+    cpu_time_soft_limit_ms: Option<u64>,
+    cpu_time_hard_limit_ms: Option<u64>,
+    cpu_time_cap_ms: Option<u64>,
+    request_cpu_time_cap_ms: Option<u64>,
+    event_loop_lag_warn_ms: Option<u64>,
+    event_loop_lag_limit_ms: Option<u64>,
+    max_resources: Option<u64>,
+    max_websocket_connections: Option<u64>,
+    websocket_idle_timeout_ms: Option<u64>,
+    fetch_timeout_ms: Option<u64>,
+    max_concurrent_fetches: Option<u64>,
+    max_emails_per_minute: Option<u64>,
+    clock_granularity_ms: Option<u64>,
+    log_sample_rate: Option<f64>,
+    log_rate_limit: Option<u64>,
+    net_access_disabled: Option<bool>,
+    allow_net: Option<Vec<String>>,
+    allow_sockets: Option<Vec<String>>,
+    allow_remote_modules: Option<bool>,
+    allow_shared_array_buffer: Option<bool>,
+    allow_wasm_threads: Option<bool>,
+    allow_atomics_wait: Option<bool>,
+    custom_module_root: Option<String>,
+    allowed_methods: Option<Vec<String>>,
+    allowed_path_prefixes: Option<Vec<String>>,
+    outbound_tls: Option<OutboundTlsOptions>,
+    locale: Option<String>,
+    timezone: Option<String>,
+}
+
+/// The body accepted by `PATCH /_internal/workers/feature-flags`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct UpdateFeatureFlagsRequest {
+    tenant: Option<String>,
+    service_path: Option<String>,
+    flags: FeatureFlags,
+}
+
+impl CreateWorkerRequest {
+    fn into_runtime_opts(self, limits: &LimitsConfig) -> UserWorkerRuntimeOpts {
+        let mut conf = UserWorkerRuntimeOpts {
+            force_create: self.force_create,
+            idempotency_key: self.idempotency_key,
+            tenant: self.tenant,
+            revision: self.revision,
+            allow_net: self.allow_net,
+            allow_sockets: self.allow_sockets,
+            custom_module_root: self.custom_module_root,
+            allowed_methods: self.allowed_methods,
+            allowed_path_prefixes: self.allowed_path_prefixes,
+            outbound_tls: self.outbound_tls,
+            feature_flags: self.feature_flags,
+            ..Default::default()
+        };
+
+        limits.apply(&mut conf);
+
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                $(
+                    if let Some(value) = self.$field {
+                        conf.$field = value;
+                    }
+                )*
+            };
+        }
+
+        merge!(
+            memory_limit_mb,
+            low_memory_multiplier,
+            memory_leak_trend_samples,
+            recycle_on_memory_leak,
+            heap_initial_mb,
+            heap_max_mb,
+            low_memory_notification_percent,
+            worker_timeout_ms,
+            cpu_time_soft_limit_ms,
+            cpu_time_hard_limit_ms,
+            cpu_time_cap_ms,
+            request_cpu_time_cap_ms,
+            event_loop_lag_warn_ms,
+            event_loop_lag_limit_ms,
+            max_resources,
+            max_websocket_connections,
+            websocket_idle_timeout_ms,
+            fetch_timeout_ms,
+            max_concurrent_fetches,
+            max_emails_per_minute,
+            clock_granularity_ms,
+            log_sample_rate,
+            log_rate_limit,
+            net_access_disabled,
+            allow_remote_modules,
+            allow_shared_array_buffer,
+            allow_wasm_threads,
+            allow_atomics_wait
+        );
+
+        conf
+    }
+}
+
+async fn create_worker(
+    opts: &FunctionRouterOpts,
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Error> {
+    let maybe_idempotency_key = req
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|it| it.to_str().ok())
+        .map(str::to_string);
+
+    let body = hyper_v014::body::to_bytes(req.into_body()).await?;
+    let mut create_req = match serde_json::from_slice::<CreateWorkerRequest>(&body) {
+        Ok(it) => it,
+        Err(err) => {
+            return Ok(emit_json_error(
+                StatusCode::BAD_REQUEST,
+                &format!("invalid worker options: {err}"),
+            ))
+        }
+    };
+
+    if create_req.idempotency_key.is_none() {
+        create_req.idempotency_key = maybe_idempotency_key;
+    }
+
+    let maybe_eszip = match create_req.eszip_url.take() {
+        Some(url) => {
+            let mut buf = vec![];
+
+            tokio::time::timeout(ESZIP_FETCH_TIMEOUT, async {
+                let res = reqwest_v011::get(&url).await?.error_for_status()?;
+
+                write_body_bounded(res, MAX_ESZIP_SIZE, &mut buf).await
+            })
+            .await
+            .map_err(|_| anyhow!("timed out"))
+            .and_then(|it| it)
+            .with_context(|| format!("failed to fetch eszip: {url}"))?;
+
+            Some(EszipPayloadKind::VecKind(buf))
+        }
+
+        None => None,
+    };
+
+    let service_path = PathBuf::from(std::mem::take(&mut create_req.service_path));
+    let env_vars = std::mem::take(&mut create_req.env_vars);
+    let import_map_path = create_req
+        .import_map_path
+        .take()
+        .or_else(|| opts.import_map_path.clone());
+    let no_module_cache = create_req
+        .no_module_cache
+        .take()
+        .unwrap_or(opts.no_module_cache);
+    let locale = WorkerLocale {
+        locale: create_req.locale.take(),
+        timezone: create_req.timezone.take(),
+    };
+    let conf = create_req.into_runtime_opts(&opts.runtime_config().limits);
+
+    let (create_tx, create_rx) = oneshot::channel::<Result<CreateUserWorkerResult, Error>>();
+
+    worker_pool_tx.send(UserWorkerMsgs::Create(
+        WorkerContextInitOpts {
+            service_path,
+            no_module_cache,
+            import_map_path,
+            env_vars,
+            timing: None,
+            conf: WorkerRuntimeOpts::UserWorker(conf),
+            maybe_eszip,
+            maybe_module_code: None,
+            maybe_module_map: None,
+            maybe_entrypoint: None,
+            maybe_decorator: opts.maybe_decorator,
+            static_patterns: vec![],
+            maybe_jsx_import_source_config: None,
+            locale,
+        },
+        create_tx,
+    ))?;
+
+    let CreateUserWorkerResult {
+        key,
+        warnings,
+        decision,
+    } = match create_rx.await? {
+        Ok(it) => it,
+        Err(err) => match err.downcast_ref::<WorkerOptionsChangedError>() {
+            // NOTE: Only the changed options are shown, not the rest of the
+            // chain.
+            Some(it) => return Ok(emit_json_error(StatusCode::CONFLICT, &it.to_string())),
+            None => return Err(err),
+        },
+    };
+    let mut res = Response::new(Body::from(
+        serde_json::json!({
+            "key": key.to_string(),
+            "warnings": warnings,
+            "decision": decision,
+        })
+        .to_string(),
+    ));
+
+    *res.status_mut() = StatusCode::CREATED;
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        http_v02::HeaderValue::from_static("application/json"),
+    );
+
+    Ok(res)
+}
+
+async fn terminate_worker(
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    key: Uuid,
+) -> Result<Response<Body>, Error> {
+    let (tx, rx) = oneshot::channel::<bool>();
+
+    worker_pool_tx.send(UserWorkerMsgs::Terminate(key, tx))?;
+
+    if !rx.await? {
+        return Ok(emit_json_error(StatusCode::NOT_FOUND, "worker not found"));
+    }
+
+    let mut res = Response::new(Body::empty());
+
+    *res.status_mut() = StatusCode::NO_CONTENT;
+
+    Ok(res)
+}
+
+async fn get_request_log(
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    key: Uuid,
+) -> Result<Response<Body>, Error> {
+    let (tx, rx) = oneshot::channel();
+
+    worker_pool_tx.send(UserWorkerMsgs::GetRequestLog(key, tx))?;
+
+    let Some(requests) = rx.await? else {
+        return Ok(emit_json_error(StatusCode::NOT_FOUND, "worker not found"));
+    };
+
+    let mut res = Response::new(Body::from(
+        serde_json::json!({ "requests": requests }).to_string(),
+    ));
+
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        http_v02::HeaderValue::from_static("application/json"),
+    );
+
+    Ok(res)
+}
+
+async fn check_worker_health(
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    key: Uuid,
+    req: Request<Body>,
+) -> Result<Response<Body>, Error> {
+    let body = hyper_v014::body::to_bytes(req.into_body()).await?;
+    let check = if body.is_empty() {
+        HealthCheck::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(it) => it,
+            Err(err) => {
+                return Ok(emit_json_error(
+                    StatusCode::BAD_REQUEST,
+                    &format!("invalid health check: {err}"),
+                ))
+            }
+        }
+    };
+
+    let (tx, rx) = oneshot::channel();
+
+    worker_pool_tx.send(UserWorkerMsgs::HealthCheck(key, check, tx))?;
+
+    let Some(report) = rx.await? else {
+        return Ok(emit_json_error(StatusCode::NOT_FOUND, "worker not found"));
+    };
+
+    let mut res = Response::new(Body::from(serde_json::to_string(&report)?));
+
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        http_v02::HeaderValue::from_static("application/json"),
+    );
+
+    Ok(res)
+}
+
+async fn update_feature_flags(
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Error> {
+    let body = hyper_v014::body::to_bytes(req.into_body()).await?;
+    let UpdateFeatureFlagsRequest {
+        tenant,
+        service_path,
+        flags,
+    } = match serde_json::from_slice(&body) {
+        Ok(it) => it,
+        Err(err) => {
+            return Ok(emit_json_error(
+                StatusCode::BAD_REQUEST,
+                &format!("invalid feature flags update: {err}"),
+            ))
+        }
+    };
+
+    if tenant.is_none() && service_path.is_none() {
+        return Ok(emit_json_error(
+            StatusCode::BAD_REQUEST,
+            "either tenant or service path must be defined",
+        ));
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let target = FeatureFlagsTarget {
+        tenant,
+        service_path,
+    };
+
+    worker_pool_tx.send(UserWorkerMsgs::UpdateFeatureFlags(target, flags, tx))?;
+
+    let mut res = Response::new(Body::from(
+        serde_json::json!({ "updated": rx.await? }).to_string(),
+    ));
+
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        http_v02::HeaderValue::from_static("application/json"),
+    );
+
+    Ok(res)
+}
+
+/// Serves the internal API that lets an external control plane manage the
+/// user workers of the pool directly:
+///
+/// - `POST /_internal/workers` creates a worker and responds with its key.
+/// - `DELETE /_internal/workers/:key` terminates a worker.
+/// - `GET /_internal/workers/:key/requests` lists the last requests handled
+///   by a worker.
+/// - `POST /_internal/workers/:key/health` sends a synthetic request to a
+///   worker and reports how it responded (see [`HealthCheck`]).
+/// - `PATCH /_internal/workers/feature-flags` merges feature flags into the
+///   running workers of a tenant and/or a service.
+///
+/// Every request must satisfy the configured [`InternalApiAuth`].
+pub async fn handle_workers_api(
+    opts: &FunctionRouterOpts,
+    auth: &InternalApiAuth,
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    req: Request<Body>,
+) -> Response<Body> {
+    if let Err((status, msg)) = auth.check(&req) {
+        return emit_json_error(status, msg);
+    }
+
+    let path = req
+        .uri()
+        .path()
+        .strip_prefix(WORKERS_API_PATH)
+        .unwrap_or_default()
+        .to_string();
+
+    let segments = path
+        .split('/')
+        .filter(|it| !it.is_empty())
+        .collect::<Vec<_>>();
+    let parse_key = |key: &str| Uuid::parse_str(key).ok();
+
+    let result = match (req.method().clone(), segments.as_slice()) {
+        (Method::POST, []) => create_worker(opts, worker_pool_tx, req).await,
+        (Method::PATCH, ["feature-flags"]) => update_feature_flags(worker_pool_tx, req).await,
+        (Method::DELETE, [key]) => match parse_key(key) {
+            Some(key) => terminate_worker(worker_pool_tx, key).await,
+            None => Ok(emit_json_error(
+                StatusCode::BAD_REQUEST,
+                "invalid worker key",
+            )),
+        },
+        (Method::GET, [key, "requests"]) => match parse_key(key) {
+            Some(key) => get_request_log(worker_pool_tx, key).await,
+            None => Ok(emit_json_error(
+                StatusCode::BAD_REQUEST,
+                "invalid worker key",
+            )),
+        },
+        (Method::POST, [key, "health"]) => match parse_key(key) {
+            Some(key) => check_worker_health(worker_pool_tx, key, req).await,
+            None => Ok(emit_json_error(
+                StatusCode::BAD_REQUEST,
+                "invalid worker key",
+            )),
+        },
+        (_, [] | [_] | [_, "requests" | "health"]) => Ok(emit_json_error(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method not allowed",
+        )),
+        _ => Ok(emit_json_error(StatusCode::NOT_FOUND, "not found")),
+    };
+
+    match result {
+        Ok(res) => res,
+        Err(err) => {
+            error!("failed to handle workers api request: {err:#}");
+            emit_json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runtime_config::LimitsConfig;
+
+    use super::CreateWorkerRequest;
+
+    #[test]
+    fn test_create_worker_request_into_runtime_opts() {
+        let conf = CreateWorkerRequest {
+            service_path: "./foo".to_string(),
+            memory_limit_mb: Some(64),
+            net_access_disabled: Some(true),
+            allow_atomics_wait: Some(false),
+            tenant: Some("acme".to_string()),
+            ..Default::default()
+        }
+        .into_runtime_opts(&LimitsConfig::default());
+
+        assert_eq!(conf.memory_limit_mb, 64);
+        assert!(conf.net_access_disabled);
+        assert!(!conf.allow_atomics_wait);
+        assert!(!conf.allow_shared_array_buffer);
+        assert_eq!(conf.tenant.as_deref(), Some("acme"));
+        assert_eq!(conf.cpu_time_soft_limit_ms, 50);
+    }
+}
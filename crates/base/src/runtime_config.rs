@@ -0,0 +1,729 @@
+//! The part of the configuration of the runtime that can be reloaded without
+//! restarting it, either on `SIGHUP` or through the internal config API.
+//!
+//! It is read from the JSON file given with `--config`. Settings missing from
+//! the file fall back to the ones given on the command line.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{bail, Context, Error};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use deno_core::serde_json::{self, Value};
+use event_worker::events::{
+    ConfigReloadedEvent, EventMetadata, WorkerEventWithMetadata, WorkerEvents,
+};
+use http_v02::{header, HeaderMap, Method, StatusCode};
+use hyper_v014::{Body, Request, Response};
+use log::{error, info};
+use ring::digest::{digest, SHA256};
+use sb_workers::context::{PoolPolicyUpdate, UserWorkerMsgs, UserWorkerRuntimeOpts};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex, Notify};
+
+use crate::rt_worker::internal_auth::InternalApiAuth;
+use crate::rt_worker::path_normalization::{normalize_path, path_has_prefix, PathNormalization};
+use crate::rt_worker::priority_class::PriorityClass;
+use crate::rt_worker::router::emit_json_error;
+use crate::utils::send_event_if_event_worker_available;
+
+pub static CONFIG_API_PATH: &str = "/_internal/config";
+
+/// Applied to the worker pool as soon as the configuration is reloaded. The
+/// services that are already running keep their parallelism.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PoolConfig {
+    pub max_parallelism: Option<usize>,
+    pub request_wait_timeout_ms: Option<u64>,
+    pub drain_timeout_ms: Option<u64>,
+}
+
+impl PoolConfig {
+    fn resolve(&self, base: PoolPolicyUpdate) -> PoolPolicyUpdate {
+        PoolPolicyUpdate {
+            max_parallelism: self.max_parallelism.unwrap_or(base.max_parallelism),
+            request_wait_timeout_ms: self
+                .request_wait_timeout_ms
+                .unwrap_or(base.request_wait_timeout_ms),
+            drain_timeout_ms: self.drain_timeout_ms.unwrap_or(base.drain_timeout_ms),
+        }
+    }
+}
+
+/// The limits of the user workers created by the function router and the
+/// internal workers API, unless they are set by the request. Workers that are
+/// already running keep their limits.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub memory_limit_mb: Option<u64>,
+    pub worker_timeout_ms: Option<u64>,
+    pub cpu_time_soft_limit_ms: Option<u64>,
+    pub cpu_time_hard_limit_ms: Option<u64>,
+}
+
+impl LimitsConfig {
+    pub fn apply(&self, conf: &mut UserWorkerRuntimeOpts) {
+        macro_rules! apply {
+            ($($field:ident),*) => {
+                $(
+                    if let Some(value) = self.$field {
+                        conf.$field = value;
+                    }
+                )*
+            };
+        }
+
+        apply!(
+            memory_limit_mb,
+            worker_timeout_ms,
+            cpu_time_soft_limit_ms,
+            cpu_time_hard_limit_ms
+        );
+    }
+}
+
+/// A route of the runtime config, given as either the service directory of
+/// the function of its name, or a rule.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Route {
+    Function(PathBuf),
+    Rule(RouteRule),
+}
+
+impl Route {
+    pub fn as_function(&self) -> Option<&Path> {
+        match self {
+            Self::Function(it) => Some(it),
+            Self::Rule(_) => None,
+        }
+    }
+
+    pub fn as_rule(&self) -> Option<&RouteRule> {
+        match self {
+            Self::Function(_) => None,
+            Self::Rule(it) => Some(it),
+        }
+    }
+}
+
+/// A rule of the function router, which maps the requests to a host and/or
+/// under a path prefix onto a service. Rules take precedence over the
+/// function names.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RouteRule {
+    /// Matches the requests to this host (without the port), or to any host
+    /// if unset.
+    pub host: Option<String>,
+    /// Matches the requests whose path is this one or is under it, once
+    /// normalized (see [`path_has_prefix`]).
+    #[serde(default = "default_path_prefix")]
+    pub path_prefix: String,
+    pub service_path: PathBuf,
+    /// Removes the path prefix from the path the worker sees.
+    #[serde(default)]
+    pub strip_prefix: bool,
+    /// Overrides the limits of the runtime config for the workers booted for
+    /// this route.
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// Rejects the requests that don't satisfy it, if set.
+    pub auth: Option<InternalApiAuth>,
+    /// The priority class of the requests of this route, over the one the
+    /// client asks for, if set.
+    pub priority: Option<PriorityClass>,
+}
+
+fn default_path_prefix() -> String {
+    "/".to_string()
+}
+
+impl RouteRule {
+    fn matches(&self, maybe_host: Option<&str>, path: &str) -> bool {
+        let is_host_matched = match (self.host.as_deref(), maybe_host) {
+            (None, _) => true,
+            (Some(expected), Some(host)) => expected.eq_ignore_ascii_case(host),
+            (Some(_), None) => false,
+        };
+
+        is_host_matched && path_has_prefix(path, &self.path_prefix)
+    }
+
+    /// Returns the path the worker sees.
+    ///
+    /// NOTE: The prefix is stripped from the normalized path, as that is what
+    /// the rule was matched against.
+    pub fn rewrite_path<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        if !self.strip_prefix {
+            return Cow::Borrowed(path);
+        }
+
+        let (Ok(normalized), Ok(prefix)) = (
+            normalize_path(path, PathNormalization::Lenient),
+            normalize_path(&self.path_prefix, PathNormalization::Lenient),
+        ) else {
+            return Cow::Borrowed(path);
+        };
+
+        match normalized.strip_prefix(prefix.trim_end_matches('/')) {
+            Some("") => Cow::Borrowed("/"),
+            Some(rest) if rest.starts_with('/') => Cow::Owned(rest.to_string()),
+            _ => Cow::Borrowed(path),
+        }
+    }
+}
+
+/// Where the router finds the session of a request, so that the requests of a
+/// session are served by the same worker of a service, e.g. for the functions
+/// that keep state in memory across requests. Given as `{ "header": "<name>" }`
+/// or `{ "cookie": "<name>" }`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub enum StickySessionConfig {
+    Header(String),
+    Cookie(String),
+}
+
+impl StickySessionConfig {
+    /// Returns the key of the session of the request, if it has one.
+    ///
+    /// The value is hashed, so that the pool does not hold on to the session
+    /// tokens of the clients.
+    pub fn session_key(&self, headers: &HeaderMap) -> Option<String> {
+        let value = match self {
+            Self::Header(name) => headers
+                .get(name.as_str())
+                .map(|it| it.as_bytes())
+                .filter(|it| !it.is_empty())?,
+
+            Self::Cookie(name) => headers
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|it| it.to_str().ok())
+                .flat_map(|it| it.split(';'))
+                .filter_map(|it| it.trim().split_once('='))
+                .find(|(key, value)| key == name && !value.is_empty())
+                .map(|(_, value)| value.as_bytes())?,
+        };
+
+        Some(URL_SAFE_NO_PAD.encode(&digest(&SHA256, value).as_ref()[..16]))
+    }
+}
+
+/// How a tenant shares the user workers with the others when the requests
+/// exceed `--max-concurrent-requests`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TenantConfig {
+    /// A tenant of weight 2 is admitted twice as many requests as one of
+    /// weight 1, which is the weight of the tenants missing here.
+    pub weight: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RuntimeConfig {
+    #[serde(default)]
+    pub pool: PoolConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// Maps function names onto service directories, ahead of the functions
+    /// directory of the function router, and routes by host and path prefix
+    /// with the rules, ahead of the function names.
+    #[serde(default)]
+    pub routes: BTreeMap<String, Route>,
+    /// Makes the requests of a session stick to a worker of the service,
+    /// if set.
+    pub sticky_session: Option<StickySessionConfig>,
+    #[serde(default)]
+    pub tenants: BTreeMap<String, TenantConfig>,
+}
+
+impl RuntimeConfig {
+    pub async fn load(path: &Path) -> Result<Self, Error> {
+        let data = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("can't read runtime config: {}", path.display()))?;
+
+        Self::from_slice(&data)
+            .with_context(|| format!("invalid runtime config: {}", path.display()))
+    }
+
+    fn from_slice(data: &[u8]) -> Result<Self, Error> {
+        let config = serde_json::from_slice::<Self>(data)?;
+
+        if config.pool.max_parallelism == Some(0) {
+            bail!("the maximum parallelism of the pool must be at least 1");
+        }
+
+        if let Some((tenant, _)) = config.tenants.iter().find(|(_, it)| it.weight == 0) {
+            bail!("the weight of tenant {tenant} must be at least 1");
+        }
+
+        for (name, rule) in config.rules() {
+            if !rule.path_prefix.starts_with('/') {
+                bail!("the path prefix of route {name} must start with a slash");
+            }
+
+            if rule.auth.as_ref().is_some_and(|it| !it.is_enabled()) {
+                bail!("the auth of route {name} must configure at least one method");
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Lists the settings that differ in the other configuration, e.g.
+    /// `pool.maxParallelism: 4 -> 8`.
+    pub fn diff(&self, other: &Self) -> Vec<String> {
+        fn flatten(prefix: String, value: Value, entries: &mut BTreeMap<String, Value>) {
+            match value {
+                Value::Null => {}
+                Value::Object(map) => {
+                    for (key, value) in map {
+                        let key = if prefix.is_empty() {
+                            key
+                        } else {
+                            format!("{prefix}.{key}")
+                        };
+
+                        flatten(key, value, entries);
+                    }
+                }
+
+                value => {
+                    entries.insert(prefix, value);
+                }
+            }
+        }
+
+        let entries = |config: &Self| {
+            let mut entries = BTreeMap::new();
+
+            flatten(
+                String::new(),
+                serde_json::to_value(config).unwrap_or_default(),
+                &mut entries,
+            );
+
+            entries
+        };
+
+        let show =
+            |value: Option<&Value>| value.map_or_else(|| "unset".to_string(), Value::to_string);
+        let (old, new) = (entries(self), entries(other));
+
+        old.keys()
+            .chain(new.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|key| old.get(*key) != new.get(*key))
+            .map(|key| format!("{key}: {} -> {}", show(old.get(key)), show(new.get(key))))
+            .collect()
+    }
+
+    /// Returns the routes that are rules, by name.
+    pub fn rules(&self) -> impl DoubleEndedIterator<Item = (&String, &RouteRule)> {
+        self.routes
+            .iter()
+            .filter_map(|(name, it)| Some((name, it.as_rule()?)))
+    }
+
+    /// Returns the most specific rule that matches the request: one for its
+    /// host over one for any host, then the one with the longest path prefix,
+    /// then the first one by name.
+    pub fn match_rule(&self, maybe_host: Option<&str>, path: &str) -> Option<&RouteRule> {
+        self.rules()
+            .rev()
+            .map(|(_, it)| it)
+            .filter(|it| it.matches(maybe_host, path))
+            .max_by_key(|it| {
+                (
+                    it.host.is_some(),
+                    it.path_prefix.trim_end_matches('/').len(),
+                )
+            })
+    }
+
+    pub fn tenant_weights(&self) -> HashMap<String, u32> {
+        self.tenants
+            .iter()
+            .map(|(tenant, it)| (tenant.clone(), it.weight))
+            .collect()
+    }
+}
+
+/// The current runtime configuration, shared with whoever applies it to the
+/// new workers.
+#[derive(Debug, Clone, Default)]
+pub struct SharedRuntimeConfig(Arc<RwLock<Arc<RuntimeConfig>>>);
+
+impl SharedRuntimeConfig {
+    pub fn get(&self) -> Arc<RuntimeConfig> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn set(&self, config: RuntimeConfig) {
+        *self.0.write().unwrap() = Arc::new(config);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigReloader {
+    path: PathBuf,
+    config: SharedRuntimeConfig,
+    /// The settings of the pool given on the command line.
+    base_pool: PoolPolicyUpdate,
+    worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    cert_reload: Arc<Notify>,
+    events_msg_tx: Option<mpsc::WeakUnboundedSender<WorkerEventWithMetadata>>,
+    // NOTE: Reloads are serialized so that the changes they report add up.
+    lock: Arc<Mutex<()>>,
+}
+
+impl ConfigReloader {
+    pub(crate) async fn new(
+        path: PathBuf,
+        base_pool: PoolPolicyUpdate,
+        worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+        cert_reload: Arc<Notify>,
+        events_msg_tx: Option<mpsc::WeakUnboundedSender<WorkerEventWithMetadata>>,
+    ) -> Result<Self, Error> {
+        let config = RuntimeConfig::load(&path).await?;
+
+        if config.pool != PoolConfig::default() {
+            worker_pool_tx.send(UserWorkerMsgs::UpdatePolicy(config.pool.resolve(base_pool)))?;
+        }
+
+        if !config.tenants.is_empty() {
+            worker_pool_tx.send(UserWorkerMsgs::UpdateTenantWeights(config.tenant_weights()))?;
+        }
+
+        let shared = SharedRuntimeConfig::default();
+
+        shared.set(config);
+
+        Ok(Self {
+            path,
+            config: shared,
+            base_pool,
+            worker_pool_tx,
+            cert_reload,
+            events_msg_tx,
+            lock: Arc::default(),
+        })
+    }
+
+    pub fn config(&self) -> &SharedRuntimeConfig {
+        &self.config
+    }
+
+    /// Reads the configuration file again and applies it, then returns the
+    /// settings that changed. The TLS certificate is reloaded as well if its
+    /// files changed.
+    ///
+    /// The current configuration is kept if the file is invalid.
+    pub async fn reload(&self) -> Result<Vec<String>, Error> {
+        let _guard = self.lock.lock().await;
+        let config = match RuntimeConfig::load(&self.path).await {
+            Ok(it) => it,
+            Err(err) => {
+                error!("failed to reload the runtime configuration: {err:#}");
+                return Err(err);
+            }
+        };
+
+        let old = self.config.get();
+        let changes = old.diff(&config);
+
+        if old.pool != config.pool {
+            let update = config.pool.resolve(self.base_pool);
+
+            if self
+                .worker_pool_tx
+                .send(UserWorkerMsgs::UpdatePolicy(update))
+                .is_err()
+            {
+                error!("failed to update the worker pool policy: worker pool is gone");
+            }
+        }
+
+        if old.tenants != config.tenants
+            && self
+                .worker_pool_tx
+                .send(UserWorkerMsgs::UpdateTenantWeights(config.tenant_weights()))
+                .is_err()
+        {
+            error!("failed to update the tenant weights: worker pool is gone");
+        }
+
+        self.config.set(config);
+
+        // NOTE: The files of the certificate are not part of the
+        // configuration, so they are checked on every reload.
+        self.cert_reload.notify_one();
+
+        if changes.is_empty() {
+            info!("runtime configuration reloaded without changes");
+        } else {
+            info!("runtime configuration reloaded: {}", changes.join(", "));
+        }
+
+        if let Some(events_msg_tx) = self
+            .events_msg_tx
+            .as_ref()
+            .and_then(mpsc::WeakUnboundedSender::upgrade)
+        {
+            send_event_if_event_worker_available(
+                Some(&events_msg_tx),
+                WorkerEvents::ConfigReloaded(ConfigReloadedEvent {
+                    changes: changes.clone(),
+                }),
+                EventMetadata::default(),
+            );
+        }
+
+        Ok(changes)
+    }
+}
+
+/// Serves `POST /_internal/config/reload`, which reloads the runtime
+/// configuration and responds with the settings that changed.
+///
+/// Every request must satisfy the configured [`InternalApiAuth`].
+pub(crate) async fn handle_config_api(
+    maybe_reloader: Option<&ConfigReloader>,
+    auth: &InternalApiAuth,
+    req: Request<Body>,
+) -> Response<Body> {
+    if let Err((status, msg)) = auth.check(&req) {
+        return emit_json_error(status, msg);
+    }
+
+    let path = req
+        .uri()
+        .path()
+        .strip_prefix(CONFIG_API_PATH)
+        .unwrap_or_default();
+
+    if path != "/reload" {
+        return emit_json_error(StatusCode::NOT_FOUND, "not found");
+    }
+
+    if req.method() != Method::POST {
+        return emit_json_error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+
+    let Some(reloader) = maybe_reloader else {
+        return emit_json_error(StatusCode::NOT_FOUND, "no runtime config file is in use");
+    };
+
+    match reloader.reload().await {
+        Ok(changes) => {
+            let mut res = Response::new(Body::from(
+                serde_json::json!({ "changes": changes }).to_string(),
+            ));
+
+            res.headers_mut().insert(
+                header::CONTENT_TYPE,
+                http_v02::HeaderValue::from_static("application/json"),
+            );
+
+            res
+        }
+
+        // NOTE: The reason was logged by the reloader; it may point into the
+        // file system of the host.
+        Err(_) => emit_json_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "failed to reload the runtime configuration",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sb_workers::context::{PoolPolicyUpdate, UserWorkerRuntimeOpts};
+
+    use std::path::Path;
+
+    use super::{PriorityClass, RuntimeConfig, StickySessionConfig};
+
+    #[test]
+    fn test_runtime_config() {
+        let old = RuntimeConfig::from_slice(
+            br#"{
+                "pool": { "maxParallelism": 4 },
+                "routes": { "hello": "./examples/hello-world" }
+            }"#,
+        )
+        .unwrap();
+
+        let new = RuntimeConfig::from_slice(
+            br#"{
+                "pool": { "maxParallelism": 8, "drainTimeoutMs": 1000 },
+                "limits": { "memoryLimitMb": 256 }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                "limits.memoryLimitMb: unset -> 256",
+                "pool.drainTimeoutMs: unset -> 1000",
+                "pool.maxParallelism: 4 -> 8",
+                "routes.hello: \"./examples/hello-world\" -> unset",
+            ]
+        );
+        assert!(new.diff(&new).is_empty());
+
+        let base = PoolPolicyUpdate {
+            max_parallelism: 2,
+            request_wait_timeout_ms: 10000,
+            drain_timeout_ms: 5000,
+        };
+
+        assert_eq!(
+            new.pool.resolve(base),
+            PoolPolicyUpdate {
+                max_parallelism: 8,
+                request_wait_timeout_ms: 10000,
+                drain_timeout_ms: 1000,
+            }
+        );
+
+        let mut conf = UserWorkerRuntimeOpts::default();
+
+        new.limits.apply(&mut conf);
+
+        assert_eq!(conf.memory_limit_mb, 256);
+        assert_eq!(
+            conf.worker_timeout_ms,
+            UserWorkerRuntimeOpts::default().worker_timeout_ms
+        );
+
+        assert!(RuntimeConfig::from_slice(br#"{ "pool": { "maxParallelism": 0 } }"#).is_err());
+        assert!(RuntimeConfig::from_slice(br#"{ "pool": { "maxWorkers": 1 } }"#).is_err());
+        assert!(RuntimeConfig::from_slice(br#"{ "tenants": { "a": { "weight": 0 } } }"#).is_err());
+    }
+
+    #[test]
+    fn test_runtime_config_rules() {
+        let config = RuntimeConfig::from_slice(
+            br#"{
+                "routes": {
+                    "a-fallback": { "servicePath": "./fallback" },
+                    "api": { "pathPrefix": "/api/", "servicePath": "./api", "stripPrefix": true },
+                    "example": {
+                        "host": "example.com",
+                        "pathPrefix": "/api",
+                        "servicePath": "./example"
+                    },
+                    "admin": {
+                        "pathPrefix": "/admin",
+                        "servicePath": "./admin",
+                        "auth": { "bearerToken": "secret" }
+                    },
+                    "import": {
+                        "pathPrefix": "/import",
+                        "servicePath": "./import",
+                        "priority": "batch"
+                    },
+                    "hello": "./examples/hello-world"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let service_path = |host: Option<&str>, path: &str| {
+            config
+                .match_rule(host, path)
+                .map(|it| it.service_path.to_str().unwrap().to_string())
+        };
+
+        assert_eq!(service_path(None, "/hello").as_deref(), Some("./fallback"));
+        assert_eq!(service_path(None, "/api").as_deref(), Some("./api"));
+        assert_eq!(service_path(None, "/api/users").as_deref(), Some("./api"));
+        assert_eq!(service_path(None, "/apis").as_deref(), Some("./fallback"));
+        assert_eq!(service_path(None, "//api/users").as_deref(), Some("./api"));
+        assert_eq!(service_path(None, "/%61pi/users").as_deref(), Some("./api"));
+        assert_eq!(
+            service_path(None, "/api/../admin").as_deref(),
+            Some("./admin")
+        );
+        assert_eq!(
+            config.routes["hello"].as_function(),
+            Some(Path::new("./examples/hello-world"))
+        );
+        assert_eq!(
+            service_path(Some("example.com"), "/api/users").as_deref(),
+            Some("./example")
+        );
+
+        let rule = config.match_rule(None, "/api/users").unwrap();
+
+        assert_eq!(rule.rewrite_path("/api/users"), "/users");
+        assert_eq!(rule.rewrite_path("/api"), "/");
+        assert_eq!(rule.rewrite_path("//api/./users"), "/users");
+        assert_eq!(rule.priority, None);
+        assert_eq!(
+            config.match_rule(None, "/import").unwrap().priority,
+            Some(PriorityClass::Batch)
+        );
+
+        // NOTE: The token must not leak through the changes of a reload.
+        assert!(RuntimeConfig::default()
+            .diff(&config)
+            .iter()
+            .all(|it| !it.contains("secret")));
+
+        assert!(RuntimeConfig::from_slice(
+            br#"{ "routes": { "api": { "pathPrefix": "api", "servicePath": "./api" } } }"#
+        )
+        .is_err());
+        assert!(RuntimeConfig::from_slice(
+            br#"{ "routes": { "api": { "servicePath": "./api", "auth": {} } } }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_sticky_session_key() {
+        use http_v02::{header, HeaderMap, HeaderValue};
+
+        let config = RuntimeConfig::from_slice(br#"{ "stickySession": { "cookie": "sid" } }"#)
+            .unwrap()
+            .sticky_session
+            .unwrap();
+
+        let headers = |cookie: &'static str| {
+            let mut headers = HeaderMap::new();
+
+            headers.insert(header::COOKIE, HeaderValue::from_static(cookie));
+            headers
+        };
+
+        let key = config.session_key(&headers("theme=dark; sid=abc")).unwrap();
+
+        assert_ne!(key, "abc");
+        assert_eq!(config.session_key(&headers("sid=abc")), Some(key.clone()));
+        assert_ne!(config.session_key(&headers("sid=abd")), Some(key));
+        assert_eq!(config.session_key(&headers("xsid=abc")), None);
+        assert_eq!(config.session_key(&headers("sid=")), None);
+        assert_eq!(config.session_key(&HeaderMap::new()), None);
+
+        let config = StickySessionConfig::Header("x-session-id".to_string());
+        let mut headers = HeaderMap::new();
+
+        headers.insert("x-session-id", HeaderValue::from_static("abc"));
+
+        assert!(config.session_key(&headers).is_some());
+    }
+}
@@ -1,43 +1,77 @@
+use crate::acceptor::{AcceptedStream, Acceptor};
+use crate::acme::{
+    get_http_01_response, load_cached_cert, AcmeChallengeType, AcmeManager, AcmeOptions,
+    ACME_TLS_ALPN_PROTOCOL,
+};
 use crate::inspector_server::Inspector;
+use crate::process_title::run_process_title;
+use crate::readiness::{PoolReport, ReadinessReport, ReadyTarget};
+use crate::response_buffer::{buffer_response, ResponseBufferLimits, ResponseOverflow, SpillQuota};
+use crate::rt_worker::client_ip::{ClientIpPolicy, CLIENT_IP_HEADER};
+use crate::rt_worker::deadline::DEADLINE_HEADER;
+use crate::rt_worker::internal_auth::{ConnInfo, InternalApiAuth};
+use crate::rt_worker::path_normalization::{normalize_request, PathNormalization};
+use crate::rt_worker::request_meta::{set_request_meta, RequestMeta, TlsMeta};
+use crate::rt_worker::router::{create_function_router, FunctionRouterOpts};
+use crate::rt_worker::startup_gate::{gate_main_worker, ServiceStartupGate, StartupGateOpts};
+use crate::rt_worker::task_dispatcher::{spawn_task_dispatcher, DEFAULT_VISIBILITY_TIMEOUT};
 use crate::rt_worker::worker_ctx::{
     create_events_worker, create_main_worker, create_user_worker_pool, TerminationToken,
 };
-use crate::rt_worker::worker_pool::WorkerPoolPolicy;
+use crate::rt_worker::worker_pool::{DuplicateCreatePolicy, WorkerPoolPolicy};
+use crate::runtime_config::ConfigReloader;
+use crate::shutdown_report::ShutdownReport;
+use crate::slow_client::{BodyReadLimits, ConnLimiter, ConnPermit, SlowBodyGuard};
+use crate::sniff::{reject_tls, sniff, Protocol, TlsPortListener};
+use crate::tls_cert::{
+    create_certified_key, get_not_after, parse_cert_chain, parse_key, CertFiles, CertMonitor,
+    CertMonitorOptions, CertResolver, CertTask,
+};
 use crate::InspectorOption;
-use anyhow::{anyhow, bail, Context, Error};
+use anyhow::{bail, Context, Error};
 use deno_config::JsxImportSourceConfig;
+use event_worker::routing::{route_events, EventRoute};
 use futures_util::future::{poll_fn, BoxFuture};
-use futures_util::{FutureExt, Stream};
+use futures_util::stream::{self, BoxStream};
+use futures_util::{FutureExt, Stream, StreamExt};
+use http_utils::utils::emit_problem_details;
+use http_v02::{header, HeaderValue, StatusCode};
+use hyper_v014::body::HttpBody;
 use hyper_v014::{server::conn::Http, service::Service, Body, Request, Response};
 use log::{debug, error, info, trace, warn};
-use rustls_pemfile::read_one_from_slice;
-use rustls_pemfile::Item;
+use sb_core::cert::{get_root_cert_store, init_default_root_cert_store, CaData};
+use sb_core::dns_cache::{init_dns_cache, DnsCacheOptions};
+use sb_core::outbound_pool::{init_outbound_pool, OutboundPoolOptions};
 use sb_core::SharedMetricSource;
 use sb_graph::DecoratorType;
-use sb_workers::context::{MainWorkerRuntimeOpts, WorkerRequestMsg};
+use sb_workers::context::{MainWorkerLimits, MainWorkerRuntimeOpts, WorkerRequestMsg};
+use sb_workers::errors::{emit_worker_error, WorkerError};
+use std::collections::HashMap;
 use std::future::{pending, Future};
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::str;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::task::Poll;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use tls_listener::TlsListener;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 use tokio::pin;
 use tokio::sync::mpsc::{Sender, UnboundedSender};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, Notify};
 use tokio::time::{sleep, timeout};
 use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ProtocolVersion, RootCertStore, ServerConfig};
 use tokio_rustls::TlsAcceptor;
 use tokio_util::sync::CancellationToken;
 use url::Url;
+use uuid::Uuid;
 
 mod signal {
     pub use tokio::signal::ctrl_c;
@@ -48,6 +82,11 @@ mod signal {
 
 pub enum ServerEvent {
     ConnectionError(hyper_v014::Error),
+    TlsCertificateExpiring {
+        not_after: SystemTime,
+        days_left: u64,
+    },
+    TlsCertificateReloaded,
     #[cfg(debug_assertions)]
     Draining,
 }
@@ -88,16 +127,18 @@ impl<S: Stream + Unpin> Stream for CancelOnDrop<S> {
 #[derive(Clone)]
 struct TerminationTokens {
     input: Option<TerminationToken>,
-    event: Option<TerminationToken>,
+    events: Vec<TerminationToken>,
     pool: TerminationToken,
     main: TerminationToken,
 }
 
 impl TerminationTokens {
-    fn new(maybe_input: Option<TerminationToken>, with_event: bool) -> Self {
+    fn new(maybe_input: Option<TerminationToken>, event_workers: usize) -> Self {
         Self {
             input: maybe_input,
-            event: with_event.then(TerminationToken::new),
+            events: (0..event_workers)
+                .map(|_| TerminationToken::new())
+                .collect(),
             pool: TerminationToken::new(),
             main: TerminationToken::new(),
         }
@@ -107,9 +148,8 @@ impl TerminationTokens {
         self.pool.cancel_and_wait().await;
         self.main.cancel_and_wait().await;
 
-        if let Some(token) = self.event.as_ref() {
-            token.cancel_and_wait().await;
-        }
+        futures_util::future::join_all(self.events.iter().map(TerminationToken::cancel_and_wait))
+            .await;
 
         if let Some(token) = self.input.as_ref() {
             assert!(token.inbound.is_cancelled());
@@ -121,9 +161,75 @@ impl TerminationTokens {
     }
 }
 
+/// hyper rejects the requests whose head does not fit in its read buffer by
+/// itself, which is this large unless told otherwise.
+static DEFAULT_MAX_BUF_SIZE: usize = 8192 + 4096 * 100;
+
+/// Limits on the head of the requests accepted by a listener, and how it is
+/// normalized once within them.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RequestHeadLimits {
+    /// Maximum size in bytes of the names and values of the headers together.
+    pub max_header_size: Option<usize>,
+    /// Maximum length in bytes of the request target.
+    pub max_uri_length: Option<usize>,
+    pub path_normalization: PathNormalization,
+}
+
+impl RequestHeadLimits {
+    /// Returns the size of the read buffer of a connection, so that hyper
+    /// leaves the requests within the limits to be checked by the service.
+    fn max_buf_size(&self) -> usize {
+        // NOTE: The buffer also holds the request line and the separators of
+        // the headers, hence the slack.
+        let needed = self.max_header_size.unwrap_or(0) + self.max_uri_length.unwrap_or(0) + 8192;
+
+        needed.max(DEFAULT_MAX_BUF_SIZE)
+    }
+
+    /// Returns the status a request is rejected with, along with the reason,
+    /// if its head exceeds the limits.
+    fn check<B>(&self, req: &Request<B>) -> Option<(StatusCode, String)> {
+        if let Some(max) = self.max_uri_length {
+            let len = req.uri().to_string().len();
+
+            if len > max {
+                return Some((
+                    StatusCode::URI_TOO_LONG,
+                    format!("request target is {len} bytes long, at most {max} are allowed"),
+                ));
+            }
+        }
+
+        if let Some(max) = self.max_header_size {
+            let size = req
+                .headers()
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>();
+
+            if size > max {
+                return Some((
+                    StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                    format!("request headers are {size} bytes large, at most {max} are allowed"),
+                ));
+            }
+        }
+
+        None
+    }
+}
+
 struct WorkerService {
     metric_src: SharedMetricSource,
     worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    conn_info: ConnInfo,
+    client_ip_policy: Arc<ClientIpPolicy>,
+    head_limits: RequestHeadLimits,
+    body_limits: BodyReadLimits,
+    response_limits: Option<ResponseBufferLimits>,
+    /// Advertises the HTTP/3 listener in the responses, if set.
+    alt_svc: Option<HeaderValue>,
     cancel: CancellationToken,
 }
 
@@ -131,12 +237,24 @@ impl WorkerService {
     fn new(
         metric_src: SharedMetricSource,
         worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+        conn_info: ConnInfo,
+        client_ip_policy: Arc<ClientIpPolicy>,
+        head_limits: RequestHeadLimits,
+        body_limits: BodyReadLimits,
+        response_limits: Option<ResponseBufferLimits>,
+        alt_svc: Option<HeaderValue>,
     ) -> (Self, CancellationToken) {
         let cancel = CancellationToken::new();
         (
             Self {
                 metric_src,
                 worker_req_tx,
+                conn_info,
+                client_ip_policy,
+                head_limits,
+                body_limits,
+                response_limits,
+                alt_svc,
                 cancel: cancel.clone(),
             },
             cancel,
@@ -153,11 +271,90 @@ impl Service<Request<Body>> for WorkerService {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        if let Some((status, detail)) = self.head_limits.check(&req) {
+            if status == StatusCode::URI_TOO_LONG {
+                self.metric_src.incl_rejected_long_uris();
+            } else {
+                self.metric_src.incl_rejected_large_headers();
+            }
+
+            debug!("request rejected: {}", detail);
+            return Box::pin(async move { Ok::<_, Error>(emit_problem_details(status, &detail)) });
+        }
+
+        if let Err(detail) = normalize_request(&mut req, self.head_limits.path_normalization) {
+            debug!("request rejected: {}", detail);
+            return Box::pin(async move {
+                Ok::<_, Error>(emit_problem_details(StatusCode::BAD_REQUEST, &detail))
+            });
+        }
+
+        if let Some(key_authorization) = get_http_01_response(req.uri().path()) {
+            return Box::pin(async move {
+                Ok::<_, Error>(Response::new(Body::from(key_authorization)))
+            });
+        }
+
+        let client_ip = self
+            .client_ip_policy
+            .resolve(self.conn_info.remote_addr, req.headers());
+
+        if !self.client_ip_policy.is_allowed(client_ip) {
+            return Box::pin(async move {
+                Ok::<_, Error>(
+                    Response::builder()
+                        .status(http_v02::StatusCode::FORBIDDEN)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+            });
+        }
+
+        // NOTE: The headers are always overwritten so that clients can't
+        // spoof them. A deadline is only ever set by the runtime itself.
+        req.headers_mut().remove(CLIENT_IP_HEADER);
+        req.headers_mut().remove(DEADLINE_HEADER);
+
+        if let Some(value) = client_ip.and_then(|it| HeaderValue::from_str(&it.to_string()).ok()) {
+            req.headers_mut().insert(CLIENT_IP_HEADER, value);
+        }
+
+        let meta = RequestMeta {
+            request_id: Uuid::new_v4().to_string(),
+            client_ip,
+            tls: self.conn_info.tls_version.map(|version| TlsMeta {
+                version: version.to_string(),
+                verified_client_cert: self.conn_info.has_verified_client_cert,
+            }),
+            tenant: None,
+            deadline_ms: None,
+            task: None,
+        };
+
+        set_request_meta(req.headers_mut(), &meta);
+
+        req.extensions_mut().insert(self.conn_info);
+
+        // NOTE: A client that sends its body too slowly fails the request, so
+        // that it does not hold on to a worker.
+        if !self.body_limits.is_unlimited() && !req.body().is_end_stream() {
+            let body_limits = self.body_limits;
+            let metric_src = self.metric_src.clone();
+
+            req = req.map(|body| {
+                Body::wrap_stream(SlowBodyGuard::new(body, body_limits, move || {
+                    metric_src.incl_slow_request_bodies();
+                }))
+            });
+        }
+
         // create a response in a future.
         let cancel = self.cancel.child_token();
         let metric_src = self.metric_src.clone();
         let worker_req_tx = self.worker_req_tx.clone();
+        let response_limits = self.response_limits.clone();
+        let alt_svc = self.alt_svc.clone();
         let fut = async move {
             let (res_tx, res_rx) = oneshot::channel::<Result<Response<Body>, hyper_v014::Error>>();
 
@@ -168,7 +365,10 @@ impl Service<Request<Body>> for WorkerService {
                 conn_token: Some(cancel.clone()),
             };
 
-            worker_req_tx.send(msg)?;
+            if worker_req_tx.send(msg).is_err() {
+                return Ok(emit_worker_error(&WorkerError::WorkerGone.into()));
+            }
+
             metric_src.incl_received_requests();
 
             tokio::spawn({
@@ -187,17 +387,26 @@ impl Service<Request<Body>> for WorkerService {
                 }
             });
 
+            // NOTE: The response sender is only dropped without a response if
+            // the main worker is gone.
             let res = match res_rx.await {
                 Ok(res) => res,
-                Err(err) => {
+                Err(_) => {
                     metric_src.incl_handled_requests();
-                    return Err(err.into());
+                    return Ok(emit_worker_error(&WorkerError::WorkerGone.into()));
                 }
             };
 
-            let res = match res {
+            let mut res = match res {
                 Ok(res) => {
-                    let (parts, body) = res.into_parts();
+                    let (parts, mut body) = res.into_parts();
+
+                    // NOTE: The response is read ahead of the client, so that
+                    // a slow client does not hold on to the worker.
+                    if let Some(limits) = response_limits.filter(|_| !body.is_end_stream()) {
+                        body = Body::wrap_stream(buffer_response(body, limits, metric_src));
+                    }
+
                     Response::from_parts(
                         parts,
                         Body::wrap_stream(CancelOnDrop {
@@ -214,17 +423,24 @@ impl Service<Request<Body>> for WorkerService {
                         e
                     );
 
-                    // FIXME: add an error body
-                    Response::builder()
-                        .status(http_v02::StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::wrap_stream(CancelOnDrop {
-                            inner: Body::empty(),
+                    let (parts, body) =
+                        emit_worker_error(&Error::from(e).context(WorkerError::WorkerGone))
+                            .into_parts();
+
+                    Response::from_parts(
+                        parts,
+                        Body::wrap_stream(CancelOnDrop {
+                            inner: body,
                             cancel: Some(cancel),
-                        }))
-                        .unwrap()
+                        }),
+                    )
                 }
             };
 
+            if let Some(alt_svc) = alt_svc {
+                res.headers_mut().entry(header::ALT_SVC).or_insert(alt_svc);
+            }
+
             Ok(res)
         };
 
@@ -246,77 +462,360 @@ pub struct ServerFlags {
     pub graceful_exit_deadline_sec: u64,
     pub graceful_exit_keepalive_deadline_ms: Option<u64>,
     pub event_worker_exit_deadline_sec: u64,
+    pub events_backlog_limit: Option<usize>,
+    pub lifecycle_history_size: Option<usize>,
+    /// Routes the requests with the routes of the runtime config instead of
+    /// a main worker, even without a functions directory.
+    pub router: bool,
     pub request_wait_timeout_ms: Option<u64>,
     pub request_idle_timeout_ms: Option<u64>,
     pub request_read_timeout_ms: Option<u64>,
+    pub request_body_idle_timeout_ms: Option<u64>,
+    /// The lowest rate at which the bodies of the requests must be received,
+    /// in bytes per second.
+    pub request_body_min_rate: Option<u64>,
+    pub max_connections_per_ip: Option<usize>,
+    /// Listens before the main worker has booted, holding the requests that
+    /// arrive in the meantime. With the router, the requests of a service are
+    /// held while its worker boots.
+    pub hold_requests_until_ready: bool,
+    pub startup_queue_size: Option<usize>,
+    pub startup_queue_timeout_ms: Option<u64>,
+    /// How long a task of the task queue is hidden from the other deliveries
+    /// while it is being delivered.
+    pub task_queue_visibility_timeout_ms: Option<u64>,
+    /// Reads this many bytes of a response ahead of its client at most.
+    pub response_buffer_size: Option<usize>,
+    pub response_buffer_overflow: ResponseOverflow,
+    /// How many bytes the responses may spill to disk at once.
+    pub response_spill_quota: Option<u64>,
+    pub request_coalescing: bool,
+    /// What the pool does when a worker is created for a service whose active
+    /// workers were created with other options.
+    pub duplicate_create: DuplicateCreatePolicy,
+    pub max_concurrent_boots: Option<usize>,
+    pub max_concurrent_requests: Option<usize>,
+    /// How many batch requests the user workers handle at once.
+    pub batch_max_concurrent_requests: Option<usize>,
+    pub batch_request_wait_timeout_ms: Option<u64>,
+    /// The CPU time in milliseconds the batch requests may use per second.
+    pub batch_cpu_budget_ms: Option<u64>,
+    pub share_code_cache: bool,
+    /// Adds the durations of the boot, wait and execution of the requests to
+    /// the `Server-Timing` header of the responses of user workers.
+    pub server_timing: bool,
+    /// Sheds the requests handed to a user worker that has used this share of
+    /// its memory limit, in percent.
+    pub shed_memory_threshold_pct: Option<u8>,
+    /// Sheds the requests handed to a user worker that has used this share of
+    /// its CPU time soft limit, in percent.
+    pub shed_cpu_time_threshold_pct: Option<u8>,
+    /// Sheds the requests to a service once this many are waiting for one of
+    /// its workers.
+    pub shed_queue_depth: Option<usize>,
+    pub shed_retry_after_sec: Option<u64>,
+    /// Refuses to boot a user worker if the host would have less memory than
+    /// this available in MiB once the worker has taken its memory limit.
+    pub boot_min_free_memory_mb: Option<u64>,
+    /// Refuses to boot a user worker while the 1-minute load average per
+    /// core of the host is past this.
+    pub boot_max_load_avg: Option<f64>,
+    /// Refuses to boot a user worker that would take the sum of the memory
+    /// limits of the user workers past this in MiB.
+    pub boot_memory_budget_mb: Option<u64>,
+    /// Lets user workers ask the runtime to simulate failures with
+    /// `EdgeRuntime.test.failNext`. Meant for local testing only.
+    pub failure_injection: bool,
+    /// Coarsens `Date` and `performance.now()` in the user workers to steps
+    /// of at least this many milliseconds.
+    pub clock_granularity_ms: Option<u64>,
+    /// Serves the gRPC control plane on this address. Requires the `grpc`
+    /// feature.
+    pub grpc_control_plane_addr: Option<SocketAddr>,
+    pub service_stats_interval_ms: Option<u64>,
+    pub runtime_stats_interval_ms: Option<u64>,
+    /// Interval at which the concurrency of every service is sampled into
+    /// the events for billing.
+    pub concurrency_sample_interval_ms: Option<u64>,
+    pub worker_drain_timeout_ms: Option<u64>,
+    pub request_log_size: Option<usize>,
+    /// How many of the last requests dispatched by the pool are traced for
+    /// `/_internal/traces`. `0` disables the tracing.
+    pub trace_buffer_size: Option<usize>,
+    pub dns_cache_size: Option<usize>,
+    pub dns_cache_max_ttl_sec: Option<u64>,
+    pub dns_cache_negative_ttl_sec: Option<u64>,
+    /// Shares the outbound connections between the user workers of the same
+    /// tenant if set.
+    pub outbound_pool: bool,
+    pub outbound_pool_max_idle_per_host: Option<usize>,
+    pub outbound_pool_idle_timeout_sec: Option<u64>,
+    pub outbound_pool_http1_only: bool,
+    pub main_worker_memory_limit_mb: Option<u64>,
+    pub main_worker_unresponsive_timeout_ms: Option<u64>,
+    pub tls_cert_check_interval_sec: Option<u64>,
+    pub tls_cert_expiry_warn_days: Option<u64>,
+    pub max_header_size: Option<usize>,
+    pub max_uri_length: Option<usize>,
+    pub tls_max_header_size: Option<usize>,
+    pub tls_max_uri_length: Option<usize>,
+    pub path_normalization: PathNormalization,
+    pub protocol_sniffing: bool,
+    /// Number of sockets accepting the connections of the HTTP port, bound to
+    /// it with `SO_REUSEPORT`. `0` means one per core.
+    pub acceptors: Option<usize>,
+    /// Keeps the title of the process up to date with the stats of the pool.
+    pub process_title: bool,
+    /// Serves HTTP/3 on the UDP port of the same number as the TLS listener,
+    /// and advertises it in the responses of the latter. Requires the `http3`
+    /// feature.
+    pub http3: bool,
+    /// Accepts and drives the connections of the HTTP port through io_uring,
+    /// on a thread of its own. Requires the `io-uring` feature, on Linux.
+    pub io_uring: bool,
 }
 
 #[derive(Debug)]
-pub struct Tls {
-    port: u16,
-    key: PrivateKeyDer<'static>,
-    cert_chain: Vec<CertificateDer<'static>>,
+enum TlsCert {
+    Static {
+        key: PrivateKeyDer<'static>,
+        cert_chain: Vec<CertificateDer<'static>>,
+        ocsp: Option<Vec<u8>>,
+        files: Option<CertFiles>,
+    },
+    Acme(AcmeOptions),
 }
 
-impl Clone for Tls {
+impl Clone for TlsCert {
     fn clone(&self) -> Self {
-        Self {
-            port: self.port,
-            key: self.key.clone_key(),
-            cert_chain: self.cert_chain.clone(),
+        match self {
+            Self::Static {
+                key,
+                cert_chain,
+                ocsp,
+                files,
+            } => Self::Static {
+                key: key.clone_key(),
+                cert_chain: cert_chain.clone(),
+                ocsp: ocsp.clone(),
+                files: files.clone(),
+            },
+            Self::Acme(opts) => Self::Acme(opts.clone()),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Tls {
+    port: u16,
+    cert: TlsCert,
+    client_ca: Option<RootCertStore>,
+}
+
 impl Tls {
     pub fn new(port: u16, key: &[u8], cert: &[u8]) -> anyhow::Result<Self> {
-        let Some((key_item, _)) =
-            read_one_from_slice(key).map_err(|err| anyhow!("can't resolve key: {:?}", err))?
-        else {
-            bail!("invalid key data")
+        Ok(Self {
+            port,
+            cert: TlsCert::Static {
+                key: parse_key(key)?,
+                cert_chain: parse_cert_chain(cert)?,
+                ocsp: None,
+                files: None,
+            },
+            client_ca: None,
+        })
+    }
+
+    /// Obtains the certificate from an ACME server, and renews it before it
+    /// expires.
+    pub fn acme(port: u16, opts: AcmeOptions) -> anyhow::Result<Self> {
+        if opts.domains.is_empty() {
+            bail!("at least one domain is required to obtain a certificate");
+        }
+
+        Ok(Self {
+            port,
+            cert: TlsCert::Acme(opts),
+            client_ca: None,
+        })
+    }
+
+    /// Loads the key and the certificate from files, which are reloaded once
+    /// they change (e.g. when the certificate has been renewed).
+    pub fn from_files(port: u16, key_path: &Path, cert_path: &Path) -> anyhow::Result<Self> {
+        let key = std::fs::read(key_path)
+            .with_context(|| format!("can't read key file: {}", key_path.display()))?;
+        let cert = std::fs::read(cert_path)
+            .with_context(|| format!("can't read cert file: {}", cert_path.display()))?;
+
+        let mut tls = Self::new(port, &key, &cert)?;
+
+        if let TlsCert::Static { files, .. } = &mut tls.cert {
+            *files = Some(CertFiles {
+                key: key_path.to_path_buf(),
+                cert: cert_path.to_path_buf(),
+                ocsp: None,
+            });
+        }
+
+        Ok(tls)
+    }
+
+    /// Staples the DER-encoded OCSP response in the given file to the
+    /// handshakes. The file is reloaded along with the certificate.
+    pub fn with_ocsp_response(mut self, path: &Path) -> anyhow::Result<Self> {
+        let TlsCert::Static { ocsp, files, .. } = &mut self.cert else {
+            bail!("OCSP responses can't be stapled to certificates obtained via ACME");
         };
 
-        let mut cert_chain = vec![];
-        let mut cert_slice = cert;
-        loop {
-            let Some((Item::X509Certificate(cert), remain_cert_slice)) =
-                read_one_from_slice(cert_slice)
-                    .map_err(|err| anyhow!("can't resolve cert: {:?}", err))?
-            else {
-                bail!("invalid cert data")
-            };
+        *ocsp = Some(
+            std::fs::read(path)
+                .with_context(|| format!("can't read OCSP response: {}", path.display()))?,
+        );
+
+        if let Some(files) = files.as_mut() {
+            files.ocsp = Some(path.to_path_buf());
+        }
 
-            cert_chain.push(cert);
+        Ok(self)
+    }
 
-            if remain_cert_slice.is_empty() {
-                break;
-            }
+    /// Verifies the certificates that clients present against the CAs in the
+    /// given PEM file. Clients without a certificate are still accepted, but
+    /// they are not let into the internal endpoints that require one.
+    pub fn with_client_ca(mut self, path: &Path) -> anyhow::Result<Self> {
+        let (roots, report) = get_root_cert_store(
+            None,
+            Some(vec![]),
+            Some(CaData::File(path.to_string_lossy().into_owned())),
+        )
+        .with_context(|| "can't load client CA")?;
+
+        report.log("client CA");
 
-            cert_slice = remain_cert_slice;
+        if roots.is_empty() {
+            bail!("no certificate found in client CA file");
         }
 
-        let key = match key_item {
-            Item::Pkcs1Key(key) => PrivateKeyDer::Pkcs1(key),
-            Item::Pkcs8Key(key) => PrivateKeyDer::Pkcs8(key),
-            Item::Sec1Key(key) => PrivateKeyDer::Sec1(key),
-            _ => bail!("invalid key data"),
+        self.client_ca = Some(roots);
+        Ok(self)
+    }
+
+    /// Makes the configuration of the HTTP/3 listener, which serves the same
+    /// certificate.
+    ///
+    /// NOTE: The certificate is not reloaded into the HTTP/3 listener when its
+    /// files change.
+    #[cfg(feature = "http3")]
+    fn quic_server_config(&self) -> anyhow::Result<quinn::ServerConfig> {
+        let TlsCert::Static {
+            key, cert_chain, ..
+        } = &self.cert
+        else {
+            bail!("HTTP/3 can't serve certificates obtained via ACME");
         };
 
-        Ok(Self {
-            port,
-            key,
-            cert_chain,
-        })
+        crate::http3::make_server_config(cert_chain.clone(), key.clone_key())
     }
 
-    fn into_acceptor(self) -> anyhow::Result<TlsAcceptor> {
-        Ok(Arc::new(
-            ServerConfig::builder()
-                .with_no_client_auth()
-                .with_single_cert(self.cert_chain, self.key)
-                .with_context(|| "can't make TLS acceptor")?,
-        )
-        .into())
+    /// Makes the configuration of the gRPC control plane, which serves the
+    /// same certificate over HTTP/2.
+    ///
+    /// NOTE: The certificate is not reloaded into the control plane when its
+    /// files change.
+    #[cfg(feature = "grpc")]
+    fn grpc_server_config(&self) -> anyhow::Result<Arc<ServerConfig>> {
+        let TlsCert::Static {
+            key, cert_chain, ..
+        } = &self.cert
+        else {
+            bail!("the gRPC control plane can't serve certificates obtained via ACME");
+        };
+
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(cert_chain.clone(), key.clone_key())
+            .with_context(|| "can't make the TLS configuration of the gRPC control plane")?;
+
+        config.alpn_protocols = vec![b"h2".to_vec()];
+
+        Ok(Arc::new(config))
+    }
+
+    fn into_acceptor(self) -> anyhow::Result<(TlsAcceptor, CertTask)> {
+        let (resolver, cert_task) = match self.cert {
+            TlsCert::Static {
+                key,
+                cert_chain,
+                ocsp,
+                files,
+            } => {
+                let not_after = get_not_after(&cert_chain)?;
+                let has_ocsp_response = ocsp.is_some();
+                let resolver = Arc::new(CertResolver::new(Some(create_certified_key(
+                    cert_chain, &key, ocsp,
+                )?)));
+
+                (
+                    resolver.clone(),
+                    CertTask::Monitor(CertMonitor {
+                        resolver,
+                        files,
+                        not_after,
+                        has_ocsp_response,
+                    }),
+                )
+            }
+
+            TlsCert::Acme(opts) => {
+                // NOTE: Until the first certificate has been issued, handshakes
+                // other than the ones of challenges fail.
+                let (certified_key, not_after) = match load_cached_cert(&opts) {
+                    Ok(Some((certified_key, not_after))) => (Some(certified_key), Some(not_after)),
+                    Ok(None) => (None, None),
+                    Err(err) => {
+                        warn!("can't load cached TLS certificate: {err:#}");
+                        (None, None)
+                    }
+                };
+
+                let resolver = Arc::new(CertResolver::new(certified_key));
+
+                (
+                    resolver.clone(),
+                    CertTask::Acme(AcmeManager {
+                        opts,
+                        resolver,
+                        not_after,
+                    }),
+                )
+            }
+        };
+
+        let is_tls_alpn_01 = matches!(
+            &cert_task,
+            CertTask::Acme(AcmeManager { opts, .. })
+                if opts.challenge_type == AcmeChallengeType::TlsAlpn01
+        );
+
+        let builder = ServerConfig::builder();
+        let builder = match self.client_ca {
+            Some(roots) => builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder(Arc::new(roots))
+                    .allow_unauthenticated()
+                    .build()
+                    .with_context(|| "can't make client certificate verifier")?,
+            ),
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder.with_cert_resolver(resolver);
+
+        if is_tls_alpn_01 {
+            config.alpn_protocols = vec![b"http/1.1".to_vec(), ACME_TLS_ALPN_PROTOCOL.to_vec()];
+        }
+
+        Ok((Arc::new(config).into(), cert_task))
     }
 }
 
@@ -325,10 +824,18 @@ pub struct Server {
     port: u16,
     tls: Option<Tls>,
     main_worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    /// Resolves once the main worker has booted, if the server listens
+    /// before it has.
+    main_worker_boot: Option<BoxFuture<'static, Result<(), Error>>>,
     callback_tx: Option<Sender<ServerHealth>>,
     termination_tokens: TerminationTokens,
     flags: ServerFlags,
     metric_src: SharedMetricSource,
+    client_ip_policy: Arc<ClientIpPolicy>,
+    readiness: Option<(ReadyTarget, PoolReport, bool)>,
+    config_reloader: Option<ConfigReloader>,
+    cert_reload: Arc<Notify>,
+    started_at: Instant,
 }
 
 impl Server {
@@ -338,7 +845,11 @@ impl Server {
         port: u16,
         tls: Option<Tls>,
         main_service_path: String,
+        maybe_functions_dir: Option<String>,
+        maybe_internal_api_auth: Option<InternalApiAuth>,
+        maybe_client_ip_policy: Option<ClientIpPolicy>,
         maybe_events_service_path: Option<String>,
+        event_worker_routes: Vec<(EventRoute, String)>,
         maybe_decorator: Option<DecoratorType>,
         maybe_user_worker_policy: Option<WorkerPoolPolicy>,
         import_map_path: Option<String>,
@@ -350,16 +861,44 @@ impl Server {
         inspector: Option<Inspector>,
         jsx_specifier: Option<String>,
         jsx_module: Option<String>,
+        ready_target: Option<ReadyTarget>,
+        maybe_config_path: Option<PathBuf>,
     ) -> Result<Self, Error> {
         let mut worker_events_tx = None;
 
+        init_default_root_cert_store().with_context(|| "can't load the root certificates")?;
+
+        if let Some(max_entries) = flags.dns_cache_size.filter(|it| *it > 0) {
+            init_dns_cache(DnsCacheOptions {
+                max_entries,
+                max_ttl: Duration::from_secs(flags.dns_cache_max_ttl_sec.unwrap_or(300)),
+                negative_ttl: Duration::from_secs(flags.dns_cache_negative_ttl_sec.unwrap_or(10)),
+            });
+        }
+
+        if flags.outbound_pool {
+            init_outbound_pool(OutboundPoolOptions {
+                max_idle_per_host: flags.outbound_pool_max_idle_per_host.unwrap_or(32),
+                idle_timeout: Duration::from_secs(
+                    flags.outbound_pool_idle_timeout_sec.unwrap_or(90),
+                ),
+                prefer_h2: !flags.outbound_pool_http1_only,
+            });
+        }
+
+        let user_worker_policy = maybe_user_worker_policy.unwrap_or_default();
+        let event_workers =
+            usize::from(maybe_events_service_path.is_some()) + event_worker_routes.len();
+        let readiness = ready_target.map(|it| (it, user_worker_policy.report(), event_workers > 0));
+
         let maybe_events_entrypoint = entrypoints.events;
         let maybe_main_entrypoint = entrypoints.main;
-        let termination_tokens =
-            TerminationTokens::new(termination_token, maybe_events_service_path.is_some());
+        let termination_tokens = TerminationTokens::new(termination_token, event_workers);
+        let mut event_termination_tokens = termination_tokens.events.iter().cloned();
+        let mut event_worker_metric_src = None;
 
         // Create Event Worker
-        let event_worker_metric_src = if let Some(events_service_path) = maybe_events_service_path {
+        if let Some(events_service_path) = maybe_events_service_path {
             let events_path = Path::new(&events_service_path);
             let events_path_buf = events_path.to_path_buf();
 
@@ -367,17 +906,42 @@ impl Server {
                 &flags,
                 events_path_buf,
                 import_map_path.clone(),
-                maybe_events_entrypoint,
+                maybe_events_entrypoint.clone(),
                 maybe_decorator,
-                Some(termination_tokens.event.clone().unwrap()),
+                event_termination_tokens.next(),
             )
             .await?;
 
             worker_events_tx = Some(sender);
-            Some(ctx.metric)
-        } else {
-            None
-        };
+            event_worker_metric_src = Some(ctx.metric);
+        }
+
+        // NOTE: The events of the routes that have an events worker of their
+        // own are taken off the default one.
+        if !event_worker_routes.is_empty() {
+            let mut routes = HashMap::new();
+
+            for (route, path) in event_worker_routes {
+                if routes.contains_key(&route) {
+                    bail!("more than one events worker is given for the route {route:?}");
+                }
+
+                let (ctx, sender) = create_events_worker(
+                    &flags,
+                    PathBuf::from(path),
+                    import_map_path.clone(),
+                    maybe_events_entrypoint.clone(),
+                    maybe_decorator,
+                    event_termination_tokens.next(),
+                )
+                .await?;
+
+                event_worker_metric_src.get_or_insert(ctx.metric);
+                routes.insert(route, sender);
+            }
+
+            worker_events_tx = Some(route_events(routes, worker_events_tx));
+        }
 
         let jsx_config = jsx_module.map(|jsx_mod| JsxImportSourceConfig {
             default_specifier: jsx_specifier,
@@ -386,9 +950,15 @@ impl Server {
             base_url: Url::from_file_path(std::env::current_dir().unwrap()).unwrap(),
         });
 
+        let base_pool_policy = user_worker_policy.as_update();
+        let request_filters = user_worker_policy.request_filters().to_vec();
+        let events_msg_tx = worker_events_tx
+            .as_ref()
+            .map(mpsc::UnboundedSender::downgrade);
+
         // Create a user worker pool
         let (shared_metric_src, worker_pool_tx) = create_user_worker_pool(
-            maybe_user_worker_policy.unwrap_or_default(),
+            user_worker_policy,
             worker_events_tx,
             Some(termination_tokens.pool.clone()),
             static_patterns,
@@ -398,31 +968,156 @@ impl Server {
         )
         .await?;
 
-        // create main worker
-        let main_worker_path = Path::new(&main_service_path).to_path_buf();
-        let main_worker_req_tx = create_main_worker(
-            main_worker_path,
-            import_map_path.clone(),
-            flags.no_module_cache,
-            MainWorkerRuntimeOpts {
+        let cert_reload = Arc::new(Notify::new());
+        let config_reloader = match maybe_config_path {
+            Some(path) => Some(
+                ConfigReloader::new(
+                    path,
+                    base_pool_policy,
+                    worker_pool_tx.clone(),
+                    cert_reload.clone(),
+                    events_msg_tx,
+                )
+                .await?,
+            ),
+
+            None => None,
+        };
+
+        #[cfg(feature = "grpc")]
+        if let Some(addr) = flags.grpc_control_plane_addr {
+            use crate::rt_worker::control_plane::{serve_control_plane, ControlPlaneOpts};
+
+            let Some(auth) = maybe_internal_api_auth
+                .clone()
+                .filter(InternalApiAuth::is_enabled)
+            else {
+                bail!("the gRPC control plane requires the internal API auth to be configured");
+            };
+
+            let opts = ControlPlaneOpts {
+                import_map_path: import_map_path.clone(),
+                no_module_cache: flags.no_module_cache,
+                maybe_decorator,
+                auth,
+            };
+
+            // NOTE: The control plane is served in the clear on a loopback
+            // address only, see `serve_control_plane`.
+            let maybe_tls_config = if addr.ip().to_canonical().is_loopback() {
+                None
+            } else {
+                let Some(tls) = tls.as_ref() else {
+                    bail!("the gRPC control plane must be bound to a loopback address unless TLS is configured");
+                };
+
+                Some(tls.grpc_server_config()?)
+            };
+
+            let worker_pool_tx = worker_pool_tx.clone();
+            let token = termination_tokens.pool.inbound.clone();
+
+            drop(tokio::spawn(async move {
+                if let Err(err) =
+                    serve_control_plane(addr, opts, maybe_tls_config, worker_pool_tx, token).await
+                {
+                    error!("failed to serve the gRPC control plane on {addr}: {err:#}");
+                }
+            }));
+        }
+
+        #[cfg(not(feature = "grpc"))]
+        if flags.grpc_control_plane_addr.is_some() {
+            bail!("the gRPC control plane requires the `grpc` feature");
+        }
+
+        let startup_gate_opts = {
+            let defaults = StartupGateOpts::default();
+
+            StartupGateOpts {
+                queue_size: flags.startup_queue_size.unwrap_or(defaults.queue_size),
+                timeout: flags
+                    .startup_queue_timeout_ms
+                    .map_or(defaults.timeout, Duration::from_millis),
+            }
+        };
+
+        let mut main_worker_boot = None;
+        let main_worker_req_tx = if maybe_functions_dir.is_some() || flags.router {
+            if maybe_functions_dir.is_none() && config_reloader.is_none() {
+                bail!("the router needs either a functions directory or a runtime config");
+            }
+
+            // route requests to user workers directly without a main worker
+            create_function_router(
+                FunctionRouterOpts {
+                    functions_dir: maybe_functions_dir.map(PathBuf::from),
+                    import_map_path: import_map_path.clone(),
+                    no_module_cache: flags.no_module_cache,
+                    maybe_decorator,
+                    internal_api_auth: maybe_internal_api_auth.filter(InternalApiAuth::is_enabled),
+                    config_reloader: config_reloader.clone(),
+                    request_filters,
+                    startup_gate: flags
+                        .hold_requests_until_ready
+                        .then(|| ServiceStartupGate::new(startup_gate_opts)),
+                },
                 worker_pool_tx,
-                shared_metric_src: Some(shared_metric_src.clone()),
-                event_worker_metric_src,
-            },
-            maybe_main_entrypoint,
-            maybe_decorator,
-            Some(termination_tokens.main.clone()),
-            if flags.allow_main_inspector {
-                inspector.map(|it| Inspector {
-                    option: InspectorOption::Inspect(it.option.socket_addr()),
-                    server: it.server,
-                })
+                Some(termination_tokens.main.clone()),
+            )?
+        } else {
+            // create main worker
+            let main_worker_path = Path::new(&main_service_path).to_path_buf();
+            let boot = create_main_worker(
+                main_worker_path,
+                import_map_path.clone(),
+                flags.no_module_cache,
+                MainWorkerRuntimeOpts {
+                    worker_pool_tx,
+                    shared_metric_src: Some(shared_metric_src.clone()),
+                    event_worker_metric_src,
+                    limits: MainWorkerLimits {
+                        memory_limit_mb: flags.main_worker_memory_limit_mb.unwrap_or(0),
+                        unresponsive_timeout_ms: flags
+                            .main_worker_unresponsive_timeout_ms
+                            .unwrap_or(0),
+                    },
+                    liveness: None,
+                },
+                maybe_main_entrypoint,
+                maybe_decorator,
+                Some(termination_tokens.main.clone()),
+                if flags.allow_main_inspector {
+                    inspector.map(|it| Inspector {
+                        option: InspectorOption::Inspect(it.option.socket_addr()),
+                        server: it.server,
+                    })
+                } else {
+                    None
+                },
+                jsx_config,
+            );
+
+            if flags.hold_requests_until_ready {
+                let (req_tx, boot) = gate_main_worker(boot, startup_gate_opts);
+
+                main_worker_boot = Some(boot);
+                req_tx
             } else {
-                None
-            },
-            jsx_config,
-        )
-        .await?;
+                boot.await?
+            }
+        };
+
+        if let Some(queue) = sb_workers::task_queue::get() {
+            spawn_task_dispatcher(
+                queue,
+                main_worker_req_tx.clone(),
+                flags
+                    .task_queue_visibility_timeout_ms
+                    .map_or(DEFAULT_VISIBILITY_TIMEOUT, Duration::from_millis),
+                termination_tokens.main.inbound.clone(),
+            );
+        }
 
         let ip = Ipv4Addr::from_str(ip)?;
 
@@ -431,10 +1126,16 @@ impl Server {
             port,
             tls,
             main_worker_req_tx,
+            main_worker_boot,
             callback_tx,
             termination_tokens,
             flags,
             metric_src: shared_metric_src,
+            client_ip_policy: Arc::new(maybe_client_ip_policy.unwrap_or_default()),
+            readiness,
+            config_reloader,
+            cert_reload,
+            started_at: Instant::now(),
         })
     }
 
@@ -442,13 +1143,75 @@ impl Server {
         self.termination_tokens.terminate().await;
     }
 
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    async fn bind_io_uring(&self, addr: SocketAddr) -> Result<Acceptor, Error> {
+        if self.flags.acceptors.unwrap_or(1) != 1 {
+            bail!("io_uring serves the HTTP port with a single acceptor");
+        }
+
+        // NOTE: The io_uring thread hands on the connections as in-memory
+        // streams, which can't be peeked at.
+        if self.flags.protocol_sniffing {
+            bail!("protocol sniffing is not supported with io_uring");
+        }
+
+        Acceptor::bind_io_uring(addr, self.flags.tcp_nodelay, self.metric_src.clone()).await
+    }
+
+    #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+    async fn bind_io_uring(&self, _addr: SocketAddr) -> Result<Acceptor, Error> {
+        bail!("io_uring requires the `io-uring` feature, on Linux");
+    }
+
+    /// Sums up what the server did since it was created.
+    pub(crate) fn shutdown_report(&self, error: Option<&Error>) -> ShutdownReport {
+        ShutdownReport::new(self.started_at.elapsed(), &self.metric_src, error)
+    }
+
     pub async fn listen(&mut self) -> Result<(), Error> {
         let addr = SocketAddr::new(IpAddr::V4(self.ip), self.port);
-        let non_secure_listener = TcpListener::bind(&addr).await?;
+        let mut non_secure_listener = if self.flags.io_uring {
+            self.bind_io_uring(addr).await?
+        } else {
+            Acceptor::bind(
+                addr,
+                self.flags.acceptors.unwrap_or(1),
+                self.metric_src.clone(),
+            )
+            .await?
+        };
+        let protocol_sniffing = self.flags.protocol_sniffing;
+        let mut maybe_cert_task = None;
+
+        #[cfg(not(feature = "http3"))]
+        if self.flags.http3 {
+            bail!("HTTP/3 requires the `http3` feature");
+        }
+
+        if self.flags.http3 && self.tls.is_none() {
+            bail!("HTTP/3 requires the TLS listener");
+        }
+
+        #[cfg(feature = "http3")]
+        let mut maybe_http3_endpoint = None;
+
         let mut secure_listener = if let Some(tls) = self.tls.take() {
             let addr = SocketAddr::new(IpAddr::V4(self.ip), tls.port);
+
+            #[cfg(feature = "http3")]
+            if self.flags.http3 {
+                maybe_http3_endpoint = Some(crate::http3::bind(addr, tls.quic_server_config()?)?);
+            }
+
+            let (acceptor, cert_task) = tls.into_acceptor()?;
+
+            maybe_cert_task = Some(cert_task);
+
             Some((
-                TlsListener::new(tls.into_acceptor()?, TcpListener::bind(addr).await?),
+                TlsListener::new(
+                    acceptor,
+                    TlsPortListener::new(TcpListener::bind(addr).await?, protocol_sniffing),
+                ),
                 addr,
             ))
         } else {
@@ -472,6 +1235,14 @@ impl Server {
             debug!("edge-runtime is listening on {:?} (secure)", addr);
         }
 
+        #[cfg(feature = "http3")]
+        if let Some(endpoint) = maybe_http3_endpoint.as_ref() {
+            debug!(
+                "edge-runtime is listening on {:?} (HTTP/3)",
+                endpoint.local_addr()?
+            );
+        }
+
         if let Some(callback) = self.callback_tx.clone() {
             can_receive_event = true;
             let _ = callback
@@ -479,41 +1250,240 @@ impl Server {
                 .await;
         }
 
+        let mut readiness = match self.readiness.take() {
+            Some((target, pool, has_event_worker)) => Some((
+                target,
+                ReadinessReport {
+                    pid: std::process::id(),
+                    version: env!("CARGO_PKG_VERSION"),
+                    addr: non_secure_listener.local_addr()?,
+                    tls_addr: secure_listener.as_ref().map(|(_, addr)| *addr),
+                    has_event_worker,
+                    pool,
+                },
+            )),
+
+            None => None,
+        };
+
+        // NOTE: A server that listens before its main worker has booted is
+        // only reported ready once it has.
+        let mut main_worker_boot = self.main_worker_boot.take();
+
+        if main_worker_boot.is_none() {
+            if let Some((target, report)) = readiness.take() {
+                target.write(&report)?;
+            }
+        }
+
         let event_tx = can_receive_event.then_some(event_tx.clone());
         let graceful_exit_token = CancellationToken::new();
 
         let ServerFlags {
             tcp_nodelay,
             request_read_timeout_ms,
+            request_body_idle_timeout_ms,
+            request_body_min_rate,
+            max_connections_per_ip,
+            response_buffer_size,
+            response_buffer_overflow,
+            response_spill_quota,
             mut graceful_exit_deadline_sec,
             mut graceful_exit_keepalive_deadline_ms,
+            tls_cert_check_interval_sec,
+            tls_cert_expiry_warn_days,
+            max_header_size,
+            max_uri_length,
+            tls_max_header_size,
+            tls_max_uri_length,
+            path_normalization,
+            process_title,
             ..
         } = self.flags;
 
+        let head_limits = RequestHeadLimits {
+            max_header_size,
+            max_uri_length,
+            path_normalization,
+        };
+
+        let tls_head_limits = RequestHeadLimits {
+            max_header_size: tls_max_header_size.or(max_header_size),
+            max_uri_length: tls_max_uri_length.or(max_uri_length),
+            path_normalization,
+        };
+
+        let cert_task_cancel = CancellationToken::new();
+        let _cert_task_guard = cert_task_cancel.clone().drop_guard();
+
+        if let Some(cert_task) = maybe_cert_task {
+            drop(tokio::spawn(cert_task.run(
+                CertMonitorOptions {
+                    check_interval: Duration::from_secs(
+                        tls_cert_check_interval_sec.unwrap_or(3600).max(1),
+                    ),
+                    expiry_warn_days: tls_cert_expiry_warn_days.unwrap_or(14),
+                },
+                event_tx.clone(),
+                self.cert_reload.clone(),
+                cert_task_cancel,
+            )));
+        }
+
+        let process_title_cancel = CancellationToken::new();
+        let _process_title_guard = process_title_cancel.clone().drop_guard();
+
+        if process_title {
+            drop(tokio::spawn(run_process_title(
+                non_secure_listener.local_addr()?,
+                metric_src.clone(),
+                process_title_cancel,
+            )));
+        }
+
         let request_read_timeout_dur = request_read_timeout_ms.map(Duration::from_millis);
+        let body_limits = BodyReadLimits {
+            idle_timeout: request_body_idle_timeout_ms.map(Duration::from_millis),
+            min_rate: request_body_min_rate,
+        };
+
+        let response_limits = response_buffer_size.map(|max_size| ResponseBufferLimits {
+            max_size,
+            overflow: response_buffer_overflow,
+            spill_quota: Arc::new(SpillQuota::new(response_spill_quota)),
+        });
+
+        let conn_limiter = ConnLimiter::new(max_connections_per_ip);
+        let tls_port = secure_listener.as_ref().map(|(_, addr)| addr.port());
+
+        #[cfg(feature = "http3")]
+        let alt_svc = maybe_http3_endpoint
+            .as_ref()
+            .map(|it| {
+                it.local_addr()
+                    .map(|addr| crate::http3::get_alt_svc(addr.port()))
+            })
+            .transpose()?;
+
+        #[cfg(not(feature = "http3"))]
+        let alt_svc: Option<HeaderValue> = None;
+
+        #[cfg(feature = "http3")]
+        if let Some(endpoint) = maybe_http3_endpoint {
+            let main_worker_req_tx = self.main_worker_req_tx.clone();
+            let client_ip_policy = self.client_ip_policy.clone();
+            let metric_src = metric_src.clone();
+            let conn_limiter = conn_limiter.clone();
+            let response_limits = response_limits.clone();
+
+            drop(tokio::spawn(crate::http3::serve(
+                endpoint,
+                move |remote_addr| {
+                    let Some(conn_permit) = conn_limiter.try_acquire(remote_addr.ip()) else {
+                        metric_src.incl_rejected_connections();
+                        return None;
+                    };
+
+                    let (service, cancel) = WorkerService::new(
+                        metric_src.clone(),
+                        main_worker_req_tx.clone(),
+                        ConnInfo {
+                            remote_addr: Some(remote_addr),
+                            has_verified_client_cert: false,
+                            tls_version: Some("TLSv1.3"),
+                        },
+                        client_ip_policy.clone(),
+                        tls_head_limits,
+                        body_limits,
+                        response_limits.clone(),
+                        None,
+                    );
+
+                    metric_src.incl_active_io();
+
+                    let active_io_count_guard = scopeguard::guard(metric_src.clone(), |it| {
+                        it.decl_active_io();
+                    });
+
+                    Some((
+                        service,
+                        (conn_permit, cancel.drop_guard(), active_io_count_guard),
+                    ))
+                },
+                graceful_exit_token.clone(),
+            )));
+        }
+
         let mut terminate_signal_fut = get_termination_signal();
+        let config_reloader = self.config_reloader.clone();
+        let mut reload_signal = get_reload_signal(config_reloader.is_some());
 
         loop {
             let main_worker_req_tx = self.main_worker_req_tx.clone();
             let event_tx = event_tx.clone();
             let metric_src = metric_src.clone();
+            let client_ip_policy = self.client_ip_policy.clone();
 
             tokio::select! {
-                msg = non_secure_listener.accept() => {
+                msg = non_secure_listener.accept(&metric_src) => {
                     match msg {
-                        Ok((stream, _)) => {
+                        Ok((stream, remote_addr)) => {
+                            let Some(conn_permit) =
+                                conn_limiter.try_acquire(remote_addr.ip())
+                            else {
+                                metric_src.incl_rejected_connections();
+                                debug!(
+                                    "connection rejected: too many connections from {}",
+                                    remote_addr.ip()
+                                );
+                                continue;
+                            };
+
                             if tcp_nodelay {
                                 let _ = stream.set_nodelay(true);
                             }
 
-                            accept_stream(
-                                stream,
-                                main_worker_req_tx,
-                                event_tx,
-                                metric_src,
-                                graceful_exit_token.clone(),
-                                request_read_timeout_dur
-                            )
+                            let accept = {
+                                let graceful_exit_token = graceful_exit_token.clone();
+
+                                move |stream| {
+                                    accept_stream(
+                                        stream,
+                                        ConnInfo {
+                                            remote_addr: Some(remote_addr),
+                                            ..Default::default()
+                                        },
+                                        main_worker_req_tx,
+                                        client_ip_policy,
+                                        head_limits,
+                                        body_limits,
+                                        response_limits.clone(),
+                                        None,
+                                        conn_permit,
+                                        event_tx,
+                                        metric_src,
+                                        graceful_exit_token,
+                                        request_read_timeout_dur
+                                    )
+                                }
+                            };
+
+                            match stream {
+                                // NOTE: The first bytes of the client are
+                                // awaited apart, so as not to hold up the
+                                // accept loop.
+                                AcceptedStream::Tcp(stream) if protocol_sniffing => {
+                                    drop(tokio::spawn(async move {
+                                        if sniff(&stream).await == Protocol::Tls {
+                                            reject_tls(stream, tls_port).await;
+                                        } else {
+                                            accept(AcceptedStream::Tcp(stream));
+                                        }
+                                    }));
+                                }
+
+                                stream => accept(stream),
+                            }
                         }
                         Err(e) => error!("socket error: {}", e)
                     }
@@ -528,14 +1498,44 @@ impl Server {
                     }.await
                 } => {
                     match msg {
-                        Ok((stream, _)) => {
+                        Ok((stream, remote_addr)) => {
+                            let Some(conn_permit) =
+                                conn_limiter.try_acquire(remote_addr.ip())
+                            else {
+                                metric_src.incl_rejected_connections();
+                                debug!(
+                                    "connection rejected: too many connections from {}",
+                                    remote_addr.ip()
+                                );
+                                continue;
+                            };
+
                             if tcp_nodelay {
                                 let _ = stream.get_ref().0.set_nodelay(true);
                             }
 
+                            let has_verified_client_cert =
+                                stream.get_ref().1.peer_certificates().is_some();
+                            let tls_version = stream
+                                .get_ref()
+                                .1
+                                .protocol_version()
+                                .map(get_tls_version_name);
+
                             accept_stream(
                                 stream,
+                                ConnInfo {
+                                    remote_addr: Some(remote_addr),
+                                    has_verified_client_cert,
+                                    tls_version,
+                                },
                                 main_worker_req_tx,
+                                client_ip_policy,
+                                tls_head_limits,
+                                body_limits,
+                                response_limits.clone(),
+                                alt_svc.clone(),
+                                conn_permit,
                                 event_tx,
                                 metric_src,
                                 graceful_exit_token.clone(),
@@ -564,6 +1564,28 @@ impl Server {
                     break;
                 }
 
+                res = async { main_worker_boot.as_mut().unwrap().await }, if main_worker_boot.is_some() => {
+                    main_worker_boot = None;
+                    res?;
+
+                    info!("main worker booted");
+
+                    if let Some((target, report)) = readiness.take() {
+                        target.write(&report)?;
+                    }
+                }
+
+                Some(()) = reload_signal.next() => {
+                    info!("reload signal received");
+
+                    if let Some(reloader) = config_reloader.clone() {
+                        drop(tokio::spawn(async move {
+                            // NOTE: Failures are logged by the reloader.
+                            let _ = reloader.reload().await;
+                        }));
+                    }
+                }
+
                 signum = &mut terminate_signal_fut => {
                     info!("shutdown signal received: {}", signum);
                     break;
@@ -702,9 +1724,47 @@ fn get_termination_signal() -> BoxFuture<'static, i32> {
     pending().boxed()
 }
 
+/// Yields whenever the runtime config should be reloaded.
+#[cfg(unix)]
+fn get_reload_signal(enabled: bool) -> BoxStream<'static, ()> {
+    use signal::unix::signal;
+    use signal::unix::SignalKind;
+
+    // NOTE: Handling `SIGHUP` replaces its default action, which terminates the
+    // process, so it is left alone unless there is something to reload.
+    if !enabled {
+        return stream::pending().boxed();
+    }
+
+    let mut hangup = signal(SignalKind::hangup()).unwrap();
+
+    stream::poll_fn(move |cx| hangup.poll_recv(cx)).boxed()
+}
+
+#[cfg(not(unix))]
+fn get_reload_signal(_enabled: bool) -> BoxStream<'static, ()> {
+    stream::pending().boxed()
+}
+
+fn get_tls_version_name(version: ProtocolVersion) -> &'static str {
+    match version {
+        ProtocolVersion::TLSv1_2 => "TLSv1.2",
+        ProtocolVersion::TLSv1_3 => "TLSv1.3",
+        _ => "unknown",
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn accept_stream<I>(
     io: I,
+    conn_info: ConnInfo,
     req_tx: UnboundedSender<WorkerRequestMsg>,
+    client_ip_policy: Arc<ClientIpPolicy>,
+    head_limits: RequestHeadLimits,
+    body_limits: BodyReadLimits,
+    response_limits: Option<ResponseBufferLimits>,
+    alt_svc: Option<HeaderValue>,
+    conn_permit: ConnPermit,
     event_tx: Option<UnboundedSender<ServerEvent>>,
     metric_src: SharedMetricSource,
     graceful_exit_token: CancellationToken,
@@ -715,7 +1775,17 @@ fn accept_stream<I>(
     metric_src.incl_active_io();
     tokio::task::spawn({
         async move {
-            let (service, cancel) = WorkerService::new(metric_src.clone(), req_tx);
+            let _conn_permit = conn_permit;
+            let (service, cancel) = WorkerService::new(
+                metric_src.clone(),
+                req_tx,
+                conn_info,
+                client_ip_policy,
+                head_limits,
+                body_limits,
+                response_limits,
+                alt_svc,
+            );
             let (io, maybe_timeout_tx) = if let Some(timeout_dur) = maybe_req_read_timeout_dur {
                 crate::timeout::Stream::with_timeout(io, timeout_dur)
             } else {
@@ -729,6 +1799,7 @@ fn accept_stream<I>(
 
             let mut shutting_down = false;
             let conn_fut = Http::new()
+                .http1_max_buf_size(head_limits.max_buf_size())
                 .serve_connection(io, crate::timeout::Service::new(service, maybe_timeout_tx))
                 .with_upgrades();
 
@@ -760,3 +1831,111 @@ fn accept_stream<I>(
         }
     });
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use http_v02::{header, HeaderValue, StatusCode};
+    use hyper_v014::service::Service;
+    use hyper_v014::{Body, Request, Response};
+    use sb_core::SharedMetricSource;
+    use tokio::sync::mpsc;
+
+    use super::{RequestHeadLimits, WorkerService, DEFAULT_MAX_BUF_SIZE};
+
+    #[test]
+    fn test_request_head_limits() {
+        let limits = RequestHeadLimits {
+            max_header_size: Some(16),
+            max_uri_length: Some(12),
+            ..Default::default()
+        };
+
+        let req = |uri: &str, value: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("x-a", value)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // NOTE: 3 bytes of name and 13 of value.
+        assert!(limits.check(&req("/0123456789", "0123456789abc")).is_none());
+        assert_eq!(
+            limits
+                .check(&req("/0123456789ab", "0"))
+                .map(|(status, _)| status),
+            Some(StatusCode::URI_TOO_LONG)
+        );
+        assert_eq!(
+            limits
+                .check(&req("/", "0123456789abcd"))
+                .map(|(status, _)| status),
+            Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        );
+
+        let unlimited = RequestHeadLimits::default();
+
+        assert!(unlimited
+            .check(&req(&format!("/{}", "a".repeat(100_000)), "0"))
+            .is_none());
+    }
+
+    #[test]
+    fn test_request_head_limits_max_buf_size() {
+        assert_eq!(
+            RequestHeadLimits::default().max_buf_size(),
+            DEFAULT_MAX_BUF_SIZE
+        );
+
+        let limits = RequestHeadLimits {
+            max_header_size: Some(1024 * 1024),
+            max_uri_length: Some(64 * 1024),
+            ..Default::default()
+        };
+
+        // NOTE: hyper must not reject what the limits would accept.
+        assert!(limits.max_buf_size() > 1024 * 1024 + 64 * 1024);
+    }
+
+    #[tokio::test]
+    async fn test_alt_svc() {
+        let (worker_req_tx, mut worker_req_rx) = mpsc::unbounded_channel();
+        let (mut service, _) = WorkerService::new(
+            SharedMetricSource::default(),
+            worker_req_tx,
+            Default::default(),
+            Arc::default(),
+            RequestHeadLimits::default(),
+            Default::default(),
+            None,
+            Some(HeaderValue::from_static("h3=\":8443\"; ma=86400")),
+        );
+
+        drop(tokio::spawn(async move {
+            while let Some(msg) = worker_req_rx.recv().await {
+                let res = match msg.req.uri().path() {
+                    "/own" => Response::builder()
+                        .header(header::ALT_SVC, "clear")
+                        .body(Body::empty())
+                        .unwrap(),
+                    _ => Response::new(Body::empty()),
+                };
+
+                let _ = msg.res_tx.send(Ok(res));
+            }
+        }));
+
+        let req = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let res = service.call(req("/hello")).await.unwrap();
+
+        assert_eq!(res.headers()[header::ALT_SVC], "h3=\":8443\"; ma=86400");
+
+        // NOTE: What the worker advertises is left as is.
+        let res = service.call(req("/own")).await.unwrap();
+
+        assert_eq!(res.headers()[header::ALT_SVC], "clear");
+    }
+}
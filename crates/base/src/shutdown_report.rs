@@ -0,0 +1,179 @@
+//! The report written once the server has stopped, which sums up what it did
+//! over its lifetime, for batch-style runs in CI and post-incident reviews.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use deno_core::serde_json;
+use event_worker::backlog::EVENTS_BACKLOG;
+use event_worker::history::{LifecycleTotals, LIFECYCLE_HISTORY};
+use sb_core::SharedMetricSource;
+use serde::Serialize;
+
+use crate::readiness::write_file_atomically;
+
+#[derive(Debug, Clone)]
+pub enum ShutdownReportTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+impl ShutdownReportTarget {
+    /// Parses the value of `--shutdown-report`, where `-` is the standard
+    /// output.
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "-" => Self::Stdout,
+            path => Self::File(PathBuf::from(path)),
+        }
+    }
+
+    pub(crate) fn write(&self, report: &ShutdownReport) -> Result<(), anyhow::Error> {
+        let mut content = serde_json::to_vec(report)?;
+
+        content.push(b'\n');
+
+        match self {
+            Self::Stdout => {
+                let mut stdout = std::io::stdout().lock();
+
+                stdout.write_all(&content)?;
+                stdout.flush()?;
+            }
+
+            Self::File(path) => {
+                write_file_atomically(path, &content).with_context(|| {
+                    format!("can't write shutdown report to {}", path.display())
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ShutdownReport {
+    pub pid: u32,
+    pub version: &'static str,
+    pub uptime_ms: u64,
+    /// Why the server stopped, if it stopped on an error.
+    pub error: Option<String>,
+    pub workers: WorkersReport,
+    pub requests: RequestsReport,
+    pub events: EventsReport,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct WorkersReport {
+    pub retired: usize,
+    /// The boots and terminations of the workers by kind, along with the
+    /// shutdowns by their reason.
+    #[serde(flatten)]
+    pub lifecycle: LifecycleTotals,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RequestsReport {
+    pub received: usize,
+    pub handled: usize,
+    pub accepted_connections: usize,
+    pub accept_errors: usize,
+    pub rejected_connections: usize,
+    pub rejected_large_headers: usize,
+    pub rejected_long_uris: usize,
+    pub slow_request_bodies: usize,
+    pub spilled_responses: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EventsReport {
+    pub dropped: usize,
+    /// The events that were still on their way to the events worker.
+    pub pending: usize,
+}
+
+impl ShutdownReport {
+    pub(crate) fn new(
+        uptime: Duration,
+        metric_src: &SharedMetricSource,
+        error: Option<&anyhow::Error>,
+    ) -> Self {
+        Self {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_ms: uptime.as_millis() as u64,
+            error: error.map(|it| format!("{:#}", it)),
+            workers: WorkersReport {
+                retired: metric_src.retired_user_workers(),
+                lifecycle: LIFECYCLE_HISTORY.totals(),
+            },
+            requests: RequestsReport {
+                received: metric_src.received_requests(),
+                handled: metric_src.handled_requests(),
+                accepted_connections: metric_src.accepted_connections(),
+                accept_errors: metric_src.accept_errors(),
+                rejected_connections: metric_src.rejected_connections(),
+                rejected_large_headers: metric_src.rejected_large_headers(),
+                rejected_long_uris: metric_src.rejected_long_uris(),
+                slow_request_bodies: metric_src.slow_request_bodies(),
+                spilled_responses: metric_src.spilled_responses(),
+            },
+            events: EventsReport {
+                dropped: EVENTS_BACKLOG.dropped(),
+                pending: EVENTS_BACKLOG.pending(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::time::Duration;
+
+    use deno_core::serde_json::{self, Value};
+    use sb_core::SharedMetricSource;
+
+    use super::{ShutdownReport, ShutdownReportTarget};
+
+    #[test]
+    fn test_write_shutdown_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        let metric_src = SharedMetricSource::default();
+
+        metric_src.incl_received_requests();
+        metric_src.incl_handled_requests();
+        metric_src.incl_rejected_connections();
+
+        let target = ShutdownReportTarget::parse(path.to_str().unwrap());
+        let report = ShutdownReport::new(
+            Duration::from_secs(3),
+            &metric_src,
+            Some(&anyhow::anyhow!("address in use")),
+        );
+
+        target.write(&report).unwrap();
+
+        let report: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+
+        assert_eq!(report["uptimeMs"], 3000);
+        assert_eq!(report["error"], "address in use");
+        assert_eq!(report["requests"]["received"], 1);
+        assert_eq!(report["requests"]["rejectedConnections"], 1);
+        assert!(report["workers"]["byKind"].is_object());
+        assert!(report["events"]["dropped"].is_u64());
+
+        assert!(matches!(
+            ShutdownReportTarget::parse("-"),
+            ShutdownReportTarget::Stdout
+        ));
+    }
+}
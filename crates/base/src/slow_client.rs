@@ -0,0 +1,255 @@
+//! Protection of the listeners against the clients that hold on to them by
+//! sending slowly, or by opening many connections at once.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+
+use futures_util::{Future, Stream};
+use hyper_v014::body::Bytes;
+use tokio::time::{sleep, Instant, Sleep};
+
+/// A client is only held to the minimum transfer rate once it has been sending
+/// its body for this long.
+static MIN_RATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Caps the connections a single IP may have open at once, if a cap is set.
+///
+/// NOTE: The IP is the one of the peer, so the clients behind the same proxy
+/// share the cap.
+#[derive(Debug, Clone, Default)]
+pub struct ConnLimiter {
+    max_per_ip: Option<usize>,
+    counts: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnLimiter {
+    pub fn new(max_per_ip: Option<usize>) -> Self {
+        Self {
+            max_per_ip: max_per_ip.map(|it| it.max(1)),
+            counts: Arc::default(),
+        }
+    }
+
+    /// Returns `None` if the IP already has as many connections as allowed.
+    pub fn try_acquire(&self, ip: IpAddr) -> Option<ConnPermit> {
+        let Some(max_per_ip) = self.max_per_ip else {
+            return Some(ConnPermit { ip, counts: None });
+        };
+
+        let ip = ip.to_canonical();
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(ip).or_default();
+
+        if *count >= max_per_ip {
+            return None;
+        }
+
+        *count += 1;
+
+        Some(ConnPermit {
+            ip,
+            counts: Some(self.counts.clone()),
+        })
+    }
+}
+
+/// A connection of an IP, which is given back once dropped.
+#[derive(Debug)]
+pub struct ConnPermit {
+    ip: IpAddr,
+    counts: Option<Arc<Mutex<HashMap<IpAddr, usize>>>>,
+}
+
+impl Drop for ConnPermit {
+    fn drop(&mut self) {
+        let Some(counts) = self.counts.as_ref() else {
+            return;
+        };
+
+        let mut counts = counts.lock().unwrap();
+
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// How slowly the body of a request may be received.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BodyReadLimits {
+    /// The longest the client may go without sending a chunk of the body.
+    pub idle_timeout: Option<Duration>,
+    /// The lowest average rate at which the body must be received, in bytes
+    /// per second.
+    pub min_rate: Option<u64>,
+}
+
+impl BodyReadLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.idle_timeout.is_none() && self.min_rate.is_none()
+    }
+}
+
+/// Fails the body of a request once it is received slower than allowed, so
+/// that the worker handling it is let go.
+pub struct SlowBodyGuard<S> {
+    inner: S,
+    limits: BodyReadLimits,
+    started_at: Instant,
+    received: u64,
+    sleep: Pin<Box<Sleep>>,
+    on_timeout: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl<S> SlowBodyGuard<S> {
+    pub fn new(
+        inner: S,
+        limits: BodyReadLimits,
+        on_timeout: impl FnOnce() + Send + 'static,
+    ) -> Self {
+        let started_at = Instant::now();
+        let mut guard = Self {
+            inner,
+            limits,
+            started_at,
+            received: 0,
+            sleep: Box::pin(sleep(Duration::ZERO)),
+            on_timeout: Some(Box::new(on_timeout)),
+        };
+
+        guard.reset_deadline();
+        guard
+    }
+
+    /// Returns when the client is cut off unless it sends more, which is the
+    /// earliest of the idle timeout and the time it falls below the minimum
+    /// rate.
+    fn get_deadline(&self) -> Option<Instant> {
+        let idle_deadline = self.limits.idle_timeout.map(|it| Instant::now() + it);
+        let rate_deadline = self.limits.min_rate.filter(|it| *it > 0).map(|it| {
+            let allowed = Duration::from_secs_f64(self.received as f64 / it as f64);
+
+            self.started_at + allowed.max(MIN_RATE_GRACE_PERIOD)
+        });
+
+        match (idle_deadline, rate_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn reset_deadline(&mut self) {
+        // NOTE: A body without limits never wakes up for them.
+        let deadline = self
+            .get_deadline()
+            .unwrap_or_else(|| Instant::now() + Duration::from_secs(86400 * 365));
+
+        self.sleep.as_mut().reset(deadline);
+    }
+}
+
+impl<S, E> Stream for SlowBodyGuard<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        if self.on_timeout.is_none() {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut self.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                self.received += chunk.len() as u64;
+                self.reset_deadline();
+
+                Poll::Ready(Some(Ok(chunk)))
+            }
+
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(std::io::Error::other(err)))),
+
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => {
+                if self.sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+
+                if let Some(on_timeout) = self.on_timeout.take() {
+                    on_timeout();
+                }
+
+                Poll::Ready(Some(Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "request body is received too slowly",
+                ))))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+    use hyper_v014::body::Bytes;
+
+    use super::{BodyReadLimits, ConnLimiter, SlowBodyGuard};
+
+    #[test]
+    fn test_conn_limiter() {
+        let limiter = ConnLimiter::new(Some(2));
+        let a = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        let first = limiter.try_acquire(a).unwrap();
+        let _second = limiter.try_acquire(a).unwrap();
+
+        assert!(limiter.try_acquire(a).is_none());
+        assert!(limiter.try_acquire(b).is_some());
+
+        drop(first);
+
+        assert!(limiter.try_acquire(a).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_slow_body_guard_idle_timeout() {
+        let (tx, rx) = futures_util::channel::mpsc::unbounded::<Result<Bytes, std::io::Error>>();
+        let mut body = SlowBodyGuard::new(
+            rx,
+            BodyReadLimits {
+                idle_timeout: Some(Duration::from_millis(50)),
+                min_rate: None,
+            },
+            || {},
+        );
+
+        tx.unbounded_send(Ok(Bytes::from_static(b"hello"))).unwrap();
+
+        assert!(body.next().await.unwrap().is_ok());
+        assert_eq!(
+            body.next().await.unwrap().unwrap_err().kind(),
+            std::io::ErrorKind::TimedOut
+        );
+        assert!(body.next().await.is_none());
+    }
+}
@@ -0,0 +1,225 @@
+//! Detection of clients that speak TLS to the plaintext port, or plain HTTP to
+//! the TLS port, so that they are told what went wrong instead of being left
+//! with a hung or garbled connection.
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use log::debug;
+use tls_listener::AsyncAccept;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+/// The content type of the records carrying handshake messages, which is the
+/// first byte sent by a TLS client.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+/// A fatal `handshake_failure` alert.
+static TLS_HANDSHAKE_FAILURE_ALERT: &[u8] = &[0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 0x28];
+
+/// How long a client has to send its first bytes before it is assumed to
+/// speak the protocol of the port.
+static SNIFF_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How much of a plain HTTP request is read to find out where to redirect it.
+static MAX_HEAD_SIZE: usize = 8192;
+
+static ACCEPT_BACKLOG: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    Tls,
+    Http,
+    Unknown,
+}
+
+impl Protocol {
+    fn detect(first_byte: u8) -> Self {
+        match first_byte {
+            TLS_HANDSHAKE_RECORD => Self::Tls,
+            // NOTE: Request methods are tokens in upper case.
+            b'A'..=b'Z' => Self::Http,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Returns the protocol the client speaks, judging by its first byte, which
+/// is left to be read by whoever serves the connection.
+pub(crate) async fn sniff(stream: &TcpStream) -> Protocol {
+    let mut buf = [0; 1];
+
+    match timeout(SNIFF_TIMEOUT, stream.peek(&mut buf)).await {
+        Ok(Ok(1)) => Protocol::detect(buf[0]),
+        _ => Protocol::Unknown,
+    }
+}
+
+/// Turns down a TLS client connected to the plaintext port with an alert, as
+/// it would not make sense of anything else.
+pub(crate) async fn reject_tls(mut stream: TcpStream, tls_port: Option<u16>) {
+    match tls_port {
+        Some(port) => {
+            debug!("TLS handshake received on the plaintext port (TLS is served on port {port})")
+        }
+        None => debug!("TLS handshake received on the plaintext port (TLS is not enabled)"),
+    }
+
+    let _ = stream.write_all(TLS_HANDSHAKE_FAILURE_ALERT).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Redirects a plain HTTP client connected to the TLS port to the HTTPS URL of
+/// its request, or tells it to use HTTPS if the URL can't be figured out.
+pub(crate) async fn redirect_http(mut stream: TcpStream) {
+    let mut buf = vec![0; MAX_HEAD_SIZE];
+    let mut len = 0;
+
+    let _ = timeout(SNIFF_TIMEOUT, async {
+        while len < buf.len() && !buf[..len].windows(4).any(|it| it == b"\r\n\r\n") {
+            match stream.read(&mut buf[len..]).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => len += n,
+            }
+        }
+    })
+    .await;
+
+    debug!("plain HTTP request received on the TLS port");
+
+    let res = match get_https_url(&buf[..len]) {
+        Some(url) => format!(
+            "HTTP/1.1 308 Permanent Redirect\r\nlocation: {url}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+        ),
+
+        None => {
+            let body = "This port only serves HTTPS requests.\n";
+
+            format!(
+                "HTTP/1.1 400 Bad Request\r\ncontent-type: text/plain\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+    };
+
+    let _ = stream.write_all(res.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Returns the HTTPS URL of a plain HTTP request, given its head.
+fn get_https_url(head: &[u8]) -> Option<String> {
+    let head = str::from_utf8(head).ok()?;
+    let mut lines = head.split("\r\n");
+    let target = lines.next()?.split(' ').nth(1)?;
+    let host = lines.take_while(|it| !it.is_empty()).find_map(|it| {
+        let (name, value) = it.split_once(':')?;
+
+        name.eq_ignore_ascii_case("host").then_some(value.trim())
+    })?;
+
+    let is_valid = |it: &str| !it.is_empty() && it.bytes().all(|b| b.is_ascii_graphic());
+
+    if !target.starts_with('/') || !is_valid(target) || !is_valid(host) {
+        return None;
+    }
+
+    Some(format!("https://{host}{target}"))
+}
+
+/// The listener of the TLS port, which redirects the plain HTTP clients if
+/// sniffing is enabled.
+pub(crate) enum TlsPortListener {
+    Plain(TcpListener),
+    Sniffing {
+        rx: mpsc::Receiver<io::Result<(TcpStream, SocketAddr)>>,
+        task: JoinHandle<()>,
+    },
+}
+
+impl TlsPortListener {
+    pub(crate) fn new(listener: TcpListener, sniffing: bool) -> Self {
+        if !sniffing {
+            return Self::Plain(listener);
+        }
+
+        let (tx, rx) = mpsc::channel(ACCEPT_BACKLOG);
+        let task = tokio::spawn(async move {
+            while !tx.is_closed() {
+                match listener.accept().await {
+                    Ok((stream, addr)) => {
+                        let tx = tx.clone();
+
+                        drop(tokio::spawn(async move {
+                            if sniff(&stream).await == Protocol::Http {
+                                redirect_http(stream).await;
+                            } else {
+                                let _ = tx.send(Ok((stream, addr))).await;
+                            }
+                        }));
+                    }
+
+                    Err(err) => {
+                        let _ = tx.send(Err(err)).await;
+                    }
+                }
+            }
+        });
+
+        Self::Sniffing { rx, task }
+    }
+}
+
+impl AsyncAccept for TlsPortListener {
+    type Connection = TcpStream;
+    type Address = SocketAddr;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(Self::Connection, Self::Address), Self::Error>> {
+        match self.get_mut() {
+            Self::Plain(listener) => listener.poll_accept(cx),
+            Self::Sniffing { rx, .. } => rx
+                .poll_recv(cx)
+                .map(|it| it.unwrap_or_else(|| Err(io::Error::other("TLS port listener is gone")))),
+        }
+    }
+}
+
+impl Drop for TlsPortListener {
+    fn drop(&mut self) {
+        if let Self::Sniffing { task, .. } = self {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_https_url, Protocol};
+
+    #[test]
+    fn test_sniff_protocol() {
+        assert_eq!(Protocol::detect(0x16), Protocol::Tls);
+        assert_eq!(Protocol::detect(b'G'), Protocol::Http);
+        assert_eq!(Protocol::detect(0), Protocol::Unknown);
+
+        assert_eq!(
+            get_https_url(b"GET /foo?bar=1 HTTP/1.1\r\nHOST: example.com:8443\r\n\r\n").as_deref(),
+            Some("https://example.com:8443/foo?bar=1")
+        );
+        assert_eq!(get_https_url(b"GET /foo HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(
+            get_https_url(b"GET http://example.com/ HTTP/1.1\r\nhost: example.com\r\n\r\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,512 @@
+//! Runs the `Deno.test` cases of a service inside user workers, so that they
+//! see the same ops, permissions and (optionally) limits as in production.
+//!
+//! Every test file gets a worker of its own, whose main module registers the
+//! cases of the file and runs them once it receives a request.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use deno_core::url::Url;
+use event_worker::events::LogEvent;
+use sb_workers::context::UserWorkerRuntimeOpts;
+use serde::Deserialize;
+
+use crate::repl::ReplSession;
+
+static TEST_FILE_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "mjs", "jsx"];
+
+/// Replaces `Deno.test` with one that only collects the cases, which are run
+/// by the main module once the test file has been evaluated.
+static TEST_REGISTER_MODULE: &str = r#"
+const tests = [];
+
+globalThis[Symbol.for("edgeRuntime.tests")] = tests;
+
+function normalize(a, b, c) {
+	if (typeof a === "function") {
+		return { name: a.name, fn: a };
+	}
+
+	if (typeof a === "string") {
+		return typeof b === "function" ? { name: a, fn: b } : { ...b, name: a, fn: c };
+	}
+
+	return typeof b === "function" ? { ...a, name: a.name ?? b.name, fn: b } : a;
+}
+
+Deno.test = (a, b, c) => {
+	const test = normalize(a, b, c);
+
+	if (typeof test?.fn !== "function") {
+		throw new TypeError("a test must have a function");
+	}
+
+	tests.push(test);
+};
+
+Deno.test.ignore = (a, b, c) => Deno.test({ ...normalize(a, b, c), ignore: true });
+Deno.test.only = (a, b, c) => Deno.test({ ...normalize(a, b, c), only: true });
+"#;
+
+static TEST_MAIN_MODULE_TEMPLATE: &str = r#"
+import "./register.js";
+import "{TEST_FILE}";
+
+function createContext(name) {
+	return {
+		name,
+		async step(a, b) {
+			const step = typeof a === "function" ? { name: a.name, fn: a } : typeof a === "string" ? { name: a, fn: b } : a;
+
+			if (step.ignore) {
+				return false;
+			}
+
+			await step.fn(createContext(step.name));
+			return true;
+		},
+	};
+}
+
+Deno.serve(async () => {
+	const tests = globalThis[Symbol.for("edgeRuntime.tests")];
+	const hasOnly = tests.some((it) => it.only);
+	const results = [];
+
+	for (const test of tests) {
+		if (test.ignore || (hasOnly && !test.only)) {
+			results.push({ name: test.name, status: "ignored", durationMs: 0 });
+			continue;
+		}
+
+		const start = performance.now();
+
+		try {
+			await test.fn(createContext(test.name));
+			results.push({ name: test.name, status: "passed", durationMs: performance.now() - start });
+		} catch (err) {
+			results.push({
+				name: test.name,
+				status: "failed",
+				durationMs: performance.now() - start,
+				error: err?.stack ?? String(err),
+			});
+		}
+	}
+
+	return Response.json(results);
+});
+"#;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TestResult {
+    pub name: String,
+    pub status: TestStatus,
+    pub duration_ms: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct TestFileReport {
+    pub path: PathBuf,
+    pub results: Vec<TestResult>,
+    /// Why the cases of the file could not be run at all, e.g. a module that
+    /// failed to load or a worker that exceeded its limits.
+    pub error: Option<String>,
+    pub logs: Vec<LogEvent>,
+}
+
+impl TestFileReport {
+    pub fn is_failed(&self) -> bool {
+        self.error.is_some() || self.count(TestStatus::Failed) > 0
+    }
+
+    fn count(&self, status: TestStatus) -> usize {
+        self.results.iter().filter(|it| it.status == status).count()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TestReporter {
+    #[default]
+    Pretty,
+    Tap,
+    Junit,
+}
+
+impl FromStr for TestReporter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "pretty" => Self::Pretty,
+            "tap" => Self::Tap,
+            "junit" => Self::Junit,
+            _ => bail!("unknown test reporter: {s}"),
+        })
+    }
+}
+
+fn is_test_file(path: &Path) -> bool {
+    let (Some(stem), Some(ext)) = (
+        path.file_stem().and_then(|it| it.to_str()),
+        path.extension().and_then(|it| it.to_str()),
+    ) else {
+        return false;
+    };
+
+    TEST_FILE_EXTENSIONS.contains(&ext)
+        && (stem == "test" || stem.ends_with("_test") || stem.ends_with(".test"))
+}
+
+/// Returns the test files under the service, following the naming of
+/// `deno test` (`test.ts`, `*_test.ts` and `*.test.ts`), in a stable order.
+pub fn find_test_files(service_path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if service_path.is_file() {
+        return Ok(vec![service_path.to_path_buf()]);
+    }
+
+    let mut files = vec![];
+    let mut dirs = vec![service_path.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let is_hidden = path
+                .file_name()
+                .and_then(|it| it.to_str())
+                .is_some_and(|it| it.starts_with('.') || it == "node_modules");
+
+            if is_hidden {
+                continue;
+            }
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_test_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Runs the cases of a test file in a worker of its own.
+pub async fn run_test_file(
+    path: &Path,
+    runtime_opts: UserWorkerRuntimeOpts,
+    import_map_path: Option<String>,
+) -> TestFileReport {
+    let mut report = TestFileReport {
+        path: path.to_path_buf(),
+        results: vec![],
+        error: None,
+        logs: vec![],
+    };
+
+    let specifier = match path.canonicalize().map(Url::from_file_path) {
+        Ok(Ok(it)) => it,
+        _ => {
+            report.error = Some(format!("invalid test file path: {}", path.display()));
+            return report;
+        }
+    };
+
+    let main_module = TEST_MAIN_MODULE_TEMPLATE.replace("{TEST_FILE}", specifier.as_str());
+    let module_map = HashMap::from([(
+        String::from("register.js"),
+        TEST_REGISTER_MODULE.to_string().into(),
+    )]);
+
+    let mut session = match ReplSession::boot(
+        runtime_opts,
+        &main_module,
+        Some(module_map),
+        import_map_path,
+    )
+    .await
+    {
+        Ok(it) => it,
+        Err(err) => {
+            report.error = Some(format!("{:#}", err));
+            return report;
+        }
+    };
+
+    let result = async {
+        let res = session.request(String::new()).await?;
+        let body = hyper_v014::body::to_bytes(res.into_body()).await?;
+
+        Ok::<_, Error>(serde_json::from_slice::<Vec<TestResult>>(&body)?)
+    }
+    .await;
+
+    match result {
+        Ok(results) => report.results = results,
+        Err(err) => report.error = Some(format!("{:#}", err)),
+    }
+
+    report.logs = session.take_logs();
+
+    let (logs, usage) = session.close().await;
+
+    report.logs.extend(logs);
+
+    // NOTE: A worker that exceeded its limits only tells why through its
+    // shutdown event.
+    if let (Some(err), Some(reason)) = (report.error.as_mut(), usage.shutdown_reason) {
+        *err = format!("{err} (worker shut down: {reason})");
+    }
+
+    report
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn format_reports(reporter: TestReporter, reports: &[TestFileReport]) -> String {
+    let mut out = String::new();
+
+    match reporter {
+        TestReporter::Pretty => {
+            let (mut passed, mut failed, mut ignored) = (0, 0, 0);
+
+            for report in reports {
+                let _ = writeln!(
+                    out,
+                    "running {} tests from {}",
+                    report.results.len(),
+                    report.path.display()
+                );
+
+                if let Some(err) = report.error.as_ref() {
+                    let _ = writeln!(out, "error: {err}");
+                    failed += 1;
+                }
+
+                for result in report.results.iter() {
+                    let label = match result.status {
+                        TestStatus::Passed => "ok",
+                        TestStatus::Failed => "FAILED",
+                        TestStatus::Ignored => "ignored",
+                    };
+
+                    let _ = writeln!(
+                        out,
+                        "{} ... {} ({:.0}ms)",
+                        result.name, label, result.duration_ms
+                    );
+
+                    if let Some(err) = result.error.as_ref() {
+                        let _ = writeln!(out, "{err}");
+                    }
+                }
+
+                passed += report.count(TestStatus::Passed);
+                failed += report.count(TestStatus::Failed);
+                ignored += report.count(TestStatus::Ignored);
+            }
+
+            let verdict = if failed > 0 { "FAILED" } else { "ok" };
+            let _ = writeln!(
+                out,
+                "\n{verdict} | {passed} passed | {failed} failed | {ignored} ignored"
+            );
+        }
+
+        TestReporter::Tap => {
+            let mut n = 0;
+
+            let _ = writeln!(out, "TAP version 13");
+
+            for report in reports {
+                let _ = writeln!(out, "# {}", report.path.display());
+
+                if let Some(err) = report.error.as_ref() {
+                    n += 1;
+                    let _ = writeln!(out, "not ok {n} - {}", report.path.display());
+                    let _ = writeln!(out, "  ---\n  message: {:?}\n  ...", err);
+                }
+
+                for result in report.results.iter() {
+                    n += 1;
+
+                    match result.status {
+                        TestStatus::Passed => {
+                            let _ = writeln!(out, "ok {n} - {}", result.name);
+                        }
+
+                        TestStatus::Ignored => {
+                            let _ = writeln!(out, "ok {n} - {} # SKIP", result.name);
+                        }
+
+                        TestStatus::Failed => {
+                            let _ = writeln!(out, "not ok {n} - {}", result.name);
+                            let _ = writeln!(
+                                out,
+                                "  ---\n  message: {:?}\n  ...",
+                                result.error.as_deref().unwrap_or_default()
+                            );
+                        }
+                    }
+                }
+            }
+
+            let _ = writeln!(out, "1..{n}");
+        }
+
+        TestReporter::Junit => {
+            let total = reports
+                .iter()
+                .map(|it| it.results.len() + usize::from(it.error.is_some()))
+                .sum::<usize>();
+            let failures = reports
+                .iter()
+                .map(|it| it.count(TestStatus::Failed) + usize::from(it.error.is_some()))
+                .sum::<usize>();
+
+            let _ = writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+            let _ = writeln!(out, r#"<testsuites tests="{total}" failures="{failures}">"#);
+
+            for report in reports {
+                let path = escape_xml(&report.path.display().to_string());
+                let time = report.results.iter().map(|it| it.duration_ms).sum::<f64>() / 1000.0;
+
+                let _ = writeln!(
+                    out,
+                    r#"  <testsuite name="{path}" tests="{}" failures="{}" skipped="{}" time="{time:.3}">"#,
+                    report.results.len() + usize::from(report.error.is_some()),
+                    report.count(TestStatus::Failed) + usize::from(report.error.is_some()),
+                    report.count(TestStatus::Ignored)
+                );
+
+                if let Some(err) = report.error.as_ref() {
+                    let _ = writeln!(out, r#"    <testcase name="{path}" classname="{path}">"#);
+                    let _ = writeln!(out, r#"      <error message="{}"/>"#, escape_xml(err));
+                    let _ = writeln!(out, "    </testcase>");
+                }
+
+                for result in report.results.iter() {
+                    let _ = write!(
+                        out,
+                        r#"    <testcase name="{}" classname="{path}" time="{:.3}""#,
+                        escape_xml(&result.name),
+                        result.duration_ms / 1000.0
+                    );
+
+                    match result.status {
+                        TestStatus::Passed => {
+                            let _ = writeln!(out, "/>");
+                        }
+
+                        TestStatus::Ignored => {
+                            let _ = writeln!(out, ">\n      <skipped/>\n    </testcase>");
+                        }
+
+                        TestStatus::Failed => {
+                            let err = escape_xml(result.error.as_deref().unwrap_or_default());
+                            let _ = writeln!(
+                                out,
+                                ">\n      <failure message=\"{}\">{err}</failure>\n    </testcase>",
+                                err.lines().next().unwrap_or_default()
+                            );
+                        }
+                    }
+                }
+
+                let _ = writeln!(out, "  </testsuite>");
+            }
+
+            let _ = writeln!(out, "</testsuites>");
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        format_reports, is_test_file, TestFileReport, TestReporter, TestResult, TestStatus,
+    };
+
+    fn report() -> TestFileReport {
+        TestFileReport {
+            path: PathBuf::from("hello_test.ts"),
+            results: vec![
+                TestResult {
+                    name: "adds".to_string(),
+                    status: TestStatus::Passed,
+                    duration_ms: 1.0,
+                    error: None,
+                },
+                TestResult {
+                    name: "a < b".to_string(),
+                    status: TestStatus::Failed,
+                    duration_ms: 2.0,
+                    error: Some("AssertionError: nope".to_string()),
+                },
+                TestResult {
+                    name: "later".to_string(),
+                    status: TestStatus::Ignored,
+                    duration_ms: 0.0,
+                    error: None,
+                },
+            ],
+            error: None,
+            logs: vec![],
+        }
+    }
+
+    #[test]
+    fn test_is_test_file() {
+        assert!(is_test_file(Path::new("a/test.ts")));
+        assert!(is_test_file(Path::new("a/hello_test.js")));
+        assert!(is_test_file(Path::new("a/hello.test.tsx")));
+        assert!(!is_test_file(Path::new("a/index.ts")));
+        assert!(!is_test_file(Path::new("a/hello_test.json")));
+    }
+
+    #[test]
+    fn test_format_reports_tap() {
+        let out = format_reports(TestReporter::Tap, &[report()]);
+
+        assert!(out.starts_with("TAP version 13\n"));
+        assert!(out.contains("ok 1 - adds\n"));
+        assert!(out.contains("not ok 2 - a < b\n"));
+        assert!(out.contains("ok 3 - later # SKIP\n"));
+        assert!(out.ends_with("1..3\n"));
+    }
+
+    #[test]
+    fn test_format_reports_junit() {
+        let out = format_reports(TestReporter::Junit, &[report()]);
+
+        assert!(out.contains(r#"<testsuites tests="3" failures="1">"#));
+        assert!(out.contains(r#"<testcase name="a &lt; b" classname="hello_test.ts""#));
+        assert!(out.contains("<skipped/>"));
+    }
+}
@@ -0,0 +1,71 @@
+//! The time zone of the isolate of a worker, which `Date` reads and parses
+//! local times in.
+//!
+//! V8 keeps the time zone of an isolate in a cache of its own, which it fills
+//! from the default time zone of ICU on first use, and ICU takes its default
+//! from `TZ` once it is told to detect it again. ICU only has one default per
+//! process though, so it is switched to the time zone of a worker only while
+//! the isolate of the worker fills its cache, then back to the one of the
+//! host. Every isolate fills its cache as it boots, under the same lock, so
+//! that none of them sees the time zone of another worker.
+
+use std::ffi::OsString;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Error};
+use deno_core::v8::{self, TimeZoneDetection};
+use deno_core::{located_script_name, JsRuntime, ModuleCodeString};
+
+static TIME_ZONE_ENV: &str = "TZ";
+static TIME_ZONE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Fills the time zone cache of the isolate with the given time zone, or with
+/// the one of the host.
+pub(crate) fn pin_isolate_time_zone(
+    js_runtime: &mut JsRuntime,
+    maybe_time_zone: Option<&str>,
+) -> Result<(), Error> {
+    let _guard = TIME_ZONE_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let Some(time_zone) = maybe_time_zone else {
+        return fill_time_zone_cache(js_runtime);
+    };
+
+    let maybe_host_time_zone = std::env::var_os(TIME_ZONE_ENV);
+
+    std::env::set_var(TIME_ZONE_ENV, time_zone);
+    js_runtime
+        .v8_isolate()
+        .date_time_configuration_change_notification(TimeZoneDetection::Redetect);
+
+    let result = fill_time_zone_cache(js_runtime);
+
+    restore_host_time_zone(maybe_host_time_zone);
+    result
+}
+
+fn fill_time_zone_cache(js_runtime: &mut JsRuntime) -> Result<(), Error> {
+    js_runtime
+        .execute_script(
+            located_script_name!(),
+            ModuleCodeString::from("new Date(0).getTimezoneOffset()".to_string()),
+        )
+        .map(drop)
+        .map_err(|err| anyhow!("failed to set the time zone: {}", err))
+}
+
+fn restore_host_time_zone(maybe_host_time_zone: Option<OsString>) {
+    match maybe_host_time_zone {
+        Some(it) => std::env::set_var(TIME_ZONE_ENV, it),
+        None => std::env::remove_var(TIME_ZONE_ENV),
+    }
+
+    // NOTE: Telling the isolate of the worker to detect the time zone again
+    // would empty its cache as well, so a throwaway isolate does it instead.
+    // It is dropped right away, as isolates must be exited in the reverse
+    // order they were entered.
+    v8::Isolate::new(Default::default())
+        .date_time_configuration_change_notification(TimeZoneDetection::Redetect);
+}
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Error};
+use log::{error, info, warn};
+use rustls_pemfile::{read_one_from_slice, Item};
+use sb_core::cert::{set_server_cert_statistics, ServerCertStatistics};
+use tokio::sync::{mpsc, Notify};
+use tokio::time::sleep;
+use tokio_rustls::rustls::crypto::ring::sign::any_supported_type;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_util::sync::CancellationToken;
+use x509_parser::extensions::GeneralName;
+
+use crate::acme::{AcmeManager, ACME_TLS_ALPN_PROTOCOL};
+use crate::server::ServerEvent;
+
+static SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub(crate) fn parse_key(key: &[u8]) -> Result<PrivateKeyDer<'static>, Error> {
+    let Some((key_item, _)) =
+        read_one_from_slice(key).map_err(|err| anyhow!("can't resolve key: {:?}", err))?
+    else {
+        bail!("invalid key data")
+    };
+
+    Ok(match key_item {
+        Item::Pkcs1Key(key) => PrivateKeyDer::Pkcs1(key),
+        Item::Pkcs8Key(key) => PrivateKeyDer::Pkcs8(key),
+        Item::Sec1Key(key) => PrivateKeyDer::Sec1(key),
+        _ => bail!("invalid key data"),
+    })
+}
+
+pub(crate) fn parse_cert_chain(cert: &[u8]) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let mut cert_chain = vec![];
+    let mut cert_slice = cert;
+    loop {
+        let Some((Item::X509Certificate(cert), remain_cert_slice)) =
+            read_one_from_slice(cert_slice)
+                .map_err(|err| anyhow!("can't resolve cert: {:?}", err))?
+        else {
+            bail!("invalid cert data")
+        };
+
+        cert_chain.push(cert);
+
+        if remain_cert_slice.is_empty() {
+            break;
+        }
+
+        cert_slice = remain_cert_slice;
+    }
+
+    Ok(cert_chain)
+}
+
+/// Returns the time the leaf certificate of the chain expires at.
+pub(crate) fn get_not_after(cert_chain: &[CertificateDer<'_>]) -> Result<SystemTime, Error> {
+    let leaf = cert_chain.first().context("empty cert chain")?;
+    let (_, cert) = x509_parser::parse_x509_certificate(leaf)
+        .map_err(|err| anyhow!("can't parse cert: {err}"))?;
+
+    Ok(UNIX_EPOCH + Duration::from_secs(cert.validity().not_after.timestamp().max(0) as u64))
+}
+
+/// Returns the DNS names in the subject alternative names of the leaf
+/// certificate of the chain.
+pub(crate) fn get_dns_names(cert_chain: &[CertificateDer<'_>]) -> Result<Vec<String>, Error> {
+    let leaf = cert_chain.first().context("empty cert chain")?;
+    let (_, cert) = x509_parser::parse_x509_certificate(leaf)
+        .map_err(|err| anyhow!("can't parse cert: {err}"))?;
+
+    let Some(san) = cert
+        .subject_alternative_name()
+        .map_err(|err| anyhow!("can't parse cert: {err}"))?
+    else {
+        return Ok(vec![]);
+    };
+
+    Ok(san
+        .value
+        .general_names
+        .iter()
+        .filter_map(|it| match it {
+            GeneralName::DNSName(name) => Some(name.to_string()),
+            _ => None,
+        })
+        .collect())
+}
+
+pub(crate) fn create_certified_key(
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: &PrivateKeyDer<'static>,
+    ocsp: Option<Vec<u8>>,
+) -> Result<CertifiedKey, Error> {
+    let signing_key = any_supported_type(key).map_err(|err| anyhow!("can't use key: {err}"))?;
+    let mut certified_key = CertifiedKey::new(cert_chain, signing_key);
+
+    certified_key.ocsp = ocsp;
+
+    Ok(certified_key)
+}
+
+/// Serves whatever certificate was loaded last, so that a renewed certificate
+/// can be swapped in without restarting the listener.
+///
+/// Handshakes of TLS-ALPN-01 challenges are served the certificate of the
+/// challenge for the requested domain instead.
+#[derive(Debug, Default)]
+pub(crate) struct CertResolver {
+    current: RwLock<Option<Arc<CertifiedKey>>>,
+    challenges: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl CertResolver {
+    pub(crate) fn new(certified_key: Option<CertifiedKey>) -> Self {
+        Self {
+            current: RwLock::new(certified_key.map(Arc::new)),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn replace(&self, certified_key: CertifiedKey) {
+        *self.current.write().unwrap() = Some(Arc::new(certified_key));
+    }
+
+    pub(crate) fn add_challenge(&self, domain: String, certified_key: CertifiedKey) {
+        self.challenges
+            .write()
+            .unwrap()
+            .insert(domain, Arc::new(certified_key));
+    }
+
+    pub(crate) fn remove_challenge(&self, domain: &str) {
+        self.challenges.write().unwrap().remove(domain);
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let is_acme_challenge = client_hello
+            .alpn()
+            .map_or(false, |mut it| it.any(|it| it == ACME_TLS_ALPN_PROTOCOL));
+
+        if is_acme_challenge {
+            let domain = client_hello.server_name()?;
+
+            return self.challenges.read().unwrap().get(domain).cloned();
+        }
+
+        self.current.read().unwrap().clone()
+    }
+}
+
+/// The files the certificate of the TLS listener was loaded from.
+#[derive(Debug, Clone)]
+pub(crate) struct CertFiles {
+    pub key: PathBuf,
+    pub cert: PathBuf,
+    pub ocsp: Option<PathBuf>,
+}
+
+impl CertFiles {
+    async fn read(&self) -> Result<(Vec<u8>, Vec<u8>, Option<Vec<u8>>), Error> {
+        let key = tokio::fs::read(&self.key)
+            .await
+            .with_context(|| format!("can't read key file: {}", self.key.display()))?;
+        let cert = tokio::fs::read(&self.cert)
+            .await
+            .with_context(|| format!("can't read cert file: {}", self.cert.display()))?;
+        let ocsp = match self.ocsp.as_ref() {
+            Some(path) => Some(
+                tokio::fs::read(path)
+                    .await
+                    .with_context(|| format!("can't read OCSP response: {}", path.display()))?,
+            ),
+
+            None => None,
+        };
+
+        Ok((key, cert, ocsp))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CertMonitorOptions {
+    pub check_interval: Duration,
+    pub expiry_warn_days: u64,
+}
+
+/// Publishes the statistics of the certificate of the TLS listener, and warns
+/// if it is about to expire.
+pub(crate) fn report_cert_expiry(
+    not_after: SystemTime,
+    reloads: u64,
+    has_ocsp_response: bool,
+    opts: &CertMonitorOptions,
+    event_tx: Option<&mpsc::UnboundedSender<ServerEvent>>,
+) {
+    let days_left = not_after
+        .duration_since(SystemTime::now())
+        .map_or(0, |it| it.as_secs() / SECS_PER_DAY);
+
+    set_server_cert_statistics(ServerCertStatistics {
+        not_after_ms: not_after
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |it| it.as_millis() as u64),
+        days_left,
+        reloads,
+        has_ocsp_response,
+    });
+
+    if days_left < opts.expiry_warn_days {
+        warn!("TLS certificate expires in {} day(s)", days_left);
+
+        if let Some(tx) = event_tx {
+            let _ = tx.send(ServerEvent::TlsCertificateExpiring {
+                not_after,
+                days_left,
+            });
+        }
+    }
+}
+
+/// Periodically checks the expiry of the certificate of the TLS listener, and
+/// reloads it once its files change. The files are also checked right away
+/// whenever `reload` is notified.
+pub(crate) struct CertMonitor {
+    pub resolver: Arc<CertResolver>,
+    pub files: Option<CertFiles>,
+    pub not_after: SystemTime,
+    pub has_ocsp_response: bool,
+}
+
+impl CertMonitor {
+    pub(crate) async fn run(
+        mut self,
+        opts: CertMonitorOptions,
+        event_tx: Option<mpsc::UnboundedSender<ServerEvent>>,
+        reload: Arc<Notify>,
+        cancel: CancellationToken,
+    ) {
+        let mut reloads = 0;
+        let mut last_contents = match self.files.as_ref() {
+            Some(files) => files.read().await.ok(),
+            None => None,
+        };
+
+        loop {
+            report_cert_expiry(
+                self.not_after,
+                reloads,
+                self.has_ocsp_response,
+                &opts,
+                event_tx.as_ref(),
+            );
+
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = sleep(opts.check_interval) => {}
+                _ = reload.notified() => {}
+            }
+
+            let Some(files) = self.files.as_ref() else {
+                continue;
+            };
+
+            let contents = match files.read().await {
+                Ok(it) => it,
+                Err(err) => {
+                    error!("can't reload TLS certificate: {err:#}");
+                    continue;
+                }
+            };
+
+            if last_contents.as_ref() == Some(&contents) {
+                continue;
+            }
+
+            let (key, cert, ocsp) = &contents;
+            let result = parse_key(key).and_then(|key| {
+                let cert_chain = parse_cert_chain(cert)?;
+                let not_after = get_not_after(&cert_chain)?;
+
+                Ok((
+                    create_certified_key(cert_chain, &key, ocsp.clone())?,
+                    not_after,
+                ))
+            });
+
+            match result {
+                Ok((certified_key, not_after)) => {
+                    self.resolver.replace(certified_key);
+                    self.not_after = not_after;
+                    self.has_ocsp_response = ocsp.is_some();
+                    last_contents = Some(contents);
+                    reloads += 1;
+
+                    info!("TLS certificate reloaded");
+
+                    if let Some(tx) = event_tx.as_ref() {
+                        let _ = tx.send(ServerEvent::TlsCertificateReloaded);
+                    }
+                }
+
+                Err(err) => error!("can't reload TLS certificate: {err:#}"),
+            }
+        }
+    }
+}
+
+/// The background task that keeps the certificate of the TLS listener fresh.
+pub(crate) enum CertTask {
+    Monitor(CertMonitor),
+    Acme(AcmeManager),
+}
+
+impl CertTask {
+    pub(crate) async fn run(
+        self,
+        opts: CertMonitorOptions,
+        event_tx: Option<mpsc::UnboundedSender<ServerEvent>>,
+        reload: Arc<Notify>,
+        cancel: CancellationToken,
+    ) {
+        match self {
+            Self::Monitor(it) => it.run(opts, event_tx, reload, cancel).await,
+            // NOTE: Certificates obtained via ACME are renewed regardless.
+            Self::Acme(it) => it.run(opts, event_tx, cancel).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{create_certified_key, get_dns_names, get_not_after, parse_cert_chain, parse_key};
+
+    #[test]
+    fn test_load_cert() {
+        let key = parse_key(include_bytes!("../tests/fixture/tls/localhost-key.pem")).unwrap();
+        let cert_chain =
+            parse_cert_chain(include_bytes!("../tests/fixture/tls/localhost.pem")).unwrap();
+
+        assert_eq!(
+            get_not_after(&cert_chain).unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1779863350)
+        );
+        assert!(get_dns_names(&cert_chain)
+            .unwrap()
+            .contains(&"localhost".to_string()));
+
+        let certified_key = create_certified_key(cert_chain, &key, Some(vec![0x30])).unwrap();
+
+        assert_eq!(certified_key.ocsp, Some(vec![0x30]));
+    }
+}
@@ -1,4 +1,7 @@
+use event_worker::backlog::EVENTS_BACKLOG;
 use event_worker::events::{EventMetadata, WorkerEventWithMetadata, WorkerEvents};
+use event_worker::history::LIFECYCLE_HISTORY;
+use event_worker::routing::EventRoute;
 use tokio::sync::mpsc;
 
 pub mod units;
@@ -8,7 +11,21 @@ pub fn send_event_if_event_worker_available(
     event: WorkerEvents,
     metadata: EventMetadata,
 ) {
+    LIFECYCLE_HISTORY.record(&event, &metadata);
+
+    let route = EventRoute::of(&event);
+    let event = WorkerEventWithMetadata { event, metadata };
+
+    #[cfg(feature = "grpc")]
+    crate::rt_worker::control_plane::tap_event(&event);
+
+    crate::event_webhook::tap_event(&event);
+
     if let Some(event_worker) = maybe_event_worker {
-        let _ = event_worker.send(WorkerEventWithMetadata { event, metadata });
+        EVENTS_BACKLOG.enter(route);
+
+        if event_worker.send(event).is_err() {
+            EVENTS_BACKLOG.leave(route);
+        }
     }
 }
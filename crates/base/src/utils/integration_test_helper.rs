@@ -235,13 +235,17 @@ impl TestBedBuilder {
             maybe_entrypoint: None,
             maybe_decorator: None,
             maybe_module_code: None,
+            maybe_module_map: None,
             conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
                 worker_pool_tx,
                 shared_metric_src: None,
                 event_worker_metric_src: None,
+                limits: Default::default(),
+                liveness: None,
             }),
             static_patterns: vec![],
             maybe_jsx_import_source_config: None,
+            locale: Default::default(),
         };
 
         let main_termination_token = TerminationToken::new();
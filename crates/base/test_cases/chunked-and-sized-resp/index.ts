@@ -0,0 +1,23 @@
+Deno.serve((req) => {
+    const encoder = new TextEncoder();
+    const headers = {
+        "Content-Type": "text/plain",
+        "Trailer": "Server-Timing",
+    };
+
+    if (new URL(req.url).searchParams.has("sized")) {
+        return new Response("meowmeow", { headers });
+    }
+
+    const stream = new ReadableStream({
+        start(controller) {
+            for (const char of ["m", "e", "o", "w", "m", "e", "o", "w"]) {
+                controller.enqueue(encoder.encode(char));
+            }
+
+            controller.close();
+        },
+    });
+
+    return new Response(stream, { headers });
+});
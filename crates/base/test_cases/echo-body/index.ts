@@ -0,0 +1,10 @@
+Deno.serve(async (req: Request) => {
+    const body = new Uint8Array(await req.arrayBuffer());
+    let checksum = 0;
+
+    for (const byte of body) {
+        checksum = (checksum * 31 + byte) >>> 0;
+    }
+
+    return Response.json({ size: body.byteLength, checksum });
+});
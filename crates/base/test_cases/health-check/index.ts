@@ -0,0 +1,8 @@
+Deno.serve((req: Request) => {
+  const { pathname } = new URL(req.url);
+  const isCheck = req.headers.get("x-edge-runtime-health-check") === "1";
+
+  return new Response(null, {
+    status: isCheck && pathname === "/__health" ? 204 : 503,
+  });
+});
@@ -0,0 +1,8 @@
+Deno.serve(async (req) => {
+    return Response.json({
+        path: new URL(req.url).pathname,
+        body: await req.text(),
+        chain: JSON.parse(req.headers.get("x-edge-runtime-invoke-chain") ?? "[]"),
+        hostPath: Deno.env.get("PATH") ?? null,
+    });
+});
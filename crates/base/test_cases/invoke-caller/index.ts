@@ -0,0 +1,17 @@
+Deno.serve(async (req) => {
+    const target = new URL(req.url).searchParams.get("target") ?? "invoke-callee";
+
+    try {
+        const res = await EdgeRuntime.invoke(target, "/echo", {
+            method: "POST",
+            body: "meow",
+        });
+
+        return new Response(res.body, {
+            status: res.status,
+            headers: res.headers,
+        });
+    } catch (err) {
+        return new Response(err.name, { status: 508 });
+    }
+});
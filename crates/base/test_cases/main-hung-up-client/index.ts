@@ -0,0 +1,12 @@
+let aborted = 0;
+
+Deno.serve(async (req: Request) => {
+  if (new URL(req.url).pathname === "/aborted") {
+    return new Response(String(aborted));
+  }
+
+  await new Promise((resolve) => req.signal.addEventListener("abort", resolve));
+  aborted++;
+
+  return new Response(null);
+});
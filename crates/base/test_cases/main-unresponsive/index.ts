@@ -0,0 +1,8 @@
+Deno.serve((req: Request) => {
+    if (new URL(req.url).pathname === "/block") {
+        while (true) {
+        }
+    }
+
+    return new Response("alive");
+});
@@ -0,0 +1,38 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+  const url = new URL(req.url);
+  const service_name = url.pathname.split("/")[1];
+  const servicePath = `./test_cases/${service_name}`;
+
+  // NOTE: The runtime takes the limits header off the requests of the
+  // clients, so it is handed on here the way a main worker may pass on the
+  // headers of an upstream proxy.
+  const headers = new Headers(req.headers);
+  const upstreamLimits = req.headers.get("x-upstream-limits");
+
+  if (upstreamLimits !== null) {
+    headers.set("x-edge-runtime-limits", upstreamLimits);
+  }
+
+  try {
+    const worker = await EdgeRuntime.userWorkers.create({
+      servicePath,
+      memoryLimitMb: 150,
+      workerTimeoutMs: 10 * 60 * 1000,
+      cpuTimeSoftLimitMs: 10 * 60 * 1000,
+      cpuTimeHardLimitMs: 10 * 60 * 1000,
+      noModuleCache: false,
+      importMapPath: null,
+      envVars: [],
+    });
+
+    return await worker.fetch(new Request(req, { headers }), {
+      limits: { requestIdleTimeoutMs: 1000 },
+    });
+  } catch (e) {
+    console.error(e);
+
+    return Response.json({ msg: e.toString() }, { status: 500 });
+  }
+});
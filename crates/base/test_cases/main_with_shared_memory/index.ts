@@ -0,0 +1,30 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+  const url = new URL(req.url);
+  const service_name = url.pathname.split("/")[1];
+  const servicePath = `./test_cases/${service_name}`;
+
+  // NOTE: The options left out keep their defaults.
+  const sharedMemoryOpts = await req.clone().json();
+
+  try {
+    const worker = await EdgeRuntime.userWorkers.create({
+      servicePath,
+      memoryLimitMb: 150,
+      workerTimeoutMs: 10 * 60 * 1000,
+      cpuTimeSoftLimitMs: 10 * 60 * 1000,
+      cpuTimeHardLimitMs: 10 * 60 * 1000,
+      noModuleCache: false,
+      importMapPath: null,
+      envVars: [],
+      ...sharedMemoryOpts,
+    });
+
+    return await worker.fetch(req);
+  } catch (e) {
+    console.error(e);
+
+    return Response.json({ msg: e.toString() }, { status: 500 });
+  }
+});
@@ -0,0 +1,15 @@
+const { requestContext } = EdgeRuntime;
+const outside = requestContext.getStore();
+
+Deno.serve(async () => {
+    const before = requestContext.getStore();
+
+    await new Promise((resolve) => setTimeout(resolve, 10));
+
+    return Response.json({
+        outside: outside ?? null,
+        before,
+        after: requestContext.getStore(),
+        sameAfterTimer: before === requestContext.getStore(),
+    });
+});
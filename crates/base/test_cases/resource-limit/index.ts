@@ -0,0 +1,18 @@
+Deno.serve(async (req: Request) => {
+    const { port, count } = await req.json();
+    const conns: Deno.Conn[] = [];
+
+    try {
+        for (let i = 0; i < count; i++) {
+            conns.push(await Deno.connect({ hostname: "127.0.0.1", port }));
+        }
+
+        return Response.json({ opened: conns.length });
+    } catch (e) {
+        return Response.json({ opened: conns.length, error: e.name });
+    } finally {
+        for (const conn of conns) {
+            conn.close();
+        }
+    }
+});
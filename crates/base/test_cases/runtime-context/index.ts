@@ -0,0 +1,3 @@
+Deno.serve(() => {
+    return Response.json(EdgeRuntime.context);
+});
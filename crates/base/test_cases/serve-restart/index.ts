@@ -0,0 +1,17 @@
+let generation = 0;
+
+function start() {
+    const current = ++generation;
+    const controller = new AbortController();
+
+    Deno.serve({ signal: controller.signal }, (req: Request) => {
+        if (new URL(req.url).pathname === "/restart") {
+            controller.abort();
+            start();
+        }
+
+        return new Response(`server ${current}`);
+    });
+}
+
+start();
@@ -0,0 +1,33 @@
+function createSharedWasmMemory() {
+    try {
+        return new WebAssembly.Memory({ initial: 1, maximum: 1, shared: true });
+    } catch {
+        return null;
+    }
+}
+
+function tryAtomicsWait(buffer: ArrayBufferLike | undefined) {
+    if (!buffer) {
+        return null;
+    }
+
+    try {
+        // NOTE: The value differs, so this returns right away when allowed.
+        return Atomics.wait(new Int32Array(buffer), 0, 1, 0);
+    } catch (e) {
+        return e.toString();
+    }
+}
+
+Deno.serve(() => {
+    const memory = createSharedWasmMemory();
+    const buffer = typeof SharedArrayBuffer === "function"
+        ? new SharedArrayBuffer(4)
+        : memory?.buffer;
+
+    return Response.json({
+        sharedArrayBuffer: typeof SharedArrayBuffer === "function",
+        wasmThreads: memory !== null,
+        atomicsWait: tryAtomicsWait(buffer),
+    });
+});
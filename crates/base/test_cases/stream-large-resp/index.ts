@@ -0,0 +1,30 @@
+// Streams `CHUNKS` chunks of 1 MiB, each of them being generated only once
+// the previous one has been pulled. A chunk starts with the time it was
+// generated at, in milliseconds since the unix epoch.
+const CHUNK_SIZE = 1024 * 1024;
+const CHUNKS = 64;
+
+const encoder = new TextEncoder();
+
+Deno.serve(() => {
+    let sent = 0;
+
+    const body = new ReadableStream({
+        pull(controller) {
+            if (sent === CHUNKS) {
+                controller.close();
+                return;
+            }
+
+            const chunk = new Uint8Array(CHUNK_SIZE);
+
+            encoder.encodeInto(String(Date.now()).padStart(16, "0"), chunk);
+            controller.enqueue(chunk);
+            sent++;
+        },
+    }, { highWaterMark: 0 });
+
+    return new Response(body, {
+        headers: { "Content-Type": "application/octet-stream" },
+    });
+});
@@ -15,18 +15,23 @@ use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Context;
 use async_tungstenite::WebSocketStream;
 use base::{
     integration_test, integration_test_listen_fut, integration_test_with_server_flag,
-    rt_worker::worker_ctx::{create_user_worker_pool, create_worker, TerminationToken},
+    repl::ReplSession,
+    rt_worker::worker_ctx::{
+        create_main_worker, create_user_worker_pool, create_worker, TerminationToken,
+    },
+    rt_worker::worker_pool::{SupervisorPolicy, WorkerPoolPolicy},
     server::{ServerEvent, ServerFlags, ServerHealth, Tls},
     DecoratorType,
 };
 use deno_core::serde_json;
+use event_worker::events::WorkerEvents;
 use futures_util::{future::BoxFuture, Future, FutureExt, SinkExt, StreamExt};
 use http::{Method, Request, Response as HttpResponse, StatusCode};
 use http_utils::utils::get_upgrade_type;
@@ -39,7 +44,8 @@ use reqwest::{
 use reqwest::{Certificate, Client, RequestBuilder};
 use sb_core::SharedMetricSource;
 use sb_workers::context::{
-    MainWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRequestMsg, WorkerRuntimeOpts,
+    HealthCheck, MainWorkerLimits, MainWorkerRuntimeOpts, UserWorkerMsgs, UserWorkerRuntimeOpts,
+    WorkerContextInitOpts, WorkerRequestMsg, WorkerRuntimeOpts,
 };
 use serde::Deserialize;
 use serial_test::serial;
@@ -203,13 +209,17 @@ async fn test_not_trigger_pku_sigsegv_due_to_jit_compilation_non_cli() {
         maybe_entrypoint: None,
         maybe_decorator: None,
         maybe_module_code: None,
+        maybe_module_map: None,
         conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
             worker_pool_tx,
             shared_metric_src: None,
             event_worker_metric_src: None,
+            limits: Default::default(),
+            liveness: None,
         }),
         static_patterns: vec![],
         maybe_jsx_import_source_config: None,
+        locale: Default::default(),
     };
 
     let ctx = create_worker((opts, main_termination_token.clone()), None, None)
@@ -361,13 +371,17 @@ async fn test_main_worker_boot_error() {
         maybe_entrypoint: None,
         maybe_decorator: None,
         maybe_module_code: None,
+        maybe_module_map: None,
         conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
             worker_pool_tx,
             shared_metric_src: None,
             event_worker_metric_src: None,
+            limits: Default::default(),
+            liveness: None,
         }),
         static_patterns: vec![],
         maybe_jsx_import_source_config: None,
+        locale: Default::default(),
     };
 
     let result = create_worker((opts, main_termination_token.clone()), None, None).await;
@@ -486,13 +500,17 @@ async fn test_main_worker_user_worker_mod_evaluate_exception() {
         maybe_entrypoint: None,
         maybe_decorator: None,
         maybe_module_code: None,
+        maybe_module_map: None,
         conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
             worker_pool_tx,
             shared_metric_src: None,
             event_worker_metric_src: None,
+            limits: Default::default(),
+            liveness: None,
         }),
         static_patterns: vec![],
         maybe_jsx_import_source_config: None,
+        locale: Default::default(),
     };
 
     let ctx = create_worker((opts, main_termination_token.clone()), None, None)
@@ -578,6 +596,65 @@ async fn test_main_worker_post_request_with_transfer_encoding_secure() {
     test_main_worker_post_request_with_transfer_encoding(new_localhost_tls(true)).await;
 }
 
+async fn test_main_worker_forward_request_body(chunked: bool) {
+    let body = (0..4 * MB).map(|it| (it % 251) as u8).collect::<Vec<_>>();
+    let checksum = body.iter().fold(0u32, |acc, it| {
+        acc.wrapping_mul(31).wrapping_add(*it as u32)
+    });
+
+    let client = Client::new();
+    let req = client
+        .request(
+            Method::POST,
+            format!("http://localhost:{}/echo-body", NON_SECURE_PORT),
+        )
+        .body(if chunked {
+            reqwest::Body::wrap_stream(futures_util::stream::iter(
+                body.chunks(64 * 1024)
+                    .map(|it| Ok::<_, io::Error>(it.to_vec()))
+                    .collect::<Vec<_>>(),
+            ))
+        } else {
+            reqwest::Body::from(body)
+        })
+        .build()
+        .unwrap();
+
+    let request_builder = Some(RequestBuilder::from_parts(client, req));
+
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        request_builder,
+        None,
+        (|resp| async move {
+            let res = resp.unwrap();
+            assert_eq!(res.status().as_u16(), 200);
+
+            let result = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(result["size"], 4 * MB);
+            assert_eq!(result["checksum"], checksum);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_main_worker_forward_request_body_sized() {
+    test_main_worker_forward_request_body(false).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_main_worker_forward_request_body_chunked() {
+    test_main_worker_forward_request_body(true).await;
+}
+
 #[tokio::test]
 #[serial]
 async fn test_null_body_with_204_status() {
@@ -868,9 +945,11 @@ async fn test_worker_boot_invalid_imports() {
         maybe_entrypoint: None,
         maybe_decorator: None,
         maybe_module_code: None,
+        maybe_module_map: None,
         conf: WorkerRuntimeOpts::UserWorker(test_user_runtime_opts()),
         static_patterns: vec![],
         maybe_jsx_import_source_config: None,
+        locale: Default::default(),
     };
 
     let result = create_test_user_worker(opts).await;
@@ -895,9 +974,11 @@ async fn test_worker_boot_with_0_byte_eszip() {
         maybe_entrypoint: Some("file:///src/index.ts".to_string()),
         maybe_decorator: None,
         maybe_module_code: None,
+        maybe_module_map: None,
         conf: WorkerRuntimeOpts::UserWorker(test_user_runtime_opts()),
         static_patterns: vec![],
         maybe_jsx_import_source_config: None,
+        locale: Default::default(),
     };
 
     let result = create_test_user_worker(opts).await;
@@ -920,9 +1001,11 @@ async fn test_worker_boot_with_invalid_entrypoint() {
         maybe_entrypoint: Some("file:///meow/mmmmeeeow.ts".to_string()),
         maybe_decorator: None,
         maybe_module_code: None,
+        maybe_module_map: None,
         conf: WorkerRuntimeOpts::UserWorker(test_user_runtime_opts()),
         static_patterns: vec![],
         maybe_jsx_import_source_config: None,
+        locale: Default::default(),
     };
 
     let result = create_test_user_worker(opts).await;
@@ -933,6 +1016,515 @@ async fn test_worker_boot_with_invalid_entrypoint() {
     );
 }
 
+#[tokio::test]
+#[serial]
+async fn test_retiring_worker_drains_in_flight_requests() {
+    let pool_termination_token = TerminationToken::new();
+    let (_, worker_pool_tx) = create_user_worker_pool(
+        WorkerPoolPolicy::new(
+            SupervisorPolicy::PerWorker,
+            1,
+            ServerFlags {
+                worker_drain_timeout_ms: Some(10 * 1000),
+                ..Default::default()
+            },
+        ),
+        None,
+        Some(pool_termination_token.clone()),
+        vec![],
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let opts = WorkerContextInitOpts {
+        service_path: "./test_cases/sleep-5000ms".into(),
+        no_module_cache: false,
+        import_map_path: None,
+        env_vars: HashMap::new(),
+        timing: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_decorator: None,
+        maybe_module_code: None,
+        maybe_module_map: None,
+        conf: WorkerRuntimeOpts::UserWorker(test_user_runtime_opts()),
+        static_patterns: vec![],
+        maybe_jsx_import_source_config: None,
+        locale: Default::default(),
+    };
+
+    let (create_tx, create_rx) = oneshot::channel();
+
+    worker_pool_tx
+        .send(UserWorkerMsgs::Create(opts, create_tx))
+        .unwrap();
+
+    let key = create_rx.await.unwrap().unwrap().key;
+    let conn_token = CancellationToken::new();
+    let (res_tx, res_rx) = oneshot::channel();
+    let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+
+    worker_pool_tx
+        .send(UserWorkerMsgs::SendRequest(
+            key,
+            req,
+            res_tx,
+            Some(conn_token.clone()),
+        ))
+        .unwrap();
+
+    // Let the worker take the request over before retiring it.
+    sleep(Duration::from_secs(1)).await;
+
+    let (terminate_tx, terminate_rx) = oneshot::channel();
+
+    worker_pool_tx
+        .send(UserWorkerMsgs::Terminate(key, terminate_tx))
+        .unwrap();
+
+    assert!(terminate_rx.await.unwrap());
+
+    let (res, _) = res_rx.await.unwrap().unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(to_bytes(res.into_body()).await.unwrap(), "meow");
+
+    conn_token.cancel();
+    pool_termination_token.cancel_and_wait().await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_health_check() {
+    let pool_termination_token = TerminationToken::new();
+    let (_, worker_pool_tx) = create_user_worker_pool(
+        WorkerPoolPolicy::new(SupervisorPolicy::PerWorker, 1, ServerFlags::default()),
+        None,
+        Some(pool_termination_token.clone()),
+        vec![],
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let opts = WorkerContextInitOpts {
+        service_path: "./test_cases/health-check".into(),
+        no_module_cache: false,
+        import_map_path: None,
+        env_vars: HashMap::new(),
+        timing: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_decorator: None,
+        maybe_module_code: None,
+        maybe_module_map: None,
+        conf: WorkerRuntimeOpts::UserWorker(test_user_runtime_opts()),
+        static_patterns: vec![],
+        maybe_jsx_import_source_config: None,
+        locale: Default::default(),
+    };
+
+    let (create_tx, create_rx) = oneshot::channel();
+
+    worker_pool_tx
+        .send(UserWorkerMsgs::Create(opts, create_tx))
+        .unwrap();
+
+    let key = create_rx.await.unwrap().unwrap().key;
+    let check = |key, check| {
+        let (tx, rx) = oneshot::channel();
+
+        worker_pool_tx
+            .send(UserWorkerMsgs::HealthCheck(key, check, tx))
+            .unwrap();
+
+        rx
+    };
+
+    let report = check(key, HealthCheck::default()).await.unwrap().unwrap();
+
+    assert_eq!(report.status, Some(204));
+    assert!(report.is_healthy());
+
+    let report = check(
+        key,
+        HealthCheck {
+            path: "/broken".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(report.status, Some(503));
+    assert!(!report.is_healthy());
+
+    // NOTE: There is nothing to check for a worker that is not in the pool.
+    assert!(check(uuid::Uuid::new_v4(), HealthCheck::default())
+        .await
+        .unwrap()
+        .is_none());
+
+    pool_termination_token.cancel_and_wait().await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_main_worker_restarts_when_unresponsive() {
+    let pool_termination_token = TerminationToken::new();
+    let main_termination_token = TerminationToken::new();
+
+    let (_, worker_pool_tx) = create_user_worker_pool(
+        test_user_worker_pool_policy(),
+        None,
+        Some(pool_termination_token.clone()),
+        vec![],
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let main_worker_tx = create_main_worker(
+        "./test_cases/main-unresponsive".into(),
+        None,
+        false,
+        MainWorkerRuntimeOpts {
+            worker_pool_tx,
+            shared_metric_src: None,
+            event_worker_metric_src: None,
+            limits: MainWorkerLimits {
+                unresponsive_timeout_ms: 1000,
+                ..Default::default()
+            },
+            liveness: None,
+        },
+        None,
+        None,
+        Some(main_termination_token.clone()),
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let send = |path: &str| {
+        let (res_tx, res_rx) = oneshot::channel();
+        let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+
+        main_worker_tx
+            .send(WorkerRequestMsg {
+                req,
+                res_tx,
+                conn_token: None,
+            })
+            .unwrap();
+
+        res_rx
+    };
+
+    // The request that wedged the worker fails once it is replaced.
+    let blocked = timeout(Duration::from_secs(10), send("/block"))
+        .await
+        .unwrap();
+
+    assert!(blocked.map_or(true, |it| it.is_err()));
+
+    let res = timeout(Duration::from_secs(10), send("/"))
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(to_bytes(res.into_body()).await.unwrap(), "alive");
+
+    main_termination_token.cancel_and_wait().await;
+    pool_termination_token.cancel_and_wait().await;
+}
+
+/// Sends a request to a user worker created for the service with the given
+/// options, and returns the status and the body of its response.
+async fn send_test_user_worker_request(
+    service_path: &str,
+    conf: UserWorkerRuntimeOpts,
+    req: Request<Body>,
+) -> (StatusCode, hyper::body::Bytes) {
+    let opts = WorkerContextInitOpts {
+        service_path: service_path.into(),
+        no_module_cache: false,
+        import_map_path: None,
+        env_vars: HashMap::new(),
+        timing: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_decorator: None,
+        maybe_module_code: None,
+        maybe_module_map: None,
+        conf: WorkerRuntimeOpts::UserWorker(conf),
+        static_patterns: vec![],
+        maybe_jsx_import_source_config: None,
+        locale: Default::default(),
+    };
+
+    let (msg_tx, scope) = create_test_user_worker(opts).await.unwrap();
+    let (res_tx, res_rx) = oneshot::channel::<Result<HttpResponse<Body>, hyper::Error>>();
+
+    msg_tx
+        .send(WorkerRequestMsg {
+            req,
+            res_tx,
+            conn_token: Some(scope.conn_token()),
+        })
+        .unwrap();
+
+    let req_scope = scope.start_request().await;
+    let res = res_rx.await.unwrap().unwrap();
+    let status = res.status();
+    let body = to_bytes(res.into_body()).await.unwrap();
+
+    req_scope.await;
+
+    (status, body)
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_serve_again_after_shutdown() {
+    let opts = WorkerContextInitOpts {
+        service_path: "./test_cases/serve-restart".into(),
+        no_module_cache: false,
+        import_map_path: None,
+        env_vars: HashMap::new(),
+        timing: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_decorator: None,
+        maybe_module_code: None,
+        maybe_module_map: None,
+        conf: WorkerRuntimeOpts::UserWorker(test_user_runtime_opts()),
+        static_patterns: vec![],
+        maybe_jsx_import_source_config: None,
+        locale: Default::default(),
+    };
+
+    let (msg_tx, scope) = create_test_user_worker(opts).await.unwrap();
+
+    // The first server shuts down while handling the request and the worker
+    // starts a new one, which takes the following requests.
+    for (path, expected) in [
+        ("/restart", "server 1"),
+        ("/", "server 2"),
+        ("/", "server 2"),
+    ] {
+        let (res_tx, res_rx) = oneshot::channel::<Result<HttpResponse<Body>, hyper::Error>>();
+        let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+
+        msg_tx
+            .send(WorkerRequestMsg {
+                req,
+                res_tx,
+                conn_token: Some(scope.conn_token()),
+            })
+            .unwrap();
+
+        let req_scope = scope.start_request().await;
+        let res = res_rx.await.unwrap().unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(to_bytes(res.into_body()).await.unwrap(), expected);
+
+        req_scope.await;
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_boot_event_breaks_down_cold_start() {
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+    let opts = WorkerContextInitOpts {
+        service_path: "./test_cases/slow_resp".into(),
+        no_module_cache: false,
+        import_map_path: None,
+        env_vars: HashMap::new(),
+        timing: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_decorator: None,
+        maybe_module_code: None,
+        maybe_module_map: None,
+        conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+            events_msg_tx: Some(events_tx),
+            ..test_user_runtime_opts()
+        }),
+        static_patterns: vec![],
+        maybe_jsx_import_source_config: None,
+        locale: Default::default(),
+    };
+
+    let (_msg_tx, _scope) = create_test_user_worker(opts).await.unwrap();
+
+    let boot = loop {
+        let event = timeout(Duration::from_secs(10), events_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+
+        if let WorkerEvents::Boot(boot) = event.event {
+            break boot;
+        }
+    };
+
+    let stages = boot.stages.unwrap();
+
+    assert!(stages.isolate_creation_us > 0);
+    assert!(stages.bootstrap_us > 0);
+    assert!(stages.module_load_us > 0);
+    assert_eq!(stages.boot_queue_us, 0);
+    assert!(boot.first_byte_ready_ms.unwrap() >= boot.boot_time as u64);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_boots_are_queued() {
+    let pool_termination_token = TerminationToken::new();
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+    let (metric_src, worker_pool_tx) = create_user_worker_pool(
+        WorkerPoolPolicy::new(
+            SupervisorPolicy::oneshot(),
+            2,
+            ServerFlags {
+                request_wait_timeout_ms: Some(4 * 1000 * 3600),
+                max_concurrent_boots: Some(1),
+                ..Default::default()
+            },
+        ),
+        Some(events_tx.clone()),
+        Some(pool_termination_token.clone()),
+        vec![],
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let create = |service_path: &str| {
+        let (create_tx, create_rx) = oneshot::channel();
+        let opts = WorkerContextInitOpts {
+            service_path: service_path.into(),
+            no_module_cache: false,
+            import_map_path: None,
+            env_vars: HashMap::new(),
+            timing: None,
+            maybe_eszip: None,
+            maybe_entrypoint: None,
+            maybe_decorator: None,
+            maybe_module_code: None,
+            maybe_module_map: None,
+            conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+                events_msg_tx: Some(events_tx.clone()),
+                ..test_user_runtime_opts()
+            }),
+            static_patterns: vec![],
+            maybe_jsx_import_source_config: None,
+            locale: Default::default(),
+        };
+
+        worker_pool_tx
+            .send(UserWorkerMsgs::Create(opts, create_tx))
+            .unwrap();
+
+        create_rx
+    };
+
+    // NOTE: Both creations are sent at once, so the second boot has to wait
+    // for the first one to finish.
+    let first = create("./test_cases/slow_resp");
+    let second = create("./test_cases/serve-restart");
+
+    assert!(first.await.unwrap().is_ok());
+    assert!(second.await.unwrap().is_ok());
+    assert_eq!(metric_src.queued_user_worker_boots(), 0);
+
+    let mut boot_queue_us = vec![];
+
+    while boot_queue_us.len() < 2 {
+        let event = timeout(Duration::from_secs(10), events_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+
+        if let WorkerEvents::Boot(boot) = event.event {
+            boot_queue_us.push(boot.stages.unwrap().boot_queue_us);
+        }
+    }
+
+    boot_queue_us.sort();
+
+    assert!(boot_queue_us[1] > boot_queue_us[0]);
+    assert!(boot_queue_us[1] > 1000);
+
+    pool_termination_token.cancel_and_wait().await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_resource_limit() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let acceptor = tokio::spawn(async move {
+        let mut conns = vec![];
+
+        while let Ok((conn, _)) = listener.accept().await {
+            conns.push(conn);
+        }
+    });
+
+    let send = |max_resources: u64| {
+        let req = Request::builder()
+            .uri("/resource-limit")
+            .method("POST")
+            .body(Body::from(
+                serde_json::json!({ "port": port, "count": 16 }).to_string(),
+            ))
+            .unwrap();
+
+        send_test_user_worker_request(
+            "./test_cases/resource-limit",
+            UserWorkerRuntimeOpts {
+                max_resources,
+                allow_sockets: Some(vec!["127.0.0.1".to_string()]),
+                ..test_user_runtime_opts()
+            },
+            req,
+        )
+    };
+
+    let (status, body) = send(8).await;
+    let result = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result["error"], "ResourceLimitExceeded");
+    assert!(result["opened"].as_u64().unwrap() < 8);
+
+    let (status, body) = send(0).await;
+    let result = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result["opened"], 16);
+    assert!(result.get("error").is_none());
+
+    acceptor.abort();
+}
+
 #[tokio::test]
 #[serial]
 async fn req_failure_case_timeout() {
@@ -1529,52 +2121,241 @@ async fn test_decorators(ty: Option<DecoratorType>) {
         (|resp| async {
             let resp = resp.unwrap();
 
-            if is_disabled {
-                assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
-                assert!(resp
-                    .text()
-                    .await
-                    .unwrap()
-                    .starts_with("{\"msg\":\"InvalidWorkerCreation: worker boot error: Uncaught SyntaxError: Invalid or unexpected token"),);
-            } else {
-                assert_eq!(resp.status(), StatusCode::OK);
-                assert_eq!(resp.text().await.unwrap().as_str(), "meow?");
-            }
-        }),
-        TerminationToken::new()
-    );
-}
+            if is_disabled {
+                assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+                assert!(resp
+                    .text()
+                    .await
+                    .unwrap()
+                    .starts_with("{\"msg\":\"InvalidWorkerCreation: worker boot error: Uncaught SyntaxError: Invalid or unexpected token"),);
+            } else {
+                assert_eq!(resp.status(), StatusCode::OK);
+                assert_eq!(resp.text().await.unwrap().as_str(), "meow?");
+            }
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_decorator_should_be_syntax_error() {
+    test_decorators(None).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_decorator_parse_tc39() {
+    test_decorators(Some(DecoratorType::Tc39)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_decorator_parse_typescript_experimental_with_metadata() {
+    test_decorators(Some(DecoratorType::TypescriptWithMetadata)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn send_partial_payload_into_closed_pipe_should_not_be_affected_worker_stability() {
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_oneshot_policy(100000)
+        .build()
+        .await;
+
+    let mut resp1 = tb
+        .request(|| {
+            Request::builder()
+                .uri("/chunked-char-1000ms")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(resp1.status().as_u16(), StatusCode::OK);
+
+    let resp1_body = resp1.body_mut();
+    let resp1_chunk1 = resp1_body.next().await.unwrap().unwrap();
+
+    assert_eq!(resp1_chunk1, "m");
+
+    drop(resp1);
+    sleep(Duration::from_secs(1)).await;
+
+    // NOTE(Nyannyacha): Before dc057b0, the statement below panics with the
+    // reason `connection closed before message completed`. This is the result
+    // of `Deno.serve` failing to properly handle an exception from a previous
+    // request.
+    let resp2 = tb
+        .request(|| {
+            Request::builder()
+                .uri("/empty-response")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(resp2.status().as_u16(), StatusCode::NO_CONTENT);
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_main_worker_streams_user_worker_response_with_backpressure() {
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_oneshot_policy(100000)
+        .build()
+        .await;
+
+    let mut resp = tb
+        .request(|| {
+            Request::builder()
+                .uri("/stream-large-resp")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status().as_u16(), StatusCode::OK);
+
+    let body = resp.body_mut();
+    let mut buf = body.next().await.unwrap().unwrap().to_vec();
+    let stalled_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+
+    sleep(Duration::from_secs(1)).await;
+
+    while let Some(chunk) = body.next().await {
+        buf.extend_from_slice(&chunk.unwrap());
+    }
 
-#[tokio::test]
-#[serial]
-async fn test_decorator_should_be_syntax_error() {
-    test_decorators(None).await;
+    assert_eq!(buf.len(), 64 * MB);
+
+    // NOTE: Were the body buffered anywhere between the user worker and the
+    // client, the whole of it would have been generated while the client was
+    // not reading.
+    let last_chunk_generated_at = std::str::from_utf8(&buf[63 * MB..63 * MB + 16])
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+
+    assert!(last_chunk_generated_at >= stalled_at + 500);
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_decorator_parse_tc39() {
-    test_decorators(Some(DecoratorType::Tc39)).await;
+async fn test_main_worker_reframes_user_worker_response() {
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_oneshot_policy(100000)
+        .build()
+        .await;
+
+    for (uri, is_chunked) in [
+        ("/chunked-and-sized-resp", true),
+        ("/chunked-and-sized-resp?sized", false),
+    ] {
+        let mut resp = tb
+            .request(|| {
+                Request::builder()
+                    .uri(uri)
+                    .method("GET")
+                    .body(Body::empty())
+                    .context("can't make request")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status().as_u16(), StatusCode::OK);
+
+        let headers = resp.headers();
+
+        assert_eq!(
+            headers
+                .get("transfer-encoding")
+                .map(|it| it.to_str().unwrap()),
+            is_chunked.then_some("chunked"),
+        );
+        assert_eq!(
+            headers.get("content-length").map(|it| it.to_str().unwrap()),
+            (!is_chunked).then_some("8"),
+        );
+
+        // NOTE: The trailers can't be relayed, so the client must not be told
+        // to expect them.
+        assert!(!headers.contains_key("trailer"));
+        assert_eq!(to_bytes(resp.body_mut()).await.unwrap(), "meowmeow");
+    }
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_decorator_parse_typescript_experimental_with_metadata() {
-    test_decorators(Some(DecoratorType::TypescriptWithMetadata)).await;
+async fn test_server_timing() {
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_worker_pool_policy(WorkerPoolPolicy::new(
+            SupervisorPolicy::oneshot(),
+            1,
+            ServerFlags {
+                request_wait_timeout_ms: Some(100000),
+                server_timing: true,
+                ..Default::default()
+            },
+        ))
+        .build()
+        .await;
+
+    let resp = tb
+        .request(|| {
+            Request::builder()
+                .uri("/empty-response")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    let server_timing = resp
+        .headers()
+        .get("server-timing")
+        .map(|it| it.to_str().unwrap().to_string())
+        .unwrap();
+
+    // NOTE: Every request boots a worker of its own under the oneshot policy.
+    let metrics = server_timing
+        .split(", ")
+        .map(|it| it.split_once(";dur=").unwrap().0)
+        .collect::<Vec<_>>();
+
+    assert_eq!(metrics, ["boot", "queue", "exec"]);
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn send_partial_payload_into_closed_pipe_should_not_be_affected_worker_stability() {
+async fn test_user_worker_invokes_another_service() {
     let tb = TestBedBuilder::new("./test_cases/main")
         .with_oneshot_policy(100000)
         .build()
         .await;
 
-    let mut resp1 = tb
+    let mut resp = tb
         .request(|| {
             Request::builder()
-                .uri("/chunked-char-1000ms")
+                .uri("/invoke-caller")
                 .method("GET")
                 .body(Body::empty())
                 .context("can't make request")
@@ -1582,24 +2363,28 @@ async fn send_partial_payload_into_closed_pipe_should_not_be_affected_worker_sta
         .await
         .unwrap();
 
-    assert_eq!(resp1.status().as_u16(), StatusCode::OK);
+    assert_eq!(resp.status().as_u16(), StatusCode::OK);
 
-    let resp1_body = resp1.body_mut();
-    let resp1_chunk1 = resp1_body.next().await.unwrap().unwrap();
+    let body =
+        serde_json::from_slice::<serde_json::Value>(&to_bytes(resp.body_mut()).await.unwrap())
+            .unwrap();
 
-    assert_eq!(resp1_chunk1, "m");
+    assert_eq!(body["path"], "/echo");
+    assert_eq!(body["body"], "meow");
+    assert_eq!(
+        body["chain"],
+        serde_json::json!(["./test_cases/invoke-caller", "./test_cases/invoke-callee"])
+    );
 
-    drop(resp1);
-    sleep(Duration::from_secs(1)).await;
+    // NOTE: The caller got the env of the main worker, which the callee must
+    // not inherit since its service was never created with it.
+    assert_eq!(body["hostPath"], serde_json::Value::Null);
 
-    // NOTE(Nyannyacha): Before dc057b0, the statement below panics with the
-    // reason `connection closed before message completed`. This is the result
-    // of `Deno.serve` failing to properly handle an exception from a previous
-    // request.
-    let resp2 = tb
+    // NOTE: A service invoking itself is a loop.
+    let mut resp = tb
         .request(|| {
             Request::builder()
-                .uri("/empty-response")
+                .uri("/invoke-caller?target=invoke-caller")
                 .method("GET")
                 .body(Body::empty())
                 .context("can't make request")
@@ -1607,7 +2392,11 @@ async fn send_partial_payload_into_closed_pipe_should_not_be_affected_worker_sta
         .await
         .unwrap();
 
-    assert_eq!(resp2.status().as_u16(), StatusCode::NO_CONTENT);
+    assert_eq!(resp.status().as_u16(), 508);
+    assert_eq!(
+        to_bytes(resp.body_mut()).await.unwrap(),
+        "InvokeLoopDetected"
+    );
 
     tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
 }
@@ -1690,6 +2479,81 @@ where
     }
 }
 
+#[tokio::test]
+#[serial]
+async fn test_request_signal_aborts_once_client_hangs_up() {
+    let token = TerminationToken::new();
+    let (health_tx, mut health_rx) = mpsc::channel(1);
+
+    let mut listen_fut = integration_test_listen_fut!(
+        NON_SECURE_PORT,
+        None::<Tls>,
+        "./test_cases/main-hung-up-client",
+        None,
+        None,
+        ServerFlags::default(),
+        health_tx,
+        Some(token.clone())
+    );
+
+    let req_fut = {
+        let token = token.clone();
+
+        async move {
+            loop {
+                if let Some(ServerHealth::Listening(..)) = health_rx.recv().await {
+                    break;
+                }
+            }
+
+            let maybe_tls = None::<Tls>;
+            let mut io = maybe_tls.stream().await;
+
+            io.write_all(b"GET /wait HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            io.flush().await.unwrap();
+
+            sleep(Duration::from_millis(500)).await;
+            drop(io);
+
+            let client = maybe_tls.client();
+            let mut aborted = String::new();
+
+            for _ in 0..50 {
+                aborted = client
+                    .get(format!("http://localhost:{}/aborted", NON_SECURE_PORT))
+                    .send()
+                    .await
+                    .unwrap()
+                    .text()
+                    .await
+                    .unwrap();
+
+                if aborted == "1" {
+                    break;
+                }
+
+                sleep(Duration::from_millis(100)).await;
+            }
+
+            assert_eq!(aborted, "1");
+
+            if timeout(Duration::from_secs(10), token.cancel_and_wait())
+                .await
+                .is_err()
+            {
+                panic!("failed to terminate server within 10 seconds");
+            }
+        }
+    };
+
+    tokio::select! {
+        res = tokio::spawn(req_fut) => res.unwrap(),
+        _ = &mut listen_fut => {}
+    }
+}
+
 async fn test_slowloris_no_prompt_timeout(maybe_tls: Option<Tls>, invert: bool) {
     test_slowloris(
         if invert { u64::MAX } else { 5000 },
@@ -1958,6 +2822,39 @@ async fn test_request_idle_timeout_streamed_response_secure() {
     test_request_idle_timeout_streamed_response(new_localhost_tls(true)).await;
 }
 
+#[tokio::test]
+#[serial]
+async fn test_request_limits_override_unsigned_header() {
+    let client = Client::new();
+    let req = client
+        .request(
+            Method::GET,
+            format!("http://localhost:{}/sleep-5000ms", NON_SECURE_PORT),
+        )
+        .header(
+            "x-upstream-limits",
+            "eyJyZXF1ZXN0SWRsZVRpbWVvdXRNcyI6MH0.forged",
+        )
+        .build()
+        .unwrap();
+
+    integration_test!(
+        "./test_cases/main_with_request_limits",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        Some(RequestBuilder::from_parts(client, req)),
+        None,
+        (|resp| async {
+            // NOTE: The signed limits still apply even though the request
+            // carried another limits header.
+            assert_eq!(resp.unwrap().status().as_u16(), StatusCode::GATEWAY_TIMEOUT);
+        }),
+        TerminationToken::new()
+    );
+}
+
 async fn test_request_idle_timeout_streamed_response_first_chunk_timeout(maybe_tls: Option<Tls>) {
     let client = maybe_tls.client();
     let req = client
@@ -2313,6 +3210,137 @@ async fn test_declarative_style_fetch_handler() {
     );
 }
 
+#[tokio::test]
+#[serial]
+async fn test_request_context() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "request-context",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let body = resp.unwrap().json::<serde_json::Value>().await.unwrap();
+            let context = &body["after"];
+
+            // NOTE: The handler runs inside `requestContext.run`, whose store
+            // follows it across the timer.
+            assert!(body["outside"].is_null());
+            assert_eq!(body["sameAfterTimer"], true);
+            assert_eq!(&body["before"], context);
+            assert!(!context["requestId"].as_str().unwrap().is_empty());
+            assert!(context["tenant"].is_null());
+        }),
+        TerminationToken::new()
+    );
+}
+
+async fn test_shared_memory<F, R>(opts: serde_json::Value, callback: F)
+where
+    F: FnOnce(serde_json::Value) -> R,
+    R: Future<Output = ()>,
+{
+    let client = Client::new();
+    let req = client
+        .request(
+            Method::POST,
+            format!("http://localhost:{}/shared-memory", NON_SECURE_PORT),
+        )
+        .json(&opts)
+        .build()
+        .unwrap();
+
+    integration_test!(
+        "./test_cases/main_with_shared_memory",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        Some(RequestBuilder::from_parts(client, req)),
+        None,
+        (|resp| async {
+            let resp = resp.unwrap();
+
+            assert_eq!(resp.status().as_u16(), StatusCode::OK);
+            callback(resp.json::<serde_json::Value>().await.unwrap()).await;
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_shared_memory_is_disallowed_by_default() {
+    test_shared_memory(serde_json::json!({}), |body| async move {
+        assert_eq!(body["sharedArrayBuffer"], false);
+        assert_eq!(body["wasmThreads"], false);
+        assert!(body["atomicsWait"].is_null());
+    })
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_shared_memory_allowed() {
+    test_shared_memory(
+        serde_json::json!({
+            "allowSharedArrayBuffer": true,
+            "allowWasmThreads": true,
+        }),
+        |body| async move {
+            assert_eq!(body["sharedArrayBuffer"], true);
+            assert_eq!(body["wasmThreads"], true);
+            assert_eq!(body["atomicsWait"], "not-equal");
+        },
+    )
+    .await;
+
+    // NOTE: Shared memory is still allowed, but blocking on it is not.
+    test_shared_memory(
+        serde_json::json!({
+            "allowWasmThreads": true,
+            "allowAtomicsWait": false,
+        }),
+        |body| async move {
+            assert_eq!(body["sharedArrayBuffer"], false);
+            assert_eq!(body["wasmThreads"], true);
+            assert!(body["atomicsWait"]
+                .as_str()
+                .unwrap()
+                .starts_with("TypeError"));
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_runtime_context() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "runtime-context",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let body = resp.unwrap().json::<serde_json::Value>().await.unwrap();
+            let limits = &body["limits"];
+            let remaining = &body["remaining"];
+
+            assert_eq!(limits["memoryLimitBytes"], 150 * 1024 * 1024);
+            assert_eq!(limits["workerTimeoutMs"], 10 * 60 * 1000);
+            assert!(remaining["memoryBytes"].as_u64().unwrap() < 150 * 1024 * 1024);
+            assert!(remaining["wallClockMs"].as_u64().unwrap() <= 10 * 60 * 1000);
+            assert!(remaining["cpuTimeMs"].as_u64().is_some());
+        }),
+        TerminationToken::new()
+    );
+}
+
 #[tokio::test]
 #[serial]
 async fn test_issue_420() {
@@ -2641,3 +3669,32 @@ impl TlsExt for Option<Tls> {
 fn new_localhost_tls(secure: bool) -> Option<Tls> {
     secure.then(|| Tls::new(SECURE_PORT, TLS_LOCALHOST_KEY, TLS_LOCALHOST_CERT).unwrap())
 }
+
+#[tokio::test]
+#[serial]
+async fn test_repl_session_keeps_global_state() {
+    let mut session = ReplSession::new(test_user_runtime_opts()).await.unwrap();
+
+    assert!(session.eval("var answer = 40;").await.unwrap().succeeded);
+
+    let outcome = session.eval("answer + 2").await.unwrap();
+
+    assert!(outcome.succeeded);
+    assert!(outcome.logs.iter().any(|it| it.msg.contains("42")));
+
+    let outcome = session.eval("throw new Error('boom')").await.unwrap();
+
+    assert!(!outcome.succeeded);
+    assert!(outcome.logs.iter().any(|it| it.msg.contains("boom")));
+
+    let outcome = session.eval("answer").await.unwrap();
+
+    assert!(outcome.succeeded);
+    assert!(outcome.logs.iter().any(|it| it.msg.contains("40")));
+
+    let (_, usage) = session.close().await;
+
+    assert_eq!(usage.evaluations, 4);
+    assert!(usage.shutdown_reason.is_some());
+    assert!(usage.cpu_time_used_ms.is_some());
+}
@@ -36,5 +36,9 @@ impl From<&'_ v8::HeapStatistics> for WorkerHeapStatistics {
 #[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
 pub struct MemCheckState {
     pub current: WorkerHeapStatistics,
+    /// Size of the array buffers of the worker, which is included in the
+    /// external memory of `current`.
+    #[serde(default)]
+    pub array_buffer_size: usize,
     pub exceeded: bool,
 }
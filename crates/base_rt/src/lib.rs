@@ -3,14 +3,17 @@ use std::num::NonZeroUsize;
 use once_cell::sync::Lazy;
 
 pub mod error;
+pub mod thread_name;
+pub mod topology;
 
 pub const DEFAULT_PRIMARY_WORKER_POOL_SIZE: usize = 2;
 pub const DEFAULT_USER_WORKER_POOL_SIZE: usize = 1;
 
 pub static SUPERVISOR_RT: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
-    tokio::runtime::Builder::new_multi_thread()
+    topology::get_runtime_topology()
+        .apply(&mut tokio::runtime::Builder::new_multi_thread())
         .enable_all()
-        .thread_name("sb-supervisor")
+        .thread_name_fn(thread_name::indexed("sb-supervisor"))
         .build()
         .unwrap()
 });
@@ -35,7 +38,7 @@ pub static PRIMARY_WORKER_RT: Lazy<tokio_util::task::LocalPoolHandle> = Lazy::ne
     )
 });
 
-pub static USER_WORKER_RT: Lazy<tokio_util::task::LocalPoolHandle> = Lazy::new(|| {
+pub(crate) static USER_WORKER_POOL_SIZE: Lazy<usize> = Lazy::new(|| {
     let maybe_pool_size = std::env::var("EDGE_RUNTIME_WORKER_POOL_SIZE")
         .ok()
         .and_then(|it| it.parse::<usize>().ok())
@@ -47,7 +50,7 @@ pub static USER_WORKER_RT: Lazy<tokio_util::task::LocalPoolHandle> = Lazy::new(|
             }
         });
 
-    tokio_util::task::LocalPoolHandle::new(if cfg!(debug_assertions) {
+    if cfg!(debug_assertions) {
         maybe_pool_size.unwrap_or(DEFAULT_USER_WORKER_POOL_SIZE)
     } else {
         maybe_pool_size.unwrap_or(
@@ -56,5 +59,8 @@ pub static USER_WORKER_RT: Lazy<tokio_util::task::LocalPoolHandle> = Lazy::new(|
                 .map(NonZeroUsize::get)
                 .unwrap_or(DEFAULT_USER_WORKER_POOL_SIZE),
         )
-    })
+    }
 });
+
+pub static USER_WORKER_RT: Lazy<tokio_util::task::LocalPoolHandle> =
+    Lazy::new(|| tokio_util::task::LocalPoolHandle::new(*USER_WORKER_POOL_SIZE));
@@ -0,0 +1,129 @@
+//! Names of the threads of the runtime as the OS sees them, so that `top -H`
+//! and `ps -L` tell them apart during an incident. Linux keeps at most 15
+//! bytes of a name, so the part that tells threads apart goes first.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub static MAX_OS_THREAD_NAME_LEN: usize = 15;
+
+/// Returns a name function for a tokio runtime, which numbers its threads,
+/// e.g. `sb-supervisor-3`. It is used for its blocking threads as well.
+pub fn indexed(prefix: &'static str) -> impl Fn() -> String + Send + Sync + 'static {
+    let next = AtomicUsize::new(0);
+
+    move || format!("{}-{}", prefix, next.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Truncates a name to what the OS keeps of it, on a char boundary.
+pub fn truncate(name: &str) -> &str {
+    if name.len() <= MAX_OS_THREAD_NAME_LEN {
+        return name;
+    }
+
+    let mut end = MAX_OS_THREAD_NAME_LEN;
+
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &name[..end]
+}
+
+/// Sets the name of the current thread as the OS sees it, leaving the one
+/// of the `std::thread::Thread` as is.
+pub fn set_os_thread_name(name: &str) {
+    imp::set(truncate(name));
+}
+
+/// Names the current thread as the OS sees it until dropped, e.g. while a
+/// blocking thread of a runtime does the work of a service.
+pub struct OsThreadNameGuard {
+    prev: Option<String>,
+}
+
+impl OsThreadNameGuard {
+    pub fn new(name: &str) -> Self {
+        let prev = imp::get();
+
+        set_os_thread_name(name);
+
+        Self { prev }
+    }
+}
+
+impl Drop for OsThreadNameGuard {
+    fn drop(&mut self) {
+        if let Some(prev) = self.prev.as_deref() {
+            imp::set(prev);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::ffi::{CStr, CString};
+
+    pub(super) fn set(name: &str) {
+        let Ok(name) = CString::new(name) else {
+            return;
+        };
+
+        // SAFETY: `name` is NUL-terminated, and the kernel copies at most 16
+        // bytes of it.
+        unsafe {
+            libc::prctl(libc::PR_SET_NAME, name.as_ptr() as libc::c_ulong, 0, 0, 0);
+        }
+    }
+
+    pub(super) fn get() -> Option<String> {
+        let mut buf = [0 as libc::c_char; 16];
+
+        // SAFETY: The kernel writes at most 16 bytes, NUL included.
+        let ret = unsafe {
+            libc::prctl(
+                libc::PR_GET_NAME,
+                buf.as_mut_ptr() as libc::c_ulong,
+                0,
+                0,
+                0,
+            )
+        };
+
+        if ret != 0 {
+            return None;
+        }
+
+        // SAFETY: The buffer is NUL-terminated by the kernel.
+        Some(
+            unsafe { CStr::from_ptr(buf.as_ptr()) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    pub(super) fn set(_name: &str) {}
+
+    pub(super) fn get() -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{indexed, truncate};
+
+    #[test]
+    fn test_thread_names() {
+        let name_fn = indexed("sb-supervisor");
+
+        assert_eq!(name_fn(), "sb-supervisor-0");
+        assert_eq!(name_fn(), "sb-supervisor-1");
+
+        assert_eq!(truncate("sb-w-acme"), "sb-w-acme");
+        assert_eq!(truncate("sb-w-acme/hello@v2"), "sb-w-acme/hello");
+        assert_eq!(truncate("sb-w-acme/hellé"), "sb-w-acme/hell");
+    }
+}
@@ -0,0 +1,231 @@
+//! The layout of the tokio runtimes, which is set once at startup before any
+//! of them is built, along with the statistics that tell layouts apart under
+//! load.
+
+use std::future::Future;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use deno_core::anyhow::{bail, Error};
+use serde::Serialize;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::LocalSet;
+use tokio_util::task::LocalPoolHandle;
+
+use crate::{thread_name, SUPERVISOR_RT, USER_WORKER_POOL_SIZE};
+
+/// How the isolates of the user workers are driven.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IsolateRuntimeFlavor {
+    /// The isolates share a fixed pool of threads, each of them driving a
+    /// single `LocalSet`.
+    #[default]
+    SharedLocalSet,
+    /// Every isolate gets a thread of its own, with a current-thread runtime.
+    CurrentThread,
+}
+
+impl FromStr for IsolateRuntimeFlavor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "shared-local-set" => Self::SharedLocalSet,
+            "current-thread" => Self::CurrentThread,
+            _ => bail!("unknown isolate runtime flavor: {s}"),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeTopology {
+    /// The worker threads of the runtime that accepts and dispatches the
+    /// requests. It is a current-thread runtime if unset.
+    pub dispatcher_threads: Option<usize>,
+    /// The upper bound of the blocking pools of the runtimes, which is the
+    /// default of tokio if unset.
+    pub max_blocking_threads: Option<usize>,
+    pub isolate_runtime_flavor: IsolateRuntimeFlavor,
+}
+
+impl RuntimeTopology {
+    pub fn build_dispatcher_runtime(&self) -> io::Result<Runtime> {
+        let mut builder = match self.dispatcher_threads {
+            Some(it) => {
+                let mut builder = Builder::new_multi_thread();
+
+                builder.worker_threads(it.max(1));
+                builder
+            }
+
+            None => Builder::new_current_thread(),
+        };
+
+        self.apply(&mut builder)
+            .enable_all()
+            .thread_name_fn(thread_name::indexed("sb-main"))
+            .build()
+    }
+
+    pub(crate) fn apply<'a>(&self, builder: &'a mut Builder) -> &'a mut Builder {
+        if let Some(it) = self.max_blocking_threads {
+            builder.max_blocking_threads(it.max(1));
+        }
+
+        builder
+    }
+}
+
+static RUNTIME_TOPOLOGY: OnceLock<RuntimeTopology> = OnceLock::new();
+
+static ISOLATE_THREADS_ALIVE: AtomicUsize = AtomicUsize::new(0);
+static ISOLATE_THREADS_SPAWNED: AtomicU64 = AtomicU64::new(0);
+static ISOLATE_TASKS_SPAWNED: AtomicU64 = AtomicU64::new(0);
+static ISOLATE_TASK_START_DELAY_US: AtomicU64 = AtomicU64::new(0);
+static ISOLATE_TASK_MAX_START_DELAY_US: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the topology of the runtimes. Returns `false` if it was already set,
+/// or the runtimes were already built with the default one.
+pub fn init_runtime_topology(topology: RuntimeTopology) -> bool {
+    RUNTIME_TOPOLOGY.set(topology).is_ok()
+}
+
+pub fn get_runtime_topology() -> &'static RuntimeTopology {
+    RUNTIME_TOPOLOGY.get_or_init(RuntimeTopology::default)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeTopologyStatistics {
+    pub topology: RuntimeTopology,
+    pub supervisor_threads: usize,
+    /// `None` if every isolate gets a thread of its own.
+    pub user_worker_pool_threads: Option<usize>,
+    pub isolate_threads_alive: usize,
+    pub isolate_threads_spawned: u64,
+    pub isolate_tasks_spawned: u64,
+    /// How long the isolates waited for their thread to pick them up, in
+    /// microseconds.
+    pub isolate_task_start_delay_avg_us: u64,
+    pub isolate_task_start_delay_max_us: u64,
+}
+
+pub fn get_runtime_topology_statistics() -> RuntimeTopologyStatistics {
+    let topology = *get_runtime_topology();
+    let tasks = ISOLATE_TASKS_SPAWNED.load(Ordering::Relaxed);
+
+    RuntimeTopologyStatistics {
+        topology,
+        supervisor_threads: SUPERVISOR_RT.metrics().num_workers(),
+        user_worker_pool_threads: (topology.isolate_runtime_flavor
+            == IsolateRuntimeFlavor::SharedLocalSet)
+            .then_some(*USER_WORKER_POOL_SIZE),
+        isolate_threads_alive: ISOLATE_THREADS_ALIVE.load(Ordering::Relaxed),
+        isolate_threads_spawned: ISOLATE_THREADS_SPAWNED.load(Ordering::Relaxed),
+        isolate_tasks_spawned: tasks,
+        isolate_task_start_delay_avg_us: ISOLATE_TASK_START_DELAY_US
+            .load(Ordering::Relaxed)
+            .checked_div(tasks)
+            .unwrap_or_default(),
+        isolate_task_start_delay_max_us: ISOLATE_TASK_MAX_START_DELAY_US.load(Ordering::Relaxed),
+    }
+}
+
+struct IsolateThreadGuard;
+
+impl IsolateThreadGuard {
+    fn new() -> Self {
+        ISOLATE_THREADS_ALIVE.fetch_add(1, Ordering::Relaxed);
+        ISOLATE_THREADS_SPAWNED.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for IsolateThreadGuard {
+    fn drop(&mut self) {
+        ISOLATE_THREADS_ALIVE.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Spawns the task that drives an isolate, either on the given pool or, for
+/// the user workers under [`IsolateRuntimeFlavor::CurrentThread`], on a
+/// thread of its own, which is given `name`.
+pub fn spawn_isolate<F, Fut>(
+    pool: &LocalPoolHandle,
+    is_user_worker: bool,
+    name: String,
+    create_task: F,
+) -> io::Result<()>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future + 'static,
+    Fut::Output: Send + 'static,
+{
+    let topology = *get_runtime_topology();
+    let spawned_at = Instant::now();
+    let create_task = move || {
+        let delay_us = spawned_at.elapsed().as_micros() as u64;
+
+        ISOLATE_TASKS_SPAWNED.fetch_add(1, Ordering::Relaxed);
+        ISOLATE_TASK_START_DELAY_US.fetch_add(delay_us, Ordering::Relaxed);
+        ISOLATE_TASK_MAX_START_DELAY_US.fetch_max(delay_us, Ordering::Relaxed);
+
+        // NOTE: The threads of a pool are shared by the isolates of many
+        // services and are left unnamed by it, so they are named after the
+        // pool rather than after any of the services.
+        if std::thread::current().name().is_none() {
+            thread_name::set_os_thread_name(if is_user_worker {
+                "sb-user-pool"
+            } else {
+                "sb-primary-pool"
+            });
+        }
+
+        create_task()
+    };
+
+    if !is_user_worker || topology.isolate_runtime_flavor == IsolateRuntimeFlavor::SharedLocalSet {
+        drop(pool.spawn_pinned(create_task));
+        return Ok(());
+    }
+
+    let rt = topology
+        .apply(&mut Builder::new_current_thread())
+        .enable_all()
+        .build()?;
+
+    std::thread::Builder::new().name(name).spawn(move || {
+        let _guard = IsolateThreadGuard::new();
+
+        // NOTE: The task is created inside the `LocalSet`, as it may spawn
+        // local tasks right away.
+        LocalSet::new().block_on(&rt, async move {
+            create_task().await;
+        });
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::IsolateRuntimeFlavor;
+
+    #[test]
+    fn test_isolate_runtime_flavor() {
+        assert_eq!(
+            "current-thread".parse::<IsolateRuntimeFlavor>().unwrap(),
+            IsolateRuntimeFlavor::CurrentThread
+        );
+        assert_eq!(
+            "shared-local-set".parse::<IsolateRuntimeFlavor>().unwrap(),
+            IsolateRuntimeFlavor::SharedLocalSet
+        );
+        assert!("dedicated".parse::<IsolateRuntimeFlavor>().is_err());
+    }
+}
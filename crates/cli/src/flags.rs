@@ -1,10 +1,12 @@
 use std::{net::SocketAddr, path::PathBuf};
 
+use base::LETS_ENCRYPT_DIRECTORY_URL;
 use clap::{
     arg,
     builder::{BoolishValueParser, FalseyValueParser, TypedValueParser},
     crate_version, value_parser, ArgAction, ArgGroup, Command, ValueEnum,
 };
+use deno_core::url::Url;
 use sb_graph::Checksum;
 
 #[derive(ValueEnum, Default, Clone, Copy)]
@@ -54,6 +56,13 @@ pub(super) fn get_cli() -> Command {
         .subcommand(get_start_command())
         .subcommand(get_bundle_command())
         .subcommand(get_unbundle_command())
+        .subcommand(get_inspect_eszip_command())
+        .subcommand(get_check_command())
+        .subcommand(get_cache_command())
+        .subcommand(get_eval_command())
+        .subcommand(get_repl_command())
+        .subcommand(get_test_command())
+        .subcommand(get_replay_command())
 }
 
 fn get_start_command() -> Command {
@@ -72,9 +81,7 @@ fn get_start_command() -> Command {
                 .env("EDGE_RUNTIME_TLS")
                 .num_args(0..=1)
                 .default_missing_value("443")
-                .value_parser(value_parser!(u16))
-                .requires("key")
-                .requires("cert"),
+                .value_parser(value_parser!(u16)),
         )
         .arg(
             arg!(--key <Path>)
@@ -88,11 +95,162 @@ fn get_start_command() -> Command {
                 .env("EDGE_RUNTIME_TLS_CERT_PATH")
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(
+            arg!(--"tls-ocsp-response" <Path>)
+                .help("Path to DER-encoded OCSP response to be stapled to TLS handshakes")
+                .env("EDGE_RUNTIME_TLS_OCSP_RESPONSE_PATH")
+                .value_parser(value_parser!(PathBuf))
+                .requires("tls"),
+        )
+        .arg(
+            arg!(--"tls-cert-check-interval" <SECONDS>)
+                .help(concat!(
+                    "Interval in seconds at which the expiry of the TLS certificate is checked, and ",
+                    "its files are reloaded if they have changed"
+                ))
+                .default_value("3600")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"tls-cert-expiry-warn-days" <DAYS>)
+                .help("Warns when the TLS certificate expires in less than this many days")
+                .default_value("14")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"acme-domain" <DOMAIN>)
+                .help(concat!(
+                    "Obtains the TLS certificate for this domain from an ACME server instead of ",
+                    "loading it from `--key` and `--cert`. Can be specified multiple times."
+                ))
+                .action(ArgAction::Append)
+                .requires("tls")
+                .conflicts_with_all(["key", "cert"]),
+        )
+        .arg(
+            arg!(--"acme-email" <EMAIL>)
+                .help("Contact email of the ACME account")
+                .env("EDGE_RUNTIME_ACME_EMAIL"),
+        )
+        .arg(
+            arg!(--"acme-directory" <URL>)
+                .help("Directory URL of the ACME server")
+                .env("EDGE_RUNTIME_ACME_DIRECTORY")
+                .default_value(LETS_ENCRYPT_DIRECTORY_URL),
+        )
+        .arg(
+            arg!(--"acme-cache-dir" <DIR>)
+                .help("Directory where the ACME account key and the issued certificate are kept")
+                .env("EDGE_RUNTIME_ACME_CACHE_DIR")
+                .default_value("./acme")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"acme-challenge" <TYPE>)
+                .help(concat!(
+                    "Challenge to prove the control of the domains with. `http-01` is answered on ",
+                    "the non-secure port, and `tls-alpn-01` on the TLS port. Either must be reachable ",
+                    "from the ACME server on port 80 or 443, respectively."
+                ))
+                .default_value("tls-alpn-01")
+                .value_parser(["http-01", "tls-alpn-01"]),
+        )
+        .arg(
+            arg!(--"acme-renew-before-days" <DAYS>)
+                .help(concat!(
+                    "Renews the TLS certificate obtained via ACME when it expires in less than this ",
+                    "many days"
+                ))
+                .default_value("30")
+                .value_parser(value_parser!(u64)),
+        )
         .arg(
             arg!(--"main-service" <DIR>)
-                .help("Path to main service directory or eszip")
+                .help("Path to main service directory, eszip or .tar.gz/.zip bundle (local or URL)")
                 .default_value("examples/main"),
         )
+        .arg(
+            arg!(--"one-shot" <SERVICE_PATH>)
+                .help(concat!(
+                    "Instead of listening, reads a single HTTP/1.1 request or JSON event from stdin, ",
+                    "hands it to a user worker booted from the service at <SERVICE_PATH> with the ",
+                    "settings of the worker pool, writes the response to stdout and exits. The exit ",
+                    "status is 0 for a response below 400, 4 for a 4xx, 5 for a 5xx, 65 for an invalid ",
+                    "input and 70 if the worker failed"
+                )),
+        )
+        .arg(
+            arg!(--"functions-dir" <DIR>)
+                .help(concat!(
+                    "Path to a directory of functions. If specified, requests to `/:function_name/*` ",
+                    "are routed to `<DIR>/<function_name>` directly instead of the main service."
+                ))
+                .env("EDGE_RUNTIME_FUNCTIONS_DIR"),
+        )
+        .arg(
+            arg!(--"router")
+                .help(concat!(
+                    "Routes requests with the `routes` of the runtime config directly ",
+                    "instead of the main service. Implied by `--functions-dir`"
+                ))
+                .env("EDGE_RUNTIME_ROUTER")
+                .requires("config")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"internal-api-token" <TOKEN>)
+                .help(concat!(
+                    "If specified along with `--functions-dir`, enables the `/_internal/workers` API ",
+                    "for creating and terminating user workers, authenticated with this bearer token."
+                ))
+                .env("EDGE_RUNTIME_INTERNAL_API_TOKEN")
+                .hide_env_values(true),
+        )
+        .arg(
+            arg!(--"internal-api-allow-cidr" <CIDR>)
+                .help(concat!(
+                    "Accepts requests to the internal API only from source addresses in this range. ",
+                    "Can be specified multiple times. Enables the internal API like `--internal-api-token`, ",
+                    "and must be satisfied along with the other internal API options if given."
+                ))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"internal-api-client-ca" <Path>)
+                .help(concat!(
+                    "Path to PEM-encoded CA certificates. Accepts requests to the internal API only ",
+                    "over TLS with a client certificate issued by one of them. Enables the internal ",
+                    "API like `--internal-api-token`."
+                ))
+                .env("EDGE_RUNTIME_INTERNAL_API_CLIENT_CA_PATH")
+                .value_parser(value_parser!(PathBuf))
+                .requires("tls"),
+        )
+        .arg(
+            arg!(--"trusted-proxy" <CIDR>)
+                .help(concat!(
+                    "Honors `X-Forwarded-For` in requests from proxies in this range when resolving ",
+                    "the client IP, which is passed to workers in the `x-client-ip` header. Can be ",
+                    "specified multiple times."
+                ))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"allow-client-cidr" <CIDR>)
+                .help(concat!(
+                    "Accepts requests only from clients in this range. Can be specified multiple ",
+                    "times."
+                ))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"deny-client-cidr" <CIDR>)
+                .help(concat!(
+                    "Rejects requests from clients in this range, even if they are allowed by ",
+                    "`--allow-client-cidr`. Can be specified multiple times."
+                ))
+                .action(ArgAction::Append),
+        )
         .arg(
             arg!(--"disable-module-cache")
                 .help("Disable using module cache")
@@ -100,7 +258,39 @@ fn get_start_command() -> Command {
                 .value_parser(FalseyValueParser::new()),
         )
         .arg(arg!(--"import-map" <Path>).help("Path to import map file"))
-        .arg(arg!(--"event-worker" <Path>).help("Path to event worker directory"))
+        .arg(
+            arg!(--"prefetch-import-map" <Path>)
+                .help(concat!(
+                    "Fetches the remote modules mapped by this import map into the module cache ",
+                    "before accepting requests. Can be specified multiple times."
+                ))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"prefetch-interval" <SECONDS>)
+                .help("Interval in seconds at which the import maps given by `--prefetch-import-map` are prefetched again")
+                .requires("prefetch-import-map")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"module-cache-max-size" <MEGABYTES>)
+                .help(concat!(
+                    "Removes the least recently used remote modules from the module cache ",
+                    "on startup and every hour, until it fits in this size (unbounded by default)"
+                ))
+                .env("EDGE_RUNTIME_MODULE_CACHE_MAX_SIZE")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(arg!(--"event-worker" <Path>).help("Path to event worker directory, eszip or .tar.gz/.zip bundle (local or URL)"))
+        .arg(
+            arg!(--"event-worker-route" <ROUTE_AND_PATH>)
+                .help(concat!(
+                    "Sends the events of a route (`lifecycle`, `logs` or `custom`) to an events worker ",
+                    "of its own, given as `<ROUTE>=<PATH>`. The events of the other routes go to ",
+                    "`--event-worker`. Can be specified multiple times."
+                ))
+                .action(ArgAction::Append),
+        )
         .arg(arg!(--"main-entrypoint" <Path>).help("Path to entrypoint in main service (only for eszips)"))
         .arg(arg!(--"events-entrypoint" <Path>).help("Path to entrypoint in events worker (only for eszips)"))
         .arg(
@@ -139,6 +329,24 @@ fn get_start_command() -> Command {
                 .default_value("10")
                 .value_parser(value_parser!(u64).range(..u64::MAX))
         )
+        .arg(
+            arg!(--"events-backlog-limit" <EVENTS>)
+                .help(concat!(
+                    "Number of events waiting for the event worker from which the log events of user workers ",
+                    "are dropped (0 disables the limit)"
+                ))
+                .default_value("10000")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"lifecycle-history-size" <RECORDS>)
+                .help(concat!(
+                    "Number of recent boots and terminations of workers the event worker can look up ",
+                    "(0 disables the history)"
+                ))
+                .default_value("256")
+                .value_parser(value_parser!(usize)),
+        )
         .arg(
             arg!(
                 --"experimental-graceful-exit-keepalive-deadline-ratio"
@@ -160,6 +368,54 @@ fn get_start_command() -> Command {
                     value_parser!(u32).range(1..9999).map(|it| -> usize { it as usize }),
                 ),
         )
+        .arg(
+            arg!(--"pool-state-file" <PATH>)
+                .help(concat!(
+                    "Path to a file where the registered user worker services are persisted on graceful shutdown. ",
+                    "They are pre-warmed from it on the next startup"
+                ))
+                .env("EDGE_RUNTIME_POOL_STATE_FILE")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"request-journal" <PATH>)
+                .help(concat!(
+                    "Path to a file where the metadata of the requests dispatched to the user workers is journaled. ",
+                    "The requests left in flight by the previous run are reported on startup"
+                ))
+                .env("EDGE_RUNTIME_REQUEST_JOURNAL")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"jwt-secret" <SECRET>)
+                .help(concat!(
+                    "Secret that the bearer tokens of the requests to the functions requiring a JWT must be signed with (HS256). ",
+                    "Such requests are rejected if it is not set"
+                ))
+                .env("EDGE_RUNTIME_JWT_SECRET")
+                .hide_env_values(true),
+        )
+        .arg(
+            arg!(--"record" <PATH>)
+                .help(concat!(
+                    "Path to a file where the requests dispatched to the user workers, their responses ",
+                    "and the responses of the fetches of the workers are recorded, to be replayed with `replay`. ",
+                    "Only the head of the bodies larger than `--record-max-body-size` is recorded, and the ",
+                    "values of the headers that carry credentials are redacted"
+                ))
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"record-max-body-size" <KIB>)
+                .help("Maximum size in kibibytes of a recorded body")
+                .default_value("1024")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"record-sensitive-headers")
+                .help("Record the values of the headers that carry credentials, such as `authorization` and `cookie`, as they are")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             arg!(--"request-wait-timeout" <MILLISECONDS>)
                 .help("Maximum time in milliseconds that can wait to establish a connection with a worker")
@@ -171,11 +427,502 @@ fn get_start_command() -> Command {
                 .help("Maximum time in milliseconds that can be waited from when a worker takes over the request (disabled by default)")
                 .value_parser(value_parser!(u64)),
         )
+        .arg(
+            arg!(--"main-worker-memory-limit" <MEGABYTES>)
+                .help("Maximum heap size in megabytes of the main worker; it is terminated when reached (disabled by default)")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"main-worker-unresponsive-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that the event loop of the main worker can stay blocked before it is restarted (disabled by default)")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"request-log-size" <ENTRIES>)
+                .help("Number of recent requests kept per user worker for the workers API (0 disables the log)")
+                .default_value("32")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"trace-buffer-size" <ENTRIES>)
+                .help("Number of recent requests whose W3C trace context spans are kept for /_internal/traces (0 disables the tracing)")
+                .default_value("256")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"dns-cache-size" <ENTRIES>)
+                .help("Number of host names whose DNS lookups are cached for the fetches of all workers (0 disables the cache)")
+                .default_value("0")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"dns-cache-max-ttl" <SECONDS>)
+                .help("Maximum time in seconds that a DNS lookup is cached, regardless of the TTL of its records")
+                .default_value("300")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"dns-cache-negative-ttl" <SECONDS>)
+                .help("Time in seconds that a host name which does not resolve is cached")
+                .default_value("10")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"outbound-pool")
+                .help("Shares the outbound connections between the user workers of the same tenant, instead of each worker opening its own")
+                .env("EDGE_RUNTIME_OUTBOUND_POOL")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"outbound-pool-max-idle-per-host" <CONNECTIONS>)
+                .help("Maximum number of idle outbound connections kept per host")
+                .default_value("32")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"outbound-pool-idle-timeout" <SECONDS>)
+                .help("Time in seconds after which an idle outbound connection is closed")
+                .default_value("90")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"outbound-pool-http1-only")
+                .help("Only offers HTTP/1.1 to the hosts of the outbound connections, instead of preferring HTTP/2")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"outbound-tls-min-version" <VERSION>)
+                .help("Minimum TLS version of the fetches of all workers, unless a worker sets its own")
+                .value_parser(["1.2", "1.3"]),
+        )
+        .arg(
+            arg!(--"outbound-tls-max-version" <VERSION>)
+                .help("Maximum TLS version of the fetches of all workers, unless a worker sets its own")
+                .value_parser(["1.2", "1.3"]),
+        )
+        .arg(
+            arg!(--"outbound-tls-cipher-suite" <SUITE>)
+                .help(concat!(
+                    "Allows this cipher suite (e.g. TLS13_AES_128_GCM_SHA256) for the fetches of all workers, ",
+                    "unless a worker sets its own. Every suite is allowed by default. Can be specified multiple times."
+                ))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"worker-drain-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a retiring worker can spend on its in-flight requests before it is terminated")
+                .default_value("5000")
+                .value_parser(value_parser!(u64)),
+        )
         .arg(
             arg!(--"request-read-timeout" <MILLISECONDS>)
                 .help("Maximum time in milliseconds that can be waited from when the connection is accepted until the request body is fully read (disabled by default)")
                 .value_parser(value_parser!(u64)),
         )
+        .arg(
+            arg!(--"request-body-idle-timeout" <MILLISECONDS>)
+                .help("Fails a request whose client goes this long without sending a chunk of its body (disabled by default)")
+                .env("EDGE_RUNTIME_REQUEST_BODY_IDLE_TIMEOUT")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"request-body-min-rate" <BYTES_PER_SEC>)
+                .help(concat!(
+                    "Fails a request whose body is received slower than this on average, ",
+                    "after a grace period of 5 seconds (disabled by default)"
+                ))
+                .env("EDGE_RUNTIME_REQUEST_BODY_MIN_RATE")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"max-connections-per-ip" <COUNT>)
+                .help("Maximum number of connections a single peer IP may have open at once (unlimited by default)")
+                .env("EDGE_RUNTIME_MAX_CONNECTIONS_PER_IP")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"hold-requests-until-ready")
+                .help(concat!(
+                    "Listens while the main worker is still booting, holding the requests that arrive ",
+                    "in the meantime instead of refusing them. With the router, the requests of a service ",
+                    "are held while its worker boots"
+                ))
+                .env("EDGE_RUNTIME_HOLD_REQUESTS_UNTIL_READY")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"startup-queue-size" <COUNT>)
+                .help("Maximum number of requests held until the main worker has booted (1024 by default)")
+                .env("EDGE_RUNTIME_STARTUP_QUEUE_SIZE")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"startup-queue-timeout" <MILLISECONDS>)
+                .help("Fails a request held for longer than this until the main worker has booted (30000 by default)")
+                .env("EDGE_RUNTIME_STARTUP_QUEUE_TIMEOUT")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"task-queue-db" <PATH>)
+                .help("Persists the tasks enqueued by the user workers with `EdgeRuntime.queue.enqueue()` in this SQLite database, and delivers them to their services through the main worker")
+                .env("EDGE_RUNTIME_TASK_QUEUE_DB")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"task-queue-visibility-timeout" <MILLISECONDS>)
+                .help("Retries a task whose delivery takes longer than this (30000 by default)")
+                .env("EDGE_RUNTIME_TASK_QUEUE_VISIBILITY_TIMEOUT")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"response-buffer-size" <BYTES>)
+                .help(concat!(
+                    "Reads up to this many bytes of a response ahead of its client, ",
+                    "so that a slow client does not hold on to the worker (disabled by default)"
+                ))
+                .env("EDGE_RUNTIME_RESPONSE_BUFFER_SIZE")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"response-buffer-overflow" <MODE>)
+                .help(concat!(
+                    "What happens once the buffer of a response is full. `backpressure` holds the worker back, ",
+                    "while `spill` writes the rest of the response to a temporary file"
+                ))
+                .env("EDGE_RUNTIME_RESPONSE_BUFFER_OVERFLOW")
+                .value_parser(["backpressure", "spill"])
+                .default_value("backpressure"),
+        )
+        .arg(
+            arg!(--"response-spill-quota" <BYTES>)
+                .help("Maximum number of bytes the responses may spill to disk at once (1 GiB by default)")
+                .env("EDGE_RUNTIME_RESPONSE_SPILL_QUOTA")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"crash-dump-dir" <DIR>)
+                .help("Writes a crash dump with the state of the workers to this directory when V8 aborts the process, e.g. on out of memory errors")
+                .env("EDGE_RUNTIME_CRASH_DUMP_DIR")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"event-webhook-url" <URL>)
+                .help("POSTs a JSON webhook to this URL when a worker fails to boot, crashes, goes over its memory or CPU time limit, or is evicted from the pool")
+                .env("EDGE_RUNTIME_EVENT_WEBHOOK_URL")
+                .value_parser(value_parser!(Url)),
+        )
+        .arg(
+            arg!(--"event-webhook-secret" <SECRET>)
+                .help("Signs the webhooks with HMAC-SHA256 of this secret, in the `x-edge-runtime-signature` header")
+                .env("EDGE_RUNTIME_EVENT_WEBHOOK_SECRET")
+                .requires("event-webhook-url"),
+        )
+        .arg(
+            arg!(--"event-webhook-transition" <TRANSITION>)
+                .help(concat!(
+                    "Only sends the webhooks of this transition (`boot-failed`, `memory-limit`, ",
+                    "`cpu-time-limit`, `crashed` or `evicted`). All but `evicted` are sent by default, ",
+                    "as every worker the pool retires, drains or scales in is evicted. ",
+                    "Can be specified multiple times."
+                ))
+                .requires("event-webhook-url")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"event-webhook-max-retries" <RETRIES>)
+                .help("How many times a webhook is retried when the receiver can't be reached or fails")
+                .default_value("3")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            arg!(--"bundle-cache-dir" <DIR>)
+                .help("Extracts .tar.gz/.zip service bundles into this directory, keyed by their digest")
+                .env("EDGE_RUNTIME_BUNDLE_CACHE_DIR")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"bundle-public-key" <KEY>)
+                .help(concat!(
+                    "Only boots workers from bundles signed by this base64-encoded ed25519 public key, ",
+                    "with the signature in a `.sig` file next to the bundle. User workers can't boot from ",
+                    "a directory or from module code then. Can be specified multiple times."
+                ))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"ready-fd" <FD>)
+                .help("Writes a JSON readiness report to this file descriptor, and closes it, once the server is ready to serve requests")
+                .value_parser(value_parser!(i32)),
+        )
+        .arg(
+            arg!(--"ready-file" <PATH>)
+                .help("Creates this file with a JSON readiness report once the server is ready to serve requests")
+                .env("EDGE_RUNTIME_READY_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with("ready-fd"),
+        )
+        .arg(
+            arg!(--"shutdown-report" <PATH>)
+                .help("Writes a JSON report of the workers, requests and events seen over the lifetime of the server to this file once it has stopped (- for stdout)")
+                .env("EDGE_RUNTIME_SHUTDOWN_REPORT"),
+        )
+        .arg(
+            arg!(--"config" <PATH>)
+                .help(concat!(
+                    "Reads the pool settings, default worker limits and routes from this JSON file. ",
+                    "The file is reloaded on SIGHUP or through POST /_internal/config/reload"
+                ))
+                .env("EDGE_RUNTIME_CONFIG")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"protocol-sniffing")
+                .help("Rejects TLS handshakes on the plaintext port and redirects plain HTTP requests on the TLS port to HTTPS, instead of leaving the connection hanging")
+                .env("EDGE_RUNTIME_PROTOCOL_SNIFFING")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"acceptors" <COUNT>)
+                .help("Number of sockets accepting the connections of the HTTP port, bound to it with SO_REUSEPORT (0 means one per core)")
+                .env("EDGE_RUNTIME_ACCEPTORS")
+                .default_value("1")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"process-title")
+                .help("Keeps the title of the process up to date with the stats of the worker pool, as shown by ps and top")
+                .env("EDGE_RUNTIME_PROCESS_TITLE")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"http3")
+                .help(concat!(
+                    "Serves HTTP/3 over QUIC on the UDP port of the same number as the TLS port, ",
+                    "and advertises it with Alt-Svc (experimental). ",
+                    "Requires the `http3` feature and a static certificate"
+                ))
+                .env("EDGE_RUNTIME_HTTP3")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"io-uring")
+                .help(concat!(
+                    "Accepts and drives the connections of the HTTP port through io_uring (experimental). ",
+                    "Requires the `io-uring` feature, on Linux, and a single acceptor"
+                ))
+                .env("EDGE_RUNTIME_IO_URING")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"max-header-size" <BYTES>)
+                .help("Maximum size in bytes of the headers of a request. Larger ones are rejected with 431 (disabled by default)")
+                .env("EDGE_RUNTIME_MAX_HEADER_SIZE")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"max-uri-length" <BYTES>)
+                .help("Maximum length in bytes of the target of a request. Longer ones are rejected with 414 (disabled by default)")
+                .env("EDGE_RUNTIME_MAX_URI_LENGTH")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"tls-max-header-size" <BYTES>)
+                .help("Overrides `--max-header-size` for the requests received by the TLS listener")
+                .env("EDGE_RUNTIME_TLS_MAX_HEADER_SIZE")
+                .value_parser(value_parser!(usize))
+                .requires("tls"),
+        )
+        .arg(
+            arg!(--"tls-max-uri-length" <BYTES>)
+                .help("Overrides `--max-uri-length` for the requests received by the TLS listener")
+                .env("EDGE_RUNTIME_TLS_MAX_URI_LENGTH")
+                .value_parser(value_parser!(usize))
+                .requires("tls"),
+        )
+        .arg(
+            arg!(--"path-normalization" <MODE>)
+                .help(concat!(
+                    "Normalizes the path and the Host header of the requests before they are routed. ",
+                    "`lenient` repairs ambiguous paths, while `strict` rejects them"
+                ))
+                .env("EDGE_RUNTIME_PATH_NORMALIZATION")
+                .value_parser(["off", "lenient", "strict"])
+                .default_value("off"),
+        )
+        .arg(
+            arg!(--"request-coalescing")
+                .help("Lets concurrent identical GET requests to the same worker share a single response")
+                .env("EDGE_RUNTIME_REQUEST_COALESCING")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"duplicate-create" <POLICY>)
+                .help(concat!(
+                    "What happens when a worker is created for a service whose active workers were created with other options. ",
+                    "`reject` fails the creation, `reuse` hands out an active worker anyway, ",
+                    "while `recreate` drains the active workers and boots a new one"
+                ))
+                .env("EDGE_RUNTIME_DUPLICATE_CREATE")
+                .value_parser(["reject", "reuse", "recreate"])
+                .default_value("reuse"),
+        )
+        .arg(
+            arg!(--"max-concurrent-boots" <COUNT>)
+                .help("Maximum number of user workers that may boot at once; further creations wait for a slot (unlimited by default)")
+                .env("EDGE_RUNTIME_MAX_CONCURRENT_BOOTS")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"max-concurrent-requests" <COUNT>)
+                .help(concat!(
+                    "Maximum number of requests the user workers handle at once; further ones wait ",
+                    "for a slot, which the tenants get in proportion to their weights in the runtime ",
+                    "config (unlimited by default)"
+                ))
+                .env("EDGE_RUNTIME_MAX_CONCURRENT_REQUESTS")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"batch-max-concurrent-requests" <COUNT>)
+                .help(concat!(
+                    "Maximum number of batch requests (tagged `x-edge-runtime-priority: batch` or ",
+                    "by their route) the user workers handle at once; batch requests only get a ",
+                    "slot once no interactive request is waiting"
+                ))
+                .env("EDGE_RUNTIME_BATCH_MAX_CONCURRENT_REQUESTS")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"batch-request-wait-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds a batch request waits for a slot (--request-wait-timeout by default)")
+                .env("EDGE_RUNTIME_BATCH_REQUEST_WAIT_TIMEOUT")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"batch-cpu-budget" <MILLISECONDS>)
+                .help("CPU time in milliseconds the batch requests may use per second across the user workers (unlimited by default)")
+                .env("EDGE_RUNTIME_BATCH_CPU_BUDGET")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"dispatcher-threads" <COUNT>)
+                .help(concat!(
+                    "Number of worker threads of the runtime that accepts and dispatches the requests ",
+                    "(a single thread by default)"
+                ))
+                .env("EDGE_RUNTIME_DISPATCHER_THREADS")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"max-blocking-threads" <COUNT>)
+                .help("Maximum number of threads of the blocking pool of each runtime (512 by default)")
+                .env("EDGE_RUNTIME_MAX_BLOCKING_THREADS")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"isolate-runtime-flavor" <FLAVOR>)
+                .help(concat!(
+                    "How the isolates of the user workers are driven: on a shared pool of threads, ",
+                    "or each on a thread of its own with a current-thread runtime"
+                ))
+                .env("EDGE_RUNTIME_ISOLATE_RUNTIME_FLAVOR")
+                .value_parser(["shared-local-set", "current-thread"])
+                .default_value("shared-local-set"),
+        )
+        .arg(
+            arg!(--"grpc-control-plane-addr" <ADDR>)
+                .help(concat!(
+                    "Serves the gRPC control plane on this address (host:port). ",
+                    "Requires the `grpc` feature and the internal API to be authenticated. ",
+                    "Served over TLS with the certificate of the server unless the address is a loopback one"
+                ))
+                .env("EDGE_RUNTIME_GRPC_CONTROL_PLANE_ADDR")
+                .value_parser(value_parser!(SocketAddr)),
+        )
+        .arg(
+            arg!(--"share-code-cache")
+                .help("Lets the workers of a service reuse the code compiled by the first one, so that scaling out boots faster")
+                .env("EDGE_RUNTIME_SHARE_CODE_CACHE")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"server-timing")
+                .help("Adds the boot (if cold), queue and execution durations of the requests to the Server-Timing header of the responses of user workers")
+                .env("EDGE_RUNTIME_SERVER_TIMING")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"shed-memory-threshold" <PERCENT>)
+                .help("Answers the requests handed to a user worker that has used this share of its memory limit with 503 and Retry-After, and drains the worker")
+                .env("EDGE_RUNTIME_SHED_MEMORY_THRESHOLD")
+                .value_parser(value_parser!(u8).range(1..=100)),
+        )
+        .arg(
+            arg!(--"shed-cpu-time-threshold" <PERCENT>)
+                .help("Answers the requests handed to a user worker that has used this share of its CPU time soft limit with 503 and Retry-After, and drains the worker")
+                .env("EDGE_RUNTIME_SHED_CPU_TIME_THRESHOLD")
+                .value_parser(value_parser!(u8).range(1..=100)),
+        )
+        .arg(
+            arg!(--"shed-queue-depth" <REQUESTS>)
+                .help("Answers the requests to a service with 503 and Retry-After instead of queueing them once this many requests are waiting for a worker of the service")
+                .env("EDGE_RUNTIME_SHED_QUEUE_DEPTH")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"shed-retry-after" <SECONDS>)
+                .help("Value of the Retry-After header of the shed requests")
+                .default_value("1")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"boot-min-free-memory" <MIB>)
+                .help("Refuses to boot a user worker if the host would have less memory available than this once the worker has taken its memory limit")
+                .env("EDGE_RUNTIME_BOOT_MIN_FREE_MEMORY")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"boot-max-load-avg" <LOAD>)
+                .help("Refuses to boot a user worker while the 1-minute load average per core of the host is past this")
+                .env("EDGE_RUNTIME_BOOT_MAX_LOAD_AVG")
+                .value_parser(value_parser!(f64)),
+        )
+        .arg(
+            arg!(--"boot-memory-budget" <MIB>)
+                .help("Refuses to boot a user worker that would take the sum of the memory limits of the user workers past this")
+                .env("EDGE_RUNTIME_BOOT_MEMORY_BUDGET")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"enable-failure-injection")
+                .help("Lets user workers simulate limit terminations, dropped responses and slow boots with EdgeRuntime.test.failNext, for testing their retry and cleanup logic locally")
+                .env("EDGE_RUNTIME_ENABLE_FAILURE_INJECTION")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"clock-granularity" <MILLISECONDS>)
+                .help("Coarsens Date and performance.now() in user workers to steps of at least this many milliseconds, to mitigate timing side channels between tenants (disabled by default)")
+                .env("EDGE_RUNTIME_CLOCK_GRANULARITY")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"service-stats-interval" <MILLISECONDS>)
+                .help("Interval in milliseconds at which per-service request statistics are reported to the event worker (disabled by default)")
+                .env("EDGE_RUNTIME_SERVICE_STATS_INTERVAL")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"runtime-stats-interval" <MILLISECONDS>)
+                .help("Interval in milliseconds at which statistics of the worker pool and the runtime itself are reported to the event worker (disabled by default)")
+                .env("EDGE_RUNTIME_RUNTIME_STATS_INTERVAL")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"concurrency-sample-interval" <MILLISECONDS>)
+                .help("Interval in milliseconds at which the in-flight requests, instances and configured memory of every service are sampled to the event worker for billing (disabled by default)")
+                .env("EDGE_RUNTIME_CONCURRENCY_SAMPLE_INTERVAL")
+                .value_parser(value_parser!(u64)),
+        )
         .arg(
             arg!(--"inspect" [HOST_AND_PORT])
                 .help("Activate inspector on host:port")
@@ -230,11 +977,17 @@ fn get_bundle_command() -> Command {
             "Creates an 'eszip' file that can be executed by the EdgeRuntime. ",
             "Such file contains all the modules in contained in a single binary."
         ))
-        .arg(arg!(--"output" <DIR>).help("Path to output eszip file").default_value("bin.eszip"))
+        .arg(
+            arg!([SERVICE_PATH])
+                .help("Path to a service directory whose index file is used as the entrypoint")
+                .value_parser(value_parser!(PathBuf))
+                .required_unless_present("entrypoint")
+                .conflicts_with("entrypoint"),
+        )
+        .arg(arg!(-o --"output" <DIR>).help("Path to output eszip file").default_value("bin.eszip"))
         .arg(
             arg!(--"entrypoint" <Path>)
-                .help("Path to entrypoint to bundle as an eszip")
-                .required(true),
+                .help("Path to entrypoint to bundle as an eszip"),
         )
         .arg(arg!(--"static" <Path>).help("Glob pattern for static files to be included"))
         .arg(arg!(--"import-map" <Path>).help("Path to import map file"))
@@ -265,3 +1018,155 @@ fn get_unbundle_command() -> Command {
                 .required(true),
         )
 }
+
+fn get_inspect_eszip_command() -> Command {
+    Command::new("inspect-eszip")
+        .about("Lists the modules, npm packages, static files and metadata of an .eszip file")
+        .arg(
+            arg!(<PATH>)
+                .help("Path of the eszip to inspect")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"json")
+                .help("Prints the content as JSON, e.g. to diff it against another eszip")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+fn get_check_command() -> Command {
+    Command::new("check")
+        .about(concat!(
+            "Loads the module graph of a service (or an eszip) without starting the server, ",
+            "and exits with a non-zero status if any module cannot be resolved or loaded."
+        ))
+        .arg(
+            arg!(<PATH>)
+                .help("Path to a service directory, an entrypoint file or an eszip file")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(arg!(--"import-map" <Path>).help("Path to import map file"))
+        .arg(
+            arg!(--"decorator" <TYPE>)
+                .help("Type of decorator to use when loading modules. If not specified, the decorator feature is disabled.")
+                .value_parser(["tc39", "typescript", "typescript_with_metadata"]),
+        )
+        .arg(
+            arg!(--"disallow-remote-modules")
+                .help("Reports remote modules as errors, as user workers created with `allowRemoteModules: false` would")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+fn get_cache_command() -> Command {
+    Command::new("cache")
+        .about(concat!(
+            "Shows the location, the number of modules and the size of the module cache ",
+            "(`$DENO_DIR/deps`), and optionally repairs, shrinks or clears it."
+        ))
+        .arg(
+            arg!(--"verify")
+                .help("Checks every module against its digest and removes the corrupted ones")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"max-size" <MEGABYTES>)
+                .help("Removes the least recently used modules until the cache fits in this size")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"clear")
+                .help("Removes every module from the cache")
+                .conflicts_with_all(["verify", "max-size"])
+                .action(ArgAction::SetTrue),
+        )
+}
+
+fn get_eval_command() -> Command {
+    with_limit_args(
+        Command::new("eval")
+            .about(concat!(
+                "Evaluates JavaScript in a user worker, prints its result and what it logged, ",
+                "and reports the resources it used."
+            ))
+            .arg(arg!(<CODE>).help("The code to evaluate")),
+    )
+}
+
+fn get_repl_command() -> Command {
+    with_limit_args(Command::new("repl").about(concat!(
+        "Starts an interactive session in a user worker, whose resource usage is reported on exit. ",
+        "Only `var` and function declarations outlive the input they are made in."
+    )))
+}
+
+fn get_test_command() -> Command {
+    with_limit_args(
+        Command::new("test")
+            .about(concat!(
+                "Runs the tests of a service in user workers, one for every test file, ",
+                "so that they see the same APIs as in production."
+            ))
+            .arg(arg!(<SERVICE_PATH>).help("Path to the service, or to a single test file"))
+            .arg(
+                arg!(--"reporter" <REPORTER>)
+                    .help("How the results are reported")
+                    .value_parser(["pretty", "tap", "junit"])
+                    .default_value("pretty"),
+            )
+            .arg(arg!(--"import-map" <Path>).help("Path to import map file"))
+            .arg(
+                arg!(--"limits")
+                    .help(concat!(
+                        "Applies the CPU time and wall clock limits to every test file. ",
+                        "Otherwise only the memory limit applies"
+                    ))
+                    .action(ArgAction::SetTrue),
+            ),
+    )
+}
+
+fn get_replay_command() -> Command {
+    with_limit_args(
+        Command::new("replay")
+            .about(concat!(
+                "Replays the requests recorded with `start --record` against a version of a service, ",
+                "with the recorded fetch responses, and reports the responses that changed."
+            ))
+            .arg(arg!(<RECORDING>).help("Path to the recording").value_parser(value_parser!(PathBuf)))
+            .arg(arg!(<SERVICE_PATH>).help("Path to the service to replay the requests against"))
+            .arg(
+                arg!(--"service" <SERVICE_PATH>)
+                    .help("The recorded service to replay, if the recording has more than one"),
+            )
+            .arg(
+                arg!(--"seed" <SEED>)
+                    .help("Seed of the random values of the worker")
+                    .default_value("0")
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(arg!(--"import-map" <Path>).help("Path to import map file")),
+    )
+}
+
+fn with_limit_args(command: Command) -> Command {
+    command
+        .arg(
+            arg!(--"memory-limit" <MIB>)
+                .help("Memory limit of the worker in MiB")
+                .default_value("512")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"cpu-time-limit" <MILLISECONDS>)
+                .help("CPU time limit of every input, of which half is the soft limit")
+                .default_value("100")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"wall-clock-limit" <MILLISECONDS>)
+                .help("Wall clock limit of every input (0 disables the limit)")
+                .default_value("300000")
+                .value_parser(value_parser!(u64)),
+        )
+}
@@ -4,39 +4,90 @@ mod flags;
 #[cfg(not(feature = "tracing"))]
 mod logger;
 
-use anyhow::{anyhow, bail, Error};
+use anyhow::{anyhow, bail, Context, Error};
 use base::commands::start_server;
+use base::event_webhook::{self, EventWebhookOpts, WebhookTransition};
 
-use base::rt_worker::worker_pool::{SupervisorPolicy, WorkerPoolPolicy};
+use base::one_shot::run_one_shot;
+use base::repl::{ReplSession, ReplUsage};
+use base::replay::{format_replay_report, replay_recording};
+use base::rt_worker::bundle_signature::{self, BundleVerifier};
+use base::rt_worker::client_ip::ClientIpPolicy;
+use base::rt_worker::internal_auth::InternalApiAuth;
+use base::rt_worker::manifest::FunctionManifest;
+use base::rt_worker::path_normalization::PathNormalization;
+use base::rt_worker::service_bundle;
+use base::rt_worker::worker_pool::{DuplicateCreatePolicy, SupervisorPolicy, WorkerPoolPolicy};
 use base::server::{ServerFlags, Tls, WorkerEntrypoints};
-use base::{DecoratorType, InspectorOption};
+use base::test_runner::{find_test_files, format_reports, run_test_file, TestReporter};
+use base::{
+    AcmeChallengeType, AcmeOptions, DecoratorType, InspectorOption, ReadyTarget, ResponseOverflow,
+    ShutdownReportTarget,
+};
+use base_rt::topology::{IsolateRuntimeFlavor, RuntimeTopology};
 use clap::ArgMatches;
+use deno_core::serde_json;
 use deno_core::url::Url;
 use env::resolve_deno_runtime_env;
+use event_worker::events::{LogEvent, LogLevel};
+use event_worker::routing::EventRoute;
 use flags::{get_cli, EszipV2ChecksumKind};
-use log::warn;
+use log::{error, info, warn};
+use sb_core::cache::deno_dir::DenoDir;
+use sb_core::cache::module_cache;
+use sb_core::cert::{init_default_outbound_tls, OutboundTlsOptions};
+use sb_core::replay::RecordingOptions;
 use sb_graph::emitter::EmitterFactory;
+use sb_graph::eszip_info::inspect_eszip;
+use sb_graph::graph_util::prefetch_import_map;
 use sb_graph::import_map::load_import_map;
-use sb_graph::{extract_from_file, generate_binary_eszip, include_glob_patterns_in_eszip};
+use sb_graph::{
+    extract_from_file, generate_binary_eszip, include_glob_patterns_in_eszip,
+    include_metadata_in_eszip, payload_to_eszip, EszipMetadata, EszipPayloadKind,
+};
+use sb_workers::context::UserWorkerRuntimeOpts;
 use std::fs::File;
 use std::io::Write;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+/// How often the module cache is shrunk to `--module-cache-max-size`.
+static MODULE_CACHE_GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
 fn main() -> Result<(), anyhow::Error> {
     resolve_deno_runtime_env();
 
-    let runtime = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .thread_name("sb-main")
-        .build()
+    let matches = get_cli().get_matches();
+
+    // NOTE: The topology must be set before any of the runtimes is built.
+    if let Some(("start", sub_matches)) = matches.subcommand() {
+        base_rt::topology::init_runtime_topology(RuntimeTopology {
+            dispatcher_threads: sub_matches.get_one::<usize>("dispatcher-threads").cloned(),
+            max_blocking_threads: sub_matches
+                .get_one::<usize>("max-blocking-threads")
+                .cloned(),
+            isolate_runtime_flavor: sub_matches
+                .get_one::<String>("isolate-runtime-flavor")
+                .unwrap()
+                .parse::<IsolateRuntimeFlavor>()?,
+        });
+    }
+
+    let runtime = base_rt::topology::get_runtime_topology()
+        .build_dispatcher_runtime()
         .unwrap();
 
     // TODO: Tokio runtime shouldn't be needed here (Address later)
     let local = tokio::task::LocalSet::new();
     let res: Result<(), Error> = local.block_on(&runtime, async {
-        let matches = get_cli().get_matches();
         let verbose = matches.get_flag("verbose");
 
         if !matches.get_flag("quiet") {
@@ -71,19 +122,56 @@ fn main() -> Result<(), anyhow::Error> {
                 let port = sub_matches.get_one::<u16>("port").copied().unwrap();
 
                 let maybe_tls = if let Some(port) = sub_matches.get_one::<u16>("tls").copied() {
-                    let Some((key_slice, cert_slice)) = sub_matches
-                        .get_one::<PathBuf>("key")
-                        .and_then(|it| std::fs::read(it).ok())
-                        .zip(
-                            sub_matches
-                                .get_one::<PathBuf>("cert")
-                                .and_then(|it| std::fs::read(it).ok()),
-                        )
-                    else {
-                        bail!("unable to load the key file or cert file");
+                    let acme_domains = sub_matches
+                        .get_many::<String>("acme-domain")
+                        .unwrap_or_default()
+                        .cloned()
+                        .collect::<Vec<_>>();
+
+                    let mut tls = if !acme_domains.is_empty() {
+                        Tls::acme(
+                            port,
+                            AcmeOptions {
+                                directory_url: sub_matches
+                                    .get_one::<String>("acme-directory")
+                                    .cloned()
+                                    .unwrap(),
+                                domains: acme_domains,
+                                contact_email: sub_matches.get_one::<String>("acme-email").cloned(),
+                                cache_dir: sub_matches
+                                    .get_one::<PathBuf>("acme-cache-dir")
+                                    .cloned()
+                                    .unwrap(),
+                                challenge_type: sub_matches
+                                    .get_one::<String>("acme-challenge")
+                                    .unwrap()
+                                    .parse::<AcmeChallengeType>()?,
+                                renew_before_days: sub_matches
+                                    .get_one::<u64>("acme-renew-before-days")
+                                    .copied()
+                                    .unwrap(),
+                            },
+                        )?
+                    } else {
+                        let Some((key_path, cert_path)) = sub_matches
+                            .get_one::<PathBuf>("key")
+                            .zip(sub_matches.get_one::<PathBuf>("cert"))
+                        else {
+                            bail!("unable to load the key file or cert file");
+                        };
+
+                        Tls::from_files(port, key_path, cert_path)?
                     };
 
-                    Some(Tls::new(port, &key_slice, &cert_slice)?)
+                    if let Some(path) = sub_matches.get_one::<PathBuf>("tls-ocsp-response") {
+                        tls = tls.with_ocsp_response(path)?;
+                    }
+
+                    if let Some(path) = sub_matches.get_one::<PathBuf>("internal-api-client-ca") {
+                        tls = tls.with_client_ca(path)?;
+                    }
+
+                    Some(tls)
                 } else {
                     None
                 };
@@ -92,13 +180,78 @@ fn main() -> Result<(), anyhow::Error> {
                     .get_one::<String>("main-service")
                     .cloned()
                     .unwrap();
+                let maybe_functions_dir = sub_matches.get_one::<String>("functions-dir").cloned();
+                let internal_api_auth = InternalApiAuth {
+                    bearer_token: sub_matches.get_one::<String>("internal-api-token").cloned(),
+                    allowed_cidrs: get_cidrs(sub_matches, "internal-api-allow-cidr")?,
+                    require_client_cert: sub_matches
+                        .get_one::<PathBuf>("internal-api-client-ca")
+                        .is_some(),
+                };
+                let client_ip_policy = ClientIpPolicy {
+                    trusted_proxies: get_cidrs(sub_matches, "trusted-proxy")?,
+                    allowed_cidrs: get_cidrs(sub_matches, "allow-client-cidr")?,
+                    denied_cidrs: get_cidrs(sub_matches, "deny-client-cidr")?,
+                };
                 let import_map_path = sub_matches.get_one::<String>("import-map").cloned();
+                let prefetch_import_maps = sub_matches
+                    .get_many::<String>("prefetch-import-map")
+                    .map(|it| it.cloned().collect::<Vec<_>>())
+                    .unwrap_or_default();
+                let maybe_prefetch_interval =
+                    sub_matches.get_one::<u64>("prefetch-interval").cloned();
 
                 let no_module_cache = sub_matches
                     .get_one::<bool>("disable-module-cache")
                     .cloned()
                     .unwrap();
 
+                let maybe_module_cache_max_size = sub_matches
+                    .get_one::<u64>("module-cache-max-size")
+                    .cloned()
+                    .filter(|_| !no_module_cache);
+
+                if let Some(max_size) = maybe_module_cache_max_size {
+                    let deps_dir = DenoDir::new(None)?.deps_folder_path();
+
+                    drop(tokio::task::spawn_local(async move {
+                        loop {
+                            let deps_dir = deps_dir.clone();
+                            let result = tokio::task::spawn_blocking(move || {
+                                module_cache::collect_garbage(&deps_dir, max_size * 1024 * 1024)
+                            })
+                            .await;
+
+                            match result {
+                                Ok(Ok(summary)) if summary.removed > 0 => info!(
+                                    "removed {} modules ({} bytes) from the module cache",
+                                    summary.removed, summary.freed
+                                ),
+
+                                Ok(Err(err)) => error!("failed to collect module cache: {err:#}"),
+                                _ => {}
+                            }
+
+                            tokio::time::sleep(MODULE_CACHE_GC_INTERVAL).await;
+                        }
+                    }));
+                }
+
+                if no_module_cache && !prefetch_import_maps.is_empty() {
+                    warn!("the module cache is disabled; import maps are not prefetched");
+                } else if !prefetch_import_maps.is_empty() {
+                    prefetch_modules(&prefetch_import_maps).await;
+
+                    if let Some(interval) = maybe_prefetch_interval.filter(|it| *it > 0) {
+                        drop(tokio::task::spawn_local(async move {
+                            loop {
+                                tokio::time::sleep(Duration::from_secs(interval)).await;
+                                prefetch_modules(&prefetch_import_maps).await;
+                            }
+                        }));
+                    }
+                }
+
                 let allow_main_inspector = sub_matches
                     .get_one::<bool>("inspect-main")
                     .cloned()
@@ -144,14 +297,141 @@ fn main() -> Result<(), anyhow::Error> {
                     .cloned()
                     .unwrap_or(0);
 
+                let maybe_events_backlog_limit = sub_matches
+                    .get_one::<usize>("events-backlog-limit")
+                    .cloned();
+                let maybe_lifecycle_history_size = sub_matches
+                    .get_one::<usize>("lifecycle-history-size")
+                    .cloned();
                 let maybe_max_parallelism =
                     sub_matches.get_one::<usize>("max-parallelism").cloned();
+                let maybe_pool_state_path =
+                    sub_matches.get_one::<PathBuf>("pool-state-file").cloned();
+                let maybe_request_journal_path =
+                    sub_matches.get_one::<PathBuf>("request-journal").cloned();
+                let maybe_recording_path = sub_matches.get_one::<PathBuf>("record").cloned();
+                let recording_opts = RecordingOptions {
+                    max_body_size: sub_matches
+                        .get_one::<usize>("record-max-body-size")
+                        .cloned()
+                        .unwrap()
+                        * 1024,
+                    record_sensitive_headers: sub_matches.get_flag("record-sensitive-headers"),
+                };
+                let maybe_jwt_secret = sub_matches.get_one::<String>("jwt-secret").cloned();
+                let outbound_tls = OutboundTlsOptions {
+                    min_version: sub_matches
+                        .get_one::<String>("outbound-tls-min-version")
+                        .map(|it| it.parse())
+                        .transpose()?,
+                    max_version: sub_matches
+                        .get_one::<String>("outbound-tls-max-version")
+                        .map(|it| it.parse())
+                        .transpose()?,
+                    cipher_suites: sub_matches
+                        .get_many::<String>("outbound-tls-cipher-suite")
+                        .map(|it| it.cloned().collect()),
+                    ..Default::default()
+                };
+
+                if outbound_tls != OutboundTlsOptions::default() {
+                    outbound_tls
+                        .validate()
+                        .map_err(|err| anyhow!("invalid outbound TLS options: {:#}", err))?;
+
+                    init_default_outbound_tls(outbound_tls);
+                }
                 let maybe_request_wait_timeout =
                     sub_matches.get_one::<u64>("request-wait-timeout").cloned();
                 let maybe_request_idle_timeout =
                     sub_matches.get_one::<u64>("request-idle-timeout").cloned();
                 let maybe_request_read_timeout =
                     sub_matches.get_one::<u64>("request-read-timeout").cloned();
+                let maybe_request_body_idle_timeout = sub_matches
+                    .get_one::<u64>("request-body-idle-timeout")
+                    .cloned();
+                let maybe_request_body_min_rate =
+                    sub_matches.get_one::<u64>("request-body-min-rate").cloned();
+                let maybe_max_connections_per_ip = sub_matches
+                    .get_one::<usize>("max-connections-per-ip")
+                    .cloned();
+                let maybe_response_buffer_size = sub_matches
+                    .get_one::<usize>("response-buffer-size")
+                    .cloned();
+                let response_buffer_overflow = sub_matches
+                    .get_one::<String>("response-buffer-overflow")
+                    .unwrap()
+                    .parse::<ResponseOverflow>()?;
+                let duplicate_create = sub_matches
+                    .get_one::<String>("duplicate-create")
+                    .unwrap()
+                    .parse::<DuplicateCreatePolicy>()?;
+                let maybe_response_spill_quota =
+                    sub_matches.get_one::<u64>("response-spill-quota").cloned();
+                let maybe_request_log_size =
+                    sub_matches.get_one::<usize>("request-log-size").cloned();
+                let maybe_trace_buffer_size =
+                    sub_matches.get_one::<usize>("trace-buffer-size").cloned();
+                let maybe_max_header_size =
+                    sub_matches.get_one::<usize>("max-header-size").cloned();
+                let maybe_max_uri_length = sub_matches.get_one::<usize>("max-uri-length").cloned();
+                let maybe_tls_max_header_size =
+                    sub_matches.get_one::<usize>("tls-max-header-size").cloned();
+                let maybe_tls_max_uri_length =
+                    sub_matches.get_one::<usize>("tls-max-uri-length").cloned();
+                let path_normalization = sub_matches
+                    .get_one::<String>("path-normalization")
+                    .unwrap()
+                    .parse::<PathNormalization>()?;
+                let maybe_dns_cache_size = sub_matches.get_one::<usize>("dns-cache-size").cloned();
+                let maybe_dns_cache_max_ttl =
+                    sub_matches.get_one::<u64>("dns-cache-max-ttl").cloned();
+                let maybe_dns_cache_negative_ttl = sub_matches
+                    .get_one::<u64>("dns-cache-negative-ttl")
+                    .cloned();
+                let maybe_outbound_pool_max_idle_per_host = sub_matches
+                    .get_one::<usize>("outbound-pool-max-idle-per-host")
+                    .cloned();
+                let maybe_outbound_pool_idle_timeout = sub_matches
+                    .get_one::<u64>("outbound-pool-idle-timeout")
+                    .cloned();
+                let maybe_worker_drain_timeout =
+                    sub_matches.get_one::<u64>("worker-drain-timeout").cloned();
+                let maybe_tls_cert_check_interval = sub_matches
+                    .get_one::<u64>("tls-cert-check-interval")
+                    .cloned();
+                let maybe_tls_cert_expiry_warn_days = sub_matches
+                    .get_one::<u64>("tls-cert-expiry-warn-days")
+                    .cloned();
+                let maybe_main_worker_memory_limit = sub_matches
+                    .get_one::<u64>("main-worker-memory-limit")
+                    .cloned();
+                let maybe_main_worker_unresponsive_timeout = sub_matches
+                    .get_one::<u64>("main-worker-unresponsive-timeout")
+                    .cloned();
+                let maybe_max_concurrent_boots = sub_matches
+                    .get_one::<usize>("max-concurrent-boots")
+                    .cloned();
+                let maybe_max_concurrent_requests = sub_matches
+                    .get_one::<usize>("max-concurrent-requests")
+                    .cloned();
+                let maybe_batch_max_concurrent_requests = sub_matches
+                    .get_one::<usize>("batch-max-concurrent-requests")
+                    .cloned();
+                let maybe_batch_request_wait_timeout = sub_matches
+                    .get_one::<u64>("batch-request-wait-timeout")
+                    .cloned();
+                let maybe_batch_cpu_budget =
+                    sub_matches.get_one::<u64>("batch-cpu-budget").cloned();
+                let maybe_service_stats_interval = sub_matches
+                    .get_one::<u64>("service-stats-interval")
+                    .cloned();
+                let maybe_runtime_stats_interval = sub_matches
+                    .get_one::<u64>("runtime-stats-interval")
+                    .cloned();
+                let maybe_concurrency_sample_interval = sub_matches
+                    .get_one::<u64>("concurrency-sample-interval")
+                    .cloned();
                 let static_patterns =
                     if let Some(val_ref) = sub_matches.get_many::<String>("static") {
                         val_ref.map(|s| s.as_str()).collect::<Vec<&str>>()
@@ -178,6 +458,83 @@ fn main() -> Result<(), anyhow::Error> {
                     None
                 };
 
+                if let Some(dir) = sub_matches.get_one::<PathBuf>("crash-dump-dir") {
+                    base::crash_dump::install(dir)?;
+                }
+
+                if let Some(url) = sub_matches.get_one::<Url>("event-webhook-url") {
+                    let transitions =
+                        match sub_matches.get_many::<String>("event-webhook-transition") {
+                            Some(it) => it
+                                .map(|it| it.parse::<WebhookTransition>())
+                                .collect::<Result<Vec<_>, _>>()?,
+                            None => WebhookTransition::DEFAULT.to_vec(),
+                        };
+
+                    event_webhook::install(EventWebhookOpts {
+                        url: url.clone(),
+                        secret: sub_matches
+                            .get_one::<String>("event-webhook-secret")
+                            .cloned(),
+                        transitions,
+                        max_retries: sub_matches
+                            .get_one::<u32>("event-webhook-max-retries")
+                            .cloned()
+                            .unwrap(),
+                    })?;
+                }
+
+                if let Some(path) = sub_matches.get_one::<PathBuf>("task-queue-db") {
+                    sb_workers::task_queue::install(path.clone())?;
+                }
+
+                if let Some(dir) = sub_matches.get_one::<PathBuf>("bundle-cache-dir") {
+                    service_bundle::set_cache_dir(dir.clone());
+                }
+
+                if let Some(keys) = sub_matches.get_many::<String>("bundle-public-key") {
+                    bundle_signature::require_signed_bundles(BundleVerifier::new(keys)?);
+                }
+
+                let main_service_path =
+                    service_bundle::resolve_service_path(Path::new(&main_service_path))
+                        .await?
+                        .to_string_lossy()
+                        .into_owned();
+
+                let event_service_manager_path = match event_service_manager_path {
+                    Some(path) => Some(
+                        service_bundle::resolve_service_path(Path::new(&path))
+                            .await?
+                            .to_string_lossy()
+                            .into_owned(),
+                    ),
+                    None => None,
+                };
+
+                let mut event_worker_routes = vec![];
+
+                for (route, path) in get_event_worker_routes(sub_matches)? {
+                    event_worker_routes.push((
+                        route,
+                        service_bundle::resolve_service_path(Path::new(&path))
+                            .await?
+                            .to_string_lossy()
+                            .into_owned(),
+                    ));
+                }
+
+                let maybe_ready_target = sub_matches
+                    .get_one::<i32>("ready-fd")
+                    .copied()
+                    .map(ReadyTarget::Fd)
+                    .or_else(|| {
+                        sub_matches
+                            .get_one::<PathBuf>("ready-file")
+                            .cloned()
+                            .map(ReadyTarget::File)
+                    });
+
                 let tcp_nodelay = sub_matches.get_one::<bool>("tcp-nodelay").copied().unwrap();
                 let flags = ServerFlags {
                     no_module_cache,
@@ -186,42 +543,157 @@ fn main() -> Result<(), anyhow::Error> {
                     graceful_exit_deadline_sec,
                     graceful_exit_keepalive_deadline_ms,
                     event_worker_exit_deadline_sec,
+                    events_backlog_limit: maybe_events_backlog_limit,
+                    lifecycle_history_size: maybe_lifecycle_history_size,
+                    router: sub_matches.get_flag("router"),
                     request_wait_timeout_ms: maybe_request_wait_timeout,
                     request_idle_timeout_ms: maybe_request_idle_timeout,
                     request_read_timeout_ms: maybe_request_read_timeout,
+                    request_body_idle_timeout_ms: maybe_request_body_idle_timeout,
+                    request_body_min_rate: maybe_request_body_min_rate,
+                    max_connections_per_ip: maybe_max_connections_per_ip,
+                    hold_requests_until_ready: sub_matches.get_flag("hold-requests-until-ready"),
+                    startup_queue_size: sub_matches.get_one::<usize>("startup-queue-size").copied(),
+                    startup_queue_timeout_ms: sub_matches
+                        .get_one::<u64>("startup-queue-timeout")
+                        .copied(),
+                    task_queue_visibility_timeout_ms: sub_matches
+                        .get_one::<u64>("task-queue-visibility-timeout")
+                        .copied(),
+                    response_buffer_size: maybe_response_buffer_size,
+                    response_buffer_overflow,
+                    response_spill_quota: maybe_response_spill_quota,
+                    request_coalescing: sub_matches.get_flag("request-coalescing"),
+                    duplicate_create,
+                    max_concurrent_boots: maybe_max_concurrent_boots,
+                    max_concurrent_requests: maybe_max_concurrent_requests,
+                    batch_max_concurrent_requests: maybe_batch_max_concurrent_requests,
+                    batch_request_wait_timeout_ms: maybe_batch_request_wait_timeout,
+                    batch_cpu_budget_ms: maybe_batch_cpu_budget,
+                    share_code_cache: sub_matches.get_flag("share-code-cache"),
+                    server_timing: sub_matches.get_flag("server-timing"),
+                    shed_memory_threshold_pct: sub_matches
+                        .get_one::<u8>("shed-memory-threshold")
+                        .copied(),
+                    shed_cpu_time_threshold_pct: sub_matches
+                        .get_one::<u8>("shed-cpu-time-threshold")
+                        .copied(),
+                    shed_queue_depth: sub_matches.get_one::<usize>("shed-queue-depth").copied(),
+                    shed_retry_after_sec: sub_matches.get_one::<u64>("shed-retry-after").copied(),
+                    boot_min_free_memory_mb: sub_matches
+                        .get_one::<u64>("boot-min-free-memory")
+                        .copied(),
+                    boot_max_load_avg: sub_matches.get_one::<f64>("boot-max-load-avg").copied(),
+                    boot_memory_budget_mb: sub_matches
+                        .get_one::<u64>("boot-memory-budget")
+                        .copied(),
+                    failure_injection: sub_matches.get_flag("enable-failure-injection"),
+                    clock_granularity_ms: sub_matches.get_one::<u64>("clock-granularity").copied(),
+                    grpc_control_plane_addr: sub_matches
+                        .get_one::<SocketAddr>("grpc-control-plane-addr")
+                        .copied(),
+                    service_stats_interval_ms: maybe_service_stats_interval,
+                    runtime_stats_interval_ms: maybe_runtime_stats_interval,
+                    concurrency_sample_interval_ms: maybe_concurrency_sample_interval,
+                    worker_drain_timeout_ms: maybe_worker_drain_timeout,
+                    request_log_size: maybe_request_log_size,
+                    trace_buffer_size: maybe_trace_buffer_size,
+                    dns_cache_size: maybe_dns_cache_size,
+                    dns_cache_max_ttl_sec: maybe_dns_cache_max_ttl,
+                    dns_cache_negative_ttl_sec: maybe_dns_cache_negative_ttl,
+                    outbound_pool: sub_matches.get_flag("outbound-pool"),
+                    outbound_pool_max_idle_per_host: maybe_outbound_pool_max_idle_per_host,
+                    outbound_pool_idle_timeout_sec: maybe_outbound_pool_idle_timeout,
+                    outbound_pool_http1_only: sub_matches.get_flag("outbound-pool-http1-only"),
+                    main_worker_memory_limit_mb: maybe_main_worker_memory_limit,
+                    main_worker_unresponsive_timeout_ms: maybe_main_worker_unresponsive_timeout,
+                    tls_cert_check_interval_sec: maybe_tls_cert_check_interval,
+                    tls_cert_expiry_warn_days: maybe_tls_cert_expiry_warn_days,
+                    max_header_size: maybe_max_header_size,
+                    max_uri_length: maybe_max_uri_length,
+                    tls_max_header_size: maybe_tls_max_header_size,
+                    tls_max_uri_length: maybe_tls_max_uri_length,
+                    path_normalization,
+                    protocol_sniffing: sub_matches.get_flag("protocol-sniffing"),
+                    acceptors: sub_matches.get_one::<usize>("acceptors").copied(),
+                    process_title: sub_matches.get_flag("process-title"),
+                    http3: sub_matches.get_flag("http3"),
+                    io_uring: sub_matches.get_flag("io-uring"),
                 };
 
+                let user_worker_policy = WorkerPoolPolicy::new(
+                    maybe_supervisor_policy,
+                    if let Some(true) = maybe_supervisor_policy
+                        .as_ref()
+                        .map(SupervisorPolicy::is_oneshot)
+                    {
+                        if let Some(parallelism) = maybe_max_parallelism {
+                            if parallelism == 0 || parallelism > 1 {
+                                warn!(
+                                    "{}",
+                                    concat!(
+                                        "if `oneshot` policy is enabled, the maximum ",
+                                        "parallelism is fixed to `1` as forcibly"
+                                    )
+                                );
+                            }
+                        }
+
+                        Some(1)
+                    } else {
+                        maybe_max_parallelism
+                    },
+                    flags,
+                )
+                .with_state_path(maybe_pool_state_path)
+                .with_request_journal_path(maybe_request_journal_path)
+                .with_recording_path(maybe_recording_path)
+                .with_recording_options(recording_opts)
+                .with_jwt_secret(maybe_jwt_secret.as_deref());
+
+                if let Some(service_path) = sub_matches.get_one::<String>("one-shot") {
+                    let service_path =
+                        service_bundle::resolve_service_path(Path::new(service_path)).await?;
+                    let mut runtime_opts = UserWorkerRuntimeOpts::default();
+                    let mut input = vec![];
+
+                    // NOTE: The worker is booted as the pool would boot it.
+                    user_worker_policy.apply_to_runtime_opts(&mut runtime_opts);
+                    tokio::io::stdin().read_to_end(&mut input).await?;
+
+                    let outcome =
+                        run_one_shot(&input, service_path, runtime_opts, import_map_path).await;
+
+                    // NOTE: What the worker logged must not end up in the
+                    // response, which may be piped to another tool.
+                    for log in outcome.logs.iter() {
+                        eprintln!("{}", log.msg.trim_end());
+                    }
+
+                    if let Some(err) = outcome.error.as_ref() {
+                        eprintln!("{err}");
+                    }
+
+                    let mut stdout = std::io::stdout().lock();
+
+                    stdout.write_all(&outcome.output)?;
+                    stdout.flush()?;
+
+                    std::process::exit(outcome.exit.code());
+                }
+
                 start_server(
                     ip.as_str(),
                     port,
                     maybe_tls,
                     main_service_path,
+                    maybe_functions_dir,
+                    Some(internal_api_auth),
+                    Some(client_ip_policy),
                     event_service_manager_path,
+                    event_worker_routes,
                     get_decorator_option(sub_matches),
-                    Some(WorkerPoolPolicy::new(
-                        maybe_supervisor_policy,
-                        if let Some(true) = maybe_supervisor_policy
-                            .as_ref()
-                            .map(SupervisorPolicy::is_oneshot)
-                        {
-                            if let Some(parallelism) = maybe_max_parallelism {
-                                if parallelism == 0 || parallelism > 1 {
-                                    warn!(
-                                        "{}",
-                                        concat!(
-                                            "if `oneshot` policy is enabled, the maximum ",
-                                            "parallelism is fixed to `1` as forcibly"
-                                        )
-                                    );
-                                }
-                            }
-
-                            Some(1)
-                        } else {
-                            maybe_max_parallelism
-                        },
-                        flags,
-                    )),
+                    Some(user_worker_policy),
                     import_map_path,
                     flags,
                     None,
@@ -234,6 +706,11 @@ fn main() -> Result<(), anyhow::Error> {
                     maybe_inspector_option,
                     jsx_specifier,
                     jsx_module,
+                    maybe_ready_target,
+                    sub_matches.get_one::<PathBuf>("config").cloned(),
+                    sub_matches
+                        .get_one::<String>("shutdown-report")
+                        .map(|it| ShutdownReportTarget::parse(it)),
                 )
                 .await?;
             }
@@ -248,12 +725,15 @@ fn main() -> Result<(), anyhow::Error> {
                         vec![]
                     };
 
-                let entrypoint_script_path = sub_matches
-                    .get_one::<String>("entrypoint")
-                    .cloned()
-                    .unwrap();
+                let maybe_service_path = sub_matches.get_one::<PathBuf>("SERVICE_PATH").cloned();
+                let entrypoint_script_path = match maybe_service_path.as_ref() {
+                    Some(service_path) => find_service_entrypoint(service_path)?,
+                    None => sub_matches
+                        .get_one::<String>("entrypoint")
+                        .map(PathBuf::from)
+                        .unwrap(),
+                };
 
-                let entrypoint_script_path = PathBuf::from(entrypoint_script_path.as_str());
                 if !entrypoint_script_path.is_file() {
                     bail!(
                         "entrypoint path does not exist ({})",
@@ -297,6 +777,25 @@ fn main() -> Result<(), anyhow::Error> {
                 include_glob_patterns_in_eszip(static_patterns, &mut eszip, entrypoint_dir_path)
                     .await?;
 
+                let bundle_root_path = match maybe_service_path.as_ref() {
+                    Some(service_path) => service_path.canonicalize()?,
+                    None => entrypoint_dir_path.to_path_buf(),
+                };
+                let maybe_manifest = FunctionManifest::load(&bundle_root_path).await?;
+
+                include_metadata_in_eszip(
+                    &EszipMetadata {
+                        entrypoint: EszipMetadata::relative_entrypoint(
+                            &entrypoint_script_path,
+                            &bundle_root_path,
+                        ),
+                        env_schema: maybe_manifest
+                            .and_then(|it| it.env_allowlist)
+                            .unwrap_or_default(),
+                    },
+                    &mut eszip,
+                )?;
+
                 let bin = eszip.into_bytes();
 
                 if output_path == "-" {
@@ -309,6 +808,79 @@ fn main() -> Result<(), anyhow::Error> {
                     file.write_all(&bin)?
                 }
             }
+            Some(("check", sub_matches)) => {
+                let path = sub_matches.get_one::<PathBuf>("PATH").cloned().unwrap();
+                let import_map_path = sub_matches.get_one::<String>("import-map").cloned();
+                let maybe_decorator = get_decorator_option(sub_matches);
+                let allow_remote_modules = !sub_matches.get_flag("disallow-remote-modules");
+
+                let report = check_service(
+                    &path,
+                    import_map_path,
+                    maybe_decorator,
+                    allow_remote_modules,
+                )
+                .await?;
+
+                if let Some(entrypoint) = report.entrypoint {
+                    println!("Entrypoint: {}", entrypoint);
+                }
+
+                println!(
+                    "Checked {} modules in {}",
+                    report.module_count,
+                    path.display()
+                );
+            }
+            Some(("cache", sub_matches)) => {
+                let deps_dir = DenoDir::new(None)?.deps_folder_path();
+
+                if sub_matches.get_flag("clear") {
+                    let entries = module_cache::list_entries(&deps_dir)?;
+
+                    for entry in entries.iter() {
+                        module_cache::remove_entry(entry)?;
+                    }
+
+                    println!(
+                        "Removed {} modules ({} bytes)",
+                        entries.len(),
+                        entries.iter().map(|it| it.size).sum::<u64>()
+                    );
+
+                    return Ok(());
+                }
+
+                if sub_matches.get_flag("verify") {
+                    for entry in module_cache::find_corrupted_entries(&deps_dir)? {
+                        module_cache::remove_entry(&entry)?;
+                        println!(
+                            "Removed corrupted module: {}",
+                            entry
+                                .url
+                                .unwrap_or_else(|| entry.path.display().to_string())
+                        );
+                    }
+                }
+
+                if let Some(max_size) = sub_matches.get_one::<u64>("max-size").cloned() {
+                    let summary = module_cache::collect_garbage(&deps_dir, max_size * 1024 * 1024)?;
+
+                    println!(
+                        "Removed {} modules ({} bytes)",
+                        summary.removed, summary.freed
+                    );
+                }
+
+                let entries = module_cache::list_entries(&deps_dir)?;
+
+                println!("Location: {}", deps_dir.display());
+                println!("Modules: {}", entries.len());
+                println!(
+                    "Size: {} bytes",
+                    entries.iter().map(|it| it.size).sum::<u64>()
+                );
+            }
             Some(("unbundle", sub_matches)) => {
                 let output_path = sub_matches.get_one::<String>("output").cloned().unwrap();
                 let eszip_path = sub_matches.get_one::<String>("eszip").cloned().unwrap();
@@ -323,6 +895,210 @@ fn main() -> Result<(), anyhow::Error> {
                     );
                 }
             }
+            Some(("inspect-eszip", sub_matches)) => {
+                let path = sub_matches.get_one::<PathBuf>("PATH").cloned().unwrap();
+                let info = inspect_eszip(EszipPayloadKind::VecKind(std::fs::read(&path)?))
+                    .await
+                    .with_context(|| format!("failed to inspect eszip: {}", path.display()))?;
+
+                if sub_matches.get_flag("json") {
+                    println!("{}", serde_json::to_string_pretty(&info)?);
+                    return Ok(());
+                }
+
+                println!("Size: {} bytes", info.size);
+                println!(
+                    "Version: {}",
+                    info.version.as_deref().unwrap_or("(unversioned)")
+                );
+                println!("Checksum: {}", info.checksum.as_deref().unwrap_or("(none)"));
+                println!(
+                    "Entrypoint: {}",
+                    info.entrypoint.as_deref().unwrap_or("(unknown)")
+                );
+
+                if let Some(metadata) = info.metadata.as_ref() {
+                    if !metadata.env_schema.is_empty() {
+                        println!("Env: {}", metadata.env_schema.join(", "));
+                    }
+                }
+
+                println!(
+                    "Modules: {} ({} bytes)",
+                    info.modules.len(),
+                    info.total_source_size()
+                );
+
+                for module in &info.modules {
+                    println!(
+                        "  {:>10}  {:<10}  {}",
+                        module.source_size,
+                        format!("{:?}", module.kind).to_lowercase(),
+                        module.specifier
+                    );
+                }
+
+                for redirect in &info.redirects {
+                    println!("  {} -> {}", redirect.specifier, redirect.target);
+                }
+
+                println!("Npm packages: {}", info.npm_packages.len());
+
+                for pkg in &info.npm_packages {
+                    println!("  {pkg}");
+                }
+
+                println!("Static files: {}", info.static_files.len());
+
+                for file in &info.static_files {
+                    println!("  {file}");
+                }
+
+                println!("VFS: {} bytes", info.vfs_size);
+            }
+            Some(("eval", sub_matches)) => {
+                let code = sub_matches.get_one::<String>("CODE").cloned().unwrap();
+                let mut session = ReplSession::new(get_limited_runtime_opts(sub_matches)).await?;
+                let outcome = session.eval(&code).await?;
+
+                print_repl_logs(&outcome.logs);
+
+                let (logs, usage) = session.close().await;
+
+                print_repl_logs(&logs);
+                print_repl_usage(&usage);
+
+                if !outcome.succeeded {
+                    bail!("evaluation failed");
+                }
+            }
+            Some(("repl", sub_matches)) => {
+                let mut session = ReplSession::new(get_limited_runtime_opts(sub_matches)).await?;
+                let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+                loop {
+                    print!("> ");
+                    std::io::stdout().flush()?;
+
+                    let Some(line) = lines.next_line().await? else {
+                        println!();
+                        break;
+                    };
+
+                    let line = line.trim();
+
+                    if line == ".exit" {
+                        break;
+                    }
+
+                    print_repl_logs(&session.take_logs());
+
+                    if line.is_empty() {
+                        continue;
+                    }
+
+                    match session.eval(line).await {
+                        Ok(outcome) => print_repl_logs(&outcome.logs),
+                        Err(err) => {
+                            eprintln!("{:#}", err);
+                            break;
+                        }
+                    }
+                }
+
+                let (logs, usage) = session.close().await;
+
+                print_repl_logs(&logs);
+                print_repl_usage(&usage);
+            }
+            Some(("test", sub_matches)) => {
+                let service_path = sub_matches
+                    .get_one::<String>("SERVICE_PATH")
+                    .cloned()
+                    .unwrap();
+                let reporter = sub_matches
+                    .get_one::<String>("reporter")
+                    .map(|it| TestReporter::from_str(it))
+                    .transpose()?
+                    .unwrap_or_default();
+                let import_map_path = sub_matches.get_one::<String>("import-map").cloned();
+                let runtime_opts = if sub_matches.get_flag("limits") {
+                    get_limited_runtime_opts(sub_matches)
+                } else {
+                    UserWorkerRuntimeOpts {
+                        worker_timeout_ms: 0,
+                        cpu_time_soft_limit_ms: 0,
+                        cpu_time_hard_limit_ms: 0,
+                        ..get_limited_runtime_opts(sub_matches)
+                    }
+                };
+
+                let test_files = find_test_files(Path::new(&service_path))?;
+
+                if test_files.is_empty() {
+                    bail!("could not find any test file in ({})", service_path);
+                }
+
+                let mut reports = vec![];
+
+                for path in test_files {
+                    let report =
+                        run_test_file(&path, runtime_opts.clone(), import_map_path.clone()).await;
+
+                    // NOTE: What the tests logged must not end up in the report,
+                    // which may be piped to another tool.
+                    for log in report.logs.iter() {
+                        eprintln!("{}", log.msg.trim_end());
+                    }
+
+                    reports.push(report);
+                }
+
+                print!("{}", format_reports(reporter, &reports));
+
+                if reports.iter().any(|it| it.is_failed()) {
+                    bail!("some tests failed");
+                }
+            }
+            Some(("replay", sub_matches)) => {
+                let recording_path = sub_matches
+                    .get_one::<PathBuf>("RECORDING")
+                    .cloned()
+                    .unwrap();
+                let service_path = sub_matches
+                    .get_one::<String>("SERVICE_PATH")
+                    .cloned()
+                    .unwrap();
+                let maybe_recorded_service_path = sub_matches.get_one::<String>("service").cloned();
+                let seed = sub_matches.get_one::<u64>("seed").cloned().unwrap();
+                let import_map_path = sub_matches.get_one::<String>("import-map").cloned();
+
+                let report = replay_recording(
+                    &recording_path,
+                    maybe_recorded_service_path.as_deref(),
+                    PathBuf::from(service_path),
+                    seed,
+                    get_limited_runtime_opts(sub_matches),
+                    import_map_path,
+                )
+                .await?;
+
+                // NOTE: What the worker logged must not end up in the report,
+                // which may be piped to another tool.
+                for log in report.logs.iter() {
+                    eprintln!("{}", log.msg.trim_end());
+                }
+
+                print!("{}", format_replay_report(&report));
+
+                if let Some(reason) = report.shutdown_reason.as_ref() {
+                    eprintln!("worker shut down: {reason}");
+                }
+
+                if report.is_failed() {
+                    bail!("some responses changed");
+                }
+            }
             _ => {
                 // unrecognized command
             }
@@ -333,6 +1109,151 @@ fn main() -> Result<(), anyhow::Error> {
     res
 }
 
+struct CheckReport {
+    module_count: usize,
+    /// The entrypoint recorded in the metadata of an eszip.
+    entrypoint: Option<String>,
+}
+
+/// Loads the module graph of a service directory, an entrypoint or an eszip,
+/// failing the same way the boot of a worker would.
+async fn check_service(
+    path: &Path,
+    import_map_path: Option<String>,
+    maybe_decorator: Option<DecoratorType>,
+    allow_remote_modules: bool,
+) -> Result<CheckReport, anyhow::Error> {
+    if path.extension().map_or(false, |it| it == "eszip") {
+        let mut eszip = payload_to_eszip(EszipPayloadKind::VecKind(std::fs::read(path)?))
+            .await
+            .map_err(|e| anyhow!("failed to parse eszip ({}): {:#}", path.display(), e))?;
+
+        eszip
+            .ensure_read_all()
+            .await
+            .map_err(|e| anyhow!("failed to load eszip ({}): {}", path.display(), e))?;
+        eszip.ensure_version().await?;
+
+        return Ok(CheckReport {
+            entrypoint: eszip.ensure_metadata().await?.and_then(|it| it.entrypoint),
+            module_count: eszip.specifiers().len(),
+        });
+    }
+
+    let entrypoint_script_path = if path.is_dir() {
+        find_service_entrypoint(path)?
+    } else if path.is_file() {
+        path.to_path_buf()
+    } else {
+        bail!("path does not exist ({})", path.display());
+    };
+
+    let entrypoint_script_path = entrypoint_script_path.canonicalize()?;
+
+    let mut emitter_factory = EmitterFactory::new();
+    let maybe_import_map = load_import_map(import_map_path.clone())
+        .map_err(|e| anyhow!("import map path is invalid ({})", e))?;
+    let mut maybe_import_map_url = None;
+    if maybe_import_map.is_some() {
+        let abs_import_map_path =
+            std::env::current_dir().map(|p| p.join(import_map_path.unwrap()))?;
+        maybe_import_map_url = Some(
+            Url::from_file_path(abs_import_map_path)
+                .map_err(|_| anyhow!("failed get import map url"))?
+                .to_string(),
+        );
+    }
+
+    emitter_factory.set_decorator_type(maybe_decorator);
+    emitter_factory.set_import_map(maybe_import_map);
+    emitter_factory.set_file_fetcher_allow_remote(allow_remote_modules);
+
+    let eszip = generate_binary_eszip(
+        &entrypoint_script_path,
+        Arc::new(emitter_factory),
+        None,
+        maybe_import_map_url,
+        None,
+    )
+    .await
+    .map_err(|e| {
+        anyhow!(
+            "failed to load the module graph ({}): {:#}",
+            entrypoint_script_path.display(),
+            e
+        )
+    })?;
+
+    Ok(CheckReport {
+        module_count: eszip.specifiers().len(),
+        entrypoint: None,
+    })
+}
+
+fn find_service_entrypoint(service_path: &Path) -> Result<PathBuf, anyhow::Error> {
+    ["ts", "tsx", "js", "mjs", "jsx"]
+        .iter()
+        .map(|ext| service_path.join(format!("index.{}", ext)))
+        .find(|it| it.is_file())
+        .ok_or_else(|| {
+            anyhow!(
+                "could not find an entrypoint in ({})",
+                service_path.display()
+            )
+        })
+}
+
+fn get_limited_runtime_opts(sub_matches: &ArgMatches) -> UserWorkerRuntimeOpts {
+    let cpu_time_limit_ms = sub_matches
+        .get_one::<u64>("cpu-time-limit")
+        .cloned()
+        .unwrap();
+
+    UserWorkerRuntimeOpts {
+        memory_limit_mb: sub_matches.get_one::<u64>("memory-limit").cloned().unwrap(),
+        worker_timeout_ms: sub_matches
+            .get_one::<u64>("wall-clock-limit")
+            .cloned()
+            .unwrap(),
+        cpu_time_soft_limit_ms: cpu_time_limit_ms / 2,
+        cpu_time_hard_limit_ms: cpu_time_limit_ms,
+        ..Default::default()
+    }
+}
+
+fn print_repl_logs(logs: &[LogEvent]) {
+    for log in logs {
+        match log.level {
+            LogLevel::Warning | LogLevel::Error => eprintln!("{}", log.msg.trim_end()),
+            LogLevel::Debug | LogLevel::Info => println!("{}", log.msg.trim_end()),
+        }
+    }
+}
+
+fn print_repl_usage(usage: &ReplUsage) {
+    let maybe_mib = |it: Option<usize>| {
+        it.map(|it| format!("{:.2} MiB", it as f64 / (1024.0 * 1024.0)))
+            .unwrap_or_else(|| "unknown".to_string())
+    };
+
+    eprintln!();
+    eprintln!("Evaluations: {}", usage.evaluations);
+    eprintln!("Wall clock time: {}ms", usage.wall_clock_time.as_millis());
+    eprintln!(
+        "CPU time: {}",
+        usage
+            .cpu_time_used_ms
+            .map(|it| format!("{}ms", it))
+            .unwrap_or_else(|| "unknown".to_string())
+    );
+    eprintln!("Heap: {}", maybe_mib(usage.heap_bytes));
+    eprintln!("External memory: {}", maybe_mib(usage.external_bytes));
+
+    if let Some(reason) = usage.shutdown_reason.as_ref() {
+        eprintln!("Shutdown reason: {}", reason);
+    }
+}
+
 fn get_decorator_option(sub_matches: &ArgMatches) -> Option<DecoratorType> {
     sub_matches
         .get_one::<String>("decorator")
@@ -345,6 +1266,52 @@ fn get_decorator_option(sub_matches: &ArgMatches) -> Option<DecoratorType> {
         })
 }
 
+fn get_cidrs<T>(sub_matches: &ArgMatches, key: &str) -> Result<Vec<T>, anyhow::Error>
+where
+    T: FromStr,
+    T::Err: std::fmt::Debug,
+{
+    sub_matches
+        .get_many::<String>(key)
+        .into_iter()
+        .flatten()
+        .map(|it| {
+            it.parse()
+                .map_err(|err| anyhow!("invalid CIDR `{it}`: {err:?}"))
+        })
+        .collect()
+}
+
+fn get_event_worker_routes(
+    sub_matches: &ArgMatches,
+) -> Result<Vec<(EventRoute, String)>, anyhow::Error> {
+    sub_matches
+        .get_many::<String>("event-worker-route")
+        .into_iter()
+        .flatten()
+        .map(|it| {
+            let Some((route, path)) = it.split_once('=') else {
+                bail!("invalid event worker route `{it}`: expected `<ROUTE>=<PATH>`");
+            };
+
+            Ok((route.parse()?, path.to_string()))
+        })
+        .collect()
+}
+
+async fn prefetch_modules(import_map_paths: &[String]) {
+    for path in import_map_paths {
+        match prefetch_import_map(path.clone()).await {
+            Ok(summary) => info!(
+                "prefetched import map {} ({} modules, {} of {} roots failed)",
+                path, summary.modules, summary.failed, summary.roots
+            ),
+
+            Err(err) => error!("{err:#}"),
+        }
+    }
+}
+
 fn get_inspector_option(key: &str, addr: &SocketAddr) -> Result<InspectorOption, anyhow::Error> {
     match key {
         "inspect" => Ok(InspectorOption::Inspect(*addr)),
@@ -353,3 +1320,40 @@ fn get_inspector_option(key: &str, addr: &SocketAddr) -> Result<InspectorOption,
         key => bail!("invalid inspector key: {}", key),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::check_service;
+
+    #[tokio::test]
+    async fn test_check_service() {
+        let report = check_service(
+            Path::new("../base/test_cases/json_import"),
+            None,
+            None,
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert!(report.module_count >= 2);
+
+        let err = check_service(
+            Path::new("../base/test_cases/graph-error-1"),
+            None,
+            None,
+            false,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(format!("{err:#}").contains("failed to load the module graph"));
+        assert!(
+            check_service(Path::new("../base/test_cases/missing"), None, None, false)
+                .await
+                .is_err()
+        );
+    }
+}
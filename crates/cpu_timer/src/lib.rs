@@ -165,6 +165,27 @@ impl CPUTimer {
         Ok(())
     }
 
+    /// Disarms the timer until it is reset, so that the CPU time spent in the
+    /// meantime does not raise alarms.
+    #[cfg(target_os = "linux")]
+    pub fn suspend(&self) -> Result<(), Error> {
+        use anyhow::Context;
+        use linux::*;
+
+        let timer = self.timer.try_lock().context("failed to get the lock")?;
+        let tmspec: libc::itimerspec = unsafe { std::mem::zeroed() };
+
+        if unsafe {
+            // a zeroed expiry disarms the timer
+            libc::timer_settime(timer.tid.0, 0, &tmspec, std::ptr::null_mut())
+        } < 0
+        {
+            bail!(std::io::Error::last_os_error())
+        }
+
+        Ok(())
+    }
+
     #[cfg(not(target_os = "linux"))]
     pub fn start(_: u64, _: u64, _: CPUAlarmVal) -> Result<Self, Error> {
         log::error!("CPU timer: not enabled (need Linux)");
@@ -175,6 +196,11 @@ impl CPUTimer {
     pub fn reset(&self) -> Result<(), Error> {
         Ok(())
     }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn suspend(&self) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 pub fn get_thread_time() -> Result<i64, Error> {
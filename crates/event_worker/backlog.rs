@@ -0,0 +1,178 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use deno_core::op2;
+use serde::Serialize;
+
+use crate::routing::EventRoute;
+
+/// The events that were sent to the events workers but not accepted by them
+/// yet.
+///
+/// Once the backlog reaches its limit, the log events of user workers are
+/// dropped instead of being queued, and the user workers can learn about it
+/// through [`op_events_backpressure`].
+///
+/// The events are counted by route. The routes that have an events worker of
+/// their own each reach the limit on their own, while the others share the
+/// queue of the default events worker and thus the limit too.
+pub static EVENTS_BACKLOG: EventsBacklog = EventsBacklog::new();
+
+#[derive(Debug)]
+pub struct EventsBacklog {
+    pending: [AtomicUsize; 3],
+    is_routed: [AtomicBool; 3],
+    dropped: AtomicUsize,
+    limit: AtomicUsize,
+}
+
+impl EventsBacklog {
+    const fn new() -> Self {
+        Self {
+            pending: [
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+            ],
+            is_routed: [
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+            ],
+            dropped: AtomicUsize::new(0),
+            limit: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets the routes that have an events worker of their own.
+    pub fn set_routes(&self, routes: impl IntoIterator<Item = EventRoute>) {
+        let routes = routes.into_iter().collect::<Vec<_>>();
+
+        for route in EventRoute::ALL {
+            self.is_routed[route.index()].store(routes.contains(&route), Ordering::Relaxed);
+        }
+    }
+
+    /// Returns how many events are in the queue the events of the route go
+    /// through.
+    fn queued(&self, route: EventRoute) -> usize {
+        let is_routed = |it: EventRoute| self.is_routed[it.index()].load(Ordering::Relaxed);
+
+        if is_routed(route) {
+            return self.pending[route.index()].load(Ordering::Relaxed);
+        }
+
+        EventRoute::ALL
+            .into_iter()
+            .filter(|it| !is_routed(*it))
+            .map(|it| self.pending[it.index()].load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Sets the number of pending events from which the backlog is considered
+    /// saturated. `0` disables the limit.
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn is_saturated(&self, route: EventRoute) -> bool {
+        let limit = self.limit.load(Ordering::Relaxed);
+
+        limit > 0 && self.queued(route) >= limit
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending
+            .iter()
+            .map(|it| it.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Accounts for an event that must be delivered regardless of the backlog.
+    pub fn enter(&self, route: EventRoute) {
+        self.pending[route.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts for an event that may be dropped. Returns `false` if the
+    /// backlog is saturated, in which case the event must not be sent.
+    pub fn try_enter(&self, route: EventRoute) -> bool {
+        if self.is_saturated(route) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        self.enter(route);
+        true
+    }
+
+    /// Called once an event left the backlog, either because the events
+    /// worker accepted it or because it could not be sent.
+    pub fn leave(&self, route: EventRoute) {
+        let _ =
+            self.pending[route.index()].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |it| {
+                Some(it.saturating_sub(1))
+            });
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventsBackpressure {
+    saturated: bool,
+    dropped_events: usize,
+}
+
+/// Tells user workers whether their log events are currently being dropped,
+/// and how many were dropped so far across the runtime.
+#[op2]
+#[serde]
+pub fn op_events_backpressure() -> EventsBackpressure {
+    EventsBackpressure {
+        saturated: EVENTS_BACKLOG.is_saturated(EventRoute::Logs),
+        dropped_events: EVENTS_BACKLOG.dropped(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routing::EventRoute;
+
+    use super::EventsBacklog;
+
+    #[test]
+    fn test_events_backlog_drops_when_saturated() {
+        let backlog = EventsBacklog::new();
+
+        backlog.set_limit(2);
+
+        assert!(backlog.try_enter(EventRoute::Logs));
+        backlog.enter(EventRoute::Lifecycle);
+        assert!(backlog.is_saturated(EventRoute::Logs));
+        assert!(!backlog.try_enter(EventRoute::Logs));
+        assert_eq!(backlog.dropped(), 1);
+
+        backlog.leave(EventRoute::Lifecycle);
+        assert!(!backlog.is_saturated(EventRoute::Logs));
+        assert!(backlog.try_enter(EventRoute::Logs));
+        assert_eq!(backlog.pending(), 2);
+    }
+
+    #[test]
+    fn test_events_backlog_routes_are_independent() {
+        let backlog = EventsBacklog::new();
+
+        backlog.set_limit(2);
+        backlog.set_routes([EventRoute::Logs]);
+
+        backlog.enter(EventRoute::Lifecycle);
+        backlog.enter(EventRoute::Custom);
+        assert!(backlog.is_saturated(EventRoute::Lifecycle));
+        assert!(backlog.try_enter(EventRoute::Logs));
+        assert!(backlog.try_enter(EventRoute::Logs));
+        assert!(!backlog.try_enter(EventRoute::Logs));
+        assert_eq!(backlog.pending(), 4);
+    }
+}
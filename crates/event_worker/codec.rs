@@ -0,0 +1,234 @@
+//! A compact binary encoding of the events, for the sinks that consume them at
+//! high volume and can't afford to go through V8 objects or JSON.
+//!
+//! An event is encoded as a frame: the length of the rest of the frame as a
+//! big-endian `u32`, the version of the schema as a byte, then the event
+//! itself as `bincode`. The schema is the one of the serde derives of
+//! [`WorkerEventWithMetadata`]: fields are encoded in the order they are
+//! declared in, and variants by their index, so adding a variant at the end of
+//! an enum is the only change that does not need a new version.
+
+use anyhow::{bail, Error};
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::events::WorkerEventWithMetadata;
+
+pub static EVENT_SCHEMA_VERSION: u8 = 2;
+
+/// Frames larger than this are rejected by the decoder, so that a corrupt
+/// length can't make it buffer without bound.
+pub static MAX_EVENT_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+static HEADER_SIZE: usize = 4;
+
+/// Encodes an event as a single frame.
+pub fn encode_event(event: &WorkerEventWithMetadata) -> Result<Vec<u8>, Error> {
+    let mut buf = BytesMut::new();
+
+    EventCodec.encode(event, &mut buf)?;
+
+    Ok(buf.to_vec())
+}
+
+/// Frames the events for a byte stream, e.g. with `tokio_util::codec::Framed`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EventCodec;
+
+impl Encoder<&WorkerEventWithMetadata> for EventCodec {
+    type Error = Error;
+
+    fn encode(
+        &mut self,
+        event: &WorkerEventWithMetadata,
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        let payload = bincode::serialize(event)?;
+        let len = payload.len() + 1;
+
+        if len > MAX_EVENT_FRAME_SIZE {
+            bail!("event frame too large: {len} bytes");
+        }
+
+        dst.reserve(HEADER_SIZE + len);
+        dst.put_u32(len as u32);
+        dst.put_u8(EVENT_SCHEMA_VERSION);
+        dst.put_slice(&payload);
+
+        Ok(())
+    }
+}
+
+impl Decoder for EventCodec {
+    type Item = WorkerEventWithMetadata;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let len = u32::from_be_bytes(src[..HEADER_SIZE].try_into().unwrap()) as usize;
+
+        if len == 0 || len > MAX_EVENT_FRAME_SIZE {
+            bail!("invalid event frame length: {len} bytes");
+        }
+        if src.len() < HEADER_SIZE + len {
+            src.reserve(HEADER_SIZE + len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(HEADER_SIZE);
+
+        let frame = src.split_to(len);
+        let version = frame[0];
+
+        if version != EVENT_SCHEMA_VERSION {
+            bail!("unsupported event schema version: {version}");
+        }
+
+        Ok(Some(bincode::deserialize(&frame[1..])?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::BytesMut;
+    use deno_core::serde_json;
+    use tokio_util::codec::Decoder;
+    use uuid::Uuid;
+
+    use crate::events::{
+        BootEvent, BootWarning, EventMetadata, LogEvent, LogLevel, LogValue, StructuredLogEvent,
+        WorkerEventWithMetadata, WorkerEvents,
+    };
+
+    use super::{encode_event, EventCodec};
+
+    #[test]
+    fn test_event_codec_round_trip() {
+        let events = [
+            WorkerEventWithMetadata {
+                event: WorkerEvents::Boot(BootEvent {
+                    boot_time: 42,
+                    stages: None,
+                    first_byte_ready_ms: Some(50),
+                    warnings: vec![BootWarning::DegradedPermission {
+                        permission: "net".to_string(),
+                        reason: "denied".to_string(),
+                    }],
+                }),
+                metadata: EventMetadata {
+                    service_path: Some("./hello".to_string()),
+                    execution_id: Some(Uuid::new_v4()),
+                    worker_id: None,
+                },
+            },
+            WorkerEventWithMetadata {
+                event: WorkerEvents::Log(LogEvent {
+                    msg: "hello".to_string(),
+                    level: LogLevel::Info,
+                }),
+                metadata: EventMetadata::default(),
+            },
+            WorkerEventWithMetadata {
+                event: WorkerEvents::StructuredLog(StructuredLogEvent {
+                    level: LogLevel::Warning,
+                    msg: "charged".to_string(),
+                    fields: [
+                        ("amount".to_string(), LogValue::Number(42.0)),
+                        (
+                            "customer".to_string(),
+                            LogValue::String("cus_1".to_string()),
+                        ),
+                        ("retry".to_string(), LogValue::Null),
+                    ]
+                    .into(),
+                    dropped: 3,
+                }),
+                metadata: EventMetadata::default(),
+            },
+        ];
+
+        let mut buf = BytesMut::new();
+
+        for event in events.iter() {
+            buf.extend_from_slice(&encode_event(event).unwrap());
+        }
+
+        // NOTE: Frames may arrive split at any point.
+        let mut codec = EventCodec;
+        let mut src = BytesMut::new();
+        let mut decoded = vec![];
+
+        for chunk in buf.chunks(7) {
+            src.extend_from_slice(chunk);
+
+            while let Some(event) = codec.decode(&mut src).unwrap() {
+                decoded.push(event);
+            }
+        }
+
+        assert_eq!(decoded.len(), 3);
+        assert!(src.is_empty());
+
+        let WorkerEvents::Boot(boot) = &decoded[0].event else {
+            panic!("unexpected event: {:?}", decoded[0].event);
+        };
+
+        assert_eq!(boot.boot_time, 42);
+        assert_eq!(
+            boot.warnings,
+            vec![BootWarning::DegradedPermission {
+                permission: "net".to_string(),
+                reason: "denied".to_string(),
+            }]
+        );
+        assert_eq!(
+            decoded[0].metadata.execution_id,
+            events[0].metadata.execution_id
+        );
+        assert!(matches!(&decoded[1].event, WorkerEvents::Log(it) if it.msg == "hello"));
+
+        let WorkerEvents::StructuredLog(log) = &decoded[2].event else {
+            panic!("unexpected event: {:?}", decoded[2].event);
+        };
+
+        assert_eq!(log.dropped, 3);
+        assert_eq!(log.fields["amount"], LogValue::Number(42.0));
+        assert_eq!(
+            log.to_line(),
+            r#"charged amount=42 customer="cus_1" retry=null"#
+        );
+
+        // NOTE: The JSON of the fields has their values as they are.
+        assert_eq!(
+            serde_json::to_string(&log.fields).unwrap(),
+            r#"{"amount":42.0,"customer":"cus_1","retry":null}"#
+        );
+
+        // NOTE: The JSON of the warnings keeps its internal tag.
+        assert_eq!(
+            serde_json::to_value(&boot.warnings[0]).unwrap()["kind"],
+            "degradedPermission"
+        );
+    }
+
+    #[test]
+    fn test_event_codec_rejects_unknown_version() {
+        let mut frame = encode_event(&WorkerEventWithMetadata {
+            event: WorkerEvents::Log(LogEvent {
+                msg: "hello".to_string(),
+                level: LogLevel::Debug,
+            }),
+            metadata: EventMetadata::default(),
+        })
+        .unwrap();
+
+        frame[4] = u8::MAX;
+
+        assert!(EventCodec
+            .decode(&mut BytesMut::from(frame.as_slice()))
+            .is_err());
+    }
+}
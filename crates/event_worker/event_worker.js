@@ -1,7 +1,8 @@
 import { primordials, core } from "ext:core/mod.js";
 const { SymbolAsyncIterator } = primordials;
 
-const { op_event_accept } = core.ops;
+const { op_event_accept, op_event_accept_encoded, op_event_lifecycle_history } =
+	core.ops;
 
 class SupabaseEventListener {
 	async nextEvent() {
@@ -28,6 +29,20 @@ class SupabaseEventListener {
 		}
 	}
 
+	// The next event as a binary frame (a big-endian u32 length, the version
+	// of the schema, then the event as bincode), for forwarding to sinks
+	// without decoding it. Resolves to `null` once there are no more events.
+	async nextEncodedEvent() {
+		return await op_event_accept_encoded();
+	}
+
+	// The recent boots and terminations of the workers, the most recent
+	// first, optionally narrowed down by `servicePath`, `workerId`, `kinds`
+	// and `limit`.
+	lifecycleHistory(query = {}) {
+		return op_event_lifecycle_history(query);
+	}
+
 	[SymbolAsyncIterator]() {
 		const scopedClass = this;
 
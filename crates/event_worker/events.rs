@@ -1,10 +1,107 @@
+use std::collections::BTreeMap;
+
 use base_mem_check::MemCheckState;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use uuid::Uuid;
 
+/// Where the time of a cold start went, in microseconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct BootStages {
+    /// Waiting for a boot slot of the pool, when the number of concurrent
+    /// boots is capped.
+    pub boot_queue_us: u64,
+    /// From the creation of the worker to its thread picking it up.
+    pub thread_spawn_us: u64,
+    /// Waiting for other runtimes being created on the same thread.
+    pub creation_queue_us: u64,
+    /// Creating the isolate from the startup snapshot. V8 deserializes the
+    /// snapshot as part of it, so the two can't be told apart.
+    pub isolate_creation_us: u64,
+    /// Running the bootstrap script of the runtime.
+    pub bootstrap_us: u64,
+    /// Loading, transpiling and instantiating the module graph.
+    pub module_load_us: u64,
+    /// Evaluating the main module, until its top-level awaits have settled.
+    pub top_level_await_us: u64,
+}
+
+/// Something that went wrong while booting a worker, without failing the
+/// boot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootWarning {
+    /// An entry of the import map that no module of the graph resolved to.
+    UnusedImportMapEntry { specifier: String },
+    /// A module that could not be fetched, and was read from the module cache
+    /// instead.
+    CacheFallback { specifier: String },
+    /// A permission that was requested but not granted to the worker.
+    DegradedPermission { permission: String, reason: String },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "BootWarning", tag = "kind", rename_all = "camelCase")]
+enum TaggedBootWarning {
+    UnusedImportMapEntry { specifier: String },
+    CacheFallback { specifier: String },
+    DegradedPermission { permission: String, reason: String },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "BootWarning")]
+enum CompactBootWarning {
+    UnusedImportMapEntry { specifier: String },
+    CacheFallback { specifier: String },
+    DegradedPermission { permission: String, reason: String },
+}
+
+// NOTE: Formats that are not self-describing, such as the binary encoding of
+// the events (see `crate::codec`), can't deserialize an internally tagged
+// enum, so they get the kind of the warning as an external tag instead.
+impl Serialize for BootWarning {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            TaggedBootWarning::serialize(self, serializer)
+        } else {
+            CompactBootWarning::serialize(self, serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BootWarning {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            TaggedBootWarning::deserialize(deserializer)
+        } else {
+            CompactBootWarning::deserialize(deserializer)
+        }
+    }
+}
+
+impl std::fmt::Display for BootWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnusedImportMapEntry { specifier } => {
+                write!(f, "import map entry unused: {}", specifier)
+            }
+            Self::CacheFallback { specifier } => {
+                write!(f, "fallback cache used: {}", specifier)
+            }
+            Self::DegradedPermission { permission, reason } => {
+                write!(f, "permission degraded: {} ({})", permission, reason)
+            }
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BootEvent {
     pub boot_time: usize,
+    /// `None` if the main module did not evaluate successfully.
+    pub stages: Option<BootStages>,
+    /// From the creation of the worker to it being ready to serve its first
+    /// request, in milliseconds.
+    pub first_byte_ready_ms: Option<u64>,
+    pub warnings: Vec<BootWarning>,
 }
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BootFailureEvent {
@@ -26,6 +123,8 @@ pub enum ShutdownReason {
     Memory,
     EarlyDrop,
     TerminationRequested,
+    EventLoopLag,
+    CPUTimeCap,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -41,18 +140,339 @@ pub struct UncaughtExceptionEvent {
     pub cpu_time_used: usize,
 }
 
+/// The thread of the worker panicked. Only the worker is lost, the rest of the
+/// runtime keeps serving.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CrashedEvent {
+    pub message: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EventLoopCompletedEvent {
     pub cpu_time_used: usize,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CpuBurstEvent {
+    pub cpu_time_used: usize,
+    pub frames: Vec<String>,
+}
+
+/// A turn of the event loop that kept the worker busy for longer than its warn
+/// threshold. A `cpu_time_ms` well below `lag_ms` indicates a blocked event
+/// loop rather than CPU-bound code.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EventLoopLagEvent {
+    pub lag_ms: u64,
+    pub cpu_time_ms: u64,
+}
+
+/// The memory usage of a worker crossed a share of its limit, upwards if
+/// `rising`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MemoryWatermarkEvent {
+    pub percent: u8,
+    pub rising: bool,
+    pub used_bytes: usize,
+    pub limit_bytes: usize,
+}
+
+/// The memory usage of a worker kept growing over `samples` samples in a row.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MemoryLeakSuspectedEvent {
+    pub samples: usize,
+    pub growth_bytes: usize,
+    pub used_bytes: usize,
+    pub limit_bytes: usize,
+    /// Whether the worker is drained and replaced by a fresh one.
+    pub recycled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResourceSampleEvent {
+    pub total: usize,
+    pub limit: Option<usize>,
+    /// The number of open resources by their name (e.g. `tcpStream`).
+    pub resources: BTreeMap<String, usize>,
+    /// The random bytes the worker drew since its boot, through
+    /// `crypto.getRandomValues`.
+    pub random_bytes: u64,
+    /// The UUIDs the worker generated since its boot.
+    pub uuids: u64,
+}
+
+/// An idempotent request that was retried against a fresh worker since the
+/// worker handling it died mid-flight.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestRetriedEvent {
+    pub method: String,
+    pub path: String,
+    pub reason: String,
+    /// The key of the worker the request was retried against, if one could be
+    /// created.
+    pub retry_key: Option<Uuid>,
+    pub succeeded: bool,
+}
+
+/// A request that was mirrored to a shadow worker, along with how the shadow
+/// diverged from the worker that actually responded.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestMirroredEvent {
+    pub method: String,
+    pub path: String,
+    pub shadow_service_path: String,
+    /// The key of the shadow worker, if one could be created.
+    pub shadow_key: Option<Uuid>,
+    pub primary_status: Option<u16>,
+    pub shadow_status: Option<u16>,
+    pub primary_latency_ms: u64,
+    pub shadow_latency_ms: u64,
+    pub status_diverged: bool,
+    pub shadow_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleReason {
+    /// Fewer workers than the minimum were running.
+    MinInstances,
+    /// The in-flight requests per worker exceeded the target.
+    QueueDepth,
+    /// The p95 latency exceeded the target while every worker was busy.
+    Latency,
+    /// The load stayed below the target for the scale-down delay.
+    Idle,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkerScaledEvent {
+    pub from_instances: usize,
+    pub to_instances: usize,
+    pub reason: ScaleReason,
+    /// In-flight requests across the workers of the service.
+    pub queue_depth: usize,
+    pub p95_latency_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HistogramSnapshot {
+    /// Inclusive upper bounds of the buckets. The last bucket of `counts`
+    /// holds the values that exceed every bound.
+    pub bounds: Vec<u64>,
+    pub counts: Vec<u64>,
+    pub count: u64,
+    pub sum: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServiceStatsEvent {
+    pub interval_ms: u64,
+    pub request_count: u64,
+    /// Requests that failed without a response, included in `request_count`.
+    pub error_count: u64,
+    pub request_size: HistogramSnapshot,
+    pub response_size: HistogramSnapshot,
+    pub latency_ms: HistogramSnapshot,
+}
+
+/// Periodic statistics of the worker pool, reported regardless of the tenants
+/// so that the health of the platform itself can be followed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PoolStatsEvent {
+    pub interval_ms: u64,
+    pub active_user_workers: usize,
+    pub retired_user_workers: usize,
+    /// Workers whose creation has been granted and that are still booting.
+    pub pending_boots: usize,
+    /// Of the pending boots, those still waiting for a boot slot.
+    pub queued_boots: usize,
+    pub received_requests: usize,
+    pub handled_requests: usize,
+    pub active_io: usize,
+}
+
+/// The metrics of the tokio runtime the server runs on. Only reported when
+/// built with `--cfg tokio_unstable`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TokioRuntimeStats {
+    pub workers: usize,
+    pub alive_tasks: usize,
+    pub blocking_threads: usize,
+    pub idle_blocking_threads: usize,
+    pub injection_queue_depth: usize,
+    pub blocking_queue_depth: usize,
+}
+
+/// Periodic statistics of the runtime process.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RuntimeStatsEvent {
+    pub interval_ms: u64,
+    /// `None` where the resident set size can't be read (i.e. outside of
+    /// Linux).
+    pub rss_bytes: Option<u64>,
+    /// Events sent to the events worker but not accepted by it yet.
+    pub events_backlog: usize,
+    /// Log events dropped so far since the backlog was saturated.
+    pub events_dropped: usize,
+    pub tokio: Option<TokioRuntimeStats>,
+}
+
+/// Periodic sample of the concurrency of a service, for usage-based billing.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConcurrencySampleEvent {
+    pub interval_ms: u64,
+    /// Requests sent to the workers of the service that have not been
+    /// responded to yet.
+    pub in_flight_requests: usize,
+    /// Workers of the service that are routed requests.
+    pub instances: usize,
+    /// The memory limits of the workers, summed.
+    pub memory_limit_mb: u64,
+    /// The configured memory of the workers held over the interval, assuming
+    /// they were up for the whole of it.
+    pub gb_seconds: f64,
+}
+
+/// The runtime configuration has been reloaded.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConfigReloadedEvent {
+    /// The settings that changed, e.g. `pool.maxParallelism: 4 -> 8`.
+    pub changes: Vec<String>,
+}
+
+/// A destination of a fetch presented a certificate chain that matches none
+/// of its pins, so the connection was refused.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PinViolationEvent {
+    pub host: String,
+    /// The SPKI pins of the certificates of the presented chain.
+    pub presented_pins: Vec<String>,
+}
+
+/// The boot of a user worker was refused since the host or the pool was short
+/// of resources.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BootRejectedEvent {
+    pub reason: String,
+    /// Memory available on the host in MiB, if it could be read.
+    pub available_memory_mb: Option<u64>,
+    /// 1-minute load average of the host per core, if it could be read.
+    pub load_avg_per_core: Option<f64>,
+    /// Sum of the memory limits of the user workers of the pool in MiB.
+    pub committed_memory_mb: u64,
+}
+
+/// The boot of a user worker was refused since its code is not signed by any
+/// of the trusted keys.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BundleRejectedEvent {
+    pub reason: String,
+}
+
+/// A worker was created for a service whose active workers were created with
+/// other options.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DuplicateCreateEvent {
+    /// `rejected`, `reused` or `recreated`.
+    pub decision: String,
+    /// The options that differ, without their values.
+    pub changed_options: Vec<String>,
+    /// How many active workers were drained to recreate the service.
+    pub drained_workers: usize,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LogEvent {
     pub msg: String,
     pub level: LogLevel,
 }
 
+/// A record of `EdgeRuntime.log`, with its fields as they were given instead
+/// of formatted into the message.
 #[derive(Serialize, Deserialize, Debug)]
+pub struct StructuredLogEvent {
+    pub level: LogLevel,
+    pub msg: String,
+    pub fields: BTreeMap<String, LogValue>,
+    /// The records of the worker that were sampled out or over its rate
+    /// limit since the previous one.
+    pub dropped: u64,
+}
+
+impl StructuredLogEvent {
+    /// Formats the record as a single line, e.g. `charged amount=42 ok=true`.
+    pub fn to_line(&self) -> String {
+        let mut line = self.msg.clone();
+
+        for (key, value) in &self.fields {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+
+        line
+    }
+}
+
+/// The value of a field of a structured log record. Nested values are given
+/// as their JSON by the worker.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "LogValue", untagged)]
+enum UntaggedLogValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "LogValue")]
+enum CompactLogValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+// NOTE: Like `BootWarning`, the binary encoding of the events gets the kind
+// of the value as an external tag, as it can't deserialize an untagged enum.
+impl Serialize for LogValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            UntaggedLogValue::serialize(self, serializer)
+        } else {
+            CompactLogValue::serialize(self, serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LogValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            UntaggedLogValue::deserialize(deserializer)
+        } else {
+            CompactLogValue::deserialize(deserializer)
+        }
+    }
+}
+
+impl std::fmt::Display for LogValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(it) => write!(f, "{}", it),
+            Self::Number(it) => write!(f, "{}", it),
+            Self::String(it) => write!(f, "{:?}", it),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LogLevel {
     Debug,
     Info,
@@ -65,9 +485,28 @@ pub enum WorkerEvents {
     Boot(BootEvent),
     BootFailure(BootFailureEvent),
     UncaughtException(UncaughtExceptionEvent),
+    Crashed(CrashedEvent),
     Shutdown(ShutdownEvent),
     EventLoopCompleted(EventLoopCompletedEvent),
     Log(LogEvent),
+    CpuBurst(CpuBurstEvent),
+    EventLoopLag(EventLoopLagEvent),
+    ResourceSample(ResourceSampleEvent),
+    ServiceStats(ServiceStatsEvent),
+    RequestRetried(RequestRetriedEvent),
+    RequestMirrored(RequestMirroredEvent),
+    WorkerScaled(WorkerScaledEvent),
+    PoolStats(PoolStatsEvent),
+    RuntimeStats(RuntimeStatsEvent),
+    ConfigReloaded(ConfigReloadedEvent),
+    PinViolation(PinViolationEvent),
+    BootRejected(BootRejectedEvent),
+    StructuredLog(StructuredLogEvent),
+    BundleRejected(BundleRejectedEvent),
+    MemoryWatermark(MemoryWatermarkEvent),
+    MemoryLeakSuspected(MemoryLeakSuspectedEvent),
+    DuplicateCreate(DuplicateCreateEvent),
+    ConcurrencySample(ConcurrencySampleEvent),
 }
 
 impl WorkerEvents {
@@ -89,6 +528,7 @@ impl WorkerEvents {
 pub struct EventMetadata {
     pub service_path: Option<String>,
     pub execution_id: Option<Uuid>,
+    pub worker_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
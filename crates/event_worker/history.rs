@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use deno_core::op2;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::events::{EventMetadata, WorkerEvents};
+
+const DEFAULT_LIFECYCLE_HISTORY_CAPACITY: usize = 256;
+
+/// The recent boots and terminations of the workers, so that the events worker
+/// can put an incoming event in context (e.g. why the previous worker of a
+/// service went away) through [`op_event_lifecycle_history`].
+pub static LIFECYCLE_HISTORY: LifecycleHistory = LifecycleHistory::new();
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum LifecycleKind {
+    Boot,
+    BootFailure,
+    UncaughtException,
+    Crashed,
+    Shutdown,
+    EventLoopCompleted,
+    BootRejected,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LifecycleRecord {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub kind: LifecycleKind,
+    /// Why the worker went away, for the records of terminations.
+    pub reason: Option<String>,
+    pub service_path: Option<String>,
+    pub execution_id: Option<Uuid>,
+    pub worker_id: Option<String>,
+}
+
+impl LifecycleRecord {
+    /// Returns `None` if the event is not about the lifecycle of a worker.
+    fn from_event(event: &WorkerEvents, metadata: &EventMetadata) -> Option<Self> {
+        let (kind, reason) = match event {
+            WorkerEvents::Boot(_) => (LifecycleKind::Boot, None),
+            WorkerEvents::BootFailure(it) => (LifecycleKind::BootFailure, Some(it.msg.clone())),
+            WorkerEvents::UncaughtException(it) => {
+                (LifecycleKind::UncaughtException, Some(it.exception.clone()))
+            }
+            WorkerEvents::Crashed(it) => (LifecycleKind::Crashed, Some(it.message.clone())),
+            WorkerEvents::Shutdown(it) => {
+                (LifecycleKind::Shutdown, Some(format!("{:?}", it.reason)))
+            }
+            WorkerEvents::EventLoopCompleted(_) => (LifecycleKind::EventLoopCompleted, None),
+            WorkerEvents::BootRejected(it) => {
+                (LifecycleKind::BootRejected, Some(it.reason.clone()))
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|it| it.as_millis() as u64)
+                .unwrap_or_default(),
+            kind,
+            reason,
+            service_path: metadata.service_path.clone(),
+            execution_id: metadata.execution_id,
+            worker_id: metadata.worker_id.clone(),
+        })
+    }
+}
+
+/// How many records of each kind were seen since the start, whatever the
+/// capacity of the history.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LifecycleTotals {
+    pub by_kind: BTreeMap<LifecycleKind, u64>,
+    /// The shutdowns by their reason.
+    pub shutdowns: BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+pub struct LifecycleHistory {
+    inner: Mutex<LifecycleHistoryInner>,
+}
+
+#[derive(Debug)]
+struct LifecycleHistoryInner {
+    capacity: usize,
+    records: VecDeque<LifecycleRecord>,
+    totals: LifecycleTotals,
+}
+
+impl LifecycleHistory {
+    const fn new() -> Self {
+        Self {
+            inner: Mutex::new(LifecycleHistoryInner {
+                capacity: DEFAULT_LIFECYCLE_HISTORY_CAPACITY,
+                records: VecDeque::new(),
+                totals: LifecycleTotals {
+                    by_kind: BTreeMap::new(),
+                    shutdowns: BTreeMap::new(),
+                },
+            }),
+        }
+    }
+
+    /// Sets the number of records that are kept. `0` disables the history.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.capacity = capacity;
+
+        while inner.records.len() > capacity {
+            inner.records.pop_front();
+        }
+    }
+
+    /// Records the event if it is about the lifecycle of a worker.
+    pub fn record(&self, event: &WorkerEvents, metadata: &EventMetadata) {
+        let Some(record) = LifecycleRecord::from_event(event, metadata) else {
+            return;
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+
+        *inner.totals.by_kind.entry(record.kind).or_default() += 1;
+
+        if let WorkerEvents::Shutdown(it) = event {
+            *inner
+                .totals
+                .shutdowns
+                .entry(format!("{:?}", it.reason))
+                .or_default() += 1;
+        }
+
+        if inner.capacity == 0 {
+            return;
+        }
+
+        if inner.records.len() >= inner.capacity {
+            inner.records.pop_front();
+        }
+
+        inner.records.push_back(record);
+    }
+
+    pub fn totals(&self) -> LifecycleTotals {
+        self.inner.lock().unwrap().totals.clone()
+    }
+
+    /// Returns the matching records, the most recent first.
+    pub fn query(&self, query: &LifecycleHistoryQuery) -> Vec<LifecycleRecord> {
+        let inner = self.inner.lock().unwrap();
+
+        inner
+            .records
+            .iter()
+            .rev()
+            .filter(|it| {
+                query
+                    .service_path
+                    .as_ref()
+                    .map_or(true, |path| it.service_path.as_ref() == Some(path))
+            })
+            .filter(|it| {
+                query
+                    .worker_id
+                    .as_ref()
+                    .map_or(true, |id| it.worker_id.as_ref() == Some(id))
+            })
+            .filter(|it| query.kinds.is_empty() || query.kinds.contains(&it.kind))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LifecycleHistoryQuery {
+    pub service_path: Option<String>,
+    pub worker_id: Option<String>,
+    /// Only the records of these kinds are returned, or all of them if empty.
+    pub kinds: Vec<LifecycleKind>,
+    pub limit: Option<usize>,
+}
+
+#[op2]
+#[serde]
+pub fn op_event_lifecycle_history(#[serde] query: LifecycleHistoryQuery) -> Vec<LifecycleRecord> {
+    LIFECYCLE_HISTORY.query(&query)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::events::{BootFailureEvent, EventMetadata, LogEvent, LogLevel, WorkerEvents};
+
+    use super::{LifecycleHistory, LifecycleHistoryQuery, LifecycleKind};
+
+    fn metadata(service_path: &str) -> EventMetadata {
+        EventMetadata {
+            service_path: Some(service_path.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn boot_failure(msg: &str) -> WorkerEvents {
+        WorkerEvents::BootFailure(BootFailureEvent {
+            msg: msg.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_lifecycle_history() {
+        let history = LifecycleHistory::new();
+
+        history.set_capacity(2);
+        history.record(&boot_failure("a"), &metadata("foo"));
+        history.record(
+            &WorkerEvents::Log(LogEvent {
+                msg: "hello".to_string(),
+                level: LogLevel::Info,
+            }),
+            &metadata("foo"),
+        );
+        history.record(&boot_failure("b"), &metadata("bar"));
+        history.record(&boot_failure("c"), &metadata("foo"));
+
+        let records = history.query(&LifecycleHistoryQuery::default());
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].reason.as_deref(), Some("c"));
+        assert_eq!(records[1].reason.as_deref(), Some("b"));
+
+        let records = history.query(&LifecycleHistoryQuery {
+            service_path: Some("foo".to_string()),
+            kinds: vec![LifecycleKind::BootFailure],
+            limit: Some(5),
+            ..Default::default()
+        });
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].reason.as_deref(), Some("c"));
+
+        // NOTE: The totals count the records that were evicted as well.
+        assert_eq!(
+            history.totals().by_kind.get(&LifecycleKind::BootFailure),
+            Some(&3)
+        );
+        assert_eq!(history.totals().by_kind.len(), 1);
+    }
+}
@@ -1,4 +1,7 @@
+use crate::backlog::{op_events_backpressure, EVENTS_BACKLOG};
 use crate::events::{EventMetadata, LogEvent, LogLevel, WorkerEvents};
+use crate::routing::EventRoute;
+use crate::structured_log::op_user_worker_structured_log;
 use crate::WorkerEventWithMetadata;
 use deno_core::error::AnyError;
 use deno_core::op2;
@@ -19,6 +22,10 @@ fn op_user_worker_log(
     }
 
     if let Some(tx) = maybe_tx {
+        if !EVENTS_BACKLOG.try_enter(EventRoute::Logs) {
+            return Ok(());
+        }
+
         let event_metadata = state
             .try_borrow::<EventMetadata>()
             .unwrap_or(&EventMetadata::default())
@@ -26,13 +33,16 @@ fn op_user_worker_log(
 
         let metadata = EventMetadata { ..event_metadata };
 
-        tx.send(WorkerEventWithMetadata {
+        if let Err(err) = tx.send(WorkerEventWithMetadata {
             event: WorkerEvents::Log(LogEvent {
                 msg: msg.to_string(),
                 level,
             }),
             metadata,
-        })?;
+        }) {
+            EVENTS_BACKLOG.leave(EventRoute::Logs);
+            return Err(err.into());
+        }
     } else {
         error!("[{:?}] {}", level, msg.to_string());
     }
@@ -40,4 +50,11 @@ fn op_user_worker_log(
     Ok(())
 }
 
-deno_core::extension!(sb_events_js_interceptors, ops = [op_user_worker_log,],);
+deno_core::extension!(
+    sb_events_js_interceptors,
+    ops = [
+        op_user_worker_log,
+        op_user_worker_structured_log,
+        op_events_backpressure
+    ]
+);
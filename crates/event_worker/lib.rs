@@ -1,17 +1,26 @@
+use crate::backlog::EVENTS_BACKLOG;
+use crate::codec::encode_event;
 use crate::events::{RawEvent, WorkerEventWithMetadata};
+use crate::routing::EventRoute;
 use anyhow::{bail, Error};
 use deno_core::op2;
-use deno_core::OpState;
+use deno_core::{OpState, ToJsBuffer};
 use std::cell::RefCell;
 use std::rc::Rc;
 use tokio::sync::mpsc;
 
+pub mod backlog;
+pub mod codec;
 pub mod events;
+pub mod history;
 pub mod js_interceptors;
+pub mod routing;
+pub mod structured_log;
 
-#[op2(async)]
-#[serde]
-async fn op_event_accept(state: Rc<RefCell<OpState>>) -> Result<RawEvent, Error> {
+/// Waits for the next event, or returns `None` once every sender is gone.
+async fn accept_event(
+    state: Rc<RefCell<OpState>>,
+) -> Result<Option<WorkerEventWithMetadata>, Error> {
     let rx = {
         let mut op_state = state.borrow_mut();
         op_state.try_take::<mpsc::UnboundedReceiver<WorkerEventWithMetadata>>()
@@ -23,20 +32,46 @@ async fn op_event_accept(state: Rc<RefCell<OpState>>) -> Result<RawEvent, Error>
 
     let data = rx.recv().await;
 
+    if let Some(event) = data.as_ref() {
+        EVENTS_BACKLOG.leave(EventRoute::of(&event.event));
+    }
+
     let mut op_state = state.borrow_mut();
     op_state.put::<mpsc::UnboundedReceiver<WorkerEventWithMetadata>>(rx);
 
-    match data {
-        Some(event) => Ok(RawEvent::Event(Box::new(event))),
-        None => {
-            op_state.waker.wake();
-            Ok(RawEvent::Done)
-        }
+    if data.is_none() {
+        op_state.waker.wake();
     }
+
+    Ok(data)
+}
+
+#[op2(async)]
+#[serde]
+async fn op_event_accept(state: Rc<RefCell<OpState>>) -> Result<RawEvent, Error> {
+    Ok(match accept_event(state).await? {
+        Some(event) => RawEvent::Event(Box::new(event)),
+        None => RawEvent::Done,
+    })
+}
+
+/// Like [`op_event_accept`], but hands the event over as a frame of
+/// [`codec::EventCodec`], which is cheaper than building its JS object.
+#[op2(async)]
+#[serde]
+async fn op_event_accept_encoded(state: Rc<RefCell<OpState>>) -> Result<Option<ToJsBuffer>, Error> {
+    accept_event(state)
+        .await?
+        .map(|it| encode_event(&it).map(ToJsBuffer::from))
+        .transpose()
 }
 
 deno_core::extension!(
     sb_user_event_worker,
-    ops = [op_event_accept],
+    ops = [
+        op_event_accept,
+        op_event_accept_encoded,
+        history::op_event_lifecycle_history
+    ],
     esm = ["event_worker.js"]
 );
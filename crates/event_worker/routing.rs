@@ -0,0 +1,140 @@
+//! Routing of the events to several events workers by their type, so that a
+//! flood of log events can't hold back the lifecycle events behind it.
+//!
+//! Every route that has an events worker of its own gets a queue of its own,
+//! which is also accounted for separately by [`EVENTS_BACKLOG`]. The events
+//! of the other routes go to the default events worker, if there is one.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use tokio::sync::mpsc;
+
+use crate::backlog::EVENTS_BACKLOG;
+use crate::events::{WorkerEventWithMetadata, WorkerEvents};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventRoute {
+    /// The boots and the terminations of the workers.
+    Lifecycle,
+    /// The logs of the workers.
+    Logs,
+    /// Everything else, such as the stats of the pool and of the services.
+    Custom,
+}
+
+impl EventRoute {
+    pub const ALL: [Self; 3] = [Self::Lifecycle, Self::Logs, Self::Custom];
+
+    pub fn of(event: &WorkerEvents) -> Self {
+        match event {
+            WorkerEvents::Boot(_)
+            | WorkerEvents::BootFailure(_)
+            | WorkerEvents::UncaughtException(_)
+            | WorkerEvents::Crashed(_)
+            | WorkerEvents::Shutdown(_)
+            | WorkerEvents::EventLoopCompleted(_)
+            | WorkerEvents::BootRejected(_)
+            | WorkerEvents::BundleRejected(_) => Self::Lifecycle,
+
+            WorkerEvents::Log(_) | WorkerEvents::StructuredLog(_) => Self::Logs,
+
+            _ => Self::Custom,
+        }
+    }
+
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Self::Lifecycle => 0,
+            Self::Logs => 1,
+            Self::Custom => 2,
+        }
+    }
+}
+
+impl FromStr for EventRoute {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "lifecycle" => Self::Lifecycle,
+            "logs" => Self::Logs,
+            "custom" => Self::Custom,
+            _ => bail!("unknown event route: {s}"),
+        })
+    }
+}
+
+/// Returns a sender that forwards each event to the events worker of its
+/// route, or to the default one if its route has none.
+///
+/// The events that have nowhere to go are dropped. The senders of the routes
+/// are dropped once the returned sender and all of its clones are, so that
+/// the events workers see the end of the events as usual.
+pub fn route_events(
+    routes: HashMap<EventRoute, mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    maybe_default: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+) -> mpsc::UnboundedSender<WorkerEventWithMetadata> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+
+    EVENTS_BACKLOG.set_routes(routes.keys().copied());
+
+    drop(tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            let route = EventRoute::of(&event.event);
+            let Some(route_tx) = routes.get(&route).or(maybe_default.as_ref()) else {
+                EVENTS_BACKLOG.leave(route);
+                continue;
+            };
+
+            if route_tx.send(event).is_err() {
+                EVENTS_BACKLOG.leave(route);
+            }
+        }
+    }));
+
+    tx
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tokio::sync::mpsc;
+
+    use crate::events::{EventMetadata, LogEvent, LogLevel, WorkerEventWithMetadata, WorkerEvents};
+
+    use super::{route_events, EventRoute};
+
+    fn log_event() -> WorkerEventWithMetadata {
+        WorkerEventWithMetadata {
+            event: WorkerEvents::Log(LogEvent {
+                msg: String::from("hello"),
+                level: LogLevel::Info,
+            }),
+            metadata: EventMetadata::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_events() {
+        let (logs_tx, mut logs_rx) = mpsc::unbounded_channel();
+        let (default_tx, mut default_rx) = mpsc::unbounded_channel();
+        let tx = route_events(
+            HashMap::from([(EventRoute::Logs, logs_tx)]),
+            Some(default_tx),
+        );
+
+        tx.send(log_event()).unwrap();
+        drop(tx);
+
+        assert!(matches!(
+            logs_rx.recv().await.unwrap().event,
+            WorkerEvents::Log(_)
+        ));
+
+        assert!(logs_rx.recv().await.is_none());
+        assert!(default_rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,188 @@
+//! The records of `EdgeRuntime.log`, which go to the events worker as
+//! `StructuredLog` events instead of being parsed back out of the console
+//! output downstream.
+//!
+//! A user worker can log far more than the events worker can take, so its
+//! debug and info records can be sampled, and all of its records are capped
+//! to a rate. The records that were left out are counted on the next one.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use deno_core::error::AnyError;
+use deno_core::{op2, OpState};
+use log::error;
+use tokio::sync::mpsc;
+
+use crate::backlog::EVENTS_BACKLOG;
+use crate::events::{
+    EventMetadata, LogLevel, LogValue, StructuredLogEvent, WorkerEventWithMetadata, WorkerEvents,
+};
+use crate::routing::EventRoute;
+
+static RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+pub struct StructuredLogPolicy {
+    /// The share of the debug and info records that are kept, from `0` to
+    /// `1`. Warnings and errors are never sampled.
+    pub sample_rate: f64,
+    /// Records per second past which the records of the worker are dropped.
+    /// `0` means unlimited.
+    pub rate_limit: u64,
+}
+
+impl Default for StructuredLogPolicy {
+    fn default() -> Self {
+        Self {
+            sample_rate: 1.0,
+            rate_limit: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StructuredLogLimiter {
+    policy: StructuredLogPolicy,
+    /// Sampling is done by accumulating the rate, so that it keeps an exact
+    /// share of the records rather than a random one.
+    sample_credit: f64,
+    window_start: Instant,
+    in_window: u64,
+    dropped: u64,
+}
+
+impl StructuredLogLimiter {
+    pub fn new(policy: StructuredLogPolicy) -> Self {
+        Self {
+            policy,
+            sample_credit: 0.0,
+            window_start: Instant::now(),
+            in_window: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Returns how many records were dropped since the last one that was
+    /// kept if this one is kept, or `None` if it is dropped.
+    pub fn admit(&mut self, level: LogLevel, now: Instant) -> Option<u64> {
+        if matches!(level, LogLevel::Debug | LogLevel::Info) && self.policy.sample_rate < 1.0 {
+            self.sample_credit += self.policy.sample_rate.max(0.0);
+
+            if self.sample_credit < 1.0 {
+                self.dropped += 1;
+                return None;
+            }
+
+            self.sample_credit -= 1.0;
+        }
+
+        if self.policy.rate_limit > 0 {
+            if now.saturating_duration_since(self.window_start) >= RATE_LIMIT_WINDOW {
+                self.window_start = now;
+                self.in_window = 0;
+            }
+
+            if self.in_window >= self.policy.rate_limit {
+                self.dropped += 1;
+                return None;
+            }
+
+            self.in_window += 1;
+        }
+
+        Some(std::mem::take(&mut self.dropped))
+    }
+}
+
+/// Returns whether the record was kept.
+#[op2]
+pub fn op_user_worker_structured_log(
+    state: &mut OpState,
+    #[serde] level: LogLevel,
+    #[string] msg: String,
+    #[serde] fields: BTreeMap<String, LogValue>,
+) -> Result<bool, AnyError> {
+    let dropped = match state.try_borrow_mut::<StructuredLogLimiter>() {
+        Some(limiter) => match limiter.admit(level, Instant::now()) {
+            Some(dropped) => dropped,
+            None => return Ok(false),
+        },
+
+        None => 0,
+    };
+
+    let event = StructuredLogEvent {
+        level,
+        msg,
+        fields,
+        dropped,
+    };
+
+    let Some(tx) = state.try_borrow::<mpsc::UnboundedSender<WorkerEventWithMetadata>>() else {
+        error!("[{:?}] {}", level, event.to_line());
+        return Ok(true);
+    };
+
+    if !EVENTS_BACKLOG.try_enter(EventRoute::Logs) {
+        return Ok(false);
+    }
+
+    let metadata = state
+        .try_borrow::<EventMetadata>()
+        .cloned()
+        .unwrap_or_default();
+
+    if let Err(err) = tx.send(WorkerEventWithMetadata {
+        event: WorkerEvents::StructuredLog(event),
+        metadata,
+    }) {
+        EVENTS_BACKLOG.leave(EventRoute::Logs);
+        return Err(err.into());
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::events::LogLevel;
+
+    use super::{StructuredLogLimiter, StructuredLogPolicy};
+
+    #[test]
+    fn test_structured_log_limiter() {
+        let now = Instant::now();
+        let mut limiter = StructuredLogLimiter::new(StructuredLogPolicy {
+            sample_rate: 0.5,
+            rate_limit: 0,
+        });
+
+        let kept = (0..11)
+            .filter(|_| limiter.admit(LogLevel::Info, now).is_some())
+            .count();
+
+        assert_eq!(kept, 5);
+
+        // NOTE: Errors are never sampled, and report what was dropped
+        // before them.
+        assert_eq!(limiter.admit(LogLevel::Error, now), Some(1));
+
+        let mut limiter = StructuredLogLimiter::new(StructuredLogPolicy {
+            sample_rate: 1.0,
+            rate_limit: 2,
+        });
+        let now = Instant::now();
+
+        assert_eq!(limiter.admit(LogLevel::Warning, now), Some(0));
+        assert_eq!(limiter.admit(LogLevel::Warning, now), Some(0));
+        assert_eq!(limiter.admit(LogLevel::Warning, now), None);
+        assert_eq!(limiter.admit(LogLevel::Warning, now), None);
+        assert_eq!(
+            limiter.admit(LogLevel::Warning, now + Duration::from_secs(1)),
+            Some(2)
+        );
+    }
+}
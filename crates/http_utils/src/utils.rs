@@ -43,3 +43,22 @@ pub fn emit_status_code(
     }
     .unwrap()
 }
+
+/// Emits an RFC 9457 `application/problem+json` response.
+pub fn emit_problem_details(status: StatusCode, detail: &str) -> Response<Body> {
+    let body = serde_json::json!({
+        "type": "about:blank",
+        "title": status.canonical_reason().unwrap_or("Unknown"),
+        "status": status.as_u16(),
+        "detail": detail,
+    });
+
+    response::Builder::new()
+        .status(status)
+        .header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        )
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
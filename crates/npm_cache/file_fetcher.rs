@@ -13,7 +13,7 @@ use deno_graph::source::LoaderChecksum;
 use deno_web::BlobStore;
 
 use anyhow::{bail, Context};
-use log::debug;
+use log::{debug, warn};
 
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -25,6 +25,7 @@ use std::time::SystemTime;
 
 use sb_core::auth_tokens::AuthTokens;
 use sb_core::cache::fc_permissions::FcPermissions;
+use sb_core::cache::module_cache;
 use sb_core::cache::CacheSetting;
 use sb_core::util::http_util::{
     CacheSemantics, FetchOnceArgs, FetchOnceResult, HttpClientProvider,
@@ -156,6 +157,9 @@ pub struct FileFetcher {
     http_client_provider: Arc<HttpClientProvider>,
     blob_store: Arc<BlobStore>,
     download_log_level: log::Level,
+    module_cache_dir: Option<PathBuf>,
+    allow_stale_fallback: bool,
+    cache_fallbacks: Arc<Mutex<Vec<ModuleSpecifier>>>,
 }
 
 impl FileFetcher {
@@ -175,6 +179,10 @@ impl FileFetcher {
             http_client_provider,
             blob_store,
             download_log_level: log::Level::Info,
+            module_cache_dir: None,
+            allow_stale_fallback: env::var("EDGE_RUNTIME_ALLOW_STALE_MODULES")
+                .is_ok_and(|it| it == "1" || it == "true"),
+            cache_fallbacks: Arc::default(),
         }
     }
 
@@ -187,6 +195,18 @@ impl FileFetcher {
         self.download_log_level = level;
     }
 
+    /// Sets the directory the HTTP cache keeps the modules in, so that the
+    /// modules read from it are marked as used for the cache GC.
+    pub fn set_module_cache_dir(&mut self, dir: PathBuf) {
+        self.module_cache_dir = Some(dir);
+    }
+
+    /// The modules that could not be fetched and were read from the cache
+    /// instead.
+    pub fn cache_fallbacks(&self) -> Vec<ModuleSpecifier> {
+        self.cache_fallbacks.lock().clone()
+    }
+
     /// Fetch cached remote file.
     ///
     /// This is a recursive operation if source file has redirections.
@@ -254,6 +274,21 @@ impl FileFetcher {
             },
         };
 
+        // NOTE: A corrupted module is treated as a cache miss so that it is
+        // fetched again, which overwrites it.
+        if !module_cache::verify_content(&headers, &bytes) {
+            warn!("cached module is corrupted, fetching it again: {specifier}");
+            return Ok(None);
+        }
+
+        if let Some(path) = self
+            .module_cache_dir
+            .as_ref()
+            .and_then(|it| module_cache::get_cache_path(it, specifier))
+        {
+            module_cache::touch(&path);
+        }
+
         Ok(Some(FileOrRedirect::File(File {
             specifier: specifier.clone(),
             maybe_headers: Some(headers),
@@ -392,7 +427,11 @@ impl FileFetcher {
                     Ok(FileOrRedirect::Redirect(redirect_url))
                 }
                 FetchOnceResult::Code(bytes, headers) => {
-                    self.http_cache.set(specifier, headers.clone(), &bytes)?;
+                    self.http_cache.set(
+                        specifier,
+                        module_cache::with_content_hash(headers.clone(), &bytes),
+                        &bytes,
+                    )?;
                     if let Some(checksum) = &maybe_checksum {
                         checksum.check_source(&bytes)?;
                     }
@@ -403,12 +442,30 @@ impl FileFetcher {
                     }))
                 }
                 FetchOnceResult::RequestError(err) => {
-                    handle_request_or_server_error(&mut retried, specifier, err).await?;
+                    if let Err(err) =
+                        handle_request_or_server_error(&mut retried, specifier, err).await
+                    {
+                        break self.fallback_to_cache(
+                            specifier,
+                            cache_setting,
+                            maybe_checksum,
+                            err,
+                        );
+                    }
                     continue;
                 }
                 FetchOnceResult::ServerError(status) => {
-                    handle_request_or_server_error(&mut retried, specifier, status.to_string())
-                        .await?;
+                    if let Err(err) =
+                        handle_request_or_server_error(&mut retried, specifier, status.to_string())
+                            .await
+                    {
+                        break self.fallback_to_cache(
+                            specifier,
+                            cache_setting,
+                            maybe_checksum,
+                            err,
+                        );
+                    }
                     continue;
                 }
             };
@@ -416,6 +473,36 @@ impl FileFetcher {
         }
     }
 
+    /// Reads the module from the cache when it could not be fetched, if the
+    /// cache was bypassed to reload it.
+    ///
+    /// NOTE: This serves code that may be stale, so it is opt-in through
+    /// `EDGE_RUNTIME_ALLOW_STALE_MODULES`.
+    fn fallback_to_cache(
+        &self,
+        specifier: &ModuleSpecifier,
+        cache_setting: &CacheSetting,
+        maybe_checksum: Option<&LoaderChecksum>,
+        err: AnyError,
+    ) -> Result<FileOrRedirect, AnyError> {
+        // NOTE: Otherwise, the cache was already looked up before fetching.
+        if !self.allow_stale_fallback || self.should_use_cache(specifier, cache_setting) {
+            return Err(err);
+        }
+
+        match self.fetch_cached_no_follow(specifier, maybe_checksum) {
+            Ok(Some(file_or_redirect)) => {
+                warn!(
+                    "{}. Using the cached copy instead, which may be stale (EDGE_RUNTIME_ALLOW_STALE_MODULES is set).",
+                    err
+                );
+                self.cache_fallbacks.lock().push(specifier.clone());
+                Ok(file_or_redirect)
+            }
+            _ => Err(err),
+        }
+    }
+
     /// Returns if the cache should be used for a given specifier.
     fn should_use_cache(&self, specifier: &ModuleSpecifier, cache_setting: &CacheSetting) -> bool {
         match cache_setting {
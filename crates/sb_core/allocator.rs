@@ -0,0 +1,146 @@
+use serde::Serialize;
+
+/// Statistics of the global allocator, as reported by jemalloc.
+///
+/// Only available if edge-runtime was built with the `jemalloc` feature.
+#[derive(Debug, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocatorStatistics {
+    pub allocated: usize,
+    pub active: usize,
+    pub resident: usize,
+    pub mapped: usize,
+    pub retained: usize,
+    pub arenas: Vec<ArenaStatistics>,
+}
+
+#[derive(Debug, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ArenaStatistics {
+    pub index: u32,
+    pub threads: u32,
+    pub active: usize,
+    pub dirty: usize,
+    pub resident: usize,
+}
+
+#[cfg(feature = "jemalloc")]
+mod jemalloc {
+    use std::ffi::CString;
+    use std::mem::{size_of, MaybeUninit};
+    use std::ptr;
+
+    use super::{AllocatorStatistics, ArenaStatistics};
+
+    fn read<T: Copy>(name: &str) -> Option<T> {
+        let name = CString::new(name).ok()?;
+        let mut value = MaybeUninit::<T>::uninit();
+        let mut len = size_of::<T>();
+
+        // SAFETY: `value` is large enough to hold `len` bytes, and jemalloc
+        // only writes to it if the name refers to a value of that size.
+        let ret = unsafe {
+            jemalloc_sys::mallctl(
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut _,
+                &mut len,
+                ptr::null_mut(),
+                0,
+            )
+        };
+
+        if ret != 0 || len != size_of::<T>() {
+            return None;
+        }
+
+        Some(unsafe { value.assume_init() })
+    }
+
+    fn advance_epoch() -> bool {
+        let mut epoch = 1u64;
+        let len = size_of::<u64>();
+
+        // NOTE: jemalloc caches its statistics, writing to `epoch` is what
+        // refreshes them.
+        unsafe {
+            jemalloc_sys::mallctl(
+                c"epoch".as_ptr(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut epoch as *mut _ as *mut _,
+                len,
+            ) == 0
+        }
+    }
+
+    fn get_arena_statistics(index: u32, page_size: usize) -> Option<ArenaStatistics> {
+        if !read::<bool>(&format!("arena.{index}.initialized"))? {
+            return None;
+        }
+
+        let prefix = format!("stats.arenas.{index}");
+
+        Some(ArenaStatistics {
+            index,
+            threads: read(&format!("{prefix}.nthreads"))?,
+            active: read::<usize>(&format!("{prefix}.pactive"))? * page_size,
+            dirty: read::<usize>(&format!("{prefix}.pdirty"))? * page_size,
+            resident: read(&format!("{prefix}.resident"))?,
+        })
+    }
+
+    pub fn get_allocator_statistics() -> Option<AllocatorStatistics> {
+        if !advance_epoch() {
+            return None;
+        }
+
+        let page_size = read::<usize>("arenas.page")?;
+        let num_arenas = read::<u32>("arenas.narenas")?;
+
+        Some(AllocatorStatistics {
+            allocated: read("stats.allocated")?,
+            active: read("stats.active")?,
+            resident: read("stats.resident")?,
+            mapped: read("stats.mapped")?,
+            retained: read("stats.retained")?,
+            arenas: (0..num_arenas)
+                .filter_map(|it| get_arena_statistics(it, page_size))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(feature = "jemalloc")]
+pub use jemalloc::get_allocator_statistics;
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn get_allocator_statistics() -> Option<AllocatorStatistics> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::get_allocator_statistics;
+
+    #[cfg(not(feature = "jemalloc"))]
+    #[test]
+    fn test_no_statistics_without_jemalloc() {
+        assert!(get_allocator_statistics().is_none());
+    }
+
+    #[cfg(feature = "jemalloc")]
+    #[test]
+    fn test_statistics_of_jemalloc() {
+        // NOTE: The test binary does not use jemalloc as its global allocator,
+        // so it is allocated from directly.
+        let ptr = unsafe { jemalloc_sys::malloc(4 << 20) };
+        let stats = get_allocator_statistics().unwrap();
+
+        assert!(stats.allocated >= 4 << 20);
+        assert!(stats.active >= stats.allocated);
+        assert!(stats.mapped >= stats.active);
+        assert!(!stats.arenas.is_empty());
+
+        unsafe { jemalloc_sys::free(ptr) };
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Instant;
+
+use deno_core::op2;
+use deno_core::v8;
+use deno_core::JsRuntime;
+use serde::Serialize;
+
+/// The limits a user worker runs under, along with the CPU time it has used
+/// so far. The CPU time is updated by the runtime on every turn of the event
+/// loop.
+#[derive(Debug)]
+pub struct WorkerBudget {
+    pub memory_limit_bytes: u64,
+    pub cpu_time_soft_limit_ms: u64,
+    pub cpu_time_hard_limit_ms: u64,
+    pub worker_timeout_ms: u64,
+    pub started_at: Instant,
+    pub cpu_time_used_ns: i64,
+}
+
+impl WorkerBudget {
+    pub fn new(
+        memory_limit_bytes: u64,
+        cpu_time_soft_limit_ms: u64,
+        cpu_time_hard_limit_ms: u64,
+        worker_timeout_ms: u64,
+    ) -> Self {
+        Self {
+            memory_limit_bytes,
+            cpu_time_soft_limit_ms,
+            cpu_time_hard_limit_ms,
+            worker_timeout_ms,
+            started_at: Instant::now(),
+            cpu_time_used_ns: 0,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeContextLimits {
+    memory_limit_bytes: u64,
+    cpu_time_soft_limit_ms: u64,
+    cpu_time_hard_limit_ms: u64,
+    worker_timeout_ms: u64,
+}
+
+/// What is left of each limit. `None` means the limit is disabled.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeContextRemaining {
+    memory_bytes: Option<u64>,
+    cpu_time_ms: Option<u64>,
+    wall_clock_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeContext {
+    limits: RuntimeContextLimits,
+    remaining: RuntimeContextRemaining,
+}
+
+/// Returns the limits of the worker and the remaining budget, or `None` if the
+/// worker has no budget (i.e. it is not a user worker).
+#[op2]
+#[serde]
+pub fn op_runtime_context(scope: &mut v8::HandleScope) -> Option<RuntimeContext> {
+    let state_rc = JsRuntime::op_state_from(scope);
+    let state = state_rc.borrow();
+    let budget = state.try_borrow::<WorkerBudget>()?;
+
+    let mut stats = v8::HeapStatistics::default();
+
+    scope.get_heap_statistics(&mut stats);
+
+    let memory_used_bytes = stats
+        .used_heap_size()
+        .saturating_add(stats.external_memory()) as u64;
+    let cpu_time_used_ms = (budget.cpu_time_used_ns / 1_000_000).max(0) as u64;
+    let wall_clock_used_ms = budget.started_at.elapsed().as_millis() as u64;
+
+    let remaining = |limit: u64, used: u64| (limit > 0).then(|| limit.saturating_sub(used));
+
+    Some(RuntimeContext {
+        limits: RuntimeContextLimits {
+            memory_limit_bytes: budget.memory_limit_bytes,
+            cpu_time_soft_limit_ms: budget.cpu_time_soft_limit_ms,
+            cpu_time_hard_limit_ms: budget.cpu_time_hard_limit_ms,
+            worker_timeout_ms: budget.worker_timeout_ms,
+        },
+        remaining: RuntimeContextRemaining {
+            memory_bytes: remaining(budget.memory_limit_bytes, memory_used_bytes),
+            cpu_time_ms: remaining(budget.cpu_time_hard_limit_ms, cpu_time_used_ms),
+            wall_clock_ms: remaining(budget.worker_timeout_ms, wall_clock_used_ms),
+        },
+    })
+}
@@ -10,6 +10,7 @@ use once_cell::sync::OnceCell;
 use super::cache_db::CacheDB;
 use super::cache_db::CacheDBConfiguration;
 use super::check::TYPE_CHECK_CACHE_DB;
+use super::code_cache::CODE_CACHE_DB;
 use super::deno_dir::DenoDirProvider;
 use super::incremental::INCREMENTAL_CACHE_DB;
 use super::node::NODE_ANALYSIS_CACHE_DB;
@@ -21,6 +22,7 @@ pub struct Caches {
     dep_analysis_db: OnceCell<CacheDB>,
     node_analysis_db: OnceCell<CacheDB>,
     type_checking_cache_db: OnceCell<CacheDB>,
+    code_cache_db: OnceCell<CacheDB>,
 }
 
 impl Caches {
@@ -32,6 +34,7 @@ impl Caches {
             dep_analysis_db: Default::default(),
             node_analysis_db: Default::default(),
             type_checking_cache_db: Default::default(),
+            code_cache_db: Default::default(),
         }
     }
 
@@ -104,4 +107,15 @@ impl Caches {
                 .map(|dir| dir.type_checking_cache_db_file_path()),
         )
     }
+
+    pub fn code_cache_db(&self) -> CacheDB {
+        Self::make_db(
+            &self.code_cache_db,
+            &CODE_CACHE_DB,
+            self.dir_provider
+                .get_or_create()
+                .ok()
+                .map(|dir| dir.code_cache_db_file_path()),
+        )
+    }
 }
@@ -0,0 +1,342 @@
+//! The code V8 compiled for the modules of a service, kept in memory so that
+//! the instances booted after the first one can skip compiling them again.
+//!
+//! NOTE: V8 can't snapshot an isolate once the runtime has bootstrapped it,
+//! so the code cache is what a new instance inherits from a warm one.
+//!
+//! The code caches can also be persisted in the cache directory, so that they
+//! outlive the process. The entries that have not been used for a while are
+//! evicted from there, and so are the least recently used ones once the cache
+//! outgrows its size limit.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use deno_core::error::AnyError;
+use deno_webstorage::rusqlite::params;
+
+use super::cache_db::{CacheDB, CacheDBConfiguration, CacheDBHash, CacheFailure};
+use super::common::FastInsecureHasher;
+
+// NOTE: The services are all laid out under the same root, so the same
+// specifier can stand for modules of different services.
+pub static CODE_CACHE_DB: CacheDBConfiguration = CacheDBConfiguration {
+    table_initializer: concat!(
+        "CREATE TABLE IF NOT EXISTS codecache (",
+        "specifier TEXT NOT NULL,",
+        "source_hash INTEGER NOT NULL,",
+        "data BLOB NOT NULL,",
+        "last_used INTEGER NOT NULL,",
+        "PRIMARY KEY (specifier, source_hash)",
+        ");",
+        "CREATE INDEX IF NOT EXISTS codecache_last_used ON codecache (last_used);"
+    ),
+    on_version_change: "DELETE FROM codecache;",
+    preheat_queries: &[],
+    on_failure: CacheFailure::Blackhole,
+};
+
+/// The code cache of a service stops growing past this size.
+static MAX_CODE_CACHE_BYTES: usize = 32 * 1024 * 1024;
+
+/// The code caches persisted on disk are kept within this size.
+static MAX_DISK_CODE_CACHE_BYTES: u64 = 512 * 1024 * 1024;
+
+/// The code caches persisted on disk that have not been used for this long are
+/// evicted.
+static MAX_DISK_CODE_CACHE_AGE: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// How often the last use of an entry is recorded, so that reading the cache
+/// does not write to it every time.
+static LAST_USED_RESOLUTION_SECS: u64 = 3600;
+
+#[derive(Debug, Default)]
+struct Entries {
+    modules: HashMap<String, (u64, Arc<[u8]>)>,
+    size: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ModuleCodeCache(Arc<Mutex<Entries>>);
+
+impl ModuleCodeCache {
+    /// NOTE: The hash covers the version of the runtime, as a code cache
+    /// produced by another version of V8 is of no use.
+    pub fn hash_source(source: &str) -> u64 {
+        FastInsecureHasher::new_deno_versioned()
+            .write_str(source)
+            .finish()
+    }
+
+    /// Returns the code cache of the module, unless it was compiled from a
+    /// different source.
+    pub fn get(&self, specifier: &str, hash: u64) -> Option<Arc<[u8]>> {
+        self.0
+            .lock()
+            .unwrap()
+            .modules
+            .get(specifier)
+            .filter(|(it, _)| *it == hash)
+            .map(|(_, data)| data.clone())
+    }
+
+    pub fn set(&self, specifier: &str, hash: u64, data: &[u8]) {
+        let mut entries = self.0.lock().unwrap();
+        let replaced = entries
+            .modules
+            .get(specifier)
+            .map_or(0, |(_, data)| data.len());
+
+        if entries.size - replaced + data.len() > MAX_CODE_CACHE_BYTES {
+            return;
+        }
+
+        entries.size = entries.size - replaced + data.len();
+        entries
+            .modules
+            .insert(specifier.to_string(), (hash, Arc::from(data)));
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().modules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Clone)]
+pub struct DiskCodeCache {
+    conn: CacheDB,
+    max_bytes: u64,
+    max_age: Duration,
+}
+
+impl DiskCodeCache {
+    pub fn new(db: CacheDB) -> Self {
+        Self {
+            conn: db,
+            max_bytes: MAX_DISK_CODE_CACHE_BYTES,
+            max_age: MAX_DISK_CODE_CACHE_AGE,
+        }
+    }
+
+    /// Evicts the entries past the given size, or that have not been used for
+    /// the given duration, instead of the defaults.
+    pub fn with_limits(mut self, max_bytes: u64, max_age: Duration) -> Self {
+        self.max_bytes = max_bytes;
+        self.max_age = max_age;
+        self
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|it| it.as_secs())
+            .unwrap_or_default()
+    }
+
+    fn ensure_ok<T: Default>(res: Result<T, AnyError>) -> T {
+        res.unwrap_or_else(|err| {
+            log::debug!("Error using code cache: {:#}", err);
+            T::default()
+        })
+    }
+
+    pub fn get(&self, specifier: &str, hash: u64) -> Option<Vec<u8>> {
+        self.get_at(specifier, hash, Self::now())
+    }
+
+    fn get_at(&self, specifier: &str, hash: u64, now: u64) -> Option<Vec<u8>> {
+        let query = "
+      SELECT
+        data
+      FROM
+        codecache
+      WHERE
+        specifier=?1
+        AND source_hash=?2
+      LIMIT 1";
+
+        let data = Self::ensure_ok(self.conn.query_row(
+            query,
+            params![specifier, CacheDBHash::new(hash)],
+            |row| Ok(row.get::<_, Vec<u8>>(0)?),
+        ))?;
+
+        let sql = "
+      UPDATE
+        codecache
+      SET
+        last_used=?3
+      WHERE
+        specifier=?1
+        AND source_hash=?2
+        AND last_used<?4";
+
+        Self::ensure_ok(self.conn.execute(
+            sql,
+            params![
+                specifier,
+                CacheDBHash::new(hash),
+                now,
+                now.saturating_sub(LAST_USED_RESOLUTION_SECS)
+            ],
+        ));
+
+        Some(data)
+    }
+
+    pub fn set(&self, specifier: &str, hash: u64, data: &[u8]) {
+        self.set_at(specifier, hash, data, Self::now())
+    }
+
+    fn set_at(&self, specifier: &str, hash: u64, data: &[u8], now: u64) {
+        let sql = "
+      INSERT OR REPLACE INTO
+        codecache (specifier, source_hash, data, last_used)
+      VALUES
+        (?1, ?2, ?3, ?4)";
+
+        Self::ensure_ok(
+            self.conn
+                .execute(sql, params![specifier, CacheDBHash::new(hash), data, now]),
+        );
+
+        self.evict(now);
+    }
+
+    /// Evicts the entries that have not been used for too long, and then the
+    /// least recently used ones until the cache fits in its size limit.
+    fn evict(&self, now: u64) {
+        let sql = "
+      DELETE FROM
+        codecache
+      WHERE
+        last_used<?1";
+
+        Self::ensure_ok(
+            self.conn
+                .execute(sql, params![now.saturating_sub(self.max_age.as_secs())]),
+        );
+
+        let query = "
+      SELECT
+        COALESCE(SUM(LENGTH(data)), 0)
+      FROM
+        codecache";
+
+        let size = Self::ensure_ok(
+            self.conn
+                .query_row(query, [], |row| Ok(row.get::<_, u64>(0)?)),
+        )
+        .unwrap_or_default();
+
+        if size <= self.max_bytes {
+            return;
+        }
+
+        let sql = "
+      DELETE FROM
+        codecache
+      WHERE
+        rowid IN (
+          SELECT
+            rowid
+          FROM (
+            SELECT
+              rowid,
+              SUM(LENGTH(data)) OVER (ORDER BY last_used DESC, rowid DESC) AS size
+            FROM
+              codecache
+          )
+          WHERE
+            size>?1
+        )";
+
+        Self::ensure_ok(self.conn.execute(sql, params![self.max_bytes]));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::super::cache_db::CacheDB;
+    use super::{DiskCodeCache, ModuleCodeCache, CODE_CACHE_DB};
+
+    #[test]
+    fn test_module_code_cache() {
+        let cache = ModuleCodeCache::default();
+        let hash = ModuleCodeCache::hash_source("export default 1;");
+
+        assert!(cache.is_empty());
+
+        cache.set("file:///src/index.ts", hash, &[1, 2, 3]);
+
+        assert_eq!(
+            cache.get("file:///src/index.ts", hash).as_deref(),
+            Some(&[1, 2, 3][..])
+        );
+        assert!(cache
+            .get(
+                "file:///src/index.ts",
+                ModuleCodeCache::hash_source("export default 2;")
+            )
+            .is_none());
+
+        cache.set("file:///src/index.ts", hash, &[4, 5]);
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(
+            cache.get("file:///src/index.ts", hash).as_deref(),
+            Some(&[4, 5][..])
+        );
+    }
+
+    #[test]
+    fn test_disk_code_cache() {
+        let cache = DiskCodeCache::new(CacheDB::in_memory(&CODE_CACHE_DB, "1.0.0"));
+
+        assert!(cache.get("file:///src/index.ts", 1).is_none());
+
+        cache.set("file:///src/index.ts", 1, &[1, 2, 3]);
+
+        assert_eq!(cache.get("file:///src/index.ts", 1), Some(vec![1, 2, 3]));
+        assert!(cache.get("file:///src/index.ts", 2).is_none());
+
+        cache.set("file:///src/index.ts", 2, &[4]);
+        cache.set("file:///src/index.ts", 1, &[5, 6]);
+
+        assert_eq!(cache.get("file:///src/index.ts", 1), Some(vec![5, 6]));
+        assert_eq!(cache.get("file:///src/index.ts", 2), Some(vec![4]));
+    }
+
+    #[test]
+    fn test_disk_code_cache_eviction() {
+        let day = 24 * 3600;
+        let cache = DiskCodeCache::new(CacheDB::in_memory(&CODE_CACHE_DB, "1.0.0"))
+            .with_limits(8, Duration::from_secs(7 * day));
+
+        cache.set_at("file:///a.ts", 1, &[0; 3], day);
+        cache.set_at("file:///b.ts", 1, &[0; 3], 2 * day);
+
+        // NOTE: Using an entry keeps it from being the least recently used.
+        assert!(cache.get_at("file:///a.ts", 1, 3 * day).is_some());
+
+        cache.set_at("file:///c.ts", 1, &[0; 3], 4 * day);
+
+        assert!(cache.get_at("file:///a.ts", 1, 4 * day).is_some());
+        assert!(cache.get_at("file:///b.ts", 1, 4 * day).is_none());
+        assert!(cache.get_at("file:///c.ts", 1, 4 * day).is_some());
+
+        // NOTE: Neither has been used in the week before.
+        cache.set_at("file:///d.ts", 1, &[0; 1], 12 * day);
+
+        assert!(cache.get_at("file:///a.ts", 1, 12 * day).is_none());
+        assert!(cache.get_at("file:///c.ts", 1, 12 * day).is_none());
+        assert!(cache.get_at("file:///d.ts", 1, 12 * day).is_some());
+    }
+}
@@ -108,6 +108,12 @@ impl DenoDir {
         self.root.join("check_cache_v1")
     }
 
+    /// Path for the V8 code cache.
+    pub fn code_cache_db_file_path(&self) -> PathBuf {
+        // bump this version name to invalidate the entire cache
+        self.root.join("v8_code_cache_v2")
+    }
+
     /// Path to the registries cache, used for the lps.
     pub fn registries_folder_path(&self) -> PathBuf {
         self.root.join("registries")
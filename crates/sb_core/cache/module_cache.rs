@@ -0,0 +1,293 @@
+//! Maintenance of the cache of remote modules (i.e. `$DENO_DIR/deps`), which
+//! keeps the body of each module in a file of its own next to a
+//! `.metadata.json` file holding its headers.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use deno_core::serde_json;
+use deno_core::url::Url;
+use serde::Deserialize;
+
+use crate::util::checksum;
+
+/// The header under which the SHA-256 digest of a module is kept in its
+/// metadata, so that the body can be verified when it is read back.
+pub static CONTENT_HASH_HEADER: &str = "x-sb-content-sha256";
+
+static METADATA_EXTENSION: &str = "metadata.json";
+
+/// The last use of a module is only recorded if the previous one is older than
+/// this, so that reading a module does not write to the disk every time.
+static TOUCH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize)]
+struct Metadata {
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    url: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    /// The URL of the module, if its metadata could be read.
+    pub url: Option<String>,
+    pub path: PathBuf,
+    /// Size of the body and the metadata together.
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcSummary {
+    pub removed: usize,
+    pub freed: u64,
+    pub remaining: u64,
+}
+
+/// Returns the path of the body of a module in the cache, as laid out by the
+/// global HTTP cache.
+pub fn get_cache_path(deps_dir: &Path, url: &Url) -> Option<PathBuf> {
+    let mut path = deps_dir.join(url.scheme());
+
+    match url.scheme() {
+        "http" | "https" => {
+            let host = url.host_str()?;
+
+            path.push(match url.port() {
+                Some(port) => format!("{host}_PORT{port}"),
+                None => host.to_string(),
+            });
+        }
+
+        "data" | "blob" => {}
+        _ => return None,
+    }
+
+    let mut rest = url.path().to_string();
+
+    if let Some(query) = url.query() {
+        rest.push('?');
+        rest.push_str(query);
+    }
+
+    path.push(checksum::gen(&[rest.as_bytes()]));
+    Some(path)
+}
+
+/// Returns the headers to store along with a module so that its body can be
+/// verified later.
+pub fn with_content_hash(
+    mut headers: HashMap<String, String>,
+    content: &[u8],
+) -> HashMap<String, String> {
+    headers.insert(CONTENT_HASH_HEADER.to_string(), checksum::gen(&[content]));
+    headers
+}
+
+/// Returns `false` if the body of a module does not match the digest in its
+/// headers. Modules cached without a digest are assumed to be intact.
+pub fn verify_content(headers: &HashMap<String, String>, content: &[u8]) -> bool {
+    headers
+        .get(CONTENT_HASH_HEADER)
+        .map_or(true, |it| *it == checksum::gen(&[content]))
+}
+
+/// Records that a module has just been used, so that it is the last to be
+/// collected.
+pub fn touch(path: &Path) {
+    let now = SystemTime::now();
+    let is_stale = fs::metadata(path)
+        .and_then(|it| it.modified())
+        .map_or(false, |it| {
+            now.duration_since(it).unwrap_or_default() > TOUCH_INTERVAL
+        });
+
+    if is_stale {
+        let _ = fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|it| it.set_modified(now));
+    }
+}
+
+fn get_metadata_path(path: &Path) -> PathBuf {
+    path.with_extension(METADATA_EXTENSION)
+}
+
+fn read_metadata(path: &Path) -> Option<Metadata> {
+    serde_json::from_slice(&fs::read(get_metadata_path(path)).ok()?).ok()
+}
+
+fn walk(dir: &Path, entries: &mut Vec<CacheEntry>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            walk(&path, entries)?;
+            continue;
+        }
+
+        let is_metadata = path
+            .to_str()
+            .map_or(false, |it| it.ends_with(METADATA_EXTENSION));
+
+        if !file_type.is_file() || is_metadata {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        let metadata_size = fs::metadata(get_metadata_path(&path)).map_or(0, |it| it.len());
+
+        entries.push(CacheEntry {
+            url: read_metadata(&path).map(|it| it.url),
+            size: metadata.len() + metadata_size,
+            last_used: metadata.modified()?,
+            path,
+        });
+    }
+
+    Ok(())
+}
+
+/// Lists the modules in the cache.
+pub fn list_entries(deps_dir: &Path) -> io::Result<Vec<CacheEntry>> {
+    let mut entries = vec![];
+
+    match walk(deps_dir, &mut entries) {
+        Ok(()) => Ok(entries),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(err),
+    }
+}
+
+/// Removes a module from the cache along with its metadata.
+pub fn remove_entry(entry: &CacheEntry) -> io::Result<()> {
+    fs::remove_file(&entry.path)?;
+
+    match fs::remove_file(get_metadata_path(&entry.path)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Removes the least recently used modules until the cache fits in
+/// `max_size` bytes.
+pub fn collect_garbage(deps_dir: &Path, max_size: u64) -> io::Result<GcSummary> {
+    let mut entries = list_entries(deps_dir)?;
+    let mut summary = GcSummary {
+        remaining: entries.iter().map(|it| it.size).sum(),
+        ..Default::default()
+    };
+
+    entries.sort_by_key(|it| it.last_used);
+
+    for entry in entries {
+        if summary.remaining <= max_size {
+            break;
+        }
+
+        remove_entry(&entry)?;
+        summary.removed += 1;
+        summary.freed += entry.size;
+        summary.remaining -= entry.size;
+    }
+
+    Ok(summary)
+}
+
+/// Returns the modules whose body does not match the digest in their
+/// metadata, or whose metadata is unreadable.
+pub fn find_corrupted_entries(deps_dir: &Path) -> io::Result<Vec<CacheEntry>> {
+    let mut corrupted = vec![];
+
+    for entry in list_entries(deps_dir)? {
+        let is_intact = read_metadata(&entry.path).map_or(false, |metadata| {
+            fs::read(&entry.path).map_or(false, |it| verify_content(&metadata.headers, &it))
+        });
+
+        if !is_intact {
+            corrupted.push(entry);
+        }
+    }
+
+    Ok(corrupted)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    use deno_core::serde_json::json;
+    use deno_core::url::Url;
+
+    use super::{
+        collect_garbage, find_corrupted_entries, get_cache_path, list_entries, with_content_hash,
+    };
+
+    #[test]
+    fn test_module_cache_gc() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+
+        for (idx, name) in ["a", "b", "c"].into_iter().enumerate() {
+            let url = Url::parse(&format!("https://example.com:8443/{name}.ts")).unwrap();
+            let path = get_cache_path(dir.path(), &url).unwrap();
+            let content = vec![0u8; 100];
+            let headers = with_content_hash(HashMap::new(), &content);
+
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, &content).unwrap();
+            fs::write(
+                path.with_extension("metadata.json"),
+                json!({ "headers": headers, "url": url.as_str() }).to_string(),
+            )
+            .unwrap();
+
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(60 * (3 - idx as u64)))
+                .unwrap();
+        }
+
+        assert!(dir.path().join("https/example.com_PORT8443").is_dir());
+
+        let entries = list_entries(dir.path()).unwrap();
+        let total = entries.iter().map(|it| it.size).sum::<u64>();
+
+        assert_eq!(entries.len(), 3);
+        assert!(find_corrupted_entries(dir.path()).unwrap().is_empty());
+
+        let summary = collect_garbage(dir.path(), total - 1).unwrap();
+        let remaining = list_entries(dir.path()).unwrap();
+
+        // NOTE: The least recently used module is collected first.
+        assert_eq!(summary.removed, 1);
+        assert_eq!(remaining.len(), 2);
+        assert!(remaining
+            .iter()
+            .all(|it| it.url.as_deref() != Some("https://example.com:8443/a.ts")));
+
+        let c = get_cache_path(
+            dir.path(),
+            &Url::parse("https://example.com:8443/c.ts").unwrap(),
+        )
+        .unwrap();
+
+        fs::write(&c, b"tampered").unwrap();
+
+        let corrupted = find_corrupted_entries(dir.path()).unwrap();
+
+        assert_eq!(corrupted.len(), 1);
+        assert_eq!(corrupted[0].path, c);
+    }
+}
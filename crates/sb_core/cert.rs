@@ -1,12 +1,28 @@
-use anyhow::Context;
+use anyhow::{bail, Context};
 use deno_core::error::AnyError;
 use deno_tls::deno_native_certs::load_native_certs;
-use deno_tls::rustls::RootCertStore;
+use deno_tls::rustls::crypto::{ring, CryptoProvider};
+use deno_tls::rustls::pki_types::CertificateDer;
+use deno_tls::rustls::{self, RootCertStore, SupportedProtocolVersion};
 use deno_tls::{rustls_pemfile, webpki_roots, RootCertStoreProvider};
-use std::io::{BufReader, Cursor, Read};
-use std::path::PathBuf;
+use log::{info, warn};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
+use crate::cert_pinning::is_valid_pin;
+
+static SERVER_CERT_STATISTICS: Mutex<Option<ServerCertStatistics>> = Mutex::new(None);
+static DEFAULT_OUTBOUND_TLS: OnceLock<OutboundTlsOptions> = OnceLock::new();
+static CA_DIRS: Lazy<Mutex<HashMap<PathBuf, CaDir>>> = Lazy::new(Mutex::default);
+static DEFAULT_ROOT_CERT_STORE: OnceCell<(RootCertStore, RootCertStoreReport)> = OnceCell::new();
+
 pub struct ValueRootCertStoreProvider {
     pub root_cert_store: RootCertStore,
 }
@@ -25,7 +41,8 @@ impl RootCertStoreProvider for ValueRootCertStoreProvider {
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum CaData {
-    /// The string is a file path
+    /// The string is the path of a PEM file, or of a directory of `.pem` and
+    /// `.crt` files
     File(String),
     /// This variant is not exposed as an option in the CLI, it is used internally
     /// for standalone binaries.
@@ -36,18 +53,93 @@ pub enum CaData {
 pub enum RootCertStoreLoadError {
     #[error("Unknown certificate store \"{0}\" specified (allowed: \"system,mozilla\")")]
     UnknownStore(String),
-    #[error("Unable to add pem file to certificate store: {0}")]
-    FailedAddPemFile(String),
     #[error("Failed opening CA file: {0}")]
     CaFileOpenError(String),
 }
 
+/// What went into a root certificate store.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RootCertStoreReport {
+    /// The number of certificates in the store.
+    pub loaded: usize,
+    pub skipped: Vec<SkippedCert>,
+}
+
+/// A certificate that was left out of a root certificate store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedCert {
+    /// Where the certificate comes from, e.g. `system` or the path of a CA
+    /// file, along with its position there.
+    pub source: String,
+    pub reason: String,
+}
+
+impl RootCertStoreReport {
+    /// Logs the certificates that were skipped.
+    pub fn log(&self, name: &str) {
+        for it in self.skipped.iter() {
+            warn!(
+                "skipped a certificate of the {} store ({}): {}",
+                name, it.source, it.reason
+            );
+        }
+    }
+}
+
+/// Returns the certificates of a PEM file, along with the ones that could not
+/// be parsed or would not be accepted by a root certificate store.
+fn parse_pem_certs(
+    source: &str,
+    reader: &mut dyn BufRead,
+) -> (Vec<CertificateDer<'static>>, Vec<SkippedCert>) {
+    let mut certs = vec![];
+    let mut skipped = vec![];
+    let mut store = RootCertStore::empty();
+
+    for (idx, cert) in rustls_pemfile::certs(reader).enumerate() {
+        let result = cert
+            .with_context(|| "failed to load the certificate")
+            .and_then(|it| {
+                store
+                    .add(it.clone())
+                    .with_context(|| "error adding a certificate to the store")?;
+
+                Ok(it)
+            });
+
+        match result {
+            Ok(it) => certs.push(it),
+            Err(err) => skipped.push(SkippedCert {
+                source: format!("{} #{}", source, idx),
+                reason: format!("{:#}", err),
+            }),
+        }
+    }
+
+    if certs.is_empty() && skipped.is_empty() {
+        skipped.push(SkippedCert {
+            source: source.to_string(),
+            reason: "no certificate found".to_string(),
+        });
+    }
+
+    (certs, skipped)
+}
+
+/// Builds a root certificate store from the given stores and CA data, falling
+/// back to `DENO_TLS_CA_STORE` and `DENO_CERT` respectively.
+///
+/// NOTE: A certificate that can't be loaded is skipped and reported rather
+/// than failing the whole store.
 pub fn get_root_cert_store(
     maybe_root_path: Option<PathBuf>,
     maybe_ca_stores: Option<Vec<String>>,
     maybe_ca_data: Option<CaData>,
-) -> Result<RootCertStore, RootCertStoreLoadError> {
+) -> Result<(RootCertStore, RootCertStoreReport), RootCertStoreLoadError> {
     let mut root_cert_store = RootCertStore::empty();
+    let mut skipped = vec![];
     let ca_stores: Vec<String> = maybe_ca_stores
         .or_else(|| {
             let env_ca_store = std::env::var("DENO_TLS_CA_STORE").ok()?;
@@ -67,14 +159,23 @@ pub fn get_root_cert_store(
                 root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
             }
 
-            "system" => {
-                let roots = load_native_certs().expect("could not load platform certs");
-                for root in roots {
-                    root_cert_store
-                        .add((&*root.0).into())
-                        .expect("Failed to add platform cert to root cert store");
+            "system" => match load_native_certs() {
+                Ok(roots) => {
+                    for (idx, root) in roots.into_iter().enumerate() {
+                        if let Err(err) = root_cert_store.add((&*root.0).into()) {
+                            skipped.push(SkippedCert {
+                                source: format!("system #{}", idx),
+                                reason: err.to_string(),
+                            });
+                        }
+                    }
                 }
-            }
+
+                Err(err) => skipped.push(SkippedCert {
+                    source: "system".to_string(),
+                    reason: format!("could not load platform certs: {}", err),
+                }),
+            },
 
             _ => {
                 return Err(RootCertStoreLoadError::UnknownStore(store.clone()));
@@ -84,34 +185,563 @@ pub fn get_root_cert_store(
 
     let ca_data = maybe_ca_data.or_else(|| std::env::var("DENO_CERT").ok().map(CaData::File));
     if let Some(ca_data) = ca_data {
-        let mut reader: BufReader<Box<dyn Read>> = match ca_data {
+        let (certs, ca_skipped) = match ca_data {
             CaData::File(ca_file) => {
                 let ca_file = if let Some(root) = &maybe_root_path {
                     root.join(&ca_file)
                 } else {
                     PathBuf::from(ca_file)
                 };
-                let certfile = std::fs::File::open(ca_file)
-                    .map_err(|err| RootCertStoreLoadError::CaFileOpenError(err.to_string()))?;
-                BufReader::new(Box::new(certfile) as _)
+
+                if ca_file.is_dir() {
+                    load_ca_dir(&ca_file)?
+                } else {
+                    let certfile = std::fs::File::open(&ca_file)
+                        .map_err(|err| RootCertStoreLoadError::CaFileOpenError(err.to_string()))?;
+
+                    parse_pem_certs(&ca_file.to_string_lossy(), &mut BufReader::new(certfile))
+                }
             }
 
-            CaData::Bytes(data) => BufReader::new(Box::new(Cursor::new(data)) as _),
+            CaData::Bytes(data) => parse_pem_certs("CA data", &mut Cursor::new(data)),
         };
 
-        for cert in rustls_pemfile::certs(&mut reader) {
-            if let Err(err) = cert
-                .with_context(|| "failed to load the certificate")
-                .and_then(|it| {
-                    root_cert_store
-                        .add(it.clone())
-                        .with_context(|| "error adding a certificate to the store")
-                })
-            {
-                return Err(RootCertStoreLoadError::FailedAddPemFile(err.to_string()));
+        for cert in certs {
+            // NOTE: The certificates were checked when parsed.
+            let _ = root_cert_store.add(cert);
+        }
+
+        skipped.extend(ca_skipped);
+    }
+
+    let report = RootCertStoreReport {
+        loaded: root_cert_store.len(),
+        skipped,
+    };
+
+    Ok((root_cert_store, report))
+}
+
+/// Loads the root certificate store shared by the workers, and logs what it
+/// skipped. Only the first call loads it.
+pub fn init_default_root_cert_store() -> Result<&'static RootCertStore, RootCertStoreLoadError> {
+    DEFAULT_ROOT_CERT_STORE
+        .get_or_try_init(|| {
+            let (store, report) = get_root_cert_store(None, None, None)?;
+
+            report.log("default");
+            info!("loaded {} root certificates", report.loaded);
+
+            Ok((store, report))
+        })
+        .map(|(store, _)| store)
+}
+
+pub fn get_default_root_cert_store_report() -> Option<RootCertStoreReport> {
+    DEFAULT_ROOT_CERT_STORE
+        .get()
+        .map(|(_, report)| report.clone())
+}
+
+#[derive(Default)]
+struct CaDir {
+    /// The path, modification time and size of the files the certificates
+    /// were loaded from.
+    files: Vec<(PathBuf, SystemTime, u64)>,
+    certs: Vec<CertificateDer<'static>>,
+    skipped: Vec<SkippedCert>,
+}
+
+fn is_ca_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|it| it == "pem" || it == "crt")
+}
+
+/// Returns the certificates of the `.pem` and `.crt` files of a directory,
+/// along with the ones that were skipped.
+///
+/// NOTE: The directory is only loaded again once its files have changed, so
+/// the CA files dropped into it are picked up by the workers booted afterwards
+/// without parsing every file on each boot.
+fn load_ca_dir(
+    dir: &Path,
+) -> Result<(Vec<CertificateDer<'static>>, Vec<SkippedCert>), RootCertStoreLoadError> {
+    let mut files = std::fs::read_dir(dir)
+        .map_err(|err| RootCertStoreLoadError::CaFileOpenError(err.to_string()))?
+        .filter_map(Result::ok)
+        .map(|it| it.path())
+        .filter(|it| is_ca_file(it))
+        .filter_map(|path| {
+            let metadata = std::fs::metadata(&path).ok()?;
+
+            metadata.is_file().then(|| {
+                let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+
+                (path, modified, metadata.len())
+            })
+        })
+        .collect::<Vec<_>>();
+
+    files.sort();
+
+    let mut dirs = CA_DIRS.lock().unwrap();
+    let entry = dirs.entry(dir.to_path_buf()).or_default();
+
+    if entry.files != files {
+        let mut certs = vec![];
+        let mut skipped = vec![];
+
+        for (path, ..) in files.iter() {
+            let source = path.to_string_lossy();
+
+            match std::fs::File::open(path) {
+                Ok(file) => {
+                    let (file_certs, file_skipped) =
+                        parse_pem_certs(&source, &mut BufReader::new(file));
+
+                    certs.extend(file_certs);
+                    skipped.extend(file_skipped);
+                }
+
+                Err(err) => skipped.push(SkippedCert {
+                    source: source.into_owned(),
+                    reason: err.to_string(),
+                }),
+            }
+        }
+
+        *entry = CaDir {
+            files,
+            certs,
+            skipped,
+        };
+    }
+
+    Ok((entry.certs.clone(), entry.skipped.clone()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl FromStr for TlsVersion {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1.2" => Ok(Self::Tls12),
+            "1.3" => Ok(Self::Tls13),
+            _ => bail!("unsupported TLS version: {} (allowed: 1.2, 1.3)", s),
+        }
+    }
+}
+
+/// The TLS settings of the outbound connections of a worker, in place of the
+/// ones shared by the whole runtime.
+///
+/// NOTE: There is no option for renegotiation, as rustls never renegotiates.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OutboundTlsOptions {
+    /// Stores the roots are taken from (`mozilla`, `system`). An empty list
+    /// trusts only the certificates of `ca_file`.
+    pub ca_stores: Option<Vec<String>>,
+    /// Path to PEM-encoded certificates to trust in addition to the stores,
+    /// or to a directory of them. The one of a worker is relative to its
+    /// service directory, which it can't leave (see
+    /// [`OutboundTlsOptions::confine_ca_file`]).
+    pub ca_file: Option<String>,
+    /// Only applies to `fetch`.
+    pub min_version: Option<TlsVersion>,
+    /// Only applies to `fetch`.
+    pub max_version: Option<TlsVersion>,
+    /// Only applies to `fetch`. The names are the IANA ones, e.g.
+    /// `TLS13_AES_128_GCM_SHA256`, and every suite is allowed if unset.
+    pub cipher_suites: Option<Vec<String>>,
+    /// Only applies to `fetch`. Defaults to `h2` and `http/1.1`.
+    pub alpn_protocols: Option<Vec<String>>,
+    /// The SPKI pins of the destination hosts, see [`crate::cert_pinning`].
+    /// Only applies to `fetch`.
+    pub pins: Option<BTreeMap<String, Vec<String>>>,
+}
+
+impl OutboundTlsOptions {
+    /// Returns the root store of the worker, or `None` if it should use the
+    /// one of the runtime.
+    pub fn root_cert_store(&self) -> Result<Option<RootCertStore>, RootCertStoreLoadError> {
+        if self.ca_stores.is_none() && self.ca_file.is_none() {
+            return Ok(None);
+        }
+
+        let (store, report) = get_root_cert_store(
+            None,
+            self.ca_stores.clone(),
+            self.ca_file.clone().map(CaData::File),
+        )?;
+
+        report.log("outbound TLS");
+
+        Ok(Some(store))
+    }
+
+    /// Resolves `ca_file` against the directory of the service, and rejects it
+    /// if it points outside of it.
+    ///
+    /// NOTE: Only the defaults of the runtime, which come from the operator,
+    /// may point anywhere else.
+    pub fn confine_ca_file(mut self, service_dir: &Path) -> Result<Self, AnyError> {
+        let Some(ca_file) = self.ca_file.as_deref() else {
+            return Ok(self);
+        };
+
+        let root = service_dir
+            .canonicalize()
+            .with_context(|| "can't resolve the service directory")?;
+        let path = root
+            .join(ca_file)
+            .canonicalize()
+            .with_context(|| format!("can't resolve the CA file: {}", ca_file))?;
+
+        if !path.starts_with(&root) {
+            bail!(
+                "the CA file must be within the service directory: {}",
+                ca_file
+            );
+        }
+
+        self.ca_file = Some(path.to_string_lossy().into_owned());
+
+        Ok(self)
+    }
+
+    /// Returns the options of the worker, with the unset ones taken from the
+    /// defaults of the runtime.
+    pub fn or_defaults(self, defaults: &Self) -> Self {
+        Self {
+            ca_stores: self.ca_stores.or_else(|| defaults.ca_stores.clone()),
+            ca_file: self.ca_file.or_else(|| defaults.ca_file.clone()),
+            min_version: self.min_version.or(defaults.min_version),
+            max_version: self.max_version.or(defaults.max_version),
+            cipher_suites: self
+                .cipher_suites
+                .or_else(|| defaults.cipher_suites.clone()),
+            alpn_protocols: self
+                .alpn_protocols
+                .or_else(|| defaults.alpn_protocols.clone()),
+            pins: self.pins.or_else(|| defaults.pins.clone()),
+        }
+    }
+
+    pub fn protocol_versions(&self) -> Result<Vec<&'static SupportedProtocolVersion>, AnyError> {
+        let min = self.min_version.unwrap_or(TlsVersion::Tls12);
+        let max = self.max_version.unwrap_or(TlsVersion::Tls13);
+
+        if min > max {
+            bail!("the minimum TLS version is greater than the maximum one");
+        }
+
+        Ok([
+            (TlsVersion::Tls13, &rustls::version::TLS13),
+            (TlsVersion::Tls12, &rustls::version::TLS12),
+        ]
+        .into_iter()
+        .filter(|(version, _)| (min..=max).contains(version))
+        .map(|(_, it)| it)
+        .collect())
+    }
+
+    /// Returns the crypto provider restricted to the allowed cipher suites.
+    pub fn crypto_provider(&self) -> Result<CryptoProvider, AnyError> {
+        let mut provider = ring::default_provider();
+
+        if let Some(names) = self.cipher_suites.as_ref() {
+            for name in names {
+                if !provider
+                    .cipher_suites
+                    .iter()
+                    .any(|it| cipher_suite_name(it) == *name)
+                {
+                    bail!("unsupported cipher suite: {}", name);
+                }
             }
+
+            provider
+                .cipher_suites
+                .retain(|it| names.contains(&cipher_suite_name(it)));
         }
+
+        Ok(provider)
+    }
+
+    /// Checks that a client can be configured from the options.
+    pub fn validate(&self) -> Result<(), AnyError> {
+        let versions = self.protocol_versions()?;
+        let provider = self.crypto_provider()?;
+
+        if !provider
+            .cipher_suites
+            .iter()
+            .any(|it| versions.iter().any(|v| v.version == it.version().version))
+        {
+            bail!("none of the allowed cipher suites fits the allowed TLS versions");
+        }
+
+        for (host, pins) in self.pins.iter().flatten() {
+            if pins.is_empty() {
+                bail!("no pins for host: {}", host);
+            }
+            if let Some(pin) = pins.iter().find(|it| !is_valid_pin(it)) {
+                bail!("invalid pin for host {}: {}", host, pin);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn cipher_suite_name(suite: &rustls::SupportedCipherSuite) -> String {
+    format!("{:?}", suite.suite())
+}
+
+/// Sets the TLS settings shared by the outbound connections of all workers.
+/// Only the first call has an effect.
+pub fn init_default_outbound_tls(opts: OutboundTlsOptions) {
+    let _ = DEFAULT_OUTBOUND_TLS.get_or_init(|| opts);
+}
+
+pub fn get_default_outbound_tls() -> Option<&'static OutboundTlsOptions> {
+    DEFAULT_OUTBOUND_TLS.get()
+}
+
+/// The state of the certificate served by the TLS listener of the server.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerCertStatistics {
+    pub not_after_ms: u64,
+    pub days_left: u64,
+    pub reloads: u64,
+    pub has_ocsp_response: bool,
+}
+
+pub fn set_server_cert_statistics(stats: ServerCertStatistics) {
+    *SERVER_CERT_STATISTICS.lock().unwrap() = Some(stats);
+}
+
+pub fn get_server_cert_statistics() -> Option<ServerCertStatistics> {
+    *SERVER_CERT_STATISTICS.lock().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::{
+        get_root_cert_store, parse_pem_certs, CaData, OutboundTlsOptions, RootCertStoreLoadError,
+        SkippedCert, TlsVersion,
+    };
+
+    #[test]
+    fn test_confine_ca_file() {
+        let root = tempfile::tempdir().unwrap();
+        let service_dir = root.path().join("service");
+        let confine = |ca_file: &str| {
+            OutboundTlsOptions {
+                ca_file: Some(ca_file.to_string()),
+                ..Default::default()
+            }
+            .confine_ca_file(&service_dir)
+        };
+
+        std::fs::create_dir_all(service_dir.join("certs")).unwrap();
+        std::fs::write(service_dir.join("certs/root-ca.pem"), ROOT_CA).unwrap();
+        std::fs::write(root.path().join("outside.pem"), ROOT_CA).unwrap();
+
+        let expected = service_dir
+            .join("certs/root-ca.pem")
+            .canonicalize()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+
+        assert_eq!(
+            confine("certs/root-ca.pem").unwrap().ca_file,
+            Some(expected)
+        );
+        assert!(confine("certs").is_ok());
+        assert!(confine("../outside.pem").is_err());
+        assert!(confine(&root.path().join("outside.pem").to_string_lossy()).is_err());
+        assert!(confine("certs/missing.pem").is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(
+                root.path().join("outside.pem"),
+                service_dir.join("link.pem"),
+            )
+            .unwrap();
+
+            assert!(confine("link.pem").is_err());
+        }
+
+        assert_eq!(
+            OutboundTlsOptions::default()
+                .confine_ca_file(&service_dir)
+                .unwrap(),
+            OutboundTlsOptions::default()
+        );
     }
 
-    Ok(root_cert_store)
+    static ROOT_CA: &str = include_str!("../base/tests/fixture/tls/root-ca.pem");
+
+    #[test]
+    fn test_root_cert_store_from_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let load = || {
+            get_root_cert_store(
+                None,
+                Some(vec![]),
+                Some(CaData::File(dir.path().to_string_lossy().into_owned())),
+            )
+            .unwrap()
+        };
+
+        std::fs::write(dir.path().join("root-ca.pem"), ROOT_CA).unwrap();
+        std::fs::write(dir.path().join("broken.crt"), "not a certificate").unwrap();
+        std::fs::write(dir.path().join("README"), ROOT_CA).unwrap();
+
+        let (store, report) = load();
+
+        assert_eq!(store.len(), 1);
+        assert_eq!(report.loaded, 1);
+        assert_eq!(
+            report.skipped,
+            vec![SkippedCert {
+                source: dir.path().join("broken.crt").to_string_lossy().into_owned(),
+                reason: "no certificate found".to_string(),
+            }]
+        );
+
+        std::fs::write(dir.path().join("root-ca-copy.crt"), ROOT_CA).unwrap();
+
+        assert_eq!(load().0.len(), 2);
+    }
+
+    static BROKEN_CERT: &str = "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n";
+
+    #[test]
+    fn test_parse_pem_certs_skips_bad_certs() {
+        let pem = format!("{}{}{}", ROOT_CA, BROKEN_CERT, ROOT_CA);
+        let (certs, skipped) = parse_pem_certs("bundle.pem", &mut Cursor::new(pem));
+
+        assert_eq!(certs.len(), 2);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].source, "bundle.pem #1");
+
+        let (certs, skipped) = parse_pem_certs("empty.pem", &mut Cursor::new(""));
+
+        assert!(certs.is_empty());
+        assert_eq!(
+            skipped,
+            vec![SkippedCert {
+                source: "empty.pem".to_string(),
+                reason: "no certificate found".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_root_cert_store_from_bytes() {
+        let pem = format!("{}{}", BROKEN_CERT, ROOT_CA);
+        let (store, report) =
+            get_root_cert_store(None, Some(vec![]), Some(CaData::Bytes(pem.into_bytes()))).unwrap();
+
+        assert_eq!(store.len(), 1);
+        assert_eq!(report.loaded, 1);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].source, "CA data #0");
+    }
+
+    #[test]
+    fn test_root_cert_store_errors() {
+        assert!(matches!(
+            get_root_cert_store(None, Some(vec!["nope".to_string()]), None),
+            Err(RootCertStoreLoadError::UnknownStore(_))
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(matches!(
+            get_root_cert_store(
+                Some(dir.path().to_path_buf()),
+                Some(vec![]),
+                Some(CaData::File("missing.pem".to_string())),
+            ),
+            Err(RootCertStoreLoadError::CaFileOpenError(_))
+        ));
+    }
+
+    #[test]
+    fn test_outbound_tls_protocol_versions() {
+        let tls = OutboundTlsOptions {
+            max_version: Some(TlsVersion::Tls12),
+            ..Default::default()
+        };
+
+        assert_eq!(tls.protocol_versions().unwrap().len(), 1);
+        assert!(tls.validate().is_ok());
+
+        let tls = OutboundTlsOptions {
+            min_version: Some(TlsVersion::Tls13),
+            max_version: Some(TlsVersion::Tls12),
+            ..Default::default()
+        };
+
+        assert!(tls.protocol_versions().is_err());
+    }
+
+    #[test]
+    fn test_outbound_tls_cipher_suites() {
+        let tls = OutboundTlsOptions {
+            cipher_suites: Some(vec!["TLS13_AES_128_GCM_SHA256".to_string()]),
+            ..Default::default()
+        };
+
+        assert_eq!(tls.crypto_provider().unwrap().cipher_suites.len(), 1);
+        assert!(tls.validate().is_ok());
+
+        let tls = OutboundTlsOptions {
+            max_version: Some(TlsVersion::Tls12),
+            ..tls
+        };
+
+        assert!(tls.validate().is_err());
+
+        let tls = OutboundTlsOptions {
+            cipher_suites: Some(vec!["TLS_RSA_WITH_RC4_128_MD5".to_string()]),
+            ..Default::default()
+        };
+
+        assert!(tls.crypto_provider().is_err());
+    }
+
+    #[test]
+    fn test_outbound_tls_or_defaults() {
+        let defaults = OutboundTlsOptions {
+            min_version: Some(TlsVersion::Tls12),
+            cipher_suites: Some(vec!["TLS13_AES_128_GCM_SHA256".to_string()]),
+            ..Default::default()
+        };
+        let tls = OutboundTlsOptions {
+            min_version: Some(TlsVersion::Tls13),
+            ..Default::default()
+        }
+        .or_defaults(&defaults);
+
+        assert_eq!(tls.min_version, Some(TlsVersion::Tls13));
+        assert_eq!(tls.cipher_suites, defaults.cipher_suites);
+    }
 }
@@ -0,0 +1,166 @@
+//! Pinning of the public keys presented by the destinations of the outbound
+//! connections of a worker.
+//!
+//! A pin is the base64-encoded SHA-256 digest of the DER-encoded subject public
+//! key info (SPKI) of a certificate, as in `openssl x509 -pubkey | openssl pkey
+//! -pubin -outform der | openssl dgst -sha256 -binary | base64`. A destination
+//! with pins is only trusted if one of the certificates of its chain matches
+//! one of them, on top of the usual verification.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use deno_tls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use deno_tls::rustls::client::WebPkiServerVerifier;
+use deno_tls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use deno_tls::rustls::{self, CertificateError, DigitallySignedStruct, SignatureScheme};
+use ring::digest::{digest, SHA256};
+
+/// A destination presented a chain that matches none of its pins.
+#[derive(Debug, Clone)]
+pub struct PinViolation {
+    pub host: String,
+    /// The pins of the certificates of the presented chain.
+    pub presented_pins: Vec<String>,
+}
+
+pub type PinViolationHandler = Arc<dyn Fn(PinViolation) + Send + Sync>;
+
+/// Returns the pin of a DER-encoded certificate.
+pub fn get_spki_pin(cert: &[u8]) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+
+    Some(STANDARD.encode(digest(&SHA256, cert.tbs_certificate.subject_pki.raw)))
+}
+
+/// Checks that a pin is the base64 encoding of a SHA-256 digest.
+pub fn is_valid_pin(pin: &str) -> bool {
+    STANDARD
+        .decode(pin)
+        .is_ok_and(|it| it.len() == SHA256.output_len)
+}
+
+pub struct PinningVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: BTreeMap<String, Vec<String>>,
+    maybe_on_violation: Option<PinViolationHandler>,
+}
+
+impl fmt::Debug for PinningVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PinningVerifier")
+            .field("inner", &self.inner)
+            .field("pins", &self.pins)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PinningVerifier {
+    pub fn new(
+        inner: Arc<WebPkiServerVerifier>,
+        pins: BTreeMap<String, Vec<String>>,
+        maybe_on_violation: Option<PinViolationHandler>,
+    ) -> Self {
+        Self {
+            inner,
+            pins,
+            maybe_on_violation,
+        }
+    }
+
+    fn get_pins(&self, host: &str) -> Option<&Vec<String>> {
+        self.pins
+            .iter()
+            .find(|(it, _)| it.eq_ignore_ascii_case(host))
+            .map(|(_, pins)| pins)
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let host = match server_name {
+            ServerName::DnsName(it) => it.as_ref().to_string(),
+            ServerName::IpAddress(it) => IpAddr::from(*it).to_string(),
+            _ => return Ok(verified),
+        };
+
+        let Some(pins) = self.get_pins(&host) else {
+            return Ok(verified);
+        };
+
+        let presented_pins = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|it| get_spki_pin(it))
+            .collect::<Vec<_>>();
+
+        if presented_pins.iter().any(|it| pins.contains(it)) {
+            return Ok(verified);
+        }
+
+        if let Some(on_violation) = self.maybe_on_violation.as_ref() {
+            on_violation(PinViolation {
+                host,
+                presented_pins,
+            });
+        }
+
+        Err(rustls::Error::InvalidCertificate(
+            CertificateError::ApplicationVerificationFailure,
+        ))
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_valid_pin;
+
+    #[test]
+    fn test_is_valid_pin() {
+        assert!(is_valid_pin("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="));
+        assert!(!is_valid_pin("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NM"));
+        assert!(!is_valid_pin("not a pin"));
+    }
+}
@@ -0,0 +1,182 @@
+//! The clock of a user worker, which can be coarsened so that the workers of
+//! different tenants sharing a host can't time each other precisely enough
+//! for cache and CPU side-channel attacks.
+//!
+//! Once installed, `Date` and `performance.now()` read it instead of the
+//! clocks of the host. Its time only moves in steps of the granularity, and
+//! its monotonic time counts from the boot of the worker.
+//!
+//! The monotonic time is coarsened at its source, `op_now` of `deno_web` (see
+//! [`op_now_coarsened`]), so that everything built on it, e.g.
+//! `performance.mark()`, `performance.measure()` and the time stamps of
+//! events, is coarsened too.
+//!
+//! A replayed worker reads the [`ReplayClock`] instead.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use deno_core::{op2, OpState};
+
+use crate::replay::ReplayClock;
+
+/// The precision of the monotonic time of a worker without a clock of its
+/// own, which is what `deno_web` gives without the high-resolution time
+/// permission.
+static HOST_CLOCK_PRECISION: Duration = Duration::from_millis(2);
+
+/// When the runtime started, which the monotonic time of a worker without a
+/// clock of its own counts from.
+#[derive(Debug, Clone, Copy)]
+pub struct HostClockOrigin(pub Instant);
+
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerClock {
+    origin: Instant,
+    origin_epoch_ms: u64,
+    granularity_ms: u64,
+}
+
+impl WorkerClock {
+    pub fn new(granularity_ms: u64) -> Self {
+        Self {
+            origin: Instant::now(),
+            origin_epoch_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|it| it.as_millis() as u64)
+                .unwrap_or_default(),
+            granularity_ms: granularity_ms.max(1),
+        }
+    }
+
+    pub fn granularity_ms(&self) -> u64 {
+        self.granularity_ms
+    }
+
+    /// Milliseconds since the boot of the worker.
+    pub fn monotonic_ms(&self) -> u64 {
+        self.coarsen(self.origin.elapsed().as_millis() as u64)
+    }
+
+    /// Milliseconds since the Unix epoch.
+    ///
+    /// NOTE: This is derived from the monotonic time, so it does not follow
+    /// adjustments of the clock of the host made after the boot.
+    pub fn now_ms(&self) -> u64 {
+        self.coarsen(self.origin_epoch_ms + self.origin.elapsed().as_millis() as u64)
+    }
+
+    fn coarsen(&self, ms: u64) -> u64 {
+        ms - ms % self.granularity_ms
+    }
+}
+
+/// Milliseconds since the Unix epoch, as the worker sees them.
+pub fn worker_now_ms(state: &OpState) -> u64 {
+    if let Some(clock) = state.try_borrow::<ReplayClock>() {
+        return clock.now_ms();
+    }
+
+    match state.try_borrow::<WorkerClock>() {
+        Some(clock) => clock.now_ms(),
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|it| it.as_millis() as u64)
+            .unwrap_or_default(),
+    }
+}
+
+/// Returns the granularity of the clock of the worker, or `0` if it reads
+/// the clocks of the host.
+#[op2(fast)]
+pub fn op_clock_granularity(state: &mut OpState) -> f64 {
+    if state.has::<ReplayClock>() {
+        return 1.0;
+    }
+
+    state
+        .try_borrow::<WorkerClock>()
+        .map_or(0, WorkerClock::granularity_ms) as f64
+}
+
+#[op2(fast)]
+pub fn op_clock_now(state: &mut OpState) -> f64 {
+    worker_now_ms(state) as f64
+}
+
+/// Writes the monotonic time of the worker into the buffer, as seconds and
+/// the nanoseconds past them.
+///
+/// NOTE: This replaces the implementation of `op_now` of `deno_web`.
+#[op2(fast)]
+pub fn op_now_coarsened(state: &mut OpState, #[buffer] buf: &mut [u8]) {
+    let elapsed = if let Some(clock) = state.try_borrow::<ReplayClock>() {
+        Duration::from_millis(clock.monotonic_ms())
+    } else if let Some(clock) = state.try_borrow::<WorkerClock>() {
+        Duration::from_millis(clock.monotonic_ms())
+    } else {
+        let elapsed = state
+            .try_borrow::<HostClockOrigin>()
+            .map(|it| it.0.elapsed())
+            .unwrap_or_default();
+        let precision = HOST_CLOCK_PRECISION.as_nanos() as u32;
+
+        Duration::new(
+            elapsed.as_secs(),
+            elapsed.subsec_nanos() - elapsed.subsec_nanos() % precision,
+        )
+    };
+
+    write_now(buf, elapsed);
+}
+
+fn write_now(buf: &mut [u8], elapsed: Duration) {
+    if buf.len() < 8 {
+        return;
+    }
+
+    buf[..4].copy_from_slice(&(elapsed.as_secs() as u32).to_ne_bytes());
+    buf[4..8].copy_from_slice(&elapsed.subsec_nanos().to_ne_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{write_now, WorkerClock};
+
+    #[test]
+    fn test_worker_clock() {
+        let clock = WorkerClock {
+            origin: Instant::now() - Duration::from_millis(1234),
+            origin_epoch_ms: 1_700_000_000_000,
+            granularity_ms: 100,
+        };
+
+        assert_eq!(clock.monotonic_ms() % 100, 0);
+        assert!(clock.monotonic_ms() >= 1200);
+        assert_eq!(clock.now_ms() % 100, 0);
+        assert!(clock.now_ms() >= 1_700_000_001_200);
+
+        // NOTE: A granularity of 0 would not coarsen anything.
+        assert_eq!(WorkerClock::new(0).granularity_ms(), 1);
+    }
+
+    #[test]
+    fn test_write_now() {
+        let mut buf = [0u8; 8];
+
+        write_now(&mut buf, Duration::from_millis(1_200));
+
+        assert_eq!(u32::from_ne_bytes(buf[..4].try_into().unwrap()), 1);
+        assert_eq!(
+            u32::from_ne_bytes(buf[4..].try_into().unwrap()),
+            200_000_000
+        );
+
+        let mut short = [0u8; 4];
+
+        write_now(&mut short, Duration::from_millis(1_200));
+
+        assert_eq!(short, [0; 4]);
+    }
+}
@@ -1,4 +1,6 @@
-use deno_core::Resource;
+use std::rc::Rc;
+
+use deno_core::{CancelHandle, Resource};
 use tokio_util::sync::CancellationToken;
 
 pub struct ConnWatcher(pub Option<CancellationToken>);
@@ -15,5 +17,22 @@ impl ConnWatcher {
     }
 }
 
+/// Watches the client connection of a single request, until the request has
+/// been responded to.
+pub struct ConnCloseWatch {
+    pub token: CancellationToken,
+    pub cancel: CancelHandle,
+}
+
+impl Resource for ConnCloseWatch {
+    fn name(&self) -> std::borrow::Cow<str> {
+        "connCloseWatch".into()
+    }
+
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+    }
+}
+
 #[derive(Clone)]
 pub struct DenoRuntimeDropToken(pub CancellationToken);
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use deno_fetch::reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::outbound_cancel::or_cancelled;
+
+static DNS_CACHE: OnceLock<Arc<DnsCache>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+pub struct DnsCacheOptions {
+    /// Maximum number of host names kept in the cache.
+    pub max_entries: usize,
+    /// Upper bound of the TTL of the records.
+    pub max_ttl: Duration,
+    /// How long a host name that does not resolve is remembered.
+    pub negative_ttl: Duration,
+}
+
+#[derive(Debug, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsCacheStatistics {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+enum CachedLookup {
+    Found(Arc<[IpAddr]>),
+    NotFound,
+}
+
+struct CacheEntry {
+    lookup: CachedLookup,
+    expires_at: Instant,
+}
+
+/// A process-wide cache of DNS lookups, shared by the fetch ops of all
+/// workers.
+pub struct DnsCache {
+    opts: DnsCacheOptions,
+    resolver: TokioAsyncResolver,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl DnsCache {
+    fn new(opts: DnsCacheOptions) -> Self {
+        // NOTE: The resolver keeps its connections on the runtime it was
+        // created on, so it must outlive the runtimes of the workers.
+        let _guard = base_rt::SUPERVISOR_RT.enter();
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .unwrap_or_else(|_| TokioAsyncResolver::tokio(Default::default(), Default::default()));
+
+        Self {
+            opts,
+            resolver,
+            entries: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn statistics(&self) -> DnsCacheStatistics {
+        DnsCacheStatistics {
+            entries: self.entries.lock().unwrap().len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn get(&self, host: &str) -> Option<io::Result<Arc<[IpAddr]>>> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(host)?;
+
+        if entry.expires_at <= Instant::now() {
+            return None;
+        }
+
+        Some(match &entry.lookup {
+            CachedLookup::Found(addrs) => Ok(addrs.clone()),
+            CachedLookup::NotFound => Err(not_found(host)),
+        })
+    }
+
+    fn insert(&self, host: String, lookup: CachedLookup, ttl: Duration) {
+        if self.opts.max_entries == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= self.opts.max_entries && !entries.contains_key(&host) {
+            let len = entries.len();
+
+            entries.retain(|_, it| it.expires_at > now);
+
+            let mut evicted = (len - entries.len()) as u64;
+
+            if entries.len() >= self.opts.max_entries {
+                let soonest = entries
+                    .iter()
+                    .min_by_key(|(_, it)| it.expires_at)
+                    .map(|(key, _)| key.clone());
+
+                if let Some(key) = soonest {
+                    entries.remove(&key);
+                    evicted += 1;
+                }
+            }
+
+            self.evictions.fetch_add(evicted, Ordering::Relaxed);
+        }
+
+        entries.insert(
+            host,
+            CacheEntry {
+                lookup,
+                expires_at: now + ttl,
+            },
+        );
+    }
+
+    pub async fn lookup(self: Arc<Self>, host: String) -> io::Result<Arc<[IpAddr]>> {
+        if let Some(result) = self.get(&host) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return result;
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let resolver = self.resolver.clone();
+        let handle = base_rt::SUPERVISOR_RT.spawn({
+            let host = host.clone();
+            async move { resolver.lookup_ip(host).await }
+        });
+
+        // NOTE: The lookup is aborted along with the fetch waiting for it,
+        // rather than left running on the supervisor runtime.
+        let _abort_guard = scopeguard::guard(handle.abort_handle(), |it| it.abort());
+        let result = handle
+            .await
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        match result {
+            Ok(lookup) => {
+                let ttl = lookup
+                    .valid_until()
+                    .saturating_duration_since(Instant::now())
+                    .min(self.opts.max_ttl);
+                let addrs = lookup.iter().collect::<Arc<[_]>>();
+
+                self.insert(host, CachedLookup::Found(addrs.clone()), ttl);
+
+                Ok(addrs)
+            }
+
+            Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                self.insert(host.clone(), CachedLookup::NotFound, self.opts.negative_ttl);
+
+                Err(not_found(&host))
+            }
+
+            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err)),
+        }
+    }
+}
+
+fn not_found(host: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("failed to lookup address information: {host}"),
+    )
+}
+
+pub(crate) struct DnsCacheResolver {
+    pub(crate) cache: Arc<DnsCache>,
+    /// Cancels the lookups of the client of a worker once the worker is
+    /// terminated.
+    pub(crate) maybe_cancel: Option<CancellationToken>,
+}
+
+impl Resolve for DnsCacheResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let cache = self.cache.clone();
+        let maybe_cancel = self.maybe_cancel.clone();
+        let host = name.as_str().to_string();
+
+        Box::pin(async move {
+            let addrs = or_cancelled(maybe_cancel.as_ref(), cache.lookup(host)).await?;
+            let addrs: Addrs = Box::new(
+                addrs
+                    .iter()
+                    .map(|it| SocketAddr::new(*it, 0))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            );
+
+            Ok(addrs)
+        })
+    }
+}
+
+/// Enables the process-wide DNS cache. Only the first call has an effect.
+pub fn init_dns_cache(opts: DnsCacheOptions) {
+    let _ = DNS_CACHE.get_or_init(|| Arc::new(DnsCache::new(opts)));
+}
+
+pub fn get_dns_cache() -> Option<Arc<DnsCache>> {
+    DNS_CACHE.get().cloned()
+}
+
+pub fn get_dns_cache_statistics() -> Option<DnsCacheStatistics> {
+    DNS_CACHE.get().map(|it| it.statistics())
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{CachedLookup, DnsCache, DnsCacheOptions};
+
+    #[test]
+    fn test_dns_cache_evicts_soonest_expiring_entry() {
+        let cache = DnsCache::new(DnsCacheOptions {
+            max_entries: 2,
+            max_ttl: Duration::from_secs(60),
+            negative_ttl: Duration::from_secs(1),
+        });
+
+        let addrs = Arc::<[IpAddr]>::from([IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+
+        cache.insert(
+            "a".into(),
+            CachedLookup::Found(addrs.clone()),
+            Duration::from_secs(30),
+        );
+        cache.insert("b".into(), CachedLookup::NotFound, Duration::from_secs(1));
+        cache.insert(
+            "c".into(),
+            CachedLookup::Found(addrs.clone()),
+            Duration::from_secs(30),
+        );
+
+        assert!(cache.get("a").unwrap().is_ok());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").unwrap().is_ok());
+        assert_eq!(cache.statistics().evictions, 1);
+        assert_eq!(cache.statistics().entries, 2);
+    }
+}
@@ -59,6 +59,11 @@ fn get_module_resolution_error_class(_: &ModuleResolutionError) -> &'static str
 }
 
 fn get_request_error_class(error: &reqwest::Error) -> &'static str {
+    // NOTE: The timeout of the client has no source of its own to classify.
+    if error.is_timeout() {
+        return "TimedOut";
+    }
+
     error
         .source()
         .and_then(|inner_err| {
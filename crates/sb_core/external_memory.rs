@@ -20,6 +20,11 @@ impl CustomAllocator {
         })
     }
 
+    /// Returns the size of the array buffers currently backed by the allocator.
+    pub fn allocated_size(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
     pub fn set_waker(&self, waker: Arc<AtomicWaker>) {
         _ = self.waker.try_write().unwrap().insert(waker);
     }
@@ -0,0 +1,130 @@
+//! Feature flags of a user worker, which are given when the worker is created
+//! and can be toggled while it is running, so that operators can change the
+//! behavior of a function per tenant without redeploying its code.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use deno_core::serde_json::Value;
+use deno_core::{op2, OpState};
+use tokio::sync::{watch, Mutex as AsyncMutex};
+
+pub type FeatureFlags = HashMap<String, Value>;
+
+#[derive(Debug, Clone)]
+pub struct FeatureFlagProvider(Arc<watch::Sender<Arc<FeatureFlags>>>);
+
+impl Default for FeatureFlagProvider {
+    fn default() -> Self {
+        Self::new(FeatureFlags::new())
+    }
+}
+
+impl FeatureFlagProvider {
+    pub fn new(flags: FeatureFlags) -> Self {
+        let (tx, _) = watch::channel(Arc::new(flags));
+        Self(Arc::new(tx))
+    }
+
+    pub fn snapshot(&self) -> Arc<FeatureFlags> {
+        self.0.borrow().clone()
+    }
+
+    /// Merges the given flags into the current ones and notifies the worker if
+    /// anything changed. A flag set to `null` is removed.
+    pub fn update(&self, flags: FeatureFlags) -> bool {
+        self.0.send_if_modified(|current| {
+            let mut next = (**current).clone();
+
+            merge(&mut next, flags);
+
+            if next == **current {
+                return false;
+            }
+
+            *current = Arc::new(next);
+            true
+        })
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Arc<FeatureFlags>> {
+        self.0.subscribe()
+    }
+}
+
+/// Merges flags the same way [`FeatureFlagProvider::update`] does.
+pub fn merge(flags: &mut FeatureFlags, update: FeatureFlags) {
+    for (name, value) in update {
+        if value.is_null() {
+            flags.remove(&name);
+        } else {
+            flags.insert(name, value);
+        }
+    }
+}
+
+#[op2]
+#[serde]
+pub fn op_feature_flags(state: &mut OpState) -> FeatureFlags {
+    state
+        .try_borrow::<FeatureFlagProvider>()
+        .map(|it| (*it.snapshot()).clone())
+        .unwrap_or_default()
+}
+
+/// The receiver the flags of the worker are watched with, subscribed once so
+/// that an update made between two calls of `op_feature_flags_changed` is not
+/// missed.
+struct FeatureFlagChanges(Rc<AsyncMutex<watch::Receiver<Arc<FeatureFlags>>>>);
+
+/// Resolves to `true` once the flags have been updated, or to `false` if they
+/// can no longer change.
+#[op2(async)]
+pub async fn op_feature_flags_changed(state: Rc<RefCell<OpState>>) -> bool {
+    let rx = {
+        let mut state = state.borrow_mut();
+
+        if state.try_borrow::<FeatureFlagChanges>().is_none() {
+            let Some(rx) = state
+                .try_borrow::<FeatureFlagProvider>()
+                .map(FeatureFlagProvider::subscribe)
+            else {
+                return false;
+            };
+
+            state.put(FeatureFlagChanges(Rc::new(AsyncMutex::new(rx))));
+        }
+
+        state.borrow::<FeatureFlagChanges>().0.clone()
+    };
+
+    let mut rx = rx.lock().await;
+
+    rx.changed().await.is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use deno_core::serde_json::{self, json, Value};
+
+    use super::{FeatureFlagProvider, FeatureFlags};
+
+    fn flags(value: Value) -> FeatureFlags {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_update_feature_flags() {
+        let provider = FeatureFlagProvider::new(flags(json!({ "a": true, "b": "blue" })));
+        let rx = provider.subscribe();
+
+        assert!(!provider.update(flags(json!({ "a": true }))));
+        assert!(!rx.has_changed().unwrap());
+
+        assert!(provider.update(flags(json!({ "a": false, "b": null, "c": 1 }))));
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(*provider.snapshot(), flags(json!({ "a": false, "c": 1 })));
+    }
+}
@@ -0,0 +1,254 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::{AsyncResult, BufMutView, BufView, OpState, Resource, ResourceId};
+use deno_fetch::FetchResponse;
+
+use crate::outbound_cancel::OutboundCancelToken;
+use crate::outbound_pool::get_outbound_pool;
+
+/// Limits the outbound requests a worker can make with `fetch`.
+#[derive(Debug, Default)]
+pub struct FetchBudget {
+    /// Maximum number of requests in flight at once. `None` means unlimited.
+    pub max_concurrent: Option<usize>,
+    active: Rc<Cell<usize>>,
+}
+
+impl FetchBudget {
+    pub fn new(max_concurrent: Option<usize>) -> Self {
+        Self {
+            max_concurrent,
+            active: Rc::default(),
+        }
+    }
+
+    /// Number of requests in flight.
+    pub fn active(&self) -> usize {
+        self.active.get()
+    }
+}
+
+/// A request taken from the budget of a worker. It is given back once
+/// dropped.
+#[derive(Debug)]
+pub struct FetchPermit(Rc<Cell<usize>>);
+
+impl Drop for FetchPermit {
+    fn drop(&mut self) {
+        self.0.set(self.0.get().saturating_sub(1));
+    }
+}
+
+/// Takes a request from the budget of the worker before it is sent. Returns
+/// `None` if the worker has no budget.
+pub fn acquire_fetch_permit(state: &OpState) -> Result<Option<FetchPermit>, AnyError> {
+    // NOTE: A worker that is being terminated can't start new requests,
+    // since they would not be cancelled along with the others.
+    if state
+        .try_borrow::<OutboundCancelToken>()
+        .is_some_and(|it| it.0.is_cancelled())
+    {
+        return Err(custom_error("Interrupted", "the worker is terminating"));
+    }
+
+    let Some(budget) = state.try_borrow::<FetchBudget>() else {
+        return Ok(None);
+    };
+
+    if let Some(limit) = budget.max_concurrent {
+        if budget.active.get() >= limit {
+            return Err(custom_error(
+                "ResourceLimitExceeded",
+                format!("too many concurrent outbound requests (limit: {limit})"),
+            ));
+        }
+    }
+
+    budget.active.set(budget.active.get() + 1);
+
+    if let Some(pool) = get_outbound_pool() {
+        pool.record_request();
+    }
+
+    Ok(Some(FetchPermit(budget.active.clone())))
+}
+
+/// The body of a response that holds its request in the budget of the worker
+/// until it has been read to the end or closed.
+pub struct BudgetedResponseBody {
+    inner: Rc<dyn Resource>,
+    permit: RefCell<Option<FetchPermit>>,
+}
+
+impl BudgetedResponseBody {
+    pub fn new(inner: Rc<dyn Resource>, permit: FetchPermit) -> Self {
+        Self {
+            inner,
+            permit: RefCell::new(Some(permit)),
+        }
+    }
+}
+
+impl Resource for BudgetedResponseBody {
+    fn name(&self) -> Cow<str> {
+        self.inner.name()
+    }
+
+    fn read(self: Rc<Self>, limit: usize) -> AsyncResult<BufView> {
+        Box::pin(async move {
+            let result = self.inner.clone().read(limit).await;
+
+            if !matches!(&result, Ok(it) if !it.is_empty()) {
+                self.permit.take();
+            }
+
+            result
+        })
+    }
+
+    fn read_byob(self: Rc<Self>, buf: BufMutView) -> AsyncResult<(usize, BufMutView)> {
+        Box::pin(async move {
+            let result = self.inner.clone().read_byob(buf).await;
+
+            if !matches!(&result, Ok((nread, _)) if *nread > 0) {
+                self.permit.take();
+            }
+
+            result
+        })
+    }
+
+    fn close(self: Rc<Self>) {
+        self.permit.take();
+
+        match Rc::try_unwrap(self) {
+            Ok(this) => this.inner.close(),
+            Err(this) => this.inner.clone().close(),
+        }
+    }
+
+    fn size_hint(&self) -> (u64, Option<u64>) {
+        self.inner.size_hint()
+    }
+}
+
+/// Sends a request made with `fetch` once the budget of the worker allows it.
+/// The request is held in the budget until its response body is done.
+///
+/// NOTE: This replaces the implementation of `op_fetch_send`, so that the
+/// budget can't be worked around from JS.
+#[op2(async)]
+#[serde]
+pub async fn op_fetch_send_budgeted(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<FetchResponse, AnyError> {
+    let maybe_permit = match acquire_fetch_permit(&state.borrow()) {
+        Ok(it) => it,
+        Err(err) => {
+            // NOTE: The request was built but is never sent.
+            let _ = state.borrow_mut().resource_table.close(rid);
+            return Err(err);
+        }
+    };
+
+    let mut res = deno_fetch::op_fetch_send::call(state.clone(), rid).await?;
+
+    // NOTE: The body of a switching protocols response is taken over by
+    // `op_fetch_response_upgrade`, so it can't be wrapped.
+    if let Some(permit) = maybe_permit.filter(|_| res.status != 101) {
+        let mut op_state = state.borrow_mut();
+
+        if let Ok(body) = op_state.resource_table.take_any(res.response_rid) {
+            res.response_rid = op_state
+                .resource_table
+                .add(BudgetedResponseBody::new(body, permit));
+        }
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use deno_core::{BufView, OpState, Resource};
+
+    use super::{acquire_fetch_permit, BudgetedResponseBody, FetchBudget};
+
+    struct Chunks(std::cell::RefCell<Vec<&'static [u8]>>);
+
+    impl Resource for Chunks {
+        fn read(self: Rc<Self>, _limit: usize) -> deno_core::AsyncResult<BufView> {
+            let chunk = self.0.borrow_mut().pop();
+
+            Box::pin(async move { Ok(chunk.map(BufView::from).unwrap_or_else(BufView::empty)) })
+        }
+    }
+
+    #[test]
+    fn test_fetch_budget_limits_concurrent_requests() {
+        let mut state = OpState::new(None);
+
+        state.put(FetchBudget::new(Some(2)));
+
+        let first = acquire_fetch_permit(&state).unwrap().unwrap();
+        let _second = acquire_fetch_permit(&state).unwrap().unwrap();
+
+        assert!(acquire_fetch_permit(&state).is_err());
+        assert_eq!(state.borrow::<FetchBudget>().active(), 2);
+
+        drop(first);
+
+        assert!(acquire_fetch_permit(&state).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_fetch_budget_is_unlimited_without_budget() {
+        let state = OpState::new(None);
+
+        assert!(acquire_fetch_permit(&state).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_budgeted_response_body_releases_at_body_end() {
+        let mut state = OpState::new(None);
+
+        state.put(FetchBudget::new(Some(1)));
+
+        let permit = acquire_fetch_permit(&state).unwrap().unwrap();
+        let body = Rc::new(BudgetedResponseBody::new(
+            Rc::new(Chunks(vec![b"b".as_slice(), b"a".as_slice()].into())),
+            permit,
+        ));
+
+        assert_eq!(&*body.clone().read(16).await.unwrap(), b"a");
+        assert_eq!(&*body.clone().read(16).await.unwrap(), b"b");
+        assert_eq!(state.borrow::<FetchBudget>().active(), 1);
+
+        assert!(body.clone().read(16).await.unwrap().is_empty());
+        assert_eq!(state.borrow::<FetchBudget>().active(), 0);
+    }
+
+    #[test]
+    fn test_budgeted_response_body_releases_on_close() {
+        let mut state = OpState::new(None);
+
+        state.put(FetchBudget::new(Some(1)));
+
+        let permit = acquire_fetch_permit(&state).unwrap().unwrap();
+        let body = Rc::new(BudgetedResponseBody::new(
+            Rc::new(Chunks(vec![b"a".as_slice()].into())),
+            permit,
+        ));
+
+        body.close();
+
+        assert_eq!(state.borrow::<FetchBudget>().active(), 0);
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use deno_core::error::AnyError;
+use deno_fetch::reqwest;
+use deno_tls::rustls::client::WebPkiServerVerifier;
+use deno_tls::rustls::crypto::ring;
+use deno_tls::rustls::{self, ClientConfig};
+use deno_tls::RootCertStoreProvider;
+use tokio_util::sync::CancellationToken;
+
+use crate::cert::OutboundTlsOptions;
+use crate::cert_pinning::{PinViolationHandler, PinningVerifier};
+use crate::dns_cache::{DnsCache, DnsCacheResolver};
+use crate::outbound_pool::OutboundPool;
+
+/// Creates the client used by the fetch ops of a worker, for when the default
+/// client of `deno_fetch` does not do: it has no way to plug a resolver in, to
+/// restrict the TLS versions and cipher suites, to pin the keys of the
+/// destinations, nor to bound the duration of the requests, nor to configure
+/// how the connections are kept alive (see [`OutboundPool`]).
+///
+/// It is built from the same [`deno_fetch::Options`] as the default client and
+/// otherwise mirrors it, so the proxy given there or, failing that, the one of
+/// the environment (`HTTP_PROXY`, `HTTPS_PROXY`, `NO_PROXY`) still applies.
+#[allow(clippy::disallowed_types)] // reqwest::Client allowed here
+pub fn create_http_client(
+    fetch_options: &deno_fetch::Options,
+    maybe_tls: Option<&OutboundTlsOptions>,
+    maybe_dns_cache: Option<Arc<DnsCache>>,
+    maybe_timeout: Option<Duration>,
+    maybe_on_pin_violation: Option<PinViolationHandler>,
+    maybe_pool: Option<&OutboundPool>,
+    maybe_cancel: Option<CancellationToken>,
+) -> Result<reqwest::Client, AnyError> {
+    let (provider, versions) = match maybe_tls {
+        Some(tls) => (tls.crypto_provider()?, tls.protocol_versions()?),
+        None => (ring::default_provider(), rustls::DEFAULT_VERSIONS.to_vec()),
+    };
+
+    let provider = Arc::new(provider);
+    let builder =
+        ClientConfig::builder_with_provider(provider.clone()).with_protocol_versions(&versions)?;
+    let root_cert_store = Arc::new(match &fetch_options.root_cert_store_provider {
+        Some(provider) => provider.get_or_try_init()?.clone(),
+        None => rustls::RootCertStore::empty(),
+    });
+
+    let mut tls_config = match maybe_tls.and_then(|it| it.pins.clone()) {
+        Some(pins) => {
+            let verifier =
+                WebPkiServerVerifier::builder_with_provider(root_cert_store, provider).build()?;
+
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinningVerifier::new(
+                    verifier,
+                    pins,
+                    maybe_on_pin_violation,
+                )))
+                .with_no_client_auth()
+        }
+
+        None => builder
+            .with_root_certificates(root_cert_store)
+            .with_no_client_auth(),
+    };
+
+    tls_config.alpn_protocols = match maybe_tls.and_then(|it| it.alpn_protocols.as_ref()) {
+        Some(protocols) => protocols.iter().map(|it| it.as_bytes().to_vec()).collect(),
+        None if maybe_pool.is_some_and(|it| !it.options().prefer_h2) => {
+            vec!["http/1.1".into()]
+        }
+        None => vec!["h2".into(), "http/1.1".into()],
+    };
+
+    let mut builder = client_builder(fetch_options, tls_config)?;
+
+    if let Some(pool) = maybe_pool {
+        let opts = pool.options();
+
+        builder = builder
+            .pool_max_idle_per_host(opts.max_idle_per_host)
+            .pool_idle_timeout(opts.idle_timeout)
+            .dns_resolver(Arc::new(pool.resolver(maybe_dns_cache)));
+
+        if !opts.prefer_h2 {
+            builder = builder.http1_only();
+        }
+    } else if let Some(cache) = maybe_dns_cache {
+        builder = builder.dns_resolver(Arc::new(DnsCacheResolver {
+            cache,
+            maybe_cancel,
+        }));
+    }
+    if let Some(timeout) = maybe_timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Returns the builder `deno_fetch` starts its default client from.
+#[allow(clippy::disallowed_types)] // reqwest::Client allowed here
+fn client_builder(
+    fetch_options: &deno_fetch::Options,
+    tls_config: ClientConfig,
+) -> Result<reqwest::ClientBuilder, AnyError> {
+    let mut headers = reqwest::header::HeaderMap::new();
+
+    headers.insert(
+        reqwest::header::USER_AGENT,
+        fetch_options.user_agent.parse()?,
+    );
+
+    let mut builder = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .default_headers(headers)
+        .use_preconfigured_tls(tls_config);
+
+    if let Some(proxy) = &fetch_options.proxy {
+        let mut reqwest_proxy = reqwest::Proxy::all(&proxy.url)?;
+
+        if let Some(basic_auth) = &proxy.basic_auth {
+            reqwest_proxy = reqwest_proxy.basic_auth(&basic_auth.username, &basic_auth.password);
+        }
+
+        builder = builder.proxy(reqwest_proxy);
+    }
+
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use deno_tls::Proxy;
+    use hyper_v014::service::{make_service_fn, service_fn};
+    use hyper_v014::{Body, Response, Server};
+
+    use super::create_http_client;
+
+    #[tokio::test]
+    async fn test_client_goes_through_proxy_of_fetch_options() {
+        let seen_uris = Arc::new(Mutex::new(vec![]));
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service_fn({
+            let seen_uris = seen_uris.clone();
+
+            move |_| {
+                let seen_uris = seen_uris.clone();
+
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| {
+                        seen_uris.lock().unwrap().push(req.uri().to_string());
+                        async { Ok::<_, Infallible>(Response::new(Body::from("proxied"))) }
+                    }))
+                }
+            }
+        }));
+
+        let addr = server.local_addr();
+
+        drop(tokio::spawn(server));
+
+        let client = create_http_client(
+            &deno_fetch::Options {
+                user_agent: "test".into(),
+                proxy: Some(Proxy {
+                    url: format!("http://{addr}"),
+                    basic_auth: None,
+                }),
+                ..Default::default()
+            },
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let res = client
+            .get("http://upstream.invalid/path")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.text().await.unwrap(), "proxied");
+        assert_eq!(
+            *seen_uris.lock().unwrap(),
+            vec!["http://upstream.invalid/path".to_string()]
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
@@ -5,12 +6,15 @@ use deno_core::error::bad_resource;
 use deno_core::error::bad_resource_id;
 use deno_core::error::AnyError;
 use deno_core::op2;
+use deno_core::CancelFuture;
+use deno_core::CancelHandle;
 use deno_core::OpState;
+use deno_core::RcRef;
 use deno_core::ResourceId;
 use deno_http::http_create_conn_resource;
 use tokio_util::sync::CancellationToken;
 
-use crate::conn_sync::ConnWatcher;
+use crate::conn_sync::{ConnCloseWatch, ConnWatcher};
 use crate::http::DuplexStream2;
 use crate::net::TokioDuplexResource;
 
@@ -49,4 +53,42 @@ fn op_http_start(
     Err(bad_resource_id())
 }
 
-deno_core::extension!(sb_core_http_start, ops = [op_http_start]);
+/// Returns a watch on the client connection of a request, or `None` if the
+/// connection is not tracked (e.g. the request was not forwarded by the
+/// server).
+#[op2]
+#[serde]
+fn op_http_conn_close_watch(
+    state: &mut OpState,
+    #[smi] watcher_rid: ResourceId,
+) -> Result<Option<ResourceId>, AnyError> {
+    let Some(token) = state.resource_table.get::<ConnWatcher>(watcher_rid)?.get() else {
+        return Ok(None);
+    };
+
+    // NOTE: The watcher itself must not be held on to while waiting, since it
+    // is taken out of the table when the request is forwarded to a user
+    // worker.
+    Ok(Some(state.resource_table.add(ConnCloseWatch {
+        token,
+        cancel: CancelHandle::new(),
+    })))
+}
+
+/// Resolves to `true` once the client has hung up, or to `false` once the
+/// watch is closed.
+#[op2(async)]
+async fn op_http_conn_closed(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<bool, AnyError> {
+    let watch = state.borrow().resource_table.get::<ConnCloseWatch>(rid)?;
+    let cancel = RcRef::map(&watch, |it| &it.cancel);
+
+    Ok(watch.token.cancelled().or_cancel(cancel).await.is_ok())
+}
+
+deno_core::extension!(
+    sb_core_http_start,
+    ops = [op_http_start, op_http_conn_close_watch, op_http_conn_closed]
+);
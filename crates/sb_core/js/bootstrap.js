@@ -20,7 +20,7 @@ import * as webSocket from 'ext:deno_websocket/01_websocket.js';
 import * as response from 'ext:deno_fetch/23_response.js';
 import * as request from 'ext:deno_fetch/23_request.js';
 import * as globalInterfaces from 'ext:deno_web/04_global_interfaces.js';
-import { SUPABASE_ENV } from 'ext:sb_env/env.js';
+import { SUPABASE_ENV, watchEnvChanges } from 'ext:sb_env/env.js';
 import ai from 'ext:sb_ai/js/ai.js';
 import { registerErrors } from 'ext:sb_core_main_js/js/errors.js';
 import {
@@ -40,13 +40,16 @@ import {
 	setNumCpus,
 	setUserAgent,
 } from 'ext:sb_core_main_js/js/navigator.js';
+import { localize } from 'ext:sb_core_main_js/js/intl.js';
 
 import { promiseRejectMacrotaskCallback } from 'ext:sb_core_main_js/js/promises.js';
 import { denoOverrides, fsVars } from 'ext:sb_core_main_js/js/denoOverrides.js';
+import { getFetchDeadline, requestContext } from 'ext:sb_core_main_js/js/http.js';
 import { registerDeclarativeServer } from 'ext:sb_core_main_js/js/00_serve.js';
 import * as performance from 'ext:deno_web/15_performance.js';
 import * as messagePort from 'ext:deno_web/13_message_port.js';
 import { SupabaseEventListener } from 'ext:sb_user_event_worker/event_worker.js';
+import { invokeService } from 'ext:sb_user_workers/user_workers.js';
 import * as MainWorker from 'ext:sb_core_main_js/js/main_worker.js';
 import * as DenoWSStream from 'ext:deno_websocket/02_websocketstream.js';
 import * as eventSource from 'ext:deno_fetch/27_eventsource.js';
@@ -61,19 +64,39 @@ const ops = core.ops;
 const {
 	Error,
 	ArrayPrototypePop,
+	ArrayPrototypePush,
 	ArrayPrototypeShift,
+	DateNow,
+	JSONStringify,
 	ObjectAssign,
 	ObjectKeys,
 	ObjectDefineProperty,
 	ObjectDefineProperties,
+	ObjectFreeze,
 	ObjectSetPrototypeOf,
 	ObjectHasOwn,
+	ObjectPrototypeIsPrototypeOf,
+	PromisePrototypeThen,
+	PromiseReject,
+	ReflectApply,
+	ReflectConstruct,
 	SafeSet,
 	StringPrototypeIncludes,
 	StringPrototypeSplit,
-	StringPrototypeTrim
+	String,
+	StringPrototypeTrim,
+	TypedArrayPrototypeGetBuffer,
+	TypedArrayPrototypeGetByteLength,
+	TypedArrayPrototypeSet,
+	TypedArrayPrototypeSubarray,
+	Uint8Array
 } = primordials;
 
+const DEADLINE_HEADER = 'x-deadline-ms';
+
+// Keep in sync with `REQUEST_META_HEADER` in `request_meta.rs`.
+const REQUEST_META_HEADER = 'x-edge-runtime-meta';
+
 let image;
 function ImageNonEnumerable(getter) {
 	let valueIsSet = false;
@@ -423,6 +446,286 @@ const PATCH_DENO_API_LIST = {
 	'memoryUsage': () => ops.op_runtime_memory_usage(),
 };
 
+function withWebSocketIdleTimeout(BaseWebSocket, idleTimeoutMs) {
+	return class WebSocket extends BaseWebSocket {
+		constructor(url, protocols) {
+			super(url, protocols);
+
+			let idleTimer = null;
+			const resetIdleTimer = () => {
+				timers.clearTimeout(idleTimer);
+				idleTimer = timers.setTimeout(() => this.close(1000, 'idle timeout'), idleTimeoutMs);
+			};
+
+			resetIdleTimer();
+			this.addEventListener('message', resetIdleTimer);
+			this.addEventListener('close', () => timers.clearTimeout(idleTimer), { once: true });
+		}
+	};
+}
+
+// NOTE: The fetches made while handling a request that carries a deadline
+// are aborted once it expires, and always pass it on upstream in place of the
+// one they set, if any.
+function withDeadline(fetch) {
+	return function (...args) {
+		const deadline = getFetchDeadline();
+
+		if (deadline === null) {
+			return ReflectApply(fetch, this, args);
+		}
+
+		const timeLeft = deadline - DateNow();
+
+		if (timeLeft <= 0) {
+			return PromiseReject(
+				new globalThis_.Deno.errors.TimedOut('the deadline of the request has expired'),
+			);
+		}
+
+		let req;
+
+		try {
+			req = new request.Request(args[0], args[1]);
+		} catch (err) {
+			return PromiseReject(err);
+		}
+
+		req.headers.set(DEADLINE_HEADER, String(deadline));
+
+		req = new request.Request(req, {
+			signal: abortSignal.AbortSignal.any([
+				req.signal,
+				abortSignal.AbortSignal.timeout(timeLeft),
+			]),
+		});
+
+		return ReflectApply(fetch, this, [req]);
+	};
+}
+
+// Reads a body up to `maxSize` bytes, and returns what it read along with
+// whether the body was longer.
+//
+// NOTE: The rest of the body is left unread, so that a long stream is not
+// held in memory in full for its head to be recorded.
+async function readBodyHead(body, maxSize) {
+	const chunks = [];
+	let size = 0;
+	let truncated = false;
+
+	if (body !== null) {
+		const reader = body.getReader();
+
+		while (true) {
+			const { value, done } = await reader.read();
+
+			if (done) {
+				break;
+			}
+
+			const len = TypedArrayPrototypeGetByteLength(value);
+
+			if (size + len > maxSize) {
+				ArrayPrototypePush(chunks, TypedArrayPrototypeSubarray(value, 0, maxSize - size));
+				size = maxSize;
+				truncated = true;
+				await reader.cancel();
+				break;
+			}
+
+			ArrayPrototypePush(chunks, value);
+			size += len;
+		}
+	}
+
+	const buf = new Uint8Array(size);
+	let offset = 0;
+
+	for (const chunk of chunks) {
+		TypedArrayPrototypeSet(buf, chunk, offset);
+		offset += TypedArrayPrototypeGetByteLength(chunk);
+	}
+
+	return { buf, truncated };
+}
+
+// NOTE: A recorded worker records the response of every fetch along with the
+// request that got it, and a replayed worker gets these responses instead of
+// reaching the network (see `replay.rs`). The responses are recorded along
+// with the request being handled, if any, to be replayed with it.
+function withReplay(fetch, mode) {
+	if (mode === 'record') {
+		const maxBodySize = ops.op_replay_max_body_size();
+
+		return async function (...args) {
+			const req = new request.Request(args[0], args[1]);
+			const requestId = requestContext.getStore()?.requestId ?? null;
+			const res = await ReflectApply(fetch, this, [req]);
+
+			PromisePrototypeThen(
+				readBodyHead(res.clone().body, maxBodySize),
+				({ buf, truncated }) =>
+					ops.op_replay_record_fetch({
+						requestId,
+						method: req.method,
+						url: req.url,
+						status: res.status,
+						headers: [...res.headers],
+						bodyTruncated: truncated,
+					}, buf),
+				() => {},
+			);
+
+			return res;
+		};
+	}
+
+	return async function (...args) {
+		const req = new request.Request(args[0], args[1]);
+
+		req.signal.throwIfAborted();
+
+		const { status, headers, body } = ops.op_replay_take_fetch(req.method, req.url);
+
+		return new response.Response(
+			TypedArrayPrototypeGetByteLength(body) === 0 ? null : body,
+			{ status, headers },
+		);
+	};
+}
+
+// Makes `Date` read the clock of the worker, which only moves in steps of its
+// granularity (see `clock.rs`).
+//
+// NOTE: `performance.now()` and what is built on it are coarsened by the
+// runtime itself, as they read `op_now`.
+function virtualizeClock() {
+	const DateCtor = globalThis.Date;
+
+	function Date(...args) {
+		if (new.target === undefined) {
+			// NOTE: The string is in the time zone of the worker, if it has
+			// one (see `time_zone.rs`).
+			return new DateCtor(ops.op_clock_now()).toString();
+		}
+
+		return ReflectConstruct(
+			DateCtor,
+			args.length === 0 ? [ops.op_clock_now()] : args,
+			new.target,
+		);
+	}
+
+	ObjectSetPrototypeOf(Date, DateCtor);
+	ObjectDefineProperties(Date, {
+		length: { value: 7 },
+		prototype: { value: DateCtor.prototype },
+		now: {
+			value: function now() {
+				return ops.op_clock_now();
+			},
+			writable: true,
+			configurable: true,
+		},
+	});
+	ObjectDefineProperty(DateCtor.prototype, 'constructor', {
+		value: Date,
+		writable: true,
+		configurable: true,
+	});
+
+	globalThis.Date = Date;
+}
+
+// Sends a record of `EdgeRuntime.log` to the events worker. The fields keep
+// their primitive values, and others are given as their JSON (see
+// `structured_log.rs`). Returns whether the record was kept.
+function structuredLog(level, msg, fields = {}) {
+	if (fields === null || typeof fields !== 'object') {
+		throw new TypeError('The fields of a log record must be an object');
+	}
+
+	const values = {};
+
+	for (const key of ObjectKeys(fields)) {
+		const value = fields[key];
+
+		switch (typeof value) {
+			case 'undefined':
+			case 'function':
+			case 'symbol':
+				break;
+			case 'string':
+			case 'number':
+			case 'boolean':
+				values[key] = value;
+				break;
+			case 'bigint':
+				values[key] = String(value);
+				break;
+			default:
+				try {
+					values[key] = value === null ? null : JSONStringify(value);
+				} catch {
+					values[key] = String(value);
+				}
+		}
+	}
+
+	return ops.op_user_worker_structured_log(level, String(msg), values);
+}
+
+// Archives `source`, a response or a stream, to S3-compatible storage while it
+// is being read (see `storage_tee.rs`). Returns the stream to read instead, as
+// a response if `source` is one, and a promise of the archived object.
+function teeToStorage(source, target) {
+	const isResponse = ObjectPrototypeIsPrototypeOf(
+		response.ResponsePrototype,
+		source,
+	);
+	const stream = isResponse ? source.body : source;
+
+	if (!ObjectPrototypeIsPrototypeOf(streams.ReadableStreamPrototype, stream)) {
+		throw new TypeError('Only a response with a body or a stream can be archived');
+	}
+
+	const { bodyRid, uploadRid } = ops.op_storage_tee(
+		streams.resourceForReadableStream(stream),
+		target,
+	);
+	const body = streams.readableStreamForRid(bodyRid);
+	const uploaded = ops.op_storage_tee_result(uploadRid);
+
+	return {
+		[isResponse ? 'response' : 'stream']: isResponse
+			? new response.Response(body, {
+				status: source.status,
+				statusText: source.statusText,
+				headers: source.headers,
+			})
+			: body,
+		uploaded,
+	};
+}
+
+// Dispatches a `featureflagschange` event on the global scope whenever the
+// feature flags are updated from outside of the worker.
+async function watchFeatureFlagChanges() {
+	while (true) {
+		const promise = ops.op_feature_flags_changed();
+
+		// NOTE: This must not keep the event loop alive by itself.
+		core.unrefOpPromise(promise);
+
+		if (!(await promise)) {
+			break;
+		}
+
+		globalThis.dispatchEvent(new event.Event('featureflagschange'));
+	}
+}
+
 globalThis.bootstrapSBEdge = (opts, extraCtx) => {
 	globalThis_ = globalThis;
 
@@ -445,7 +748,11 @@ globalThis.bootstrapSBEdge = (opts, extraCtx) => {
 		3: edgeRuntimeVersion,
 		4: denoVersion,
 		5: shouldDisableDeprecatedApiWarning,
-		6: shouldUseVerboseDeprecatedApiWarning
+		6: shouldUseVerboseDeprecatedApiWarning,
+		7: locale,
+		8: timeZone,
+		9: allowSharedArrayBuffer,
+		10: allowWasmThreads,
 	} = opts;
 
 	deprecatedApiWarningDisabled = shouldDisableDeprecatedApiWarning;
@@ -479,7 +786,8 @@ globalThis.bootstrapSBEdge = (opts, extraCtx) => {
 		// TODO: It should be changed to a well-known name for the ecosystem.
 		`Deno/${globalThis.DENO_VERSION} (variant; SupabaseEdgeRuntime/${globalThis.SUPABASE_VERSION})`,
 	);
-	setLanguage('en');
+	setLanguage(locale ?? 'en');
+	localize(locale, timeZone);
 
 	Object.defineProperty(globalThis, 'Supabase', {
 		get() {
@@ -500,6 +808,11 @@ globalThis.bootstrapSBEdge = (opts, extraCtx) => {
 	// we explicitly disabled the shared buffer option between isolate globally
 	// in `deno_runtime.rs`, so this patch also applies regardless of worker
 	// type.
+
+	// NOTE: The operator may still allow shared memory within a user worker
+	// (see `allowSharedArrayBuffer` and `allowWasmThreads`). A shared memory
+	// of WebAssembly is backed by a `SharedArrayBuffer` even if the global is
+	// deleted.
 	const wasmMemoryCtor = globalThis.WebAssembly.Memory;
 	const wasmMemoryPrototypeGrow = wasmMemoryCtor.prototype.grow;
 
@@ -521,13 +834,102 @@ globalThis.bootstrapSBEdge = (opts, extraCtx) => {
 		return new wasmMemoryCtor(maybeOpts);
 	}
 
-	delete globalThis.SharedArrayBuffer;
-	globalThis.WebAssembly.Memory = patchedWasmMemoryCtor;
+	if (!allowSharedArrayBuffer) {
+		delete globalThis.SharedArrayBuffer;
+	}
+	if (!allowWasmThreads) {
+		globalThis.WebAssembly.Memory = patchedWasmMemoryCtor;
+	}
 
 	/// DISABLE SHARED MEMORY INSTALL MEM CHECK TIMING
 
 	if (isUserWorker) {
-		delete globalThis.EdgeRuntime;
+		// NOTE: User workers only get a narrow view of the runtime, so that
+		// functions can adapt to the limits they run under.
+		ObjectDefineProperties(globalThis, {
+			EdgeRuntime: nonEnumerable({
+				get context() {
+					return ops.op_runtime_context();
+				},
+				// NOTE: Log-heavy code can check this to back off while the
+				// events worker cannot keep up and log events are dropped.
+				get eventsBackpressure() {
+					return ops.op_events_backpressure();
+				},
+				// NOTE: Flags can be toggled by the main worker while the
+				// worker is running, which dispatches a `featureflagschange`
+				// event on the global scope.
+				get featureFlags() {
+					return ops.op_feature_flags();
+				},
+				// NOTE: Invokes another function of the pool without a round
+				// trip through the network, e.g.
+				// `EdgeRuntime.invoke("other", "/path", { method: "POST" })`.
+				invoke: invokeService,
+				// NOTE: Records go to the events worker as they are, e.g.
+				// `EdgeRuntime.log.info("charged", { customer, amount })`,
+				// and may be sampled or rate limited by the runtime.
+				log: ObjectFreeze({
+					debug: (msg, fields) => structuredLog('Debug', msg, fields),
+					info: (msg, fields) => structuredLog('Info', msg, fields),
+					warn: (msg, fields) => structuredLog('Warning', msg, fields),
+					error: (msg, fields) => structuredLog('Error', msg, fields),
+				}),
+				// NOTE: Enqueues a task that is delivered later as a `POST` of
+				// its payload to a function, this one unless named otherwise,
+				// e.g. `EdgeRuntime.queue.enqueue({ id }, { name: "mailer",
+				// delayMs: 1000, maxAttempts: 3 })`, which resolves to the ID of
+				// the task. Only works if the runtime is started with
+				// `--task-queue-db`.
+				queue: ObjectFreeze({
+					enqueue(payload, options = {}) {
+						return ops.op_task_queue_enqueue({
+							...options,
+							payload: JSONStringify(payload ?? null),
+						});
+					},
+				}),
+				// NOTE: Archives a body while it is streamed, e.g.
+				// `const { response, uploaded } = EdgeRuntime.storage.tee(res,
+				// { endpoint, bucket, key, accessKeyId, secretAccessKey })`.
+				// `uploaded` rejects if the archive could not be completed.
+				storage: ObjectFreeze({ tee: teeToStorage }),
+				// NOTE: Sends an email through the given server, e.g.
+				// `EdgeRuntime.smtp.send({ host, port, username, password },
+				// { from, to, subject, text })`. The host must be allowed by
+				// the network permissions of the worker.
+				smtp: ObjectFreeze({
+					send: (transport, message) =>
+						ops.op_smtp_send(transport, message),
+				}),
+				// NOTE: Only works if the runtime is started with
+				// `--enable-failure-injection`, e.g.
+				// `EdgeRuntime.test.failNext("bootDelay", { delayMs: 500 })`.
+				test: ObjectFreeze({
+					failNext(kind, options = {}) {
+						ops.op_user_worker_fail_next({ kind, ...options });
+					},
+				}),
+				// NOTE: Returns what the server knows about the request, e.g.
+				// the IP of the client, or `null` if it did not come through
+				// the server.
+				requestMeta(request) {
+					const value = request?.headers?.get(REQUEST_META_HEADER);
+
+					return value ? ops.op_request_meta(value) : null;
+				},
+				// NOTE: The context of the request being handled, e.g.
+				// `EdgeRuntime.requestContext.getStore()?.requestId`. It is
+				// an `AsyncLocalStorage`, so it can be bound and snapshotted
+				// like one, and its store is `undefined` outside a handler.
+				requestContext,
+				// NOTE: Unlike `crypto.randomUUID()`, the UUIDs sort by the
+				// time they were created at.
+				randomUUIDv7() {
+					return ops.op_random_uuid(7);
+				},
+			}),
+		});
 
 		// override console
 		ObjectDefineProperties(globalThis, {
@@ -550,6 +952,53 @@ globalThis.bootstrapSBEdge = (opts, extraCtx) => {
 			}
 		}
 
+		const replayMode = ops.op_replay_mode();
+
+		if (replayMode !== null) {
+			globalThis.fetch = withReplay(globalThis.fetch, replayMode);
+		}
+
+		// NOTE: `Math.random()` is seeded by V8 on its own, so a replayed
+		// worker draws it from the seeded generator of the runtime instead.
+		if (replayMode === 'replay') {
+			const words = new Uint32Array(2);
+			const bytes = new Uint8Array(TypedArrayPrototypeGetBuffer(words));
+
+			ObjectDefineProperty(globalThis.Math, 'random', {
+				value: function random() {
+					crypto.crypto.getRandomValues(bytes);
+					return ((words[0] >>> 5) * 67108864 + (words[1] >>> 6)) / 9007199254740992;
+				},
+				writable: true,
+				configurable: true,
+			});
+		}
+
+		globalThis.fetch = withDeadline(globalThis.fetch);
+
+		const wsIdleTimeoutMs = ops.op_ws_idle_timeout_ms();
+
+		if (wsIdleTimeoutMs > 0) {
+			globalThis.WebSocket = withWebSocketIdleTimeout(globalThis.WebSocket, wsIdleTimeoutMs);
+		}
+
+		ObjectDefineProperties(crypto.Crypto.prototype, {
+			randomUUID: {
+				value: function randomUUID() {
+					return ops.op_random_uuid(4);
+				},
+				writable: true,
+				configurable: true,
+			},
+		});
+
+		if (ops.op_clock_granularity() > 0) {
+			virtualizeClock();
+		}
+
+		watchEnvChanges();
+		watchFeatureFlagChanges();
+
 		// find declarative fetch handler
 		core.addMainModuleHandler(main => {
 			if (ObjectHasOwn(main, 'default')) {
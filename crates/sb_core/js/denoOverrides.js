@@ -7,6 +7,7 @@ import { serve, serveHttp, upgradeWebSocket } from 'ext:sb_core_main_js/js/http.
 import * as fs from 'ext:deno_fs/30_fs.js';
 import { osCalls } from 'ext:sb_os/os.js';
 import * as io from 'ext:deno_io/12_io.js';
+import { op_net_listen_udp, op_net_listen_unixpacket } from 'ext:core/ops';
 
 const osCallsVars = {
 	gid: osCalls.gid,
@@ -100,6 +101,7 @@ const denoOverrides = {
 	upgradeWebSocket,
 	listen: net.listen,
 	connect: net.connect,
+	listenDatagram: net.createListenDatagram(op_net_listen_udp, op_net_listen_unixpacket),
 	connectTls: tls.connectTls,
 	startTls: tls.startTls,
 	resolveDns: net.resolveDns,
@@ -27,6 +27,12 @@ const buildDomErrorClass = (name) => class extends DOMException {
 const InvalidWorkerResponse = buildErrorClass("InvalidWorkerResponse");
 const InvalidWorkerCreation = buildErrorClass("InvalidWorkerCreation");
 const WorkerRequestCancelled = buildErrorClass("WorkerRequestCancelled");
+const WorkerPoolOverloaded = buildErrorClass("WorkerPoolOverloaded");
+const InsufficientResources = buildErrorClass("InsufficientResources");
+const UnsignedBundle = buildErrorClass("UnsignedBundle");
+const WorkerOptionsChanged = buildErrorClass("WorkerOptionsChanged");
+const InvokeLoopDetected = buildErrorClass("InvokeLoopDetected");
+const ResourceLimitExceeded = buildErrorClass("ResourceLimitExceeded");
 const NotFound = buildErrorClass("NotFound");
 const PermissionDenied = buildErrorClass("PermissionDenied");
 const ConnectionRefused = buildErrorClass("ConnectionRefused");
@@ -43,6 +49,8 @@ const WriteZero = buildErrorClass("WriteZero");
 const WouldBlock = buildErrorClass("WouldBlock");
 const UnexpectedEof = buildErrorClass("UnexpectedEof");
 const Http = buildErrorClass("Http");
+const SmtpError = buildErrorClass("SmtpError");
+const StorageError = buildErrorClass("StorageError");
 const Busy = buildErrorClass("Busy");
 const NotSupported = buildErrorClass("NotSupported");
 const DOMExceptionOperationError = buildDomErrorClass("OperationError");
@@ -57,6 +65,12 @@ function registerErrors() {
     core.registerErrorClass("InvalidWorkerResponse", InvalidWorkerResponse);
     core.registerErrorClass("InvalidWorkerCreation", InvalidWorkerCreation);
     core.registerErrorClass("WorkerRequestCancelled", WorkerRequestCancelled);
+    core.registerErrorClass("WorkerPoolOverloaded", WorkerPoolOverloaded);
+    core.registerErrorClass("InsufficientResources", InsufficientResources);
+    core.registerErrorClass("UnsignedBundle", UnsignedBundle);
+    core.registerErrorClass("WorkerOptionsChanged", WorkerOptionsChanged);
+    core.registerErrorClass("InvokeLoopDetected", InvokeLoopDetected);
+    core.registerErrorClass("ResourceLimitExceeded", ResourceLimitExceeded);
     core.registerErrorClass("NotFound", NotFound);
     core.registerErrorClass("PermissionDenied", PermissionDenied);
     core.registerErrorClass("ConnectionRefused", ConnectionRefused);
@@ -72,6 +86,8 @@ function registerErrors() {
     core.registerErrorClass("WriteZero", WriteZero);
     core.registerErrorClass("UnexpectedEof", UnexpectedEof);
     core.registerErrorClass("Http", Http);
+    core.registerErrorClass("SmtpError", SmtpError);
+    core.registerErrorClass("StorageError", StorageError);
     core.registerErrorClass("Busy", Busy);
     core.registerErrorClass("NotSupported", NotSupported);
     core.registerErrorClass(
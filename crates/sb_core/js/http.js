@@ -2,24 +2,117 @@ import "ext:deno_http/01_http.js";
 
 import { core, internals, primordials } from "ext:core/mod.js";
 import { fromInnerResponse, newInnerResponse } from "ext:deno_fetch/23_response.js";
-import { RequestPrototype } from "ext:deno_fetch/23_request.js";
+import { abortRequest, RequestPrototype } from "ext:deno_fetch/23_request.js";
 import { HttpConn } from "ext:sb_core_main_js/js/01_http.js";
 import { upgradeWebSocket } from "ext:deno_http/02_websocket.ts";
+import { AsyncLocalStorage } from "node:async_hooks";
 
 const ops = core.ops;
 
 const { internalRidSymbol } = core;
-const { ObjectPrototypeIsPrototypeOf } = primordials;
+const {
+	ArrayPrototypeIndexOf,
+	ArrayPrototypePush,
+	ArrayPrototypeSplice,
+	MathMax,
+	NumberIsNaN,
+	NumberParseInt,
+	ObjectFreeze,
+	ObjectPrototypeIsPrototypeOf,
+	ReflectApply,
+	SafePromiseAll,
+	SafeSet,
+	SafeSetIterator,
+	SetPrototypeAdd,
+	SetPrototypeClear,
+	SetPrototypeDelete,
+} = primordials;
 
 const HttpConnPrototypeNextRequest = HttpConn.prototype.nextRequest;
 const HttpConnPrototypeClose = HttpConn.prototype.close;
 
 const kSupabaseTag = Symbol("kSupabaseTag");
+const kHandleConnection = Symbol("kHandleConnection");
 const RAW_UPGRADE_RESPONSE_SENTINEL = fromInnerResponse(
 	newInnerResponse(101),
 	"immutable",
 );
 
+// NOTE: A user worker receives its connections from a single internal
+// transport, so only one server can consume them at a time.
+let activeServer = null;
+
+// NOTE: Only one accept op can wait on the internal transport, and it cannot
+// be cancelled, so a single loop outlives the servers and hands each
+// connection to the one that is active.
+let acceptLoop = null;
+
+async function acceptConnections(listener) {
+	for await (const conn of listener) {
+		if (activeServer === null) {
+			// NOTE: There is no server to take the connection after a
+			// shutdown, so it is dropped here.
+			try {
+				conn.close();
+			} catch {
+				// connection has already been closed
+			}
+
+			continue;
+		}
+
+		activeServer[kHandleConnection](conn);
+	}
+}
+
+const DEADLINE_HEADER = "x-deadline-ms";
+
+// NOTE: The deadlines of the requests being handled, in milliseconds since
+// the unix epoch. The requests without a deadline are counted as `Infinity`.
+const inFlightDeadlines = [];
+
+function getRequestDeadline(request) {
+	const deadline = NumberParseInt(request.headers.get(DEADLINE_HEADER), 10);
+
+	return NumberIsNaN(deadline) ? Infinity : deadline;
+}
+
+// Returns the deadline the fetches of the handlers are bounded by, if any.
+//
+// NOTE: This is the latest deadline of the requests being handled, so that a
+// fetch is never cut short when the worker handles several requests at once.
+// It is exactly the deadline of the request when it handles one at a time.
+function getFetchDeadline() {
+	if (inFlightDeadlines.length === 0) {
+		return null;
+	}
+
+	const deadline = ReflectApply(MathMax, null, inFlightDeadlines);
+
+	return deadline === Infinity ? null : deadline;
+}
+
+// Keep in sync with `REQUEST_META_HEADER` in `request_meta.rs`.
+const REQUEST_META_HEADER = "x-edge-runtime-meta";
+
+// NOTE: The context of the request a handler runs for, which follows it
+// through its promises and timers like any `AsyncLocalStorage`.
+const requestContext = new AsyncLocalStorage();
+
+function getRequestContext(request, deadline) {
+	const value = request.headers.get(REQUEST_META_HEADER);
+	const context = value ? ops.op_request_context(value) : null;
+
+	// NOTE: A request that did not come through the server (e.g. one sent
+	// by the main worker without its headers) has no ID, but may still have
+	// a deadline.
+	return ObjectFreeze({
+		requestId: context?.requestId ?? null,
+		tenant: context?.tenant ?? null,
+		deadlineMs: context?.deadlineMs ?? (deadline === Infinity ? null : deadline),
+	});
+}
+
 function internalServerError() {
 	// "Internal Server Error"
 	return new Response(
@@ -50,11 +143,35 @@ function internalServerError() {
 	);
 }
 
+// Aborts the signal of the request once its client hangs up, until it has
+// been responded to. Returns the rid of the watch, if the client connection
+// is tracked.
+function watchConnClose(watcherRid, request) {
+	const rid = ops.op_http_conn_close_watch(watcherRid);
+
+	if (rid === null) {
+		return null;
+	}
+
+	const promise = ops.op_http_conn_closed(rid);
+
+	// NOTE: The watch must not keep the event loop of the worker alive.
+	core.unrefOpPromise(promise);
+	promise.then((hungUp) => {
+		if (hungUp) {
+			abortRequest(request);
+		}
+	}, () => {});
+
+	return rid;
+}
+
 function serveHttp(conn) {
 	let closed = false;
 
 	const [connRid, watcherRid] = ops.op_http_start(conn[internalRidSymbol]);
 	const httpConn = new HttpConn(connRid, conn.remoteAddr, conn.localAddr);
+	const closeWatches = new SafeSet();
 
 	httpConn.nextRequest = async () => {
 		const nextRequest = await HttpConnPrototypeNextRequest.call(httpConn);
@@ -68,12 +185,34 @@ function serveHttp(conn) {
 			streamRid: nextRequest.streamRid
 		};
 
+		const closeWatchRid = watchConnClose(watcherRid, nextRequest.request);
+
+		if (closeWatchRid !== null) {
+			const respondWith = nextRequest.respondWith;
+
+			SetPrototypeAdd(closeWatches, closeWatchRid);
+			nextRequest.respondWith = async (resp) => {
+				try {
+					return await respondWith(resp);
+				} finally {
+					SetPrototypeDelete(closeWatches, closeWatchRid);
+					core.tryClose(closeWatchRid);
+				}
+			};
+		}
+
 		return nextRequest;
 	};
 
 	httpConn.close = () => {
 		if (!closed) {
 			closed = true;
+
+			for (const rid of new SafeSetIterator(closeWatches)) {
+				core.tryClose(rid);
+			}
+
+			SetPrototypeClear(closeWatches);
 			core.tryClose(watcherRid);
 			HttpConnPrototypeClose.call(httpConn);
 		}
@@ -89,8 +228,6 @@ function serve(args1, args2) {
 		transport: "tcp",
 	};
 
-	const listener = Deno.listen(options);
-
 	if (typeof args1 === "function") {
 		options["handler"] = args1;
 	} else if (typeof args2 === "function") {
@@ -108,8 +245,35 @@ function serve(args1, args2) {
 		if (typeof args1["onError"] === "function") {
 			options["onError"] = args1["onError"];
 		}
+		if (args1["signal"] !== void 0) {
+			options["signal"] = args1["signal"];
+		}
+	}
+
+	if (activeServer !== null) {
+		throw new TypeError(
+			"Deno.serve() can only be called once in a worker: all requests are routed to the first server",
+		);
+	}
+
+	// NOTE: The listener does not open a socket. Its connections are taken
+	// from the internal transport the worker pool forwards requests through.
+	const listener = Deno.listen(options);
+	const addr = {
+		hostname: listener.addr.hostname,
+		port: listener.addr.port,
+		transport: options.transport,
+	};
+
+	if (acceptLoop === null) {
+		acceptLoop = acceptConnections(listener).finally(() => {
+			acceptLoop = null;
+		});
 	}
 
+	const connections = new SafeSet();
+	let isShuttingDown = false;
+
 	const handleHttp = async (conn) => {
 		const currentHttpConn = serveHttp(conn);
 
@@ -129,44 +293,86 @@ function serve(args1, args2) {
 		}
 	};
 
-	const finished = (async () => {
-		options["onListen"]?.({
-			hostname: options.hostname,
-			port: options.port
-		});
+	let resolveShutdown;
+	const shutdownRequested = new Promise((resolve) => {
+		resolveShutdown = resolve;
+	});
 
-		for await (const conn of listener) {
-			handleHttp(conn);
-		}
+	const handleConnection = (conn) => {
+		const handled = handleHttp(conn);
+
+		SetPrototypeAdd(connections, handled);
+		handled.finally(() => SetPrototypeDelete(connections, handled));
+	};
+
+	const finished = (async () => {
+		await Promise.race([acceptLoop, shutdownRequested]);
+		await SafePromiseAll([...connections]);
 	})();
 
+	let server;
 	const shutdown = () => {
-		// TODO: Not currently supported
+		if (!isShuttingDown) {
+			isShuttingDown = true;
+
+			// NOTE: The connections that arrive from now on go to the server
+			// started next, if any.
+			if (activeServer === server) {
+				activeServer = null;
+			}
+
+			resolveShutdown();
+		}
+
+		return finished;
 	};
 
-	return {
+	server = {
+		addr,
 		finished,
 		shutdown,
 		ref() {
-			// TODO: Not currently supported
-		},
-		unref() {
-			// TODO: Not currently supported
+			// NOTE: The worker lifetime is managed by its supervisor, so the
+			// server never holds the event loop open by itself.
 		},
+		unref() {},
+		[kHandleConnection]: handleConnection,
 	};
+
+	activeServer = server;
+	options["onListen"]?.(addr);
+
+	if (options["signal"]?.aborted) {
+		shutdown();
+	} else {
+		options["signal"]?.addEventListener("abort", () => shutdown(), {
+			once: true,
+		});
+	}
+
+	return server;
 }
 
 async function respond(requestEvent, httpConn, options) {
 	/** @type {Response} */
 	let response;
+	const deadline = getRequestDeadline(requestEvent.request);
+
+	ArrayPrototypePush(inFlightDeadlines, deadline);
+
 	try {
-		response = await options["handler"](requestEvent.request, {
-			remoteAddr: {
-				port: options.port,
-				hostname: options.hostname,
-				transport: options.transport
-			}
-		});
+		response = await requestContext.run(
+			getRequestContext(requestEvent.request, deadline),
+			options["handler"],
+			requestEvent.request,
+			{
+				remoteAddr: {
+					port: options.port,
+					hostname: options.hostname,
+					transport: options.transport
+				}
+			},
+		);
 
 	} catch (error) {
 		if (options["onError"] !== void 0) {
@@ -176,6 +382,12 @@ async function respond(requestEvent, httpConn, options) {
 			console.error(error);
 			response = internalServerError();
 		}
+	} finally {
+		ArrayPrototypeSplice(
+			inFlightDeadlines,
+			ArrayPrototypeIndexOf(inFlightDeadlines, deadline),
+			1,
+		);
 	}
 
 	if (response === internals.RAW_UPGRADE_RESPONSE_SENTINEL) {
@@ -245,5 +457,7 @@ export {
 	serveHttp,
 	getSupabaseTag,
 	applySupabaseTag,
+	getFetchDeadline,
+	requestContext,
 	upgradeWebSocket
 };
@@ -0,0 +1,134 @@
+// Makes `Intl` and the `toLocale*` methods format in the locale and the time
+// zone of the worker, in place of the ones of the host.
+//
+// NOTE: The local time of `Date` is already in the time zone of the worker,
+// which is set on its isolate (see `time_zone.rs`). `Intl` takes the default
+// time zone of ICU instead, which is the one of the host.
+
+import { primordials } from 'ext:core/mod.js';
+
+const {
+	ObjectDefineProperties,
+	ObjectDefineProperty,
+	ObjectSetPrototypeOf,
+	ReflectApply,
+	ReflectConstruct,
+	SafeSet,
+} = primordials;
+
+const INTL_CONSTRUCTORS = [
+	'Collator',
+	'DateTimeFormat',
+	'DisplayNames',
+	'ListFormat',
+	'NumberFormat',
+	'PluralRules',
+	'RelativeTimeFormat',
+	'Segmenter',
+];
+
+// NOTE: Unlike the others, these can be called without `new`.
+const CALLABLE_INTL_CONSTRUCTORS = new SafeSet(['Collator', 'DateTimeFormat', 'NumberFormat']);
+
+let defaultLocale;
+let defaultTimeZone;
+
+function withDefaults(locales, options, isDateTime) {
+	if (isDateTime && defaultTimeZone !== undefined && options?.timeZone === undefined) {
+		options = { ...options, timeZone: defaultTimeZone };
+	}
+
+	return [locales ?? defaultLocale, options];
+}
+
+function defineMethod(proto, name, fn) {
+	ObjectDefineProperty(proto, name, {
+		value: fn,
+		writable: true,
+		configurable: true,
+	});
+}
+
+function localizeIntl() {
+	const Intl = globalThis.Intl;
+
+	for (const name of INTL_CONSTRUCTORS) {
+		const Ctor = Intl[name];
+
+		if (Ctor === undefined) {
+			continue;
+		}
+
+		const isDateTime = name === 'DateTimeFormat';
+		const Localized = {
+			[name]: function (locales, options) {
+				if (new.target === undefined && !CALLABLE_INTL_CONSTRUCTORS.has(name)) {
+					return ReflectApply(Ctor, this, [locales, options]);
+				}
+
+				return ReflectConstruct(
+					Ctor,
+					withDefaults(locales, options, isDateTime),
+					new.target ?? Ctor,
+				);
+			},
+		}[name];
+
+		ObjectSetPrototypeOf(Localized, Ctor);
+		ObjectDefineProperties(Localized, {
+			length: { value: Ctor.length },
+			prototype: { value: Ctor.prototype },
+		});
+		defineMethod(Ctor.prototype, 'constructor', Localized);
+		defineMethod(Intl, name, Localized);
+	}
+
+	for (const name of ['toLocaleString', 'toLocaleDateString', 'toLocaleTimeString']) {
+		const method = globalThis.Date.prototype[name];
+
+		defineMethod(globalThis.Date.prototype, name, {
+			[name]: function (locales, options) {
+				return ReflectApply(method, this, withDefaults(locales, options, true));
+			},
+		}[name]);
+	}
+
+	for (const proto of [globalThis.Number.prototype, globalThis.BigInt.prototype]) {
+		const method = proto.toLocaleString;
+
+		defineMethod(proto, 'toLocaleString', function toLocaleString(locales, options) {
+			return ReflectApply(method, this, withDefaults(locales, options, false));
+		});
+	}
+
+	const StringPrototype = globalThis.String.prototype;
+	const localeCompare = StringPrototype.localeCompare;
+
+	defineMethod(StringPrototype, 'localeCompare', function localeCompare_(that, locales, options) {
+		return ReflectApply(localeCompare, this, [that, locales ?? defaultLocale, options]);
+	});
+
+	for (const name of ['toLocaleLowerCase', 'toLocaleUpperCase']) {
+		const method = StringPrototype[name];
+
+		defineMethod(StringPrototype, name, {
+			[name]: function (locales) {
+				return ReflectApply(method, this, [locales ?? defaultLocale]);
+			},
+		}[name]);
+	}
+}
+
+// Applies the locale and the time zone of the worker, if any is set.
+function localize(locale, timeZone) {
+	defaultLocale = locale ?? undefined;
+	defaultTimeZone = timeZone ?? undefined;
+
+	if (defaultLocale === undefined && defaultTimeZone === undefined) {
+		return;
+	}
+
+	localizeIntl();
+}
+
+export { localize };
@@ -17,22 +17,39 @@ use tokio::sync::oneshot;
 
 mod upgrade;
 
+pub mod allocator;
 pub mod auth_tokens;
+pub mod budget;
 pub mod cache;
 pub mod cert;
+pub mod cert_pinning;
+pub mod clock;
 pub mod conn_sync;
+pub mod dns_cache;
 pub mod emit;
 pub mod errors_rt;
 pub mod external_memory;
+pub mod feature_flags;
+pub mod fetch_budget;
 pub mod http;
+pub mod http_client;
 pub mod http_start;
 pub mod net;
 pub mod node;
 pub mod npm;
+pub mod outbound_cancel;
+pub mod outbound_pool;
 pub mod permissions;
+pub mod random;
+pub mod replay;
+pub mod request_meta;
+pub mod resource_limit;
 pub mod runtime;
+pub mod smtp;
+pub mod storage_tee;
 pub mod transpiler;
 pub mod util;
+pub mod websocket;
 
 pub struct MemCheckWaker(Arc<AtomicWaker>);
 
@@ -46,12 +63,40 @@ impl From<Arc<AtomicWaker>> for MemCheckWaker {
 pub struct SharedMetricSource {
     active_user_workers: Arc<AtomicUsize>,
     retired_user_workers: Arc<AtomicUsize>,
+    pending_user_worker_boots: Arc<AtomicUsize>,
+    queued_user_worker_boots: Arc<AtomicUsize>,
     received_requests: Arc<AtomicUsize>,
     handled_requests: Arc<AtomicUsize>,
     active_io: Arc<AtomicUsize>,
+    rejected_large_headers: Arc<AtomicUsize>,
+    rejected_long_uris: Arc<AtomicUsize>,
+    rejected_connections: Arc<AtomicUsize>,
+    slow_request_bodies: Arc<AtomicUsize>,
+    accepted_connections: Arc<AtomicUsize>,
+    accept_errors: Arc<AtomicUsize>,
+    queued_accepts: Arc<AtomicUsize>,
+    buffered_response_bytes: Arc<AtomicUsize>,
+    spilled_response_bytes: Arc<AtomicUsize>,
+    spilled_responses: Arc<AtomicUsize>,
 }
 
 impl SharedMetricSource {
+    pub fn active_user_workers(&self) -> usize {
+        self.active_user_workers.load(Ordering::Relaxed)
+    }
+
+    pub fn retired_user_workers(&self) -> usize {
+        self.retired_user_workers.load(Ordering::Relaxed)
+    }
+
+    pub fn pending_user_worker_boots(&self) -> usize {
+        self.pending_user_worker_boots.load(Ordering::Relaxed)
+    }
+
+    pub fn queued_user_worker_boots(&self) -> usize {
+        self.queued_user_worker_boots.load(Ordering::Relaxed)
+    }
+
     pub fn active_io(&self) -> usize {
         self.active_io.load(Ordering::Relaxed)
     }
@@ -64,6 +109,50 @@ impl SharedMetricSource {
         self.handled_requests.load(Ordering::Relaxed)
     }
 
+    pub fn accepted_connections(&self) -> usize {
+        self.accepted_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn accept_errors(&self) -> usize {
+        self.accept_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected_large_headers(&self) -> usize {
+        self.rejected_large_headers.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected_long_uris(&self) -> usize {
+        self.rejected_long_uris.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected_connections(&self) -> usize {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn slow_request_bodies(&self) -> usize {
+        self.slow_request_bodies.load(Ordering::Relaxed)
+    }
+
+    /// Connections accepted by the acceptors but not yet picked up by the
+    /// accept loop of the server.
+    pub fn queued_accepts(&self) -> usize {
+        self.queued_accepts.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of the responses read ahead of their clients, held in memory.
+    pub fn buffered_response_bytes(&self) -> usize {
+        self.buffered_response_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of the responses read ahead of their clients, spilled to disk.
+    pub fn spilled_response_bytes(&self) -> usize {
+        self.spilled_response_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn spilled_responses(&self) -> usize {
+        self.spilled_responses.load(Ordering::Relaxed)
+    }
+
     pub fn incl_active_user_workers(&self) {
         self.active_user_workers.fetch_add(1, Ordering::Relaxed);
     }
@@ -76,6 +165,26 @@ impl SharedMetricSource {
         self.retired_user_workers.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn incl_pending_user_worker_boots(&self) {
+        self.pending_user_worker_boots
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decl_pending_user_worker_boots(&self) {
+        self.pending_user_worker_boots
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn incl_queued_user_worker_boots(&self) {
+        self.queued_user_worker_boots
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decl_queued_user_worker_boots(&self) {
+        self.queued_user_worker_boots
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+
     pub fn incl_received_requests(&self) {
         self.received_requests.fetch_add(1, Ordering::Relaxed);
     }
@@ -84,6 +193,38 @@ impl SharedMetricSource {
         self.handled_requests.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn incl_rejected_large_headers(&self) {
+        self.rejected_large_headers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn incl_rejected_long_uris(&self) {
+        self.rejected_long_uris.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn incl_rejected_connections(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn incl_slow_request_bodies(&self) {
+        self.slow_request_bodies.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn incl_accepted_connections(&self) {
+        self.accepted_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn incl_accept_errors(&self) {
+        self.accept_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn incl_queued_accepts(&self) {
+        self.queued_accepts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decl_queued_accepts(&self) {
+        self.queued_accepts.fetch_sub(1, Ordering::Relaxed);
+    }
+
     pub fn incl_active_io(&self) {
         self.active_io.fetch_add(1, Ordering::Relaxed);
     }
@@ -92,12 +233,48 @@ impl SharedMetricSource {
         self.active_io.fetch_sub(1, Ordering::Relaxed);
     }
 
+    pub fn incl_buffered_response_bytes(&self, bytes: usize) {
+        self.buffered_response_bytes
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn decl_buffered_response_bytes(&self, bytes: usize) {
+        self.buffered_response_bytes
+            .fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub fn incl_spilled_response_bytes(&self, bytes: usize) {
+        self.spilled_response_bytes
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn decl_spilled_response_bytes(&self, bytes: usize) {
+        self.spilled_response_bytes
+            .fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub fn incl_spilled_responses(&self) {
+        self.spilled_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn reset(&self) {
         self.active_user_workers.store(0, Ordering::Relaxed);
         self.retired_user_workers.store(0, Ordering::Relaxed);
+        self.pending_user_worker_boots.store(0, Ordering::Relaxed);
+        self.queued_user_worker_boots.store(0, Ordering::Relaxed);
         self.received_requests.store(0, Ordering::Relaxed);
         self.handled_requests.store(0, Ordering::Relaxed);
         self.active_io.store(0, Ordering::Relaxed);
+        self.rejected_large_headers.store(0, Ordering::Relaxed);
+        self.rejected_long_uris.store(0, Ordering::Relaxed);
+        self.rejected_connections.store(0, Ordering::Relaxed);
+        self.slow_request_bodies.store(0, Ordering::Relaxed);
+        self.accepted_connections.store(0, Ordering::Relaxed);
+        self.accept_errors.store(0, Ordering::Relaxed);
+        self.queued_accepts.store(0, Ordering::Relaxed);
+        self.buffered_response_bytes.store(0, Ordering::Relaxed);
+        self.spilled_response_bytes.store(0, Ordering::Relaxed);
+        self.spilled_responses.store(0, Ordering::Relaxed);
     }
 }
 
@@ -229,8 +406,20 @@ struct RuntimeHeapStatistics {
 struct RuntimeSharedStatistics {
     active_user_workers_count: usize,
     retired_user_workers_count: usize,
+    pending_user_worker_boots_count: usize,
+    queued_user_worker_boots_count: usize,
     received_requests_count: usize,
     handled_requests_count: usize,
+    rejected_large_headers_count: usize,
+    rejected_long_uris_count: usize,
+    rejected_connections_count: usize,
+    slow_request_bodies_count: usize,
+    accepted_connections_count: usize,
+    accept_errors_count: usize,
+    queued_accepts_count: usize,
+    buffered_response_bytes: usize,
+    spilled_response_bytes: usize,
+    spilled_responses_count: usize,
 }
 
 impl RuntimeSharedStatistics {
@@ -238,8 +427,20 @@ impl RuntimeSharedStatistics {
         Self {
             active_user_workers_count: src.active_user_workers.load(Ordering::Relaxed),
             retired_user_workers_count: src.retired_user_workers.load(Ordering::Relaxed),
+            pending_user_worker_boots_count: src.pending_user_worker_boots(),
+            queued_user_worker_boots_count: src.queued_user_worker_boots(),
             received_requests_count: src.received_requests.load(Ordering::Relaxed),
             handled_requests_count: src.handled_requests.load(Ordering::Relaxed),
+            rejected_large_headers_count: src.rejected_large_headers.load(Ordering::Relaxed),
+            rejected_long_uris_count: src.rejected_long_uris.load(Ordering::Relaxed),
+            rejected_connections_count: src.rejected_connections.load(Ordering::Relaxed),
+            slow_request_bodies_count: src.slow_request_bodies.load(Ordering::Relaxed),
+            accepted_connections_count: src.accepted_connections(),
+            accept_errors_count: src.accept_errors.load(Ordering::Relaxed),
+            queued_accepts_count: src.queued_accepts(),
+            buffered_response_bytes: src.buffered_response_bytes(),
+            spilled_response_bytes: src.spilled_response_bytes(),
+            spilled_responses_count: src.spilled_responses(),
         }
     }
 }
@@ -251,6 +452,12 @@ struct RuntimeMetrics {
     heap_stats: RuntimeHeapStatistics,
     #[serde(flatten)]
     shared_stats: RuntimeSharedStatistics,
+    allocator_stats: Option<allocator::AllocatorStatistics>,
+    dns_cache_stats: Option<dns_cache::DnsCacheStatistics>,
+    outbound_pool_stats: Option<outbound_pool::OutboundPoolStatistics>,
+    server_cert_stats: Option<cert::ServerCertStatistics>,
+    root_cert_store_report: Option<cert::RootCertStoreReport>,
+    runtime_topology_stats: Option<base_rt::topology::RuntimeTopologyStatistics>,
 }
 /*
 #[op2(fast)]
@@ -281,6 +488,13 @@ async fn op_runtime_metrics(state: Rc<RefCell<OpState>>) -> Result<RuntimeMetric
     runtime_metrics.heap_stats = runtime_metric_src.get_heap_statistics().await;
     runtime_metrics.shared_stats =
         RuntimeSharedStatistics::from_shared_metric_src(&runtime_metric_src.shared);
+    runtime_metrics.allocator_stats = allocator::get_allocator_statistics();
+    runtime_metrics.dns_cache_stats = dns_cache::get_dns_cache_statistics();
+    runtime_metrics.outbound_pool_stats = outbound_pool::get_outbound_pool_statistics();
+    runtime_metrics.server_cert_stats = cert::get_server_cert_statistics();
+    runtime_metrics.root_cert_store_report = cert::get_default_root_cert_store_report();
+    runtime_metrics.runtime_topology_stats =
+        Some(base_rt::topology::get_runtime_topology_statistics());
 
     Ok(runtime_metrics)
 }
@@ -371,6 +585,22 @@ deno_core::extension!(
         op_set_raw,
         op_bootstrap_unstable_args,
         op_raise_segfault,
+        websocket::op_ws_idle_timeout_ms,
+        budget::op_runtime_context,
+        feature_flags::op_feature_flags,
+        feature_flags::op_feature_flags_changed,
+        request_meta::op_request_meta,
+        request_meta::op_request_context,
+        clock::op_clock_granularity,
+        clock::op_clock_now,
+        random::op_random_uuid,
+        replay::op_replay_mode,
+        replay::op_replay_max_body_size,
+        replay::op_replay_record_fetch,
+        replay::op_replay_take_fetch,
+        smtp::op_smtp_send,
+        storage_tee::op_storage_tee,
+        storage_tee::op_storage_tee_result,
     ],
     esm_entry_point = "ext:sb_core_main_js/js/bootstrap.js",
     esm = [
@@ -381,9 +611,21 @@ deno_core::extension!(
         "js/http.js",
         "js/denoOverrides.js",
         "js/navigator.js",
+        "js/intl.js",
         "js/bootstrap.js",
         "js/main_worker.js",
         "js/00_serve.js",
         "js/01_http.js"
-    ]
+    ],
+    middleware = |op| match op.name {
+        "op_fetch_send" => op.with_implementation_from(&fetch_budget::op_fetch_send_budgeted()),
+        "op_now" => op.with_implementation_from(&clock::op_now_coarsened()),
+        "op_crypto_get_random_values" => {
+            op.with_implementation_from(&random::op_crypto_get_random_values_counted())
+        }
+        _ => op,
+    },
+    state = |state| {
+        state.put(clock::HostClockOrigin(std::time::Instant::now()));
+    }
 );
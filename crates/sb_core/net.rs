@@ -7,6 +7,7 @@ use deno_core::CancelHandle;
 use deno_core::OpState;
 use deno_core::Resource;
 use deno_core::ResourceId;
+use deno_net::ops::op_node_unstable_net_listen_udp;
 use deno_net::ops::IpAddr;
 use std::borrow::Cow;
 use std::cell::RefCell;
@@ -21,6 +22,7 @@ use tracing::span;
 use tracing::Level;
 
 use crate::conn_sync::DenoRuntimeDropToken;
+use crate::permissions::Permissions;
 
 pub struct TokioDuplexResource {
     id: usize,
@@ -190,10 +192,14 @@ deno_core::extension!(
         "op_net_listen_tcp" => op.with_implementation_from(&op_net_listen()),
         "op_net_accept_tcp" => op.with_implementation_from(&op_net_accept()),
 
-        // disable listening on TLS, UDP and Unix sockets
+        // NOTE: UDP sockets are gated by the socket permissions of the
+        // worker. The node variant skips the unstable feature check.
+        "op_net_listen_udp" => {
+            op.with_implementation_from(&op_node_unstable_net_listen_udp::<Permissions>())
+        }
+
+        // disable listening on TLS and Unix sockets
         "op_net_listen_tls" => op.with_implementation_from(&op_net_unsupported()),
-        "op_net_listen_udp" => op.with_implementation_from(&op_net_unsupported()),
-        "op_node_unstable_net_listen_udp" => op.with_implementation_from(&op_net_unsupported()),
         "op_net_listen_unix" => op.with_implementation_from(&op_net_unsupported()),
         "op_net_listen_unixpacket" => op.with_implementation_from(&op_net_unsupported()),
         "op_node_unstable_net_listen_unixpacket" =>
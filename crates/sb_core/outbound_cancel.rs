@@ -0,0 +1,96 @@
+//! Cancellation of the outbound I/O of a worker once the supervisor asks for
+//! it to be terminated.
+//!
+//! The fetches and the web sockets of a worker are driven by connection tasks
+//! of the runtime, which would otherwise keep their sockets open for as long
+//! as the resources of the worker hold on to them, even though the isolate is
+//! no longer there to read them.
+
+use std::future::Future;
+use std::io;
+
+use deno_core::OpState;
+use tokio_util::sync::CancellationToken;
+
+/// The resources that hold outbound connections of a worker. Closing a
+/// `fetchCancelHandle` aborts the fetch it belongs to, even while it is
+/// awaited by an op.
+static OUTBOUND_RESOURCE_NAMES: &[&str] = &[
+    "fetchCancelHandle",
+    "fetchRequest",
+    "fetchResponse",
+    "httpClient",
+    "serverWebSocket",
+];
+
+/// Cancelled once the supervisor requests the termination of the worker.
+#[derive(Debug, Clone)]
+pub struct OutboundCancelToken(pub CancellationToken);
+
+/// Closes the outbound resources of the worker, and returns how many were
+/// closed.
+pub fn close_outbound_resources(state: &mut OpState) -> usize {
+    let rids = state
+        .resource_table
+        .names()
+        .filter(|(_, name)| OUTBOUND_RESOURCE_NAMES.contains(&name.as_ref()))
+        .map(|(rid, _)| rid)
+        .collect::<Vec<_>>();
+
+    for rid in rids.iter() {
+        let _ = state.resource_table.close(*rid);
+    }
+
+    rids.len()
+}
+
+/// Runs `fut` until the token is cancelled, if there is one.
+pub(crate) async fn or_cancelled<T, F>(
+    maybe_token: Option<&CancellationToken>,
+    fut: F,
+) -> io::Result<T>
+where
+    F: Future<Output = io::Result<T>>,
+{
+    let Some(token) = maybe_token else {
+        return fut.await;
+    };
+
+    tokio::select! {
+        res = fut => res,
+        _ = token.cancelled() => Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "the worker is terminating",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::pending;
+    use std::io;
+
+    use tokio_util::sync::CancellationToken;
+
+    use super::or_cancelled;
+
+    #[tokio::test]
+    async fn test_or_cancelled() {
+        let token = CancellationToken::new();
+
+        assert_eq!(
+            or_cancelled(Some(&token), async { Ok(1) }).await.unwrap(),
+            1
+        );
+
+        token.cancel();
+
+        assert_eq!(
+            or_cancelled(Some(&token), pending::<io::Result<()>>())
+                .await
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Interrupted
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+use deno_core::error::AnyError;
+use deno_core::serde_json;
+use deno_fetch::reqwest;
+use deno_fetch::reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Serialize;
+
+use crate::cert::OutboundTlsOptions;
+use crate::dns_cache::{DnsCache, DnsCacheResolver};
+
+static OUTBOUND_POOL: OnceLock<Arc<OutboundPool>> = OnceLock::new();
+
+thread_local! {
+    static RUNTIME: RuntimeClients = RuntimeClients(thread::current().id());
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OutboundPoolOptions {
+    /// Maximum number of idle connections kept per host.
+    pub max_idle_per_host: usize,
+    /// How long an idle connection is kept before it is closed.
+    pub idle_timeout: Duration,
+    /// Negotiates HTTP/2 with the hosts that support it, so that the requests
+    /// to a host are multiplexed over a single connection. Otherwise only
+    /// HTTP/1.1 is offered.
+    pub prefer_h2: bool,
+}
+
+#[derive(Debug, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboundPoolStatistics {
+    /// Number of clients shared by the workers of a tenant.
+    pub shared_clients: usize,
+    /// Number of connections opened to host names by the pooled clients.
+    pub connections_opened: u64,
+    /// Number of outbound requests of user workers.
+    pub requests: u64,
+    pub max_idle_per_host: usize,
+    pub idle_timeout_ms: u64,
+    pub prefer_h2: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    /// The thread of the runtime that drives the connections of the client.
+    runtime: ThreadId,
+    tenant: Option<String>,
+    timeout: Option<Duration>,
+    /// The TLS options the client was built with, serialized.
+    tls: Option<String>,
+}
+
+/// Forgets the clients of the runtime of a thread once the thread exits,
+/// since their connections went away along with the runtime.
+struct RuntimeClients(ThreadId);
+
+impl Drop for RuntimeClients {
+    fn drop(&mut self) {
+        if let Some(pool) = OUTBOUND_POOL.get() {
+            pool.forget_runtime(self.0);
+        }
+    }
+}
+
+/// A process-wide pool of outbound connections. The fetches of the user
+/// workers of the same tenant go through a shared client, so that the
+/// connections opened by one isolate are reused by the others instead of
+/// being set up again for each worker.
+///
+/// NOTE: The connections of a client are driven by the runtime of the worker
+/// that opened them, so a client is only shared by the workers of the same
+/// runtime. Every isolate thread has a runtime of its own (see
+/// `base_rt::topology`), hence the runtime is told apart by its thread.
+pub struct OutboundPool {
+    opts: OutboundPoolOptions,
+    clients: Mutex<HashMap<ClientKey, reqwest::Client>>,
+    connections_opened: Arc<AtomicU64>,
+    requests: AtomicU64,
+}
+
+impl OutboundPool {
+    fn new(opts: OutboundPoolOptions) -> Self {
+        Self {
+            opts,
+            clients: Mutex::default(),
+            connections_opened: Arc::default(),
+            requests: AtomicU64::new(0),
+        }
+    }
+
+    pub fn options(&self) -> &OutboundPoolOptions {
+        &self.opts
+    }
+
+    /// Returns the client shared by the user workers of the tenant on the
+    /// current runtime whose fetches have the given timeout and TLS options,
+    /// creating it with `create` if there is none yet.
+    pub fn get_or_create_client<F>(
+        &self,
+        tenant: Option<&str>,
+        maybe_timeout: Option<Duration>,
+        maybe_tls: Option<&OutboundTlsOptions>,
+        create: F,
+    ) -> Result<reqwest::Client, AnyError>
+    where
+        F: FnOnce() -> Result<reqwest::Client, AnyError>,
+    {
+        let key = ClientKey {
+            runtime: RUNTIME.with(|it| it.0),
+            tenant: tenant.map(str::to_string),
+            timeout: maybe_timeout,
+            tls: maybe_tls.map(serde_json::to_string).transpose()?,
+        };
+
+        let mut clients = self.clients.lock().unwrap();
+
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        let client = create()?;
+
+        clients.insert(key, client.clone());
+
+        Ok(client)
+    }
+
+    fn forget_runtime(&self, runtime: ThreadId) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|key, _| key.runtime != runtime);
+    }
+
+    /// Returns a resolver that counts the connections opened by a client of
+    /// the pool.
+    pub(crate) fn resolver(&self, maybe_dns_cache: Option<Arc<DnsCache>>) -> PoolResolver {
+        PoolResolver {
+            maybe_dns_cache,
+            connections_opened: self.connections_opened.clone(),
+        }
+    }
+
+    pub fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn statistics(&self) -> OutboundPoolStatistics {
+        OutboundPoolStatistics {
+            shared_clients: self.clients.lock().unwrap().len(),
+            connections_opened: self.connections_opened.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            max_idle_per_host: self.opts.max_idle_per_host,
+            idle_timeout_ms: self.opts.idle_timeout.as_millis() as u64,
+            prefer_h2: self.opts.prefer_h2,
+        }
+    }
+}
+
+/// Resolves the host names of the connections a pooled client opens, through
+/// the DNS cache if it is enabled.
+///
+/// NOTE: A lookup only happens when there is no idle connection to the host
+/// left to reuse, so the lookups are counted as the opened connections.
+pub(crate) struct PoolResolver {
+    maybe_dns_cache: Option<Arc<DnsCache>>,
+    connections_opened: Arc<AtomicU64>,
+}
+
+impl Resolve for PoolResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        self.connections_opened.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(cache) = self.maybe_dns_cache.clone() {
+            // NOTE: A pooled client is shared by the workers of a tenant, so
+            // its lookups are not tied to any of them.
+            return DnsCacheResolver {
+                cache,
+                maybe_cancel: None,
+            }
+            .resolve(name);
+        }
+
+        let host = name.as_str().to_string();
+
+        Box::pin(async move {
+            let addrs: Addrs = Box::new(
+                tokio::net::lookup_host((host, 0))
+                    .await?
+                    .collect::<Vec<SocketAddr>>()
+                    .into_iter(),
+            );
+
+            Ok(addrs)
+        })
+    }
+}
+
+/// Enables the process-wide pool of outbound connections. Only the first call
+/// has an effect.
+pub fn init_outbound_pool(opts: OutboundPoolOptions) {
+    let _ = OUTBOUND_POOL.get_or_init(|| Arc::new(OutboundPool::new(opts)));
+}
+
+pub fn get_outbound_pool() -> Option<Arc<OutboundPool>> {
+    OUTBOUND_POOL.get().cloned()
+}
+
+pub fn get_outbound_pool_statistics() -> Option<OutboundPoolStatistics> {
+    OUTBOUND_POOL.get().map(|it| it.statistics())
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+
+    use deno_fetch::reqwest;
+
+    use crate::cert::OutboundTlsOptions;
+
+    use super::{OutboundPool, OutboundPoolOptions, RUNTIME};
+
+    #[test]
+    #[allow(clippy::disallowed_types)]
+    fn test_outbound_pool_shares_clients_by_tenant_and_timeout() {
+        let pool = OutboundPool::new(OutboundPoolOptions {
+            max_idle_per_host: 8,
+            idle_timeout: Duration::from_secs(30),
+            prefer_h2: true,
+        });
+
+        let mut created = 0;
+        let tls = OutboundTlsOptions {
+            ca_file: Some("/etc/ssl/acme.pem".to_string()),
+            ..Default::default()
+        };
+
+        let mut get =
+            |tenant: Option<&str>, timeout: Option<Duration>, tls: Option<&OutboundTlsOptions>| {
+                pool.get_or_create_client(tenant, timeout, tls, || {
+                    created += 1;
+                    Ok(reqwest::Client::new())
+                })
+                .unwrap()
+            };
+
+        get(Some("acme"), None, None);
+        get(Some("acme"), None, None);
+        get(Some("acme"), Some(Duration::from_secs(5)), None);
+        get(Some("acme"), None, Some(&tls));
+        get(Some("acme"), None, Some(&tls));
+        get(Some("globex"), None, None);
+        get(None, None, None);
+
+        drop(get);
+
+        assert_eq!(created, 5);
+
+        pool.record_request();
+
+        let stats = pool.statistics();
+
+        assert_eq!(stats.shared_clients, 5);
+        assert_eq!(stats.requests, 1);
+        assert_eq!(stats.connections_opened, 0);
+        assert_eq!(stats.idle_timeout_ms, 30_000);
+    }
+
+    #[test]
+    #[allow(clippy::disallowed_types)]
+    fn test_outbound_pool_shares_clients_by_runtime() {
+        let pool = OutboundPool::new(OutboundPoolOptions {
+            max_idle_per_host: 8,
+            idle_timeout: Duration::from_secs(30),
+            prefer_h2: true,
+        });
+
+        let get = || {
+            pool.get_or_create_client(None, None, None, || Ok(reqwest::Client::new()))
+                .unwrap()
+        };
+
+        get();
+        get();
+
+        let other_runtime = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    get();
+                    RUNTIME.with(|it| it.0)
+                })
+                .join()
+                .unwrap()
+        });
+
+        assert_eq!(pool.statistics().shared_clients, 2);
+
+        pool.forget_runtime(other_runtime);
+
+        assert_eq!(pool.statistics().shared_clients, 1);
+    }
+}
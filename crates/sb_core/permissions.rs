@@ -2,26 +2,115 @@ use deno_core::error::{custom_error, generic_error, AnyError};
 use deno_core::url::Url;
 use deno_fs::OpenOptions;
 use deno_permissions::NetDescriptor;
+use log::{info, warn};
 use std::borrow::Cow;
+use std::io;
 use std::path::Path;
 
+use crate::resource_limit::ResourceLimit;
+
 pub struct Permissions {
     net_access_disabled: bool,
     allow_net: Option<Vec<NetDescriptor>>,
+    /// Hosts (and ports) raw TCP/UDP sockets may be opened to, on top of
+    /// `allow_net`. Listening on a socket needs its address to be listed here.
+    /// A descriptor without a port allows any port of the host.
+    allow_sockets: Option<Vec<NetDescriptor>>,
+    /// Taken from by the checks of the APIs that open resources. `None` means
+    /// unlimited.
+    resource_limit: Option<ResourceLimit>,
 }
 
 impl Default for Permissions {
     fn default() -> Self {
-        Self::new(false, None)
+        Self::new(false, None, None)
     }
 }
 
 impl Permissions {
-    pub fn new(net_access_disabled: bool, allow_net: Option<Vec<NetDescriptor>>) -> Self {
+    pub fn new(
+        net_access_disabled: bool,
+        allow_net: Option<Vec<NetDescriptor>>,
+        allow_sockets: Option<Vec<NetDescriptor>>,
+    ) -> Self {
         Self {
             net_access_disabled,
             allow_net,
+            allow_sockets,
+            resource_limit: None,
+        }
+    }
+
+    pub fn set_resource_limit(&mut self, limit: ResourceLimit) {
+        self.resource_limit = Some(limit);
+    }
+
+    fn take_resource(&self) -> Result<(), AnyError> {
+        self.resource_limit
+            .as_ref()
+            .map_or(Ok(()), ResourceLimit::take)
+    }
+
+    fn check_socket<T: AsRef<str>>(
+        &self,
+        host: &(T, Option<u16>),
+        api_name: &str,
+    ) -> Result<(), AnyError> {
+        let descriptor = NetDescriptor(host.0.as_ref().parse()?, host.1);
+
+        self.check_allow_net(&descriptor, api_name)?;
+
+        let is_listen = api_name.starts_with("Deno.listen");
+        let allowed = match &self.allow_sockets {
+            Some(allow_sockets) => is_allowed(allow_sockets, &descriptor),
+            None => !is_listen,
+        };
+
+        if !allowed {
+            warn!("denied socket access to {descriptor} ({api_name})");
+            return Err(custom_error(
+                "PermissionDenied",
+                format!("Socket access to {descriptor} is not allowed for user worker"),
+            ));
+        }
+
+        if self.allow_sockets.is_some() {
+            info!("granted socket access to {descriptor} ({api_name})");
+        }
+
+        Ok(())
+    }
+
+    /// Checks a host against the net allowlist, if any. A descriptor without
+    /// a port allows any port of the host.
+    fn check_allow_net(&self, descriptor: &NetDescriptor, api_name: &str) -> Result<(), AnyError> {
+        let Some(allow_net) = &self.allow_net else {
+            return Ok(());
+        };
+
+        if !is_allowed(allow_net, descriptor) {
+            warn!("denied net access to {descriptor} ({api_name})");
+            return Err(custom_error(
+                "PermissionDenied",
+                format!("Access to {descriptor} is not allowed for user worker"),
+            ));
         }
+
+        Ok(())
+    }
+
+    /// Checks a connection the runtime opens on behalf of the worker, e.g. to
+    /// send email, which is gated by the net allowlist as `fetch` is rather
+    /// than by the socket one.
+    pub fn check_outbound(&self, host: &str, port: u16, api_name: &str) -> Result<(), AnyError> {
+        if self.net_access_disabled {
+            return Err(custom_error(
+                "PermissionDenied",
+                "net access disabled for the user worker",
+            ));
+        }
+
+        self.check_allow_net(&NetDescriptor(host.parse()?, Some(port)), api_name)
     }
 
     pub fn check_env(&mut self, _var: &str) -> Result<(), AnyError> {
@@ -42,11 +131,25 @@ impl Permissions {
     }
 }
 
+fn is_allowed(allowlist: &[NetDescriptor], descriptor: &NetDescriptor) -> bool {
+    allowlist
+        .iter()
+        .any(|it| it.0 == descriptor.0 && (it.1.is_none() || it.1 == descriptor.1))
+}
+
 deno_core::extension!(
     sb_core_permissions,
-    options = { net_access_disabled: bool, allow_net: Option<Vec<NetDescriptor>> },
+    options = {
+        net_access_disabled: bool,
+        allow_net: Option<Vec<NetDescriptor>>,
+        allow_sockets: Option<Vec<NetDescriptor>>
+    },
     state = |state, options| {
-        state.put::<Permissions>(Permissions::new(options.net_access_disabled, options.allow_net));
+        state.put::<Permissions>(Permissions::new(
+            options.net_access_disabled,
+            options.allow_net,
+            options.allow_sockets,
+        ));
     }
 );
 
@@ -76,7 +179,7 @@ impl deno_fetch::FetchPermissions for Permissions {
             }
         }
 
-        Ok(())
+        self.take_resource()
     }
 
     fn check_read(&mut self, _p: &Path, _api_name: &str) -> Result<(), AnyError> {
@@ -88,7 +191,7 @@ impl deno_net::NetPermissions for Permissions {
     fn check_net<T: AsRef<str>>(
         &mut self,
         host: &(T, Option<u16>),
-        _api_name: &str,
+        api_name: &str,
     ) -> Result<(), AnyError> {
         if self.net_access_disabled {
             return Err(custom_error(
@@ -97,17 +200,20 @@ impl deno_net::NetPermissions for Permissions {
             ));
         }
 
-        if let Some(allow_net) = &self.allow_net {
-            let hostname = host.0.as_ref().parse()?;
-            let descriptor = NetDescriptor(hostname, host.1);
-            if !allow_net.contains(&descriptor) {
-                return Err(custom_error(
-                    "PermissionDenied",
-                    format!("Access to {descriptor} is not allowed for user worker"),
-                ));
+        // NOTE: Every API checked here except DNS resolution opens a raw
+        // socket, which is gated by the socket list on top of the net one.
+        // Sending a datagram is checked on every send, but opens nothing.
+        if api_name != "Deno.resolveDns()" {
+            self.check_socket(host, api_name)?;
+
+            if api_name != "Deno.DatagramConn.send()" {
+                self.take_resource()?;
             }
+
+            return Ok(());
         }
-        Ok(())
+
+        self.check_allow_net(&NetDescriptor(host.0.as_ref().parse()?, host.1), api_name)
     }
 
     fn check_read(&mut self, _path: &Path, _api_name: &str) -> Result<(), AnyError> {
@@ -137,7 +243,11 @@ impl deno_websocket::WebSocketPermissions for Permissions {
                 ));
             }
         }
-        Ok(())
+
+        match &self.resource_limit {
+            Some(limit) => limit.take_websocket(url.as_str()),
+            None => Ok(()),
+        }
     }
 }
 
@@ -153,6 +263,10 @@ impl deno_fs::FsPermissions for Permissions {
         path: &'a Path,
         _api_name: &str,
     ) -> Result<Cow<'a, Path>, deno_io::fs::FsError> {
+        self.take_resource().map_err(|err| {
+            deno_io::fs::FsError::Io(io::Error::new(io::ErrorKind::Other, err.to_string()))
+        })?;
+
         Ok(Cow::Borrowed(path))
     }
 
@@ -240,3 +354,134 @@ impl sb_node::NodePermissions for Permissions {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use deno_core::url::Url;
+    use deno_net::NetPermissions;
+
+    use crate::resource_limit::ResourceLimit;
+
+    use super::Permissions;
+
+    #[test]
+    fn test_socket_permissions() {
+        let mut perms = Permissions::new(
+            false,
+            None,
+            Some(vec![
+                "smtp.example.com:587".parse().unwrap(),
+                "db.example.com".parse().unwrap(),
+            ]),
+        );
+
+        let mut check =
+            |host: &str, port: u16| perms.check_net(&(host, Some(port)), "Deno.connect()");
+
+        assert!(check("smtp.example.com", 587).is_ok());
+        assert!(check("smtp.example.com", 25).is_err());
+        assert!(check("db.example.com", 5432).is_ok());
+        assert!(check("example.com", 80).is_err());
+
+        assert!(perms
+            .check_net(&("db.example.com", Some(5432)), "Deno.listenDatagram()")
+            .is_ok());
+        assert!(perms
+            .check_net(&("example.com", Some(5432)), "Deno.listenDatagram()")
+            .is_err());
+
+        // Without a socket list, sockets follow the net allowlist, but can't
+        // listen.
+        let mut perms = Permissions::default();
+
+        assert!(perms
+            .check_net(&("example.com", Some(80)), "Deno.connect()")
+            .is_ok());
+        assert!(perms
+            .check_net(&("0.0.0.0", Some(5353)), "Deno.listenDatagram()")
+            .is_err());
+
+        let mut perms = Permissions::new(false, Some(vec!["example.com".parse().unwrap()]), None);
+
+        assert!(perms
+            .check_net(&("example.com", Some(5432)), "Deno.connect()")
+            .is_ok());
+        assert!(perms
+            .check_net(&("db.example.com", Some(5432)), "Deno.connect()")
+            .is_err());
+
+        let mut perms = Permissions::new(
+            false,
+            Some(vec!["example.com".parse().unwrap()]),
+            Some(vec!["db.example.com".parse().unwrap()]),
+        );
+
+        assert!(perms
+            .check_net(&("db.example.com", Some(5432)), "Deno.connect()")
+            .is_err());
+    }
+
+    #[test]
+    fn test_outbound_permissions() {
+        let perms = Permissions::new(
+            false,
+            Some(vec![
+                "smtp.example.com:587".parse().unwrap(),
+                "mail.example.com".parse().unwrap(),
+            ]),
+            Some(vec![]),
+        );
+
+        let check =
+            |host: &str, port: u16| perms.check_outbound(host, port, "EdgeRuntime.smtp.send()");
+
+        assert!(check("smtp.example.com", 587).is_ok());
+        assert!(check("smtp.example.com", 465).is_err());
+        assert!(check("mail.example.com", 465).is_ok());
+        assert!(check("example.com", 587).is_err());
+
+        assert!(Permissions::new(true, None, None)
+            .check_outbound("smtp.example.com", 587, "EdgeRuntime.smtp.send()")
+            .is_err());
+    }
+
+    #[test]
+    fn test_resource_limit_permissions() {
+        let mut perms = Permissions::default();
+
+        perms.set_resource_limit(ResourceLimit::new(Some(2), Some(1)));
+
+        let url = Url::parse("wss://example.com/socket").unwrap();
+
+        // A WebSocket is checked twice, but only takes one slot.
+        assert!(deno_websocket::WebSocketPermissions::check_net_url(
+            &mut perms,
+            &url,
+            "new WebSocket()"
+        )
+        .is_ok());
+        assert!(deno_websocket::WebSocketPermissions::check_net_url(
+            &mut perms,
+            &url,
+            "new WebSocket()"
+        )
+        .is_ok());
+        assert!(deno_websocket::WebSocketPermissions::check_net_url(
+            &mut perms,
+            &url,
+            "new WebSocket()"
+        )
+        .is_err());
+
+        assert!(perms
+            .check_net(&("example.com", Some(53)), "Deno.DatagramConn.send()")
+            .is_ok());
+        assert!(perms
+            .check_net(&("example.com", Some(80)), "Deno.connect()")
+            .is_ok());
+        assert!(perms
+            .check_net(&("example.com", Some(80)), "Deno.connect()")
+            .is_err());
+        assert!(deno_fetch::FetchPermissions::check_net_url(&mut perms, &url, "fetch()").is_err());
+    }
+}
@@ -0,0 +1,132 @@
+//! Random values and UUIDs for the workers, drawn from a CSPRNG on the Rust
+//! side rather than generated in the isolate, along with how much of them
+//! each worker used, which is reported in its resource samples.
+//!
+//! `crypto.getRandomValues` keeps the op of `deno_crypto`, whose
+//! implementation is swapped for [`op_crypto_get_random_values_counted`] by
+//! the middleware of the extension.
+
+use deno_core::error::{type_error, AnyError};
+use deno_core::{op2, OpState};
+use rand::RngCore;
+use uuid::{Builder, Uuid};
+
+use crate::clock::worker_now_ms;
+use crate::replay::ReplayRng;
+
+/// The same limit as `crypto.getRandomValues` of `deno_crypto`.
+pub static MAX_RANDOM_VALUES_SIZE: usize = 65536;
+
+/// How much randomness a worker used since its boot.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomUsage {
+    pub bytes: u64,
+    pub uuids: u64,
+}
+
+fn record_usage(state: &mut OpState, bytes: usize, uuids: u64) {
+    if let Some(usage) = state.try_borrow_mut::<RandomUsage>() {
+        usage.bytes += bytes as u64;
+        usage.uuids += uuids;
+    }
+}
+
+/// Fills the buffer from the seeded generator of a replayed worker, or from
+/// the CSPRNG otherwise.
+fn fill_random(state: &mut OpState, buf: &mut [u8]) {
+    match state.try_borrow_mut::<ReplayRng>() {
+        Some(rng) => rng.fill_bytes(buf),
+        None => rand::thread_rng().fill_bytes(buf),
+    }
+}
+
+fn random_uuid(version: u8, now_ms: u64) -> Result<Uuid, AnyError> {
+    let mut bytes = [0u8; 16];
+
+    rand::thread_rng().fill_bytes(&mut bytes);
+    uuid_from_bytes(bytes, version, now_ms)
+}
+
+fn uuid_from_bytes(mut bytes: [u8; 16], version: u8, now_ms: u64) -> Result<Uuid, AnyError> {
+    Ok(match version {
+        4 => Builder::from_random_bytes(bytes).into_uuid(),
+        7 => {
+            // NOTE: The first 48 bits are the Unix timestamp in milliseconds,
+            // so that the UUIDs sort by their creation.
+            bytes[..6].copy_from_slice(&now_ms.to_be_bytes()[2..]);
+            bytes[6] = (bytes[6] & 0x0f) | 0x70;
+            bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+            Uuid::from_bytes(bytes)
+        }
+
+        _ => return Err(type_error(format!("unsupported UUID version: {version}"))),
+    })
+}
+
+/// Replaces `op_crypto_get_random_values` of `deno_crypto`, with the same
+/// signature, so that the bytes drawn by `crypto.getRandomValues` are counted
+/// and replayed workers draw them from their seeded generator.
+#[op2(fast)]
+pub fn op_crypto_get_random_values_counted(
+    state: &mut OpState,
+    #[buffer] out: &mut [u8],
+) -> Result<(), AnyError> {
+    if out.len() > MAX_RANDOM_VALUES_SIZE {
+        return Err(type_error(format!(
+            "the length of the array exceeds the maximum of {MAX_RANDOM_VALUES_SIZE} bytes"
+        )));
+    }
+
+    fill_random(state, out);
+    record_usage(state, out.len(), 0);
+
+    Ok(())
+}
+
+/// Returns a UUID of the given version, 4 or 7. The timestamp of a UUIDv7
+/// is read from the clock of the worker, coarsened or not.
+#[op2]
+#[string]
+pub fn op_random_uuid(state: &mut OpState, #[smi] version: u8) -> Result<String, AnyError> {
+    let now_ms = worker_now_ms(state);
+    let uuid = match state.try_borrow_mut::<ReplayRng>() {
+        Some(rng) => {
+            let mut bytes = [0u8; 16];
+
+            rng.fill_bytes(&mut bytes);
+            uuid_from_bytes(bytes, version, now_ms)?
+        }
+
+        None => random_uuid(version, now_ms)?,
+    };
+
+    record_usage(state, 0, 1);
+
+    Ok(uuid.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::{Variant, Version};
+
+    use super::random_uuid;
+
+    #[test]
+    fn test_random_uuid() {
+        let v4 = random_uuid(4, 0).unwrap();
+
+        assert_eq!(v4.get_version(), Some(Version::Random));
+        assert_eq!(v4.get_variant(), Variant::RFC4122);
+
+        let now_ms = 1_700_000_000_000;
+        let v7 = random_uuid(7, now_ms).unwrap();
+
+        assert_eq!(v7.get_version_num(), 7);
+        assert_eq!(v7.get_variant(), Variant::RFC4122);
+        assert!(v7.to_string().starts_with(&format!("{:012x}", now_ms)[..8]));
+        assert!(random_uuid(7, now_ms + 1).unwrap() > v7);
+
+        assert!(random_uuid(1, now_ms).is_err());
+    }
+}
@@ -0,0 +1,581 @@
+//! Recording of the traffic of the user workers, and its deterministic replay
+//! against another version of a function, offline.
+//!
+//! While recording, the pool writes every request dispatched to a user worker
+//! along with its response, and the workers add the responses of the fetches
+//! they make. While replaying, the recorded fetch responses stand in for the
+//! network, the clock of the worker reads the time the request was recorded
+//! at, and the random values are drawn from a seeded generator.
+//!
+//! A recording is a file of JSON lines, one [`RecordingEntry`] per line. The
+//! values of the headers that carry credentials are redacted unless told
+//! otherwise, and only the head of the bodies over
+//! [`RecordingOptions::max_body_size`] is recorded.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Error};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use deno_core::error::{custom_error, AnyError};
+use deno_core::{op2, serde_json, OpState, ToJsBuffer};
+use log::error;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::request_meta::REQUEST_META_HEADER;
+
+/// The headers whose values are redacted from a recording by default.
+static SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "apikey",
+    "x-api-key",
+    REQUEST_META_HEADER,
+];
+
+static REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordingOptions {
+    /// Maximum size of a recorded body, in bytes. Only the head of a larger
+    /// body is recorded.
+    pub max_body_size: usize,
+    /// Records the values of the headers that carry credentials as they are.
+    pub record_sensitive_headers: bool,
+}
+
+impl Default for RecordingOptions {
+    fn default() -> Self {
+        Self {
+            max_body_size: 1024 * 1024,
+            record_sensitive_headers: false,
+        }
+    }
+}
+
+/// A request dispatched to a user worker, and how the worker responded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedExchange {
+    pub service_path: String,
+    /// The ID of the request, which the fetches made while handling it are
+    /// recorded with.
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// Milliseconds since the unix epoch.
+    pub started_at: u64,
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    /// Base64.
+    pub body: String,
+    /// Whether only the head of the body was recorded.
+    #[serde(default)]
+    pub body_truncated: bool,
+    /// `None` if the request failed.
+    pub status: Option<u16>,
+    pub response_headers: Vec<(String, String)>,
+    /// Base64.
+    pub response_body: String,
+    #[serde(default)]
+    pub response_body_truncated: bool,
+    pub error: Option<String>,
+}
+
+/// A fetch made by a user worker, and the response it got.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedFetch {
+    pub service_path: String,
+    /// The ID of the request the fetch was made while handling, if any.
+    #[serde(default)]
+    pub request_id: Option<String>,
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// Base64.
+    pub body: String,
+    #[serde(default)]
+    pub body_truncated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RecordingEntry {
+    Request(RecordedExchange),
+    Fetch(RecordedFetch),
+}
+
+/// Reads the entries of a recording.
+///
+/// NOTE: The last entry may have been cut short if the runtime was killed
+/// while recording, so unreadable lines are skipped.
+pub fn read_recording(path: &Path) -> Result<Vec<RecordingEntry>, Error> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read the recording: {}", path.display()))?;
+
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Appends the entries to a recording. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    entries_tx: mpsc::UnboundedSender<RecordingEntry>,
+    opts: RecordingOptions,
+    /// The service the fetches recorded through this recorder are made by.
+    service_path: String,
+}
+
+impl Recorder {
+    /// Opens the recording at the given path, appending to it if it exists.
+    pub fn open(path: &Path, opts: RecordingOptions) -> Result<Self, Error> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open the recording: {}", path.display()))?;
+
+        let (entries_tx, entries_rx) = mpsc::unbounded_channel();
+
+        drop(tokio::spawn(write_recording(
+            path.to_path_buf(),
+            tokio::fs::File::from_std(file),
+            entries_rx,
+        )));
+
+        Ok(Self {
+            entries_tx,
+            opts,
+            service_path: String::new(),
+        })
+    }
+
+    /// Returns a recorder for the fetches of the workers of a service.
+    pub fn with_service_path(&self, service_path: &str) -> Self {
+        Self {
+            entries_tx: self.entries_tx.clone(),
+            opts: self.opts,
+            service_path: service_path.to_string(),
+        }
+    }
+
+    pub fn max_body_size(&self) -> usize {
+        self.opts.max_body_size
+    }
+
+    /// Returns the headers to record, the values of the sensitive ones being
+    /// redacted unless told otherwise.
+    pub fn headers<'a>(
+        &self,
+        headers: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    ) -> Vec<(String, String)> {
+        headers
+            .into_iter()
+            .map(|(name, value)| {
+                let value = if !self.opts.record_sensitive_headers
+                    && SENSITIVE_HEADERS
+                        .iter()
+                        .any(|it| it.eq_ignore_ascii_case(name))
+                {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value).into_owned()
+                };
+
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+
+    pub fn record(&self, entry: RecordingEntry) {
+        let _ = self.entries_tx.send(entry);
+    }
+}
+
+async fn write_recording(
+    path: PathBuf,
+    mut file: tokio::fs::File,
+    mut entries_rx: mpsc::UnboundedReceiver<RecordingEntry>,
+) {
+    let mut buf = Vec::new();
+
+    while let Some(entry) = entries_rx.recv().await {
+        buf.clear();
+
+        if serde_json::to_writer(&mut buf, &entry).is_err() {
+            continue;
+        }
+
+        buf.push(b'\n');
+
+        if let Err(err) = file.write_all(&buf).await {
+            error!(
+                "failed to write the recording {}: {:#}",
+                path.display(),
+                err
+            );
+        }
+    }
+
+    let _ = file.flush().await;
+}
+
+/// The recorded fetch responses a worker is replayed with.
+#[derive(Debug, Default)]
+pub struct ReplayFixtures {
+    fetches: Mutex<VecDeque<RecordedFetch>>,
+    /// The request ID of the exchange being replayed.
+    exchange: Mutex<Option<String>>,
+}
+
+impl ReplayFixtures {
+    pub fn new(fetches: impl IntoIterator<Item = RecordedFetch>) -> Self {
+        Self {
+            fetches: Mutex::new(fetches.into_iter().collect()),
+            exchange: Mutex::default(),
+        }
+    }
+
+    pub fn set_exchange(&self, request_id: Option<String>) {
+        *self.exchange.lock().unwrap() = request_id;
+    }
+
+    pub fn exchange(&self) -> Option<String> {
+        self.exchange.lock().unwrap().clone()
+    }
+
+    /// Takes the earliest response recorded for the method and the URL while
+    /// handling the given exchange, so that repeated fetches get their
+    /// responses in the recorded order, and concurrent exchanges the
+    /// responses they got.
+    ///
+    /// NOTE: The fetches recorded outside of any request, or before the
+    /// fetches were recorded along with their request, are taken by any
+    /// exchange.
+    pub fn take(&self, exchange: Option<&str>, method: &str, url: &str) -> Option<RecordedFetch> {
+        let mut fetches = self.fetches.lock().unwrap();
+        let is_match = |it: &RecordedFetch| it.method.eq_ignore_ascii_case(method) && it.url == url;
+        let idx = fetches
+            .iter()
+            .position(|it| is_match(it) && it.request_id.as_deref() == exchange)
+            .or_else(|| {
+                fetches
+                    .iter()
+                    .position(|it| is_match(it) && it.request_id.is_none())
+            })?;
+
+        fetches.remove(idx)
+    }
+}
+
+/// The clock of a replayed worker. It reads the time the request being
+/// replayed was recorded at, and does not move while the request is handled.
+#[derive(Debug, Clone)]
+pub struct ReplayClock {
+    origin_ms: u64,
+    now_ms: Arc<AtomicU64>,
+}
+
+impl ReplayClock {
+    pub fn new(origin_ms: u64) -> Self {
+        Self {
+            origin_ms,
+            now_ms: Arc::new(AtomicU64::new(origin_ms)),
+        }
+    }
+
+    pub fn set(&self, now_ms: u64) {
+        self.now_ms
+            .store(now_ms.max(self.origin_ms), Ordering::Release);
+    }
+
+    /// Milliseconds since the unix epoch.
+    pub fn now_ms(&self) -> u64 {
+        self.now_ms.load(Ordering::Acquire)
+    }
+
+    /// Milliseconds since the first request of the replay.
+    pub fn monotonic_ms(&self) -> u64 {
+        self.now_ms() - self.origin_ms
+    }
+}
+
+/// The generator the random values of a replayed worker are drawn from.
+#[derive(Debug)]
+pub struct ReplayRng(StdRng);
+
+impl ReplayRng {
+    pub fn new(seed: u64) -> Self {
+        Self(StdRng::seed_from_u64(seed))
+    }
+
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        self.0.fill_bytes(buf);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub fixtures: Arc<ReplayFixtures>,
+    pub clock: ReplayClock,
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum ReplayMode {
+    /// The worker records the responses of its fetches.
+    Record(Recorder),
+    /// The worker is replayed from a recording.
+    Replay(Replay),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchRecord {
+    request_id: Option<String>,
+    method: String,
+    url: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body_truncated: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayedFetch {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: ToJsBuffer,
+}
+
+/// Returns `record` or `replay` if the worker is recorded or replayed.
+#[op2]
+#[serde]
+pub fn op_replay_mode(state: &mut OpState) -> Option<String> {
+    if state.has::<Recorder>() {
+        Some("record".to_string())
+    } else if state.has::<Arc<ReplayFixtures>>() {
+        Some("replay".to_string())
+    } else {
+        None
+    }
+}
+
+/// Returns the maximum size of the bodies of the fetches a recorded worker
+/// records, `0` if it is not recorded.
+#[op2(fast)]
+#[number]
+pub fn op_replay_max_body_size(state: &mut OpState) -> usize {
+    state
+        .try_borrow::<Recorder>()
+        .map_or(0, Recorder::max_body_size)
+}
+
+#[op2]
+pub fn op_replay_record_fetch(
+    state: &mut OpState,
+    #[serde] fetch: FetchRecord,
+    #[buffer] body: &[u8],
+) {
+    let Some(recorder) = state.try_borrow::<Recorder>() else {
+        return;
+    };
+
+    let len = body.len().min(recorder.max_body_size());
+
+    recorder.record(RecordingEntry::Fetch(RecordedFetch {
+        service_path: recorder.service_path.clone(),
+        request_id: fetch.request_id,
+        method: fetch.method,
+        url: fetch.url,
+        status: fetch.status,
+        headers: recorder.headers(
+            fetch
+                .headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_bytes())),
+        ),
+        body: STANDARD.encode(&body[..len]),
+        body_truncated: fetch.body_truncated || len < body.len(),
+    }));
+}
+
+/// Responds to a fetch of a replayed worker with the recorded response.
+#[op2]
+#[serde]
+pub fn op_replay_take_fetch(
+    state: &mut OpState,
+    #[string] method: &str,
+    #[string] url: &str,
+) -> Result<ReplayedFetch, AnyError> {
+    let fetch = state
+        .try_borrow::<Arc<ReplayFixtures>>()
+        .and_then(|it| it.take(it.exchange().as_deref(), method, url))
+        .ok_or_else(|| {
+            custom_error(
+                "NotFound",
+                format!("no response was recorded for {method} {url}"),
+            )
+        })?;
+
+    if fetch.body_truncated {
+        return Err(custom_error(
+            "NotSupported",
+            format!("the response to {method} {url} was not recorded in full"),
+        ));
+    }
+
+    Ok(ReplayedFetch {
+        status: fetch.status,
+        headers: fetch.headers,
+        body: STANDARD.decode(fetch.body)?.into(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        RecordedFetch, Recorder, RecordingEntry, RecordingOptions, ReplayClock, ReplayFixtures,
+    };
+
+    fn fetch(url: &str, status: u16) -> RecordedFetch {
+        RecordedFetch {
+            service_path: "./hello".to_string(),
+            request_id: None,
+            method: "GET".to_string(),
+            url: url.to_string(),
+            status,
+            headers: vec![],
+            body: String::new(),
+            body_truncated: false,
+        }
+    }
+
+    #[test]
+    fn test_replay_fixtures() {
+        let fixtures = ReplayFixtures::new([
+            fetch("https://a.test/", 200),
+            fetch("https://b.test/", 404),
+            fetch("https://a.test/", 500),
+        ]);
+
+        assert_eq!(
+            fixtures
+                .take(None, "get", "https://a.test/")
+                .unwrap()
+                .status,
+            200
+        );
+        assert_eq!(
+            fixtures
+                .take(None, "GET", "https://a.test/")
+                .unwrap()
+                .status,
+            500
+        );
+        assert!(fixtures.take(None, "GET", "https://a.test/").is_none());
+        assert!(fixtures.take(None, "POST", "https://b.test/").is_none());
+
+        let of = |request_id: &str, status| RecordedFetch {
+            request_id: Some(request_id.to_string()),
+            ..fetch("https://a.test/", status)
+        };
+        let fixtures =
+            ReplayFixtures::new([of("1", 200), of("2", 404), fetch("https://a.test/", 500)]);
+
+        // NOTE: The exchanges were recorded concurrently, so the second one
+        // fetched first.
+        assert_eq!(
+            fixtures
+                .take(Some("2"), "GET", "https://a.test/")
+                .unwrap()
+                .status,
+            404
+        );
+        assert_eq!(
+            fixtures
+                .take(Some("2"), "GET", "https://a.test/")
+                .unwrap()
+                .status,
+            500
+        );
+        assert_eq!(
+            fixtures
+                .take(Some("1"), "GET", "https://a.test/")
+                .unwrap()
+                .status,
+            200
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recorder_redacts_sensitive_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let headers = [
+            ("Authorization", b"Bearer secret".as_slice()),
+            ("content-type", b"text/plain".as_slice()),
+        ];
+
+        let recorder = Recorder::open(&dir.path().join("a.jsonl"), Default::default()).unwrap();
+
+        assert_eq!(
+            recorder.headers(headers),
+            vec![
+                ("Authorization".to_string(), "[redacted]".to_string()),
+                ("content-type".to_string(), "text/plain".to_string()),
+            ]
+        );
+
+        let recorder = Recorder::open(
+            &dir.path().join("b.jsonl"),
+            RecordingOptions {
+                record_sensitive_headers: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(recorder.headers(headers)[0].1, "Bearer secret");
+    }
+
+    #[test]
+    fn test_replay_clock() {
+        let clock = ReplayClock::new(1_000);
+
+        clock.set(1_500);
+        assert_eq!(clock.now_ms(), 1_500);
+        assert_eq!(clock.monotonic_ms(), 500);
+
+        // NOTE: Requests recorded out of order must not move it backwards
+        // past the start of the replay.
+        clock.set(10);
+        assert_eq!(clock.monotonic_ms(), 0);
+    }
+
+    #[test]
+    fn test_recording_entry_format() {
+        let line = r#"{"type":"fetch","servicePath":"./hello","method":"GET","url":"https://a.test/","status":200,"headers":[["etag","1"]],"body":"aGk="}"#;
+
+        assert_eq!(
+            deno_core::serde_json::from_str::<RecordingEntry>(line).unwrap(),
+            RecordingEntry::Fetch(RecordedFetch {
+                headers: vec![("etag".to_string(), "1".to_string())],
+                body: "aGk=".to_string(),
+                ..fetch("https://a.test/", 200)
+            })
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! What the server knows about a request, forwarded to the workers as a whole
+//! in a single header rather than as a handful of ad-hoc ones.
+//!
+//! The server always drops the header a client sent and sets its own, signed
+//! with a key that never leaves the process, so a worker can trust what it
+//! reads from it: a header forged by a client or by the JS of a worker reads as
+//! if there were none. It is read by user workers through
+//! `EdgeRuntime.requestMeta(request)`.
+//!
+//! The part of it that identifies the request is also set as the context the
+//! handler of a user worker runs in, so that the libraries it uses can read it
+//! through `EdgeRuntime.requestContext.getStore()` without being handed the
+//! request.
+
+use std::net::IpAddr;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use deno_core::op2;
+use deno_core::serde_json;
+use once_cell::sync::Lazy;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use serde::{Deserialize, Serialize};
+
+// Keep in sync with `REQUEST_META_HEADER` in `bootstrap.js`.
+pub static REQUEST_META_HEADER: &str = "x-edge-runtime-meta";
+
+static SIGNING_KEY: Lazy<hmac::Key> = Lazy::new(|| {
+    hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+        .expect("failed to generate the signing key of request meta")
+});
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestMeta {
+    /// Identifies the request across the workers it goes through.
+    pub request_id: String,
+    /// The IP of the client, as resolved by the server.
+    pub client_ip: Option<IpAddr>,
+    /// Set if the request came in through the TLS port.
+    pub tls: Option<TlsMeta>,
+    /// The tenant of the user worker the request is handed to.
+    pub tenant: Option<String>,
+    /// The deadline of the request, in milliseconds since the unix epoch.
+    pub deadline_ms: Option<u64>,
+    /// Set if the request delivers a task of the task queue.
+    #[serde(default)]
+    pub task: Option<TaskMeta>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskMeta {
+    pub id: String,
+    /// The attempt this delivery is, starting at 1.
+    pub attempt: u32,
+    pub max_attempts: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsMeta {
+    /// The negotiated protocol version, e.g. `TLSv1.3`.
+    pub version: String,
+    /// Whether the client presented a certificate that was verified against
+    /// the configured client CA.
+    pub verified_client_cert: bool,
+}
+
+/// What a request runs with, as seen by the code it runs.
+///
+/// NOTE: The IP of the client and the TLS details are left out, as this is
+/// readable by any library the worker uses, unlike the request itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestContext {
+    pub request_id: String,
+    pub tenant: Option<String>,
+    pub deadline_ms: Option<u64>,
+}
+
+impl From<RequestMeta> for RequestContext {
+    fn from(meta: RequestMeta) -> Self {
+        Self {
+            request_id: meta.request_id,
+            tenant: meta.tenant,
+            deadline_ms: meta.deadline_ms,
+        }
+    }
+}
+
+impl RequestMeta {
+    /// Encodes the metadata as a header value, i.e. as base64 of its JSON
+    /// followed by its signature.
+    pub fn encode(&self) -> String {
+        let payload =
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("request meta is serializable"));
+        let tag = hmac::sign(&SIGNING_KEY, payload.as_bytes());
+
+        format!("{payload}.{}", URL_SAFE_NO_PAD.encode(tag.as_ref()))
+    }
+
+    /// Decodes a header value, returning `None` if it is malformed or was not
+    /// signed by this process.
+    pub fn decode(value: &[u8]) -> Option<Self> {
+        let value = std::str::from_utf8(value).ok()?;
+        let (payload, tag) = value.split_once('.')?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+
+        hmac::verify(&SIGNING_KEY, payload.as_bytes(), &tag).ok()?;
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+}
+
+#[op2]
+#[serde]
+pub fn op_request_meta(#[string] value: &str) -> Option<RequestMeta> {
+    RequestMeta::decode(value.as_bytes())
+}
+
+#[op2]
+#[serde]
+pub fn op_request_context(#[string] value: &str) -> Option<RequestContext> {
+    RequestMeta::decode(value.as_bytes()).map(RequestContext::from)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv6Addr};
+
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use deno_core::serde_json;
+
+    use super::{RequestContext, RequestMeta, TlsMeta};
+
+    #[test]
+    fn test_request_meta_round_trip() {
+        let meta = RequestMeta {
+            request_id: "5f0e3c43-2a4e-4c8e-9d8e-6b1f0b1c2d3e".to_string(),
+            client_ip: Some(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+            tls: Some(TlsMeta {
+                version: "TLSv1.3".to_string(),
+                verified_client_cert: true,
+            }),
+            tenant: Some("acme".to_string()),
+            deadline_ms: Some(1_700_000_000_000),
+            task: None,
+        };
+
+        let value = meta.encode();
+
+        assert!(value
+            .bytes()
+            .all(|it| it.is_ascii_alphanumeric() || matches!(it, b'-' | b'_' | b'.')));
+        assert_eq!(RequestMeta::decode(value.as_bytes()), Some(meta.clone()));
+        assert_eq!(
+            RequestContext::from(meta),
+            RequestContext {
+                request_id: "5f0e3c43-2a4e-4c8e-9d8e-6b1f0b1c2d3e".to_string(),
+                tenant: Some("acme".to_string()),
+                deadline_ms: Some(1_700_000_000_000),
+            }
+        );
+
+        assert_eq!(RequestMeta::decode(b"not meta"), None);
+        assert_eq!(RequestMeta::decode(b"e30"), None);
+    }
+
+    #[test]
+    fn test_request_meta_rejects_forged_values() {
+        let meta = RequestMeta {
+            request_id: "5f0e3c43-2a4e-4c8e-9d8e-6b1f0b1c2d3e".to_string(),
+            ..Default::default()
+        };
+
+        let value = meta.encode();
+        let (_, tag) = value.split_once('.').unwrap();
+        let forged = RequestMeta {
+            tenant: Some("acme".to_string()),
+            ..meta
+        };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+
+        assert_eq!(
+            RequestMeta::decode(format!("{payload}.{tag}").as_bytes()),
+            None
+        );
+        assert_eq!(RequestMeta::decode(payload.as_bytes()), None);
+    }
+}
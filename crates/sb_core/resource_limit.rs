@@ -0,0 +1,191 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use deno_core::error::{custom_error, AnyError};
+use deno_core::OpState;
+
+/// Names of the resources that stand for a WebSocket connection of a worker,
+/// from the moment it starts to connect.
+static WEBSOCKET_RESOURCE_NAMES: &[&str] = &["webSocketCancel", "serverWebSocket"];
+
+/// Limits the resources (sockets, files, ...) and the WebSocket connections a
+/// worker may hold open at once.
+///
+/// The limits are enforced by the permission checks of the APIs that open
+/// resources, each of which takes a slot. The slots are synced with the
+/// resource table of the worker on every turn of its event loop, so that
+/// closed resources give theirs back while a burst of opens within one turn
+/// can't overshoot.
+#[derive(Debug, Clone)]
+pub struct ResourceLimit(Rc<ResourceLimitInner>);
+
+#[derive(Debug)]
+struct ResourceLimitInner {
+    max_resources: Option<usize>,
+    max_websockets: Option<usize>,
+    resources: Cell<usize>,
+    websockets: Cell<usize>,
+    /// URLs of the WebSockets that took a slot in the current turn.
+    ///
+    /// NOTE: Deno checks the permission of a WebSocket twice, in its
+    /// constructor and again once it connects, and panics if the latter
+    /// fails. The second check finds its URL here and takes no other slot.
+    pending_websockets: RefCell<Vec<String>>,
+}
+
+impl ResourceLimit {
+    pub fn new(max_resources: Option<usize>, max_websockets: Option<usize>) -> Self {
+        Self(Rc::new(ResourceLimitInner {
+            max_resources,
+            max_websockets,
+            resources: Cell::new(0),
+            websockets: Cell::new(0),
+            pending_websockets: RefCell::default(),
+        }))
+    }
+
+    pub fn max_resources(&self) -> Option<usize> {
+        self.0.max_resources
+    }
+
+    /// Takes a slot for a new resource.
+    pub fn take(&self) -> Result<(), AnyError> {
+        take_slot(&self.0.resources, self.0.max_resources, "open resources")
+    }
+
+    /// Takes a slot for a new WebSocket connection to the given URL, which
+    /// also counts as a resource.
+    pub fn take_websocket(&self, url: &str) -> Result<(), AnyError> {
+        let mut pending = self.0.pending_websockets.borrow_mut();
+
+        if let Some(idx) = pending.iter().position(|it| it == url) {
+            pending.swap_remove(idx);
+            return Ok(());
+        }
+
+        take_slot(
+            &self.0.websockets,
+            self.0.max_websockets,
+            "open websocket connections",
+        )?;
+
+        if let Err(err) = self.take() {
+            self.0.websockets.set(self.0.websockets.get() - 1);
+            return Err(err);
+        }
+
+        pending.push(url.to_string());
+        Ok(())
+    }
+
+    /// Syncs the slots with the resources the worker actually holds. Called on
+    /// every turn of its event loop.
+    pub fn sync(&self, state: &OpState) {
+        let mut resources = 0;
+        let mut websockets = 0;
+
+        for (_, name) in state.resource_table.names() {
+            resources += 1;
+
+            if WEBSOCKET_RESOURCE_NAMES.contains(&name.as_ref()) {
+                websockets += 1;
+            }
+        }
+
+        self.0.resources.set(resources);
+        self.0.websockets.set(websockets);
+        self.0.pending_websockets.borrow_mut().clear();
+    }
+}
+
+fn take_slot(taken: &Cell<usize>, max: Option<usize>, what: &str) -> Result<(), AnyError> {
+    if let Some(limit) = max {
+        if taken.get() >= limit {
+            return Err(custom_error(
+                "ResourceLimitExceeded",
+                format!("too many {what} (limit: {limit})"),
+            ));
+        }
+    }
+
+    taken.set(taken.get() + 1);
+    Ok(())
+}
+
+/// Returns the number of open resources of a worker, grouped by their name.
+pub fn count_resources(state: &OpState) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+
+    for (_, name) in state.resource_table.names() {
+        *counts.entry(name.into_owned()).or_default() += 1;
+    }
+
+    counts
+}
+
+#[cfg(test)]
+mod test {
+    use deno_core::error::get_custom_error_class;
+    use deno_core::{OpState, Resource};
+
+    use super::ResourceLimit;
+
+    struct DummyResource(&'static str);
+
+    impl Resource for DummyResource {
+        fn name(&self) -> std::borrow::Cow<str> {
+            self.0.into()
+        }
+    }
+
+    #[test]
+    fn test_resource_limit() {
+        let mut state = OpState::new(None);
+        let limit = ResourceLimit::new(Some(2), None);
+
+        assert!(limit.take().is_ok());
+        assert!(limit.take().is_ok());
+
+        let err = limit.take().unwrap_err();
+
+        assert_eq!(get_custom_error_class(&err), Some("ResourceLimitExceeded"));
+
+        // Only one of the slots taken in the turn ended up as a resource.
+        let rid = state.resource_table.add(DummyResource("tcpStream"));
+
+        limit.sync(&state);
+
+        assert!(limit.take().is_ok());
+        assert!(limit.take().is_err());
+
+        state.resource_table.close(rid).unwrap();
+        limit.sync(&state);
+
+        assert!(limit.take().is_ok());
+        assert!(limit.take().is_ok());
+    }
+
+    #[test]
+    fn test_websocket_limit() {
+        let mut state = OpState::new(None);
+        let limit = ResourceLimit::new(None, Some(1));
+
+        // The constructor and the connection check the same WebSocket.
+        assert!(limit.take_websocket("wss://a.example.com").is_ok());
+        assert!(limit.take_websocket("wss://a.example.com").is_ok());
+        assert!(limit.take_websocket("wss://a.example.com").is_err());
+        assert!(limit.take_websocket("wss://b.example.com").is_err());
+
+        let rid = state.resource_table.add(DummyResource("serverWebSocket"));
+
+        limit.sync(&state);
+
+        assert!(limit.take_websocket("wss://b.example.com").is_err());
+
+        state.resource_table.close(rid).unwrap();
+        limit.sync(&state);
+
+        assert!(limit.take_websocket("wss://b.example.com").is_ok());
+    }
+}
@@ -0,0 +1,643 @@
+//! Sends the transactional email of the user workers over SMTP, so that
+//! functions need neither raw socket access nor an SMTP library of their own.
+//!
+//! The messages are built and sent with `lettre`, over connections the
+//! runtime opens itself: the server is resolved through the DNS cache, and
+//! the connection secured with the root certificates of the worker. The
+//! connections are kept open for a while to be reused by the next messages
+//! sent to the same server.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use deno_core::error::{custom_error, type_error, AnyError};
+use deno_core::{op2, OpState};
+use deno_tls::rustls::crypto::ring;
+use deno_tls::rustls::pki_types::ServerName;
+use deno_tls::rustls::{ClientConfig, RootCertStore};
+use lettre::message::header::ContentType;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::{AsyncSmtpConnection, AsyncTokioStream};
+use lettre::transport::smtp::commands::{Data, Mail, Rcpt, Rset, Starttls};
+use lettre::transport::smtp::extension::ClientId;
+use lettre::Message;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use uuid::Uuid;
+
+use crate::dns_cache::get_dns_cache;
+use crate::outbound_cancel::OutboundCancelToken;
+use crate::permissions::Permissions;
+
+static API_NAME: &str = "EdgeRuntime.smtp.send()";
+
+/// How long connecting to the server and sending a message may take.
+static SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a connection is kept open for reuse. Servers usually close idle
+/// connections after a minute or more.
+static IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+static MAX_IDLE_PER_SERVER: usize = 2;
+static MAX_RECIPIENTS: usize = 50;
+
+/// The name the runtime introduces itself with in `EHLO`.
+static CLIENT_NAME: &str = "localhost";
+
+/// How the connection to the server is secured. Plaintext connections are
+/// not supported since they would expose the credentials.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum SmtpTls {
+    /// Upgrades a plaintext connection with `STARTTLS`, usually on port 587.
+    #[default]
+    StartTls,
+    /// Connects with TLS from the start, usually on port 465.
+    Implicit,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SmtpTransport {
+    pub host: String,
+    /// Defaults to the usual port of the TLS mode.
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl SmtpTransport {
+    fn port(&self) -> u16 {
+        self.port.unwrap_or(match self.tls {
+            SmtpTls::StartTls => 587,
+            SmtpTls::Implicit => 465,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SmtpMessage {
+    /// An address, optionally with a display name, e.g. `Acme <no-reply@acme.com>`.
+    pub from: String,
+    #[serde(default)]
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    /// Recipients left out of the headers of the message.
+    #[serde(default)]
+    pub bcc: Vec<String>,
+    pub reply_to: Option<String>,
+    #[serde(default)]
+    pub subject: String,
+    pub text: Option<String>,
+    pub html: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SmtpSendResult {
+    pub message_id: String,
+    /// The recipients the server accepted the message for.
+    pub accepted: Vec<String>,
+    /// The recipients the server refused, the message being sent to the others.
+    pub rejected: Vec<String>,
+}
+
+struct IdleConnection {
+    conn: AsyncSmtpConnection,
+    idle_since: Instant,
+}
+
+/// The email of a worker: its send quota and its connections left open for
+/// reuse.
+pub struct SmtpClient {
+    tls_config: Arc<ClientConfig>,
+    /// Maximum number of messages sent in a minute. `None` means unlimited.
+    max_per_minute: Option<usize>,
+    sent_at: VecDeque<Instant>,
+    idle: HashMap<SmtpTransport, Vec<IdleConnection>>,
+}
+
+impl SmtpClient {
+    pub fn new(
+        root_cert_store: RootCertStore,
+        max_per_minute: Option<usize>,
+    ) -> Result<Self, AnyError> {
+        let tls_config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(root_cert_store)
+            .with_no_client_auth();
+
+        Ok(Self {
+            tls_config: Arc::new(tls_config),
+            max_per_minute,
+            sent_at: VecDeque::new(),
+            idle: HashMap::new(),
+        })
+    }
+
+    /// Takes a message from the quota of the worker.
+    fn acquire(&mut self, now: Instant) -> Result<(), AnyError> {
+        let Some(limit) = self.max_per_minute else {
+            return Ok(());
+        };
+
+        while self
+            .sent_at
+            .front()
+            .is_some_and(|it| now.duration_since(*it) >= Duration::from_secs(60))
+        {
+            self.sent_at.pop_front();
+        }
+
+        if self.sent_at.len() >= limit {
+            return Err(custom_error(
+                "ResourceLimitExceeded",
+                format!("too many emails sent in the last minute (limit: {limit})"),
+            ));
+        }
+
+        self.sent_at.push_back(now);
+        Ok(())
+    }
+
+    fn take_idle(&mut self, transport: &SmtpTransport) -> Option<AsyncSmtpConnection> {
+        let conns = self.idle.get_mut(transport)?;
+
+        conns.retain(|it| it.idle_since.elapsed() < IDLE_TIMEOUT);
+        conns.pop().map(|it| it.conn)
+    }
+
+    fn put_idle(&mut self, transport: SmtpTransport, conn: AsyncSmtpConnection) {
+        let conns = self.idle.entry(transport).or_default();
+
+        if conns.len() < MAX_IDLE_PER_SERVER && !conn.has_broken() {
+            conns.push(IdleConnection {
+                conn,
+                idle_since: Instant::now(),
+            });
+        }
+    }
+}
+
+trait SmtpIo: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T> SmtpIo for T where T: AsyncRead + AsyncWrite + Unpin {}
+
+enum StreamState {
+    Tcp(TcpStream),
+    Handshake(tokio_rustls::Connect<TcpStream>),
+    Tls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+    Failed,
+}
+
+/// The connection `lettre` talks to the server over.
+///
+/// NOTE: `lettre` would secure the connection with root certificates of its
+/// own, so the stream is upgraded here instead, with the ones of the worker:
+/// from the start for the implicit TLS, or once the server accepted
+/// `STARTTLS`, before the next command is written.
+struct SmtpStream {
+    state: StreamState,
+    upgrade: Arc<AtomicBool>,
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+    peer_addr: SocketAddr,
+}
+
+impl SmtpStream {
+    fn poll_upgrade(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match &mut self.state {
+                StreamState::Tcp(_) if self.upgrade.load(Ordering::Acquire) => {
+                    let StreamState::Tcp(tcp) =
+                        std::mem::replace(&mut self.state, StreamState::Failed)
+                    else {
+                        unreachable!();
+                    };
+
+                    self.state = StreamState::Handshake(
+                        self.connector.connect(self.server_name.clone(), tcp),
+                    );
+                }
+
+                StreamState::Handshake(connect) => match ready!(Pin::new(connect).poll(cx)) {
+                    Ok(tls) => self.state = StreamState::Tls(Box::new(tls)),
+                    Err(err) => {
+                        self.state = StreamState::Failed;
+                        return Poll::Ready(Err(err));
+                    }
+                },
+
+                StreamState::Failed => {
+                    return Poll::Ready(Err(io::ErrorKind::NotConnected.into()));
+                }
+
+                _ => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+
+    fn poll_io<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        f: impl FnOnce(Pin<&mut dyn SmtpIo>, &mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        ready!(self.poll_upgrade(cx))?;
+
+        match &mut self.state {
+            StreamState::Tcp(it) => f(Pin::new(it), cx),
+            StreamState::Tls(it) => f(Pin::new(it.as_mut()), cx),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl AsyncRead for SmtpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().poll_io(cx, |io, cx| io.poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for SmtpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_io(cx, |io, cx| io.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_io(cx, |io, cx| io.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_io(cx, |io, cx| io.poll_shutdown(cx))
+    }
+}
+
+impl fmt::Debug for SmtpStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmtpStream")
+            .field("peer_addr", &self.peer_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncTokioStream for SmtpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.peer_addr)
+    }
+}
+
+fn smtp_error(err: lettre::transport::smtp::Error) -> AnyError {
+    custom_error("SmtpError", err.to_string())
+}
+
+/// Connects to the server, resolving it through the DNS cache when it is
+/// enabled, as `fetch` does.
+async fn connect(
+    transport: &SmtpTransport,
+    tls_config: Arc<ClientConfig>,
+) -> Result<AsyncSmtpConnection, AnyError> {
+    let server_name = ServerName::try_from(transport.host.clone())
+        .map_err(|_| type_error(format!("invalid SMTP host: {}", transport.host)))?;
+    let port = transport.port();
+    let tcp = match get_dns_cache() {
+        Some(cache) => {
+            let addrs = cache
+                .lookup(transport.host.clone())
+                .await?
+                .iter()
+                .map(|it| SocketAddr::new(*it, port))
+                .collect::<Vec<_>>();
+
+            TcpStream::connect(addrs.as_slice()).await?
+        }
+
+        None => TcpStream::connect((transport.host.as_str(), port)).await?,
+    };
+
+    let upgrade = Arc::new(AtomicBool::new(transport.tls == SmtpTls::Implicit));
+    let stream = SmtpStream {
+        peer_addr: tcp.peer_addr()?,
+        state: StreamState::Tcp(tcp),
+        upgrade: upgrade.clone(),
+        connector: TlsConnector::from(tls_config),
+        server_name,
+    };
+
+    let hello_name = ClientId::Domain(CLIENT_NAME.to_string());
+    let mut conn = AsyncSmtpConnection::connect_with_transport(Box::new(stream), &hello_name)
+        .await
+        .map_err(smtp_error)?;
+
+    if transport.tls == SmtpTls::StartTls {
+        if !conn.can_starttls() {
+            return Err(custom_error(
+                "SmtpError",
+                "the server does not support STARTTLS",
+            ));
+        }
+
+        conn.command(Starttls).await.map_err(smtp_error)?;
+        upgrade.store(true, Ordering::Release);
+        conn.ehlo(&hello_name).await.map_err(smtp_error)?;
+    }
+
+    if let Some(username) = transport.username.clone() {
+        let credentials =
+            Credentials::new(username, transport.password.clone().unwrap_or_default());
+
+        conn.auth(&[Mechanism::Plain, Mechanism::Login], &credentials)
+            .await
+            .map_err(smtp_error)?;
+    }
+
+    Ok(conn)
+}
+
+/// Sends a message over the connection, returning the recipients that were
+/// accepted and the ones that were not.
+///
+/// NOTE: `lettre` gives up on the message as soon as a recipient is refused,
+/// so the transaction is driven here to send it to the others.
+async fn send_message(
+    conn: &mut AsyncSmtpConnection,
+    message: &Message,
+) -> Result<(Vec<String>, Vec<String>), AnyError> {
+    let envelope = message.envelope();
+
+    conn.command(Mail::new(envelope.from().cloned(), vec![]))
+        .await
+        .map_err(smtp_error)?;
+
+    let (mut accepted, mut rejected) = (vec![], vec![]);
+
+    for rcpt in envelope.to() {
+        match conn.command(Rcpt::new(rcpt.clone(), vec![])).await {
+            Ok(_) => accepted.push(rcpt.to_string()),
+            Err(err) if err.is_permanent() => rejected.push(rcpt.to_string()),
+            Err(err) => return Err(smtp_error(err)),
+        }
+    }
+
+    if accepted.is_empty() {
+        conn.command(Rset).await.map_err(smtp_error)?;
+        return Err(custom_error(
+            "SmtpError",
+            format!(
+                "the server refused every recipient: {}",
+                rejected.join(", ")
+            ),
+        ));
+    }
+
+    conn.command(Data).await.map_err(smtp_error)?;
+    conn.message(&message.formatted())
+        .await
+        .map_err(smtp_error)?;
+
+    Ok((accepted, rejected))
+}
+
+fn parse_mailbox(value: &str) -> Result<Mailbox, AnyError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| type_error(format!("invalid email address: {value}")))
+}
+
+/// Builds a message. The blind copies are left out of its headers, but not
+/// of its envelope.
+fn build_message(message: &SmtpMessage, message_id: &str) -> Result<Message, AnyError> {
+    let recipients = message.to.len() + message.cc.len() + message.bcc.len();
+
+    if recipients == 0 {
+        return Err(type_error("the message has no recipient"));
+    }
+    if recipients > MAX_RECIPIENTS {
+        return Err(type_error(format!(
+            "the message has too many recipients (limit: {MAX_RECIPIENTS})"
+        )));
+    }
+
+    let mut builder = Message::builder()
+        .from(parse_mailbox(&message.from)?)
+        .subject(message.subject.as_str())
+        .message_id(Some(format!("<{message_id}>")))
+        .date_now();
+
+    for it in message.to.iter() {
+        builder = builder.to(parse_mailbox(it)?);
+    }
+    for it in message.cc.iter() {
+        builder = builder.cc(parse_mailbox(it)?);
+    }
+    for it in message.bcc.iter() {
+        builder = builder.bcc(parse_mailbox(it)?);
+    }
+    if let Some(reply_to) = message.reply_to.as_deref() {
+        builder = builder.reply_to(parse_mailbox(reply_to)?);
+    }
+
+    let message = match (message.text.clone(), message.html.clone()) {
+        (Some(text), Some(html)) => {
+            builder.multipart(MultiPart::alternative_plain_html(text, html))
+        }
+
+        (None, Some(html)) => builder.header(ContentType::TEXT_HTML).body(html),
+        (text, _) => builder
+            .header(ContentType::TEXT_PLAIN)
+            .body(text.unwrap_or_default()),
+    };
+
+    message.map_err(|err| type_error(format!("invalid message: {err}")))
+}
+
+/// Sends a message through the given server on behalf of the worker.
+///
+/// The server must be allowed by the net permissions of the worker, as for
+/// `fetch`, and the message counts towards the email quota of the worker.
+#[op2(async)]
+#[serde]
+pub async fn op_smtp_send(
+    state: Rc<RefCell<OpState>>,
+    #[serde] transport: SmtpTransport,
+    #[serde] message: SmtpMessage,
+) -> Result<SmtpSendResult, AnyError> {
+    let domain = parse_mailbox(&message.from)?.email.domain().to_string();
+    let message_id = format!("{}@{domain}", Uuid::new_v4().simple());
+    let message = build_message(&message, &message_id)?;
+
+    let (maybe_conn, tls_config, maybe_cancel) = {
+        let mut state = state.borrow_mut();
+
+        state.borrow::<Permissions>().check_outbound(
+            &transport.host,
+            transport.port(),
+            API_NAME,
+        )?;
+
+        let maybe_cancel = state
+            .try_borrow::<OutboundCancelToken>()
+            .map(|it| it.0.clone());
+
+        if maybe_cancel.as_ref().is_some_and(|it| it.is_cancelled()) {
+            return Err(custom_error("Interrupted", "the worker is terminating"));
+        }
+
+        let Some(client) = state.try_borrow_mut::<SmtpClient>() else {
+            return Err(custom_error(
+                "NotSupported",
+                "email is not available in this worker",
+            ));
+        };
+
+        client.acquire(Instant::now())?;
+
+        (
+            client.take_idle(&transport),
+            client.tls_config.clone(),
+            maybe_cancel,
+        )
+    };
+
+    let send = async {
+        let mut maybe_conn = maybe_conn;
+
+        // NOTE: A connection left idle may have been closed by the server
+        // in the meantime, in which case a new one is opened.
+        if let Some(conn) = maybe_conn.as_mut() {
+            if !conn.test_connected().await {
+                maybe_conn = None;
+            }
+        }
+
+        let mut conn = match maybe_conn {
+            Some(conn) => conn,
+            None => connect(&transport, tls_config).await?,
+        };
+
+        let (accepted, rejected) = send_message(&mut conn, &message).await?;
+
+        Ok::<_, AnyError>((conn, accepted, rejected))
+    };
+
+    let send = async {
+        tokio::time::timeout(SEND_TIMEOUT, send)
+            .await
+            .map_err(|_| custom_error("SmtpError", "timed out sending the email"))
+            .and_then(|it| it)
+    };
+
+    let (conn, accepted, rejected) = match maybe_cancel {
+        Some(cancel) => tokio::select! {
+            result = send => result?,
+            _ = cancel.cancelled() => {
+                return Err(custom_error("Interrupted", "the worker is terminating"));
+            }
+        },
+
+        None => send.await?,
+    };
+
+    if let Some(client) = state.borrow_mut().try_borrow_mut::<SmtpClient>() {
+        client.put_idle(transport, conn);
+    }
+
+    Ok(SmtpSendResult {
+        message_id,
+        accepted,
+        rejected,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{build_message, parse_mailbox, SmtpMessage};
+
+    #[test]
+    fn test_parse_mailbox() {
+        let mailbox = parse_mailbox("Acme <no-reply@acme.com>").unwrap();
+
+        assert_eq!(mailbox.name.as_deref(), Some("Acme"));
+        assert_eq!(mailbox.email.to_string(), "no-reply@acme.com");
+        assert_eq!(
+            parse_mailbox(" jane@acme.com ").unwrap().email.to_string(),
+            "jane@acme.com"
+        );
+        assert!(parse_mailbox("jane@acme.com\r\nBcc: eve@evil.com").is_err());
+        assert!(parse_mailbox("jane").is_err());
+    }
+
+    #[test]
+    fn test_build_message() {
+        let message = SmtpMessage {
+            from: "Acme <no-reply@acme.com>".to_string(),
+            to: vec!["jane@example.com".to_string()],
+            bcc: vec!["audit@acme.com".to_string()],
+            subject: "Your receipt ✓".to_string(),
+            text: Some("hello".to_string()),
+            ..Default::default()
+        };
+
+        let built = build_message(&message, "1@acme.com").unwrap();
+        let envelope = built.envelope();
+        let data = String::from_utf8(built.formatted()).unwrap();
+
+        assert_eq!(
+            envelope.from().map(ToString::to_string).as_deref(),
+            Some("no-reply@acme.com")
+        );
+        assert_eq!(
+            envelope
+                .to()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["jane@example.com", "audit@acme.com"]
+        );
+        assert!(data.contains("<no-reply@acme.com>"));
+        assert!(data.contains("jane@example.com"));
+        assert!(data.contains("Message-ID: <1@acme.com>\r\n"));
+        assert!(!data.contains("Your receipt"));
+        assert!(!data.contains("audit@acme.com"));
+
+        let message = SmtpMessage {
+            to: vec![],
+            ..message
+        };
+
+        assert!(build_message(&message, "1@acme.com").is_ok());
+        assert!(build_message(
+            &SmtpMessage {
+                from: "no-reply@acme.com".to_string(),
+                ..Default::default()
+            },
+            "1@acme.com"
+        )
+        .is_err());
+    }
+}
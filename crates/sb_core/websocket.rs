@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+use deno_core::op2;
+use deno_core::OpState;
+
+/// A WebSocket connection opened by the worker that receives nothing for this
+/// long is closed.
+#[derive(Debug, Clone, Copy)]
+pub struct WebSocketIdleTimeout(pub Duration);
+
+/// Returns the idle timeout of the WebSockets of the worker in milliseconds
+/// (`0` if disabled).
+///
+/// NOTE: The number of connections is limited by the permission checks of
+/// the worker instead (see `ResourceLimit`).
+#[op2(fast)]
+pub fn op_ws_idle_timeout_ms(state: &mut OpState) -> u32 {
+    state
+        .try_borrow::<WebSocketIdleTimeout>()
+        .map_or(0, |it| it.0.as_millis().try_into().unwrap_or(u32::MAX))
+}
@@ -1,4 +1,5 @@
-const { ops } = globalThis.__bootstrap.core;
+const core = globalThis.__bootstrap.core;
+const { ops } = core;
 
 class SupaEnv {
   setEnv(key, value) {
@@ -28,4 +29,21 @@ const SUPABASE_ENV = {
   delete: supaEnvInstance.deleteEnv,
 };
 
-export { SUPABASE_ENV };
+// Dispatches an `envchange` event on the global scope whenever the env vars
+// are updated from outside of the worker.
+async function watchEnvChanges() {
+  while (true) {
+    const promise = ops.op_env_changed();
+
+    // NOTE: This must not keep the event loop alive by itself.
+    core.unrefOpPromise(promise);
+
+    if (!(await promise)) {
+      break;
+    }
+
+    globalThis.dispatchEvent(new Event("envchange"));
+  }
+}
+
+export { SUPABASE_ENV, watchEnvChanges };
@@ -4,14 +4,64 @@ use deno_core::op2;
 use deno_core::OpState;
 use sb_core::permissions::Permissions;
 use sb_node::NODE_ENV_VAR_ALLOWLIST;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use tokio::sync::{watch, Mutex as AsyncMutex};
 
 // NOTE(Nyannyacha): This declaration does not create a unique TypeId and is therefore unsafe.
 pub type EnvVars = HashMap<String, String>;
 
+/// A read-only snapshot of the env vars of a worker that can be replaced at
+/// runtime (e.g. when secrets are rotated) without restarting the worker.
+#[derive(Debug, Clone)]
+pub struct EnvProvider(Arc<watch::Sender<Arc<EnvVars>>>);
+
+impl Default for EnvProvider {
+    fn default() -> Self {
+        Self::new(EnvVars::new())
+    }
+}
+
+impl EnvProvider {
+    pub fn new(env_vars: EnvVars) -> Self {
+        let (tx, _) = watch::channel(Arc::new(env_vars));
+        Self(Arc::new(tx))
+    }
+
+    pub fn snapshot(&self) -> Arc<EnvVars> {
+        self.0.borrow().clone()
+    }
+
+    /// Sets the env vars without notifying the worker. This is meant to be used
+    /// while the worker is being bootstrapped.
+    pub fn init(&self, env_vars: EnvVars) {
+        self.0.send_if_modified(|it| {
+            *it = Arc::new(env_vars);
+            false
+        });
+    }
+
+    /// Replaces the env vars and notifies the worker about the change.
+    pub fn update(&self, env_vars: EnvVars) {
+        self.0.send_replace(Arc::new(env_vars));
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Arc<EnvVars>> {
+        self.0.subscribe()
+    }
+}
+
 deno_core::extension!(
     sb_env,
-    ops = [op_set_env, op_env, op_get_env, op_delete_env],
+    ops = [
+        op_set_env,
+        op_env,
+        op_get_env,
+        op_delete_env,
+        op_env_changed
+    ],
     esm_entry_point = "ext:sb_env/env.js",
     esm = ["env.js"]
 );
@@ -29,8 +79,8 @@ fn op_set_env(
 #[serde]
 fn op_env(state: &mut OpState) -> Result<HashMap<String, String>, AnyError> {
     state.borrow_mut::<Permissions>().check_env_all()?;
-    let env_vars = state.borrow::<EnvVars>();
-    Ok(env_vars.clone())
+    let env_vars = state.borrow::<EnvProvider>().snapshot();
+    Ok((*env_vars).clone())
 }
 
 #[op2]
@@ -53,10 +103,74 @@ fn op_get_env(state: &mut OpState, #[string] key: String) -> Result<Option<Strin
         )));
     }
 
-    let env_vars = state.borrow::<EnvVars>();
+    let env_vars = state.borrow::<EnvProvider>().snapshot();
     let r = env_vars.get(&key).cloned();
     Ok(r)
 }
 
 #[op2(fast)]
 fn op_delete_env(_state: &mut OpState, #[string] _key: String) {}
+
+/// The receiver the env vars of the worker are watched with.
+///
+/// NOTE: It is subscribed once and kept, so that an update made while the
+/// worker is handling the previous one is seen by the next call of
+/// `op_env_changed` rather than missed.
+struct EnvChanges(Rc<AsyncMutex<watch::Receiver<Arc<EnvVars>>>>);
+
+/// Resolves to `true` once the env vars have been updated, or to `false` if
+/// they can no longer change.
+#[op2(async)]
+async fn op_env_changed(state: Rc<RefCell<OpState>>) -> bool {
+    let rx = {
+        let mut state = state.borrow_mut();
+
+        if state.try_borrow::<EnvChanges>().is_none() {
+            let rx = state.borrow::<EnvProvider>().subscribe();
+
+            state.put(EnvChanges(Rc::new(AsyncMutex::new(rx))));
+        }
+
+        state.borrow::<EnvChanges>().0.clone()
+    };
+
+    let mut rx = rx.lock().await;
+
+    rx.changed().await.is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EnvProvider, EnvVars};
+
+    fn env(pairs: &[(&str, &str)]) -> EnvVars {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_env_provider_notifies_on_update_only() {
+        let provider = EnvProvider::default();
+        let mut rx = provider.subscribe();
+
+        provider.init(env(&[("A", "1")]));
+
+        assert!(!rx.has_changed().unwrap());
+        assert_eq!(*provider.snapshot(), env(&[("A", "1")]));
+
+        // NOTE: Both updates land before the receiver is polled again, as
+        // they would while the worker handles the previous change.
+        provider.update(env(&[("A", "2")]));
+        provider.update(env(&[("A", "3")]));
+
+        assert!(rx.changed().await.is_ok());
+        assert_eq!(**rx.borrow_and_update(), env(&[("A", "3")]));
+        assert!(!rx.has_changed().unwrap());
+
+        drop(provider);
+
+        assert!(rx.changed().await.is_err());
+    }
+}
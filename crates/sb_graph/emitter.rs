@@ -364,7 +364,7 @@ impl EmitterFactory {
             let http_client_provider = self.http_client_provider();
             let blob_store = Arc::new(deno_web::BlobStore::default());
 
-            Ok(Arc::new(FileFetcher::new(
+            let mut file_fetcher = FileFetcher::new(
                 global_cache.clone(),
                 self.file_fetcher_cache_strategy
                     .clone()
@@ -372,7 +372,11 @@ impl EmitterFactory {
                 self.file_fetcher_allow_remote,
                 http_client_provider.clone(),
                 blob_store,
-            )))
+            );
+
+            file_fetcher.set_module_cache_dir(self.deno_dir.deps_folder_path());
+
+            Ok(Arc::new(file_fetcher))
         })
     }
 
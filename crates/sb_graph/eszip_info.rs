@@ -0,0 +1,261 @@
+//! Describes the content of an eszip without loading it, e.g. so that a deploy
+//! pipeline can validate a bundle, or diff it against the one it replaces,
+//! before shipping it.
+
+use anyhow::{anyhow, Context};
+use eszip::v2::{EszipV2Module, EszipV2SourceSlot};
+use eszip::ModuleKind;
+use sb_eszip_shared::{
+    AsyncEszipDataRead, METADATA_ESZIP_KEY, SOURCE_CODE_ESZIP_KEY, STATIC_FILES_ESZIP_KEY,
+    SUPABASE_ESZIP_VERSION_KEY, VFS_ESZIP_KEY,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{eszip_migrate, payload_to_eszip, EszipMetadata, EszipPayloadKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EszipModuleKind {
+    JavaScript,
+    Json,
+    Jsonc,
+    OpaqueData,
+}
+
+impl From<ModuleKind> for EszipModuleKind {
+    fn from(value: ModuleKind) -> Self {
+        match value {
+            ModuleKind::JavaScript => Self::JavaScript,
+            ModuleKind::Json => Self::Json,
+            ModuleKind::Jsonc => Self::Jsonc,
+            ModuleKind::OpaqueData => Self::OpaqueData,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EszipModuleInfo {
+    pub specifier: String,
+    pub kind: EszipModuleKind,
+    pub source_size: usize,
+    pub source_map_size: usize,
+    /// The hex SHA-256 of the source, to tell the modules that changed between
+    /// two eszips.
+    pub source_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EszipRedirectInfo {
+    pub specifier: String,
+    pub target: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EszipInfo {
+    /// The size of the eszip, in bytes, or 0 if it was given already parsed.
+    pub size: usize,
+    /// The version of the eszip format, before it is migrated. `None` for the
+    /// eszips made before the format was versioned.
+    pub version: Option<String>,
+    /// The checksum of the sources, e.g. `Sha256`.
+    pub checksum: Option<String>,
+    pub entrypoint: Option<String>,
+    /// The metadata written by the `bundle` command, if any.
+    pub metadata: Option<EszipMetadata>,
+    /// The modules of the eszip, sorted by specifier. The entries the runtime
+    /// keeps for itself (e.g. the VFS) are not listed.
+    pub modules: Vec<EszipModuleInfo>,
+    pub redirects: Vec<EszipRedirectInfo>,
+    /// The IDs of the npm packages, e.g. `@supabase/supabase-js@2.42.0`.
+    pub npm_packages: Vec<String>,
+    /// The specifiers of the static files, e.g. `static:data/a.json`.
+    pub static_files: Vec<String>,
+    /// The size of the VFS of the npm packages, in bytes.
+    pub vfs_size: usize,
+}
+
+impl EszipInfo {
+    /// The size of the sources of the modules, in bytes.
+    pub fn total_source_size(&self) -> usize {
+        self.modules.iter().map(|it| it.source_size).sum()
+    }
+}
+
+static INTERNAL_ESZIP_KEYS: [&str; 5] = [
+    SUPABASE_ESZIP_VERSION_KEY,
+    VFS_ESZIP_KEY,
+    SOURCE_CODE_ESZIP_KEY,
+    STATIC_FILES_ESZIP_KEY,
+    METADATA_ESZIP_KEY,
+];
+
+pub async fn inspect_eszip(payload: EszipPayloadKind) -> Result<EszipInfo, anyhow::Error> {
+    let size = match &payload {
+        EszipPayloadKind::JsBufferKind(it) => it.len(),
+        EszipPayloadKind::VecKind(it) => it.len(),
+        EszipPayloadKind::Eszip(_) => 0,
+    };
+
+    let eszip = payload_to_eszip(payload).await?;
+    let version = match eszip.ensure_module(SUPABASE_ESZIP_VERSION_KEY) {
+        Some(module) => module
+            .source()
+            .await
+            .map(|it| String::from_utf8_lossy(it.as_ref()).into_owned()),
+        None => None,
+    };
+
+    let checksum = eszip.options.checksum.map(|it| format!("{it:?}"));
+    let mut eszip = eszip_migrate::try_migrate_if_needed(eszip)
+        .await
+        .map_err(|_| anyhow!("eszip migration failed"))?;
+
+    eszip.ensure_read_all().await?;
+
+    let metadata = eszip.ensure_metadata().await?;
+    let mut static_files = vec![];
+
+    if let Some(module) = eszip.ensure_module(STATIC_FILES_ESZIP_KEY) {
+        if let Some(data) = module.source().await {
+            let archived = rkyv::check_archived_root::<Vec<String>>(&data)
+                .map_err(|err| anyhow!("{err}"))
+                .context("cannot deserialize specifiers for static files")?;
+
+            static_files.extend(archived.iter().map(|it| it.as_str().to_string()));
+        }
+    }
+
+    let mut modules = vec![];
+    let mut redirects = vec![];
+    let mut vfs_size = 0;
+
+    for (specifier, module) in eszip.modules.0.lock().unwrap().iter() {
+        match module {
+            EszipV2Module::Module {
+                kind,
+                source,
+                source_map,
+            } => {
+                if specifier == VFS_ESZIP_KEY {
+                    vfs_size = slot_data(source).map_or(0, <[u8]>::len);
+                }
+                if INTERNAL_ESZIP_KEYS.contains(&specifier.as_str()) {
+                    continue;
+                }
+
+                let source = slot_data(source).unwrap_or_default();
+
+                modules.push(EszipModuleInfo {
+                    specifier: specifier.clone(),
+                    kind: (*kind).into(),
+                    source_size: source.len(),
+                    source_map_size: slot_data(source_map).map_or(0, <[u8]>::len),
+                    source_hash: faster_hex::hex_string(&Sha256::digest(source)),
+                });
+            }
+
+            EszipV2Module::Redirect { target } => redirects.push(EszipRedirectInfo {
+                specifier: specifier.clone(),
+                target: target.clone(),
+            }),
+        }
+    }
+
+    modules.sort_by(|a, b| a.specifier.cmp(&b.specifier));
+    redirects.sort_by(|a, b| a.specifier.cmp(&b.specifier));
+
+    let mut npm_packages = eszip
+        .take_npm_snapshot()
+        .map(|it| {
+            it.as_serialized()
+                .packages
+                .iter()
+                .map(|pkg| pkg.id.as_serialized())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    npm_packages.sort();
+
+    Ok(EszipInfo {
+        size,
+        version,
+        checksum,
+        entrypoint: metadata.as_ref().and_then(|it| it.entrypoint.clone()),
+        metadata,
+        modules,
+        redirects,
+        npm_packages,
+        static_files,
+        vfs_size,
+    })
+}
+
+/// Returns `None` if the data of the slot was not read, or was taken.
+fn slot_data(slot: &EszipV2SourceSlot) -> Option<&[u8]> {
+    match slot {
+        EszipV2SourceSlot::Ready(it) => Some(it.as_ref()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use tokio::fs;
+
+    use crate::EszipPayloadKind;
+
+    use super::{inspect_eszip, EszipModuleKind};
+
+    const MIGRATE_TEST_DIR: &str = "../base/test_cases/eszip-migration";
+
+    async fn read_eszip(name: &str) -> EszipPayloadKind {
+        EszipPayloadKind::VecKind(
+            fs::read(PathBuf::from(format!(
+                "{}/npm-supabase-js/{}",
+                MIGRATE_TEST_DIR, name
+            )))
+            .await
+            .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_inspect_eszip() {
+        let info = inspect_eszip(read_eszip("v1_1_xx_hash3.eszip").await)
+            .await
+            .unwrap();
+
+        assert_eq!(info.version.as_deref(), Some("1.1"));
+        assert!(info.size > 0);
+        assert!(info.vfs_size > 0);
+        assert!(info
+            .modules
+            .iter()
+            .any(|it| it.specifier.ends_with("/index.ts")
+                && it.kind == EszipModuleKind::JavaScript
+                && it.source_size > 0));
+        assert!(info
+            .npm_packages
+            .iter()
+            .any(|it| it.starts_with("@supabase/supabase-js@2.42.0")));
+        assert!(info
+            .modules
+            .windows(2)
+            .all(|it| it[0].specifier < it[1].specifier));
+    }
+
+    #[tokio::test]
+    async fn test_inspect_eszip_v0() {
+        let info = inspect_eszip(read_eszip("v0.eszip").await).await.unwrap();
+
+        assert_eq!(info.version, None);
+        assert!(!info.modules.is_empty());
+    }
+}
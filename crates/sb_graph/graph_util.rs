@@ -1,5 +1,6 @@
 use crate::emitter::EmitterFactory;
 use crate::graph_fs::DenoGraphFsAdapter;
+use crate::import_map::{get_remote_specifiers, load_import_map};
 use crate::jsr::CliJsrUrlProvider;
 use crate::resolver::CliGraphResolver;
 use anyhow::Context;
@@ -16,10 +17,13 @@ use deno_lockfile::Lockfile;
 use deno_semver::package::{PackageNv, PackageReq};
 use eszip::EszipV2;
 use import_map::ImportMapError;
+use log::warn;
 use npm_cache::file_fetcher::File;
 use sb_core::cache::parsed_source::ParsedSourceCache;
+use sb_core::cache::CacheSetting;
 use sb_core::util::errors::get_error_class_name;
 use sb_npm::CliNpmResolver;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -396,13 +400,46 @@ pub async fn create_eszip_from_graph_raw(
     })
 }
 
+/// Where the modules of a worker that are given in memory rather than read
+/// from the file system live, the main module being `index.ts`.
+pub static MEMORY_MODULE_ROOT: &str = "file:///src/";
+
+/// Makes the given modules available to the module graph without touching the
+/// file system. Relative specifiers are resolved against
+/// [`MEMORY_MODULE_ROOT`], so the main module given in memory can import them
+/// as `./<specifier>`.
+pub fn insert_memory_modules(
+    emitter_factory: &EmitterFactory,
+    modules: &HashMap<String, FastString>,
+) -> Result<(), AnyError> {
+    let root = ModuleSpecifier::parse(MEMORY_MODULE_ROOT).unwrap();
+    let file_fetcher = emitter_factory.file_fetcher()?;
+
+    for (specifier, code) in modules {
+        let specifier = root
+            .join(specifier)
+            .with_context(|| format!("invalid in-memory module specifier: {specifier}"))?;
+
+        file_fetcher.insert_memory_files(File {
+            specifier,
+            maybe_headers: None,
+            source: code.as_bytes().into(),
+        });
+    }
+
+    Ok(())
+}
+
 pub async fn create_graph(
     file: PathBuf,
     emitter_factory: Arc<EmitterFactory>,
     maybe_code: &Option<FastString>,
 ) -> Result<ModuleGraph, AnyError> {
     let module_specifier = if let Some(code) = maybe_code {
-        let specifier = ModuleSpecifier::parse("file:///src/index.ts").unwrap();
+        let specifier = ModuleSpecifier::parse(MEMORY_MODULE_ROOT)
+            .unwrap()
+            .join("index.ts")
+            .unwrap();
 
         emitter_factory.file_fetcher()?.insert_memory_files(File {
             specifier: specifier.clone(),
@@ -427,6 +464,62 @@ pub async fn create_graph(
         .context("failed to create the graph")
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrefetchSummary {
+    /// Remote modules mapped by the import map.
+    pub roots: usize,
+    /// Modules in the cache once the roots and their dependencies have been
+    /// fetched.
+    pub modules: usize,
+    /// Roots that could not be fetched.
+    pub failed: usize,
+}
+
+/// Fetches the remote modules mapped by the import map, along with their
+/// dependencies, into the module cache, so that workers importing them don't
+/// have to wait for the network on boot.
+///
+/// A root that fails to be fetched is logged and skipped.
+pub async fn prefetch_import_map(import_map_path: String) -> Result<PrefetchSummary, AnyError> {
+    let Some(import_map) = load_import_map(Some(import_map_path.clone()))
+        .with_context(|| format!("failed to load import map: {import_map_path}"))?
+    else {
+        return Ok(PrefetchSummary::default());
+    };
+
+    let roots = get_remote_specifiers(&import_map);
+    let mut emitter_factory = EmitterFactory::new();
+
+    emitter_factory.set_file_fetcher_cache_strategy(CacheSetting::Use);
+    emitter_factory.set_import_map(Some(import_map));
+
+    let builder = ModuleGraphBuilder::new(Arc::new(emitter_factory), false);
+    let mut modules = HashSet::new();
+    let mut summary = PrefetchSummary {
+        roots: roots.len(),
+        ..Default::default()
+    };
+
+    // NOTE: Every root gets a graph of its own, so that a broken one does not
+    // keep the others out of the cache.
+    for root in roots {
+        match builder
+            .create_graph_and_maybe_check(vec![root.clone()])
+            .await
+        {
+            Ok(graph) => modules.extend(graph.modules().map(|it| it.specifier().clone())),
+            Err(err) => {
+                warn!("failed to prefetch {root}: {err:#}");
+                summary.failed += 1;
+            }
+        }
+    }
+
+    summary.modules = modules.len();
+
+    Ok(summary)
+}
+
 /// Adds more explanatory information to a resolution error.
 pub fn enhanced_resolution_error_message(error: &ResolutionError) -> String {
     let message = format!("{error}");
@@ -610,3 +703,42 @@ impl<'a> deno_graph::source::Locker for LockfileLocker<'a> {
             .insert_package(package_nv.to_string(), checksum.into_string());
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::prefetch_import_map;
+
+    #[tokio::test]
+    async fn test_prefetch_import_map_skips_failed_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("import_map.json");
+
+        std::fs::write(
+            &path,
+            r#"{
+                "imports": {
+                    "unreachable": "http://127.0.0.1:1/mod.ts",
+                    "local": "./local.ts"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let summary = prefetch_import_map(path.to_string_lossy().into_owned())
+            .await
+            .unwrap();
+
+        assert_eq!(summary.roots, 1);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.modules, 0);
+
+        assert!(prefetch_import_map(
+            dir.path()
+                .join("missing.json")
+                .to_string_lossy()
+                .into_owned()
+        )
+        .await
+        .is_err());
+    }
+}
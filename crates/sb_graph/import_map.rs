@@ -1,9 +1,16 @@
-use anyhow::{anyhow, Error};
+use anyhow::{anyhow, Context, Error};
+use deno_core::serde_json::{self, Map, Value};
 use deno_core::url::Url;
 use import_map::{parse_from_json, ImportMap};
 use std::fs;
 use std::path::Path;
-use urlencoding::decode;
+use urlencoding::{decode, encode};
+
+/// Schemes of the specifiers that are fetched from the network.
+static REMOTE_SCHEMES: &[&str] = &["http", "https", "jsr", "npm"];
+
+static DENO_CONFIG_FILE_NAMES: &[&str] = &["deno.json", "deno.jsonc"];
+static PACKAGE_JSON_FILE_NAME: &str = "package.json";
 
 pub fn load_import_map(maybe_path: Option<String>) -> Result<Option<ImportMap>, Error> {
     if let Some(path_str) = maybe_path {
@@ -33,3 +40,343 @@ pub fn load_import_map(maybe_path: Option<String>) -> Result<Option<ImportMap>,
         Ok(None)
     }
 }
+
+/// Finds the import map of a service in its directory, for when none was
+/// given, in the same places as the Deno CLI does:
+///
+/// 1. The `importMap` of `deno.json(c)`, or its own `imports` and `scopes`.
+/// 2. The `dependencies` of `package.json`, which are mapped to `npm:`
+///    specifiers.
+///
+/// Returns what [`load_import_map`] takes, i.e. the path of the import map, or
+/// a data URI of the one made from the config.
+pub fn find_service_import_map(service_path: &Path) -> Result<Option<String>, Error> {
+    let base_dir_path = std::env::current_dir().map(|p| p.join(service_path))?;
+
+    for name in DENO_CONFIG_FILE_NAMES {
+        let path = base_dir_path.join(name);
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let config = jsonc_parser::parse_to_serde_value(
+            &fs::read_to_string(&path)?,
+            &jsonc_parser::ParseOptions::default(),
+        )
+        .map_err(|err| anyhow!("{err}"))
+        .and_then(|it| it.ok_or_else(|| anyhow!("the config is empty")))
+        .with_context(|| format!("invalid config: {}", path.display()))?;
+
+        return import_map_from_deno_config(&base_dir_path, &config);
+    }
+
+    let path = base_dir_path.join(PACKAGE_JSON_FILE_NAME);
+
+    if path.is_file() {
+        let package_json = serde_json::from_slice::<Value>(&fs::read(&path)?)
+            .with_context(|| format!("invalid package.json: {}", path.display()))?;
+
+        return import_map_from_package_json(&base_dir_path, &package_json);
+    }
+
+    Ok(None)
+}
+
+fn import_map_from_deno_config(
+    base_dir_path: &Path,
+    config: &Value,
+) -> Result<Option<String>, Error> {
+    if let Some(path) = config.get("importMap").and_then(Value::as_str) {
+        return Ok(Some(
+            base_dir_path.join(path).to_string_lossy().into_owned(),
+        ));
+    }
+
+    let mut import_map = Map::new();
+
+    for key in ["imports", "scopes"] {
+        if let Some(value) = config.get(key) {
+            import_map.insert(key.to_string(), value.clone());
+        }
+    }
+
+    if import_map.is_empty() {
+        return Ok(None);
+    }
+
+    to_data_uri(base_dir_path, &Value::Object(import_map)).map(Some)
+}
+
+fn import_map_from_package_json(
+    base_dir_path: &Path,
+    package_json: &Value,
+) -> Result<Option<String>, Error> {
+    let Some(dependencies) = package_json.get("dependencies").and_then(Value::as_object) else {
+        return Ok(None);
+    };
+
+    let mut imports = Map::new();
+
+    for (name, version) in dependencies {
+        let Some(version) = version.as_str() else {
+            continue;
+        };
+
+        // NOTE: Aliases (`"npm:chalk@5"`) are kept as is, while the
+        // dependencies on a path, a tarball or a git repository are left for
+        // the service to import by themselves.
+        let specifier = match version.strip_prefix("npm:") {
+            Some(alias) => alias.to_string(),
+            None if version.contains(':') || version.contains('/') => continue,
+            None => format!("{name}@{version}"),
+        };
+
+        imports.insert(name.clone(), Value::from(format!("npm:{specifier}")));
+        imports.insert(
+            format!("{name}/"),
+            Value::from(format!("npm:/{specifier}/")),
+        );
+    }
+
+    if imports.is_empty() {
+        return Ok(None);
+    }
+
+    to_data_uri(
+        base_dir_path,
+        &serde_json::json!({ "imports": Value::Object(imports) }),
+    )
+    .map(Some)
+}
+
+/// Encodes an import map in the data URI format [`load_import_map`] takes.
+fn to_data_uri(base_dir_path: &Path, import_map: &Value) -> Result<String, Error> {
+    Ok(format!(
+        "data:{}?{}",
+        encode(&serde_json::to_string(import_map)?),
+        encode(&base_dir_path.to_string_lossy())
+    ))
+}
+
+/// Returns the remote modules the import map resolves to, in all of its
+/// scopes.
+///
+/// Mappings of a prefix (e.g. `"std/": "https://deno.land/std/"`) are left
+/// out, as they don't point to a module of their own.
+pub fn get_remote_specifiers(import_map: &ImportMap) -> Vec<Url> {
+    let mut specifiers = import_map
+        .imports()
+        .entries()
+        .chain(
+            import_map
+                .scopes()
+                .flat_map(|scope| scope.imports.entries()),
+        )
+        .filter(|it| !it.key.ends_with('/'))
+        .filter_map(|it| it.value)
+        .filter(|it| REMOTE_SCHEMES.contains(&it.scheme()))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    specifiers.sort();
+    specifiers.dedup();
+    specifiers
+}
+
+/// Returns the entries of the import map, in all of its scopes, that none of
+/// the given modules has been resolved through.
+///
+/// Only the entries mapping to a local or an HTTP(S) module are considered,
+/// as the others are not kept under the specifier they map to.
+pub fn get_unused_entries(import_map: &ImportMap, specifiers: &[String]) -> Vec<String> {
+    let mut unused = import_map
+        .imports()
+        .entries()
+        .chain(
+            import_map
+                .scopes()
+                .flat_map(|scope| scope.imports.entries()),
+        )
+        .filter(|it| {
+            let Some(value) = it.value else {
+                return false;
+            };
+
+            if !matches!(value.scheme(), "file" | "http" | "https") {
+                return false;
+            }
+
+            if it.key.ends_with('/') {
+                !specifiers.iter().any(|s| s.starts_with(value.as_str()))
+            } else {
+                !specifiers.iter().any(|s| s == value.as_str())
+            }
+        })
+        .map(|it| it.key.to_string())
+        .collect::<Vec<_>>();
+
+    unused.sort();
+    unused.dedup();
+    unused
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use deno_core::url::Url;
+    use import_map::parse_from_json;
+
+    use super::{
+        find_service_import_map, get_remote_specifiers, get_unused_entries, load_import_map,
+    };
+
+    fn resolve(service_dir: &std::path::Path, specifier: &str) -> String {
+        let path = find_service_import_map(service_dir).unwrap().unwrap();
+        let import_map = load_import_map(Some(path)).unwrap().unwrap();
+        let referrer = Url::from_file_path(service_dir.join("index.ts")).unwrap();
+
+        import_map
+            .resolve(specifier, &referrer)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_find_service_import_map_in_deno_config() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert_eq!(find_service_import_map(dir.path()).unwrap(), None);
+
+        fs::write(
+            dir.path().join("deno.jsonc"),
+            r#"{
+                // The imports of the service.
+                "imports": { "oak": "https://deno.land/x/oak/mod.ts", "@/": "./src/" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(resolve(dir.path(), "oak"), "https://deno.land/x/oak/mod.ts");
+        assert_eq!(
+            resolve(dir.path(), "@/util.ts"),
+            Url::from_file_path(dir.path().join("src/util.ts"))
+                .unwrap()
+                .to_string()
+        );
+
+        fs::write(
+            dir.path().join("deno.json"),
+            r#"{ "importMap": "./import_map.json", "function": {} }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            find_service_import_map(dir.path()).unwrap(),
+            Some(
+                dir.path()
+                    .join("./import_map.json")
+                    .to_string_lossy()
+                    .into_owned()
+            )
+        );
+
+        fs::write(dir.path().join("deno.json"), r#"{ "function": {} }"#).unwrap();
+
+        assert_eq!(find_service_import_map(dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_find_service_import_map_in_package_json() {
+        let dir = tempfile::tempdir().unwrap();
+
+        fs::write(
+            dir.path().join("package.json"),
+            r#"{
+                "dependencies": {
+                    "chalk": "5.3.0",
+                    "color": "npm:chalk@5",
+                    "local": "file:../local"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(resolve(dir.path(), "chalk"), "npm:chalk@5.3.0");
+        assert_eq!(resolve(dir.path(), "chalk/ansi"), "npm:/chalk@5.3.0/ansi");
+        assert_eq!(resolve(dir.path(), "color"), "npm:chalk@5");
+
+        let path = find_service_import_map(dir.path()).unwrap().unwrap();
+
+        assert!(!load_import_map(Some(path))
+            .unwrap()
+            .unwrap()
+            .imports()
+            .entries()
+            .any(|it| it.key == "local"));
+    }
+
+    #[test]
+    fn test_get_unused_entries() {
+        let import_map = parse_from_json(
+            Url::parse("file:///src/").unwrap(),
+            r#"{
+                "imports": {
+                    "used": "https://deno.land/x/used/mod.ts",
+                    "unused": "https://deno.land/x/unused/mod.ts",
+                    "std/": "https://deno.land/std/",
+                    "npm-dep": "npm:chalk@5"
+                }
+            }"#,
+        )
+        .unwrap()
+        .import_map;
+
+        let unused = get_unused_entries(
+            &import_map,
+            &[
+                "file:///src/index.ts".to_string(),
+                "https://deno.land/x/used/mod.ts".to_string(),
+                "https://deno.land/std/path/mod.ts".to_string(),
+            ],
+        );
+
+        assert_eq!(unused, vec!["unused".to_string()]);
+    }
+
+    #[test]
+    fn test_get_remote_specifiers() {
+        let import_map = parse_from_json(
+            Url::parse("file:///src/").unwrap(),
+            r#"{
+                "imports": {
+                    "oak": "https://deno.land/x/oak/mod.ts",
+                    "std/": "https://deno.land/std/",
+                    "chalk": "npm:chalk@5",
+                    "local": "./local.ts"
+                },
+                "scopes": {
+                    "/vendor/": {
+                        "oak": "https://deno.land/x/oak/mod.ts",
+                        "zod": "https://deno.land/x/zod/mod.ts"
+                    }
+                }
+            }"#,
+        )
+        .unwrap()
+        .import_map;
+
+        assert_eq!(
+            get_remote_specifiers(&import_map)
+                .iter()
+                .map(Url::as_str)
+                .collect::<Vec<_>>(),
+            vec![
+                "https://deno.land/x/oak/mod.ts",
+                "https://deno.land/x/zod/mod.ts",
+                "npm:chalk@5",
+            ]
+        );
+    }
+}
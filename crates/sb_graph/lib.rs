@@ -5,7 +5,7 @@ use anyhow::{bail, Context};
 use deno_ast::MediaType;
 use deno_core::futures::io::{AllowStdIo, BufReader};
 use deno_core::url::Url;
-use deno_core::{FastString, JsBuffer, ModuleSpecifier};
+use deno_core::{serde_json, FastString, JsBuffer, ModuleSpecifier};
 use deno_fs::{FileSystem, RealFs};
 use deno_npm::NpmSystemInfo;
 use eszip::v2::{EszipV2Module, EszipV2Modules, EszipV2SourceSlot, Options, Section};
@@ -15,8 +15,8 @@ use futures::{AsyncReadExt, AsyncSeekExt};
 use glob::glob;
 use log::error;
 use sb_eszip_shared::{
-    AsyncEszipDataRead, SOURCE_CODE_ESZIP_KEY, STATIC_FILES_ESZIP_KEY, SUPABASE_ESZIP_VERSION,
-    SUPABASE_ESZIP_VERSION_KEY, VFS_ESZIP_KEY,
+    AsyncEszipDataRead, METADATA_ESZIP_KEY, SOURCE_CODE_ESZIP_KEY, STATIC_FILES_ESZIP_KEY,
+    SUPABASE_ESZIP_VERSION, SUPABASE_ESZIP_VERSION_KEY, VFS_ESZIP_KEY,
 };
 use sb_fs::{build_vfs, VfsOpts};
 use sb_npm::InnerCliNpmResolverRef;
@@ -35,6 +35,7 @@ mod eszip_parse;
 
 pub mod emitter;
 pub mod errors;
+pub mod eszip_info;
 pub mod eszip_migrate;
 pub mod graph_fs;
 pub mod graph_util;
@@ -198,6 +199,20 @@ impl LazyLoadableEszip {
 
         Ok(())
     }
+
+    pub async fn ensure_metadata(&self) -> Result<Option<EszipMetadata>, anyhow::Error> {
+        let Some(module) = self.ensure_module(METADATA_ESZIP_KEY) else {
+            return Ok(None);
+        };
+
+        let Some(data) = module.source().await else {
+            return Ok(None);
+        };
+
+        Ok(Some(
+            serde_json::from_slice(&data).with_context(|| "cannot deserialize eszip metadata")?,
+        ))
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -792,6 +807,52 @@ where
     Ok(())
 }
 
+/// Describes how an eszip should be served. This is written by the `bundle`
+/// command and is not required for loading an eszip.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EszipMetadata {
+    /// Path of the entrypoint relative to the root of the bundle (the service
+    /// directory), with `/` as separator. It does not depend on where the
+    /// bundle was built.
+    pub entrypoint: Option<String>,
+    pub env_schema: Vec<String>,
+}
+
+impl EszipMetadata {
+    /// Returns the path of the entrypoint relative to the root of the bundle,
+    /// in the form kept in [`EszipMetadata::entrypoint`]. Returns `None` if
+    /// the entrypoint is not inside the root.
+    pub fn relative_entrypoint(entrypoint: &Path, root: &Path) -> Option<String> {
+        let relative = entrypoint.strip_prefix(root).ok()?;
+        let segments = relative
+            .components()
+            .map(|it| match it {
+                std::path::Component::Normal(it) => it.to_str(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        (!segments.is_empty()).then(|| segments.join("/"))
+    }
+}
+
+pub fn include_metadata_in_eszip(
+    metadata: &EszipMetadata,
+    eszip: &mut EszipV2,
+) -> Result<(), anyhow::Error> {
+    eszip.add_opaque_data(
+        String::from(METADATA_ESZIP_KEY),
+        Arc::from(
+            serde_json::to_vec(metadata)
+                .with_context(|| "cannot serialize eszip metadata")?
+                .into_boxed_slice(),
+        ),
+    );
+
+    Ok(())
+}
+
 fn extract_file_specifiers(eszip: &EszipV2) -> Vec<String> {
     eszip
         .specifiers()
@@ -904,10 +965,11 @@ pub async fn extract_from_file(eszip_file: PathBuf, output_path: PathBuf) -> boo
 #[cfg(test)]
 mod test {
     use crate::{
-        extract_eszip, generate_binary_eszip, EmitterFactory, EszipPayloadKind, ExtractEszipPayload,
+        extract_eszip, generate_binary_eszip, EmitterFactory, EszipMetadata, EszipPayloadKind,
+        ExtractEszipPayload,
     };
     use std::fs::remove_dir_all;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
     use std::sync::Arc;
 
     #[tokio::test]
@@ -935,4 +997,19 @@ mod test {
         assert!(PathBuf::from("../base/test_cases/extracted-npm/hello.js").exists());
         remove_dir_all(PathBuf::from("../base/test_cases/extracted-npm/")).unwrap();
     }
+
+    #[test]
+    fn test_relative_entrypoint() {
+        let root = Path::new("/home/build/functions/hello");
+
+        assert_eq!(
+            EszipMetadata::relative_entrypoint(&root.join("src/index.ts"), root).as_deref(),
+            Some("src/index.ts")
+        );
+        assert_eq!(
+            EszipMetadata::relative_entrypoint(Path::new("/home/build/other/index.ts"), root),
+            None
+        );
+        assert_eq!(EszipMetadata::relative_entrypoint(root, root), None);
+    }
 }
@@ -12,6 +12,7 @@ use deno_tls::RootCertStoreProvider;
 use futures_util::future::OptionFuture;
 use import_map::{parse_from_json, ImportMap};
 use sb_core::cache::caches::Caches;
+use sb_core::cache::code_cache::{DiskCodeCache, ModuleCodeCache};
 use sb_core::cache::deno_dir::DenoDirProvider;
 use sb_core::cache::node::NodeAnalysisCache;
 use sb_core::cache::CacheSetting;
@@ -52,8 +53,12 @@ struct StandaloneRootCertStoreProvider {
 impl RootCertStoreProvider for StandaloneRootCertStoreProvider {
     fn get_or_try_init(&self) -> Result<&RootCertStore, AnyError> {
         self.cell.get_or_try_init(|| {
-            get_root_cert_store(None, self.ca_stores.clone(), self.ca_data.clone())
-                .map_err(|err| err.into())
+            let (store, report) =
+                get_root_cert_store(None, self.ca_stores.clone(), self.ca_data.clone())?;
+
+            report.log("standalone");
+
+            Ok(store)
         })
     }
 }
@@ -64,6 +69,8 @@ pub async fn create_module_loader_for_eszip<P>(
     metadata: Metadata,
     maybe_import_map: Option<ImportMap>,
     include_source_map: bool,
+    maybe_code_cache: Option<ModuleCodeCache>,
+    persist_code_cache: bool,
 ) -> Result<RuntimeProviders, AnyError>
 where
     P: AsRef<Path>,
@@ -161,6 +168,8 @@ where
     let cjs_resolutions = Arc::new(CjsResolutionStore::default());
     let cache_db = Caches::new(deno_dir_provider.clone());
     let node_analysis_cache = NodeAnalysisCache::new(cache_db.node_analysis_db());
+    let maybe_disk_code_cache =
+        persist_code_cache.then(|| DiskCodeCache::new(cache_db.code_cache_db()));
     let cjs_esm_code_analyzer = CliCjsCodeAnalyzer::new(node_analysis_cache, fs.clone());
     let node_code_translator = Arc::new(NodeCodeTranslator::new(
         cjs_esm_code_analyzer,
@@ -203,6 +212,8 @@ where
         module_loader: Rc::new(EmbeddedModuleLoader {
             shared: module_loader_factory.shared.clone(),
             include_source_map,
+            code_cache: maybe_code_cache,
+            disk_code_cache: maybe_disk_code_cache,
         }),
         vfs,
         module_code: entry_module_source,
@@ -218,6 +229,8 @@ pub async fn create_module_loader_for_standalone_from_eszip_kind<P>(
     maybe_import_map: Option<ImportMap>,
     maybe_import_map_path: Option<String>,
     include_source_map: bool,
+    maybe_code_cache: Option<ModuleCodeCache>,
+    persist_code_cache: bool,
 ) -> Result<RuntimeProviders, AnyError>
 where
     P: AsRef<Path>,
@@ -260,6 +273,8 @@ where
         },
         maybe_import_map,
         include_source_map,
+        maybe_code_cache,
+        persist_code_cache,
     )
     .await
 }
@@ -11,16 +11,21 @@ use deno_core::error::AnyError;
 use deno_core::futures::FutureExt;
 use deno_core::ModuleType;
 use deno_core::ResolutionKind;
+use deno_core::SourceCodeCacheInfo;
 use deno_core::{ModuleLoader, ModuleSourceCode};
 use deno_core::{ModuleSpecifier, RequestedModuleType};
 use deno_semver::npm::NpmPackageReqReference;
 use eszip::deno_graph;
 use eszip::EszipRelativeFileBaseUrl;
+use sb_core::cache::code_cache::{DiskCodeCache, ModuleCodeCache};
 use sb_eszip_shared::AsyncEszipDataRead;
 use sb_graph::resolver::CliNodeResolver;
 use sb_graph::resolver::NpmModuleLoader;
 use sb_graph::LazyLoadableEszip;
 use sb_node::NodeResolutionMode;
+use std::borrow::Cow;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 use tracing::instrument;
 
@@ -68,6 +73,9 @@ pub struct SharedModuleLoaderState {
 pub struct EmbeddedModuleLoader {
     pub(crate) shared: Arc<SharedModuleLoaderState>,
     pub(crate) include_source_map: bool,
+    /// Shared by the workers of the same service, if any.
+    pub(crate) code_cache: Option<ModuleCodeCache>,
+    pub(crate) disk_code_cache: Option<DiskCodeCache>,
 }
 
 impl ModuleLoader for EmbeddedModuleLoader {
@@ -237,6 +245,8 @@ impl ModuleLoader for EmbeddedModuleLoader {
         };
 
         let original_specifier = original_specifier.clone();
+        let code_cache = self.code_cache.clone();
+        let disk_code_cache = self.disk_code_cache.clone();
 
         deno_core::ModuleLoadResponse::Async(
             async move {
@@ -266,6 +276,30 @@ impl ModuleLoader for EmbeddedModuleLoader {
 
                     Arc::from(src)
                 };
+                let maybe_code_cache_info = (code_cache.is_some() || disk_code_cache.is_some())
+                    .then(|| {
+                        let specifier = module.specifier.as_str();
+                        let hash = ModuleCodeCache::hash_source(&maybe_code_with_source_map);
+                        let maybe_data = code_cache
+                            .as_ref()
+                            .and_then(|it| it.get(specifier, hash))
+                            .map(|it| it.to_vec())
+                            .or_else(|| {
+                                let data = disk_code_cache.as_ref()?.get(specifier, hash)?;
+
+                                if let Some(cache) = code_cache.as_ref() {
+                                    cache.set(specifier, hash, &data);
+                                }
+
+                                Some(data)
+                            });
+
+                        SourceCodeCacheInfo {
+                            hash,
+                            data: maybe_data.map(Cow::Owned),
+                        }
+                    });
+
                 Ok(deno_core::ModuleSource::new_with_redirect(
                     match module.inner.kind {
                         eszip::ModuleKind::JavaScript => ModuleType::JavaScript,
@@ -280,10 +314,26 @@ impl ModuleLoader for EmbeddedModuleLoader {
                     ModuleSourceCode::String(maybe_code_with_source_map.into()),
                     &original_specifier,
                     &module.specifier,
-                    None,
+                    maybe_code_cache_info,
                 ))
             }
             .boxed_local(),
         )
     }
+
+    fn code_cache_ready(
+        &self,
+        module_specifier: ModuleSpecifier,
+        hash: u64,
+        code_cache: &[u8],
+    ) -> Pin<Box<dyn Future<Output = ()>>> {
+        if let Some(cache) = self.code_cache.as_ref() {
+            cache.set(module_specifier.as_str(), hash, code_cache);
+        }
+        if let Some(cache) = self.disk_code_cache.as_ref() {
+            cache.set(module_specifier.as_str(), hash, code_cache);
+        }
+
+        async {}.boxed_local()
+    }
 }
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use deno_config::JsxImportSourceConfig;
+use deno_core::url::Url;
+use deno_core::FastString;
+use sb_graph::{DecoratorType, EszipPayloadKind};
+
+use crate::context::{
+    EventWorkerRuntimeOpts, MainWorkerRuntimeOpts, Timing, UserWorkerRuntimeOpts,
+    WorkerContextInitOpts, WorkerLocale, WorkerRuntimeOpts,
+};
+use crate::errors::WorkerOptsError;
+
+#[derive(Debug, Default)]
+struct CommonOpts {
+    service_path: PathBuf,
+    no_module_cache: bool,
+    import_map_path: Option<String>,
+    env_vars: HashMap<String, String>,
+    timing: Option<Timing>,
+    maybe_eszip: Option<EszipPayloadKind>,
+    maybe_module_code: Option<FastString>,
+    maybe_module_map: Option<HashMap<String, FastString>>,
+    maybe_entrypoint: Option<String>,
+    maybe_decorator: Option<DecoratorType>,
+    static_patterns: Vec<String>,
+    maybe_jsx_import_source_config: Option<JsxImportSourceConfig>,
+    locale: WorkerLocale,
+}
+
+impl CommonOpts {
+    fn new(service_path: PathBuf) -> Self {
+        Self {
+            service_path,
+            ..Default::default()
+        }
+    }
+
+    fn validate(&self) -> Result<(), WorkerOptsError> {
+        if self.maybe_module_code.is_some() {
+            if self.maybe_eszip.is_some() {
+                return Err(WorkerOptsError::ModuleCodeWithEszip);
+            }
+            if self.maybe_entrypoint.is_some() {
+                return Err(WorkerOptsError::ModuleCodeWithEntrypoint);
+            }
+        }
+
+        if self.maybe_module_map.is_some() && self.maybe_eszip.is_some() {
+            return Err(WorkerOptsError::ModuleMapWithEszip);
+        }
+
+        // NOTE: Only the syntax is checked here, as whether ICU knows them is
+        // only found out once the isolate is bootstrapped.
+        if let Some(locale) = self.locale.locale.as_deref() {
+            if !is_valid_name(locale, &['-']) {
+                return Err(WorkerOptsError::InvalidLocale(locale.to_string()));
+            }
+        }
+
+        if let Some(timezone) = self.locale.timezone.as_deref() {
+            if !is_valid_name(timezone, &['-', '_', '+', '/']) {
+                return Err(WorkerOptsError::InvalidTimezone(timezone.to_string()));
+            }
+        }
+
+        if let Some(entrypoint) = self.maybe_entrypoint.as_deref() {
+            if let Err(err) = Url::parse(entrypoint) {
+                return Err(WorkerOptsError::InvalidEntrypoint(
+                    entrypoint.to_string(),
+                    err,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn into_opts(self, conf: WorkerRuntimeOpts) -> WorkerContextInitOpts {
+        WorkerContextInitOpts {
+            service_path: self.service_path,
+            no_module_cache: self.no_module_cache,
+            import_map_path: self.import_map_path,
+            env_vars: self.env_vars,
+            timing: self.timing,
+            conf,
+            maybe_eszip: self.maybe_eszip,
+            maybe_module_code: self.maybe_module_code,
+            maybe_module_map: self.maybe_module_map,
+            maybe_entrypoint: self.maybe_entrypoint,
+            maybe_decorator: self.maybe_decorator,
+            static_patterns: self.static_patterns,
+            maybe_jsx_import_source_config: self.maybe_jsx_import_source_config,
+            locale: self.locale,
+        }
+    }
+}
+
+fn is_valid_name(value: &str, separators: &[char]) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|it| it.is_ascii_alphanumeric() || separators.contains(&it))
+}
+
+macro_rules! impl_common_setters {
+    ($builder:ty) => {
+        impl $builder {
+            pub fn no_module_cache(mut self, value: bool) -> Self {
+                self.common.no_module_cache = value;
+                self
+            }
+
+            pub fn import_map_path(mut self, value: Option<String>) -> Self {
+                self.common.import_map_path = value;
+                self
+            }
+
+            pub fn env_vars(mut self, value: HashMap<String, String>) -> Self {
+                self.common.env_vars = value;
+                self
+            }
+
+            pub fn timing(mut self, value: Option<Timing>) -> Self {
+                self.common.timing = value;
+                self
+            }
+
+            pub fn eszip(mut self, value: Option<EszipPayloadKind>) -> Self {
+                self.common.maybe_eszip = value;
+                self
+            }
+
+            pub fn module_code(mut self, value: Option<FastString>) -> Self {
+                self.common.maybe_module_code = value;
+                self
+            }
+
+            pub fn module_map(mut self, value: Option<HashMap<String, FastString>>) -> Self {
+                self.common.maybe_module_map = value;
+                self
+            }
+
+            pub fn entrypoint(mut self, value: Option<String>) -> Self {
+                self.common.maybe_entrypoint = value;
+                self
+            }
+
+            pub fn decorator(mut self, value: Option<DecoratorType>) -> Self {
+                self.common.maybe_decorator = value;
+                self
+            }
+
+            pub fn static_patterns(mut self, value: Vec<String>) -> Self {
+                self.common.static_patterns = value;
+                self
+            }
+
+            pub fn jsx_import_source_config(
+                mut self,
+                value: Option<JsxImportSourceConfig>,
+            ) -> Self {
+                self.common.maybe_jsx_import_source_config = value;
+                self
+            }
+
+            pub fn locale(mut self, value: Option<String>) -> Self {
+                self.common.locale.locale = value;
+                self
+            }
+
+            pub fn timezone(mut self, value: Option<String>) -> Self {
+                self.common.locale.timezone = value;
+                self
+            }
+        }
+    };
+}
+
+/// Builds the options of a user worker.
+///
+/// Unlike filling [`WorkerContextInitOpts`] by hand, the combination of the
+/// eszip, the module code and the entrypoint is checked when calling
+/// [`UserWorkerBuilder::build`], not while the worker is booting.
+#[derive(Debug)]
+pub struct UserWorkerBuilder {
+    common: CommonOpts,
+    conf: UserWorkerRuntimeOpts,
+}
+
+impl UserWorkerBuilder {
+    pub fn new<P: Into<PathBuf>>(service_path: P) -> Self {
+        Self {
+            common: CommonOpts::new(service_path.into()),
+            conf: UserWorkerRuntimeOpts::default(),
+        }
+    }
+
+    pub fn runtime_opts(mut self, value: UserWorkerRuntimeOpts) -> Self {
+        self.conf = value;
+        self
+    }
+
+    pub fn build(self) -> Result<WorkerContextInitOpts, WorkerOptsError> {
+        self.common.validate()?;
+
+        if self.conf.cpu_time_soft_limit_ms > self.conf.cpu_time_hard_limit_ms {
+            return Err(WorkerOptsError::CpuTimeSoftLimitExceedsHardLimit(
+                self.conf.cpu_time_soft_limit_ms,
+                self.conf.cpu_time_hard_limit_ms,
+            ));
+        }
+
+        if self.conf.memory_limit_mb == 0 {
+            return Err(WorkerOptsError::ZeroMemoryLimit);
+        }
+
+        Ok(self
+            .common
+            .into_opts(WorkerRuntimeOpts::UserWorker(self.conf)))
+    }
+}
+
+/// Builds the options of the main worker.
+#[derive(Debug)]
+pub struct MainWorkerBuilder {
+    common: CommonOpts,
+    conf: MainWorkerRuntimeOpts,
+}
+
+impl MainWorkerBuilder {
+    pub fn new<P: Into<PathBuf>>(service_path: P, conf: MainWorkerRuntimeOpts) -> Self {
+        Self {
+            common: CommonOpts::new(service_path.into()),
+            conf,
+        }
+    }
+
+    pub fn build(self) -> Result<WorkerContextInitOpts, WorkerOptsError> {
+        self.common.validate()?;
+
+        Ok(self
+            .common
+            .into_opts(WorkerRuntimeOpts::MainWorker(self.conf)))
+    }
+}
+
+/// Builds the options of the events worker.
+#[derive(Debug)]
+pub struct EventsWorkerBuilder {
+    common: CommonOpts,
+    conf: EventWorkerRuntimeOpts,
+}
+
+impl EventsWorkerBuilder {
+    pub fn new<P: Into<PathBuf>>(service_path: P, conf: EventWorkerRuntimeOpts) -> Self {
+        Self {
+            common: CommonOpts::new(service_path.into()),
+            conf,
+        }
+    }
+
+    pub fn build(self) -> Result<WorkerContextInitOpts, WorkerOptsError> {
+        self.common.validate()?;
+
+        if self.conf.events_msg_rx.is_none() {
+            return Err(WorkerOptsError::MissingEventsReceiver);
+        }
+
+        Ok(self
+            .common
+            .into_opts(WorkerRuntimeOpts::EventsWorker(self.conf)))
+    }
+}
+
+impl_common_setters!(UserWorkerBuilder);
+impl_common_setters!(MainWorkerBuilder);
+impl_common_setters!(EventsWorkerBuilder);
+
+#[cfg(test)]
+mod test {
+    use deno_core::FastString;
+
+    use super::{EventsWorkerBuilder, UserWorkerBuilder};
+    use crate::context::{EventWorkerRuntimeOpts, UserWorkerRuntimeOpts};
+    use crate::errors::WorkerOptsError;
+
+    #[test]
+    fn test_user_worker_builder_rejects_invalid_combinations() {
+        let err = UserWorkerBuilder::new("./")
+            .module_code(Some(FastString::from_static("export {}")))
+            .entrypoint(Some("file:///main.ts".to_string()))
+            .build()
+            .unwrap_err();
+
+        assert!(matches!(err, WorkerOptsError::ModuleCodeWithEntrypoint));
+
+        let err = UserWorkerBuilder::new("./")
+            .entrypoint(Some("main.ts".to_string()))
+            .build()
+            .unwrap_err();
+
+        assert!(matches!(err, WorkerOptsError::InvalidEntrypoint(..)));
+
+        let err = UserWorkerBuilder::new("./")
+            .runtime_opts(UserWorkerRuntimeOpts {
+                cpu_time_soft_limit_ms: 200,
+                cpu_time_hard_limit_ms: 100,
+                ..Default::default()
+            })
+            .build()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            WorkerOptsError::CpuTimeSoftLimitExceedsHardLimit(200, 100)
+        ));
+
+        let err = UserWorkerBuilder::new("./")
+            .timezone(Some("Europe/Berlin; rm".to_string()))
+            .build()
+            .unwrap_err();
+
+        assert!(matches!(err, WorkerOptsError::InvalidTimezone(_)));
+    }
+
+    #[test]
+    fn test_user_worker_builder_builds_valid_options() {
+        let opts = UserWorkerBuilder::new("./hello")
+            .entrypoint(Some("file:///hello/main.ts".to_string()))
+            .timezone(Some("America/New_York".to_string()))
+            .build()
+            .unwrap();
+
+        assert_eq!(opts.service_path.to_str(), Some("./hello"));
+        assert_eq!(
+            opts.maybe_entrypoint.as_deref(),
+            Some("file:///hello/main.ts")
+        );
+        assert!(opts.conf.is_user_worker());
+    }
+
+    #[test]
+    fn test_events_worker_builder_requires_receiver() {
+        let err = EventsWorkerBuilder::new(
+            "./",
+            EventWorkerRuntimeOpts {
+                events_msg_rx: None,
+                event_worker_exit_deadline_sec: None,
+            },
+        )
+        .build()
+        .unwrap_err();
+
+        assert!(matches!(err, WorkerOptsError::MissingEventsReceiver));
+    }
+}
@@ -1,13 +1,27 @@
-use anyhow::{anyhow, Error};
+use anyhow::{anyhow, bail, Error};
 use deno_config::JsxImportSourceConfig;
 use deno_core::FastString;
 use enum_as_inner::EnumAsInner;
-use event_worker::events::{UncaughtExceptionEvent, WorkerEventWithMetadata};
+use event_worker::events::{
+    BootWarning, CrashedEvent, UncaughtExceptionEvent, WorkerEventWithMetadata,
+};
+use event_worker::history::LifecycleRecord;
+use futures_util::task::AtomicWaker;
 use hyper_v014::{Body, Request, Response};
+use sb_core::cache::code_cache::ModuleCodeCache;
+use sb_core::cert::OutboundTlsOptions;
+use sb_core::feature_flags::{FeatureFlagProvider, FeatureFlags};
+use sb_core::replay::ReplayMode;
 use sb_core::util::sync::AtomicFlag;
 use sb_core::{MetricSource, SharedMetricSource};
+use sb_env::EnvProvider;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::path::PathBuf;
-use std::sync::atomic::AtomicUsize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::task::Waker;
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::{mpsc, oneshot, Mutex, Notify, OwnedSemaphorePermit};
@@ -16,10 +30,16 @@ use uuid::Uuid;
 
 use sb_graph::{DecoratorType, EszipPayloadKind};
 
+use crate::failure_injection::WorkerFailureInjection;
+use crate::header_policy::HeaderPolicy;
+use crate::invoke::InvokeChains;
+use crate::rewrite_policy::RewritePolicy;
+
 #[derive(Debug, Clone)]
 pub enum WorkerExitStatus {
     Normal,
     WithUncaughtException(UncaughtExceptionEvent),
+    Crashed(CrashedEvent),
 }
 
 impl Default for WorkerExitStatus {
@@ -38,6 +58,9 @@ impl WorkerExit {
             WorkerExitStatus::WithUncaughtException(UncaughtExceptionEvent {
                 exception, ..
             }) => Some(anyhow!("{exception}")),
+            WorkerExitStatus::Crashed(CrashedEvent { message }) => {
+                Some(anyhow!("worker crashed: {message}"))
+            }
         }
     }
 
@@ -46,10 +69,80 @@ impl WorkerExit {
     }
 }
 
+/// The logical identity of a user worker. Unlike the key of a worker, it is
+/// kept when the worker is restarted or re-created.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerIdentity {
+    pub tenant: Option<String>,
+    pub service: String,
+    pub revision: Option<String>,
+}
+
+impl WorkerIdentity {
+    /// Returns the identity of the user worker the options describe.
+    pub fn of(opts: &WorkerContextInitOpts) -> Self {
+        let conf = opts.conf.as_user_worker();
+
+        Self {
+            tenant: conf.and_then(|it| it.tenant.clone()),
+            service: opts.service_path.to_str().unwrap_or("").to_string(),
+            revision: conf.and_then(|it| it.revision.clone()),
+        }
+    }
+}
+
+impl fmt::Display for WorkerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(tenant) = self.tenant.as_ref() {
+            write!(f, "{}/", tenant)?;
+        }
+
+        write!(f, "{}", self.service)?;
+
+        if let Some(revision) = self.revision.as_ref() {
+            write!(f, "@{}", revision)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Selects the workers whose feature flags are updated. Workers must match
+/// every field that is given.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FeatureFlagsTarget {
+    pub tenant: Option<String>,
+    pub service_path: Option<String>,
+}
+
+impl FeatureFlagsTarget {
+    pub fn matches(&self, tenant: Option<&str>, service_path: &str) -> bool {
+        self.tenant.as_deref().map_or(true, |it| Some(it) == tenant)
+            && self
+                .service_path
+                .as_deref()
+                .map_or(true, |it| it == service_path)
+    }
+}
+
+/// The settings of the worker pool that can be changed while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolPolicyUpdate {
+    pub max_parallelism: usize,
+    pub request_wait_timeout_ms: u64,
+    pub drain_timeout_ms: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct UserWorkerRuntimeOpts {
     pub service_path: Option<String>,
     pub key: Option<Uuid>,
+    pub identity: Option<WorkerIdentity>,
+
+    pub tenant: Option<String>,
+    pub revision: Option<String>,
 
     pub pool_msg_tx: Option<mpsc::UnboundedSender<UserWorkerMsgs>>,
     pub events_msg_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
@@ -57,17 +150,158 @@ pub struct UserWorkerRuntimeOpts {
 
     pub memory_limit_mb: u64,
     pub low_memory_multiplier: u64,
+    /// The memory usage of the worker growing over this many samples in a
+    /// row, taken a second apart, is reported as a suspected leak. A value
+    /// below `2` disables the detector.
+    pub memory_leak_trend_samples: u64,
+    /// Drains the worker once a leak is suspected, so that it is replaced by a
+    /// fresh one before it hits its memory limit.
+    pub recycle_on_memory_leak: bool,
+    /// The initial size of the V8 heap of the worker in MiB. `0` leaves it to
+    /// V8.
+    ///
+    /// NOTE: V8 sizes the young generation from the heap limits itself, as
+    /// its size is not exposed on its own.
+    pub heap_initial_mb: u64,
+    /// The size of the V8 heap in MiB, capped to `memory_limit_mb`. `0` means
+    /// the memory limit.
+    ///
+    /// Once the heap nears it, the worker is terminated and the termination
+    /// is reported as a memory limit shutdown, as if it had hit
+    /// `memory_limit_mb`.
+    ///
+    /// NOTE: V8 flags such as `--optimize_for_size` apply to every isolate of
+    /// the process, so they can only be given to the whole runtime, with
+    /// `V8_FLAGS`.
+    pub heap_max_mb: u64,
+    /// Tells V8 the worker is low on memory once it uses this share of its
+    /// memory limit, in percent, so that it collects what it can before the
+    /// limit terminates the worker. `0` disables the notifications.
+    pub low_memory_notification_percent: u64,
 
     pub worker_timeout_ms: u64, // wall clock limit
 
     pub cpu_time_soft_limit_ms: u64,
     pub cpu_time_hard_limit_ms: u64,
+    /// The CPU time the worker may use over its lifetime before it is
+    /// terminated, regardless of how it is spread out. `0` disables the cap.
+    pub cpu_time_cap_ms: u64,
+    /// Like `cpu_time_cap_ms`, for the CPU time used by a single request.
+    pub request_cpu_time_cap_ms: u64,
+
+    /// A turn of the event loop taking longer than this is reported to the
+    /// events worker. `0` disables the warning.
+    pub event_loop_lag_warn_ms: u64,
+    /// A turn of the event loop taking longer than this terminates the
+    /// worker. `0` disables the limit.
+    pub event_loop_lag_limit_ms: u64,
+
+    /// Maximum number of resources (sockets, files, ...) the worker may hold
+    /// open at once. `0` means unlimited.
+    pub max_resources: u64,
+
+    /// Maximum number of WebSocket connections the worker may open at once.
+    /// `0` means unlimited.
+    pub max_websocket_connections: u64,
+    /// A WebSocket connection opened by the worker that receives nothing for
+    /// this long is closed. `0` disables the timeout.
+    pub websocket_idle_timeout_ms: u64,
+
+    /// An outbound request of the worker taking longer than this, its
+    /// response body included, is aborted. `0` disables the timeout.
+    pub fetch_timeout_ms: u64,
+    /// Maximum number of outbound requests the worker may have in flight at
+    /// once. `0` means unlimited.
+    pub max_concurrent_fetches: u64,
+    /// Maximum number of emails the worker may send in a minute with
+    /// `EdgeRuntime.smtp.send()`. `0` means unlimited.
+    pub max_emails_per_minute: u64,
+
+    /// Coarsens `Date` and `performance.now()` in the worker to steps of
+    /// this many milliseconds. `0` leaves them as they are.
+    pub clock_granularity_ms: u64,
+
+    /// The share of the debug and info records of `EdgeRuntime.log` that are
+    /// kept, from `0` to `1`.
+    pub log_sample_rate: f64,
+    /// Records of `EdgeRuntime.log` per second past which they are dropped.
+    /// `0` means unlimited.
+    pub log_rate_limit: u64,
+
+    /// How long a retiring worker may keep serving its in-flight requests
+    /// before it is terminated. Set by the pool. `0` terminates at once.
+    pub drain_timeout_ms: u64,
+    /// How long the creation of the worker waited for a boot slot. Set by the
+    /// pool.
+    pub boot_queue_us: u64,
+    /// The compiled code of the service, shared with its other instances.
+    /// Set by the pool.
+    pub code_cache: Option<ModuleCodeCache>,
+    /// What the pool had to degrade before booting the worker, reported
+    /// along with the warnings of the boot itself. Set by the pool.
+    pub boot_warnings: Vec<BootWarning>,
+
+    /// Where the worker publishes how close it is to its limits. Set by the
+    /// pool.
+    pub pressure: Option<Arc<WorkerPressure>>,
+    /// Set by the pool if failure injection is enabled.
+    pub failure_injection: Option<WorkerFailureInjection>,
+    /// Whether the worker is recorded (set by the pool) or replayed from a
+    /// recording.
+    pub replay: Option<ReplayMode>,
 
     pub force_create: bool,
+    /// Concurrent creations of the same service and revision with the same
+    /// key share a single worker instead of booting one each.
+    pub idempotency_key: Option<String>,
+    /// Creations of the same service and revision with the same key are
+    /// served by the same worker while it is alive, when the service has
+    /// several (see `--max-parallelism`).
+    pub session_key: Option<String>,
     pub net_access_disabled: bool,
     pub allow_net: Option<Vec<String>>,
+    /// Hosts (`host` or `host:port`) the worker may open raw TCP/UDP sockets
+    /// to, on top of `allow_net`. Listening on a socket is only allowed on the
+    /// addresses listed here. `None` leaves the sockets to `allow_net`.
+    pub allow_sockets: Option<Vec<String>>,
     pub custom_module_root: Option<String>,
     pub allow_remote_modules: bool,
+    /// Exposes `SharedArrayBuffer` to the worker.
+    ///
+    /// NOTE: Shared memory makes high-resolution timers out of reach of
+    /// `clock_granularity_ms`, so it is left to the operator, not to the
+    /// manifest of the function.
+    pub allow_shared_array_buffer: bool,
+    /// Allows the worker to create a shared `WebAssembly.Memory`, which the
+    /// threads of WebAssembly build on.
+    pub allow_wasm_threads: bool,
+    /// Allows `Atomics.wait` to block the thread of the worker.
+    pub allow_atomics_wait: bool,
+    /// The base64-encoded signature of the eszip the worker is created from
+    /// in memory, see `--bundle-public-key`.
+    pub eszip_signature: Option<String>,
+
+    pub allowed_methods: Option<Vec<String>>,
+    /// Matched against the normalized path of the requests, on whole
+    /// segments.
+    pub allowed_path_prefixes: Option<Vec<String>>,
+
+    /// Replaces the root store and TLS settings of the runtime for the
+    /// outbound connections of the worker.
+    pub outbound_tls: Option<OutboundTlsOptions>,
+
+    pub env_provider: Option<EnvProvider>,
+    /// Boots the worker with the env vars its service was last created with
+    /// in place of the ones given. Set for the workers booted by an
+    /// invocation, so that they don't get the env vars of their caller.
+    pub uses_service_env: bool,
+    /// The chains of the invocations the worker is handling. Set when the
+    /// worker is created.
+    pub invoke_chains: Option<InvokeChains>,
+
+    /// The feature flags the worker boots with.
+    pub feature_flags: FeatureFlags,
+    pub feature_flag_provider: Option<FeatureFlagProvider>,
 }
 
 impl Default for UserWorkerRuntimeOpts {
@@ -76,23 +310,129 @@ impl Default for UserWorkerRuntimeOpts {
             memory_limit_mb: 512,
             worker_timeout_ms: 5 * 60 * 1000,
             low_memory_multiplier: 5,
+            memory_leak_trend_samples: 0,
+            recycle_on_memory_leak: false,
+            heap_initial_mb: 0,
+            heap_max_mb: 0,
+            low_memory_notification_percent: 0,
             cpu_time_soft_limit_ms: 50,
             cpu_time_hard_limit_ms: 100,
+            cpu_time_cap_ms: 0,
+            request_cpu_time_cap_ms: 0,
+            event_loop_lag_warn_ms: 0,
+            event_loop_lag_limit_ms: 0,
+            max_resources: 0,
+            max_websocket_connections: 0,
+            websocket_idle_timeout_ms: 0,
+            fetch_timeout_ms: 0,
+            max_concurrent_fetches: 0,
+            max_emails_per_minute: 0,
+            clock_granularity_ms: 0,
+            log_sample_rate: 1.0,
+            log_rate_limit: 0,
+            drain_timeout_ms: 0,
+            boot_queue_us: 0,
+            code_cache: None,
+            boot_warnings: vec![],
+            pressure: None,
+            failure_injection: None,
+            replay: None,
 
             force_create: false,
+            idempotency_key: None,
+            session_key: None,
             key: None,
+            identity: None,
+            tenant: None,
+            revision: None,
             pool_msg_tx: None,
             events_msg_tx: None,
             cancel: None,
             net_access_disabled: false,
             allow_net: None,
+            allow_sockets: None,
             allow_remote_modules: true,
+            allow_shared_array_buffer: false,
+            allow_wasm_threads: false,
+            allow_atomics_wait: true,
             custom_module_root: None,
+            eszip_signature: None,
             service_path: None,
+            allowed_methods: None,
+            allowed_path_prefixes: None,
+            outbound_tls: None,
+            env_provider: None,
+            uses_service_env: false,
+            invoke_chains: None,
+            feature_flags: FeatureFlags::new(),
+            feature_flag_provider: None,
         }
     }
 }
 
+/// How much of its memory limit and CPU time soft limit a user worker has used,
+/// as last observed by its runtime and its supervisor.
+#[derive(Debug, Default)]
+pub struct WorkerPressure {
+    /// Memory used by the isolate in bytes, as of the last memory check.
+    pub memory_used: AtomicUsize,
+    /// CPU time in milliseconds the worker has spent serving requests.
+    pub cpu_time_used_ms: AtomicU64,
+    /// CPU time in milliseconds the worker has used since it booted, whatever
+    /// the supervisor policy.
+    pub cpu_time_accumulated_ms: AtomicU64,
+}
+
+/// Mirrors a share of the requests of a service to a shadow worker (e.g. the
+/// next revision of the service) for validation. Responses of the shadow are
+/// discarded.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MirrorPolicy {
+    /// The service to mirror to. Defaults to the service itself.
+    pub service_path: Option<String>,
+    /// The revision of the shadow worker.
+    pub revision: Option<String>,
+    /// The share of the requests to mirror, from 0 to 100.
+    pub percentage: u8,
+}
+
+/// Scales the number of workers serving a service with its load, between
+/// `min_instances` and `max_instances`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AutoscalePolicy {
+    #[serde(default = "default_min_instances")]
+    pub min_instances: usize,
+    pub max_instances: usize,
+    /// In-flight requests per worker above which workers are added.
+    #[serde(default = "default_target_queue_depth")]
+    pub target_queue_depth: usize,
+    /// A worker is added if the p95 latency exceeds this while every worker
+    /// is busy.
+    pub target_p95_latency_ms: Option<u64>,
+    /// How long the load must stay below the target before a worker is
+    /// retired, so that clients pausing between requests (i.e. their think
+    /// time) do not make the service flap.
+    #[serde(default = "default_scale_down_delay_ms")]
+    pub scale_down_delay_ms: u64,
+    /// Checks the workers booted by the autoscaler, and terminates the ones
+    /// that turn out to be unhealthy.
+    pub health_check: Option<HealthCheck>,
+}
+
+fn default_min_instances() -> usize {
+    1
+}
+
+fn default_target_queue_depth() -> usize {
+    4
+}
+
+fn default_scale_down_delay_ms() -> u64 {
+    30000
+}
+
 #[derive(Debug, Clone)]
 pub struct UserWorkerProfile {
     pub worker_request_msg_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
@@ -101,10 +441,61 @@ pub struct UserWorkerProfile {
         mpsc::UnboundedSender<()>,
     ),
     pub service_path: String,
+    pub identity: WorkerIdentity,
     pub permit: Option<Arc<OwnedSemaphorePermit>>,
     pub cancel: CancellationToken,
+    /// Terminates the worker once the requests it is handling have completed.
+    pub termination: CancellationToken,
     pub status: TimingStatus,
     pub exit: WorkerExit,
+    pub verify_jwt: bool,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_path_prefixes: Option<Vec<String>>,
+    pub header_policy: Option<Arc<HeaderPolicy>>,
+    pub rewrite_policy: Option<Arc<RewritePolicy>>,
+    pub mirror_policy: Option<Arc<MirrorPolicy>>,
+    pub autoscale_policy: Option<Arc<AutoscalePolicy>>,
+    pub env_provider: EnvProvider,
+    pub env_allowlist: Option<Vec<String>>,
+    pub feature_flag_provider: FeatureFlagProvider,
+    pub boot_warnings: Vec<BootWarning>,
+    pub limits: WorkerLimits,
+    pub booted_at: Instant,
+    /// How long the worker took to boot, the wait for a boot slot included.
+    pub boot_duration: Duration,
+    /// Raised once the worker has been sent its first request, the only one
+    /// that waited for the boot.
+    pub has_served: Arc<AtomicBool>,
+    /// The session the worker was booted for, which sticks to it.
+    pub session_key: Option<String>,
+    pub pressure: Arc<WorkerPressure>,
+    pub failure_injection: Option<WorkerFailureInjection>,
+    /// The share of the memory budget of the pool the worker holds until it
+    /// is gone, if the pool has a budget.
+    pub memory_reservation: Option<Arc<OwnedSemaphorePermit>>,
+}
+
+/// The limits a user worker has been booted with.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerLimits {
+    pub memory_limit_mb: u64,
+    pub worker_timeout_ms: u64,
+    pub cpu_time_soft_limit_ms: u64,
+    pub cpu_time_hard_limit_ms: u64,
+    pub max_concurrent_fetches: u64,
+}
+
+impl From<&UserWorkerRuntimeOpts> for WorkerLimits {
+    fn from(conf: &UserWorkerRuntimeOpts) -> Self {
+        Self {
+            memory_limit_mb: conf.memory_limit_mb,
+            worker_timeout_ms: conf.worker_timeout_ms,
+            cpu_time_soft_limit_ms: conf.cpu_time_soft_limit_ms,
+            cpu_time_hard_limit_ms: conf.cpu_time_hard_limit_ms,
+            max_concurrent_fetches: conf.max_concurrent_fetches,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -112,6 +503,45 @@ pub struct MainWorkerRuntimeOpts {
     pub worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
     pub shared_metric_src: Option<SharedMetricSource>,
     pub event_worker_metric_src: Option<MetricSource>,
+    pub limits: MainWorkerLimits,
+    pub liveness: Option<MainWorkerLiveness>,
+}
+
+/// Limits of the main worker. `0` disables a limit, which is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MainWorkerLimits {
+    /// Maximum heap size of the main worker. Reaching it terminates the
+    /// worker.
+    pub memory_limit_mb: u64,
+    /// The main worker is restarted if its event loop does not turn for this
+    /// long.
+    pub unresponsive_timeout_ms: u64,
+}
+
+/// Lets the server check that the event loop of the main worker still turns.
+#[derive(Debug, Clone, Default)]
+pub struct MainWorkerLiveness {
+    waker: Arc<AtomicWaker>,
+    is_probing: Arc<AtomicFlag>,
+}
+
+impl MainWorkerLiveness {
+    /// Called by the event loop of the main worker on every turn.
+    pub fn ack(&self, waker: &Waker) {
+        self.waker.register(waker);
+        self.is_probing.lower();
+    }
+
+    /// Wakes up the event loop so that it acknowledges the probe.
+    pub fn probe(&self) {
+        self.is_probing.raise();
+        self.waker.wake();
+    }
+
+    /// Returns `false` if the event loop has not turned since the last probe.
+    pub fn is_alive(&self) -> bool {
+        !self.is_probing.is_raised()
+    }
 }
 
 #[derive(Debug)]
@@ -187,6 +617,16 @@ impl Default for Timing {
     }
 }
 
+/// The locale and the time zone a worker formats dates and numbers in, in
+/// place of the ones of the host.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerLocale {
+    /// A BCP 47 language tag, e.g. `de-CH`.
+    pub locale: Option<String>,
+    /// An IANA time zone, e.g. `Europe/Zurich`.
+    pub timezone: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct WorkerContextInitOpts {
     pub service_path: PathBuf,
@@ -197,10 +637,14 @@ pub struct WorkerContextInitOpts {
     pub conf: WorkerRuntimeOpts,
     pub maybe_eszip: Option<EszipPayloadKind>,
     pub maybe_module_code: Option<FastString>,
+    /// Modules the worker can import without them being on the file system,
+    /// by their specifier relative to the main module given in memory.
+    pub maybe_module_map: Option<HashMap<String, FastString>>,
     pub maybe_entrypoint: Option<String>,
     pub maybe_decorator: Option<DecoratorType>,
     pub static_patterns: Vec<String>,
     pub maybe_jsx_import_source_config: Option<JsxImportSourceConfig>,
+    pub locale: WorkerLocale,
 }
 
 #[derive(Debug)]
@@ -218,13 +662,224 @@ pub enum UserWorkerMsgs {
     ),
     Idle(Uuid),
     Shutdown(Uuid),
+    Terminate(Uuid, oneshot::Sender<bool>),
+    UpdateEnv(String, HashMap<String, String>, oneshot::Sender<usize>),
+    UpdateFeatureFlags(FeatureFlagsTarget, FeatureFlags, oneshot::Sender<usize>),
+    GetRequestLog(Uuid, oneshot::Sender<Option<Vec<RequestSummary>>>),
+    GetTraces(TraceQuery, oneshot::Sender<Vec<TraceRecord>>),
+    HealthCheck(Uuid, HealthCheck, oneshot::Sender<Option<HealthReport>>),
+    GetStatus(oneshot::Sender<Vec<ServiceStatus>>),
+    UpdatePolicy(PoolPolicyUpdate),
+    UpdateTenantWeights(HashMap<String, u32>),
 }
 
 pub type SendRequestResult = (Response<Body>, mpsc::UnboundedSender<()>);
 
+/// What the pool knows about the workers of a service.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceStatus {
+    /// The identity of the service as a single string, e.g. `acme/hello@v2`.
+    pub key: String,
+    #[serde(flatten)]
+    pub identity: WorkerIdentity,
+    /// Workers that are routed requests, the ones being drained excluded.
+    pub active_instances: usize,
+    /// Requests sent to the workers that have not been responded to yet.
+    pub in_flight_requests: usize,
+    /// Requests waiting for a worker, as the service has as many as allowed.
+    pub queue_depth: usize,
+    /// The limits of the most recently booted worker, if any is active.
+    pub limits: Option<WorkerLimits>,
+    pub last_termination: Option<LifecycleRecord>,
+    /// How long the oldest active worker has been running.
+    pub uptime_ms: Option<u64>,
+}
+
+/// A summary of a request handled by a user worker.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestSummary {
+    pub method: String,
+    pub path: String,
+    /// `None` if the request failed without a response.
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+    /// Sizes are only known if the body is not streamed.
+    pub request_size: Option<u64>,
+    pub response_size: Option<u64>,
+    pub error: Option<String>,
+    /// Milliseconds since the unix epoch.
+    pub started_at: u64,
+}
+
+/// The span of a request dispatched by the pool to a user worker, with the ids
+/// of the W3C trace context it was forwarded with.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceRecord {
+    pub trace_id: String,
+    pub span_id: String,
+    /// The span of the caller, if the request came with a `traceparent`.
+    pub parent_span_id: Option<String>,
+    pub service_path: String,
+    pub worker_key: Uuid,
+    pub method: String,
+    pub path: String,
+    /// `None` if the request failed without a response.
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    /// Milliseconds since the unix epoch.
+    pub started_at: u64,
+    pub duration_ms: u64,
+}
+
+/// Matches the status of a [`TraceRecord`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceStatusFilter {
+    /// A status code, e.g. `404`.
+    Exact(u16),
+    /// A class of status codes, e.g. `5xx`.
+    Class(u16),
+    /// No response at all.
+    Error,
+}
+
+impl FromStr for TraceStatusFilter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "error" {
+            return Ok(Self::Error);
+        }
+
+        if let Some(class) = s.strip_suffix("xx").filter(|it| it.len() == 1) {
+            return match class.parse::<u16>() {
+                Ok(class @ 1..=5) => Ok(Self::Class(class)),
+                _ => bail!("invalid status class: {s}"),
+            };
+        }
+
+        match s.parse::<u16>() {
+            Ok(status @ 100..=599) => Ok(Self::Exact(status)),
+            _ => bail!("invalid status: {s}"),
+        }
+    }
+}
+
+/// Selects the traces returned by the pool, from the newest to the oldest.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceQuery {
+    pub service_path: Option<String>,
+    pub status: Option<TraceStatusFilter>,
+    /// Returns every matching trace if not set.
+    pub limit: Option<usize>,
+}
+
+impl TraceQuery {
+    pub fn matches(&self, record: &TraceRecord) -> bool {
+        if self
+            .service_path
+            .as_ref()
+            .is_some_and(|it| *it != record.service_path)
+        {
+            return false;
+        }
+
+        match (self.status, record.status) {
+            (None, _) => true,
+            (Some(TraceStatusFilter::Exact(expected)), Some(status)) => expected == status,
+            (Some(TraceStatusFilter::Class(class)), Some(status)) => status / 100 == class,
+            (Some(TraceStatusFilter::Error), status) => status.is_none(),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// A synthetic request sent to a worker to tell whether it is healthy.
+///
+/// It bypasses the filters and the schedulers of the pool, and is neither
+/// logged nor counted in the metrics of the service.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct HealthCheck {
+    #[serde(default = "default_health_check_method")]
+    pub method: String,
+    #[serde(default = "default_health_check_path")]
+    pub path: String,
+    /// How long the worker has to respond before it is deemed unhealthy.
+    #[serde(default = "default_health_check_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            method: default_health_check_method(),
+            path: default_health_check_path(),
+            timeout_ms: default_health_check_timeout_ms(),
+        }
+    }
+}
+
+fn default_health_check_method() -> String {
+    String::from("GET")
+}
+
+fn default_health_check_path() -> String {
+    String::from("/__health")
+}
+
+fn default_health_check_timeout_ms() -> u64 {
+    5000
+}
+
+/// How a worker responded to a [`HealthCheck`].
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    /// `None` if the worker failed to respond.
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.status.is_some_and(|it| (200..300).contains(&it))
+    }
+}
+
 #[derive(Debug)]
 pub struct CreateUserWorkerResult {
     pub key: Uuid,
+    /// Also returned when an existing worker is handed out instead of a new
+    /// one, so that retrying a creation reports the same warnings.
+    pub warnings: Vec<BootWarning>,
+    pub decision: CreateDecision,
+}
+
+/// How the pool fulfilled the creation of a worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CreateDecision {
+    /// A new worker was booted.
+    Created,
+    /// An active worker of the service was handed out.
+    Reused,
+    /// The active workers of the service, created with other options, were
+    /// drained and a new worker was booted in their place.
+    Recreated,
+}
+
+impl fmt::Display for CreateDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Created => "created",
+            Self::Reused => "reused",
+            Self::Recreated => "recreated",
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -233,3 +888,37 @@ pub struct WorkerRequestMsg {
     pub res_tx: oneshot::Sender<Result<Response<Body>, hyper_v014::Error>>,
     pub conn_token: Option<CancellationToken>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::{UserWorkerRuntimeOpts, WorkerIdentity};
+    use crate::builder::UserWorkerBuilder;
+
+    fn identity(tenant: Option<&str>, revision: Option<&str>) -> WorkerIdentity {
+        let opts = UserWorkerBuilder::new("./hello")
+            .runtime_opts(UserWorkerRuntimeOpts {
+                tenant: tenant.map(str::to_string),
+                revision: revision.map(str::to_string),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+
+        WorkerIdentity::of(&opts)
+    }
+
+    #[test]
+    fn test_worker_identity() {
+        assert_eq!(identity(None, None).to_string(), "./hello");
+        assert_eq!(
+            identity(Some("acme"), Some("v2")).to_string(),
+            "acme/./hello@v2"
+        );
+
+        // NOTE: The same service is a different worker for every tenant and
+        // revision.
+        assert_ne!(identity(Some("acme"), None), identity(Some("other"), None));
+        assert_ne!(identity(None, Some("v1")), identity(None, Some("v2")));
+        assert_eq!(identity(Some("acme"), None), identity(Some("acme"), None));
+    }
+}
@@ -1,7 +1,131 @@
+use std::fmt;
+
+use anyhow::Error;
+use http_utils::utils::emit_problem_details;
+use hyper_v014::header::{self, HeaderValue};
+use hyper_v014::{Body, Response, StatusCode};
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WorkerError {
     #[error("request has been cancelled by supervisor")]
     RequestCancelledBySupervisor,
+    #[error("worker pool has too many pending requests")]
+    PoolOverloaded,
+    #[error("main worker is unresponsive")]
+    MainWorkerUnresponsive,
+    #[error("worker is no longer available")]
+    WorkerGone,
+    #[error("worker did not respond in time")]
+    TimedOut,
+    #[error("request has been shed since the service is near its limits")]
+    Shed { retry_after_sec: u64 },
+    #[error("insufficient resources to boot a worker: {reason}")]
+    InsufficientResources { reason: ResourceShortage },
+    #[error("worker is still starting")]
+    Starting,
+    #[error("client has disconnected")]
+    ClientDisconnected,
+}
+
+/// Why the code of a worker was refused while signatures are required.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BundleSignatureError {
+    #[error("the code of the worker is not signed")]
+    Unsigned,
+    #[error("the signature of the bundle is malformed")]
+    Malformed,
+    #[error("the signature of the bundle matches none of the trusted keys")]
+    Untrusted,
+}
+
+/// Why a worker was not created for a service whose active workers were
+/// created with other options, when the pool is set to reject it.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error(
+    "the active workers of the service were created with other options: {}",
+    .changed_options.join(", ")
+)]
+pub struct WorkerOptionsChangedError {
+    pub changed_options: Vec<String>,
+}
+
+/// What the host or the pool was short of when the boot of a worker was
+/// refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceShortage {
+    Memory,
+    Load,
+    MemoryBudget,
+}
+
+impl fmt::Display for ResourceShortage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Memory => "not enough free memory on the host",
+            Self::Load => "load average of the host too high",
+            Self::MemoryBudget => "memory budget of the pool exhausted",
+        })
+    }
+}
+
+impl WorkerError {
+    /// The status of the response to a request that failed with this error.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::RequestCancelledBySupervisor | Self::WorkerGone => StatusCode::BAD_GATEWAY,
+            Self::PoolOverloaded
+            | Self::MainWorkerUnresponsive
+            | Self::Shed { .. }
+            | Self::InsufficientResources { .. }
+            | Self::Starting => StatusCode::SERVICE_UNAVAILABLE,
+            Self::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+            // NOTE: Nobody is there to read it, but the request logs use the
+            // status some proxies use for it (client closed request).
+            Self::ClientDisconnected => StatusCode::from_u16(499).unwrap(),
+        }
+    }
+}
+
+/// Answers a request that a worker failed to respond to with a problem
+/// details response. Errors other than a [`WorkerError`] are answered with
+/// 500.
+///
+/// NOTE: Only the [`WorkerError`] itself is shown to the client. Anything else
+/// in the chain may tell about the host (paths, addresses, the code of other
+/// services), so it is left to the logs of the caller.
+pub fn emit_worker_error(err: &Error) -> Response<Body> {
+    let maybe_err = err.downcast_ref::<WorkerError>();
+    let status = maybe_err.map_or(StatusCode::INTERNAL_SERVER_ERROR, WorkerError::status_code);
+    let detail = maybe_err.map_or_else(|| "internal server error".to_string(), ToString::to_string);
+    let mut res = emit_problem_details(status, &detail);
+
+    if let Some(WorkerError::Shed { retry_after_sec }) = maybe_err {
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(*retry_after_sec));
+    }
+
+    res
+}
+
+#[derive(Error, Debug)]
+pub enum WorkerOptsError {
+    #[error("module code cannot be used together with an eszip")]
+    ModuleCodeWithEszip,
+    #[error("module code cannot be used together with an entrypoint")]
+    ModuleCodeWithEntrypoint,
+    #[error("in-memory modules cannot be used together with an eszip")]
+    ModuleMapWithEszip,
+    #[error("invalid entrypoint {0}: {1}")]
+    InvalidEntrypoint(String, deno_core::url::ParseError),
+    #[error("cpu time soft limit ({0}ms) must not exceed the hard limit ({1}ms)")]
+    CpuTimeSoftLimitExceedsHardLimit(u64, u64),
+    #[error("memory limit must be greater than zero")]
+    ZeroMemoryLimit,
+    #[error("events worker requires a receiver for worker events")]
+    MissingEventsReceiver,
+    #[error("invalid locale {0}")]
+    InvalidLocale(String),
+    #[error("invalid time zone {0}")]
+    InvalidTimezone(String),
 }
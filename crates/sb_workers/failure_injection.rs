@@ -0,0 +1,134 @@
+//! Failures a user worker can ask the runtime to simulate, so that function
+//! authors can test their retry and cleanup logic locally. Only available if
+//! the runtime is started with `--enable-failure-injection`.
+//!
+//! The failures are queued per service, so that the ones meant for the next
+//! boot survive the termination of the worker that queued them.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use deno_core::error::{custom_error, AnyError};
+use deno_core::{op2, OpState};
+use serde::Deserialize;
+use tokio::sync::watch;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "kind", deny_unknown_fields)]
+pub enum InjectedFailure {
+    /// Terminates the worker handling the next request as if it had run out
+    /// of memory.
+    MemoryLimit,
+    /// Terminates the worker handling the next request as if it had used up
+    /// its CPU time.
+    CpuTimeLimit,
+    /// Drops the next request without a response, as if the worker had gone
+    /// away before answering it.
+    DropResponse,
+    /// Delays the next boot of a worker of the service.
+    BootDelay {
+        #[serde(rename = "delayMs")]
+        delay_ms: u64,
+    },
+}
+
+/// The failures queued for a service.
+#[derive(Debug, Default)]
+pub struct FailureInjector {
+    pending: Mutex<VecDeque<InjectedFailure>>,
+}
+
+impl FailureInjector {
+    pub fn fail_next(&self, failure: InjectedFailure) {
+        self.pending.lock().unwrap().push_back(failure);
+    }
+
+    /// Takes the failure the next request to the service must go through, if
+    /// any.
+    pub fn take_request_failure(&self) -> Option<InjectedFailure> {
+        self.take(|it| !matches!(it, InjectedFailure::BootDelay { .. }))
+    }
+
+    /// Takes the delay the next boot of a worker of the service must go
+    /// through, if any.
+    pub fn take_boot_delay(&self) -> Option<Duration> {
+        match self.take(|it| matches!(it, InjectedFailure::BootDelay { .. }))? {
+            InjectedFailure::BootDelay { delay_ms } => Some(Duration::from_millis(delay_ms)),
+            _ => None,
+        }
+    }
+
+    fn take(&self, predicate: impl Fn(&InjectedFailure) -> bool) -> Option<InjectedFailure> {
+        let mut pending = self.pending.lock().unwrap();
+        let idx = pending.iter().position(predicate)?;
+
+        pending.remove(idx)
+    }
+}
+
+/// What a user worker needs to have failures injected. Set by the pool.
+#[derive(Debug, Clone)]
+pub struct WorkerFailureInjection {
+    pub injector: Arc<FailureInjector>,
+    /// Makes the supervisor of the worker terminate it as if it had hit the
+    /// limit of the failure.
+    pub limit_tx: Arc<watch::Sender<Option<InjectedFailure>>>,
+}
+
+impl WorkerFailureInjection {
+    pub fn new(injector: Arc<FailureInjector>) -> Self {
+        Self {
+            injector,
+            limit_tx: Arc::new(watch::channel(None).0),
+        }
+    }
+}
+
+#[op2]
+pub fn op_user_worker_fail_next(
+    state: &mut OpState,
+    #[serde] failure: InjectedFailure,
+) -> Result<(), AnyError> {
+    let Some(injection) = state.try_borrow::<WorkerFailureInjection>() else {
+        return Err(custom_error(
+            "NotSupported",
+            "failure injection is not enabled (see --enable-failure-injection)",
+        ));
+    };
+
+    injection.injector.fail_next(failure);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{FailureInjector, InjectedFailure};
+
+    #[test]
+    fn test_failure_injector() {
+        let injector = FailureInjector::default();
+
+        injector.fail_next(serde_json::from_str(r#"{"kind":"bootDelay","delayMs":250}"#).unwrap());
+        injector.fail_next(serde_json::from_str(r#"{"kind":"memoryLimit"}"#).unwrap());
+        injector.fail_next(InjectedFailure::DropResponse);
+
+        assert_eq!(
+            injector.take_request_failure(),
+            Some(InjectedFailure::MemoryLimit)
+        );
+        assert_eq!(
+            injector.take_request_failure(),
+            Some(InjectedFailure::DropResponse)
+        );
+        assert_eq!(injector.take_request_failure(), None);
+
+        assert_eq!(injector.take_boot_delay(), Some(Duration::from_millis(250)));
+        assert_eq!(injector.take_boot_delay(), None);
+
+        assert!(serde_json::from_str::<InjectedFailure>(r#"{"kind":"oom"}"#).is_err());
+    }
+}
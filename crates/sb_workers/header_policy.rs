@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+
+use hyper_v014::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper_v014::{Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+
+/// How the responses of a service are rewritten before they leave the
+/// runtime, so that functions do not have to deal with CORS or security
+/// headers themselves.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct HeaderPolicy {
+    pub cors: Option<CorsPolicy>,
+    /// Adds `X-Content-Type-Options`, `X-Frame-Options` and `Referrer-Policy`.
+    #[serde(default)]
+    pub security_headers: bool,
+    /// Adds `Strict-Transport-Security` with this `max-age`.
+    pub hsts_max_age_sec: Option<u64>,
+    /// Headers added to every response unless the worker has set them.
+    #[serde(default)]
+    pub set_headers: BTreeMap<String, String>,
+    /// Headers removed from every response. A trailing `*` matches any header
+    /// that starts with the rest of the name (e.g. `x-internal-*`).
+    #[serde(default)]
+    pub strip_headers: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CorsPolicy {
+    /// Origins allowed to read the responses. `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed in preflights. Any method is allowed if omitted.
+    pub allowed_methods: Option<Vec<String>>,
+    /// Headers allowed in preflights. The requested headers are allowed if
+    /// omitted.
+    pub allowed_headers: Option<Vec<String>>,
+    #[serde(default)]
+    pub exposed_headers: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    pub max_age_sec: Option<u64>,
+}
+
+impl CorsPolicy {
+    fn is_origin_allowed(&self, origin: &HeaderValue) -> bool {
+        let Ok(origin) = origin.to_str() else {
+            return false;
+        };
+
+        self.allowed_origins
+            .iter()
+            .any(|it| it == "*" || it.eq_ignore_ascii_case(origin))
+    }
+}
+
+impl HeaderPolicy {
+    /// Answers a CORS preflight request on behalf of the worker. Returns `None`
+    /// if the request is not a preflight or no CORS policy is configured.
+    ///
+    /// The headers shared with actual responses are added by [`Self::apply`],
+    /// which must be called on the returned response as well.
+    pub fn preflight(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        let cors = self.cors.as_ref()?;
+        let headers = req.headers();
+
+        if req.method() != Method::OPTIONS
+            || !headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            return None;
+        }
+
+        let origin = headers.get(header::ORIGIN)?;
+        let mut res = Response::new(Body::empty());
+
+        *res.status_mut() = StatusCode::NO_CONTENT;
+
+        if !cors.is_origin_allowed(origin) {
+            return Some(res);
+        }
+
+        let res_headers = res.headers_mut();
+        let allowed_methods = match cors.allowed_methods.as_ref() {
+            Some(methods) => HeaderValue::from_str(&methods.join(", ")).ok(),
+            None => headers.get(header::ACCESS_CONTROL_REQUEST_METHOD).cloned(),
+        };
+        let allowed_headers = match cors.allowed_headers.as_ref() {
+            Some(names) => HeaderValue::from_str(&names.join(", ")).ok(),
+            None => headers.get(header::ACCESS_CONTROL_REQUEST_HEADERS).cloned(),
+        };
+
+        if let Some(value) = allowed_methods {
+            res_headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, value);
+        }
+        if let Some(value) = allowed_headers {
+            res_headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, value);
+        }
+        if let Some(max_age) = cors.max_age_sec {
+            res_headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age.into());
+        }
+
+        Some(res)
+    }
+
+    /// Rewrites the headers of a response to the request sent from `origin`.
+    pub fn apply(&self, origin: Option<&HeaderValue>, res: &mut Response<Body>) {
+        let headers = res.headers_mut();
+
+        if !self.strip_headers.is_empty() {
+            strip_headers(headers, &self.strip_headers);
+        }
+
+        if let Some((cors, origin)) = self.cors.as_ref().zip(origin) {
+            if cors.is_origin_allowed(origin) {
+                apply_cors(cors, origin, headers);
+            }
+        }
+
+        if self.security_headers {
+            let defaults = [
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+                (header::X_FRAME_OPTIONS, "DENY"),
+                (header::REFERRER_POLICY, "strict-origin-when-cross-origin"),
+            ];
+
+            for (name, value) in defaults {
+                headers
+                    .entry(name)
+                    .or_insert(HeaderValue::from_static(value));
+            }
+        }
+
+        if let Some(max_age) = self.hsts_max_age_sec {
+            if let Ok(value) = HeaderValue::from_str(&format!("max-age={max_age}")) {
+                headers
+                    .entry(header::STRICT_TRANSPORT_SECURITY)
+                    .or_insert(value);
+            }
+        }
+
+        for (name, value) in self.set_headers.iter() {
+            let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) else {
+                continue;
+            };
+
+            headers.entry(name).or_insert(value);
+        }
+    }
+}
+
+pub(crate) fn strip_headers(headers: &mut HeaderMap, patterns: &[String]) {
+    let names = headers
+        .keys()
+        .filter(|name| {
+            patterns
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => name
+                        .as_str()
+                        .get(..prefix.len())
+                        .map_or(false, |it| it.eq_ignore_ascii_case(prefix)),
+                    None => name.as_str().eq_ignore_ascii_case(pattern),
+                })
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for name in names {
+        headers.remove(name);
+    }
+}
+
+fn apply_cors(cors: &CorsPolicy, origin: &HeaderValue, headers: &mut HeaderMap) {
+    // NOTE: Browsers refuse a wildcard along with credentials, so the origin
+    // is echoed back in that case.
+    let is_wildcard = cors.allowed_origins.iter().any(|it| it == "*");
+    let allow_origin = if is_wildcard && !cors.allow_credentials {
+        HeaderValue::from_static("*")
+    } else {
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        origin.clone()
+    };
+
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+
+    if cors.allow_credentials {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+
+    if !cors.exposed_headers.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(&cors.exposed_headers.join(", ")) {
+            headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper_v014::header::HeaderValue;
+    use hyper_v014::{Body, Request, Response, StatusCode};
+
+    use super::{CorsPolicy, HeaderPolicy};
+
+    #[test]
+    fn test_header_policy() {
+        let policy = HeaderPolicy {
+            cors: Some(CorsPolicy {
+                allowed_origins: vec!["https://example.com".to_string()],
+                allowed_methods: Some(vec!["GET".to_string(), "POST".to_string()]),
+                max_age_sec: Some(600),
+                ..Default::default()
+            }),
+            security_headers: true,
+            strip_headers: vec!["x-internal-*".to_string()],
+            ..Default::default()
+        };
+
+        let preflight = |origin: &str| {
+            Request::builder()
+                .method("OPTIONS")
+                .header("origin", origin)
+                .header("access-control-request-method", "POST")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = policy.preflight(&preflight("https://example.com")).unwrap();
+
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.headers()["access-control-allow-methods"], "GET, POST");
+        assert_eq!(res.headers()["access-control-max-age"], "600");
+
+        let res = policy.preflight(&preflight("https://evil.com")).unwrap();
+
+        assert!(!res.headers().contains_key("access-control-allow-methods"));
+
+        let mut res = Response::builder()
+            .header("x-internal-trace", "1")
+            .header("x-frame-options", "SAMEORIGIN")
+            .body(Body::empty())
+            .unwrap();
+
+        policy.apply(
+            Some(&HeaderValue::from_static("https://example.com")),
+            &mut res,
+        );
+
+        assert!(!res.headers().contains_key("x-internal-trace"));
+        assert_eq!(res.headers()["x-frame-options"], "SAMEORIGIN");
+        assert_eq!(res.headers()["x-content-type-options"], "nosniff");
+        assert_eq!(
+            res.headers()["access-control-allow-origin"],
+            "https://example.com"
+        );
+        assert_eq!(res.headers()["vary"], "Origin");
+    }
+}
@@ -0,0 +1,295 @@
+//! Invocations of the services of the pool by user workers, which go straight
+//! to the worker of the service instead of through the network.
+//!
+//! A user worker can only invoke the services next to its own, i.e. the other
+//! functions of the functions directory. The services an invocation went
+//! through are kept by the runtime along with the request, so that loops are
+//! cut off before they exhaust the pool, and the invocations made on behalf of
+//! a request share a single budget.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+
+use crate::context::{UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerLimits};
+
+pub static INVOKE_CHAIN_HEADER: &str = "x-edge-runtime-invoke-chain";
+
+/// How many invocations deep a request may go.
+pub static MAX_INVOKE_HOPS: usize = 8;
+
+/// How many invocations a request may make in total, counting those made by
+/// the services it invokes.
+pub static MAX_INVOKES_PER_CHAIN: usize = 32;
+
+/// Lets a user worker managed by a pool invoke the other services of the
+/// pool. Put into the op state of the worker.
+#[derive(Clone)]
+pub struct ServiceInvoker {
+    pub pool_msg_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    /// The service of the worker, whose siblings it can invoke.
+    pub service_path: String,
+    pub tenant: Option<String>,
+    /// The limits of the worker, which the invoked workers are booted with.
+    pub limits: WorkerLimits,
+    /// The chains of the invocations the worker is handling.
+    pub chains: InvokeChains,
+}
+
+impl ServiceInvoker {
+    /// Returns the service of the worker as it appears in invocation chains.
+    pub fn caller(&self) -> String {
+        // NOTE: The path is rebuilt from its components, so that it is written
+        // the same way as the services it invokes.
+        Path::new(&self.service_path)
+            .components()
+            .collect::<PathBuf>()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Returns the service of the function with the given name, next to the
+    /// service of the worker.
+    pub fn resolve(&self, name: &str) -> Option<PathBuf> {
+        // NOTE: The name is used as a path component, so anything that could
+        // escape from the directory of the services must be rejected.
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '%', '\0']) {
+            return None;
+        }
+
+        let service_path = Path::new(&self.caller()).parent()?.join(name);
+
+        service_path.is_dir().then_some(service_path)
+    }
+
+    /// Returns the options the invoked worker is booted with. It gets the env
+    /// vars of its own service, not those of the worker.
+    pub fn runtime_opts(&self) -> UserWorkerRuntimeOpts {
+        UserWorkerRuntimeOpts {
+            tenant: self.tenant.clone(),
+            memory_limit_mb: self.limits.memory_limit_mb,
+            worker_timeout_ms: self.limits.worker_timeout_ms,
+            cpu_time_soft_limit_ms: self.limits.cpu_time_soft_limit_ms,
+            cpu_time_hard_limit_ms: self.limits.cpu_time_hard_limit_ms,
+            max_concurrent_fetches: self.limits.max_concurrent_fetches,
+            uses_service_env: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum InvokeChainError {
+    #[error("invocation loop detected: {0} has already been invoked")]
+    Loop(String),
+    #[error("too many nested invocations (limit: {MAX_INVOKE_HOPS})")]
+    TooDeep,
+    #[error("too many invocations for a single request (limit: {MAX_INVOKES_PER_CHAIN})")]
+    BudgetExhausted,
+}
+
+/// The services an invocation went through, and how many more invocations
+/// can be made on behalf of the request that started it.
+#[derive(Debug)]
+pub struct InvokeChain {
+    services: Vec<String>,
+    budget: Arc<AtomicUsize>,
+}
+
+impl InvokeChain {
+    pub fn services(&self) -> &[String] {
+        &self.services
+    }
+
+    /// Returns the chain the invocation of `target` by `caller` carries on,
+    /// given the chain of the request the caller is handling, if any.
+    pub fn extend(
+        maybe_parent: Option<&Self>,
+        caller: &str,
+        target: &str,
+    ) -> Result<Self, InvokeChainError> {
+        let (mut services, budget) = match maybe_parent {
+            Some(parent) => (parent.services.clone(), parent.budget.clone()),
+            None => (vec![], Arc::new(AtomicUsize::new(MAX_INVOKES_PER_CHAIN))),
+        };
+
+        if services.last().map(String::as_str) != Some(caller) {
+            services.push(caller.to_string());
+        }
+
+        if services.iter().any(|it| it == target) {
+            return Err(InvokeChainError::Loop(target.to_string()));
+        }
+
+        if services.len() > MAX_INVOKE_HOPS {
+            return Err(InvokeChainError::TooDeep);
+        }
+
+        budget
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |it| it.checked_sub(1))
+            .map_err(|_| InvokeChainError::BudgetExhausted)?;
+
+        services.push(target.to_string());
+
+        Ok(Self { services, budget })
+    }
+}
+
+/// The chains of the invocations a worker is handling. Shared between the
+/// op state of the worker and what hands the requests over to it, so that JS
+/// has no say in them.
+#[derive(Debug, Clone, Default)]
+pub struct InvokeChains(Arc<Mutex<Vec<Arc<InvokeChain>>>>);
+
+impl InvokeChains {
+    /// Marks the chain as handled by the worker until the guard is dropped.
+    pub fn enter(&self, chain: Arc<InvokeChain>) -> InvokeChainGuard {
+        self.0.lock().unwrap().push(chain.clone());
+
+        InvokeChainGuard {
+            chains: self.clone(),
+            chain,
+        }
+    }
+
+    /// Returns the chain the invocations of the worker carry on, if any.
+    ///
+    /// NOTE: A worker handling several requests at once can't tell which of
+    /// them an invocation is made for, so it carries on the longest chain. The
+    /// chains then only grow, so that a loop is always cut off.
+    pub fn longest(&self) -> Option<Arc<InvokeChain>> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .max_by_key(|it| it.services.len())
+            .cloned()
+    }
+}
+
+pub struct InvokeChainGuard {
+    chains: InvokeChains,
+    chain: Arc<InvokeChain>,
+}
+
+impl Drop for InvokeChainGuard {
+    fn drop(&mut self) {
+        let mut chains = self.chains.0.lock().unwrap();
+
+        if let Some(idx) = chains.iter().position(|it| Arc::ptr_eq(it, &self.chain)) {
+            chains.swap_remove(idx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use crate::context::WorkerLimits;
+
+    use super::{
+        InvokeChain, InvokeChainError, InvokeChains, ServiceInvoker, MAX_INVOKES_PER_CHAIN,
+        MAX_INVOKE_HOPS,
+    };
+
+    #[test]
+    fn test_service_invoker_resolve() {
+        let (pool_msg_tx, _) = mpsc::unbounded_channel();
+        let invoker = ServiceInvoker {
+            pool_msg_tx,
+            service_path: "./examples/hello-world/".to_string(),
+            tenant: None,
+            limits: WorkerLimits::default(),
+            chains: InvokeChains::default(),
+        };
+
+        assert_eq!(invoker.caller(), "./examples/hello-world");
+
+        for name in ["", ".", "..", "../etc", "a/b", "a%2fb"] {
+            assert!(invoker.resolve(name).is_none());
+        }
+    }
+
+    fn services(
+        result: Result<InvokeChain, InvokeChainError>,
+    ) -> Result<Vec<String>, InvokeChainError> {
+        result.map(|it| it.services().to_vec())
+    }
+
+    #[test]
+    fn test_extend_invoke_chain() {
+        let chain = InvokeChain::extend(None, "./a", "./b").unwrap();
+
+        assert_eq!(chain.services(), ["./a", "./b"]);
+        assert_eq!(
+            services(InvokeChain::extend(Some(&chain), "./b", "./c")),
+            Ok(vec![
+                "./a".to_string(),
+                "./b".to_string(),
+                "./c".to_string()
+            ])
+        );
+        assert_eq!(
+            services(InvokeChain::extend(Some(&chain), "./b", "./a")),
+            Err(InvokeChainError::Loop("./a".to_string()))
+        );
+        assert_eq!(
+            services(InvokeChain::extend(None, "./a", "./a")),
+            Err(InvokeChainError::Loop("./a".to_string()))
+        );
+
+        let mut chain = InvokeChain::extend(None, "./0", "./1").unwrap();
+
+        for it in 2..=MAX_INVOKE_HOPS {
+            chain = InvokeChain::extend(Some(&chain), &format!("./{}", it - 1), &format!("./{it}"))
+                .unwrap();
+        }
+
+        assert_eq!(chain.services().len(), MAX_INVOKE_HOPS + 1);
+        assert_eq!(
+            services(InvokeChain::extend(
+                Some(&chain),
+                &format!("./{MAX_INVOKE_HOPS}"),
+                "./z"
+            )),
+            Err(InvokeChainError::TooDeep)
+        );
+    }
+
+    #[test]
+    fn test_invoke_chain_budget_is_shared() {
+        let root = InvokeChain::extend(None, "./a", "./b").unwrap();
+
+        for _ in 1..MAX_INVOKES_PER_CHAIN {
+            assert!(InvokeChain::extend(Some(&root), "./b", "./c").is_ok());
+        }
+
+        assert_eq!(
+            services(InvokeChain::extend(Some(&root), "./b", "./d")),
+            Err(InvokeChainError::BudgetExhausted)
+        );
+    }
+
+    #[test]
+    fn test_invoke_chains_carry_on_the_longest() {
+        let chains = InvokeChains::default();
+        let short = Arc::new(InvokeChain::extend(None, "./a", "./b").unwrap());
+        let long = Arc::new(InvokeChain::extend(Some(&short), "./b", "./c").unwrap());
+
+        assert!(chains.longest().is_none());
+
+        let _short_guard = chains.enter(short.clone());
+        let long_guard = chains.enter(long.clone());
+
+        assert!(Arc::ptr_eq(&chains.longest().unwrap(), &long));
+
+        drop(long_guard);
+
+        assert!(Arc::ptr_eq(&chains.longest().unwrap(), &short));
+    }
+}
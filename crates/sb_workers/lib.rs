@@ -1,9 +1,17 @@
+pub mod builder;
 pub mod context;
 pub mod errors;
-
+pub mod failure_injection;
+pub mod header_policy;
+pub mod invoke;
+pub mod request_limits;
+pub mod rewrite_policy;
+pub mod task_queue;
+
+use crate::builder::UserWorkerBuilder;
 use crate::context::{
-    CreateUserWorkerResult, UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts,
-    WorkerRuntimeOpts,
+    CreateDecision, CreateUserWorkerResult, FeatureFlagsTarget, UserWorkerMsgs,
+    UserWorkerRuntimeOpts,
 };
 use anyhow::Error;
 use context::SendRequestResult;
@@ -17,22 +25,30 @@ use deno_core::{
     JsBuffer, OpState, RcRef, Resource, ResourceId, WriteOutcome,
 };
 use deno_http::{HttpRequestReader, HttpStreamReadResource};
-use errors::WorkerError;
+use errors::{BundleSignatureError, WorkerError, WorkerOptionsChangedError};
+use event_worker::events::BootWarning;
 use http_utils::utils::get_upgrade_type;
 use hyper_v014::body::HttpBody;
-use hyper_v014::header::{HeaderName, HeaderValue, CONTENT_LENGTH};
+use hyper_v014::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, TRAILER, TRANSFER_ENCODING,
+};
 use hyper_v014::upgrade::OnUpgrade;
-use hyper_v014::{Body, Method, Request};
+use hyper_v014::{Body, Method, Request, Response};
+use invoke::{InvokeChain, ServiceInvoker, INVOKE_CHAIN_HEADER};
 use log::error;
+use request_limits::RequestLimits;
+use sb_core::cert::OutboundTlsOptions;
 use sb_core::conn_sync::ConnWatcher;
+use sb_core::feature_flags::FeatureFlags;
+use sb_core::fetch_budget::{acquire_fetch_permit, BudgetedResponseBody};
 use sb_graph::{DecoratorType, EszipPayloadKind};
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::path::PathBuf;
 use std::pin::Pin;
 use std::rc::Rc;
-use std::task::{Context, Poll};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
 use tokio::sync::{mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
@@ -43,6 +59,13 @@ deno_core::extension!(
         op_user_worker_create,
         op_user_worker_fetch_build,
         op_user_worker_fetch_send,
+        op_user_worker_invoke,
+        op_user_worker_response_trailers,
+        op_user_worker_update_env,
+        op_user_worker_update_feature_flags,
+        op_user_worker_sign_limits,
+        failure_injection::op_user_worker_fail_next,
+        task_queue::op_task_queue_enqueue,
     ],
     esm_entry_point = "ext:sb_user_workers/user_workers.js",
     esm = ["user_workers.js",]
@@ -64,30 +87,73 @@ pub struct UserWorkerCreateOptions {
     import_map_path: Option<String>,
     env_vars: Vec<(String, String)>,
     force_create: bool,
+    idempotency_key: Option<String>,
+    session_key: Option<String>,
     allow_remote_modules: bool,
+    allow_shared_array_buffer: bool,
+    allow_wasm_threads: bool,
+    allow_atomics_wait: bool,
     net_access_disabled: bool,
     allow_net: Option<Vec<String>>,
+    allow_sockets: Option<Vec<String>>,
     custom_module_root: Option<String>,
     maybe_eszip: Option<JsBuffer>,
+    maybe_eszip_signature: Option<String>,
     maybe_entrypoint: Option<String>,
     maybe_module_code: Option<String>,
+    maybe_module_map: Option<HashMap<String, String>>,
+    allowed_methods: Option<Vec<String>>,
+    allowed_path_prefixes: Option<Vec<String>>,
+    outbound_tls: Option<OutboundTlsOptions>,
+    tenant: Option<String>,
+    revision: Option<String>,
+    feature_flags: FeatureFlags,
 
     memory_limit_mb: u64,
     low_memory_multiplier: u64,
+    memory_leak_trend_samples: u64,
+    recycle_on_memory_leak: bool,
+    heap_initial_mb: u64,
+    heap_max_mb: u64,
+    low_memory_notification_percent: u64,
     worker_timeout_ms: u64,
     cpu_time_soft_limit_ms: u64,
     cpu_time_hard_limit_ms: u64,
+    cpu_time_cap_ms: u64,
+    request_cpu_time_cap_ms: u64,
+    event_loop_lag_warn_ms: u64,
+    event_loop_lag_limit_ms: u64,
+    max_resources: u64,
+    max_websocket_connections: u64,
+    websocket_idle_timeout_ms: u64,
+    fetch_timeout_ms: u64,
+    max_concurrent_fetches: u64,
+    max_emails_per_minute: u64,
+    clock_granularity_ms: u64,
+    log_sample_rate: f64,
+    log_rate_limit: u64,
+    locale: Option<String>,
+    timezone: Option<String>,
 
     jsx_import_source_config: Option<JsxImportBaseConfig>,
     decorator_type: Option<DecoratorType>,
 }
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserWorkerCreateResponse {
+    key: String,
+    /// What went wrong while booting the worker, without failing the boot.
+    warnings: Vec<BootWarning>,
+    decision: CreateDecision,
+}
+
 #[op2(async)]
-#[string]
+#[serde]
 pub async fn op_user_worker_create(
     state: Rc<RefCell<OpState>>,
     #[serde] opts: UserWorkerCreateOptions,
-) -> Result<String, AnyError> {
+) -> Result<UserWorkerCreateResponse, AnyError> {
     let result_rx = {
         let op_state = state.borrow();
         let tx = op_state.borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>();
@@ -99,19 +165,53 @@ pub async fn op_user_worker_create(
             import_map_path,
             env_vars,
             force_create,
+            idempotency_key,
+            session_key,
             net_access_disabled,
             allow_net,
+            allow_sockets,
             allow_remote_modules,
+            allow_shared_array_buffer,
+            allow_wasm_threads,
+            allow_atomics_wait,
             custom_module_root,
             maybe_eszip,
+            maybe_eszip_signature,
             maybe_entrypoint,
             maybe_module_code,
+            maybe_module_map,
+            allowed_methods,
+            allowed_path_prefixes,
+            outbound_tls,
+            tenant,
+            revision,
+            feature_flags,
 
             memory_limit_mb,
             low_memory_multiplier,
+            memory_leak_trend_samples,
+            recycle_on_memory_leak,
+            heap_initial_mb,
+            heap_max_mb,
+            low_memory_notification_percent,
             worker_timeout_ms,
             cpu_time_soft_limit_ms,
             cpu_time_hard_limit_ms,
+            cpu_time_cap_ms,
+            request_cpu_time_cap_ms,
+            event_loop_lag_warn_ms,
+            event_loop_lag_limit_ms,
+            max_resources,
+            max_websocket_connections,
+            websocket_idle_timeout_ms,
+            fetch_timeout_ms,
+            max_concurrent_fetches,
+            max_emails_per_minute,
+            clock_granularity_ms,
+            log_sample_rate,
+            log_rate_limit,
+            locale,
+            timezone,
             jsx_import_source_config,
             decorator_type: maybe_decorator,
         } = opts;
@@ -137,36 +237,83 @@ pub async fn op_user_worker_create(
             }
         };
 
-        let user_worker_options = WorkerContextInitOpts {
-            service_path: PathBuf::from(service_path),
-            no_module_cache,
-            import_map_path,
-            env_vars: env_vars_map,
-            timing: None,
-            maybe_eszip: maybe_eszip.map(EszipPayloadKind::JsBufferKind),
-            maybe_entrypoint,
-            maybe_module_code: maybe_module_code.map(|v| v.into()),
-            maybe_decorator,
-            conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+        let user_worker_options = UserWorkerBuilder::new(service_path)
+            .no_module_cache(no_module_cache)
+            .import_map_path(import_map_path)
+            .env_vars(env_vars_map)
+            .eszip(maybe_eszip.map(EszipPayloadKind::JsBufferKind))
+            .entrypoint(maybe_entrypoint)
+            .module_code(maybe_module_code.map(|v| v.into()))
+            .module_map(maybe_module_map.map(|it| {
+                it.into_iter()
+                    .map(|(specifier, code)| (specifier, code.into()))
+                    .collect()
+            }))
+            .decorator(maybe_decorator)
+            .jsx_import_source_config(jsx_import_conf)
+            .locale(locale)
+            .timezone(timezone)
+            .runtime_opts(UserWorkerRuntimeOpts {
                 memory_limit_mb,
                 low_memory_multiplier,
+                memory_leak_trend_samples,
+                recycle_on_memory_leak,
+                heap_initial_mb,
+                heap_max_mb,
+                low_memory_notification_percent,
                 worker_timeout_ms,
                 cpu_time_soft_limit_ms,
                 cpu_time_hard_limit_ms,
+                cpu_time_cap_ms,
+                request_cpu_time_cap_ms,
+                event_loop_lag_warn_ms,
+                event_loop_lag_limit_ms,
+                max_resources,
+                max_websocket_connections,
+                websocket_idle_timeout_ms,
+                fetch_timeout_ms,
+                max_concurrent_fetches,
+                max_emails_per_minute,
+                clock_granularity_ms,
+                log_sample_rate,
+                log_rate_limit,
+                drain_timeout_ms: 0,
+                boot_queue_us: 0,
+                code_cache: None,
+                boot_warnings: vec![],
+                pressure: None,
+                failure_injection: None,
                 force_create,
+                idempotency_key,
+                session_key,
                 net_access_disabled,
                 allow_net,
+                allow_sockets,
                 allow_remote_modules,
+                allow_shared_array_buffer,
+                allow_wasm_threads,
+                allow_atomics_wait,
                 custom_module_root,
+                eszip_signature: maybe_eszip_signature,
                 key: None,
                 pool_msg_tx: None,
                 events_msg_tx: None,
                 cancel: None,
                 service_path: None,
-            }),
-            static_patterns: vec![],
-            maybe_jsx_import_source_config: jsx_import_conf,
-        };
+                allowed_methods,
+                allowed_path_prefixes,
+                outbound_tls,
+                identity: None,
+                tenant,
+                revision,
+                env_provider: None,
+                uses_service_env: false,
+                invoke_chains: None,
+                feature_flags,
+                feature_flag_provider: None,
+            })
+            .build()
+            .map_err(|err| custom_error("InvalidWorkerCreation", err.to_string()))?;
 
         tx.send(UserWorkerMsgs::Create(user_worker_options, result_tx))?;
         result_rx
@@ -183,9 +330,96 @@ pub async fn op_user_worker_create(
     // channel returns a Result<T, E>, we need to unwrap it first;
     let result = result.unwrap();
     match result {
-        Err(e) => Err(custom_error("InvalidWorkerCreation", format!("{e:#}"))),
-        Ok(res) => Ok(res.key.to_string()),
+        Err(e) => match e.downcast_ref() {
+            Some(err @ WorkerError::InsufficientResources { .. }) => {
+                Err(custom_error("InsufficientResources", err.to_string()))
+            }
+
+            _ if e.downcast_ref::<BundleSignatureError>().is_some() => {
+                Err(custom_error("UnsignedBundle", format!("{e:#}")))
+            }
+
+            _ if e.downcast_ref::<WorkerOptionsChangedError>().is_some() => {
+                Err(custom_error("WorkerOptionsChanged", format!("{e:#}")))
+            }
+
+            _ => Err(custom_error("InvalidWorkerCreation", format!("{e:#}"))),
+        },
+        Ok(res) => Ok(UserWorkerCreateResponse {
+            key: res.key.to_string(),
+            warnings: res.warnings,
+            decision: res.decision,
+        }),
+    }
+}
+
+#[op2(async)]
+#[smi]
+pub async fn op_user_worker_update_env(
+    state: Rc<RefCell<OpState>>,
+    #[string] service_path: String,
+    #[serde] env_vars: Vec<(String, String)>,
+) -> Result<u32, AnyError> {
+    let (result_tx, result_rx) = oneshot::channel::<usize>();
+
+    {
+        let op_state = state.borrow();
+        let tx = op_state.borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>();
+
+        tx.send(UserWorkerMsgs::UpdateEnv(
+            service_path,
+            env_vars.into_iter().collect(),
+            result_tx,
+        ))?;
     }
+
+    Ok(result_rx.await? as u32)
+}
+
+#[op2(async)]
+#[smi]
+pub async fn op_user_worker_update_feature_flags(
+    state: Rc<RefCell<OpState>>,
+    #[serde] target: FeatureFlagsTarget,
+    #[serde] flags: FeatureFlags,
+) -> Result<u32, AnyError> {
+    if target.tenant.is_none() && target.service_path.is_none() {
+        return Err(type_error("either tenant or service path must be defined"));
+    }
+
+    let (result_tx, result_rx) = oneshot::channel::<usize>();
+
+    {
+        let op_state = state.borrow();
+        let tx = op_state.borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>();
+
+        tx.send(UserWorkerMsgs::UpdateFeatureFlags(target, flags, result_tx))?;
+    }
+
+    Ok(result_rx.await? as u32)
+}
+
+/// Signs the limits of a single request, so that the user worker it is sent to
+/// trusts them.
+#[op2]
+#[string]
+pub fn op_user_worker_sign_limits(
+    state: &mut OpState,
+    #[serde] limits: RequestLimits,
+) -> Result<String, AnyError> {
+    // NOTE: Only the main worker can talk to the pool of user workers, so only
+    // it can sign limits.
+    if state
+        .try_borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>()
+        .is_none()
+    {
+        return Err(custom_error(
+            "PermissionDenied",
+            "only the main worker can set the limits of a request",
+        ));
+    }
+
+    Ok(request_limits::sign(&limits))
 }
 
 #[derive(Deserialize, Debug)]
@@ -195,8 +429,17 @@ pub struct UserWorkerRequest {
     url: String,
     headers: Vec<(String, String)>,
     has_body: bool,
+    /// The stream of the incoming request of the main worker, whose body is
+    /// moved to the user worker as is instead of being written from JS.
+    #[serde(default)]
+    forward_body_rid: Option<ResourceId>,
 }
 
+/// Marks a request whose body should be taken from the incoming request
+/// behind the given stream right before it is sent.
+#[derive(Clone, Copy)]
+struct ForwardedBody(ResourceId);
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UserWorkerBuiltRequest {
@@ -211,6 +454,7 @@ pub struct UserWorkerResponse {
     status_text: String,
     headers: Vec<(ByteString, ByteString)>,
     body_rid: ResourceId,
+    trailers_rid: ResourceId,
     size: Option<u64>,
 }
 
@@ -282,6 +526,61 @@ impl Resource for UserWorkerRequestBodyResource {
 
 type BytesStream = Pin<Box<dyn Stream<Item = Result<bytes::Bytes, std::io::Error>> + Unpin>>;
 
+/// The data of the body of a response, whose trailers are handed over once
+/// the data has ended.
+struct ResponseBodyStream {
+    body: Body,
+    trailers_tx: Option<oneshot::Sender<HeaderMap>>,
+    is_data_done: bool,
+}
+
+impl Stream for ResponseBodyStream {
+    type Item = Result<bytes::Bytes, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let into_io_error = |err| std::io::Error::new(std::io::ErrorKind::Other, err);
+
+        if !this.is_data_done {
+            match ready!(Pin::new(&mut this.body).poll_data(cx)) {
+                Some(result) => return Poll::Ready(Some(result.map_err(into_io_error))),
+                None => this.is_data_done = true,
+            }
+        }
+
+        let Some(trailers_tx) = this.trailers_tx.take() else {
+            return Poll::Ready(None);
+        };
+
+        match Pin::new(&mut this.body).poll_trailers(cx) {
+            Poll::Pending => {
+                this.trailers_tx = Some(trailers_tx);
+                Poll::Pending
+            }
+
+            Poll::Ready(Ok(maybe_trailers)) => {
+                if let Some(trailers) = maybe_trailers {
+                    let _ = trailers_tx.send(trailers);
+                }
+
+                Poll::Ready(None)
+            }
+
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(into_io_error(err)))),
+        }
+    }
+}
+
+/// The trailers of a response, which arrive once its body has ended. See
+/// [`ResponseBodyStream`].
+struct UserWorkerResponseTrailersResource(RefCell<Option<oneshot::Receiver<HeaderMap>>>);
+
+impl Resource for UserWorkerResponseTrailersResource {
+    fn name(&self) -> std::borrow::Cow<str> {
+        "userWorkerResponseTrailers".into()
+    }
+}
+
 struct UserWorkerResponseBodyResource {
     reader: AsyncRefCell<Peekable<BytesStream>>,
     size: Option<u64>,
@@ -360,7 +659,9 @@ pub fn op_user_worker_fetch_build(
     let mut body = Body::empty();
     let mut request_body_rid = None;
 
-    if req.has_body {
+    if let Some(stream_rid) = req.forward_body_rid {
+        builder = builder.extension(ForwardedBody(stream_rid));
+    } else if req.has_body {
         let (tx, stream) = mpsc::channel(1);
 
         body = Body::wrap_stream(BodyStream(stream));
@@ -441,6 +742,20 @@ pub async fn op_user_worker_fetch_send(
             }
         }
 
+        if let Some(ForwardedBody(body_stream_rid)) = req.0.extensions_mut().remove() {
+            let req_stream = state
+                .borrow_mut()
+                .resource_table
+                .get::<HttpStreamReadResource>(body_stream_rid)?;
+
+            let mut req_reader_mut = RcRef::map(&req_stream, |r| &r.rd).borrow_mut().await;
+            let HttpRequestReader::Headers(orig_req) = &mut *req_reader_mut else {
+                return Err(type_error("request body has already been read"));
+            };
+
+            *req.0.body_mut() = std::mem::take(orig_req.body_mut());
+        }
+
         (tx, req)
     };
 
@@ -489,28 +804,164 @@ pub async fn op_user_worker_fetch_send(
         }
     });
 
-    let res = result_rx.await?;
-    let (res, req_end_tx) = match res {
-        Ok((res, req_end_tx)) => (res, req_end_tx),
-        Err(err) => {
-            error!("user worker failed to respond: {}", err);
+    let (res, req_end_tx) = result_rx.await?.map_err(into_worker_response_error)?;
 
-            match err.downcast_ref() {
-                Some(err @ WorkerError::RequestCancelledBySupervisor) => {
-                    return Err(custom_error("WorkerRequestCancelled", err.to_string()));
-                }
+    drop(request_body_guard);
 
-                None => {
-                    return Err(custom_error("InvalidWorkerResponse", err.to_string()));
-                }
+    Ok(add_user_worker_response(
+        &state, res, req_end_tx, conn_token,
+    ))
+}
+
+/// Sends a request to the service of the function with the given name, next to
+/// the service of the calling user worker. See [`invoke`].
+#[op2(async)]
+#[serde]
+pub async fn op_user_worker_invoke(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+    #[smi] rid: ResourceId,
+    #[smi] request_body_rid: Option<ResourceId>,
+) -> Result<UserWorkerResponse, AnyError> {
+    let (invoker, mut req, maybe_permit) = {
+        let mut op_state = state.borrow_mut();
+        let Some(invoker) = op_state.try_borrow::<ServiceInvoker>().cloned() else {
+            return Err(custom_error(
+                "PermissionDenied",
+                "only the user workers of a pool can invoke services",
+            ));
+        };
+
+        let maybe_permit = match acquire_fetch_permit(&op_state) {
+            Ok(it) => it,
+            Err(err) => {
+                let _ = op_state.resource_table.close(rid);
+                return Err(err);
             }
-        }
+        };
+
+        let req = Rc::try_unwrap(
+            op_state
+                .resource_table
+                .take::<UserWorkerRequestResource>(rid)?,
+        )
+        .ok()
+        .expect("multiple op_user_worker_invoke ongoing");
+
+        (invoker, req.0, maybe_permit)
     };
 
+    let Some(service_path) = invoker.resolve(&name) else {
+        return Err(custom_error(
+            "NotFound",
+            format!("could not find the service of function {name}"),
+        ));
+    };
+
+    let chain = InvokeChain::extend(
+        invoker.chains.longest().as_deref(),
+        &invoker.caller(),
+        &service_path.to_string_lossy(),
+    )
+    .map_err(|err| custom_error("InvokeLoopDetected", err.to_string()))?;
+
+    // NOTE: The header only lets the invoked service see the chain. The chain
+    // itself travels along with the request, out of reach of JS.
+    req.headers_mut().insert(
+        INVOKE_CHAIN_HEADER,
+        HeaderValue::from_bytes(&serde_json::to_vec(chain.services())?)?,
+    );
+    req.extensions_mut().insert(Arc::new(chain));
+
+    let (create_tx, create_rx) = oneshot::channel::<Result<CreateUserWorkerResult, Error>>();
+    let opts = UserWorkerBuilder::new(service_path)
+        .runtime_opts(invoker.runtime_opts())
+        .build()?;
+
+    invoker
+        .pool_msg_tx
+        .send(UserWorkerMsgs::Create(opts, create_tx))?;
+
+    let CreateUserWorkerResult { key, .. } = create_rx
+        .await
+        .map_err(|_| WorkerError::WorkerGone)?
+        .map_err(|err| custom_error("InvalidWorkerCreation", format!("{err:#}")))?;
+
+    let (result_tx, result_rx) = oneshot::channel::<Result<SendRequestResult, Error>>();
+
+    invoker
+        .pool_msg_tx
+        .send(UserWorkerMsgs::SendRequest(key, req, result_tx, None))?;
+
+    let request_body_guard = scopeguard::guard(request_body_rid, |rid| {
+        if let Some(Ok(res)) = rid.map(|it| {
+            state
+                .borrow()
+                .resource_table
+                .get::<UserWorkerRequestBodyResource>(it)
+        }) {
+            res.cancel.cancel();
+        }
+    });
+
+    let (res, req_end_tx) = result_rx.await?.map_err(into_worker_response_error)?;
+
     drop(request_body_guard);
 
+    let mut res = add_user_worker_response(&state, res, req_end_tx, None);
+
+    // NOTE: The request is held in the budget of the worker until its
+    // response body is done.
+    if let Some(permit) = maybe_permit {
+        let mut op_state = state.borrow_mut();
+
+        if let Ok(body) = op_state.resource_table.take_any(res.body_rid) {
+            res.body_rid = op_state
+                .resource_table
+                .add(BudgetedResponseBody::new(body, permit));
+        }
+    }
+
+    Ok(res)
+}
+
+fn into_worker_response_error(err: Error) -> AnyError {
+    error!("user worker failed to respond: {}", err);
+
+    match err.downcast_ref() {
+        Some(err @ WorkerError::RequestCancelledBySupervisor) => {
+            custom_error("WorkerRequestCancelled", err.to_string())
+        }
+
+        Some(err @ (WorkerError::PoolOverloaded | WorkerError::Shed { .. })) => {
+            custom_error("WorkerPoolOverloaded", err.to_string())
+        }
+
+        Some(_) | None => custom_error("InvalidWorkerResponse", err.to_string()),
+    }
+}
+
+/// Hands the response of a user worker over to JS, with its body as a
+/// resource.
+fn add_user_worker_response(
+    state: &Rc<RefCell<OpState>>,
+    res: Response<Body>,
+    req_end_tx: mpsc::UnboundedSender<()>,
+    conn_token: Option<CancellationToken>,
+) -> UserWorkerResponse {
     let mut headers = vec![];
     for (key, value) in res.headers().iter() {
+        // NOTE: How the body was framed only concerns the connection to the
+        // worker. The server of the main worker frames it again for the client,
+        // in chunks unless its length is known.
+        //
+        // The trailers are handed over to JS along with the body (see
+        // `op_user_worker_response_trailers`), but the server of the main
+        // worker can't send them over HTTP/1, so they must not be announced.
+        if key == TRANSFER_ENCODING || key == TRAILER {
+            continue;
+        }
+
         headers.push((
             ByteString::from(key.as_str()),
             ByteString::from(value.to_str().unwrap_or_default()),
@@ -525,12 +976,19 @@ pub async fn op_user_worker_fetch_send(
         .to_string();
 
     let size = HttpBody::size_hint(res.body()).exact();
-    let stream: BytesStream = Box::pin(
-        res.into_body()
-            .map(|r| r.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))),
-    );
+    let (trailers_tx, trailers_rx) = oneshot::channel();
+    let stream: BytesStream = Box::pin(ResponseBodyStream {
+        body: res.into_body(),
+        trailers_tx: Some(trailers_tx),
+        is_data_done: false,
+    });
 
     let mut op_state = state.borrow_mut();
+    let trailers_rid = op_state
+        .resource_table
+        .add(UserWorkerResponseTrailersResource(RefCell::new(Some(
+            trailers_rx,
+        ))));
 
     let body_rid = op_state.resource_table.add(UserWorkerResponseBodyResource {
         reader: AsyncRefCell::new(stream.peekable()),
@@ -540,15 +998,44 @@ pub async fn op_user_worker_fetch_send(
         conn_token,
     });
 
-    let response = UserWorkerResponse {
+    UserWorkerResponse {
         status,
         status_text,
         headers,
         body_rid,
+        trailers_rid,
         size,
+    }
+}
+
+/// Returns the trailers of a response of a user worker once its body has
+/// ended, or `None` if it had none or the body was dropped before its end.
+#[op2(async)]
+#[serde]
+pub async fn op_user_worker_response_trailers(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<Option<Vec<(ByteString, ByteString)>>, AnyError> {
+    let resource = state
+        .borrow_mut()
+        .resource_table
+        .take::<UserWorkerResponseTrailersResource>(rid)?;
+
+    let Some(trailers_rx) = resource.0.borrow_mut().take() else {
+        return Ok(None);
     };
 
-    Ok(response)
+    Ok(trailers_rx.await.ok().map(|trailers| {
+        trailers
+            .iter()
+            .map(|(key, value)| {
+                (
+                    ByteString::from(key.as_str()),
+                    ByteString::from(value.to_str().unwrap_or_default()),
+                )
+            })
+            .collect()
+    }))
 }
 
 /// Wraps a [`mpsc::Receiver`] in a [`Stream`] that can be used as a Hyper [`Body`].
@@ -561,3 +1048,51 @@ impl Stream for BodyStream {
         self.0.poll_recv(cx)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use deno_core::futures::StreamExt;
+    use hyper_v014::header::{HeaderMap, HeaderValue};
+    use hyper_v014::Body;
+    use tokio::sync::oneshot;
+
+    use super::ResponseBodyStream;
+
+    #[tokio::test]
+    async fn test_response_body_stream_hands_over_trailers() {
+        let (mut sender, body) = Body::channel();
+        let (trailers_tx, trailers_rx) = oneshot::channel();
+        let mut stream = ResponseBodyStream {
+            body,
+            trailers_tx: Some(trailers_tx),
+            is_data_done: false,
+        };
+
+        tokio::spawn(async move {
+            let mut trailers = HeaderMap::new();
+
+            trailers.insert("server-timing", HeaderValue::from_static("app;dur=1"));
+            sender.send_data("meow".into()).await.unwrap();
+            sender.send_trailers(trailers).await.unwrap();
+        });
+
+        assert_eq!(&stream.next().await.unwrap().unwrap()[..], b"meow");
+        assert!(stream.next().await.is_none());
+        assert!(stream.next().await.is_none());
+        assert_eq!(trailers_rx.await.unwrap()["server-timing"], "app;dur=1");
+    }
+
+    #[tokio::test]
+    async fn test_response_body_stream_without_trailers() {
+        let (trailers_tx, trailers_rx) = oneshot::channel();
+        let mut stream = ResponseBodyStream {
+            body: Body::from("meow"),
+            trailers_tx: Some(trailers_tx),
+            is_data_done: false,
+        };
+
+        assert_eq!(&stream.next().await.unwrap().unwrap()[..], b"meow");
+        assert!(stream.next().await.is_none());
+        assert!(trailers_rx.await.is_err());
+    }
+}
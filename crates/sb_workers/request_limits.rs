@@ -0,0 +1,106 @@
+//! Limits that the main worker sets for a single request to a user worker,
+//! overriding the ones the worker was created with.
+//!
+//! They travel in a header signed with a key that never leaves the process, so
+//! a client can't raise its own limits by sending the header itself.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper_v014::Request;
+use log::warn;
+use once_cell::sync::Lazy;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use serde::{Deserialize, Serialize};
+
+pub static REQUEST_LIMITS_HEADER: &str = "x-edge-runtime-limits";
+
+static SIGNING_KEY: Lazy<hmac::Key> = Lazy::new(|| {
+    hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+        .expect("failed to generate the signing key of request limits")
+});
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RequestLimits {
+    /// Replaces the request idle timeout of the worker. `0` disables it.
+    pub request_idle_timeout_ms: Option<u64>,
+}
+
+/// Returns the value of [`REQUEST_LIMITS_HEADER`] that carries the limits.
+pub fn sign(limits: &RequestLimits) -> String {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(limits).unwrap());
+    let tag = hmac::sign(&SIGNING_KEY, payload.as_bytes());
+
+    format!("{payload}.{}", URL_SAFE_NO_PAD.encode(tag.as_ref()))
+}
+
+fn verify(value: &[u8]) -> Option<RequestLimits> {
+    let value = std::str::from_utf8(value).ok()?;
+    let (payload, tag) = value.split_once('.')?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+
+    hmac::verify(&SIGNING_KEY, payload.as_bytes(), &tag).ok()?;
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+}
+
+/// Removes the limits header from the request, returning the limits it carries
+/// if it was signed by this process.
+///
+/// NOTE: The header is removed even if it is not trusted, so that the worker
+/// never sees it.
+pub fn take_request_limits<B>(req: &mut Request<B>) -> Option<RequestLimits> {
+    let values = match req.headers_mut().entry(REQUEST_LIMITS_HEADER) {
+        hyper_v014::header::Entry::Occupied(it) => it.remove_entry_mult().1.collect::<Vec<_>>(),
+        hyper_v014::header::Entry::Vacant(_) => return None,
+    };
+
+    let [value] = values.as_slice() else {
+        warn!("request carries more than one limits header, ignoring them");
+        return None;
+    };
+
+    let maybe_limits = verify(value.as_bytes());
+
+    if maybe_limits.is_none() {
+        warn!("request carries a limits header that is not trusted, ignoring it");
+    }
+
+    maybe_limits
+}
+
+#[cfg(test)]
+mod test {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use hyper_v014::Request;
+
+    use super::{sign, take_request_limits, RequestLimits, REQUEST_LIMITS_HEADER};
+
+    #[test]
+    fn test_take_request_limits() {
+        let limits = RequestLimits {
+            request_idle_timeout_ms: Some(60_000),
+        };
+
+        let mut req = Request::builder()
+            .header(REQUEST_LIMITS_HEADER, sign(&limits))
+            .body(())
+            .unwrap();
+
+        assert_eq!(take_request_limits(&mut req), Some(limits));
+        assert!(!req.headers().contains_key(REQUEST_LIMITS_HEADER));
+
+        let signed = sign(&limits);
+        let (_, tag) = signed.split_once('.').unwrap();
+        let payload = URL_SAFE_NO_PAD.encode(br#"{"requestIdleTimeoutMs":0}"#);
+        let forged = format!("{payload}.{tag}");
+        let mut req = Request::builder()
+            .header(REQUEST_LIMITS_HEADER, forged)
+            .body(())
+            .unwrap();
+
+        assert_eq!(take_request_limits(&mut req), None);
+        assert!(!req.headers().contains_key(REQUEST_LIMITS_HEADER));
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use hyper_v014::header::{self, HeaderName, HeaderValue};
+use hyper_v014::http::uri::PathAndQuery;
+use hyper_v014::{Body, Request, Response, Uri};
+use serde::Deserialize;
+
+use crate::header_policy::strip_headers;
+
+/// How the requests to a service are rewritten before they reach its worker,
+/// so that functions receive normalized requests without a separate proxy in
+/// front of the runtime.
+///
+/// The request filters, the allowlists and the JWT check all see the request
+/// as the client sent it.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RewritePolicy {
+    /// Removes this prefix from the path of the requests that start with it.
+    /// A redirect of the worker to an absolute path gets the prefix back.
+    pub strip_path_prefix: Option<String>,
+    /// Replaces the `Host` header of the requests.
+    pub host: Option<String>,
+    /// Headers set on every request, replacing the ones sent by the client.
+    #[serde(default)]
+    pub set_headers: BTreeMap<String, String>,
+    /// Headers removed from every request. A trailing `*` matches any header
+    /// that starts with the rest of the name (e.g. `x-forwarded-*`).
+    #[serde(default)]
+    pub remove_headers: Vec<String>,
+}
+
+impl RewritePolicy {
+    fn path_prefix(&self) -> Option<&str> {
+        self.strip_path_prefix
+            .as_deref()
+            .map(|it| it.trim_end_matches('/'))
+            .filter(|it| !it.is_empty())
+    }
+
+    /// Rewrites a request before it is sent to the worker. The values that are
+    /// not valid in a request are skipped.
+    pub fn rewrite_request(&self, req: &mut Request<Body>) {
+        if let Some(uri) = self
+            .path_prefix()
+            .and_then(|it| strip_prefix(req.uri(), it))
+        {
+            *req.uri_mut() = uri;
+        }
+
+        let headers = req.headers_mut();
+
+        if !self.remove_headers.is_empty() {
+            strip_headers(headers, &self.remove_headers);
+        }
+
+        for (name, value) in self.set_headers.iter() {
+            let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) else {
+                continue;
+            };
+
+            headers.insert(name, value);
+        }
+
+        if let Some(Ok(host)) = self.host.as_deref().map(HeaderValue::from_str) {
+            headers.insert(header::HOST, host);
+        }
+    }
+
+    /// Rewrites a response of the worker to a request rewritten by
+    /// [`Self::rewrite_request`].
+    pub fn rewrite_response(&self, res: &mut Response<Body>) {
+        let Some(prefix) = self.path_prefix() else {
+            return;
+        };
+
+        let Some(location) = res
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|it| it.to_str().ok())
+            .filter(|it| it.starts_with('/') && !it.starts_with("//"))
+        else {
+            return;
+        };
+
+        if let Ok(value) = HeaderValue::from_str(&format!("{prefix}{location}")) {
+            res.headers_mut().insert(header::LOCATION, value);
+        }
+    }
+}
+
+fn strip_prefix(uri: &Uri, prefix: &str) -> Option<Uri> {
+    let rest = uri.path().strip_prefix(prefix)?;
+
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+
+    let path = if rest.is_empty() { "/" } else { rest };
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use hyper_v014::{Body, Request, Response};
+
+    use super::RewritePolicy;
+
+    #[test]
+    fn test_rewrite_policy() {
+        let policy = RewritePolicy {
+            strip_path_prefix: Some("/api/".to_string()),
+            host: Some("internal.local".to_string()),
+            set_headers: BTreeMap::from([("x-env".to_string(), "prod".to_string())]),
+            remove_headers: vec!["x-forwarded-*".to_string()],
+        };
+
+        let mut req = Request::builder()
+            .uri("http://example.com/api/users?id=1")
+            .header("host", "example.com")
+            .header("x-forwarded-for", "1.2.3.4")
+            .header("x-env", "dev")
+            .body(Body::empty())
+            .unwrap();
+
+        policy.rewrite_request(&mut req);
+
+        assert_eq!(req.uri().path(), "/users");
+        assert_eq!(req.uri().query(), Some("id=1"));
+        assert_eq!(req.headers()["host"], "internal.local");
+        assert_eq!(req.headers()["x-env"], "prod");
+        assert!(!req.headers().contains_key("x-forwarded-for"));
+
+        let mut req = Request::builder().uri("/apix").body(Body::empty()).unwrap();
+
+        policy.rewrite_request(&mut req);
+        assert_eq!(req.uri().path(), "/apix");
+
+        let mut req = Request::builder().uri("/api").body(Body::empty()).unwrap();
+
+        policy.rewrite_request(&mut req);
+        assert_eq!(req.uri().path(), "/");
+
+        let mut res = Response::builder()
+            .header("location", "/login")
+            .body(Body::empty())
+            .unwrap();
+
+        policy.rewrite_response(&mut res);
+        assert_eq!(res.headers()["location"], "/api/login");
+    }
+}
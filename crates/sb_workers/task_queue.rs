@@ -0,0 +1,411 @@
+//! A durable queue of tasks for the work a user worker defers past its
+//! response, persisted in SQLite so that it survives the recycling of the
+//! worker and the restarts of the runtime. Only available if the runtime is
+//! started with `--task-queue-db`.
+//!
+//! A task is delivered as a `POST` to a service, through the main worker like
+//! any request, and is leased for the visibility timeout while it is being
+//! delivered. It is deleted once the service responds with a 2xx, and retried
+//! with a backoff otherwise, until it has used up its attempts. A task whose
+//! lease expires, e.g. because the runtime went away mid-delivery, is
+//! delivered again. A task that used up its attempts is kept aside for
+//! [`DEAD_TASK_RETENTION`], then swept.
+//!
+//! SQLite blocks on the disk, so the queries are run on the blocking threads
+//! (see [`TaskQueue::run_blocking`]).
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Error};
+use deno_core::error::{custom_error, AnyError};
+use deno_core::unsync::spawn_blocking;
+use deno_core::{op2, OpState};
+use deno_webstorage::rusqlite::{params, Connection};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::invoke::ServiceInvoker;
+
+static TASK_QUEUE: OnceCell<Arc<TaskQueue>> = OnceCell::new();
+
+static DEFAULT_MAX_ATTEMPTS: u32 = 5;
+static MAX_PAYLOAD_SIZE: usize = 256 * 1024;
+static RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+static RETRY_MAX_DELAY: Duration = Duration::from_secs(15 * 60);
+
+/// How long a task that used up its attempts is kept, for inspection, before
+/// it is swept.
+pub static DEAD_TASK_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+static SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tasks (
+        id TEXT PRIMARY KEY,
+        service TEXT NOT NULL,
+        path TEXT NOT NULL,
+        payload BLOB NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        max_attempts INTEGER NOT NULL,
+        visible_at INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        dead INTEGER NOT NULL DEFAULT 0,
+        last_error TEXT
+    );
+    CREATE INDEX IF NOT EXISTS tasks_visible_at ON tasks (dead, visible_at);
+";
+
+/// A task leased for delivery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeasedTask {
+    pub id: String,
+    /// The name of the function the task is delivered to.
+    pub service: String,
+    /// The path of the request, under the function.
+    pub path: String,
+    pub payload: Vec<u8>,
+    /// The attempt this delivery is, starting at 1.
+    pub attempt: u32,
+    pub max_attempts: u32,
+}
+
+#[derive(Debug)]
+pub struct TaskQueue {
+    conn: Mutex<Connection>,
+    /// Notified whenever a task is enqueued, so that it is not left waiting
+    /// for the next poll.
+    pub enqueued: Notify,
+}
+
+impl TaskQueue {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("can't open the task queue: {}", path.display()))?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+
+        Self::from_connection(conn)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            enqueued: Notify::new(),
+        })
+    }
+
+    /// Runs `f` on a blocking thread, as the queries wait on the disk.
+    pub async fn run_blocking<T, F>(self: &Arc<Self>, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Self) -> Result<T, Error> + Send + 'static,
+    {
+        let this = self.clone();
+
+        spawn_blocking(move || f(&this)).await?
+    }
+
+    pub fn enqueue(
+        &self,
+        service: &str,
+        path: &str,
+        payload: &[u8],
+        delay: Duration,
+        max_attempts: u32,
+        now_ms: u64,
+    ) -> Result<String, Error> {
+        let id = Uuid::new_v4().to_string();
+
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO tasks (id, service, path, payload, max_attempts, visible_at, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                id,
+                service,
+                path,
+                payload,
+                max_attempts,
+                now_ms.saturating_add(delay.as_millis() as u64) as i64,
+                now_ms as i64
+            ],
+        )?;
+
+        self.enqueued.notify_one();
+
+        Ok(id)
+    }
+
+    /// Leases up to `limit` tasks that are due, hiding them from the other
+    /// leases for `visibility_timeout`.
+    pub fn lease(
+        &self,
+        limit: usize,
+        visibility_timeout: Duration,
+        now_ms: u64,
+    ) -> Result<Vec<LeasedTask>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "UPDATE tasks SET attempts = attempts + 1, visible_at = ?1
+             WHERE id IN (
+                SELECT id FROM tasks WHERE dead = 0 AND visible_at <= ?2
+                ORDER BY visible_at LIMIT ?3
+             )
+             RETURNING id, service, path, payload, attempts, max_attempts",
+        )?;
+
+        let tasks = stmt
+            .query_map(
+                params![
+                    now_ms.saturating_add(visibility_timeout.as_millis() as u64) as i64,
+                    now_ms as i64,
+                    limit as i64
+                ],
+                |row| {
+                    Ok(LeasedTask {
+                        id: row.get(0)?,
+                        service: row.get(1)?,
+                        path: row.get(2)?,
+                        payload: row.get(3)?,
+                        attempt: row.get(4)?,
+                        max_attempts: row.get(5)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(tasks)
+    }
+
+    /// Deletes a task that was delivered.
+    pub fn complete(&self, id: &str) -> Result<(), Error> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM tasks WHERE id = ?1", params![id])?;
+
+        Ok(())
+    }
+
+    /// Schedules the retry of a task that failed to be delivered, or keeps it
+    /// aside if it has used up its attempts. Returns whether it will be
+    /// retried.
+    pub fn fail(&self, task: &LeasedTask, error: &str, now_ms: u64) -> Result<bool, Error> {
+        let retry = task.attempt < task.max_attempts;
+
+        // NOTE: A task kept aside is never visible again, so the time it was
+        // kept aside at is kept in its place, to be swept from.
+        let visible_at = if retry {
+            now_ms.saturating_add(get_retry_delay(task.attempt).as_millis() as u64)
+        } else {
+            now_ms
+        };
+
+        self.conn.lock().unwrap().execute(
+            "UPDATE tasks SET dead = ?2, visible_at = ?3, last_error = ?4 WHERE id = ?1",
+            params![task.id, !retry, visible_at as i64, error],
+        )?;
+
+        Ok(retry)
+    }
+
+    /// Deletes the tasks that were kept aside longer than `retention` ago.
+    /// Returns how many were deleted.
+    pub fn sweep(&self, retention: Duration, now_ms: u64) -> Result<usize, Error> {
+        Ok(self.conn.lock().unwrap().execute(
+            "DELETE FROM tasks WHERE dead = 1 AND visible_at <= ?1",
+            params![now_ms.saturating_sub(retention.as_millis() as u64) as i64],
+        )?)
+    }
+}
+
+fn get_retry_delay(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(RETRY_MAX_DELAY)
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|it| it.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Opens the task queue of the runtime. Can only be called once.
+pub fn install(path: PathBuf) -> Result<(), Error> {
+    let queue = TaskQueue::open(&path)?;
+
+    if TASK_QUEUE.set(Arc::new(queue)).is_err() {
+        bail!("the task queue is already open");
+    }
+
+    Ok(())
+}
+
+/// Returns the task queue of the runtime, if it has one.
+pub fn get() -> Option<Arc<TaskQueue>> {
+    TASK_QUEUE.get().cloned()
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct EnqueueTaskOptions {
+    /// The function the task is delivered to, the one of the worker if not
+    /// given.
+    name: Option<String>,
+    path: Option<String>,
+    /// The body of the request the task is delivered with, as JSON.
+    payload: String,
+    delay_ms: Option<u64>,
+    max_attempts: Option<u32>,
+}
+
+#[op2(async)]
+#[string]
+pub async fn op_task_queue_enqueue(
+    state: Rc<RefCell<OpState>>,
+    #[serde] opts: EnqueueTaskOptions,
+) -> Result<String, AnyError> {
+    let Some(queue) = get() else {
+        return Err(custom_error(
+            "NotSupported",
+            "the task queue is not enabled, start the runtime with --task-queue-db",
+        ));
+    };
+
+    let service = {
+        let state = state.borrow();
+        let Some(invoker) = state.try_borrow::<ServiceInvoker>() else {
+            return Err(custom_error(
+                "PermissionDenied",
+                "only the user workers of a pool can enqueue tasks",
+            ));
+        };
+
+        match opts.name {
+            Some(name) => {
+                if invoker.resolve(&name).is_none() {
+                    return Err(custom_error(
+                        "NotFound",
+                        format!("could not find the service of function {name}"),
+                    ));
+                }
+
+                name
+            }
+
+            None => Path::new(&invoker.caller())
+                .file_name()
+                .map(|it| it.to_string_lossy().into_owned())
+                .ok_or_else(|| custom_error("NotFound", "the worker has no function name"))?,
+        }
+    };
+
+    let path = opts.path.unwrap_or_else(|| String::from("/"));
+
+    if !path.starts_with('/') {
+        return Err(custom_error(
+            "TypeError",
+            "the path of a task must start with /",
+        ));
+    }
+    if opts.payload.len() > MAX_PAYLOAD_SIZE {
+        return Err(custom_error(
+            "RangeError",
+            format!("the payload of a task can't exceed {MAX_PAYLOAD_SIZE} bytes"),
+        ));
+    }
+
+    let delay = Duration::from_millis(opts.delay_ms.unwrap_or_default());
+    let max_attempts = opts.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1);
+
+    Ok(queue
+        .run_blocking(move |it| {
+            it.enqueue(
+                &service,
+                &path,
+                opts.payload.as_bytes(),
+                delay,
+                max_attempts,
+                now_ms(),
+            )
+        })
+        .await?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use deno_webstorage::rusqlite::Connection;
+
+    use super::{get_retry_delay, TaskQueue, DEAD_TASK_RETENTION};
+
+    static VT: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn test_task_queue_lease() {
+        let queue = TaskQueue::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let id = queue
+            .enqueue("hello", "/", b"{}", Duration::ZERO, 2, 1_000)
+            .unwrap();
+
+        queue
+            .enqueue("later", "/", b"{}", Duration::from_secs(60), 2, 1_000)
+            .unwrap();
+
+        let tasks = queue.lease(10, VT, 1_000).unwrap();
+
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].id, id);
+        assert_eq!(tasks[0].attempt, 1);
+
+        // NOTE: A leased task is hidden until its lease expires.
+        assert!(queue.lease(10, VT, 2_000).unwrap().is_empty());
+        assert_eq!(queue.lease(10, VT, 31_000).unwrap()[0].attempt, 2);
+
+        queue.complete(&id).unwrap();
+
+        assert_eq!(queue.lease(10, VT, 61_000).unwrap()[0].service, "later");
+        assert_eq!(queue.lease(10, VT, 1_000_000).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_task_queue_fail() {
+        let queue = TaskQueue::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+
+        queue
+            .enqueue("hello", "/", b"{}", Duration::ZERO, 2, 0)
+            .unwrap();
+
+        let task = queue.lease(1, VT, 0).unwrap().remove(0);
+
+        assert!(queue.fail(&task, "500", 0).unwrap());
+        assert!(queue.lease(1, VT, 999).unwrap().is_empty());
+
+        let task = queue.lease(1, VT, 1_000).unwrap().remove(0);
+
+        assert!(!queue.fail(&task, "500", 1_000).unwrap());
+        assert!(queue.lease(1, VT, 1_000_000).unwrap().is_empty());
+
+        let retention_ms = DEAD_TASK_RETENTION.as_millis() as u64;
+
+        assert_eq!(queue.sweep(DEAD_TASK_RETENTION, retention_ms).unwrap(), 0);
+        assert_eq!(
+            queue
+                .sweep(DEAD_TASK_RETENTION, 1_000 + retention_ms)
+                .unwrap(),
+            1
+        );
+
+        assert_eq!(get_retry_delay(1), Duration::from_secs(1));
+        assert_eq!(get_retry_delay(3), Duration::from_secs(4));
+        assert_eq!(get_retry_delay(40), Duration::from_secs(15 * 60));
+    }
+}
@@ -1,16 +1,33 @@
 import { primordials, core } from "ext:core/mod.js";
-import { readableStreamForRid, writableStreamForRid } from "ext:deno_web/06_streams.js";
-import { getSupabaseTag } from "ext:sb_core_main_js/js/http.js";
+import {
+	getReadableStreamResourceBacking,
+	isReadableStreamDisturbed,
+	readableStreamForRid,
+	writableStreamForRid,
+} from "ext:deno_web/06_streams.js";
+import {
+	getFetchDeadline,
+	getSupabaseTag,
+} from "ext:sb_core_main_js/js/http.js";
 
 const ops = core.ops;
 
-const { TypeError } = primordials;
+const { ArrayPrototypeFilter, PromisePrototypeThen, TypeError } = primordials;
 
 const {
 	op_user_worker_fetch_send,
+	op_user_worker_invoke,
+	op_user_worker_response_trailers,
 	op_user_worker_create,
+	op_user_worker_update_env,
+	op_user_worker_update_feature_flags,
+	op_user_worker_sign_limits,
 } = ops;
 
+// Keep in sync with `REQUEST_LIMITS_HEADER` in `request_limits.rs`.
+const REQUEST_LIMITS_HEADER = "x-edge-runtime-limits";
+const DEADLINE_HEADER = "x-deadline-ms";
+
 const NO_SUPABASE_TAG_WARN_MSG = `Unable to find the supabase tag from the request instance.\n\
 Invoke \`EdgeRuntime.applySupabaseTag(origReq, newReq)\` if you have cloned the original request.`
 
@@ -23,16 +40,69 @@ function redirectStatus(status) {
 		status === 307 || status === 308;
 }
 
+function intoResponse(result, method, signal) {
+	const response = {
+		headers: result.headers,
+		status: result.status,
+		statusText: result.statusText,
+		body: null,
+	};
+
+	// TODO: add a test
+	if (nullBodyStatus(result.status) || redirectStatus(result.status)) {
+		core.tryClose(result.bodyRid);
+	} else {
+		if (method === "HEAD" || method === "CONNECT") {
+			core.tryClose(result.bodyRid);
+		} else {
+			// The stream stays backed by the body resource. If the main
+			// worker responds with it as is, its server writes the resource
+			// as the client pulls it, without the body ever being read in
+			// this isolate.
+			const stream = readableStreamForRid(result.bodyRid);
+
+			signal?.addEventListener("abort", () => {
+				core.tryClose(result.bodyRid);
+			});
+			
+			response.body = stream;
+		}
+	}
+
+	const res = new Response(response.body ? response.body : null, {
+		headers: response.headers,
+		status: response.status,
+		statusText: response.statusText,
+	});
+
+	// NOTE: The trailers arrive once the body has been read to its end. The
+	// promise resolves to `null` if there are none.
+	const trailers = op_user_worker_response_trailers(result.trailersRid);
+
+	core.unrefOpPromise(trailers);
+
+	res.trailers = PromisePrototypeThen(
+		trailers,
+		(it) => it === null ? null : new Headers(it),
+	);
+
+	return res;
+}
+
 class UserWorker {
-	constructor(key) {
+	constructor(key, warnings = [], decision = "created") {
 		this.key = key;
+		// What went wrong while booting the worker, without failing the boot.
+		this.warnings = warnings;
+		// `created`, `reused` or `recreated`.
+		this.decision = decision;
 	}
 
 	async fetch(request, options = {}) {
 		const tag = getSupabaseTag(request);
 		
 		const { method, url, headers, body, bodyUsed } = request;
-		const { signal } = options;
+		const { signal, limits } = options;
 
 		signal?.throwIfAborted();
 
@@ -40,13 +110,40 @@ class UserWorker {
 			console.warn(NO_SUPABASE_TAG_WARN_MSG);
 		} 
 
-		const headersArray = Array.from(headers.entries());
+		// Whatever the request already carries under the limits header is
+		// dropped, as it isn't signed and the user worker would ignore all the
+		// limits of a request carrying more than one.
+		const headersArray = ArrayPrototypeFilter(
+			Array.from(headers.entries()),
+			([name]) => name !== REQUEST_LIMITS_HEADER,
+		);
+
+		// The limits of this request override the ones the worker was created
+		// with.
+		if (limits !== void 0 && limits !== null) {
+			headersArray.push([
+				REQUEST_LIMITS_HEADER,
+				op_user_worker_sign_limits(limits),
+			]);
+		}
 		const hasBody = !bodyUsed && !!body;
 
+		// If the body is still the untouched incoming stream of the main
+		// worker, it is moved to the user worker in Rust rather than being
+		// read and written again from here.
+		const forwardBodyRid = hasBody
+			&& tag !== void 0
+			&& !body.locked
+			&& !isReadableStreamDisturbed(body)
+			&& getReadableStreamResourceBacking(body)?.rid === tag.streamRid
+			? tag.streamRid
+			: null;
+
 		const userWorkerReq = {
 			method,
 			url,
 			hasBody,
+			forwardBodyRid,
 			headers: headersArray,
 		};
 
@@ -57,7 +154,7 @@ class UserWorker {
 		// stream the request body
 		let requestBodyPromise = null;
 
-		if (hasBody) {
+		if (hasBody && forwardBodyRid === null) {
 			let writableStream = writableStreamForRid(requestBodyRid);
 			requestBodyPromise = body.pipeTo(writableStream, { signal });
 		}
@@ -83,56 +180,61 @@ class UserWorker {
 			throw responsePromiseResult.reason;
 		}
 
-		const result = responsePromiseResult.value;
-		const response = {
-			headers: result.headers,
-			status: result.status,
-			statusText: result.statusText,
-			body: null,
-		};
-
-		// TODO: add a test
-		if (nullBodyStatus(result.status) || redirectStatus(result.status)) {
-			core.tryClose(result.bodyRid);
-		} else {
-			if (request.method === "HEAD" || request.method === "CONNECT") {
-				core.tryClose(result.bodyRid);
-			} else {
-				const stream = readableStreamForRid(result.bodyRid);
-
-				signal?.addEventListener("abort", () => {
-					core.tryClose(result.bodyRid);
-				});
-				
-				response.body = stream;
-			}
-		}
-
-		return new Response(response.body ? response.body : null, {
-			headers: response.headers,
-			status: response.status,
-			statusText: response.statusText,
-		});
+		return intoResponse(responsePromiseResult.value, request.method, signal);
 	}
 
 	static async create(opts) {
 		const readyOptions = {
 			memoryLimitMb: 512,
 			lowMemoryMultiplier: 5,
+			memoryLeakTrendSamples: 0,
+			recycleOnMemoryLeak: false,
+			heapInitialMb: 0,
+			heapMaxMb: 0,
+			lowMemoryNotificationPercent: 0,
 			workerTimeoutMs: 5 * 60 * 1000,
 			cpuTimeSoftLimitMs: 50,
 			cpuTimeHardLimitMs: 100,
+			cpuTimeCapMs: 0,
+			requestCpuTimeCapMs: 0,
+			eventLoopLagWarnMs: 0,
+			eventLoopLagLimitMs: 0,
+			maxResources: 0,
+			maxWebsocketConnections: 0,
+			websocketIdleTimeoutMs: 0,
+			fetchTimeoutMs: 0,
+			maxConcurrentFetches: 0,
+			maxEmailsPerMinute: 0,
+			clockGranularityMs: 0,
+			logSampleRate: 1,
+			logRateLimit: 0,
+			locale: null,
+			timezone: null,
 			noModuleCache: false,
 			importMapPath: null,
 			envVars: [],
 			forceCreate: false,
+			idempotencyKey: null,
+			sessionKey: null,
 			netAccessDisabled: false,
 			allowNet: null,
+			allowSockets: null,
 			allowRemoteModules: true,
+			allowSharedArrayBuffer: false,
+			allowWasmThreads: false,
+			allowAtomicsWait: true,
 			customModuleRoot: '',
 			maybeEszip: null,
+			maybeEszipSignature: null,
 			maybeEntrypoint: null,
 			maybeModuleCode: null,
+			maybeModuleMap: null,
+			allowedMethods: null,
+			allowedPathPrefixes: null,
+			outboundTls: null,
+			tenant: null,
+			revision: null,
+			featureFlags: {},
 			...opts,
 		};
 
@@ -142,12 +244,89 @@ class UserWorker {
 			throw new TypeError("service path must be defined");
 		}
 
-		const key = await op_user_worker_create(readyOptions);
+		const { key, warnings, decision } = await op_user_worker_create(readyOptions);
 
-		return new UserWorker(key);
+		return new UserWorker(key, warnings, decision);
 	}
+
+	static async updateEnv(servicePath, envVars) {
+		if (!servicePath || servicePath === "") {
+			throw new TypeError("service path must be defined");
+		}
+
+		return await op_user_worker_update_env(servicePath, envVars);
+	}
+
+	// Merges the flags into the feature flags of the workers of the tenant
+	// and/or the service, and resolves to how many workers have been updated.
+	// A flag set to `null` is removed.
+	static async updateFeatureFlags(target, flags) {
+		const { tenant = null, servicePath = null } = target ?? {};
+
+		if (!tenant && !servicePath) {
+			throw new TypeError("either tenant or service path must be defined");
+		}
+
+		return await op_user_worker_update_feature_flags({ tenant, servicePath }, flags);
+	}
+}
+
+// Invokes the function with the given name next to the service of this user
+// worker, in the same pool and without going through the network. The request
+// may be given with a path only.
+//
+// The invocation carries the deadline of the request being handled, or of
+// `init.parent` if given, and counts against the outbound requests of the
+// worker. The chain of invocations it belongs to is kept by the runtime.
+async function invokeService(name, input, init = {}) {
+	const { parent = null, ...requestInit } = init ?? {};
+
+	if (typeof input === "string" && input.startsWith("/")) {
+		input = `http://localhost${input}`;
+	}
+
+	const request = new Request(input, requestInit);
+	const { method, url, headers, body, signal } = request;
+
+	signal?.throwIfAborted();
+
+	const deadline = parent !== null
+		? parent.headers.get(DEADLINE_HEADER)
+		: getFetchDeadline();
+
+	// NOTE: Only the runtime sets the deadline of a service.
+	if (deadline !== null) {
+		headers.set(DEADLINE_HEADER, String(deadline));
+	} else {
+		headers.delete(DEADLINE_HEADER);
+	}
+
+	const hasBody = !!body;
+
+	const { requestRid, requestBodyRid } = await ops.op_user_worker_fetch_build({
+		method,
+		url,
+		hasBody,
+		forwardBodyRid: null,
+		headers: Array.from(headers.entries()),
+	});
+
+	const requestBodyPromise = hasBody
+		? body.pipeTo(writableStreamForRid(requestBodyRid), { signal })
+		: null;
+
+	const [_, responsePromiseResult] = await Promise.allSettled([
+		requestBodyPromise,
+		op_user_worker_invoke(name, requestRid, requestBodyRid),
+	]);
+
+	if (responsePromiseResult.status === "rejected") {
+		throw responsePromiseResult.reason;
+	}
+
+	return intoResponse(responsePromiseResult.value, method, signal);
 }
 
 const SUPABASE_USER_WORKERS = UserWorker;
 
-export { SUPABASE_USER_WORKERS };
+export { SUPABASE_USER_WORKERS, invokeService };
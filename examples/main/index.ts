@@ -96,6 +96,10 @@ Deno.serve(async (req: Request) => {
 		const envVars = Object.keys(envVarsObj).map((k) => [k, envVarsObj[k]]);
 		const forceCreate = false;
 		const netAccessDisabled = false;
+		// raw TCP/UDP sockets (e.g. `Deno.connect`) can be further limited to
+		// the hosts listed here, as `host` or `host:port`. Listening on a
+		// socket (`Deno.listenDatagram`) needs its address to be listed.
+		const allowSockets: string[] | null = null;
 
 		// load source from an eszip
 		//const maybeEszip = await Deno.readFile('./bin.eszip');
@@ -117,6 +121,7 @@ Deno.serve(async (req: Request) => {
 			envVars,
 			forceCreate,
 			netAccessDisabled,
+			allowSockets,
 			cpuTimeSoftLimitMs,
 			cpuTimeHardLimitMs,
 			// maybeEszip,
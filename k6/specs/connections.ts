@@ -0,0 +1,44 @@
+/*
+Measures the socket IO path of the external listener rather than the workers
+themselves: every iteration opens a fresh connection, so most of the time is
+spent accepting and tearing down sockets. Run it once against each of the
+commands below to compare the default (epoll) path with io_uring.
+
+./scripts/run.sh
+
+#!/usr/bin/env bash
+
+GIT_V_TAG=0.1.1 cargo build --release --features cli/io-uring && \
+EDGE_RUNTIME_PORT=9998 ./target/release/edge-runtime "$@" start \
+    --main-service ./examples/main
+
+./scripts/run.sh --io-uring   # io_uring
+./scripts/run.sh              # epoll
+
+*/
+
+import http from "k6/http";
+
+import { check } from "k6";
+import { Options } from "k6/options";
+
+import { target } from "../config";
+
+export const options: Options = {
+    noConnectionReuse: true,
+    scenarios: {
+        connections: {
+            executor: "constant-vus",
+            vus: 64,
+            duration: "1m",
+        }
+    }
+};
+
+export default function connections() {
+    const res = http.get(`${target}/serve`);
+
+    check(res, {
+        "status is 200": r => r.status === 200
+    });
+}